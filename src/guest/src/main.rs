@@ -1,5 +1,5 @@
 fn main() {
-    dbg!(woo as usize);
+    dbg!(woo as fn() as usize);
 }
 
 fn woo() {}
@@ -0,0 +1,274 @@
+//! Derive macros for `crt-marshal`.
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote, ToTokens};
+use syn::{
+    parse_macro_input, spanned::Spanned, Attribute, Data, DeriveInput, Fields, Index, Member, Type,
+};
+
+// === Helpers === //
+
+fn is_repr_c(attrs: &[Attribute]) -> bool {
+    attrs.iter().any(|attr| {
+        let mut is_c = false;
+        if attr.path().is_ident("repr") {
+            let _ = attr.parse_nested_meta(|meta| {
+                is_c |= meta.path.is_ident("C");
+                Ok(())
+            });
+        }
+        is_c
+    })
+}
+
+// === AtomicFields === //
+
+/// Derives a `crt_marshal::AtomicField` constant for every field marked `#[atomic]`, named after
+/// the field in upper case.
+///
+/// The struct must be `#[repr(C)]` and the marked fields must implement `AtomicValue`. Unmarked
+/// fields are left to be accessed as plain `Pod` data.
+#[proc_macro_derive(AtomicFields, attributes(atomic))]
+pub fn derive_atomic_fields(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match derive_atomic_fields_inner(input) {
+        Ok(output) => output.into(),
+        Err(err) => err.into_compile_error().into(),
+    }
+}
+
+fn derive_atomic_fields_inner(input: DeriveInput) -> syn::Result<TokenStream> {
+    let krate = quote! { ::crt_marshal };
+
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.span(),
+            "`AtomicFields` can only be derived for structs",
+        ));
+    };
+
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new(
+            input.span(),
+            "`AtomicFields` can only be derived for structs with named fields",
+        ));
+    };
+
+    if !is_repr_c(&input.attrs) {
+        return Err(syn::Error::new(
+            input.span(),
+            "`AtomicFields` can only be derived for `#[repr(C)]` structs",
+        ));
+    }
+
+    let mut consts = Vec::new();
+
+    for field in &fields.named {
+        let Some(attr) = field
+            .attrs
+            .iter()
+            .find(|attr| attr.path().is_ident("atomic"))
+        else {
+            continue;
+        };
+        attr.meta.require_path_only()?;
+
+        let ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        let name = format_ident!(
+            "{}",
+            ident.to_string().trim_start_matches("r#").to_uppercase(),
+            span = ident.span()
+        );
+        let doc = format!("The atomic field `{ident}`.");
+
+        consts.push(quote! {
+            #[doc = #doc]
+            pub const #name: #krate::AtomicField<Self, #ty> =
+                #krate::AtomicField::new(::core::mem::offset_of!(Self, #ident) as u32);
+        });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics #ident #ty_generics #where_clause {
+            #(#consts)*
+        }
+    })
+}
+
+// === Marshaled === //
+
+/// Derives `crt_marshal::MarshaledTy` for a `#[repr(C)]` struct small enough to be passed by
+/// value, along with a `Pod` copy of the struct named after it with an `Le` prefix.
+///
+/// The copy stores the struct's integer fields as their little-endian `Le*` wrappers, making it the
+/// form in which the struct lives in guest memory. It is checked to be free of padding and every
+/// one of its fields must be `Pod`. The struct itself is marshaled as the bytes of its copy packed
+/// into a `u64`, so the copy may be at most 8 bytes long. Larger structs should be passed through a
+/// `WasmPtr` to their copy instead.
+#[proc_macro_derive(Marshaled)]
+pub fn derive_marshaled(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match derive_marshaled_inner(input) {
+        Ok(output) => output.into(),
+        Err(err) => err.into_compile_error().into(),
+    }
+}
+
+/// The little-endian wrapper numbers of a given type are stored in, if any.
+fn le_wrapper(ty: &Type) -> Option<TokenStream> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+
+    if path.qself.is_some() {
+        return None;
+    }
+
+    let wrapper = match path.path.get_ident()?.to_string().as_str() {
+        "u16" => "LeU16",
+        "i16" => "LeI16",
+        "u32" => "LeU32",
+        "i32" => "LeI32",
+        "u64" => "LeU64",
+        "i64" => "LeI64",
+        "f32" => "LeF32",
+        "f64" => "LeF64",
+        _ => return None,
+    };
+
+    let wrapper = format_ident!("{wrapper}", span = ty.span());
+    Some(quote! { ::crt_marshal::#wrapper })
+}
+
+fn derive_marshaled_inner(input: DeriveInput) -> syn::Result<TokenStream> {
+    let krate = quote! { ::crt_marshal };
+
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.span(),
+            "`Marshaled` can only be derived for structs",
+        ));
+    };
+
+    if !is_repr_c(&input.attrs) {
+        return Err(syn::Error::new(
+            input.span(),
+            "`Marshaled` can only be derived for `#[repr(C)]` structs",
+        ));
+    }
+
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            input.generics.span(),
+            "`Marshaled` can't be derived for generic structs",
+        ));
+    }
+
+    let ident = &input.ident;
+    let vis = &input.vis;
+    let le_ident = format_ident!("Le{ident}", span = ident.span());
+
+    let members = data
+        .fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(i)),
+        })
+        .collect::<Vec<_>>();
+
+    let le_tys = data
+        .fields
+        .iter()
+        .map(|field| le_wrapper(&field.ty).unwrap_or_else(|| field.ty.to_token_stream()))
+        .collect::<Vec<_>>();
+
+    let le_fields = data.fields.iter().zip(&le_tys).map(|(field, le_ty)| {
+        let vis = &field.vis;
+        match &field.ident {
+            Some(ident) => quote! { #vis #ident: #le_ty },
+            None => quote! { #vis #le_ty },
+        }
+    });
+
+    let le_body = match &data.fields {
+        Fields::Named(_) => quote! { { #(#le_fields,)* } },
+        Fields::Unnamed(_) => quote! { ( #(#le_fields,)* ); },
+        Fields::Unit => quote! { ; },
+    };
+
+    let doc = format!(
+        "The in-memory form of [`{ident}`], with its integers stored in little-endian order."
+    );
+    let padding_msg = format!("`{le_ident}` must not contain padding");
+    let size_msg = format!(
+        "`{ident}` is too big to be marshaled by value. Pass a `WasmPtr<{le_ident}>` instead."
+    );
+
+    Ok(quote! {
+        #[doc = #doc]
+        #[derive(Copy, Clone)]
+        #[repr(C)]
+        #vis struct #le_ident #le_body
+
+        const _: () = {
+            fn assert_pod<T: #krate::macro_rexp::Pod>() {}
+
+            #[allow(dead_code)]
+            fn assert_fields_pod() {
+                #(assert_pod::<#le_tys>();)*
+            }
+
+            assert!(
+                ::core::mem::size_of::<#le_ident>() == 0 #(+ ::core::mem::size_of::<#le_tys>())*,
+                #padding_msg,
+            );
+
+            assert!(::core::mem::size_of::<#le_ident>() <= 8, #size_msg);
+        };
+
+        unsafe impl #krate::macro_rexp::Zeroable for #le_ident {}
+        unsafe impl #krate::macro_rexp::Pod for #le_ident {}
+
+        impl #le_ident {
+            pub fn new(value: #ident) -> Self {
+                Self { #(#members: ::core::convert::From::from(value.#members),)* }
+            }
+
+            pub fn get(self) -> #ident {
+                #ident { #(#members: ::core::convert::From::from(self.#members),)* }
+            }
+        }
+
+        impl ::core::convert::From<#ident> for #le_ident {
+            fn from(value: #ident) -> Self {
+                Self::new(value)
+            }
+        }
+
+        impl ::core::convert::From<#le_ident> for #ident {
+            fn from(value: #le_ident) -> Self {
+                value.get()
+            }
+        }
+
+        impl #krate::MarshaledTy for #ident {
+            type Prim = u64;
+
+            fn into_prim(me: Self) -> Self::Prim {
+                #krate::macro_rexp::pack_prim(#le_ident::new(me))
+            }
+
+            fn from_prim(me: Self::Prim) -> #krate::macro_rexp::Option<Self> {
+                #krate::macro_rexp::unpack_prim::<#le_ident>(me).map(#le_ident::get)
+            }
+        }
+    })
+}
@@ -0,0 +1,182 @@
+//! Compares `MemoryRead`'s typed loads against the two-lookup implementation they replaced.
+
+use std::any::type_name;
+
+use anyhow::Context;
+use bytemuck::Pod;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use crt_marshal_host::{size_of_32, MemoryRead};
+
+const MEMORY_SIZE: usize = 1 << 16;
+const COUNT: usize = 4096;
+
+/// The loads of `MemoryRead` as they were before they shared a single bounds check.
+mod two_lookups {
+    use super::*;
+
+    pub fn load_range(mem: &[u8], base: u32, len: u32) -> anyhow::Result<&[u8]> {
+        mem.get(base as usize..)
+            .and_then(|s| s.get(..len as usize))
+            .with_context(|| {
+                format!(
+                    "failed to read memory range from {base} to {len} (memory size: {})",
+                    mem.len()
+                )
+            })
+    }
+
+    pub fn load_struct<T: Pod>(mem: &[u8], ptr: u32) -> anyhow::Result<&T> {
+        bytemuck::try_from_bytes(load_range(mem, ptr, size_of_32::<T>())?).map_err(|err| {
+            anyhow::anyhow!(
+                "failed to parse object (ty: {}, base: {ptr}): {err}",
+                type_name::<T>()
+            )
+        })
+    }
+
+    pub fn load_slice<T: Pod>(mem: &[u8], base: u32, len: u32) -> anyhow::Result<&[T]> {
+        bytemuck::try_cast_slice(load_range(
+            mem,
+            base,
+            len.checked_mul(size_of_32::<T>())
+                .context("slice is too big")?,
+        )?)
+        .map_err(|err| {
+            anyhow::anyhow!(
+                "failed to parse slice (ty: {}, base: {base}, len: {len}): {err}",
+                type_name::<T>()
+            )
+        })
+    }
+
+    pub fn load_str(mem: &[u8], base: u32, len: u32) -> anyhow::Result<&str> {
+        load_range(mem, base, len)
+            .and_then(|data| std::str::from_utf8(data).context("invalid UTF-8"))
+    }
+}
+
+/// A memory filled with ASCII, so that any range of it is also a valid string, along with a set
+/// of `(base, len)` pairs scattered throughout it. Bases are aligned to 16 bytes.
+fn inputs(max_len: u32) -> (Vec<u32>, Vec<(u32, u32)>) {
+    // A cheap xorshift so that the inputs are the same on every run.
+    let mut state = 0x2545_F491_4F6C_DD1Du64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    // Back the memory with `u32`s so that it is suitably aligned for every load.
+    let memory = (0..MEMORY_SIZE / 4)
+        .map(|i| u32::from_le_bytes([b'a' + (i % 26) as u8; 4]))
+        .collect();
+
+    let ranges = (0..COUNT)
+        .map(|_| {
+            let raw = next();
+            let len = (raw >> 32) as u32 % (max_len + 1);
+            let base = (raw as u32 % (MEMORY_SIZE as u32 - max_len * 4)) & !15;
+            (base, len)
+        })
+        .collect();
+
+    (memory, ranges)
+}
+
+fn bench_load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("load");
+    group.throughput(Throughput::Elements(COUNT as u64));
+
+    let (memory, ranges) = inputs(32);
+    let memory: &[u8] = bytemuck::cast_slice(&memory);
+
+    group.bench_with_input(
+        BenchmarkId::new("MemoryRead", "struct"),
+        &ranges,
+        |b, ranges| {
+            b.iter(|| {
+                let mem = black_box(memory);
+                ranges.iter().fold(0u32, |sum, &(base, _)| {
+                    let value = mem.load_struct_raw::<[u32; 4]>(base).unwrap();
+                    sum.wrapping_add(value[0])
+                })
+            })
+        },
+    );
+
+    group.bench_with_input(
+        BenchmarkId::new("two-lookups", "struct"),
+        &ranges,
+        |b, ranges| {
+            b.iter(|| {
+                let mem = black_box(memory);
+                ranges.iter().fold(0u32, |sum, &(base, _)| {
+                    let value = two_lookups::load_struct::<[u32; 4]>(mem, base).unwrap();
+                    sum.wrapping_add(value[0])
+                })
+            })
+        },
+    );
+
+    group.bench_with_input(
+        BenchmarkId::new("MemoryRead", "slice"),
+        &ranges,
+        |b, ranges| {
+            b.iter(|| {
+                let mem = black_box(memory);
+                ranges.iter().fold(0usize, |sum, &(base, len)| {
+                    sum.wrapping_add(mem.load_slice_raw::<u32>(base, len).unwrap().len())
+                })
+            })
+        },
+    );
+
+    group.bench_with_input(
+        BenchmarkId::new("two-lookups", "slice"),
+        &ranges,
+        |b, ranges| {
+            b.iter(|| {
+                let mem = black_box(memory);
+                ranges.iter().fold(0usize, |sum, &(base, len)| {
+                    sum.wrapping_add(
+                        two_lookups::load_slice::<u32>(mem, base, len)
+                            .unwrap()
+                            .len(),
+                    )
+                })
+            })
+        },
+    );
+
+    group.bench_with_input(
+        BenchmarkId::new("MemoryRead", "str"),
+        &ranges,
+        |b, ranges| {
+            b.iter(|| {
+                let mem = black_box(memory);
+                ranges.iter().fold(0usize, |sum, &(base, len)| {
+                    sum.wrapping_add(mem.load_str_raw(base, len).unwrap().len())
+                })
+            })
+        },
+    );
+
+    group.bench_with_input(
+        BenchmarkId::new("two-lookups", "str"),
+        &ranges,
+        |b, ranges| {
+            b.iter(|| {
+                let mem = black_box(memory);
+                ranges.iter().fold(0usize, |sum, &(base, len)| {
+                    sum.wrapping_add(two_lookups::load_str(mem, base, len).unwrap().len())
+                })
+            })
+        },
+    );
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_load);
+criterion_main!(benches);
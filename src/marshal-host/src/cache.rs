@@ -0,0 +1,260 @@
+//! An on-disk cache of precompiled modules so launches can skip compilation.
+//!
+//! Entries are keyed by the digest of the module's code and by the engine's
+//! [precompile compatibility hash](wasmtime::Engine::precompile_compatibility_hash), which covers
+//! the wasmtime version, the compilation target, and every setting affecting code generation. Each
+//! entry records the hash of its payload, which is checked before anything is handed to
+//! [`Module::deserialize`].
+
+use std::{
+    fs,
+    hash::Hash as _,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use blake3::Hash;
+use wasmall::util::write_atomic;
+use wasmtime::{Engine, Module};
+
+pub const CACHE_ENTRY_MAGIC: [u8; 8] = *b"CRTCWASM";
+
+const HEADER_LEN: usize = CACHE_ENTRY_MAGIC.len() + 2 * blake3::OUT_LEN;
+
+// === CacheKey === //
+
+/// Adapts a blake3 hasher to [`std::hash::Hasher`] so engine state can be folded into a stable key.
+struct StableHasher(blake3::Hasher);
+
+impl std::hash::Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        unreachable!("use the underlying blake3 hasher instead");
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+}
+
+/// Derives the key under which the module with the specified digest is cached for `engine`.
+pub fn cache_key(engine: &Engine, module_digest: Hash) -> Hash {
+    let mut hasher = StableHasher(blake3::Hasher::new());
+    hasher.0.update(module_digest.as_bytes());
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+    hasher.0.finalize()
+}
+
+// === ModuleCache === //
+
+#[derive(Debug, Clone, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+
+    /// The number of entries which were discarded because they failed their integrity check or
+    /// could not be deserialized.
+    pub corrupted: u64,
+}
+
+#[derive(Debug)]
+pub struct ModuleCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    stats: CacheStats,
+}
+
+impl ModuleCache {
+    /// Opens the cache in `dir`, creating the directory if necessary. The cache is limited to
+    /// 1 GiB by default.
+    pub fn new(dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create module cache directory {dir:?}"))?;
+
+        Ok(Self {
+            dir,
+            max_bytes: 1 << 30,
+            stats: CacheStats::default(),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Sets the total size of the entries above which the least recently used ones are evicted.
+    pub fn set_max_bytes(&mut self, max_bytes: u64) {
+        self.max_bytes = max_bytes;
+    }
+
+    pub fn stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    fn entry_path(&self, key: Hash) -> PathBuf {
+        self.dir.join(format!("{key}.cwasm"))
+    }
+
+    /// Loads the module with the specified digest from the cache, returning `None` on a miss.
+    /// Entries which are corrupted or which the engine rejects are deleted and treated as misses.
+    pub fn get(&mut self, engine: &Engine, module_digest: Hash) -> anyhow::Result<Option<Module>> {
+        let key = cache_key(engine, module_digest);
+        let path = self.entry_path(key);
+
+        let entry = match fs::read(&path) {
+            Ok(entry) => entry,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                self.stats.misses += 1;
+                return Ok(None);
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to read cache entry {path:?}"))
+            }
+        };
+
+        let Some(payload) = Self::check_entry(&entry, key) else {
+            self.discard(&path)?;
+            return Ok(None);
+        };
+
+        // Safety: the payload was written by `Module::serialize` for an engine with a matching
+        // compatibility hash and has not been modified since, as its hash attests.
+        let module = match unsafe { Module::deserialize(engine, payload) } {
+            Ok(module) => module,
+            Err(_) => {
+                self.discard(&path)?;
+                return Ok(None);
+            }
+        };
+
+        // Bump the modification time so eviction treats the entry as recently used.
+        if let Ok(file) = fs::File::options().write(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+
+        self.stats.hits += 1;
+        Ok(Some(module))
+    }
+
+    fn check_entry(entry: &[u8], key: Hash) -> Option<&[u8]> {
+        if entry.len() < HEADER_LEN || entry[..CACHE_ENTRY_MAGIC.len()] != CACHE_ENTRY_MAGIC {
+            return None;
+        }
+
+        let (header, payload) = entry.split_at(HEADER_LEN);
+        let header = &header[CACHE_ENTRY_MAGIC.len()..];
+        let (entry_key, payload_hash) = header.split_at(blake3::OUT_LEN);
+
+        (entry_key == key.as_bytes() && payload_hash == blake3::hash(payload).as_bytes())
+            .then_some(payload)
+    }
+
+    fn discard(&mut self, path: &Path) -> anyhow::Result<()> {
+        self.stats.corrupted += 1;
+        self.stats.misses += 1;
+
+        match fs::remove_file(path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => {
+                Err(err).with_context(|| format!("failed to remove corrupted cache entry {path:?}"))
+            }
+        }
+    }
+
+    /// Serializes `module` into the cache under the specified digest and evicts old entries if the
+    /// cache has grown too large.
+    pub fn put(
+        &mut self,
+        engine: &Engine,
+        module_digest: Hash,
+        module: &Module,
+    ) -> anyhow::Result<()> {
+        let key = cache_key(engine, module_digest);
+        let path = self.entry_path(key);
+        let payload = module.serialize().context("failed to serialize module")?;
+
+        let mut entry = Vec::with_capacity(HEADER_LEN + payload.len());
+        entry.extend_from_slice(&CACHE_ENTRY_MAGIC);
+        entry.extend_from_slice(key.as_bytes());
+        entry.extend_from_slice(blake3::hash(&payload).as_bytes());
+        entry.extend_from_slice(&payload);
+
+        write_atomic(&path, &entry, "cache entry", false)?;
+
+        self.evict(Some(&path))
+    }
+
+    /// Loads the module with the specified digest from the cache or, on a miss, compiles the code
+    /// produced by `assemble` and caches the result. `assemble` is only called on a miss so callers
+    /// can skip assembling the module entirely when it is already cached.
+    pub fn get_or_compile(
+        &mut self,
+        engine: &Engine,
+        module_digest: Hash,
+        assemble: impl FnOnce() -> anyhow::Result<Vec<u8>>,
+    ) -> anyhow::Result<Module> {
+        if let Some(module) = self.get(engine, module_digest)? {
+            return Ok(module);
+        }
+
+        let code = assemble()?;
+        let actual_digest = blake3::hash(&code);
+        anyhow::ensure!(
+            actual_digest == module_digest,
+            "assembled module digest mismatch; expected {module_digest}, got {actual_digest}"
+        );
+
+        let module = Module::new(engine, &code).context("failed to compile module")?;
+        self.put(engine, module_digest, &module)?;
+
+        Ok(module)
+    }
+
+    /// Removes the least recently used entries until the cache fits within its size limit. The
+    /// entry at `keep`, if any, is never evicted.
+    pub fn evict(&mut self, keep: Option<&Path>) -> anyhow::Result<()> {
+        let mut entries = Vec::new();
+        let mut total = 0;
+
+        let read_dir = fs::read_dir(&self.dir)
+            .with_context(|| format!("failed to list module cache directory {:?}", self.dir))?;
+
+        for dirent in read_dir {
+            let dirent = dirent?;
+            let path = dirent.path();
+            if path.extension().is_none_or(|ext| ext != "cwasm") {
+                continue;
+            }
+
+            let meta = dirent.metadata()?;
+            total += meta.len();
+
+            if Some(path.as_path()) != keep {
+                entries.push((meta.modified()?, meta.len(), path));
+            }
+        }
+
+        entries.sort_unstable();
+
+        for (_, len, path) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+
+            match fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("failed to evict cache entry {path:?}"))
+                }
+            }
+            total -= len;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+//! Cooperative cancellation of long-running guest calls.
+//!
+//! Cancelling a [`CancelToken`] sets the guest's cancellation flag, which guests poll with
+//! [`is_cancelled`](crate::is_cancelled). Guests which don't stop within a grace period are
+//! trapped with a [`CancelTimeout`].
+//!
+//! The flag is written from the store's epoch deadline callback, so the engine must have been
+//! created with [`Config::epoch_interruption`](wasmtime::Config::epoch_interruption) enabled and
+//! something must be incrementing its epoch.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::Context;
+use wasmtime::{AsContextMut, Instance, Memory, Store, UpdateDeadline};
+
+use crate::{LeU32, MemoryWrite, WasmPtr, CANCEL_FLAG_EXPORT};
+
+// === CancelToken === //
+
+/// A flag the host can trip from any thread to cancel the guest calls of the stores it is
+/// installed in.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The trap raised when a guest keeps running after its grace period.
+#[derive(Debug, Copy, Clone)]
+pub struct CancelTimeout {
+    /// The number of epoch ticks the guest was given to stop.
+    pub grace_ticks: u64,
+}
+
+impl fmt::Display for CancelTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "guest ignored its cancellation for {} epoch tick(s)",
+            self.grace_ticks
+        )
+    }
+}
+
+impl std::error::Error for CancelTimeout {}
+
+// === Cancellation === //
+
+/// A [`CancelToken`] installed into a store.
+#[derive(Debug, Clone)]
+pub struct Cancellation {
+    token: CancelToken,
+    flag: Option<(Memory, WasmPtr<LeU32>)>,
+}
+
+impl Cancellation {
+    /// Makes the calls of `instance` cancellable by `token`, replacing the store's epoch deadline
+    /// callback. Guests which don't export their flag can only be stopped by the backstop.
+    pub fn install<T>(
+        store: &mut Store<T>,
+        instance: &Instance,
+        token: &CancelToken,
+        grace_ticks: u64,
+    ) -> anyhow::Result<Self> {
+        // Looking up the flag calls into the guest, which mustn't be interrupted by a stale
+        // deadline.
+        store.set_epoch_deadline(1);
+        store.epoch_deadline_callback(|_| Ok(UpdateDeadline::Continue(1)));
+
+        let flag = match instance.get_typed_func::<(), u32>(&mut *store, CANCEL_FLAG_EXPORT) {
+            Ok(flag_func) => {
+                let addr = flag_func
+                    .call(&mut *store, ())
+                    .context("failed to get the guest's cancellation flag")?;
+
+                let memory = instance
+                    .get_memory(&mut *store, "memory")
+                    .context("guest does not export its memory")?;
+
+                Some((memory, WasmPtr::new(LeU32::new(addr))))
+            }
+            Err(_) => None,
+        };
+
+        let me = Self {
+            token: token.clone(),
+            flag,
+        };
+
+        let cancellation = me.clone();
+        let mut ticks_since_cancel = None;
+
+        store.epoch_deadline_callback(move |mut cx| {
+            if !cancellation.token.is_cancelled() {
+                ticks_since_cancel = None;
+                return Ok(UpdateDeadline::Continue(1));
+            }
+
+            // The flag is rewritten every tick in case the cancellation was reset and tripped
+            // again in between.
+            cancellation.set_flag(&mut cx, true)?;
+
+            let ticks = *ticks_since_cancel.insert(ticks_since_cancel.map_or(0, |ticks| ticks + 1));
+
+            if ticks > grace_ticks {
+                return Err(CancelTimeout { grace_ticks }.into());
+            }
+
+            Ok(UpdateDeadline::Continue(1))
+        });
+
+        Ok(me)
+    }
+
+    pub fn token(&self) -> &CancelToken {
+        &self.token
+    }
+
+    /// Whether the guest exports a flag it can poll.
+    pub fn is_cooperative(&self) -> bool {
+        self.flag.is_some()
+    }
+
+    /// Clears the token and the guest's flag so new work can be started.
+    pub fn reset(&self, mut store: impl AsContextMut) -> anyhow::Result<()> {
+        self.token.0.store(false, Ordering::Relaxed);
+        self.set_flag(&mut store, false)
+    }
+
+    fn set_flag(&self, mut store: impl AsContextMut, cancelled: bool) -> anyhow::Result<()> {
+        let Some((memory, ptr)) = self.flag else {
+            return Ok(());
+        };
+
+        memory
+            .data_mut(&mut store)
+            .write_struct(ptr, &LeU32::new(cancelled.into()))
+            .context("failed to write the guest's cancellation flag")
+    }
+}
@@ -0,0 +1,142 @@
+//! The optional protocols a guest reports supporting.
+//!
+//! Guests export their [`WasmCapabilities`] with [`export_capabilities!`](crate::export_capabilities)
+//! and hosts read them once, right after instantiating the guest, with
+//! [`install_capabilities`]. Optional fast paths should then be gated on the
+//! [`GuestCapabilities`] kept in the store's data.
+
+use anyhow::Context;
+use wasmtime::{AsContextMut, Instance, Store};
+
+use crate::{CapabilityFlags, LeU32, MemoryRead, WasmCapabilities, WasmPtr, CAPABILITIES_EXPORT};
+
+// === GuestCapabilities === //
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GuestCapabilities {
+    /// The layout version of the structure the guest exported, or zero if it didn't export one.
+    pub version: u32,
+    pub flags: CapabilityFlags,
+    pub scratch_stack_version: u32,
+    pub event_queue_version: u32,
+}
+
+impl Default for GuestCapabilities {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+impl GuestCapabilities {
+    /// The capabilities of guests which don't export any.
+    pub const NONE: Self = Self {
+        version: 0,
+        flags: CapabilityFlags::NONE,
+        scratch_stack_version: 0,
+        event_queue_version: 1,
+    };
+
+    /// Reads the capabilities `instance` exports, returning [`NONE`](Self::NONE) if it doesn't
+    /// export any.
+    pub fn query(mut store: impl AsContextMut, instance: &Instance) -> anyhow::Result<Self> {
+        let Ok(query) = instance.get_typed_func::<(), u32>(&mut store, CAPABILITIES_EXPORT) else {
+            return Ok(Self::NONE);
+        };
+
+        let addr = query
+            .call(&mut store, ())
+            .context("failed to query the guest's capabilities")?;
+
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .context("guest does not export its memory")?;
+
+        let memory = memory.data(&store);
+        let version = memory
+            .load_struct(WasmPtr::<LeU32>::new(LeU32::new(addr)))
+            .context("failed to read the guest's capabilities version")?
+            .get();
+
+        anyhow::ensure!(
+            version >= 1,
+            "guest reported capabilities with an invalid version of 0"
+        );
+
+        let raw = memory
+            .load_struct(WasmPtr::<WasmCapabilities>::new(LeU32::new(addr)))
+            .context("failed to read the guest's capabilities")?;
+
+        Ok(Self {
+            version,
+            flags: raw.flags,
+            scratch_stack_version: raw.scratch_stack_version.get(),
+            event_queue_version: raw.event_queue_version.get(),
+        })
+    }
+
+    pub fn supports(&self, flags: CapabilityFlags) -> bool {
+        self.flags.contains(flags)
+    }
+
+    /// Fails if the guest lacks any of the `flags`.
+    pub fn require(&self, flags: CapabilityFlags) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.supports(flags),
+            "guest lacks the capabilities {:?} (it supports {:?})",
+            CapabilityFlags::from_bits_retain(flags.bits() & !self.flags.bits()),
+            self.flags,
+        );
+
+        Ok(())
+    }
+}
+
+// === StoreHasCapabilities === //
+
+pub trait StoreHasCapabilities {
+    fn guest_capabilities(&self) -> &GuestCapabilities;
+
+    fn guest_capabilities_mut(&mut self) -> &mut GuestCapabilities;
+}
+
+/// Queries the capabilities of `instance` and records them in the store's data.
+pub fn install_capabilities<T: StoreHasCapabilities>(
+    store: &mut Store<T>,
+    instance: &Instance,
+) -> anyhow::Result<GuestCapabilities> {
+    let capabilities = GuestCapabilities::query(&mut *store, instance)?;
+    *store.data_mut().guest_capabilities_mut() = capabilities;
+    Ok(capabilities)
+}
+
+pub trait ContextCapabilitiesExt: Sized + AsContextMut<Data = Self::Data_> {
+    type Data_: StoreHasCapabilities;
+
+    fn supports(&self, flags: CapabilityFlags) -> bool {
+        self.as_context()
+            .data()
+            .guest_capabilities()
+            .supports(flags)
+    }
+
+    /// Runs `fast` if the guest supports every one of the `flags` and `fallback` otherwise.
+    fn gated<R>(
+        &mut self,
+        flags: CapabilityFlags,
+        fast: impl FnOnce(&mut Self) -> R,
+        fallback: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        if self.supports(flags) {
+            fast(self)
+        } else {
+            fallback(self)
+        }
+    }
+}
+
+impl<T: AsContextMut> ContextCapabilitiesExt for T
+where
+    T::Data: StoreHasCapabilities,
+{
+    type Data_ = T::Data;
+}
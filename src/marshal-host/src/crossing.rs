@@ -0,0 +1,266 @@
+//! A fixed-size record of the most recent calls across the host-guest boundary, for attaching to
+//! crash reports.
+//!
+//! Each crossing only stores a label and the raw bits of its first few primitive arguments, so
+//! recording is cheap enough to leave on in production. Host functions are recorded by binding them
+//! through [`Recorded`] and guest callbacks by calling them with
+//! [`WasmFuncRef::call_recorded`].
+
+use std::{collections::VecDeque, fmt, sync::Arc};
+
+use anyhow::Context;
+
+use crate::{
+    for_each_prim_bits, impl_variadic, HostSideMarshaledFunc, MarshaledTy, MarshaledTyList,
+    WasmFuncRef, WasmPrimitiveList,
+};
+
+/// The number of arguments recorded per crossing. Later arguments are dropped.
+pub const MAX_RECORDED_ARGS: usize = 6;
+
+// === CrossingLog === //
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CrossingKind {
+    /// The guest called into the host.
+    HostCall,
+
+    /// The host called back into the guest.
+    GuestCall,
+}
+
+/// The bits of the first [`MAX_RECORDED_ARGS`] primitive arguments of a call.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ArgSummary {
+    args: [u64; MAX_RECORDED_ARGS],
+    arg_count: u8,
+    truncated: bool,
+}
+
+impl ArgSummary {
+    pub fn capture(args: &impl WasmPrimitiveList) -> Self {
+        let mut summary = Self::default();
+
+        for_each_prim_bits(args, |bits| {
+            if let Some(slot) = summary.args.get_mut(summary.arg_count as usize) {
+                *slot = bits;
+                summary.arg_count += 1;
+            } else {
+                summary.truncated = true;
+            }
+        });
+
+        summary
+    }
+
+    pub fn args(&self) -> &[u64] {
+        &self.args[..self.arg_count as usize]
+    }
+
+    /// Whether arguments were dropped because there were more than [`MAX_RECORDED_ARGS`].
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+impl fmt::Display for ArgSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("(")?;
+        for (i, arg) in self.args().iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{arg:#x}")?;
+        }
+        if self.truncated {
+            f.write_str(", ...")?;
+        }
+        f.write_str(")")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Crossing {
+    /// The index of the crossing since the log was created.
+    pub seq: u64,
+    pub kind: CrossingKind,
+    pub label: Arc<str>,
+    pub args: ArgSummary,
+}
+
+impl fmt::Display for Crossing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            CrossingKind::HostCall => "host",
+            CrossingKind::GuestCall => "guest",
+        };
+
+        write!(f, "#{} {kind} {}{}", self.seq, self.label, self.args)
+    }
+}
+
+/// A ring buffer of the most recent crossings of a store.
+#[derive(Debug, Clone)]
+pub struct CrossingLog {
+    entries: VecDeque<Crossing>,
+    capacity: usize,
+    next_seq: u64,
+}
+
+impl CrossingLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            next_seq: 0,
+        }
+    }
+
+    pub fn record(&mut self, kind: CrossingKind, label: &Arc<str>, args: &impl WasmPrimitiveList) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(Crossing {
+            seq,
+            kind,
+            label: label.clone(),
+            args: ArgSummary::capture(args),
+        });
+    }
+
+    /// The recorded crossings, oldest first.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = &Crossing> + '_ {
+        self.entries.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Takes a snapshot of the log for an error report.
+    pub fn dump(&self) -> CrossingDump {
+        CrossingDump(self.entries.iter().cloned().collect())
+    }
+}
+
+/// A snapshot of a [`CrossingLog`], displayed as one crossing per line.
+#[derive(Debug, Clone)]
+pub struct CrossingDump(pub Vec<Crossing>);
+
+impl fmt::Display for CrossingDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "last {} boundary crossing(s):", self.0.len())?;
+        for crossing in &self.0 {
+            write!(f, "\n  {crossing}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Attaches the crossings recorded by the store to an error, unless it already has a dump
+/// attached.
+pub fn attach_crossings(err: anyhow::Error, data: &mut impl StoreHasCrossingLog) -> anyhow::Error {
+    if err.downcast_ref::<CrossingDump>().is_some() {
+        return err;
+    }
+
+    match data.crossing_log() {
+        Some(log) => err.context(log.dump()),
+        None => err,
+    }
+}
+
+// === StoreHasCrossingLog === //
+
+pub trait StoreHasCrossingLog {
+    /// The store's log, or `None` if recording is disabled for it.
+    fn crossing_log(&mut self) -> Option<&mut CrossingLog>;
+}
+
+// === Recording === //
+
+/// A host function which records its calls in the store's [`CrossingLog`]. Arguments which fail to
+/// unmarshal are reported along with the log.
+pub struct Recorded<F> {
+    pub label: Arc<str>,
+    pub func: F,
+}
+
+impl<F> Recorded<F> {
+    pub fn new(label: impl Into<Arc<str>>, func: F) -> Self {
+        Self {
+            label: label.into(),
+            func,
+        }
+    }
+}
+
+macro_rules! impl_func_ty {
+    ($($ty:ident)*) => {
+        impl<D, F, Ret, $($ty: MarshaledTy,)*> HostSideMarshaledFunc<D, ($($ty,)*), Ret> for Recorded<F>
+        where
+            D: 'static + StoreHasCrossingLog,
+            Ret: MarshaledTyList,
+            F: 'static + Send + Sync + Fn(wasmtime::Caller<'_, D>, $($ty,)*) -> anyhow::Result<Ret>,
+        {
+            type PrimParams<'a> = (wasmtime::Caller<'a, D>, $(<$ty as MarshaledTy>::Prim,)*);
+            type PrimResults = anyhow::Result<Ret::Prims>;
+
+            #[allow(non_snake_case, unused_mut)]
+            fn wrap_host(self) -> impl for<'a> wasmtime::IntoFunc<D, Self::PrimParams<'a>, Self::PrimResults> {
+                let Self { label, func } = self;
+
+                move |mut caller: wasmtime::Caller<'_, D>, $($ty: <$ty as MarshaledTy>::Prim,)*| {
+                    let prims = ($($ty,)*);
+                    if let Some(log) = caller.data_mut().crossing_log() {
+                        log.record(CrossingKind::HostCall, &label, &prims);
+                    }
+
+                    let Some(($($ty,)*)) = <($($ty,)*)>::from_prims(prims) else {
+                        let err = anyhow::anyhow!("failed to parse arguments to {label}");
+                        return Err(attach_crossings(err, caller.data_mut()));
+                    };
+
+                    func(caller, $($ty),*).map(MarshaledTyList::into_prims)
+                }
+            }
+        }
+    };
+}
+
+impl_variadic!(impl_func_ty);
+
+impl<A, R> WasmFuncRef<A, R>
+where
+    A: MarshaledTyList,
+    R: MarshaledTyList,
+{
+    /// Calls the function like [`call`](Self::call), recording the call in the store's
+    /// [`CrossingLog`] and attaching the log to any error.
+    pub fn call_recorded<D: StoreHasCrossingLog>(
+        &self,
+        mut store: impl wasmtime::AsContextMut<Data = D>,
+        label: &Arc<str>,
+        args: A,
+    ) -> anyhow::Result<R> {
+        let args = A::into_prims(args);
+        let mut cx = store.as_context_mut();
+
+        if let Some(log) = cx.data_mut().crossing_log() {
+            log.record(CrossingKind::GuestCall, label, &args);
+        }
+
+        self.0
+            .call(&mut cx, args)
+            .and_then(|res| R::from_prims(res).context("failed to deserialize results"))
+            .map_err(|err| attach_crossings(err, cx.data_mut()))
+    }
+}
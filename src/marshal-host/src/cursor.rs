@@ -0,0 +1,346 @@
+//! Sequential parsing and writing of streams in guest memory.
+//!
+//! Command buffers, event queues, and other variable-length payloads are read through a
+//! [`MemoryCursor`] and written through a [`MemoryCursorMut`], which advance through a region of
+//! guest memory checked once up front. Every operation fails rather than reading or writing past
+//! the end of the region. Objects in streams are copied out so they needn't be aligned.
+
+use std::{borrow::Cow, mem::size_of, ops::Range};
+
+use anyhow::Context;
+use bytemuck::Pod;
+
+use crate::{size_of_32, MemoryRead, MemoryWrite, WasmSlice};
+
+/// LEB128 encodings of 64-bit integers are at most this many bytes long.
+const MAX_LEB128_LEN: usize = 10;
+
+// === Helpers === //
+
+#[cold]
+#[inline(never)]
+fn overrun_error(addr: u64, len: u64, remaining: usize) -> anyhow::Error {
+    anyhow::anyhow!(
+        "cursor overran its region accessing {len} bytes at {addr} ({remaining} bytes remaining)"
+    )
+}
+
+/// Tracks the position of a cursor in a region `len` bytes long starting at the guest address
+/// `base`.
+#[derive(Debug, Copy, Clone)]
+struct CursorState {
+    base: u32,
+    len: usize,
+    offset: usize,
+}
+
+impl CursorState {
+    fn new(base: u32, len: usize) -> Self {
+        Self {
+            base,
+            len,
+            offset: 0,
+        }
+    }
+
+    fn position(&self) -> u32 {
+        // (the region was checked to lie in the 32-bit address space)
+        self.base + self.offset as u32
+    }
+
+    fn remaining(&self) -> usize {
+        self.len - self.offset
+    }
+
+    fn take(&mut self, len: usize) -> anyhow::Result<Range<usize>> {
+        if len > self.remaining() {
+            return Err(overrun_error(
+                self.position().into(),
+                len as u64,
+                self.remaining(),
+            ));
+        }
+
+        let start = self.offset;
+        self.offset += len;
+        Ok(start..self.offset)
+    }
+
+    fn take_elems(&mut self, count: u32, size: u32) -> anyhow::Result<Range<usize>> {
+        let len = count as u64 * size as u64;
+        let remaining = self.remaining();
+
+        match usize::try_from(len) {
+            Ok(len) => self.take(len),
+            Err(_) => Err(overrun_error(self.position().into(), len, remaining)),
+        }
+    }
+
+    fn padding_to(&self, align: u32) -> anyhow::Result<usize> {
+        anyhow::ensure!(
+            align.is_power_of_two(),
+            "alignment {align} is not a power of two"
+        );
+
+        let misalignment = self.position() & (align - 1);
+        Ok(((align - misalignment) & (align - 1)) as usize)
+    }
+}
+
+// === MemoryCursor === //
+
+/// Reads sequentially from a region of guest memory.
+#[derive(Debug, Clone)]
+pub struct MemoryCursor<'a> {
+    region: &'a [u8],
+    state: CursorState,
+}
+
+impl<'a> MemoryCursor<'a> {
+    /// Creates a cursor over the `len` bytes of `memory` starting at `base`.
+    pub fn new(
+        memory: &'a (impl MemoryRead + ?Sized),
+        base: u32,
+        len: u32,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            region: memory.load_range(base, len)?,
+            state: CursorState::new(base, len as usize),
+        })
+    }
+
+    pub fn from_slice(
+        memory: &'a (impl MemoryRead + ?Sized),
+        region: WasmSlice<u8>,
+    ) -> anyhow::Result<Self> {
+        Self::new(memory, region.base.addr().get(), region.len.get())
+    }
+
+    /// The guest address of the next byte to be read.
+    pub fn position(&self) -> u32 {
+        self.state.position()
+    }
+
+    /// The number of bytes left to read.
+    pub fn remaining(&self) -> usize {
+        self.state.remaining()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    pub fn skip(&mut self, len: u32) -> anyhow::Result<()> {
+        self.state.take(len as usize).map(drop)
+    }
+
+    /// Skips to the next address aligned to `align` bytes, which must be a power of two.
+    pub fn align_to(&mut self, align: u32) -> anyhow::Result<()> {
+        let padding = self.state.padding_to(align)?;
+        self.state.take(padding).map(drop)
+    }
+
+    pub fn read_bytes(&mut self, len: u32) -> anyhow::Result<&'a [u8]> {
+        let region = self.region;
+        Ok(&region[self.state.take(len as usize)?])
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        let range = self.state.take(N)?;
+        Ok(self.region[range].try_into().unwrap())
+    }
+
+    pub fn read_struct<T: Pod>(&mut self) -> anyhow::Result<T> {
+        let range = self.state.take(size_of::<T>())?;
+        Ok(bytemuck::pod_read_unaligned(&self.region[range]))
+    }
+
+    /// Reads `len` consecutive objects, borrowing them if they happen to be aligned.
+    pub fn read_slice<T: Pod>(&mut self, len: u32) -> anyhow::Result<Cow<'a, [T]>> {
+        let region = self.region;
+        region[self.state.take_elems(len, size_of_32::<T>())?].read_slice_raw(0, len)
+    }
+
+    pub fn read_str(&mut self, len: u32) -> anyhow::Result<&'a str> {
+        std::str::from_utf8(self.read_bytes(len)?).context("invalid UTF-8")
+    }
+
+    /// Reads an unsigned LEB128 integer.
+    pub fn read_uleb128(&mut self) -> anyhow::Result<u64> {
+        let start = self.position();
+        let mut value = 0u64;
+
+        for i in 0..MAX_LEB128_LEN {
+            let [byte] = self.read_array()?;
+            let shift = i as u32 * 7;
+            let bits = (byte & 0x7F) as u64;
+
+            anyhow::ensure!(
+                bits << shift >> shift == bits,
+                "LEB128 integer at {start} overflows 64 bits"
+            );
+            value |= bits << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        anyhow::bail!("LEB128 integer at {start} is longer than {MAX_LEB128_LEN} bytes")
+    }
+
+    /// Reads a signed LEB128 integer.
+    pub fn read_sleb128(&mut self) -> anyhow::Result<i64> {
+        let start = self.position();
+        let mut value = 0i64;
+
+        for i in 0..MAX_LEB128_LEN {
+            let [byte] = self.read_array()?;
+            let shift = i as u32 * 7;
+            value |= ((byte & 0x7F) as i64) << shift;
+
+            if byte & 0x80 == 0 {
+                // The final group of the longest encoding only holds the sign bit.
+                anyhow::ensure!(
+                    i + 1 < MAX_LEB128_LEN || byte == 0 || byte == 0x7F,
+                    "LEB128 integer at {start} overflows 64 bits"
+                );
+
+                let shift = shift + 7;
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+
+                return Ok(value);
+            }
+        }
+
+        anyhow::bail!("LEB128 integer at {start} is longer than {MAX_LEB128_LEN} bytes")
+    }
+}
+
+// === MemoryCursorMut === //
+
+/// Writes sequentially into a region of guest memory.
+#[derive(Debug)]
+pub struct MemoryCursorMut<'a> {
+    region: &'a mut [u8],
+    state: CursorState,
+}
+
+impl<'a> MemoryCursorMut<'a> {
+    /// Creates a cursor over the `len` bytes of `memory` starting at `base`.
+    pub fn new(
+        memory: &'a mut (impl MemoryWrite + ?Sized),
+        base: u32,
+        len: u32,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            region: memory.load_range_mut(base, len)?,
+            state: CursorState::new(base, len as usize),
+        })
+    }
+
+    pub fn from_slice(
+        memory: &'a mut (impl MemoryWrite + ?Sized),
+        region: WasmSlice<u8>,
+    ) -> anyhow::Result<Self> {
+        Self::new(memory, region.base.addr().get(), region.len.get())
+    }
+
+    /// The guest address of the next byte to be written.
+    pub fn position(&self) -> u32 {
+        self.state.position()
+    }
+
+    /// The number of bytes left to write.
+    pub fn remaining(&self) -> usize {
+        self.state.remaining()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// The number of bytes written so far, including those skipped.
+    pub fn written(&self) -> usize {
+        self.state.offset
+    }
+
+    /// Skips over `len` bytes, leaving them as they were.
+    pub fn skip(&mut self, len: u32) -> anyhow::Result<()> {
+        self.state.take(len as usize).map(drop)
+    }
+
+    /// Zeroes up to the next address aligned to `align` bytes, which must be a power of two.
+    pub fn align_to(&mut self, align: u32) -> anyhow::Result<()> {
+        let padding = self.state.padding_to(align)?;
+        let range = self.state.take(padding)?;
+        self.region[range].fill(0);
+        Ok(())
+    }
+
+    pub fn write_bytes(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        let range = self.state.take(data.len())?;
+        self.region[range].copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn write_struct<T: Pod>(&mut self, value: &T) -> anyhow::Result<()> {
+        self.write_bytes(bytemuck::bytes_of(value))
+    }
+
+    pub fn write_slice<T: Pod>(&mut self, values: &[T]) -> anyhow::Result<()> {
+        self.write_bytes(bytemuck::cast_slice(values))
+    }
+
+    /// Writes the bytes of a string, without its length.
+    pub fn write_str(&mut self, value: &str) -> anyhow::Result<()> {
+        self.write_bytes(value.as_bytes())
+    }
+
+    /// Writes an unsigned LEB128 integer.
+    pub fn write_uleb128(&mut self, mut value: u64) -> anyhow::Result<()> {
+        let mut buf = [0; MAX_LEB128_LEN];
+        let mut len = 0;
+
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+
+            if value == 0 {
+                buf[len] = byte;
+                len += 1;
+                break;
+            }
+
+            buf[len] = byte | 0x80;
+            len += 1;
+        }
+
+        self.write_bytes(&buf[..len])
+    }
+
+    /// Writes a signed LEB128 integer.
+    pub fn write_sleb128(&mut self, mut value: i64) -> anyhow::Result<()> {
+        let mut buf = [0; MAX_LEB128_LEN];
+        let mut len = 0;
+
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+
+            let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+            if done {
+                buf[len] = byte;
+                len += 1;
+                break;
+            }
+
+            buf[len] = byte | 0x80;
+            len += 1;
+        }
+
+        self.write_bytes(&buf[..len])
+    }
+}
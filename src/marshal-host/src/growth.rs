@@ -0,0 +1,181 @@
+//! Notifications of guest memory growth.
+//!
+//! A [`GrowthNotifier`] wraps the store's [`ResourceLimiter`], reporting each attempt its guest
+//! makes to grow a memory to the callbacks registered on it. The wrapped limiter still decides
+//! whether growth is allowed.
+
+use std::{fmt, sync::Arc};
+
+use wasmtime::{ResourceLimiter, Store, StoreLimits};
+
+// === MemoryGrowth === //
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GrowthOutcome {
+    /// The limiter allowed the memory to grow.
+    Allowed,
+
+    /// The limiter refused to let the memory grow.
+    Denied,
+
+    /// The limiter allowed the memory to grow but the growth itself failed.
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryGrowth {
+    /// The name of the plugin whose store the memory belongs to.
+    pub plugin: Arc<str>,
+
+    /// The size of the memory in bytes before growing.
+    pub old_size: usize,
+
+    /// The requested size of the memory in bytes.
+    pub new_size: usize,
+
+    /// The maximum size of the memory in bytes, if it has one.
+    pub maximum: Option<usize>,
+    pub outcome: GrowthOutcome,
+}
+
+impl fmt::Display for MemoryGrowth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outcome = match self.outcome {
+            GrowthOutcome::Allowed => "grew",
+            GrowthOutcome::Denied => "was denied growth",
+            GrowthOutcome::Failed => "failed to grow",
+        };
+
+        write!(
+            f,
+            "memory of {} {outcome} from {} to {} bytes",
+            self.plugin, self.old_size, self.new_size
+        )
+    }
+}
+
+// === GrowthNotifier === //
+
+type GrowthCallback = Box<dyn FnMut(&MemoryGrowth) + Send + Sync>;
+
+pub struct GrowthNotifier<L = StoreLimits> {
+    plugin: Arc<str>,
+    limiter: L,
+    callbacks: Vec<GrowthCallback>,
+
+    /// The most recent growth which was allowed, in case wasmtime reports that it failed.
+    last_allowed: Option<MemoryGrowth>,
+}
+
+impl<L: fmt::Debug> fmt::Debug for GrowthNotifier<L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GrowthNotifier")
+            .field("plugin", &self.plugin)
+            .field("limiter", &self.limiter)
+            .field("callbacks", &self.callbacks.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<L: ResourceLimiter> GrowthNotifier<L> {
+    pub fn new(plugin: impl Into<Arc<str>>, limiter: L) -> Self {
+        Self {
+            plugin: plugin.into(),
+            limiter,
+            callbacks: Vec::new(),
+            last_allowed: None,
+        }
+    }
+
+    pub fn plugin(&self) -> &Arc<str> {
+        &self.plugin
+    }
+
+    pub fn limiter(&mut self) -> &mut L {
+        &mut self.limiter
+    }
+
+    pub fn on_growth(&mut self, f: impl 'static + Send + Sync + FnMut(&MemoryGrowth)) -> &mut Self {
+        self.callbacks.push(Box::new(f));
+        self
+    }
+
+    fn notify(&mut self, growth: &MemoryGrowth) {
+        for callback in &mut self.callbacks {
+            callback(growth);
+        }
+    }
+}
+
+impl<L: ResourceLimiter> ResourceLimiter for GrowthNotifier<L> {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        let allowed = self.limiter.memory_growing(current, desired, maximum)?;
+        let growth = MemoryGrowth {
+            plugin: self.plugin.clone(),
+            old_size: current,
+            new_size: desired,
+            maximum,
+            outcome: if allowed {
+                GrowthOutcome::Allowed
+            } else {
+                GrowthOutcome::Denied
+            },
+        };
+
+        self.notify(&growth);
+        self.last_allowed = allowed.then_some(growth);
+        Ok(allowed)
+    }
+
+    fn memory_grow_failed(&mut self, error: anyhow::Error) -> anyhow::Result<()> {
+        if let Some(mut growth) = self.last_allowed.take() {
+            growth.outcome = GrowthOutcome::Failed;
+            self.notify(&growth);
+        }
+
+        self.limiter.memory_grow_failed(error)
+    }
+
+    fn table_growing(
+        &mut self,
+        current: u32,
+        desired: u32,
+        maximum: Option<u32>,
+    ) -> anyhow::Result<bool> {
+        self.limiter.table_growing(current, desired, maximum)
+    }
+
+    fn table_grow_failed(&mut self, error: anyhow::Error) -> anyhow::Result<()> {
+        self.limiter.table_grow_failed(error)
+    }
+
+    fn instances(&self) -> usize {
+        self.limiter.instances()
+    }
+
+    fn tables(&self) -> usize {
+        self.limiter.tables()
+    }
+
+    fn memories(&self) -> usize {
+        self.limiter.memories()
+    }
+}
+
+// === StoreHasGrowthNotifier === //
+
+pub trait StoreHasGrowthNotifier {
+    type Limiter: ResourceLimiter;
+
+    fn growth_notifier(&mut self) -> &mut GrowthNotifier<Self::Limiter>;
+}
+
+/// Makes the store's [`GrowthNotifier`] its resource limiter.
+pub fn install_growth_notifier<T: StoreHasGrowthNotifier>(store: &mut Store<T>) {
+    store.limiter(|data| data.growth_notifier());
+}
@@ -0,0 +1,148 @@
+//! Hooks run around every call of a host function, for cross-cutting behavior like permission
+//! checks, metrics, and rate limiting.
+//!
+//! Interceptors are added to a [`BindingRegistry`](crate::registry::BindingRegistry) and run, in
+//! the order they were added, around the functions it binds with
+//! [`bind_intercepted`](crate::registry::BindingRegistry::bind_intercepted). Functions only see the
+//! interceptors which were added before they were bound.
+
+use std::{fmt, sync::Arc};
+
+use crate::{
+    crossing::ArgSummary, impl_variadic, HostSideMarshaledFunc, MarshaledTy, MarshaledTyList,
+};
+
+// === Interceptor === //
+
+/// The host function being called.
+#[derive(Debug, Clone)]
+pub struct CallInfo {
+    pub module: Arc<str>,
+    pub name: Arc<str>,
+}
+
+impl fmt::Display for CallInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}::{}", self.module, self.name)
+    }
+}
+
+pub trait Interceptor: Send + Sync {
+    /// Runs before the function with the primitives of its arguments. Returning an error fails
+    /// the call without running the function or any later interceptors.
+    fn before(&self, call: &CallInfo, args: &ArgSummary) -> anyhow::Result<()> {
+        let _ = (call, args);
+        Ok(())
+    }
+
+    /// Runs after the function with the primitives of its results, or its error. Returning an
+    /// error fails the call. Only the interceptors whose `before` hook succeeded are run, in
+    /// reverse order.
+    fn after(
+        &self,
+        call: &CallInfo,
+        args: &ArgSummary,
+        result: Result<&ArgSummary, &anyhow::Error>,
+    ) -> anyhow::Result<()> {
+        let _ = (call, args, result);
+        Ok(())
+    }
+}
+
+/// An ordered list of interceptors.
+#[derive(Clone, Default)]
+pub struct InterceptorChain(Vec<Arc<dyn Interceptor>>);
+
+impl fmt::Debug for InterceptorChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("InterceptorChain")
+            .field(&self.0.len())
+            .finish()
+    }
+}
+
+impl InterceptorChain {
+    pub fn push(&mut self, interceptor: impl 'static + Interceptor) {
+        self.0.push(Arc::new(interceptor));
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Runs `f` between the hooks of every interceptor.
+    pub fn run<R: MarshaledTyList>(
+        &self,
+        call: &CallInfo,
+        args: ArgSummary,
+        f: impl FnOnce() -> anyhow::Result<R::Prims>,
+    ) -> anyhow::Result<R::Prims> {
+        let mut entered = 0;
+        let entering = self.0.iter().try_for_each(|interceptor| {
+            interceptor.before(call, &args)?;
+            entered += 1;
+            anyhow::Ok(())
+        });
+
+        let mut res = entering.and_then(|()| f());
+
+        for interceptor in self.0[..entered].iter().rev() {
+            let exited = match &res {
+                Ok(prims) => interceptor.after(call, &args, Ok(&ArgSummary::capture(prims))),
+                Err(err) => interceptor.after(call, &args, Err(err)),
+            };
+
+            if let Err(err) = exited {
+                res = Err(err);
+            }
+        }
+
+        res
+    }
+}
+
+// === Intercepted === //
+
+/// A host function which runs an [`InterceptorChain`] around its calls.
+pub struct Intercepted<F> {
+    pub call: CallInfo,
+    pub chain: InterceptorChain,
+    pub func: F,
+}
+
+macro_rules! impl_func_ty {
+    ($($ty:ident)*) => {
+        impl<D, F, Ret, $($ty: MarshaledTy,)*> HostSideMarshaledFunc<D, ($($ty,)*), Ret> for Intercepted<F>
+        where
+            D: 'static,
+            Ret: MarshaledTyList,
+            F: 'static + Send + Sync + Fn(wasmtime::Caller<'_, D>, $($ty,)*) -> anyhow::Result<Ret>,
+        {
+            type PrimParams<'a> = (wasmtime::Caller<'a, D>, $(<$ty as MarshaledTy>::Prim,)*);
+            type PrimResults = anyhow::Result<Ret::Prims>;
+
+            #[allow(non_snake_case, unused_mut)]
+            fn wrap_host(self) -> impl for<'a> wasmtime::IntoFunc<D, Self::PrimParams<'a>, Self::PrimResults> {
+                let Self { call, chain, func } = self;
+
+                move |caller: wasmtime::Caller<'_, D>, $($ty: <$ty as MarshaledTy>::Prim,)*| {
+                    let prims = ($($ty,)*);
+
+                    chain.run::<Ret>(&call, ArgSummary::capture(&prims), || {
+                        let Some(($($ty,)*)) = <($($ty,)*)>::from_prims(prims) else {
+                            anyhow::bail!("failed to parse arguments to {call}");
+                        };
+
+                        func(caller, $($ty),*).map(MarshaledTyList::into_prims)
+                    })
+                }
+            }
+        }
+    };
+}
+
+impl_variadic!(impl_func_ty);
@@ -1,7 +1,33 @@
-use std::{any::type_name, marker::PhantomData};
+pub mod cache;
+pub mod cancel;
+pub mod capabilities;
+pub mod crossing;
+pub mod cursor;
+pub mod growth;
+pub mod intercept;
+pub mod log;
+pub mod manifest;
+pub mod registry;
+pub mod resource;
+pub mod sandbox;
+pub mod shared;
+pub mod telemetry;
+pub mod testing;
+pub mod trace;
+
+use std::{
+    any::{type_name, Any, TypeId},
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    marker::PhantomData,
+    ops::Range,
+};
 
 use anyhow::Context;
 use bytemuck::Pod;
+use cursor::{MemoryCursor, MemoryCursorMut};
+use registry::DynValue;
 
 // === Re-Exports === //
 
@@ -43,65 +69,305 @@ pub const fn align_of_32<T>() -> u32 {
     <AlignOf<T>>::SIZE
 }
 
+/// Resolves the `count * size` bytes starting at `base` to a range of a memory `mem_len` bytes long
+/// with a single bounds check. The end is computed in 64 bits, where it cannot overflow.
+#[inline(always)]
+fn range_of(mem_len: usize, base: u32, count: u32, size: u32) -> Option<Range<usize>> {
+    let end = usize::try_from(base as u64 + count as u64 * size as u64).ok()?;
+    (end <= mem_len).then_some(base as usize..end)
+}
+
+#[cold]
+#[inline(never)]
+fn range_error(mem_len: usize, base: u32, count: u32, size: u32) -> anyhow::Error {
+    anyhow::anyhow!(
+        "failed to read memory range from {base} to {} (memory size: {mem_len})",
+        base as u64 + count as u64 * size as u64,
+    )
+}
+
+/// Like [`range_of`] but for 64-bit memories, where computing the end can overflow.
+#[inline(always)]
+fn range_of64(mem_len: usize, base: u64, count: u64, size: u64) -> Option<Range<usize>> {
+    let end = count
+        .checked_mul(size)
+        .and_then(|len| base.checked_add(len))?;
+    let end = usize::try_from(end).ok()?;
+    (end <= mem_len).then_some(base as usize..end)
+}
+
+#[cold]
+#[inline(never)]
+fn range_error64(mem_len: usize, base: u64, count: u64, size: u64) -> anyhow::Error {
+    anyhow::anyhow!(
+        "failed to read memory range from {base} to {} (memory size: {mem_len})",
+        base as u128 + count as u128 * size as u128,
+    )
+}
+
+#[cold]
+#[inline(never)]
+fn align_error<T>(kind: &str, base: impl fmt::Display) -> anyhow::Error {
+    anyhow::anyhow!(
+        "failed to parse {kind} (ty: {}, base: {base}): pointer is not aligned to {} bytes",
+        type_name::<T>(),
+        align_of_32::<T>(),
+    )
+}
+
+fn null_error<T>() -> anyhow::Error {
+    anyhow::anyhow!(
+        "failed to parse object (ty: {}): pointer is null",
+        type_name::<T>()
+    )
+}
+
 pub trait MemoryRead {
     fn as_slice(&self) -> &[u8];
 
+    #[inline]
     fn load_range(&self, base: u32, len: u32) -> anyhow::Result<&[u8]> {
-        self.as_slice()
-            .get(base as usize..)
-            .and_then(|s| s.get(..len as usize))
-            .with_context(|| {
-                format!(
-                    "failed to read memory range from {base} to {len} (memory size: {})",
-                    self.as_slice().len()
-                )
-            })
+        load_elems(self.as_slice(), base, len, 1)
     }
 
+    #[inline]
     fn load_struct_raw<T: Pod>(&self, ptr: u32) -> anyhow::Result<&T> {
-        bytemuck::try_from_bytes(self.load_range(ptr, size_of_32::<T>())?).map_err(|err| {
-            anyhow::anyhow!(
-                "failed to parse object (ty: {}, base: {ptr}): {err}",
-                type_name::<T>()
-            )
-        })
+        let data = load_elems(self.as_slice(), ptr, 1, size_of_32::<T>())?;
+        if !is_aligned::<T>(data) {
+            return Err(align_error::<T>("object", ptr));
+        }
+
+        // Safety: `data` is exactly `size_of::<T>()` bytes long and properly aligned, and `T` is
+        // valid for any bit pattern.
+        Ok(unsafe { &*data.as_ptr().cast::<T>() })
     }
 
+    #[inline]
     fn load_slice_raw<T: Pod>(&self, base: u32, len: u32) -> anyhow::Result<&[T]> {
-        bytemuck::try_cast_slice(
-            self.load_range(
-                base,
-                len.checked_mul(size_of_32::<T>())
-                    .context("slice is too big")?,
-            )?,
-        )
-        .map_err(|err| {
-            anyhow::anyhow!(
-                "failed to parse slice (ty: {}, base: {base}, len: {len}): {err}",
-                type_name::<T>()
-            )
-        })
+        let data = load_elems(self.as_slice(), base, len, size_of_32::<T>())?;
+        if !is_aligned::<T>(data) {
+            return Err(align_error::<T>("slice", base));
+        }
+
+        // Safety: `data` is exactly `len` elements long and properly aligned, and `T` is valid for
+        // any bit pattern.
+        Ok(unsafe { std::slice::from_raw_parts(data.as_ptr().cast::<T>(), len as usize) })
+    }
+
+    /// Copies out an object, wherever the pointer to it is aligned.
+    #[inline]
+    fn read_struct_raw<T: Pod>(&self, ptr: u32) -> anyhow::Result<T> {
+        let data = load_elems(self.as_slice(), ptr, 1, size_of_32::<T>())?;
+        Ok(bytemuck::pod_read_unaligned(data))
+    }
+
+    /// Borrows a slice if it is aligned and copies it out otherwise.
+    #[inline]
+    fn read_slice_raw<T: Pod>(&self, base: u32, len: u32) -> anyhow::Result<Cow<'_, [T]>> {
+        let data = load_elems(self.as_slice(), base, len, size_of_32::<T>())?;
+        if is_aligned::<T>(data) {
+            // Safety: see `load_slice_raw`.
+            let slice =
+                unsafe { std::slice::from_raw_parts(data.as_ptr().cast::<T>(), len as usize) };
+            return Ok(Cow::Borrowed(slice));
+        }
+
+        // (zero-sized elements can't be read out of their chunks)
+        if size_of::<T>() == 0 {
+            return Ok(Cow::Owned(vec![T::zeroed(); len as usize]));
+        }
+
+        Ok(Cow::Owned(
+            data.chunks_exact(size_of::<T>())
+                .map(bytemuck::pod_read_unaligned)
+                .collect(),
+        ))
     }
 
+    #[inline]
     fn load_str_raw(&self, base: u32, len: u32) -> anyhow::Result<&str> {
-        self.load_range(base, len)
-            .and_then(|data| std::str::from_utf8(data).context("invalid UTF-8"))
+        std::str::from_utf8(self.load_range(base, len)?).context("invalid UTF-8")
     }
 
+    /// Loads the object behind a pointer, rejecting null pointers. Use
+    /// [`load_struct_opt`](Self::load_struct_opt) for pointers which may be null.
+    #[inline]
     fn load_struct<T: Pod>(&self, ptr: WasmPtr<T>) -> anyhow::Result<&T> {
+        if ptr.is_null() {
+            return Err(null_error::<T>());
+        }
+
         self.load_struct_raw(ptr.addr().get())
     }
 
+    /// Like [`load_struct`](Self::load_struct) but copies the object out so that pointers which
+    /// aren't aligned for `T` can be read too.
+    #[inline]
+    fn read_struct<T: Pod>(&self, ptr: WasmPtr<T>) -> anyhow::Result<T> {
+        if ptr.is_null() {
+            return Err(null_error::<T>());
+        }
+
+        self.read_struct_raw(ptr.addr().get())
+    }
+
+    /// Like [`load_slice`](Self::load_slice) but falls back to copying the slice if it isn't
+    /// aligned for `T`.
+    #[inline]
+    fn read_slice<T: Pod>(&self, ptr: WasmSlice<T>) -> anyhow::Result<Cow<'_, [T]>> {
+        self.read_slice_raw(ptr.base.addr().get(), ptr.len.get())
+    }
+
+    #[inline]
+    fn load_struct_opt<T: Pod>(&self, ptr: Option<WasmPtr<T>>) -> anyhow::Result<Option<&T>> {
+        ptr.map(|ptr| self.load_struct(ptr)).transpose()
+    }
+
+    #[inline]
     fn load_slice<T: Pod>(&self, ptr: WasmSlice<T>) -> anyhow::Result<&[T]> {
         self.load_slice_raw(ptr.base.addr().get(), ptr.len.get())
     }
 
+    #[inline]
     fn load_str(&self, ptr: WasmStr) -> anyhow::Result<&str> {
         self.load_str_raw(ptr.0.base.addr().get(), ptr.0.len.get())
     }
+
+    /// Loads every slice of a table of slices.
+    fn load_nested_slice<T: Pod>(&self, ptr: WasmSlice<WasmSlice<T>>) -> anyhow::Result<Vec<&[T]>> {
+        self.load_slice(ptr)?
+            .iter()
+            .enumerate()
+            .map(|(i, &inner)| {
+                self.load_slice(inner)
+                    .with_context(|| format!("failed to read slice {i} of the table"))
+            })
+            .collect()
+    }
+
+    /// Loads every string of a table of strings.
+    fn load_slice_of_strs(&self, ptr: WasmSlice<WasmStr>) -> anyhow::Result<Vec<&str>> {
+        self.load_slice(ptr)?
+            .iter()
+            .enumerate()
+            .map(|(i, &inner)| {
+                self.load_str(inner)
+                    .with_context(|| format!("failed to read string {i} of the table"))
+            })
+            .collect()
+    }
+
+    #[inline]
+    fn load_range64(&self, base: u64, len: u64) -> anyhow::Result<&[u8]> {
+        load_elems64(self.as_slice(), base, len, 1)
+    }
+
+    #[inline]
+    fn load_struct_raw64<T: Pod>(&self, ptr: u64) -> anyhow::Result<&T> {
+        let data = load_elems64(self.as_slice(), ptr, 1, size_of_32::<T>().into())?;
+        if !is_aligned::<T>(data) {
+            return Err(align_error::<T>("object", ptr));
+        }
+
+        // Safety: see `load_struct_raw`.
+        Ok(unsafe { &*data.as_ptr().cast::<T>() })
+    }
+
+    #[inline]
+    fn load_slice_raw64<T: Pod>(&self, base: u64, len: u64) -> anyhow::Result<&[T]> {
+        let data = load_elems64(self.as_slice(), base, len, size_of_32::<T>().into())?;
+        if !is_aligned::<T>(data) {
+            return Err(align_error::<T>("slice", base));
+        }
+
+        // Safety: see `load_slice_raw`.
+        Ok(unsafe { std::slice::from_raw_parts(data.as_ptr().cast::<T>(), len as usize) })
+    }
+
+    #[inline]
+    fn load_struct64<T: Pod>(&self, ptr: WasmPtr64<T>) -> anyhow::Result<&T> {
+        if ptr.is_null() {
+            return Err(null_error::<T>());
+        }
+
+        self.load_struct_raw64(ptr.addr().get())
+    }
+
+    #[inline]
+    fn load_slice64<T: Pod>(&self, ptr: WasmSlice64<T>) -> anyhow::Result<&[T]> {
+        self.load_slice_raw64(ptr.base.addr().get(), ptr.len.get())
+    }
+
+    /// Calls `f` with consecutive chunks of at most `chunk_len` elements of a slice, stopping at the
+    /// first error. The whole slice is checked before `f` sees any of it.
+    fn for_each_chunk<T: Pod>(
+        &self,
+        ptr: WasmSlice<T>,
+        chunk_len: u32,
+        mut f: impl FnMut(&[T]) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(chunk_len > 0, "chunks must be at least one element long");
+
+        for chunk in self.load_slice(ptr)?.chunks(chunk_len as usize) {
+            f(chunk)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the bytes of a slice incrementally through [`std::io::Read`].
+    fn slice_reader(&self, ptr: WasmSlice<u8>) -> anyhow::Result<SliceReader<'_>> {
+        Ok(SliceReader(self.load_slice(ptr)?))
+    }
+
+    /// Reads a stream out of a region of memory.
+    fn cursor(&self, region: WasmSlice<u8>) -> anyhow::Result<MemoryCursor<'_>> {
+        MemoryCursor::from_slice(self.as_slice(), region)
+    }
+}
+
+/// A [`std::io::Read`] adapter over a slice of guest memory, created by
+/// [`MemoryRead::slice_reader`].
+#[derive(Debug, Clone)]
+pub struct SliceReader<'a>(&'a [u8]);
+
+impl SliceReader<'_> {
+    /// The number of bytes left to read.
+    pub fn remaining(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl std::io::Read for SliceReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+#[inline(always)]
+fn load_elems(mem: &[u8], base: u32, count: u32, size: u32) -> anyhow::Result<&[u8]> {
+    match range_of(mem.len(), base, count, size) {
+        // `range_of` has already checked the range, so this indexing check folds away.
+        Some(range) => Ok(&mem[range]),
+        None => Err(range_error(mem.len(), base, count, size)),
+    }
+}
+
+#[inline(always)]
+fn load_elems64(mem: &[u8], base: u64, count: u64, size: u64) -> anyhow::Result<&[u8]> {
+    match range_of64(mem.len(), base, count, size) {
+        Some(range) => Ok(&mem[range]),
+        None => Err(range_error64(mem.len(), base, count, size)),
+    }
+}
+
+#[inline(always)]
+fn is_aligned<T>(data: &[u8]) -> bool {
+    data.as_ptr().cast::<T>().is_aligned()
 }
 
 impl MemoryRead for [u8] {
+    #[inline]
     fn as_slice(&self) -> &[u8] {
         self
     }
@@ -110,16 +376,12 @@ impl MemoryRead for [u8] {
 pub trait MemoryWrite: MemoryRead {
     fn as_slice_mut(&mut self) -> &mut [u8];
 
+    #[inline]
     fn load_range_mut(&mut self, base: u32, len: u32) -> anyhow::Result<&mut [u8]> {
-        let mem_len = self.as_slice().len();
-        self.as_slice_mut()
-            .get_mut(base as usize..)
-            .and_then(|s| s.get_mut(..len as usize))
-            .with_context(|| {
-                format!("failed to read memory range from {base} to {len} (memory size: {mem_len})")
-            })
+        load_elems_mut(self.as_slice_mut(), base, len, 1)
     }
 
+    #[inline]
     fn write_range_mut(&mut self, base: u32, data: &[u8]) -> anyhow::Result<()> {
         self.load_range_mut(base, u32::try_from(data.len()).context("slice is too big")?)?
             .copy_from_slice(data);
@@ -127,10 +389,50 @@ pub trait MemoryWrite: MemoryRead {
         Ok(())
     }
 
+    #[inline]
     fn write_struct<T: Pod>(&mut self, base: WasmPtr<T>, data: &T) -> anyhow::Result<()> {
         self.write_range_mut(base.addr().get(), bytemuck::bytes_of(data))
     }
 
+    #[inline]
+    fn load_range_mut64(&mut self, base: u64, len: u64) -> anyhow::Result<&mut [u8]> {
+        load_elems_mut64(self.as_slice_mut(), base, len, 1)
+    }
+
+    #[inline]
+    fn write_range_mut64(&mut self, base: u64, data: &[u8]) -> anyhow::Result<()> {
+        self.load_range_mut64(base, data.len() as u64)?
+            .copy_from_slice(data);
+
+        Ok(())
+    }
+
+    #[inline]
+    fn write_struct64<T: Pod>(&mut self, base: WasmPtr64<T>, data: &T) -> anyhow::Result<()> {
+        self.write_range_mut64(base.addr().get(), bytemuck::bytes_of(data))
+    }
+
+    fn write_slice64<'a, T: Pod>(
+        &mut self,
+        base: WasmPtr64<T>,
+        items: impl IntoIterator<Item = &'a T>,
+    ) -> anyhow::Result<u64> {
+        let mut offset = base.addr().get();
+        let mut count = 0;
+
+        for item in items {
+            self.write_struct64(WasmPtr64::new(offset.into()), item)?;
+
+            offset = offset
+                .checked_add(size_of_32::<T>().into())
+                .context("wrote too many elements into memory")?;
+
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
     fn write_slice<'a, T: Pod>(
         &mut self,
         base: WasmPtr<T>,
@@ -151,9 +453,67 @@ pub trait MemoryWrite: MemoryRead {
 
         Ok(count)
     }
+
+    /// Mutates a structure in place, checking its bounds and alignment only once.
+    #[inline]
+    fn update_struct<T: Pod, R>(
+        &mut self,
+        ptr: WasmPtr<T>,
+        f: impl FnOnce(&mut T) -> R,
+    ) -> anyhow::Result<R> {
+        let base = ptr.addr().get();
+        let data = load_elems_mut(self.as_slice_mut(), base, 1, size_of_32::<T>())?;
+        if !is_aligned::<T>(data) {
+            return Err(align_error::<T>("object", base));
+        }
+
+        // Safety: see `load_struct_raw`.
+        Ok(f(unsafe { &mut *data.as_mut_ptr().cast::<T>() }))
+    }
+
+    /// Mutates the elements of a slice in place, checking its bounds and alignment only once.
+    #[inline]
+    fn update_slice<T: Pod, R>(
+        &mut self,
+        ptr: WasmSlice<T>,
+        f: impl FnOnce(&mut [T]) -> R,
+    ) -> anyhow::Result<R> {
+        let (base, len) = (ptr.base.addr().get(), ptr.len.get());
+        let data = load_elems_mut(self.as_slice_mut(), base, len, size_of_32::<T>())?;
+        if !is_aligned::<T>(data) {
+            return Err(align_error::<T>("slice", base));
+        }
+
+        // Safety: see `load_slice_raw`.
+        Ok(f(unsafe {
+            std::slice::from_raw_parts_mut(data.as_mut_ptr().cast::<T>(), len as usize)
+        }))
+    }
+
+    /// Writes a stream into a region of memory.
+    fn cursor_mut(&mut self, region: WasmSlice<u8>) -> anyhow::Result<MemoryCursorMut<'_>> {
+        MemoryCursorMut::from_slice(self.as_slice_mut(), region)
+    }
+}
+
+#[inline(always)]
+fn load_elems_mut(mem: &mut [u8], base: u32, count: u32, size: u32) -> anyhow::Result<&mut [u8]> {
+    match range_of(mem.len(), base, count, size) {
+        Some(range) => Ok(&mut mem[range]),
+        None => Err(range_error(mem.len(), base, count, size)),
+    }
+}
+
+#[inline(always)]
+fn load_elems_mut64(mem: &mut [u8], base: u64, count: u64, size: u64) -> anyhow::Result<&mut [u8]> {
+    match range_of64(mem.len(), base, count, size) {
+        Some(range) => Ok(&mut mem[range]),
+        None => Err(range_error64(mem.len(), base, count, size)),
+    }
 }
 
 impl MemoryWrite for [u8] {
+    #[inline]
     fn as_slice_mut(&mut self) -> &mut [u8] {
         self
     }
@@ -208,6 +568,163 @@ where
     linker.func_wrap(module, name, func.wrap_host())
 }
 
+// HostSideRetAreaFunc
+pub trait HostSideRetAreaFunc<D, Params, Results>: Sized {
+    type PrimParams<'a>;
+
+    #[rustfmt::skip]
+    fn wrap_host(self) ->
+        impl for<'a> wasmtime::IntoFunc<D, Self::PrimParams<'a>, anyhow::Result<()>>;
+}
+
+macro_rules! impl_ret_area_func_ty {
+    ($($ty:ident)*) => {
+        impl<D, F, Ret, $($ty: MarshaledTy,)*> HostSideRetAreaFunc<D, ($($ty,)*), Ret> for F
+        where
+            D: 'static + StoreHasMemory,
+            Ret: MarshaledTyList,
+            F: 'static + Send + Sync + Fn(&mut wasmtime::Caller<'_, D>, $($ty,)*) -> anyhow::Result<Ret>,
+        {
+            type PrimParams<'a> = (wasmtime::Caller<'a, D>, u32, $(<$ty as MarshaledTy>::Prim,)*);
+
+            #[allow(non_snake_case)]
+            fn wrap_host(self) -> impl for<'a> wasmtime::IntoFunc<D, Self::PrimParams<'a>, anyhow::Result<()>> {
+                move |mut caller: wasmtime::Caller<'_, D>, ret_area: u32, $($ty: <$ty as MarshaledTy>::Prim,)*| {
+                    let res = self(&mut caller, $(<$ty>::from_prim($ty).context("failed to parse argument")?),*)?;
+                    write_ret_area(&mut caller, ret_area, &Ret::into_prims(res))
+                }
+            }
+        }
+    };
+}
+
+impl_variadic!(impl_ret_area_func_ty);
+
+fn write_ret_area(
+    mut cx: impl ContextMemoryExt,
+    ret_area: u32,
+    prims: &impl WasmPrimitiveList,
+) -> anyhow::Result<()> {
+    let mut bytes = Vec::new();
+    for_each_prim_bits(prims, |bits| bytes.extend_from_slice(&bits.to_le_bytes()));
+
+    cx.main_memory()
+        .write_range_mut(ret_area, &bytes)
+        .context("failed to write results into the return area")
+}
+
+// `bind_ret_area_to_linker`
+
+/// Binds a host function returning several values to guests which import it with
+/// [`guest_import!`], writing its results into the return area the guest passes rather than
+/// returning them. The function borrows the caller since the results are written through it
+/// afterwards.
+pub fn bind_ret_area_to_linker<'l, F, T, Params, Results>(
+    linker: &'l mut wasmtime::Linker<T>,
+    module: &str,
+    name: &str,
+    func: F,
+) -> anyhow::Result<&'l mut wasmtime::Linker<T>>
+where
+    F: HostSideRetAreaFunc<T, Params, Results>,
+{
+    linker.func_wrap(module, name, func.wrap_host())
+}
+
+// HostSideMarshaledAsyncFunc
+pub trait HostSideMarshaledAsyncFunc<D, Params, Results>: Sized {
+    fn bind_async<'l>(
+        self,
+        linker: &'l mut wasmtime::Linker<D>,
+        module: &str,
+        name: &str,
+    ) -> anyhow::Result<&'l mut wasmtime::Linker<D>>;
+}
+
+/// An `async fn(Caller<'a, D>, ...)` whose future may borrow the caller.
+pub trait AsyncHostFn<'a, D: 'a, Params>: 'static + Send + Sync {
+    type Output;
+    type Future: 'a + Send + std::future::Future<Output = anyhow::Result<Self::Output>>;
+
+    fn call(&self, caller: wasmtime::Caller<'a, D>, params: Params) -> Self::Future;
+}
+
+macro_rules! impl_async_func_ty {
+    ($($wrap:ident => $($ty:ident)*;)*) => {$(
+        impl<'a, D, F, Fut, Ret, $($ty,)*> AsyncHostFn<'a, D, ($($ty,)*)> for F
+        where
+            D: 'a,
+            F: 'static + Send + Sync + Fn(wasmtime::Caller<'a, D>, $($ty,)*) -> Fut,
+            Fut: 'a + Send + std::future::Future<Output = anyhow::Result<Ret>>,
+        {
+            type Output = Ret;
+            type Future = Fut;
+
+            #[allow(non_snake_case)]
+            fn call(&self, caller: wasmtime::Caller<'a, D>, ($($ty,)*): ($($ty,)*)) -> Fut {
+                self(caller, $($ty,)*)
+            }
+        }
+
+        impl<D, F, Ret, $($ty: MarshaledTy,)*> HostSideMarshaledAsyncFunc<D, ($($ty,)*), Ret> for F
+        where
+            D: 'static,
+            Ret: MarshaledTyList,
+            F: for<'a> AsyncHostFn<'a, D, ($($ty,)*), Output = Ret>,
+        {
+            #[allow(non_snake_case, clippy::redundant_closure_call)]
+            fn bind_async<'l>(
+                self,
+                linker: &'l mut wasmtime::Linker<D>,
+                module: &str,
+                name: &str,
+            ) -> anyhow::Result<&'l mut wasmtime::Linker<D>> {
+                linker.$wrap(module, name, move |caller: wasmtime::Caller<'_, D>, $($ty: <$ty as MarshaledTy>::Prim,)*| {
+                    let params = (|| Ok(($(<$ty>::from_prim($ty).context("failed to parse argument")?,)*)))();
+                    let fut: Box<dyn std::future::Future<Output = anyhow::Result<Ret::Prims>> + Send> = match params {
+                        Ok(params) => {
+                            let fut = self.call(caller, params);
+                            Box::new(async move { fut.await.map(MarshaledTyList::into_prims) })
+                        }
+                        Err(err) => Box::new(std::future::ready(Err(err))),
+                    };
+                    fut
+                })
+            }
+        }
+    )*};
+}
+
+impl_async_func_ty! {
+    func_wrap0_async => ;
+    func_wrap1_async => V1;
+    func_wrap2_async => V1 V2;
+    func_wrap3_async => V1 V2 V3;
+    func_wrap4_async => V1 V2 V3 V4;
+    func_wrap5_async => V1 V2 V3 V4 V5;
+    func_wrap6_async => V1 V2 V3 V4 V5 V6;
+    func_wrap7_async => V1 V2 V3 V4 V5 V6 V7;
+    func_wrap8_async => V1 V2 V3 V4 V5 V6 V7 V8;
+    func_wrap9_async => V1 V2 V3 V4 V5 V6 V7 V8 V9;
+    func_wrap10_async => V1 V2 V3 V4 V5 V6 V7 V8 V9 V10;
+    func_wrap11_async => V1 V2 V3 V4 V5 V6 V7 V8 V9 V10 V11;
+    func_wrap12_async => V1 V2 V3 V4 V5 V6 V7 V8 V9 V10 V11 V12;
+}
+
+// `bind_to_linker_async`
+/// Defines an `async fn` as a host function. The linker's engine must have async support enabled.
+pub fn bind_to_linker_async<'l, F, T, Params, Results>(
+    linker: &'l mut wasmtime::Linker<T>,
+    module: &str,
+    name: &str,
+    func: F,
+) -> anyhow::Result<&'l mut wasmtime::Linker<T>>
+where
+    F: HostSideMarshaledAsyncFunc<T, Params, Results>,
+{
+    func.bind_async(linker, module, name)
+}
+
 // === Guest-Side Function Handling === //
 
 pub struct WasmFuncRef<A, R = ()>(pub wasmtime::TypedFunc<A::Prims, R::Prims>)
@@ -237,11 +754,32 @@ where
     A: MarshaledTyList,
     R: MarshaledTyList,
 {
+    /// Looks up a function the guest exported with
+    /// [`generate_guest_export!`](crate::generate_guest_export).
+    pub fn from_export(
+        mut cx: impl wasmtime::AsContextMut,
+        instance: &wasmtime::Instance,
+        name: &str,
+    ) -> anyhow::Result<Self> {
+        instance
+            .get_typed_func(&mut cx, name)
+            .map(Self)
+            .with_context(|| format!("failed to get exported function `{name}`"))
+    }
+
     pub fn decode<T: StoreHasTable>(
         mut cx: impl wasmtime::AsContextMut<Data = T>,
         idx: WasmFunc<A, R>,
     ) -> anyhow::Result<Self> {
         let table = cx.as_context().data().func_table();
+        let table_size = table.size(&cx);
+
+        if let Some(cache) = cx.as_context_mut().data_mut().func_ref_cache() {
+            if let Some(func) = cache.get::<A, R>(idx.addr().get(), table_size) {
+                return Ok(Self(func));
+            }
+        }
+
         let func = table
             .get(&mut cx, idx.addr().get())
             .with_context(|| format!("failed to resolve table entry with index {idx:?}"))?;
@@ -251,7 +789,13 @@ where
             .flatten()
             .context("entry is not a `funcref`")?;
 
-        Ok(Self(func.typed(&cx).context("func has wrong type")?))
+        let func = func.typed(&cx).context("func has wrong type")?;
+
+        if let Some(cache) = cx.as_context_mut().data_mut().func_ref_cache() {
+            cache.insert::<A, R>(idx.addr().get(), table_size, func);
+        }
+
+        Ok(Self(func))
     }
 
     pub fn call<D>(
@@ -262,65 +806,345 @@ where
         R::from_prims(self.0.call(&mut store, A::into_prims(args))?)
             .context("failed to deserialize results")
     }
-}
 
-// === WasmDynamic Extensions === //
+    /// Calls the function on a store with async support enabled.
+    pub fn call_async<D: Send>(
+        &self,
+        mut store: impl wasmtime::AsContextMut<Data = D> + Send,
+        args: A,
+    ) -> impl std::future::Future<Output = anyhow::Result<R>> + Send {
+        let func = self.0;
+        let args = A::into_prims(args);
 
-pub trait WasmDynamicExt {
-    type Vtable: 'static;
+        async move {
+            R::from_prims(func.call_async(&mut store, args).await?)
+                .context("failed to deserialize results")
+        }
+    }
+}
 
-    fn get_vtable(self, mem: &(impl ?Sized + MemoryRead)) -> anyhow::Result<&Self::Vtable>
-    where
-        Self::Vtable: bytemuck::Pod;
+/// The error of a call to a guest function returning a [`WasmResult`].
+#[derive(Debug)]
+pub enum GuestError<E> {
+    /// The guest returned an error.
+    Returned(E),
 
-    fn run_dtor<S>(self, cx: impl wasmtime::AsContextMut<Data = S>) -> anyhow::Result<()>
-    where
-        S: StoreHasMemory + StoreHasTable;
+    /// The call trapped or its result couldn't be parsed.
+    Trapped(anyhow::Error),
 }
 
-impl<V: 'static> WasmDynamicExt for WasmDynamic<V> {
-    type Vtable = V;
-
-    fn get_vtable(self, mem: &(impl ?Sized + MemoryRead)) -> anyhow::Result<&Self::Vtable>
-    where
-        Self::Vtable: bytemuck::Pod,
-    {
-        mem.load_struct(mem.load_struct(self.0.meta)?.vtable)
+impl<E> From<anyhow::Error> for GuestError<E> {
+    fn from(err: anyhow::Error) -> Self {
+        Self::Trapped(err)
     }
+}
 
-    fn run_dtor<S>(self, mut cx: impl wasmtime::AsContextMut<Data = S>) -> anyhow::Result<()>
-    where
-        S: StoreHasMemory + StoreHasTable,
-    {
-        let table = cx.main_memory().load_struct(self.0.meta)?;
-        if table.needs_drop.get() != 0 {
-            let dtor = table.dtor;
-            let dtor = WasmFuncRef::decode(&mut cx, dtor)?;
-            dtor.call(cx, (self.0.base, self.0.meta))?;
+impl<E: fmt::Debug> fmt::Display for GuestError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Returned(err) => write!(f, "guest returned an error: {err:?}"),
+            Self::Trapped(_) => f.write_str("guest call failed"),
         }
-        Ok(())
     }
 }
 
-pub trait WasmDynamicFuncExt {
-    type Args;
-    type Res;
-
-    fn call<S>(
-        self,
-        cx: impl wasmtime::AsContextMut<Data = S>,
-        args: Self::Args,
-    ) -> anyhow::Result<Self::Res>
-    where
-        S: StoreHasMemory + StoreHasTable;
+impl<E: fmt::Debug> std::error::Error for GuestError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Returned(_) => None,
+            Self::Trapped(err) => Some(err.as_ref()),
+        }
+    }
 }
 
-impl<A, R> WasmDynamicFuncExt for WasmDynamicFunc<A, R>
+impl<A, T, E> WasmFuncRef<A, WasmResult<T, E>>
 where
-    A: ExtensibleMarshaledTyList,
-    R: MarshaledTyList,
+    A: MarshaledTyList,
+    WasmResult<T, E>: MarshaledTy,
 {
-    type Args = A;
+    /// Calls the function, separating the errors it returns from its traps.
+    pub fn try_call<D>(
+        &self,
+        store: impl wasmtime::AsContextMut<Data = D>,
+        args: A,
+    ) -> Result<T, GuestError<E>> {
+        self.call(store, args)?.0.map_err(GuestError::Returned)
+    }
+}
+
+/// A guest function whose signature is only known at runtime, called with [`DynValue`]s.
+#[derive(Debug, Clone)]
+pub struct DynWasmFuncRef {
+    func: wasmtime::Func,
+    params: Vec<TyDescriptor>,
+    results: Vec<TyDescriptor>,
+}
+
+impl DynWasmFuncRef {
+    /// Wraps `func`, describing its integers as signed, its floats as floats, and everything else
+    /// as opaque. Use [`with_signature`](Self::with_signature) to describe them precisely.
+    pub fn new(store: impl wasmtime::AsContext, func: wasmtime::Func) -> Self {
+        let describe = |ty: wasmtime::ValType| match ty {
+            wasmtime::ValType::I32 => TyDescriptor::Int {
+                signed: true,
+                bits: 32,
+            },
+            wasmtime::ValType::I64 => TyDescriptor::Int {
+                signed: true,
+                bits: 64,
+            },
+            wasmtime::ValType::F32 => TyDescriptor::Float { bits: 32 },
+            wasmtime::ValType::F64 => TyDescriptor::Float { bits: 64 },
+            _ => TyDescriptor::Opaque,
+        };
+
+        let ty = func.ty(&store);
+
+        Self {
+            func,
+            params: ty.params().map(describe).collect(),
+            results: ty.results().map(describe).collect(),
+        }
+    }
+
+    pub fn from_export(
+        mut store: impl wasmtime::AsContextMut,
+        instance: &wasmtime::Instance,
+        name: &str,
+    ) -> anyhow::Result<Self> {
+        let func = instance
+            .get_func(&mut store, name)
+            .with_context(|| format!("guest does not export a function named {name:?}"))?;
+
+        Ok(Self::new(&store, func))
+    }
+
+    pub fn from_table(
+        mut store: impl wasmtime::AsContextMut,
+        table: wasmtime::Table,
+        index: u32,
+    ) -> anyhow::Result<Self> {
+        let func = table
+            .get(&mut store, index)
+            .with_context(|| format!("failed to resolve table entry with index {index}"))?;
+
+        let func = *func
+            .funcref()
+            .flatten()
+            .context("entry is not a `funcref`")?;
+
+        Ok(Self::new(&store, func))
+    }
+
+    /// Describes the function's parameters and results, checking that they are marshaled as the
+    /// wasm types the function actually takes.
+    pub fn with_signature(
+        mut self,
+        store: impl wasmtime::AsContext,
+        params: Vec<TyDescriptor>,
+        results: Vec<TyDescriptor>,
+    ) -> anyhow::Result<Self> {
+        fn check(
+            kind: &str,
+            actual: impl ExactSizeIterator<Item = wasmtime::ValType>,
+            described: &[TyDescriptor],
+        ) -> anyhow::Result<()> {
+            anyhow::ensure!(
+                actual.len() == described.len(),
+                "function has {} {kind}(s) but {} were described",
+                actual.len(),
+                described.len(),
+            );
+
+            for (i, (actual, &described)) in actual.zip(described).enumerate() {
+                let matches = match described {
+                    TyDescriptor::Bool | TyDescriptor::Char => {
+                        matches!(actual, wasmtime::ValType::I32)
+                    }
+                    TyDescriptor::Int { bits: 64, .. } => matches!(actual, wasmtime::ValType::I64),
+                    TyDescriptor::Int { .. } => matches!(actual, wasmtime::ValType::I32),
+                    TyDescriptor::Float { bits: 64 } => matches!(actual, wasmtime::ValType::F64),
+                    TyDescriptor::Float { .. } => matches!(actual, wasmtime::ValType::F32),
+                    TyDescriptor::Opaque => true,
+                };
+
+                anyhow::ensure!(
+                    matches,
+                    "{kind} {i} is described as a {described} but has wasm type {actual}"
+                );
+            }
+
+            Ok(())
+        }
+
+        let ty = self.func.ty(&store);
+        check("parameter", ty.params(), &params)?;
+        check("result", ty.results(), &results)?;
+
+        self.params = params;
+        self.results = results;
+        Ok(self)
+    }
+
+    /// Describes the function's signature as that of a function taking `A` and returning `R`.
+    pub fn with_marshaled_signature<A, R>(
+        self,
+        store: impl wasmtime::AsContext,
+    ) -> anyhow::Result<Self>
+    where
+        A: MarshaledTyList,
+        R: MarshaledTyList,
+    {
+        self.with_signature(
+            store,
+            registry::descriptors::<A>(),
+            registry::descriptors::<R>(),
+        )
+    }
+
+    pub fn func(&self) -> wasmtime::Func {
+        self.func
+    }
+
+    pub fn params(&self) -> &[TyDescriptor] {
+        &self.params
+    }
+
+    pub fn results(&self) -> &[TyDescriptor] {
+        &self.results
+    }
+
+    /// Whether every parameter and result is a scalar, so the function can be called.
+    pub fn is_scalar(&self) -> bool {
+        self.params
+            .iter()
+            .chain(&self.results)
+            .all(|ty| ty.is_scalar())
+    }
+
+    pub fn call(
+        &self,
+        mut store: impl wasmtime::AsContextMut,
+        args: &[DynValue],
+    ) -> anyhow::Result<Vec<DynValue>> {
+        anyhow::ensure!(
+            self.is_scalar(),
+            "function {self} takes or returns non-scalar values"
+        );
+        anyhow::ensure!(
+            args.len() == self.params.len(),
+            "function {self} takes {} argument(s) but {} were given",
+            self.params.len(),
+            args.len(),
+        );
+
+        let params = args
+            .iter()
+            .zip(&self.params)
+            .enumerate()
+            .map(|(i, (arg, &ty))| {
+                arg.to_val(ty)
+                    .with_context(|| format!("invalid argument {i}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut results = vec![wasmtime::Val::I32(0); self.results.len()];
+        self.func.call(&mut store, &params, &mut results)?;
+
+        results
+            .iter()
+            .zip(&self.results)
+            .map(|(val, &ty)| DynValue::from_val(val, ty))
+            .collect()
+    }
+}
+
+impl fmt::Display for DynWasmFuncRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |f: &mut fmt::Formatter<'_>, types: &[TyDescriptor]| {
+            f.write_str("(")?;
+            for (i, ty) in types.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                ty.fmt(f)?;
+            }
+            f.write_str(")")
+        };
+
+        f.write_str("fn")?;
+        list(f, &self.params)?;
+        f.write_str(" -> ")?;
+        list(f, &self.results)
+    }
+}
+
+// === WasmDynamic Extensions === //
+
+pub trait WasmDynamicExt {
+    type Vtable: 'static;
+
+    fn get_vtable(self, mem: &(impl ?Sized + MemoryRead)) -> anyhow::Result<&Self::Vtable>
+    where
+        Self::Vtable: bytemuck::Pod;
+
+    fn run_dtor<S>(self, cx: impl wasmtime::AsContextMut<Data = S>) -> anyhow::Result<()>
+    where
+        S: StoreHasMemory + StoreHasTable;
+}
+
+impl<V: 'static> WasmDynamicExt for WasmDynamic<V> {
+    type Vtable = V;
+
+    fn get_vtable(self, mem: &(impl ?Sized + MemoryRead)) -> anyhow::Result<&Self::Vtable>
+    where
+        Self::Vtable: bytemuck::Pod,
+    {
+        mem.load_struct(mem.load_struct(self.0.meta)?.vtable)
+    }
+
+    fn run_dtor<S>(self, mut cx: impl wasmtime::AsContextMut<Data = S>) -> anyhow::Result<()>
+    where
+        S: StoreHasMemory + StoreHasTable,
+    {
+        let table = cx.main_memory().load_struct(self.0.meta)?;
+        if table.needs_drop.get() != 0 {
+            let dtor = table.dtor;
+            let dtor = WasmFuncRef::decode(&mut cx, dtor)?;
+            dtor.call(cx, (self.0.base, self.0.meta))?;
+        }
+        Ok(())
+    }
+}
+
+pub trait WasmDynamicFuncExt {
+    type Args;
+    type Res;
+
+    fn call<S>(
+        self,
+        cx: impl wasmtime::AsContextMut<Data = S>,
+        args: Self::Args,
+    ) -> anyhow::Result<Self::Res>
+    where
+        S: StoreHasMemory + StoreHasTable;
+
+    /// Calls the function on a store with async support enabled.
+    fn call_async<S>(
+        self,
+        cx: impl wasmtime::AsContextMut<Data = S> + Send,
+        args: Self::Args,
+    ) -> impl std::future::Future<Output = anyhow::Result<Self::Res>> + Send
+    where
+        S: StoreHasMemory + StoreHasTable + Send;
+}
+
+impl<A, R> WasmDynamicFuncExt for WasmDynamicFunc<A, R>
+where
+    A: ExtensibleMarshaledTyList,
+    R: MarshaledTyList,
+{
+    type Args = A;
     type Res = R;
 
     fn call<S>(
@@ -335,20 +1159,260 @@ where
         let func = WasmFuncRef::decode(&mut cx, func.0)?;
         func.call(cx, args.push_on_first(self.0.base))
     }
+
+    fn call_async<S>(
+        self,
+        mut cx: impl wasmtime::AsContextMut<Data = S> + Send,
+        args: Self::Args,
+    ) -> impl std::future::Future<Output = anyhow::Result<Self::Res>> + Send
+    where
+        S: StoreHasMemory + StoreHasTable + Send,
+    {
+        let call = (|| {
+            let func = *self.get_vtable(cx.main_memory())?;
+            let func = WasmFuncRef::decode(&mut cx, func.0)?;
+            anyhow::Ok(func.call_async(cx, args.push_on_first(self.0.base)))
+        })();
+
+        async move { call?.await }
+    }
 }
 
 // === StoreHasTable === //
 
 pub trait StoreHasTable {
     fn func_table(&self) -> wasmtime::Table;
+
+    /// The cache [`WasmFuncRef::decode`] memoizes its lookups in.
+    fn func_ref_cache(&mut self) -> Option<&mut FuncRefCache> {
+        None
+    }
+}
+
+/// Memoizes the typed functions which table entries resolve to, by table index and signature.
+///
+/// The cache is cleared whenever the table's size changes. Entries which are replaced without
+/// changing the size of the table, which guests compiled from Rust never do, must be
+/// [invalidated](Self::invalidate) by whoever replaces them.
+#[derive(Debug, Default)]
+pub struct FuncRefCache {
+    table_size: u32,
+    entries: HashMap<(u32, TypeId), Box<dyn Any + Send + Sync>>,
+}
+
+impl FuncRefCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn sync_table_size(&mut self, table_size: u32) {
+        if self.table_size != table_size {
+            self.table_size = table_size;
+            self.entries.clear();
+        }
+    }
+
+    fn get<A, R>(
+        &mut self,
+        index: u32,
+        table_size: u32,
+    ) -> Option<wasmtime::TypedFunc<A::Prims, R::Prims>>
+    where
+        A: MarshaledTyList,
+        R: MarshaledTyList,
+    {
+        self.sync_table_size(table_size);
+        self.entries
+            .get(&(index, TypeId::of::<(A, R)>()))
+            .and_then(|func| func.downcast_ref().copied())
+    }
+
+    fn insert<A, R>(
+        &mut self,
+        index: u32,
+        table_size: u32,
+        func: wasmtime::TypedFunc<A::Prims, R::Prims>,
+    ) where
+        A: MarshaledTyList,
+        R: MarshaledTyList,
+    {
+        self.sync_table_size(table_size);
+        self.entries
+            .insert((index, TypeId::of::<(A, R)>()), Box::new(func));
+    }
+
+    /// Forgets every signature cached for the entry at `index`.
+    pub fn invalidate(&mut self, index: u32) {
+        self.entries.retain(|&(entry, _), _| entry != index);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
 }
 
 // === StoreHasMemory === //
 
+/// Statistics about a guest's allocator.
+///
+/// When these are collected on the host, only the allocations the host made and freed itself are
+/// seen.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct AllocStats {
+    /// The number of bytes currently allocated.
+    pub allocated: u64,
+
+    /// The number of live allocations.
+    pub live_blocks: u64,
+
+    /// The largest number of bytes ever allocated at once.
+    pub high_water_mark: u64,
+}
+
+impl AllocStats {
+    pub fn record_alloc(&mut self, size: u64) {
+        self.allocated += size;
+        self.live_blocks += 1;
+        self.high_water_mark = self.high_water_mark.max(self.allocated);
+    }
+
+    pub fn record_dealloc(&mut self, size: u64) {
+        self.allocated = self.allocated.saturating_sub(size);
+        self.live_blocks = self.live_blocks.saturating_sub(1);
+    }
+}
+
+impl From<WasmAllocStats> for AllocStats {
+    fn from(stats: WasmAllocStats) -> Self {
+        Self {
+            allocated: stats.allocated.get(),
+            live_blocks: stats.live_blocks.get(),
+            high_water_mark: stats.high_water_mark.get(),
+        }
+    }
+}
+
+/// The size in bytes above which [`ContextMemoryExt::alloc_slice`] makes sure the guest has room
+/// for an allocation before making it.
+pub const LARGE_ALLOC_THRESHOLD: u32 = 1 << 20;
+
+/// The size of a wasm page.
+pub const WASM_PAGE_SIZE: u32 = 1 << 16;
+
+/// Selects one of the memories of a guest using the multi-memory proposal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MemorySelector<'a> {
+    /// The [main memory](StoreHasMemory::main_memory), which is also memory `0`.
+    Main,
+    Index(u32),
+    Named(&'a str),
+}
+
+impl fmt::Display for MemorySelector<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Main => f.write_str("main memory"),
+            Self::Index(index) => write!(f, "memory {index}"),
+            Self::Named(name) => write!(f, "memory `{name}`"),
+        }
+    }
+}
+
+impl MemorySelector<'_> {
+    pub fn resolve(
+        self,
+        data: &(impl ?Sized + StoreHasMemory),
+    ) -> anyhow::Result<wasmtime::Memory> {
+        let index = match self {
+            Self::Main => 0,
+            Self::Index(index) => index,
+            Self::Named(name) => data
+                .memory_index(name)
+                .with_context(|| format!("guest has no {self}"))?,
+        };
+
+        anyhow::ensure!(
+            index < data.memory_count(),
+            "guest has no {self} (it has {} memories)",
+            data.memory_count(),
+        );
+
+        data.memory_at(index)
+            .with_context(|| format!("{self} is not available"))
+    }
+}
+
 pub trait StoreHasMemory {
     fn main_memory(&self) -> wasmtime::Memory;
 
+    /// The number of memories the guest has, including its main memory.
+    fn memory_count(&self) -> u32 {
+        1
+    }
+
+    /// The memory with the specified index, where the [main memory](Self::main_memory) is memory
+    /// `0`. Guests with a single memory only need to provide the main memory.
+    fn memory_at(&self, index: u32) -> Option<wasmtime::Memory> {
+        (index == 0).then(|| self.main_memory())
+    }
+
+    /// The index of the memory known under the specified name.
+    fn memory_index(&self, name: &str) -> Option<u32> {
+        let _ = name;
+        None
+    }
+
     fn alloc_func(&self) -> WasmFuncRef<(u32, u32), WasmPtr<()>>;
+
+    /// The guest's deallocation function, taking a pointer returned by the
+    /// [`alloc_func`](Self::alloc_func) and the size and alignment it was allocated with.
+    fn dealloc_func(&self) -> Option<WasmFuncRef<(WasmPtr<()>, u32, u32)>> {
+        None
+    }
+
+    /// The guest's allocation function if its main memory is a 64-bit memory, taking the same
+    /// arguments as the [`alloc_func`](Self::alloc_func) but as 64-bit integers.
+    fn alloc64_func(&self) -> Option<WasmFuncRef<(u64, u64), WasmPtr64<()>>> {
+        None
+    }
+
+    /// The guest's function for making room for an allocation of the specified number of bytes,
+    /// which returns whether it succeeded. Guests typically implement this by growing their memory
+    /// and handing the new pages to their allocator.
+    fn reserve_func(&self) -> Option<WasmFuncRef<(u32,), bool>> {
+        None
+    }
+
+    /// The size in bytes up to which the host may grow the main memory of guests without a
+    /// [`reserve_func`](Self::reserve_func) directly. `None` disables direct growth.
+    fn memory_growth_limit(&self) -> Option<usize> {
+        None
+    }
+
+    /// The guest's function for querying its allocator's statistics.
+    fn alloc_stats_func(&self) -> Option<WasmFuncRef<(), WasmPtr<WasmAllocStats>>> {
+        None
+    }
+
+    /// The counters which allocations made through [`ContextMemoryExt::alloc`] are recorded in,
+    /// for guests without an [`alloc_stats_func`](Self::alloc_stats_func).
+    fn alloc_counters(&mut self) -> Option<&mut AllocStats> {
+        None
+    }
+
+    /// Called with the new size of the main memory whenever [`ContextMemoryExt::ensure_guest_capacity`]
+    /// may have grown it.
+    fn main_memory_resized(&mut self, new_size: usize) {
+        let _ = new_size;
+    }
 }
 
 pub trait ContextMemoryExt: Sized + wasmtime::AsContextMut<Data = Self::Data_> {
@@ -363,9 +1427,168 @@ pub trait ContextMemoryExt: Sized + wasmtime::AsContextMut<Data = Self::Data_> {
         self.split_main_memory().0
     }
 
+    fn split_memory(
+        &mut self,
+        selector: MemorySelector<'_>,
+    ) -> anyhow::Result<(&mut [u8], &mut Self::Data_)> {
+        let memory = selector.resolve(self.as_context().data())?;
+        Ok(memory.data_and_store_mut(self))
+    }
+
+    /// The contents of one of the guest's memories, which can be accessed through [`MemoryRead`]
+    /// and [`MemoryWrite`] like those of the main memory.
+    fn memory(&mut self, selector: MemorySelector<'_>) -> anyhow::Result<&mut [u8]> {
+        self.split_memory(selector).map(|(memory, _)| memory)
+    }
+
+    fn load_struct_in<T: Pod>(
+        &mut self,
+        selector: MemorySelector<'_>,
+        ptr: WasmPtr<T>,
+    ) -> anyhow::Result<&T> {
+        self.memory(selector)?
+            .load_struct(ptr)
+            .with_context(|| format!("failed to read from {selector}"))
+    }
+
+    fn load_slice_in<T: Pod>(
+        &mut self,
+        selector: MemorySelector<'_>,
+        ptr: WasmSlice<T>,
+    ) -> anyhow::Result<&[T]> {
+        self.memory(selector)?
+            .load_slice(ptr)
+            .with_context(|| format!("failed to read from {selector}"))
+    }
+
+    fn write_struct_in<T: Pod>(
+        &mut self,
+        selector: MemorySelector<'_>,
+        ptr: WasmPtr<T>,
+        value: &T,
+    ) -> anyhow::Result<()> {
+        self.memory(selector)?
+            .write_struct(ptr, value)
+            .with_context(|| format!("failed to write to {selector}"))
+    }
+
     fn alloc(&mut self, size: u32, align: u32) -> anyhow::Result<WasmPtr<()>> {
         let alloc = self.as_context_mut().data().alloc_func();
-        alloc.call(self, (size, align))
+        let ptr = alloc.call(&mut *self, (size, align))?;
+
+        if let Some(counters) = self.as_context_mut().data_mut().alloc_counters() {
+            counters.record_alloc(size.into());
+        }
+
+        Ok(ptr)
+    }
+
+    /// Frees an allocation made by [`alloc`](Self::alloc) with the same `size` and `align`.
+    fn dealloc(&mut self, ptr: WasmPtr<()>, size: u32, align: u32) -> anyhow::Result<()> {
+        let dealloc = self
+            .as_context()
+            .data()
+            .dealloc_func()
+            .context("guest has no deallocation function")?;
+
+        dealloc
+            .call(&mut *self, (ptr, size, align))
+            .with_context(|| format!("failed to free the allocation at {ptr:?}"))?;
+
+        if let Some(counters) = self.as_context_mut().data_mut().alloc_counters() {
+            counters.record_dealloc(size.into());
+        }
+
+        Ok(())
+    }
+
+    /// Whether the main memory is a 64-bit memory.
+    fn main_memory_is_64(&mut self) -> bool {
+        let memory = self.as_context().data().main_memory();
+        memory.ty(&*self).is_64()
+    }
+
+    /// Allocates in the main memory whatever its address width. 64-bit memories are allocated in
+    /// through the [`alloc64_func`](StoreHasMemory::alloc64_func) and 32-bit memories through
+    /// [`alloc`](Self::alloc).
+    fn alloc64(&mut self, size: u64, align: u64) -> anyhow::Result<WasmPtr64<()>> {
+        if !self.main_memory_is_64() {
+            let size = u32::try_from(size).context("allocation is too big for a 32-bit memory")?;
+            let align = u32::try_from(align).context("alignment is too big for a 32-bit memory")?;
+            return self.alloc(size, align).map(WasmPtr64::from);
+        }
+
+        let alloc = self
+            .as_context()
+            .data()
+            .alloc64_func()
+            .context("guest has a 64-bit memory but no 64-bit allocation function")?;
+
+        let ptr = alloc.call(&mut *self, (size, align))?;
+
+        if let Some(counters) = self.as_context_mut().data_mut().alloc_counters() {
+            counters.record_alloc(size);
+        }
+
+        Ok(ptr)
+    }
+
+    /// Queries the guest's allocator statistics, preferring those the guest reports itself over
+    /// those counted by the host. Returns `None` if neither are available.
+    fn alloc_stats(&mut self) -> anyhow::Result<Option<AllocStats>> {
+        if let Some(query) = self.as_context().data().alloc_stats_func() {
+            let ptr = query
+                .call(&mut *self, ())
+                .context("failed to query the guest's allocator statistics")?;
+
+            return Ok(Some((*self.main_memory().load_struct(ptr)?).into()));
+        }
+
+        Ok(self
+            .as_context_mut()
+            .data_mut()
+            .alloc_counters()
+            .map(|counters| *counters))
+    }
+
+    /// Makes sure the guest has room for an allocation of `bytes` bytes, returning the size of its
+    /// main memory afterwards.
+    ///
+    /// Guests with a [`reserve_func`](StoreHasMemory::reserve_func) are asked to make room
+    /// themselves. Otherwise, the memory is grown directly so that it extends `bytes` past its
+    /// current end, as long as that stays within the
+    /// [growth limit](StoreHasMemory::memory_growth_limit).
+    fn ensure_guest_capacity(&mut self, bytes: u32) -> anyhow::Result<usize> {
+        let memory = self.as_context().data().main_memory();
+
+        if let Some(reserve) = self.as_context().data().reserve_func() {
+            let reserved = reserve
+                .call(&mut *self, (bytes,))
+                .context("failed to ask the guest to reserve memory")?;
+
+            anyhow::ensure!(reserved, "guest failed to reserve {bytes} bytes of memory");
+        } else if let Some(limit) = self.as_context().data().memory_growth_limit() {
+            let old_size = memory.data_size(&*self);
+            let pages = bytes.div_ceil(WASM_PAGE_SIZE);
+            let new_size = old_size as u64 + pages as u64 * WASM_PAGE_SIZE as u64;
+
+            anyhow::ensure!(
+                new_size <= limit as u64,
+                "growing memory from {old_size} to {new_size} bytes would exceed its limit of \
+                 {limit} bytes",
+            );
+
+            memory
+                .grow(&mut *self, pages.into())
+                .with_context(|| format!("failed to grow memory by {pages} page(s)"))?;
+        }
+
+        let new_size = memory.data_size(&*self);
+        self.as_context_mut()
+            .data_mut()
+            .main_memory_resized(new_size);
+
+        Ok(new_size)
     }
 
     fn alloc_struct<T: Pod>(&mut self, value: &T) -> anyhow::Result<WasmPtr<T>> {
@@ -387,6 +1610,10 @@ pub trait ContextMemoryExt: Sized + wasmtime::AsContextMut<Data = Self::Data_> {
             .checked_mul(len)
             .context("slice is too big")?;
 
+        if size > LARGE_ALLOC_THRESHOLD {
+            self.ensure_guest_capacity(size)?;
+        }
+
         let base = self
             .alloc(size, align_of_32::<T>())
             .map(|v| WasmPtr::<T>::new(v.addr()))?;
@@ -403,6 +1630,183 @@ pub trait ContextMemoryExt: Sized + wasmtime::AsContextMut<Data = Self::Data_> {
     fn alloc_str(&mut self, data: &str) -> anyhow::Result<WasmStr> {
         self.alloc_slice(data.as_bytes().iter()).map(WasmStr)
     }
+
+    fn free_struct<T: Pod>(&mut self, ptr: WasmPtr<T>) -> anyhow::Result<()> {
+        self.dealloc(
+            WasmPtr::new(ptr.addr()),
+            size_of_32::<T>(),
+            align_of_32::<T>(),
+        )
+    }
+
+    fn free_slice<T: Pod>(&mut self, slice: WasmSlice<T>) -> anyhow::Result<()> {
+        let size = size_of_32::<T>()
+            .checked_mul(slice.len.get())
+            .context("slice is too big")?;
+
+        self.dealloc(WasmPtr::new(slice.base.addr()), size, align_of_32::<T>())
+    }
+
+    /// Allocates a table of slices along with the slices themselves. The table and the slices
+    /// share a single allocation, which starts with the table, so it must be freed with
+    /// [`free_nested_slice`](Self::free_nested_slice).
+    fn alloc_nested_slice<T: Pod>(
+        &mut self,
+        values: &[impl AsRef<[T]>],
+    ) -> anyhow::Result<WasmSlice<WasmSlice<T>>> {
+        let lens = values
+            .iter()
+            .map(|value| u32::try_from(value.as_ref().len()).context("too many elements in slice"))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let (size, offsets) = nested_slice_layout::<T>(&lens)?;
+
+        if size > LARGE_ALLOC_THRESHOLD {
+            self.ensure_guest_capacity(size)?;
+        }
+
+        let base = self.alloc(size, nested_slice_align::<T>())?.addr().get();
+
+        base.checked_add(size)
+            .context("guest returned an allocation past the end of memory")?;
+
+        let (memory, _) = self.split_main_memory();
+        let mut table = Vec::with_capacity(values.len());
+
+        for ((value, &len), offset) in values.iter().zip(&lens).zip(offsets) {
+            memory.write_range_mut(base + offset, bytemuck::cast_slice(value.as_ref()))?;
+            table.push(WasmSlice::<T> {
+                base: WasmPtr::new((base + offset).into()),
+                len: len.into(),
+            });
+        }
+
+        memory.write_slice(WasmPtr::new(base.into()), &table)?;
+
+        Ok(WasmSlice {
+            base: WasmPtr::new(base.into()),
+            len: (lens.len() as u32).into(),
+        })
+    }
+
+    /// Like [`alloc_nested_slice`](Self::alloc_nested_slice) but for a table of strings.
+    fn alloc_str_slice(
+        &mut self,
+        values: &[impl AsRef<str>],
+    ) -> anyhow::Result<WasmSlice<WasmStr>> {
+        let values = values
+            .iter()
+            .map(|value| value.as_ref().as_bytes())
+            .collect::<Vec<_>>();
+
+        let table = self.alloc_nested_slice(&values)?;
+
+        Ok(WasmSlice {
+            base: WasmPtr::new(table.base.addr()),
+            len: table.len,
+        })
+    }
+
+    /// Frees a table allocated by [`alloc_nested_slice`](Self::alloc_nested_slice). The size of
+    /// the allocation is recomputed from the lengths in the table, so they must not have changed.
+    fn free_nested_slice<T: Pod>(&mut self, table: WasmSlice<WasmSlice<T>>) -> anyhow::Result<()> {
+        let lens = self
+            .main_memory()
+            .load_slice(table)?
+            .iter()
+            .map(|inner| inner.len.get())
+            .collect::<Vec<_>>();
+
+        let (size, _) = nested_slice_layout::<T>(&lens)?;
+        self.dealloc(
+            WasmPtr::new(table.base.addr()),
+            size,
+            nested_slice_align::<T>(),
+        )
+    }
+
+    /// Frees a table allocated by [`alloc_str_slice`](Self::alloc_str_slice).
+    fn free_str_slice(&mut self, table: WasmSlice<WasmStr>) -> anyhow::Result<()> {
+        self.free_nested_slice::<u8>(WasmSlice {
+            base: WasmPtr::new(table.base.addr()),
+            len: table.len,
+        })
+    }
+
+    fn alloc_box<T: Pod>(&mut self, value: &T) -> anyhow::Result<WasmBox<T>> {
+        self.alloc_struct(value).map(WasmBox::from_ptr)
+    }
+
+    fn alloc_box_slice<'a, T: Pod>(
+        &mut self,
+        values: impl ExactSizeIterator<Item = &'a T>,
+    ) -> anyhow::Result<WasmBox<T>> {
+        self.alloc_slice(values).map(WasmBox::from_slice)
+    }
+
+    fn alloc_struct64<T: Pod>(&mut self, value: &T) -> anyhow::Result<WasmPtr64<T>> {
+        let ptr = self
+            .alloc64(size_of_32::<T>().into(), align_of_32::<T>().into())
+            .map(|v| WasmPtr64::<T>::new(v.addr()))?;
+
+        let (memory, _) = self.split_main_memory();
+        memory.write_struct64(ptr, value)?;
+        Ok(ptr)
+    }
+
+    /// Like [`alloc_slice`](Self::alloc_slice) but for either address width. The guest isn't
+    /// asked to make room for large slices ahead of time.
+    fn alloc_slice64<'a, T: Pod>(
+        &mut self,
+        values: impl ExactSizeIterator<Item = &'a T>,
+    ) -> anyhow::Result<WasmSlice64<T>> {
+        let len = values.len() as u64;
+        let size = u64::from(size_of_32::<T>())
+            .checked_mul(len)
+            .context("slice is too big")?;
+
+        let base = self
+            .alloc64(size, align_of_32::<T>().into())
+            .map(|v| WasmPtr64::<T>::new(v.addr()))?;
+
+        let (memory, _) = self.split_main_memory();
+        memory.write_slice64(base, values)?;
+
+        Ok(WasmSlice64 {
+            base,
+            len: len.into(),
+        })
+    }
+}
+
+/// Computes the size of a table of slices with the specified lengths followed by their contents,
+/// along with the offset of each slice's contents from the start of the table.
+fn nested_slice_layout<T>(lens: &[u32]) -> anyhow::Result<(u32, Vec<u32>)> {
+    let align = align_of_32::<T>();
+    let mut offsets = Vec::with_capacity(lens.len());
+    let mut size = u32::try_from(lens.len())
+        .ok()
+        .and_then(|count| count.checked_mul(size_of_32::<WasmSlice<()>>()))
+        .context("too many slices in table")?;
+
+    for &len in lens {
+        let offset = size
+            .checked_next_multiple_of(align)
+            .context("table of slices is too big")?;
+
+        size = size_of_32::<T>()
+            .checked_mul(len)
+            .and_then(|len| offset.checked_add(len))
+            .context("table of slices is too big")?;
+
+        offsets.push(offset);
+    }
+
+    Ok((size, offsets))
+}
+
+fn nested_slice_align<T>() -> u32 {
+    align_of_32::<T>().max(align_of_32::<WasmSlice<()>>())
 }
 
 impl<T: wasmtime::AsContextMut> ContextMemoryExt for T
@@ -411,3 +1815,76 @@ where
 {
     type Data_ = T::Data;
 }
+
+// === WasmBox === //
+
+/// A guest allocation of one or more `T`s owned by the host, which remembers the layout it was
+/// allocated with. A box must either be [freed](Self::free) or handed off to the guest with
+/// [`into_slice`](Self::into_slice) or [`into_ptr`](Self::into_ptr), otherwise it leaks.
+#[must_use = "dropping a `WasmBox` leaks its allocation"]
+pub struct WasmBox<T: 'static> {
+    slice: WasmSlice<T>,
+}
+
+impl<T> fmt::Debug for WasmBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WasmBox")
+            .field("base", &self.slice.base)
+            .field("len", &self.slice.len.get())
+            .finish()
+    }
+}
+
+impl<T: Pod> WasmBox<T> {
+    /// Takes ownership of a single `T` which was allocated with the layout of `T`.
+    pub fn from_ptr(ptr: WasmPtr<T>) -> Self {
+        Self::from_slice(WasmSlice {
+            base: ptr,
+            len: LeU32::new(1),
+        })
+    }
+
+    /// Takes ownership of a slice which was allocated with the layout of an array of `T`.
+    pub fn from_slice(slice: WasmSlice<T>) -> Self {
+        Self { slice }
+    }
+
+    pub fn ptr(&self) -> WasmPtr<T> {
+        self.slice.base
+    }
+
+    pub fn slice(&self) -> WasmSlice<T> {
+        self.slice
+    }
+
+    pub fn len(&self) -> u32 {
+        self.slice.len.get()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The size of the allocation in bytes.
+    pub fn size(&self) -> u64 {
+        u64::from(size_of_32::<T>()) * u64::from(self.len())
+    }
+
+    pub fn align(&self) -> u32 {
+        align_of_32::<T>()
+    }
+
+    /// Gives up ownership of the allocation, typically to pass it to a guest which frees it.
+    pub fn into_slice(self) -> WasmSlice<T> {
+        self.slice
+    }
+
+    /// Like [`into_slice`](Self::into_slice) but only returns the pointer to the first element.
+    pub fn into_ptr(self) -> WasmPtr<T> {
+        self.slice.base
+    }
+
+    pub fn free(self, mut cx: impl ContextMemoryExt) -> anyhow::Result<()> {
+        cx.free_slice(self.slice)
+    }
+}
@@ -0,0 +1,149 @@
+//! Log messages streamed from guests.
+//!
+//! Guests format messages through a [`GuestLogWriter`](crate::GuestLogWriter), which passes them
+//! to the host in small chunks so that logging never allocates on the guest. The host buffers each
+//! message's chunks in the store's [`GuestLog`] and hands the reassembled message to its
+//! [`LogSink`] once the guest flushes it.
+
+use std::{fmt, sync::Arc};
+
+use anyhow::Context;
+use wasmtime::{Caller, Linker};
+
+use crate::{
+    ContextMemoryExt, LogLevel, MarshaledTy, MemoryRead, StoreHasMemory, WasmSlice, LOG_MODULE,
+};
+
+// === LogSink === //
+
+#[derive(Debug, Clone)]
+pub struct GuestLogRecord<'a> {
+    pub level: LogLevel,
+    pub message: &'a str,
+
+    /// Whether the end of the message was dropped for exceeding the log's maximum length.
+    pub truncated: bool,
+}
+
+impl fmt::Display for GuestLogRecord<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.level, self.message)?;
+
+        if self.truncated {
+            f.write_str("...")?;
+        }
+
+        Ok(())
+    }
+}
+
+pub trait LogSink: Send + Sync {
+    fn log(&self, record: &GuestLogRecord<'_>);
+}
+
+impl<F: Fn(&GuestLogRecord<'_>) + Send + Sync> LogSink for F {
+    fn log(&self, record: &GuestLogRecord<'_>) {
+        self(record)
+    }
+}
+
+// === GuestLog === //
+
+/// The maximum length of a message, in bytes, unless configured otherwise.
+pub const DEFAULT_MAX_MESSAGE_LEN: usize = 16 * 1024;
+
+pub struct GuestLog {
+    sink: Arc<dyn LogSink>,
+    pending: Vec<u8>,
+    max_len: usize,
+    truncated: bool,
+}
+
+impl fmt::Debug for GuestLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GuestLog")
+            .field("pending", &self.pending.len())
+            .field("max_len", &self.max_len)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GuestLog {
+    pub fn new(sink: impl 'static + LogSink) -> Self {
+        Self {
+            sink: Arc::new(sink),
+            pending: Vec::new(),
+            max_len: DEFAULT_MAX_MESSAGE_LEN,
+            truncated: false,
+        }
+    }
+
+    /// Limits how much of a message is buffered. The rest of longer messages is dropped.
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    pub fn append(&mut self, chunk: &[u8]) {
+        let room = self.max_len.saturating_sub(self.pending.len());
+        self.truncated |= chunk.len() > room;
+        self.pending
+            .extend_from_slice(&chunk[..chunk.len().min(room)]);
+    }
+
+    /// Passes the buffered message to the sink. Characters split by truncation are replaced
+    /// rather than rejected.
+    pub fn flush(&mut self, level: LogLevel) {
+        self.sink.log(&GuestLogRecord {
+            level,
+            message: &String::from_utf8_lossy(&self.pending),
+            truncated: self.truncated,
+        });
+
+        self.pending.clear();
+        self.truncated = false;
+    }
+}
+
+pub trait StoreHasGuestLog {
+    fn guest_log(&mut self) -> &mut GuestLog;
+}
+
+// === Bindings === //
+
+/// Defines the imports used by [`GuestLogWriter`](crate::GuestLogWriter) in the `linker`.
+pub fn bind_guest_log<D>(linker: &mut Linker<D>) -> anyhow::Result<()>
+where
+    D: 'static + StoreHasMemory + StoreHasGuestLog,
+{
+    linker.func_wrap(
+        LOG_MODULE,
+        "log_write",
+        |mut caller: Caller<'_, D>, chunk: u64| -> anyhow::Result<()> {
+            let chunk = <WasmSlice<u8>>::from_prim(chunk).context("failed to parse log chunk")?;
+
+            let (memory, data) = caller.split_main_memory();
+            data.guest_log().append(
+                memory
+                    .load_slice(chunk)
+                    .context("failed to read log chunk")?,
+            );
+
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        LOG_MODULE,
+        "log_flush",
+        |mut caller: Caller<'_, D>, level: u32| -> anyhow::Result<()> {
+            let level = LogLevel::from_u32(level)
+                .with_context(|| format!("guest logged with an unknown level {level}"))?;
+
+            caller.data_mut().guest_log().flush(level);
+            Ok(())
+        },
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,252 @@
+//! Descriptions of the exports a host expects from its guests.
+//!
+//! Checking a guest against its [`InterfaceManifest`] as soon as it is loaded reports every missing
+//! or mistyped export at once instead of failing when the host first tries to use one.
+
+use std::fmt;
+
+use wasmtime::{AsContextMut, ExternType, FuncType, Instance, Module, ValType};
+
+use crate::{for_each_val_type, MarshaledTyList};
+
+// === FuncSignature === //
+
+/// The primitive signature of a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuncSignature {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+impl FuncSignature {
+    /// The signature of a function marshaling `A` into `R`.
+    pub fn of<A: MarshaledTyList, R: MarshaledTyList>() -> Self {
+        let mut params = Vec::new();
+        for_each_val_type::<A::Prims>(|ty| params.push(ty));
+
+        let mut results = Vec::new();
+        for_each_val_type::<R::Prims>(|ty| results.push(ty));
+
+        Self { params, results }
+    }
+
+    pub fn of_func(ty: &FuncType) -> Self {
+        Self {
+            params: ty.params().collect(),
+            results: ty.results().collect(),
+        }
+    }
+}
+
+impl fmt::Display for FuncSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list(f: &mut fmt::Formatter<'_>, types: &[ValType]) -> fmt::Result {
+            f.write_str("(")?;
+            for (i, ty) in types.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{ty}")?;
+            }
+            f.write_str(")")
+        }
+
+        f.write_str("fn")?;
+        list(f, &self.params)?;
+        f.write_str(" -> ")?;
+        list(f, &self.results)
+    }
+}
+
+// === InterfaceManifest === //
+
+/// The kind of an export, along with the signature of functions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExportKind {
+    Func(FuncSignature),
+    Memory,
+    Table,
+    Global,
+}
+
+impl ExportKind {
+    pub fn of(ty: &ExternType) -> Self {
+        match ty {
+            ExternType::Func(ty) => Self::Func(FuncSignature::of_func(ty)),
+            ExternType::Memory(_) => Self::Memory,
+            ExternType::Table(_) => Self::Table,
+            ExternType::Global(_) => Self::Global,
+        }
+    }
+}
+
+impl fmt::Display for ExportKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Func(sig) => sig.fmt(f),
+            Self::Memory => f.write_str("memory"),
+            Self::Table => f.write_str("table"),
+            Self::Global => f.write_str("global"),
+        }
+    }
+}
+
+/// The exports a guest must provide.
+#[derive(Debug, Clone, Default)]
+pub struct InterfaceManifest {
+    exports: Vec<(String, ExportKind)>,
+}
+
+impl InterfaceManifest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requires a function marshaling `A` into `R`.
+    pub fn func<A: MarshaledTyList, R: MarshaledTyList>(&mut self, name: &str) -> &mut Self {
+        self.export(name, ExportKind::Func(FuncSignature::of::<A, R>()))
+    }
+
+    pub fn memory(&mut self, name: &str) -> &mut Self {
+        self.export(name, ExportKind::Memory)
+    }
+
+    pub fn table(&mut self, name: &str) -> &mut Self {
+        self.export(name, ExportKind::Table)
+    }
+
+    pub fn export(&mut self, name: &str, expected: ExportKind) -> &mut Self {
+        self.exports.push((name.to_string(), expected));
+        self
+    }
+
+    pub fn exports(&self) -> impl ExactSizeIterator<Item = (&str, &ExportKind)> + '_ {
+        self.exports
+            .iter()
+            .map(|(name, expected)| (name.as_str(), expected))
+    }
+
+    /// Compares the manifest against the exports found by `lookup`.
+    pub fn diff(&self, mut lookup: impl FnMut(&str) -> Option<ExternType>) -> ManifestDiff {
+        let mut mismatches = Vec::new();
+
+        for (name, expected) in &self.exports {
+            let Some(actual) = lookup(name) else {
+                mismatches.push(ExportMismatch::Missing {
+                    name: name.clone(),
+                    expected: expected.clone(),
+                });
+                continue;
+            };
+
+            // Only the kind of memories and tables matters, which `ExportKind` alone describes.
+            let actual = ExportKind::of(&actual);
+            if actual == *expected {
+                continue;
+            }
+
+            mismatches.push(ExportMismatch::Mismatched {
+                name: name.clone(),
+                expected: expected.clone(),
+                actual,
+            });
+        }
+
+        ManifestDiff { mismatches }
+    }
+}
+
+/// Checks the exports of a module before it is instantiated.
+pub fn verify_module(module: &Module, manifest: &InterfaceManifest) -> Result<(), ManifestDiff> {
+    manifest.diff(|name| module.get_export(name)).into_result()
+}
+
+/// Checks the exports of an instance.
+pub fn verify_instance(
+    mut store: impl AsContextMut,
+    instance: &Instance,
+    manifest: &InterfaceManifest,
+) -> Result<(), ManifestDiff> {
+    manifest
+        .diff(|name| {
+            let export = instance.get_export(&mut store, name)?;
+            Some(export.ty(&store))
+        })
+        .into_result()
+}
+
+// === ManifestDiff === //
+
+#[derive(Debug, Clone)]
+pub enum ExportMismatch {
+    Missing {
+        name: String,
+        expected: ExportKind,
+    },
+    Mismatched {
+        name: String,
+        expected: ExportKind,
+        actual: ExportKind,
+    },
+}
+
+impl ExportMismatch {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Missing { name, .. } | Self::Mismatched { name, .. } => name,
+        }
+    }
+}
+
+impl fmt::Display for ExportMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing { name, expected } => {
+                write!(f, "missing export {name:?} (expected {expected})")
+            }
+            Self::Mismatched {
+                name,
+                expected,
+                actual,
+            } => write!(f, "export {name:?} is {actual} but should be {expected}"),
+        }
+    }
+}
+
+/// Every way in which a guest's exports differ from its manifest.
+#[derive(Debug, Clone, Default)]
+pub struct ManifestDiff {
+    pub mismatches: Vec<ExportMismatch>,
+}
+
+impl ManifestDiff {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.is_ok() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ManifestDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "guest does not match its interface manifest ({} mismatch(es))",
+            self.mismatches.len()
+        )?;
+
+        for mismatch in &self.mismatches {
+            write!(f, "\n- {mismatch}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ManifestDiff {}
@@ -0,0 +1,458 @@
+//! A record of the host functions bound into a linker, used to report every import a module
+//! needs but the linker can't satisfy before instantiating it.
+
+use std::{any::type_name, collections::HashMap, fmt, sync::Arc};
+
+use anyhow::Context;
+use wasmtime::{AsContextMut, Extern, Linker, Module, Val};
+
+use crate::{
+    bind_to_linker,
+    crossing::Recorded,
+    intercept::{CallInfo, Intercepted, Interceptor, InterceptorChain},
+    manifest::{ExportKind, FuncSignature},
+    telemetry::{CallTelemetry, Timed},
+    HostSideMarshaledFunc, MarshaledTyList, TyDescriptor,
+};
+
+// === BindingRegistry === //
+
+/// A host function bound with [`BindingRegistry::bind`].
+#[derive(Debug, Clone)]
+pub struct HostBinding {
+    pub module: String,
+    pub name: String,
+    pub signature: FuncSignature,
+
+    /// The name of the marshaled parameter tuple.
+    pub params: &'static str,
+
+    /// The name of the marshaled result type.
+    pub results: &'static str,
+
+    pub param_types: Vec<TyDescriptor>,
+    pub result_types: Vec<TyDescriptor>,
+}
+
+impl HostBinding {
+    /// Whether every parameter and result is a scalar, so the function can be invoked with
+    /// [`BindingRegistry::invoke`].
+    pub fn is_scalar(&self) -> bool {
+        self.param_types
+            .iter()
+            .chain(&self.result_types)
+            .all(|ty| ty.is_scalar())
+    }
+}
+
+impl fmt::Display for HostBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}::{}: fn{} -> {} ({})",
+            self.module, self.name, self.params, self.results, self.signature
+        )
+    }
+}
+
+/// Binds host functions into linkers while remembering their marshaled signatures.
+#[derive(Debug, Clone, Default)]
+pub struct BindingRegistry {
+    bindings: HashMap<(String, String), HostBinding>,
+    interceptors: InterceptorChain,
+}
+
+impl BindingRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds `func` into `linker` with [`bind_to_linker`] and records it.
+    pub fn bind<'l, F, T, Params, Results>(
+        &mut self,
+        linker: &'l mut Linker<T>,
+        module: &str,
+        name: &str,
+        func: F,
+    ) -> anyhow::Result<&'l mut Linker<T>>
+    where
+        F: HostSideMarshaledFunc<T, Params, Results>,
+        Params: MarshaledTyList,
+        Results: MarshaledTyList,
+    {
+        let linker = bind_to_linker(linker, module, name, func)?;
+
+        self.bindings.insert(
+            (module.to_string(), name.to_string()),
+            HostBinding {
+                module: module.to_string(),
+                name: name.to_string(),
+                signature: FuncSignature::of::<Params, Results>(),
+                params: type_name::<Params>(),
+                results: type_name::<Results>(),
+                param_types: descriptors::<Params>(),
+                result_types: descriptors::<Results>(),
+            },
+        );
+
+        Ok(linker)
+    }
+
+    /// Binds `func` like [`bind`](Self::bind), recording its calls in the store's
+    /// [`CrossingLog`](crate::crossing::CrossingLog) under the label `module::name`.
+    pub fn bind_recorded<'l, F, T, Params, Results>(
+        &mut self,
+        linker: &'l mut Linker<T>,
+        module: &str,
+        name: &str,
+        func: F,
+    ) -> anyhow::Result<&'l mut Linker<T>>
+    where
+        Recorded<F>: HostSideMarshaledFunc<T, Params, Results>,
+        Params: MarshaledTyList,
+        Results: MarshaledTyList,
+    {
+        self.bind(
+            linker,
+            module,
+            name,
+            Recorded::new(format!("{module}::{name}"), func),
+        )
+    }
+
+    /// Binds `func` like [`bind`](Self::bind), reporting its slow calls to `telemetry` under the
+    /// label `module::name`.
+    pub fn bind_timed<'l, F, T, Params, Results>(
+        &mut self,
+        linker: &'l mut Linker<T>,
+        module: &str,
+        name: &str,
+        telemetry: &Arc<CallTelemetry>,
+        func: F,
+    ) -> anyhow::Result<&'l mut Linker<T>>
+    where
+        Timed<F>: HostSideMarshaledFunc<T, Params, Results>,
+        Params: MarshaledTyList,
+        Results: MarshaledTyList,
+    {
+        self.bind(
+            linker,
+            module,
+            name,
+            Timed::new(format!("{module}::{name}"), telemetry, func),
+        )
+    }
+
+    /// Adds an interceptor to run around the functions bound with
+    /// [`bind_intercepted`](Self::bind_intercepted) from now on, after the interceptors which were
+    /// added before it.
+    pub fn add_interceptor(&mut self, interceptor: impl 'static + Interceptor) -> &mut Self {
+        self.interceptors.push(interceptor);
+        self
+    }
+
+    /// Binds `func` like [`bind`](Self::bind), running the registry's interceptors around its
+    /// calls.
+    pub fn bind_intercepted<'l, F, T, Params, Results>(
+        &mut self,
+        linker: &'l mut Linker<T>,
+        module: &str,
+        name: &str,
+        func: F,
+    ) -> anyhow::Result<&'l mut Linker<T>>
+    where
+        Intercepted<F>: HostSideMarshaledFunc<T, Params, Results>,
+        Params: MarshaledTyList,
+        Results: MarshaledTyList,
+    {
+        let func = Intercepted {
+            call: CallInfo {
+                module: module.into(),
+                name: name.into(),
+            },
+            chain: self.interceptors.clone(),
+            func,
+        };
+
+        self.bind(linker, module, name, func)
+    }
+
+    pub fn get(&self, module: &str, name: &str) -> Option<&HostBinding> {
+        self.bindings.get(&(module.to_string(), name.to_string()))
+    }
+
+    pub fn bindings(&self) -> impl ExactSizeIterator<Item = &HostBinding> + '_ {
+        self.bindings.values()
+    }
+
+    /// Checks every import of `module` against what `linker` provides.
+    pub fn check_imports<T>(
+        &self,
+        mut store: impl AsContextMut<Data = T>,
+        linker: &Linker<T>,
+        module: &Module,
+    ) -> Result<(), ImportReport> {
+        let mut problems = Vec::new();
+
+        for import in module.imports() {
+            let expected = ExportKind::of(&import.ty());
+            let provided = linker
+                .get(&mut store, import.module(), import.name())
+                .map(|item| ExportKind::of(&item.ty(&store)));
+
+            if provided.as_ref() == Some(&expected) {
+                continue;
+            }
+
+            problems.push(ImportProblem {
+                module: import.module().to_string(),
+                name: import.name().to_string(),
+                expected,
+                provided,
+                binding: self.get(import.module(), import.name()).cloned(),
+            });
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ImportReport { problems })
+        }
+    }
+}
+
+pub(crate) fn descriptors<L: MarshaledTyList>() -> Vec<TyDescriptor> {
+    let mut types = Vec::new();
+    L::for_each_descriptor(&mut |ty| types.push(ty));
+    types
+}
+
+// === Dynamic Invocation === //
+
+/// A scalar argument or result of a dynamically invoked host function.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DynValue {
+    Bool(bool),
+    Char(char),
+    Signed(i64),
+    Unsigned(u64),
+    Float(f64),
+}
+
+impl fmt::Display for DynValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool(v) => v.fmt(f),
+            Self::Char(v) => write!(f, "{v:?}"),
+            Self::Signed(v) => v.fmt(f),
+            Self::Unsigned(v) => v.fmt(f),
+            Self::Float(v) => v.fmt(f),
+        }
+    }
+}
+
+impl DynValue {
+    /// Parses a value of type `ty` the way it would be displayed.
+    pub fn parse(text: &str, ty: TyDescriptor) -> anyhow::Result<Self> {
+        let text = text.trim();
+        let value = match ty {
+            TyDescriptor::Bool => Self::Bool(text.parse()?),
+            TyDescriptor::Char => {
+                let inner = text
+                    .strip_prefix('\'')
+                    .and_then(|text| text.strip_suffix('\''))
+                    .unwrap_or(text);
+
+                let mut chars = inner.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Self::Char(c),
+                    _ => anyhow::bail!("{text:?} is not a single character"),
+                }
+            }
+            TyDescriptor::Int { signed: true, .. } => Self::Signed(text.parse()?),
+            TyDescriptor::Int { signed: false, .. } => Self::Unsigned(text.parse()?),
+            TyDescriptor::Float { .. } => Self::Float(text.parse()?),
+            TyDescriptor::Opaque => anyhow::bail!("opaque values can't be parsed"),
+        };
+
+        value.to_val(ty)?;
+        Ok(value)
+    }
+
+    pub(crate) fn to_val(self, ty: TyDescriptor) -> anyhow::Result<Val> {
+        let val = match (self, ty) {
+            (Self::Bool(v), TyDescriptor::Bool) => Val::I32(v.into()),
+            (Self::Char(v), TyDescriptor::Char) => Val::I32(u32::from(v) as i32),
+            (Self::Signed(v), TyDescriptor::Int { signed: true, bits }) => {
+                let (min, max) = (i64::MIN >> (64 - bits), i64::MAX >> (64 - bits));
+                anyhow::ensure!((min..=max).contains(&v), "{v} does not fit in an i{bits}");
+
+                if bits == 64 {
+                    Val::I64(v)
+                } else {
+                    Val::I32(v as i32)
+                }
+            }
+            (
+                Self::Unsigned(v),
+                TyDescriptor::Int {
+                    signed: false,
+                    bits,
+                },
+            ) => {
+                anyhow::ensure!(
+                    v <= u64::MAX >> (64 - bits),
+                    "{v} does not fit in a u{bits}"
+                );
+
+                if bits == 64 {
+                    Val::I64(v as i64)
+                } else {
+                    Val::I32(v as u32 as i32)
+                }
+            }
+            // (narrowing to an `f32` rounds rather than failing, like `as` does)
+            (Self::Float(v), TyDescriptor::Float { bits: 32 }) => Val::F32((v as f32).to_bits()),
+            (Self::Float(v), TyDescriptor::Float { .. }) => Val::F64(v.to_bits()),
+            _ => anyhow::bail!("{self} is not a valid {ty}"),
+        };
+
+        Ok(val)
+    }
+
+    pub(crate) fn from_val(val: &Val, ty: TyDescriptor) -> anyhow::Result<Self> {
+        let value = match (ty, val) {
+            (TyDescriptor::Bool, Val::I32(v)) => Self::Bool(*v != 0),
+            (TyDescriptor::Char, Val::I32(v)) => {
+                Self::Char(char::from_u32(*v as u32).context("invalid character")?)
+            }
+            (TyDescriptor::Int { signed: true, .. }, Val::I32(v)) => Self::Signed((*v).into()),
+            (TyDescriptor::Int { signed: true, .. }, Val::I64(v)) => Self::Signed(*v),
+            (TyDescriptor::Int { signed: false, .. }, Val::I32(v)) => {
+                Self::Unsigned((*v as u32).into())
+            }
+            (TyDescriptor::Int { signed: false, .. }, Val::I64(v)) => Self::Unsigned(*v as u64),
+            (TyDescriptor::Float { .. }, Val::F32(v)) => Self::Float(f32::from_bits(*v).into()),
+            (TyDescriptor::Float { .. }, Val::F64(v)) => Self::Float(f64::from_bits(*v)),
+            _ => anyhow::bail!("unexpected {} for a {ty}", val.ty()),
+        };
+
+        Ok(value)
+    }
+}
+
+impl BindingRegistry {
+    /// Calls the host function bound as `module::name` with dynamically typed arguments. Only
+    /// functions whose signature is [scalar](HostBinding::is_scalar) can be invoked this way.
+    pub fn invoke<T>(
+        &self,
+        mut store: impl AsContextMut<Data = T>,
+        linker: &Linker<T>,
+        module: &str,
+        name: &str,
+        args: &[DynValue],
+    ) -> anyhow::Result<Vec<DynValue>> {
+        let binding = self
+            .get(module, name)
+            .with_context(|| format!("{module}::{name} was not bound through the registry"))?;
+
+        anyhow::ensure!(
+            binding.is_scalar(),
+            "{module}::{name} takes or returns non-scalar values"
+        );
+        anyhow::ensure!(
+            args.len() == binding.param_types.len(),
+            "{module}::{name} takes {} argument(s) but {} were given",
+            binding.param_types.len(),
+            args.len(),
+        );
+
+        let params = args
+            .iter()
+            .zip(&binding.param_types)
+            .map(|(arg, &ty)| arg.to_val(ty))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let Some(Extern::Func(func)) = linker.get(&mut store, module, name) else {
+            anyhow::bail!("{module}::{name} is not defined in the linker");
+        };
+
+        let mut results = vec![Val::I32(0); binding.result_types.len()];
+        func.call(&mut store, &params, &mut results)?;
+
+        results
+            .iter()
+            .zip(&binding.result_types)
+            .map(|(val, &ty)| DynValue::from_val(val, ty))
+            .collect()
+    }
+}
+
+// === ImportReport === //
+
+/// An import which the linker either doesn't provide or provides with the wrong type.
+#[derive(Debug, Clone)]
+pub struct ImportProblem {
+    pub module: String,
+    pub name: String,
+
+    /// The type the module imports.
+    pub expected: ExportKind,
+
+    /// The type the linker provides, if any.
+    pub provided: Option<ExportKind>,
+
+    /// The host function bound under this name, if it was bound through the registry.
+    pub binding: Option<HostBinding>,
+}
+
+impl fmt::Display for ImportProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            module,
+            name,
+            expected,
+            ..
+        } = self;
+
+        match (&self.provided, &self.binding) {
+            (None, _) => write!(
+                f,
+                "unsatisfied import {module}::{name} (expected {expected})"
+            ),
+            (Some(_), Some(binding)) => write!(
+                f,
+                "import {module}::{name} expects {expected} but is bound to fn{} -> {} ({})",
+                binding.params, binding.results, binding.signature
+            ),
+            (Some(provided), None) => write!(
+                f,
+                "import {module}::{name} expects {expected} but is provided as {provided}"
+            ),
+        }
+    }
+}
+
+/// Every import of a module which its linker can't satisfy.
+#[derive(Debug, Clone)]
+pub struct ImportReport {
+    pub problems: Vec<ImportProblem>,
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "module has {} unsatisfied or mismatched import(s)",
+            self.problems.len()
+        )?;
+
+        for problem in &self.problems {
+            write!(f, "\n- {problem}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ImportReport {}
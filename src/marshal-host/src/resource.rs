@@ -0,0 +1,230 @@
+//! Host objects lent to guests.
+//!
+//! A [`ResourceTable`] keeps host objects on behalf of a guest and hands out [`WasmHandle`]s to
+//! them. Guests only ever see the handles, which the host resolves back to its objects inside its
+//! host functions. Guests release their handles with [`WasmHandle::release`], which drops the
+//! object once [`bind_resources`] has defined its import.
+
+use std::{
+    any::{type_name, Any},
+    fmt,
+};
+
+use wasmtime::{Caller, Linker};
+
+use crate::{bind_to_linker, WasmHandle, RESOURCE_MODULE};
+
+// === ResourceTable === //
+
+/// Handles store a slot index in their lower bits and the generation of the slot in their upper
+/// bits so that handles to released objects aren't mistaken for the slot's new occupant.
+const INDEX_BITS: u32 = 24;
+
+const MAX_SLOTS: usize = (1 << INDEX_BITS) - 1;
+
+struct Slot {
+    generation: u8,
+    value: Option<(Box<dyn Any + Send>, &'static str)>,
+}
+
+#[derive(Default)]
+pub struct ResourceTable {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    len: usize,
+}
+
+impl fmt::Debug for ResourceTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResourceTable")
+            .field("len", &self.len)
+            .field("capacity", &self.slots.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ResourceTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert<T: 'static + Send>(&mut self, value: T) -> anyhow::Result<WasmHandle<T>> {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                anyhow::ensure!(
+                    self.slots.len() < MAX_SLOTS,
+                    "too many resources are alive at once (max: {MAX_SLOTS})"
+                );
+
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: None,
+                });
+                self.slots.len() as u32 - 1
+            }
+        };
+
+        let slot = &mut self.slots[index as usize];
+        slot.value = Some((Box::new(value), type_name::<T>()));
+        self.len += 1;
+
+        Ok(WasmHandle::from_raw(Self::encode(index, slot.generation)).unwrap())
+    }
+
+    fn encode(index: u32, generation: u8) -> u32 {
+        ((generation as u32) << INDEX_BITS) | (index + 1)
+    }
+
+    fn slot_index<T>(&self, handle: WasmHandle<T>) -> anyhow::Result<usize> {
+        let raw = handle.raw();
+
+        // Guests can forge handles, including ones whose index bits are zero.
+        let Some(index) = (raw & MAX_SLOTS as u32).checked_sub(1) else {
+            anyhow::bail!("resource handle {raw:#x} was released or never allocated");
+        };
+
+        match self.slots.get(index as usize) {
+            Some(slot) if slot.value.is_some() && Self::encode(index, slot.generation) == raw => {
+                Ok(index as usize)
+            }
+            _ => anyhow::bail!("resource handle {raw:#x} was released or never allocated"),
+        }
+    }
+
+    fn type_error<T>(raw: u32, actual: &str) -> anyhow::Error {
+        anyhow::anyhow!(
+            "resource handle {raw:#x} refers to a `{actual}`, not a `{}`",
+            type_name::<T>(),
+        )
+    }
+
+    pub fn contains<T: 'static>(&self, handle: WasmHandle<T>) -> bool {
+        self.get(handle).is_ok()
+    }
+
+    pub fn get<T: 'static>(&self, handle: WasmHandle<T>) -> anyhow::Result<&T> {
+        let index = self.slot_index(handle)?;
+        let (value, actual) = self.slots[index].value.as_ref().unwrap();
+
+        value
+            .downcast_ref()
+            .ok_or_else(|| Self::type_error::<T>(handle.raw(), actual))
+    }
+
+    pub fn get_mut<T: 'static>(&mut self, handle: WasmHandle<T>) -> anyhow::Result<&mut T> {
+        let index = self.slot_index(handle)?;
+        let (value, actual) = self.slots[index].value.as_mut().unwrap();
+
+        value
+            .downcast_mut()
+            .ok_or_else(|| Self::type_error::<T>(handle.raw(), actual))
+    }
+
+    /// Checks that the object behind a handle of one type, typically one erased by the guest, is
+    /// a `U`.
+    pub fn downcast<T, U: 'static>(&self, handle: WasmHandle<T>) -> anyhow::Result<WasmHandle<U>> {
+        let handle = handle.cast::<U>();
+        self.get(handle)?;
+        Ok(handle)
+    }
+
+    /// Takes the object out of the table, invalidating its handle.
+    pub fn remove<T: 'static>(&mut self, handle: WasmHandle<T>) -> anyhow::Result<T> {
+        self.get(handle)?;
+        let value = self.take(handle).unwrap();
+        Ok(*value.downcast().unwrap())
+    }
+
+    /// Drops the object behind a handle of any type.
+    pub fn release<T>(&mut self, handle: WasmHandle<T>) -> anyhow::Result<()> {
+        self.take(handle).map(drop)
+    }
+
+    fn take<T>(&mut self, handle: WasmHandle<T>) -> anyhow::Result<Box<dyn Any + Send>> {
+        let index = self.slot_index(handle)?;
+        let slot = &mut self.slots[index];
+        let (value, _) = slot.value.take().unwrap();
+
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(index as u32);
+        self.len -= 1;
+
+        Ok(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+pub trait StoreHasResources {
+    fn resources(&self) -> &ResourceTable;
+
+    fn resources_mut(&mut self) -> &mut ResourceTable;
+}
+
+// === Bindings === //
+
+/// Defines the import used by [`WasmHandle::release`] in the `linker`.
+pub fn bind_resources<D>(linker: &mut Linker<D>) -> anyhow::Result<()>
+where
+    D: 'static + StoreHasResources,
+{
+    bind_to_linker(
+        linker,
+        RESOURCE_MODULE,
+        "resource_drop",
+        |mut caller: Caller<'_, D>, handle: WasmHandle<()>| {
+            caller.data_mut().resources_mut().release(handle)
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bytemuck::Zeroable;
+
+    use super::*;
+
+    #[test]
+    fn forged_handles_are_rejected() {
+        let mut table = ResourceTable::new();
+        let handle = table.insert(5u32).unwrap();
+
+        let forged = [
+            WasmHandle::zeroed(),
+            WasmHandle::from_raw(1 << INDEX_BITS).unwrap(),
+            WasmHandle::from_raw(handle.raw() + 1).unwrap(),
+            WasmHandle::from_raw(handle.raw() + (1 << INDEX_BITS)).unwrap(),
+            WasmHandle::from_raw(u32::MAX).unwrap(),
+        ];
+
+        for forged in forged {
+            assert!(table.get::<u32>(forged).is_err(), "{forged:?}");
+            assert!(table.release(forged).is_err(), "{forged:?}");
+        }
+
+        assert_eq!(*table.get(handle).unwrap(), 5);
+    }
+
+    #[test]
+    fn released_handles_are_rejected() {
+        let mut table = ResourceTable::new();
+        let handle = table.insert(5u32).unwrap();
+        table.release(handle).unwrap();
+
+        // The slot is reused with a new generation.
+        let reused = table.insert(6u32).unwrap();
+        assert_ne!(reused, handle);
+        assert!(table.get(handle).is_err());
+        assert!(table.release(handle).is_err());
+        assert_eq!(*table.get(reused).unwrap(), 6);
+    }
+}
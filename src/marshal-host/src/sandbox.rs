@@ -0,0 +1,579 @@
+//! Centralized hosting of untrusted plugins under per-plugin resource quotas.
+//!
+//! Every plugin gets its own store, limited by the [`QuotaProfile`] it was spawned with. Plugins
+//! which trap, panic a host function, or exceed their quotas are torn down without affecting the
+//! others, and the [`Violation`] which got them torn down is kept for reporting.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::Context;
+use wasmtime::{Config, Engine, Instance, Linker, Module, Store, StoreLimitsBuilder, Trap};
+
+use crate::{
+    growth::{install_growth_notifier, GrowthNotifier, GrowthOutcome, StoreHasGrowthNotifier},
+    MarshaledTyList,
+};
+
+// === QuotaProfile === //
+
+/// The resources a single plugin may use.
+#[derive(Debug, Clone)]
+pub struct QuotaProfile {
+    /// The largest size in bytes any one of the plugin's memories may grow to.
+    pub memory_bytes: usize,
+
+    /// The largest number of elements any one of the plugin's tables may grow to.
+    pub table_elements: u32,
+
+    /// The number of instances the plugin may create.
+    pub instances: usize,
+
+    /// The fuel the plugin may consume over its lifetime, or `None` for no limit.
+    pub fuel: Option<u64>,
+}
+
+impl Default for QuotaProfile {
+    fn default() -> Self {
+        Self {
+            memory_bytes: 64 << 20,
+            table_elements: 10_000,
+            instances: 1,
+            fuel: None,
+        }
+    }
+}
+
+// === Violation === //
+
+/// The reason a plugin was torn down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// The plugin used up its fuel budget.
+    FuelExhausted,
+
+    /// The plugin tried to grow a memory past its quota.
+    MemoryQuota { requested: usize, limit: usize },
+
+    /// The plugin trapped or one of its calls otherwise failed.
+    Trapped(String),
+
+    /// A host function called by the plugin panicked.
+    Panicked(String),
+
+    /// The plugin was killed by the host.
+    Killed,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FuelExhausted => f.write_str("exhausted its fuel budget"),
+            Self::MemoryQuota { requested, limit } => write!(
+                f,
+                "tried to grow a memory to {requested} bytes, past its quota of {limit} bytes"
+            ),
+            Self::Trapped(err) => write!(f, "trapped: {err}"),
+            Self::Panicked(msg) => write!(f, "panicked a host function: {msg}"),
+            Self::Killed => f.write_str("was killed by the host"),
+        }
+    }
+}
+
+/// The error returned when calling into a plugin which was torn down.
+#[derive(Debug, Clone)]
+pub struct PluginTerminated {
+    pub plugin: Arc<str>,
+    pub violation: Violation,
+}
+
+impl fmt::Display for PluginTerminated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "plugin {} {}", self.plugin, self.violation)
+    }
+}
+
+impl std::error::Error for PluginTerminated {}
+
+// === SandboxData === //
+
+/// The quota usage of a plugin, shared with its growth callback.
+#[derive(Debug, Default)]
+struct UsageCounters {
+    memory_bytes: AtomicUsize,
+    peak_memory_bytes: AtomicUsize,
+
+    /// The size of the most recent refused memory growth, or zero.
+    denied_growth: AtomicUsize,
+    denied_growths: AtomicU64,
+}
+
+/// The data of a plugin's store.
+pub struct SandboxData<T> {
+    notifier: GrowthNotifier,
+    pub data: T,
+}
+
+impl<T> SandboxData<T> {
+    pub fn plugin(&self) -> &Arc<str> {
+        self.notifier.plugin()
+    }
+}
+
+impl<T> StoreHasGrowthNotifier for SandboxData<T> {
+    type Limiter = wasmtime::StoreLimits;
+
+    fn growth_notifier(&mut self) -> &mut GrowthNotifier {
+        &mut self.notifier
+    }
+}
+
+// === SandboxManager === //
+
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PluginId(u64);
+
+/// The resources a plugin has used.
+#[derive(Debug, Clone)]
+pub struct PluginUsage {
+    pub id: PluginId,
+    pub plugin: Arc<str>,
+
+    /// The bytes currently allocated to the plugin's memories.
+    pub memory_bytes: usize,
+    pub peak_memory_bytes: usize,
+
+    /// The fuel consumed so far, or `None` if the plugin has no fuel budget.
+    pub fuel_consumed: Option<u64>,
+
+    /// The number of memory growths refused by the plugin's quota.
+    pub denied_growths: u64,
+
+    /// Why the plugin was torn down, if it was.
+    pub violation: Option<Violation>,
+}
+
+struct Plugin<T> {
+    name: Arc<str>,
+    profile: QuotaProfile,
+    counters: Arc<UsageCounters>,
+    fuel_consumed: Option<u64>,
+
+    /// The fuel consumed before the plugin was last refueled.
+    fuel_banked: u64,
+
+    /// The fuel the plugin had when it was last refueled.
+    fuel_granted: u64,
+
+    state: PluginState<T>,
+}
+
+enum PluginState<T> {
+    Running {
+        store: Store<SandboxData<T>>,
+        instance: Instance,
+    },
+    Terminated(Violation),
+}
+
+/// Owns the engine and stores of a set of plugins and enforces their quotas.
+pub struct SandboxManager<T> {
+    engine: Engine,
+    plugins: HashMap<PluginId, Plugin<T>>,
+    next_id: u64,
+}
+
+impl<T: 'static> SandboxManager<T> {
+    /// Creates a manager whose engine is configured by `config`, with fuel consumption enabled.
+    pub fn new(config: &mut Config) -> anyhow::Result<Self> {
+        config.consume_fuel(true);
+
+        Ok(Self {
+            engine: Engine::new(config)?,
+            plugins: HashMap::new(),
+            next_id: 0,
+        })
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Instantiates `module` as a new plugin limited by `profile`. Plugins which fail to
+    /// instantiate are not added.
+    pub fn spawn(
+        &mut self,
+        name: impl Into<Arc<str>>,
+        profile: QuotaProfile,
+        linker: &Linker<SandboxData<T>>,
+        module: &Module,
+        data: T,
+    ) -> anyhow::Result<PluginId> {
+        let name = name.into();
+        let counters = Arc::new(UsageCounters::default());
+
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(profile.memory_bytes)
+            .table_elements(profile.table_elements)
+            .instances(profile.instances)
+            .build();
+
+        let mut notifier = GrowthNotifier::new(name.clone(), limits);
+        notifier.on_growth({
+            let counters = counters.clone();
+            move |growth| match growth.outcome {
+                GrowthOutcome::Allowed => {
+                    let added = growth.new_size - growth.old_size;
+                    let total = counters.memory_bytes.fetch_add(added, Ordering::Relaxed) + added;
+                    counters
+                        .peak_memory_bytes
+                        .fetch_max(total, Ordering::Relaxed);
+                }
+                GrowthOutcome::Denied => {
+                    counters
+                        .denied_growth
+                        .store(growth.new_size, Ordering::Relaxed);
+                    counters.denied_growths.fetch_add(1, Ordering::Relaxed);
+                }
+                GrowthOutcome::Failed => {
+                    let removed = growth.new_size - growth.old_size;
+                    counters.memory_bytes.fetch_sub(removed, Ordering::Relaxed);
+                }
+            }
+        });
+
+        let mut store = Store::new(&self.engine, SandboxData { notifier, data });
+        install_growth_notifier(&mut store);
+        store.set_fuel(profile.fuel.unwrap_or(u64::MAX))?;
+        let fuel_granted = store.get_fuel()?;
+
+        let instance = linker
+            .instantiate(&mut store, module)
+            .with_context(|| format!("failed to instantiate plugin {name}"))?;
+
+        let id = PluginId(self.next_id);
+        self.next_id += 1;
+
+        let mut plugin = Plugin {
+            name,
+            profile,
+            counters,
+            fuel_consumed: None,
+            fuel_banked: 0,
+            fuel_granted,
+            state: PluginState::Running { store, instance },
+        };
+        plugin.record_fuel();
+
+        self.plugins.insert(id, plugin);
+        Ok(id)
+    }
+
+    /// Calls the export `name` of a plugin, tearing the plugin down if the call fails or exceeds
+    /// its quotas.
+    pub fn call<A, R>(&mut self, id: PluginId, name: &str, args: A) -> anyhow::Result<R>
+    where
+        A: MarshaledTyList,
+        R: MarshaledTyList,
+    {
+        self.with_plugin(id, |store, instance| {
+            let func = instance.get_typed_func::<A::Prims, R::Prims>(&mut *store, name)?;
+            let res = func.call(&mut *store, A::into_prims(args))?;
+            R::from_prims(res).context("failed to deserialize results")
+        })
+    }
+
+    /// Runs `f` against a plugin's store, tearing the plugin down if it fails or exceeds its
+    /// quotas.
+    pub fn with_plugin<R>(
+        &mut self,
+        id: PluginId,
+        f: impl FnOnce(&mut Store<SandboxData<T>>, &Instance) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        let plugin = self.plugins.get_mut(&id).context("no such plugin")?;
+        let PluginState::Running { store, instance } = &mut plugin.state else {
+            return Err(plugin.terminated_error());
+        };
+
+        let denied_before = plugin.counters.denied_growths.load(Ordering::Relaxed);
+        let res = catch_unwind(AssertUnwindSafe(|| f(store, instance)));
+        plugin.record_fuel();
+
+        let violation = match &res {
+            Err(panic) => Some(Violation::Panicked(panic_message(&**panic))),
+            Ok(_) if plugin.counters.denied_growths.load(Ordering::Relaxed) != denied_before => {
+                Some(Violation::MemoryQuota {
+                    requested: plugin.counters.denied_growth.load(Ordering::Relaxed),
+                    limit: plugin.profile.memory_bytes,
+                })
+            }
+            Ok(Err(err)) if err.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) => {
+                Some(Violation::FuelExhausted)
+            }
+            Ok(Err(err)) => Some(Violation::Trapped(format!("{err:#}"))),
+            Ok(Ok(_)) => None,
+        };
+
+        if let Some(violation) = violation {
+            plugin.terminate(violation);
+            return Err(plugin.terminated_error());
+        }
+
+        res.unwrap()
+    }
+
+    /// Tears down a plugin, keeping its usage for reporting.
+    pub fn kill(&mut self, id: PluginId) {
+        if let Some(plugin) = self.plugins.get_mut(&id) {
+            plugin.terminate(Violation::Killed);
+        }
+    }
+
+    /// Tears down a plugin and forgets it entirely.
+    pub fn remove(&mut self, id: PluginId) -> Option<PluginUsage> {
+        let usage = self.usage(id);
+        self.plugins.remove(&id);
+        usage
+    }
+
+    /// Adds fuel to a running plugin's budget.
+    pub fn refuel(&mut self, id: PluginId, fuel: u64) -> anyhow::Result<()> {
+        let plugin = self.plugins.get_mut(&id).context("no such plugin")?;
+        let PluginState::Running { store, .. } = &mut plugin.state else {
+            return Err(plugin.terminated_error());
+        };
+
+        // Bank the fuel consumed so far since the budget may saturate and no longer tell.
+        let remaining = store.get_fuel()?;
+        plugin.fuel_banked = plugin
+            .fuel_banked
+            .saturating_add(plugin.fuel_granted.saturating_sub(remaining));
+
+        store.set_fuel(remaining.saturating_add(fuel))?;
+        plugin.fuel_granted = store.get_fuel()?;
+
+        if let Some(budget) = &mut plugin.profile.fuel {
+            *budget = budget.saturating_add(fuel);
+        }
+
+        Ok(())
+    }
+
+    pub fn data(&self, id: PluginId) -> Option<&T> {
+        match &self.plugins.get(&id)?.state {
+            PluginState::Running { store, .. } => Some(&store.data().data),
+            PluginState::Terminated(_) => None,
+        }
+    }
+
+    pub fn data_mut(&mut self, id: PluginId) -> Option<&mut T> {
+        match &mut self.plugins.get_mut(&id)?.state {
+            PluginState::Running { store, .. } => Some(&mut store.data_mut().data),
+            PluginState::Terminated(_) => None,
+        }
+    }
+
+    pub fn usage(&self, id: PluginId) -> Option<PluginUsage> {
+        let plugin = self.plugins.get(&id)?;
+
+        Some(PluginUsage {
+            id,
+            plugin: plugin.name.clone(),
+            memory_bytes: match plugin.state {
+                PluginState::Running { .. } => plugin.counters.memory_bytes.load(Ordering::Relaxed),
+                PluginState::Terminated(_) => 0,
+            },
+            peak_memory_bytes: plugin.counters.peak_memory_bytes.load(Ordering::Relaxed),
+            fuel_consumed: plugin.fuel_consumed,
+            denied_growths: plugin.counters.denied_growths.load(Ordering::Relaxed),
+            violation: match &plugin.state {
+                PluginState::Running { .. } => None,
+                PluginState::Terminated(violation) => Some(violation.clone()),
+            },
+        })
+    }
+
+    /// The usage of every plugin, ordered by when they were spawned.
+    pub fn report(&self) -> Vec<PluginUsage> {
+        let mut ids = self.plugins.keys().copied().collect::<Vec<_>>();
+        ids.sort();
+        ids.into_iter().filter_map(|id| self.usage(id)).collect()
+    }
+}
+
+impl<T> Plugin<T> {
+    fn record_fuel(&mut self) {
+        let (Some(_), PluginState::Running { store, .. }) = (self.profile.fuel, &self.state) else {
+            return;
+        };
+
+        if let Ok(remaining) = store.get_fuel() {
+            let consumed = self.fuel_granted.saturating_sub(remaining);
+            self.fuel_consumed = Some(self.fuel_banked.saturating_add(consumed));
+        }
+    }
+
+    fn terminate(&mut self, violation: Violation) {
+        if let PluginState::Running { .. } = self.state {
+            self.state = PluginState::Terminated(violation);
+        }
+    }
+
+    fn terminated_error(&self) -> anyhow::Error {
+        let PluginState::Terminated(violation) = &self.state else {
+            unreachable!("plugin is still running");
+        };
+
+        PluginTerminated {
+            plugin: self.name.clone(),
+            violation: violation.clone(),
+        }
+        .into()
+    }
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    if let Some(msg) = panic.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = panic.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WAT: &str = r#"
+        (module
+            (import "host" "boom" (func $boom))
+            (memory 1)
+            (func (export "spin") (loop br 0))
+            (func (export "trap") unreachable)
+            (func (export "boom") call $boom)
+            (func (export "grow") (result i32) (memory.grow (i32.const 10)))
+            (func (export "work") (local i32)
+                (loop
+                    (local.set 0 (i32.add (local.get 0) (i32.const 1)))
+                    (br_if 0 (i32.lt_u (local.get 0) (i32.const 10))))))
+    "#;
+
+    fn spawn(profile: QuotaProfile) -> (SandboxManager<()>, PluginId) {
+        let mut manager = SandboxManager::new(&mut Config::new()).unwrap();
+        let module = Module::new(manager.engine(), WAT).unwrap();
+
+        let mut linker = Linker::new(manager.engine());
+        linker
+            .func_wrap("host", "boom", || -> () { panic!("the host went boom") })
+            .unwrap();
+
+        let id = manager
+            .spawn("test", profile, &linker, &module, ())
+            .unwrap();
+        (manager, id)
+    }
+
+    fn violation(manager: &SandboxManager<()>, id: PluginId) -> Option<Violation> {
+        manager.usage(id).unwrap().violation
+    }
+
+    fn assert_terminated(err: anyhow::Error, expected: &Violation) {
+        let err = err.downcast::<PluginTerminated>().unwrap();
+        assert_eq!(&err.violation, expected);
+    }
+
+    #[test]
+    fn out_of_fuel() {
+        let (mut manager, id) = spawn(QuotaProfile {
+            fuel: Some(1000),
+            ..QuotaProfile::default()
+        });
+
+        let err = manager.call::<(), ()>(id, "spin", ()).unwrap_err();
+        assert_terminated(err, &Violation::FuelExhausted);
+        assert_eq!(violation(&manager, id), Some(Violation::FuelExhausted));
+        assert_eq!(manager.usage(id).unwrap().fuel_consumed, Some(1000));
+
+        // Calls into a torn down plugin fail without running anything.
+        let err = manager.call::<(), ()>(id, "work", ()).unwrap_err();
+        assert_terminated(err, &Violation::FuelExhausted);
+    }
+
+    #[test]
+    fn memory_quota() {
+        let (mut manager, id) = spawn(QuotaProfile {
+            memory_bytes: 4 << 16,
+            ..QuotaProfile::default()
+        });
+        assert_eq!(manager.usage(id).unwrap().memory_bytes, 1 << 16);
+
+        let expected = Violation::MemoryQuota {
+            requested: 11 << 16,
+            limit: 4 << 16,
+        };
+        let err = manager.call::<(), i32>(id, "grow", ()).unwrap_err();
+        assert_terminated(err, &expected);
+
+        let usage = manager.usage(id).unwrap();
+        assert_eq!(usage.violation, Some(expected));
+        assert_eq!(usage.denied_growths, 1);
+        assert_eq!(usage.peak_memory_bytes, 1 << 16);
+        assert_eq!(usage.memory_bytes, 0);
+    }
+
+    #[test]
+    fn trap() {
+        let (mut manager, id) = spawn(QuotaProfile::default());
+
+        manager.call::<(), ()>(id, "trap", ()).unwrap_err();
+        let Some(Violation::Trapped(msg)) = violation(&manager, id) else {
+            panic!("plugin wasn't torn down for trapping");
+        };
+        assert!(msg.contains("unreachable"), "{msg}");
+    }
+
+    #[test]
+    fn host_panic() {
+        let (mut manager, id) = spawn(QuotaProfile::default());
+
+        let err = manager.call::<(), ()>(id, "boom", ()).unwrap_err();
+        let expected = Violation::Panicked("the host went boom".to_string());
+        assert_terminated(err, &expected);
+        assert_eq!(violation(&manager, id), Some(expected));
+    }
+
+    #[test]
+    fn refuel() {
+        let (mut manager, id) = spawn(QuotaProfile {
+            fuel: Some(1000),
+            ..QuotaProfile::default()
+        });
+
+        manager.call::<(), ()>(id, "work", ()).unwrap();
+        let consumed = manager.usage(id).unwrap().fuel_consumed.unwrap();
+        assert!(consumed > 0 && consumed < 1000);
+
+        // Refueling grows the budget without counting as consumption.
+        manager.refuel(id, 500).unwrap();
+        manager.refuel(id, u64::MAX).unwrap();
+        manager.with_plugin(id, |_, _| Ok(())).unwrap();
+        assert_eq!(manager.usage(id).unwrap().fuel_consumed, Some(consumed));
+
+        manager.call::<(), ()>(id, "work", ()).unwrap();
+        assert_eq!(manager.usage(id).unwrap().fuel_consumed, Some(2 * consumed));
+
+        // Refueling a torn down plugin fails.
+        manager.kill(id);
+        assert_eq!(violation(&manager, id), Some(Violation::Killed));
+        assert!(manager.refuel(id, 500).is_err());
+    }
+}
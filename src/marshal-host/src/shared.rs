@@ -0,0 +1,182 @@
+//! Atomic access to individual fields of structs living in shared memories.
+//!
+//! Guests running on other threads may be touching the same structs, so the fields used to
+//! synchronize with them (ring buffer heads, sequence numbers, locks) must be accessed atomically.
+//! Operations are sequentially consistent, like wasm's own atomic instructions.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use anyhow::Context;
+use wasmtime::SharedMemory;
+
+use crate::{AtomicField, AtomicValue, WasmPtr};
+
+// === SharedMemoryExt === //
+
+pub trait SharedMemoryExt {
+    /// Atomically reads the `F` at `offset` bytes into the struct at `ptr`.
+    fn read_field_atomic<F: AtomicValue, S>(
+        &self,
+        ptr: WasmPtr<S>,
+        offset: u32,
+    ) -> anyhow::Result<F>;
+
+    /// Atomically writes the `F` at `offset` bytes into the struct at `ptr`.
+    fn write_field_atomic<F: AtomicValue, S>(
+        &self,
+        ptr: WasmPtr<S>,
+        offset: u32,
+        value: F,
+    ) -> anyhow::Result<()>;
+
+    /// Atomically replaces the `F` at `offset` bytes into the struct at `ptr` with `new` if it is
+    /// `current`, returning the previous value in `Ok` if it was replaced and in `Err` otherwise.
+    fn compare_exchange_field_atomic<F: AtomicValue, S>(
+        &self,
+        ptr: WasmPtr<S>,
+        offset: u32,
+        current: F,
+        new: F,
+    ) -> anyhow::Result<Result<F, F>>;
+
+    fn load_atomic<S, F: AtomicValue>(
+        &self,
+        ptr: WasmPtr<S>,
+        field: AtomicField<S, F>,
+    ) -> anyhow::Result<F> {
+        self.read_field_atomic(ptr, field.offset())
+    }
+
+    fn store_atomic<S, F: AtomicValue>(
+        &self,
+        ptr: WasmPtr<S>,
+        field: AtomicField<S, F>,
+        value: F,
+    ) -> anyhow::Result<()> {
+        self.write_field_atomic(ptr, field.offset(), value)
+    }
+
+    fn compare_exchange_atomic<S, F: AtomicValue>(
+        &self,
+        ptr: WasmPtr<S>,
+        field: AtomicField<S, F>,
+        current: F,
+        new: F,
+    ) -> anyhow::Result<Result<F, F>> {
+        self.compare_exchange_field_atomic(ptr, field.offset(), current, new)
+    }
+}
+
+/// An atomic integer in a shared memory, of either width.
+enum AtomicCell<'a> {
+    U32(&'a AtomicU32),
+    U64(&'a AtomicU64),
+}
+
+impl AtomicCell<'_> {
+    fn load(&self) -> u64 {
+        match self {
+            Self::U32(cell) => u32::from_le(cell.load(Ordering::SeqCst)).into(),
+            Self::U64(cell) => u64::from_le(cell.load(Ordering::SeqCst)),
+        }
+    }
+
+    fn store(&self, bits: u64) {
+        match self {
+            Self::U32(cell) => cell.store((bits as u32).to_le(), Ordering::SeqCst),
+            Self::U64(cell) => cell.store(bits.to_le(), Ordering::SeqCst),
+        }
+    }
+
+    fn compare_exchange(&self, current: u64, new: u64) -> Result<u64, u64> {
+        let (ordering, failure) = (Ordering::SeqCst, Ordering::SeqCst);
+
+        match self {
+            Self::U32(cell) => cell
+                .compare_exchange(
+                    (current as u32).to_le(),
+                    (new as u32).to_le(),
+                    ordering,
+                    failure,
+                )
+                .map(|bits| u32::from_le(bits).into())
+                .map_err(|bits| u32::from_le(bits).into()),
+            Self::U64(cell) => cell
+                .compare_exchange(current.to_le(), new.to_le(), ordering, failure)
+                .map(u64::from_le)
+                .map_err(u64::from_le),
+        }
+    }
+}
+
+fn atomic_cell<F: AtomicValue>(
+    mem: &SharedMemory,
+    base: u32,
+    offset: u32,
+) -> anyhow::Result<AtomicCell<'_>> {
+    let data = mem.data();
+    let addr = u64::from(base) + u64::from(offset);
+
+    let cell = usize::try_from(addr)
+        .ok()
+        .filter(|&addr| addr as u64 + u64::from(F::WIDTH) <= data.len() as u64)
+        .map(|addr| data[addr].get())
+        .with_context(|| {
+            format!(
+                "atomic field at {addr:#x} of width {} lies outside of the {}-byte shared memory",
+                F::WIDTH,
+                data.len(),
+            )
+        })?;
+
+    // Safety: the cell is in bounds and shared memories are only ever accessed through
+    // `UnsafeCell`s, so aliasing it as an atomic is sound once it is aligned.
+    unsafe {
+        match F::WIDTH {
+            4 if cell.cast::<AtomicU32>().is_aligned() => {
+                Ok(AtomicCell::U32(&*cell.cast::<AtomicU32>()))
+            }
+            8 if cell.cast::<AtomicU64>().is_aligned() => {
+                Ok(AtomicCell::U64(&*cell.cast::<AtomicU64>()))
+            }
+            4 | 8 => anyhow::bail!("atomic field at {addr:#x} is misaligned"),
+            width => unreachable!("unsupported atomic width {width}"),
+        }
+    }
+}
+
+impl SharedMemoryExt for SharedMemory {
+    fn read_field_atomic<F: AtomicValue, S>(
+        &self,
+        ptr: WasmPtr<S>,
+        offset: u32,
+    ) -> anyhow::Result<F> {
+        let cell = atomic_cell::<F>(self, ptr.addr().get(), offset)?;
+        Ok(F::from_bits(cell.load()))
+    }
+
+    fn write_field_atomic<F: AtomicValue, S>(
+        &self,
+        ptr: WasmPtr<S>,
+        offset: u32,
+        value: F,
+    ) -> anyhow::Result<()> {
+        let cell = atomic_cell::<F>(self, ptr.addr().get(), offset)?;
+        cell.store(value.to_bits());
+        Ok(())
+    }
+
+    fn compare_exchange_field_atomic<F: AtomicValue, S>(
+        &self,
+        ptr: WasmPtr<S>,
+        offset: u32,
+        current: F,
+        new: F,
+    ) -> anyhow::Result<Result<F, F>> {
+        let cell = atomic_cell::<F>(self, ptr.addr().get(), offset)?;
+        Ok(cell
+            .compare_exchange(current.to_bits(), new.to_bits())
+            .map(F::from_bits)
+            .map_err(F::from_bits))
+    }
+}
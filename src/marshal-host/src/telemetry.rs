@@ -0,0 +1,159 @@
+//! Reporting of host calls which take longer than a threshold.
+//!
+//! Host functions are timed by binding them through [`Timed`]. Calls under the threshold only cost
+//! two clock reads, and nothing is timed at all while the threshold is disabled.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    crossing::ArgSummary, impl_variadic, HostSideMarshaledFunc, MarshaledTy, MarshaledTyList,
+};
+
+// === CallTelemetry === //
+
+/// A host call which took at least as long as its telemetry's threshold.
+#[derive(Debug, Clone)]
+pub struct SlowCall {
+    pub label: Arc<str>,
+    pub duration: Duration,
+    pub args: ArgSummary,
+}
+
+impl fmt::Display for SlowCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{} took {:.2?}", self.label, self.args, self.duration)
+    }
+}
+
+pub trait TelemetrySink: Send + Sync {
+    fn slow_call(&self, call: SlowCall);
+}
+
+impl<F: Send + Sync + Fn(SlowCall)> TelemetrySink for F {
+    fn slow_call(&self, call: SlowCall) {
+        self(call)
+    }
+}
+
+/// The threshold above which calls are reported, and where they're reported to.
+pub struct CallTelemetry {
+    threshold_nanos: AtomicU64,
+    sink: Box<dyn TelemetrySink>,
+}
+
+impl fmt::Debug for CallTelemetry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CallTelemetry")
+            .field("threshold", &self.threshold())
+            .finish_non_exhaustive()
+    }
+}
+
+impl CallTelemetry {
+    pub fn new(threshold: Option<Duration>, sink: impl 'static + TelemetrySink) -> Arc<Self> {
+        let telemetry = Self {
+            threshold_nanos: AtomicU64::new(0),
+            sink: Box::new(sink),
+        };
+        telemetry.set_threshold(threshold);
+        Arc::new(telemetry)
+    }
+
+    /// The duration above which calls are reported, or `None` if timing is disabled.
+    pub fn threshold(&self) -> Option<Duration> {
+        match self.threshold_nanos.load(Ordering::Relaxed) {
+            0 => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+
+    pub fn set_threshold(&self, threshold: Option<Duration>) {
+        let nanos = threshold.map_or(0, |threshold| {
+            u64::try_from(threshold.as_nanos())
+                .unwrap_or(u64::MAX)
+                .max(1)
+        });
+        self.threshold_nanos.store(nanos, Ordering::Relaxed);
+    }
+
+    fn finish(&self, label: &Arc<str>, start: Instant, threshold: Duration, args: ArgSummary) {
+        let duration = start.elapsed();
+        if duration < threshold {
+            return;
+        }
+
+        self.sink.slow_call(SlowCall {
+            label: label.clone(),
+            duration,
+            args,
+        });
+    }
+}
+
+// === Timed === //
+
+/// A host function whose calls are reported to a [`CallTelemetry`] when they're slow.
+pub struct Timed<F> {
+    pub label: Arc<str>,
+    pub telemetry: Arc<CallTelemetry>,
+    pub func: F,
+}
+
+impl<F> Timed<F> {
+    pub fn new(label: impl Into<Arc<str>>, telemetry: &Arc<CallTelemetry>, func: F) -> Self {
+        Self {
+            label: label.into(),
+            telemetry: telemetry.clone(),
+            func,
+        }
+    }
+}
+
+macro_rules! impl_func_ty {
+    ($($ty:ident)*) => {
+        impl<D, F, Ret, $($ty: MarshaledTy,)*> HostSideMarshaledFunc<D, ($($ty,)*), Ret> for Timed<F>
+        where
+            D: 'static,
+            Ret: MarshaledTyList,
+            F: 'static + Send + Sync + Fn(wasmtime::Caller<'_, D>, $($ty,)*) -> anyhow::Result<Ret>,
+        {
+            type PrimParams<'a> = (wasmtime::Caller<'a, D>, $(<$ty as MarshaledTy>::Prim,)*);
+            type PrimResults = anyhow::Result<Ret::Prims>;
+
+            #[allow(non_snake_case, unused_mut)]
+            fn wrap_host(self) -> impl for<'a> wasmtime::IntoFunc<D, Self::PrimParams<'a>, Self::PrimResults> {
+                let Self { label, telemetry, func } = self;
+
+                move |caller: wasmtime::Caller<'_, D>, $($ty: <$ty as MarshaledTy>::Prim,)*| {
+                    let prims = ($($ty,)*);
+
+                    // The arguments are captured up front since they're consumed by the call.
+                    let timing = telemetry
+                        .threshold()
+                        .map(|threshold| (Instant::now(), threshold, ArgSummary::capture(&prims)));
+
+                    let Some(($($ty,)*)) = <($($ty,)*)>::from_prims(prims) else {
+                        anyhow::bail!("failed to parse arguments to {label}");
+                    };
+
+                    let res = func(caller, $($ty),*).map(MarshaledTyList::into_prims);
+
+                    if let Some((start, threshold, args)) = timing {
+                        telemetry.finish(&label, start, threshold, args);
+                    }
+
+                    res
+                }
+            }
+        }
+    };
+}
+
+impl_variadic!(impl_func_ty);
@@ -0,0 +1,237 @@
+//! A harness for running the tests a guest exports and reporting their results like a normal test
+//! runner.
+//!
+//! Tests are exported functions taking and returning nothing whose names start with
+//! [`TEST_EXPORT_PREFIX`]. Each runs in a fresh store and fails if it can't be instantiated,
+//! traps, or exceeds its timeout. Guests can log output, including their panic messages, by
+//! calling the `crt_test.log` import with a [`WasmStr`]. This output is captured per test.
+//!
+//! Timeouts are enforced with epoch interruption, so the engine of the harness's linker must have
+//! been created with [`Config::epoch_interruption`](wasmtime::Config::epoch_interruption) enabled.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use wasmtime::{Caller, Extern, Linker, Module, Store, Trap};
+
+use crate::{MarshaledTy, MemoryRead, WasmStr};
+
+/// The prefix of the names of exported tests.
+pub const TEST_EXPORT_PREFIX: &str = "__crt_test_";
+
+/// The module under which the harness provides its imports.
+pub const TEST_IMPORT_MODULE: &str = "crt_test";
+
+/// How often the epoch is incremented while tests run.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+// === TestHarness === //
+
+pub struct TestHarness<T> {
+    linker: Linker<T>,
+    make_data: Box<dyn Fn() -> T>,
+
+    /// The longest a single test may run for.
+    pub timeout: Duration,
+}
+
+impl<T: 'static> TestHarness<T> {
+    /// Creates a harness instantiating tests with the imports of `linker` and creating the data of
+    /// each test's store with `make_data`.
+    pub fn new(linker: &Linker<T>, make_data: impl 'static + Fn() -> T) -> Self {
+        let mut linker = linker.clone();
+        linker.allow_shadowing(true);
+
+        Self {
+            linker,
+            make_data: Box::new(make_data),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// The names of the tests `module` exports, without their prefix.
+    pub fn tests(module: &Module) -> Vec<String> {
+        module
+            .exports()
+            .filter(|export| {
+                export
+                    .ty()
+                    .func()
+                    .is_some_and(|ty| ty.params().len() == 0 && ty.results().len() == 0)
+            })
+            .filter_map(|export| export.name().strip_prefix(TEST_EXPORT_PREFIX))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Runs every test `module` exports whose name contains `filter`.
+    pub fn run(&self, module: &Module, filter: &str) -> TestReport {
+        let ticking = AtomicBool::new(true);
+        let engine = self.linker.engine();
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                while ticking.load(Ordering::Relaxed) {
+                    std::thread::sleep(EPOCH_TICK);
+                    engine.increment_epoch();
+                }
+            });
+
+            let results = Self::tests(module)
+                .into_iter()
+                .filter(|name| name.contains(filter))
+                .map(|name| self.run_test(module, name))
+                .collect();
+
+            ticking.store(false, Ordering::Relaxed);
+            TestReport { results }
+        })
+    }
+
+    fn run_test(&self, module: &Module, name: String) -> TestResult {
+        let start = Instant::now();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let outcome = match self.try_run_test(module, &name, &output) {
+            Ok(()) => TestOutcome::Passed,
+            Err(err) if err.downcast_ref::<Trap>() == Some(&Trap::Interrupt) => {
+                TestOutcome::TimedOut
+            }
+            Err(err) => TestOutcome::Failed(format!("{err:#}")),
+        };
+
+        let output = std::mem::take(&mut *output.lock().unwrap());
+
+        TestResult {
+            name,
+            outcome,
+            output,
+            duration: start.elapsed(),
+        }
+    }
+
+    fn try_run_test(
+        &self,
+        module: &Module,
+        name: &str,
+        output: &Arc<Mutex<Vec<String>>>,
+    ) -> anyhow::Result<()> {
+        let mut linker = self.linker.clone();
+        let output = output.clone();
+        linker.func_wrap(
+            TEST_IMPORT_MODULE,
+            "log",
+            move |mut caller: Caller<'_, T>, msg: u64| -> anyhow::Result<()> {
+                let msg = WasmStr::from_prim(msg).context("invalid string")?;
+                let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+                    anyhow::bail!("guest does not export its memory");
+                };
+
+                let msg = memory.data(&mut caller).load_str(msg)?.to_string();
+                output.lock().unwrap().push(msg);
+                Ok(())
+            },
+        )?;
+
+        let mut store = Store::new(linker.engine(), (self.make_data)());
+        let ticks = self.timeout.as_millis() / EPOCH_TICK.as_millis();
+        store.set_epoch_deadline(ticks.try_into().unwrap_or(u64::MAX).max(1));
+        store.epoch_deadline_trap();
+
+        let instance = linker
+            .instantiate(&mut store, module)
+            .context("failed to instantiate the module")?;
+
+        instance
+            .get_typed_func::<(), ()>(&mut store, &format!("{TEST_EXPORT_PREFIX}{name}"))?
+            .call(&mut store, ())
+    }
+}
+
+// === TestReport === //
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TestOutcome {
+    Passed,
+    Failed(String),
+    TimedOut,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub name: String,
+    pub outcome: TestOutcome,
+
+    /// The messages the test logged.
+    pub output: Vec<String>,
+    pub duration: Duration,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.outcome == TestOutcome::Passed
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TestReport {
+    pub results: Vec<TestResult>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> usize {
+        self.results.iter().filter(|res| res.passed()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.passed()
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.failed() == 0
+    }
+
+    /// Fails with the report if any test failed.
+    pub fn ensure_ok(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.is_ok(), "{self}");
+        Ok(())
+    }
+}
+
+impl fmt::Display for TestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for res in &self.results {
+            let status = match res.outcome {
+                TestOutcome::Passed => "ok",
+                TestOutcome::Failed(_) => "FAILED",
+                TestOutcome::TimedOut => "TIMED OUT",
+            };
+
+            writeln!(f, "test {} ... {status} ({:.2?})", res.name, res.duration)?;
+        }
+
+        for res in self.results.iter().filter(|res| !res.passed()) {
+            writeln!(f, "\n---- {} ----", res.name)?;
+            for line in &res.output {
+                writeln!(f, "{line}")?;
+            }
+            if let TestOutcome::Failed(err) = &res.outcome {
+                writeln!(f, "{err}")?;
+            }
+        }
+
+        write!(
+            f,
+            "\ntest result: {}. {} passed; {} failed",
+            if self.is_ok() { "ok" } else { "FAILED" },
+            self.passed(),
+            self.failed(),
+        )
+    }
+}
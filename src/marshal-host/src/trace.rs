@@ -0,0 +1,147 @@
+//! Tracing of host calls with their decoded arguments, for debugging guests.
+//!
+//! Host functions are traced by binding them through [`Traced`], or with [`bind_to_linker_traced`].
+//! Each call is reported to the [`MarshalHooks`] of the store it was made in, so tracing can be
+//! switched on and off per store at runtime. Calls made while the store has no hooks only cost a
+//! lookup.
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    bind_to_linker, impl_variadic, intercept::CallInfo, HostSideMarshaledFunc, MarshaledTy,
+    MarshaledTyList,
+};
+
+// === MarshalHooks === //
+
+pub trait MarshalHooks: Send + Sync {
+    /// Runs with the decoded arguments of a call, before the function.
+    fn before(&self, call: &CallInfo, args: &[&dyn fmt::Debug]) {
+        let _ = (call, args);
+    }
+
+    /// Runs with the results of the function, its error, or the error its arguments failed to
+    /// decode with. `elapsed` covers decoding the arguments and running the function.
+    fn after(
+        &self,
+        call: &CallInfo,
+        elapsed: Duration,
+        result: Result<&dyn fmt::Debug, &anyhow::Error>,
+    ) {
+        let _ = (call, elapsed, result);
+    }
+}
+
+/// Hooks which print every call to standard error.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PrintHooks;
+
+impl MarshalHooks for PrintHooks {
+    fn before(&self, call: &CallInfo, args: &[&dyn fmt::Debug]) {
+        eprintln!("-> {call}{args:?}");
+    }
+
+    fn after(
+        &self,
+        call: &CallInfo,
+        elapsed: Duration,
+        result: Result<&dyn fmt::Debug, &anyhow::Error>,
+    ) {
+        match result {
+            Ok(res) => eprintln!("<- {call} = {res:?} ({elapsed:?})"),
+            Err(err) => eprintln!("<- {call} failed ({elapsed:?}): {err:#}"),
+        }
+    }
+}
+
+pub trait StoreHasMarshalHooks {
+    /// The store's hooks, or `None` if tracing is disabled for it.
+    fn marshal_hooks(&self) -> Option<Arc<dyn MarshalHooks>>;
+}
+
+// === Traced === //
+
+/// A host function which reports its calls to the store's [`MarshalHooks`].
+pub struct Traced<F> {
+    pub call: CallInfo,
+    pub func: F,
+}
+
+impl<F> Traced<F> {
+    pub fn new(module: &str, name: &str, func: F) -> Self {
+        Self {
+            call: CallInfo {
+                module: module.into(),
+                name: name.into(),
+            },
+            func,
+        }
+    }
+}
+
+macro_rules! impl_func_ty {
+    ($($ty:ident)*) => {
+        impl<D, F, Ret, $($ty: MarshaledTy + fmt::Debug,)*> HostSideMarshaledFunc<D, ($($ty,)*), Ret> for Traced<F>
+        where
+            D: 'static + StoreHasMarshalHooks,
+            Ret: MarshaledTyList + fmt::Debug,
+            F: 'static + Send + Sync + Fn(wasmtime::Caller<'_, D>, $($ty,)*) -> anyhow::Result<Ret>,
+        {
+            type PrimParams<'a> = (wasmtime::Caller<'a, D>, $(<$ty as MarshaledTy>::Prim,)*);
+            type PrimResults = anyhow::Result<Ret::Prims>;
+
+            #[allow(non_snake_case, unused_mut)]
+            fn wrap_host(self) -> impl for<'a> wasmtime::IntoFunc<D, Self::PrimParams<'a>, Self::PrimResults> {
+                let Self { call, func } = self;
+
+                move |caller: wasmtime::Caller<'_, D>, $($ty: <$ty as MarshaledTy>::Prim,)*| {
+                    let prims = ($($ty,)*);
+
+                    let Some(hooks) = caller.data().marshal_hooks() else {
+                        let Some(($($ty,)*)) = <($($ty,)*)>::from_prims(prims) else {
+                            anyhow::bail!("failed to parse arguments to {call}");
+                        };
+
+                        return func(caller, $($ty),*).map(MarshaledTyList::into_prims);
+                    };
+
+                    let start = Instant::now();
+                    let Some(($($ty,)*)) = <($($ty,)*)>::from_prims(prims) else {
+                        let err = anyhow::anyhow!("failed to parse arguments to {call}");
+                        hooks.after(&call, start.elapsed(), Err(&err));
+                        return Err(err);
+                    };
+
+                    hooks.before(&call, &[$(&$ty as &dyn fmt::Debug),*]);
+                    let res = func(caller, $($ty),*);
+
+                    match &res {
+                        Ok(res) => hooks.after(&call, start.elapsed(), Ok(res)),
+                        Err(err) => hooks.after(&call, start.elapsed(), Err(err)),
+                    }
+
+                    res.map(MarshaledTyList::into_prims)
+                }
+            }
+        }
+    };
+}
+
+impl_variadic!(impl_func_ty);
+
+/// Like [`bind_to_linker`] but traces the function's calls through the store's [`MarshalHooks`].
+pub fn bind_to_linker_traced<'l, F, T, Params, Results>(
+    linker: &'l mut wasmtime::Linker<T>,
+    module: &str,
+    name: &str,
+    func: F,
+) -> anyhow::Result<&'l mut wasmtime::Linker<T>>
+where
+    Traced<F>: HostSideMarshaledFunc<T, Params, Results>,
+{
+    bind_to_linker(linker, module, name, Traced::new(module, name, func))
+}
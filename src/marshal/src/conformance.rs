@@ -0,0 +1,138 @@
+//! Checks of the invariants every [`MarshaledTy`] implementation must uphold, usually run through
+//! [`marshal_conformance_tests!`](crate::marshal_conformance_tests).
+//!
+//! - Every valid value survives a trip through its primitive.
+//! - Every primitive which is accepted is the canonical encoding of the value it decodes to, so no
+//!   two primitives decode to the same value.
+//! - Primitives which don't encode a value are rejected instead of being truncated or wrapped.
+
+use core::fmt;
+
+use crate::{MarshaledTy, WasmPrimitive};
+
+/// A primitive with a set of edge-case values which every implementation is checked against.
+pub trait BoundaryPrim: 'static + WasmPrimitive + Copy + fmt::Debug {
+    const BOUNDARIES: &'static [Self];
+
+    /// The bits of the primitive, so that floats can be compared exactly.
+    fn bits(self) -> u64;
+}
+
+macro_rules! impl_boundary_prim {
+    ($($ty:ty => [$($boundary:expr),*$(,)?] as $bits:ty),*$(,)?) => {$(
+        impl BoundaryPrim for $ty {
+            const BOUNDARIES: &'static [Self] = &[$($boundary),*];
+
+            fn bits(self) -> u64 {
+                <$bits>::from_ne_bytes(self.to_ne_bytes()) as u64
+            }
+        }
+    )*};
+}
+
+impl_boundary_prim!(
+    u32 => [0, 1, 0x7F, 0x80, 0xFF, 0x100, 0xFFFF, 0x1_0000, 0xD7FF, 0xD800, 0xDFFF, 0x10_FFFF,
+        0x11_0000, u32::MAX >> 1, u32::MAX] as u32,
+    i32 => [0, 1, -1, i8::MIN as i32 - 1, i8::MIN as i32, i8::MAX as i32, i8::MAX as i32 + 1,
+        i16::MIN as i32 - 1, i16::MIN as i32, i16::MAX as i32, i16::MAX as i32 + 1, i32::MIN,
+        i32::MAX] as u32,
+    u64 => [0, 1, u32::MAX as u64, u32::MAX as u64 + 1, u64::MAX >> 1, u64::MAX] as u64,
+    i64 => [0, 1, -1, i32::MIN as i64, i32::MAX as i64, i64::MIN, i64::MAX] as u64,
+    f32 => [0.0, -0.0, 1.0, f32::MIN, f32::MAX, f32::INFINITY, f32::NEG_INFINITY, f32::NAN] as u32,
+    f64 => [0.0, -0.0, 1.0, f64::MIN, f64::MAX, f64::INFINITY, f64::NEG_INFINITY, f64::NAN] as u64,
+);
+
+/// Asserts that `value` decodes back from its primitive into a value with the same primitive.
+pub fn assert_roundtrip<T: MarshaledTy>(value: T)
+where
+    T::Prim: BoundaryPrim,
+{
+    let prim = T::into_prim(value);
+    let Some(decoded) = T::from_prim(prim) else {
+        panic!(
+            "{} rejected the primitive {prim:?} it encoded",
+            core::any::type_name::<T>()
+        );
+    };
+
+    let reencoded = T::into_prim(decoded);
+    assert_eq!(
+        prim.bits(),
+        reencoded.bits(),
+        "{} encoded a value as {prim:?} but its decoding as {reencoded:?}",
+        core::any::type_name::<T>(),
+    );
+}
+
+/// Asserts that every boundary primitive which `T` accepts is re-encoded as itself.
+pub fn assert_canonical_boundaries<T: MarshaledTy>()
+where
+    T::Prim: BoundaryPrim,
+{
+    for &prim in <T::Prim as BoundaryPrim>::BOUNDARIES {
+        let Some(decoded) = T::from_prim(prim) else {
+            continue;
+        };
+
+        let reencoded = T::into_prim(decoded);
+        assert_eq!(
+            prim.bits(),
+            reencoded.bits(),
+            "{} accepted the primitive {prim:?} but re-encoded it as {reencoded:?}",
+            core::any::type_name::<T>(),
+        );
+    }
+}
+
+/// Asserts that `T` rejects `prim`.
+pub fn assert_rejects<T: MarshaledTy>(prim: T::Prim)
+where
+    T::Prim: BoundaryPrim,
+{
+    assert!(
+        T::from_prim(prim).is_none(),
+        "{} accepted the invalid primitive {prim:?}",
+        core::any::type_name::<T>(),
+    );
+}
+
+/// Generates tests checking a [`MarshaledTy`] implementation against the invariants described in
+/// the [`conformance`](crate::conformance) module.
+///
+/// ```ignore
+/// marshal_conformance_tests!(mod bool_conformance: bool, valid: [false, true], invalid_prims: [2]);
+/// ```
+///
+/// Without a `mod` name, the tests are generated directly in the invoking module.
+#[macro_export]
+macro_rules! marshal_conformance_tests {
+    (mod $name:ident: $($rest:tt)*) => {
+        mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            $crate::marshal_conformance_tests!($($rest)*);
+        }
+    };
+    (
+        $ty:ty,
+        valid: [$($valid:expr),*$(,)?],
+        invalid_prims: [$($invalid:expr),*$(,)?]
+        $(,)?
+    ) => {
+        #[test]
+        fn roundtrip() {
+            $($crate::conformance::assert_roundtrip::<$ty>($valid);)*
+        }
+
+        #[test]
+        fn boundaries() {
+            $crate::conformance::assert_canonical_boundaries::<$ty>();
+        }
+
+        #[test]
+        fn rejects_invalid() {
+            $($crate::conformance::assert_rejects::<$ty>($invalid);)*
+        }
+    };
+}
@@ -1,14 +1,26 @@
 #![no_std]
 #![allow(clippy::missing_safety_doc)]
 
+// Lets `crt-marshal-derive` refer to this crate as `::crt_marshal` from within it too.
+extern crate self as crt_marshal;
+
 #[cfg(feature = "alloc")]
 extern crate alloc;
 
+#[cfg(feature = "stubs")]
+extern crate std;
+
+#[cfg(feature = "stubs")]
+pub mod stubs;
+
+pub mod conformance;
+
 use core::{
     any::type_name,
-    fmt,
+    fmt, hash,
     marker::PhantomData,
     ptr::{self, NonNull},
+    sync::atomic::{AtomicU32, Ordering},
 };
 
 use bytemuck::{Pod, Zeroable};
@@ -17,7 +29,27 @@ use bytemuck::{Pod, Zeroable};
 
 #[doc(hidden)]
 pub mod macro_rexp {
+    pub use bytemuck::{Pod, Zeroable};
     pub use core::option::Option;
+
+    #[cfg(feature = "wasmtime")]
+    pub use wasmtime;
+
+    /// Packs the bytes of a value of at most 8 bytes into a `u64`, as they'd be loaded from memory.
+    pub fn pack_prim<T: Pod>(value: T) -> u64 {
+        let mut bytes = [0; 8];
+        bytes[..size_of::<T>()].copy_from_slice(bytemuck::bytes_of(&value));
+        u64::from_le_bytes(bytes)
+    }
+
+    /// Unpacks a value packed by [`pack_prim`], rejecting primitives whose unused bytes are set.
+    pub fn unpack_prim<T: Pod>(prim: u64) -> Option<T> {
+        let bytes = prim.to_le_bytes();
+        let (value, rest) = bytes.split_at(size_of::<T>());
+        rest.iter()
+            .all(|&byte| byte == 0)
+            .then(|| bytemuck::pod_read_unaligned(value))
+    }
 }
 
 // === Helpers === //
@@ -69,19 +101,38 @@ where
 
 #[cfg(feature = "wasmtime")]
 mod sealed {
-    pub trait WasmPrimitive: wasmtime::WasmTy {}
+    pub trait WasmPrimitive: wasmtime::WasmTy {
+        const VAL_TYPE: wasmtime::ValType;
+
+        fn to_bits(&self) -> u64;
+
+        fn from_bits(bits: u64) -> Self;
+    }
 
     pub trait WasmPrimitiveList:
         wasmtime::WasmRet + wasmtime::WasmResults + wasmtime::WasmParams
     {
+        const LEN: usize;
+
+        fn for_each_val_type(f: &mut dyn FnMut(wasmtime::ValType));
+
+        fn for_each_bits(&self, f: &mut dyn FnMut(u64));
+
+        fn from_each_bits(f: &mut dyn FnMut() -> u64) -> Self;
     }
 }
 
 #[cfg(not(feature = "wasmtime"))]
 mod sealed {
-    pub trait WasmPrimitive {}
+    pub trait WasmPrimitive {
+        fn from_bits(bits: u64) -> Self;
+    }
 
-    pub trait WasmPrimitiveList {}
+    pub trait WasmPrimitiveList {
+        const LEN: usize;
+
+        fn from_each_bits(f: &mut dyn FnMut() -> u64) -> Self;
+    }
 }
 
 pub trait WasmPrimitive: sealed::WasmPrimitive {}
@@ -89,34 +140,181 @@ pub trait WasmPrimitive: sealed::WasmPrimitive {}
 pub trait WasmPrimitiveList: sealed::WasmPrimitiveList {}
 
 macro_rules! impl_wasm_primitive {
-    ($($ty:ty),*$(,)?) => {
-        $(impl sealed::WasmPrimitive for $ty {})*
+    ($($ty:ty => $val_ty:ident $bits:ty),*$(,)?) => {
+        $(
+            #[cfg(feature = "wasmtime")]
+            impl sealed::WasmPrimitive for $ty {
+                const VAL_TYPE: wasmtime::ValType = wasmtime::ValType::$val_ty;
+
+                fn to_bits(&self) -> u64 {
+                    <$bits>::from_ne_bytes(self.to_ne_bytes()) as u64
+                }
+
+                fn from_bits(bits: u64) -> Self {
+                    Self::from_ne_bytes((bits as $bits).to_ne_bytes())
+                }
+            }
+
+            #[cfg(not(feature = "wasmtime"))]
+            impl sealed::WasmPrimitive for $ty {
+                fn from_bits(bits: u64) -> Self {
+                    Self::from_ne_bytes((bits as $bits).to_ne_bytes())
+                }
+            }
+        )*
         $(impl WasmPrimitive for $ty {})*
     };
 }
 
 macro_rules! impl_wasm_primitive_list {
     ($($param:ident)*) => {
-        impl<$($param: WasmPrimitive),*> sealed::WasmPrimitiveList for ($($param,)*) {}
+        #[cfg(feature = "wasmtime")]
+        impl<$($param: WasmPrimitive),*> sealed::WasmPrimitiveList for ($($param,)*) {
+            const LEN: usize = <[&str]>::len(&[$(stringify!($param)),*]);
+
+            #[allow(unused)]
+            fn for_each_val_type(f: &mut dyn FnMut(wasmtime::ValType)) {
+                $(f(<$param as sealed::WasmPrimitive>::VAL_TYPE);)*
+            }
+
+            #[allow(unused, non_snake_case)]
+            fn for_each_bits(&self, f: &mut dyn FnMut(u64)) {
+                let ($($param,)*) = self;
+                $(f(sealed::WasmPrimitive::to_bits($param));)*
+            }
+
+            #[allow(unused, clippy::unused_unit)]
+            fn from_each_bits(f: &mut dyn FnMut() -> u64) -> Self {
+                ($(<$param as sealed::WasmPrimitive>::from_bits(f()),)*)
+            }
+        }
+
+        #[cfg(not(feature = "wasmtime"))]
+        impl<$($param: WasmPrimitive),*> sealed::WasmPrimitiveList for ($($param,)*) {
+            const LEN: usize = <[&str]>::len(&[$(stringify!($param)),*]);
+
+            #[allow(unused, clippy::unused_unit)]
+            fn from_each_bits(f: &mut dyn FnMut() -> u64) -> Self {
+                ($(<$param as sealed::WasmPrimitive>::from_bits(f()),)*)
+            }
+        }
+
         impl<$($param: WasmPrimitive),*> WasmPrimitiveList for ($($param,)*) {}
     };
 }
 
-impl_wasm_primitive!(u32, i32, u64, i64, f32, f64);
+impl_wasm_primitive!(
+    u32 => I32 u32,
+    i32 => I32 u32,
+    u64 => I64 u64,
+    i64 => I64 u64,
+    f32 => F32 u32,
+    f64 => F64 u64,
+);
 impl_variadic!(impl_wasm_primitive_list);
 
-impl<T: WasmPrimitive> sealed::WasmPrimitiveList for T {}
+#[cfg(feature = "wasmtime")]
+impl<T: WasmPrimitive> sealed::WasmPrimitiveList for T {
+    const LEN: usize = 1;
+
+    fn for_each_val_type(f: &mut dyn FnMut(wasmtime::ValType)) {
+        f(<T as sealed::WasmPrimitive>::VAL_TYPE);
+    }
+
+    fn for_each_bits(&self, f: &mut dyn FnMut(u64)) {
+        f(sealed::WasmPrimitive::to_bits(self));
+    }
+
+    fn from_each_bits(f: &mut dyn FnMut() -> u64) -> Self {
+        sealed::WasmPrimitive::from_bits(f())
+    }
+}
+
+#[cfg(not(feature = "wasmtime"))]
+impl<T: WasmPrimitive> sealed::WasmPrimitiveList for T {
+    const LEN: usize = 1;
+
+    fn from_each_bits(f: &mut dyn FnMut() -> u64) -> Self {
+        sealed::WasmPrimitive::from_bits(f())
+    }
+}
 
 impl<T: WasmPrimitive> WasmPrimitiveList for T {}
 
+/// Calls `f` with the type of each primitive in the list, in order.
+#[cfg(feature = "wasmtime")]
+pub fn for_each_val_type<L: WasmPrimitiveList>(mut f: impl FnMut(wasmtime::ValType)) {
+    <L as sealed::WasmPrimitiveList>::for_each_val_type(&mut f);
+}
+
+/// Calls `f` with the bits of each primitive in the list, in order. Floats are passed by their bit
+/// patterns and 32-bit values are zero-extended.
+#[cfg(feature = "wasmtime")]
+pub fn for_each_prim_bits<L: WasmPrimitiveList>(list: &L, mut f: impl FnMut(u64)) {
+    <L as sealed::WasmPrimitiveList>::for_each_bits(list, &mut f);
+}
+
+/// The number of primitives in the list.
+pub const fn prim_list_len<L: WasmPrimitiveList>() -> usize {
+    <L as sealed::WasmPrimitiveList>::LEN
+}
+
+/// Reads a list of primitives from a return area, in which each primitive occupies a slot of
+/// eight bytes. Floats are stored by their bit patterns and 32-bit values are zero-extended.
+pub fn prims_from_slots<L: WasmPrimitiveList>(slots: &[u64]) -> L {
+    assert_eq!(
+        slots.len(),
+        prim_list_len::<L>(),
+        "return area has the wrong length"
+    );
+
+    let mut slots = slots.iter();
+    <L as sealed::WasmPrimitiveList>::from_each_bits(&mut || *slots.next().unwrap())
+}
+
 // === MarshaledTy === //
 
+/// What a marshaled type represents, for tools which inspect signatures at runtime.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TyDescriptor {
+    Bool,
+    Char,
+    Int { signed: bool, bits: u8 },
+    Float { bits: u8 },
+
+    /// A pointer, handle, or other value which can't be described further.
+    Opaque,
+}
+
+impl TyDescriptor {
+    pub fn is_scalar(self) -> bool {
+        self != Self::Opaque
+    }
+}
+
+impl fmt::Display for TyDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool => f.write_str("bool"),
+            Self::Char => f.write_str("char"),
+            Self::Int { signed: true, bits } => write!(f, "i{bits}"),
+            Self::Int { signed: false, bits } => write!(f, "u{bits}"),
+            Self::Float { bits } => write!(f, "f{bits}"),
+            Self::Opaque => f.write_str("opaque"),
+        }
+    }
+}
+
 pub trait MarshaledTy: Sized + 'static {
     type Prim: WasmPrimitive;
 
     fn into_prim(me: Self) -> Self::Prim;
 
     fn from_prim(me: Self::Prim) -> Option<Self>;
+
+    fn descriptor() -> TyDescriptor {
+        TyDescriptor::Opaque
+    }
 }
 
 #[macro_export]
@@ -132,6 +330,10 @@ macro_rules! forward_marshaled_ty {
             let $ctor_me = <$ty as $crate::MarshaledTy>::from_prim(me)?;
             $ctor
         }
+
+        fn descriptor() -> $crate::TyDescriptor {
+            <$ty as $crate::MarshaledTy>::descriptor()
+        }
     };
     ($ty:ty) => {
         type Prim = <$ty as $crate::MarshaledTy>::Prim;
@@ -143,11 +345,15 @@ macro_rules! forward_marshaled_ty {
         fn from_prim(me: Self::Prim) -> $crate::macro_rexp::Option<Self> {
             <$ty as $crate::MarshaledTy>::from_prim(me).map(Self)
         }
+
+        fn descriptor() -> $crate::TyDescriptor {
+            <$ty as $crate::MarshaledTy>::descriptor()
+        }
     };
 }
 
 macro_rules! impl_func_ty {
-    ($($ty:ty => $prim:ty),*$(,)?) => {$(
+    ($($ty:ty => $prim:ty as $descriptor:expr),*$(,)?) => {$(
         impl MarshaledTy for $ty {
             type Prim = $prim;
 
@@ -158,20 +364,26 @@ macro_rules! impl_func_ty {
             fn from_prim(me: Self::Prim) -> Option<Self> {
                 Self::try_from(me).ok()
             }
+
+            fn descriptor() -> TyDescriptor {
+                $descriptor
+            }
         }
     )*};
 }
 
 impl_func_ty!(
-    u8 => u32,
-    u16 => u32,
-    u32 => u32,
-    i8 => i32,
-    i16 => i32,
-    i32 => i32,
-    u64 => u64,
-    i64 => i64,
-    char => u32,
+    u8 => u32 as TyDescriptor::Int { signed: false, bits: 8 },
+    u16 => u32 as TyDescriptor::Int { signed: false, bits: 16 },
+    u32 => u32 as TyDescriptor::Int { signed: false, bits: 32 },
+    i8 => i32 as TyDescriptor::Int { signed: true, bits: 8 },
+    i16 => i32 as TyDescriptor::Int { signed: true, bits: 16 },
+    i32 => i32 as TyDescriptor::Int { signed: true, bits: 32 },
+    u64 => u64 as TyDescriptor::Int { signed: false, bits: 64 },
+    i64 => i64 as TyDescriptor::Int { signed: true, bits: 64 },
+    char => u32 as TyDescriptor::Char,
+    f32 => f32 as TyDescriptor::Float { bits: 32 },
+    f64 => f64 as TyDescriptor::Float { bits: 64 },
 );
 
 impl MarshaledTy for bool {
@@ -188,8 +400,14 @@ impl MarshaledTy for bool {
             _ => None,
         }
     }
+
+    fn descriptor() -> TyDescriptor {
+        TyDescriptor::Bool
+    }
 }
 
+pub use crt_marshal_derive::Marshaled;
+
 // === MarshaledTyList === //
 
 // Core
@@ -210,6 +428,9 @@ pub trait MarshaledTyList: Sized + 'static {
     fn into_prims(me: Self) -> Self::Prims;
 
     fn from_prims(me: Self::Prims) -> Option<Self>;
+
+    /// Calls `f` with the descriptor of each type in the list, in order.
+    fn for_each_descriptor(f: &mut dyn FnMut(TyDescriptor));
 }
 
 // Derivations
@@ -243,6 +464,10 @@ impl<T: MarshaledTy> MarshaledTyList for T {
     fn from_prims(me: Self::Prims) -> Option<Self> {
         T::from_prim(me)
     }
+
+    fn for_each_descriptor(f: &mut dyn FnMut(TyDescriptor)) {
+        f(T::descriptor());
+    }
 }
 
 macro_rules! impl_marshaled_res_ty {
@@ -281,6 +506,11 @@ macro_rules! impl_marshaled_res_ty {
             fn from_prims(($($para,)*): Self::Prims) -> Option<Self> {
                 Some(( $(MarshaledTy::from_prim($para)?,)* ))
             }
+
+            #[allow(unused)]
+            fn for_each_descriptor(f: &mut dyn FnMut(TyDescriptor)) {
+                $(f(<$para as MarshaledTy>::descriptor());)*
+            }
         }
     };
 }
@@ -439,6 +669,85 @@ define_le! {
     LeU64 u64,
 }
 
+macro_rules! define_le_float {
+    ($($name:ident $ty:ty => $bits:ty),*$(,)?) => {$(
+        /// Stores the float by its bit pattern so that NaN payloads are preserved.
+        #[derive(Copy, Clone, Pod, Zeroable)]
+        #[repr(transparent)]
+        pub struct $name($bits);
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.get().fmt(f)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.get().fmt(f)
+            }
+        }
+
+        impl fmt::LowerExp for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.get().fmt(f)
+            }
+        }
+
+        impl fmt::UpperExp for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.get().fmt(f)
+            }
+        }
+
+        impl $name {
+            pub const fn new(value: $ty) -> Self {
+                Self(value.to_bits().to_le())
+            }
+
+            pub const fn get(self) -> $ty {
+                <$ty>::from_bits(<$bits>::from_le(self.0))
+            }
+
+            pub fn set(&mut self, v: $ty) {
+                *self = Self::new(v)
+            }
+
+            pub fn update<R>(&mut self, f: impl FnOnce(&mut $ty) -> R) -> R {
+                let mut ne = self.get();
+                let res = f(&mut ne);
+                self.set(ne);
+                res
+            }
+
+            pub fn map(self, f: impl FnOnce($ty) -> $ty) -> Self {
+                f(self.into()).into()
+            }
+        }
+
+        impl From<$ty> for $name {
+            fn from(value: $ty) -> Self {
+                Self::new(value)
+            }
+        }
+
+        impl From<$name> for $ty {
+            fn from(value: $name) -> Self {
+                value.get()
+            }
+        }
+
+        impl MarshaledTy for $name {
+            forward_marshaled_ty!($ty, get |me| me.get(), new |prim| Some(Self::new(prim)));
+        }
+    )*};
+}
+
+define_le_float! {
+    LeF32 f32 => u32,
+    LeF64 f64 => u64,
+}
+
 // === Pointers === //
 
 // WasmPtr
@@ -486,9 +795,17 @@ impl<T> WasmPtr<T> {
         }
     }
 
+    pub const fn null() -> Self {
+        Self::new(LeU32::new(0))
+    }
+
     pub fn addr(self) -> LeU32 {
         unsafe { self.addr.addr }
     }
+
+    pub fn is_null(self) -> bool {
+        self.addr().get() == 0
+    }
 }
 
 impl<T> MarshaledTy for WasmPtr<T> {
@@ -503,6 +820,19 @@ impl<T> MarshaledTy for WasmPtr<T> {
     }
 }
 
+/// Passes `None` as the null pointer.
+impl<T> MarshaledTy for Option<WasmPtr<T>> {
+    type Prim = u32;
+
+    fn into_prim(me: Self) -> Self::Prim {
+        me.map_or(0, |ptr| ptr.addr().get())
+    }
+
+    fn from_prim(me: Self::Prim) -> Option<Self> {
+        Some((me != 0).then(|| WasmPtr::new(LeU32::new(me))))
+    }
+}
+
 // WasmSlice
 pub struct WasmSlice<T: 'static> {
     pub base: WasmPtr<T>,
@@ -550,6 +880,127 @@ impl<T> MarshaledTy for WasmSlice<T> {
     }
 }
 
+// WasmPtr64
+/// A pointer into a 64-bit memory, as used by memory64 guests.
+#[repr(transparent)]
+pub struct WasmPtr64<T: 'static> {
+    _ty: PhantomData<fn() -> T>,
+    addr: LeU64,
+}
+
+impl<T> fmt::Debug for WasmPtr64<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.addr.get())
+    }
+}
+
+impl<T> fmt::Pointer for WasmPtr64<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.addr.get())
+    }
+}
+
+impl<T> Copy for WasmPtr64<T> {}
+
+impl<T> Clone for WasmPtr64<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+unsafe impl<T> Pod for WasmPtr64<T> {}
+unsafe impl<T> Zeroable for WasmPtr64<T> {}
+
+impl<T> WasmPtr64<T> {
+    pub const fn new(addr: LeU64) -> Self {
+        Self {
+            _ty: PhantomData,
+            addr,
+        }
+    }
+
+    pub const fn null() -> Self {
+        Self::new(LeU64::new(0))
+    }
+
+    pub fn addr(self) -> LeU64 {
+        self.addr
+    }
+
+    pub fn is_null(self) -> bool {
+        self.addr.get() == 0
+    }
+}
+
+impl<T> From<WasmPtr<T>> for WasmPtr64<T> {
+    fn from(ptr: WasmPtr<T>) -> Self {
+        Self::new(LeU64::new(ptr.addr().get().into()))
+    }
+}
+
+impl<T> MarshaledTy for WasmPtr64<T> {
+    type Prim = u64;
+
+    fn into_prim(me: Self) -> Self::Prim {
+        me.addr.get()
+    }
+
+    fn from_prim(me: Self::Prim) -> Option<Self> {
+        Some(Self::new(LeU64::new(me)))
+    }
+}
+
+/// Passes `None` as the null pointer.
+impl<T> MarshaledTy for Option<WasmPtr64<T>> {
+    type Prim = u64;
+
+    fn into_prim(me: Self) -> Self::Prim {
+        me.map_or(0, |ptr| ptr.addr.get())
+    }
+
+    fn from_prim(me: Self::Prim) -> Option<Self> {
+        Some((me != 0).then(|| WasmPtr64::new(LeU64::new(me))))
+    }
+}
+
+// WasmSlice64
+/// A slice of a 64-bit memory. Unlike [`WasmSlice`], it is too big to be passed as a single
+/// primitive so it must be passed through memory or as its base and length separately.
+#[repr(C)]
+pub struct WasmSlice64<T: 'static> {
+    pub base: WasmPtr64<T>,
+    pub len: LeU64,
+}
+
+impl<T> fmt::Debug for WasmSlice64<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WasmSlice64")
+            .field("base", &self.base)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl<T> Copy for WasmSlice64<T> {}
+
+impl<T> Clone for WasmSlice64<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+unsafe impl<T: 'static> Pod for WasmSlice64<T> {}
+unsafe impl<T: 'static> Zeroable for WasmSlice64<T> {}
+
+impl<T> From<WasmSlice<T>> for WasmSlice64<T> {
+    fn from(slice: WasmSlice<T>) -> Self {
+        Self {
+            base: slice.base.into(),
+            len: LeU64::new(slice.len.get().into()),
+        }
+    }
+}
+
 // WasmStr
 #[derive(Debug, Copy, Clone, Pod, Zeroable)]
 #[repr(C)]
@@ -639,6 +1090,11 @@ where
     pub fn addr(self) -> LeU32 {
         self.addr.addr()
     }
+
+    /// Whether this is the null function, which no table entry refers to.
+    pub fn is_null(self) -> bool {
+        self.addr.is_null()
+    }
 }
 
 impl<A, R> MarshaledTy for WasmFunc<A, R>
@@ -660,11 +1116,28 @@ where
     }
 }
 
-// WasmWidePtrRaw
-pub struct WasmWidePtrRaw<M: 'static, T: 'static = ()> {
-    pub base: WasmPtr<T>,
-    pub meta: WasmPtr<M>,
-}
+/// Passes `None` as the null function.
+impl<A, R> MarshaledTy for Option<WasmFunc<A, R>>
+where
+    A: MarshaledTyList,
+    R: MarshaledTyList,
+{
+    type Prim = u32;
+
+    fn into_prim(me: Self) -> Self::Prim {
+        me.map_or(0, |func| func.addr().get())
+    }
+
+    fn from_prim(me: Self::Prim) -> Option<Self> {
+        Some((me != 0).then(|| WasmFunc::new(WasmPtr::new(LeU32::new(me)))))
+    }
+}
+
+// WasmWidePtrRaw
+pub struct WasmWidePtrRaw<M: 'static, T: 'static = ()> {
+    pub base: WasmPtr<T>,
+    pub meta: WasmPtr<M>,
+}
 
 impl<M, T> fmt::Debug for WasmWidePtrRaw<M, T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -859,6 +1332,731 @@ where
         }));
 }
 
+// === WasmResult and WasmOption === //
+
+/// A primitive which can be stored in the 32-bit payload slot of a [`WasmResult`] or
+/// [`WasmOption`].
+pub trait WasmPrimitive32: WasmPrimitive {
+    fn into_bits32(me: Self) -> u32;
+
+    fn from_bits32(me: u32) -> Self;
+}
+
+impl WasmPrimitive32 for u32 {
+    fn into_bits32(me: Self) -> u32 {
+        me
+    }
+
+    fn from_bits32(me: u32) -> Self {
+        me
+    }
+}
+
+impl WasmPrimitive32 for i32 {
+    fn into_bits32(me: Self) -> u32 {
+        me as u32
+    }
+
+    fn from_bits32(me: u32) -> Self {
+        me as i32
+    }
+}
+
+/// A value which can be the payload of a [`WasmResult`] or [`WasmOption`]. This is every marshaled
+/// type with a 32-bit primitive as well as `()`.
+pub trait WasmPayload: Sized + 'static {
+    fn into_payload(me: Self) -> u32;
+
+    fn from_payload(me: u32) -> Option<Self>;
+}
+
+impl WasmPayload for () {
+    fn into_payload((): Self) -> u32 {
+        0
+    }
+
+    fn from_payload(me: u32) -> Option<Self> {
+        (me == 0).then_some(())
+    }
+}
+
+impl<T> WasmPayload for T
+where
+    T: MarshaledTy,
+    T::Prim: WasmPrimitive32,
+{
+    fn into_payload(me: Self) -> u32 {
+        WasmPrimitive32::into_bits32(T::into_prim(me))
+    }
+
+    fn from_payload(me: u32) -> Option<Self> {
+        T::from_prim(WasmPrimitive32::from_bits32(me))
+    }
+}
+
+/// A [`Result`] passed across the guest boundary as a tag in the upper half of a `u64` and its
+/// payload in the lower half. Larger payloads should be passed behind a [`WasmPtr`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct WasmResult<T, E = ()>(pub Result<T, E>);
+
+impl<T, E> WasmResult<T, E> {
+    const TAG_OK: u64 = 0;
+    const TAG_ERR: u64 = 1;
+
+    pub const fn ok(value: T) -> Self {
+        Self(Ok(value))
+    }
+
+    pub const fn err(error: E) -> Self {
+        Self(Err(error))
+    }
+
+    pub fn into_result(self) -> Result<T, E> {
+        self.0
+    }
+}
+
+impl<T, E> From<Result<T, E>> for WasmResult<T, E> {
+    fn from(value: Result<T, E>) -> Self {
+        Self(value)
+    }
+}
+
+impl<T, E> From<WasmResult<T, E>> for Result<T, E> {
+    fn from(value: WasmResult<T, E>) -> Self {
+        value.0
+    }
+}
+
+impl<T, E> MarshaledTy for WasmResult<T, E>
+where
+    T: WasmPayload,
+    E: WasmPayload,
+{
+    type Prim = u64;
+
+    fn into_prim(me: Self) -> Self::Prim {
+        match me.0 {
+            Ok(value) => (Self::TAG_OK << 32) | T::into_payload(value) as u64,
+            Err(error) => (Self::TAG_ERR << 32) | E::into_payload(error) as u64,
+        }
+    }
+
+    fn from_prim(me: Self::Prim) -> Option<Self> {
+        let payload = me as u32;
+
+        match me >> 32 {
+            Self::TAG_OK => T::from_payload(payload).map(Self::ok),
+            Self::TAG_ERR => E::from_payload(payload).map(Self::err),
+            _ => None,
+        }
+    }
+}
+
+/// An [`Option`] passed across the guest boundary like a [`WasmResult`]. Prefer `Option<WasmPtr<T>>`
+/// and `Option<WasmFunc<A, R>>` for pointers, which pass `None` as null.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct WasmOption<T>(pub Option<T>);
+
+impl<T> WasmOption<T> {
+    const TAG_NONE: u64 = 0;
+    const TAG_SOME: u64 = 1;
+
+    pub const fn some(value: T) -> Self {
+        Self(Some(value))
+    }
+
+    pub const fn none() -> Self {
+        Self(None)
+    }
+
+    pub fn into_option(self) -> Option<T> {
+        self.0
+    }
+}
+
+impl<T> From<Option<T>> for WasmOption<T> {
+    fn from(value: Option<T>) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> From<WasmOption<T>> for Option<T> {
+    fn from(value: WasmOption<T>) -> Self {
+        value.0
+    }
+}
+
+impl<T: WasmPayload> MarshaledTy for WasmOption<T> {
+    type Prim = u64;
+
+    fn into_prim(me: Self) -> Self::Prim {
+        match me.0 {
+            Some(value) => (Self::TAG_SOME << 32) | T::into_payload(value) as u64,
+            None => Self::TAG_NONE << 32,
+        }
+    }
+
+    fn from_prim(me: Self::Prim) -> Option<Self> {
+        match me >> 32 {
+            Self::TAG_SOME => T::from_payload(me as u32).map(Self::some),
+            Self::TAG_NONE if me == 0 => Some(Self::none()),
+            _ => None,
+        }
+    }
+}
+
+// === Atomic Fields === //
+
+pub use crt_marshal_derive::AtomicFields;
+
+/// A value which can be accessed atomically as a single little-endian integer, for fields of
+/// structs shared between threads.
+pub unsafe trait AtomicValue: Pod {
+    /// The width of the value in bytes, which must also be its alignment.
+    const WIDTH: u32;
+
+    fn from_bits(bits: u64) -> Self;
+
+    fn to_bits(self) -> u64;
+}
+
+macro_rules! impl_atomic_value {
+    ($($ty:ty => $width:literal),*$(,)?) => {$(
+        unsafe impl AtomicValue for $ty {
+            const WIDTH: u32 = $width;
+
+            fn from_bits(bits: u64) -> Self {
+                Self::new(bits as _)
+            }
+
+            fn to_bits(self) -> u64 {
+                self.get() as u64
+            }
+        }
+    )*};
+}
+
+impl_atomic_value!(
+    LeU32 => 4,
+    LeI32 => 4,
+    LeU64 => 8,
+    LeI64 => 8,
+);
+
+/// The location of a field of `S` which must be accessed atomically. These are usually derived
+/// with [`AtomicFields`].
+pub struct AtomicField<S, F> {
+    _ty: PhantomData<fn() -> (S, F)>,
+    offset: u32,
+}
+
+impl<S, F> fmt::Debug for AtomicField<S, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "AtomicField<{}, {}>({})",
+            type_name::<S>(),
+            type_name::<F>(),
+            self.offset,
+        )
+    }
+}
+
+impl<S, F> Copy for AtomicField<S, F> {}
+
+impl<S, F> Clone for AtomicField<S, F> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S: Pod, F: AtomicValue> AtomicField<S, F> {
+    pub const fn new(offset: u32) -> Self {
+        assert!(
+            offset as usize + F::WIDTH as usize <= size_of::<S>(),
+            "atomic field lies outside of its struct"
+        );
+        assert!(offset.is_multiple_of(F::WIDTH), "atomic field is misaligned");
+
+        Self {
+            _ty: PhantomData,
+            offset,
+        }
+    }
+}
+
+impl<S, F> AtomicField<S, F> {
+    /// The offset of the field from the start of its struct.
+    pub const fn offset(self) -> u32 {
+        self.offset
+    }
+}
+
+// === Allocator Statistics === //
+
+/// The statistics a guest reports about its allocator.
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub struct WasmAllocStats {
+    /// The number of bytes currently allocated.
+    pub allocated: LeU64,
+
+    /// The number of live allocations.
+    pub live_blocks: LeU64,
+
+    /// The largest number of bytes ever allocated at once.
+    pub high_water_mark: LeU64,
+}
+
+// === Capabilities === //
+
+/// The name of the export through which guests expose the address of their [`WasmCapabilities`].
+pub const CAPABILITIES_EXPORT: &str = "crt_capabilities";
+
+/// A set of optional protocols a guest supports.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash, Pod, Zeroable)]
+#[repr(transparent)]
+pub struct CapabilityFlags(u32);
+
+impl CapabilityFlags {
+    pub const NONE: Self = Self(0);
+
+    /// The guest exports a function for resizing allocations in place.
+    pub const REALLOC: Self = Self(1 << 0);
+
+    /// The guest reserves a scratch stack the host may use for short-lived arguments.
+    pub const SCRATCH_STACK: Self = Self(1 << 1);
+
+    /// The guest understands version 2 of the event queue protocol.
+    pub const EVENT_QUEUE_V2: Self = Self(1 << 2);
+
+    pub const ALL: Self = Self(Self::REALLOC.0 | Self::SCRATCH_STACK.0 | Self::EVENT_QUEUE_V2.0);
+
+    /// Creates a set from its bits, keeping bits which no flag is defined for.
+    pub const fn from_bits_retain(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl core::ops::BitOr for CapabilityFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        self.union(rhs)
+    }
+}
+
+impl fmt::Debug for CapabilityFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (Self::REALLOC, "REALLOC"),
+            (Self::SCRATCH_STACK, "SCRATCH_STACK"),
+            (Self::EVENT_QUEUE_V2, "EVENT_QUEUE_V2"),
+        ];
+
+        let mut set = f.debug_set();
+        for (flag, name) in names {
+            if self.contains(flag) {
+                set.entry(&format_args!("{name}"));
+            }
+        }
+
+        let unknown = self.0 & !Self::ALL.0;
+        if unknown != 0 {
+            set.entry(&format_args!("{unknown:#x}"));
+        }
+
+        set.finish()
+    }
+}
+
+/// The capabilities a guest reports to its host, exported with [`export_capabilities!`].
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+#[repr(C)]
+pub struct WasmCapabilities {
+    /// The layout version of this structure. Fields are only ever appended so hosts can read the
+    /// prefix of newer layouts.
+    pub version: LeU32,
+    pub flags: CapabilityFlags,
+
+    /// The version of the scratch stack protocol the guest speaks.
+    pub scratch_stack_version: LeU32,
+
+    /// The version of the event queue protocol the guest speaks.
+    pub event_queue_version: LeU32,
+}
+
+impl WasmCapabilities {
+    /// The layout version this crate writes.
+    pub const VERSION: u32 = 1;
+
+    pub const fn new(flags: CapabilityFlags) -> Self {
+        Self {
+            version: LeU32::new(Self::VERSION),
+            flags,
+            scratch_stack_version: LeU32::new(
+                flags.contains(CapabilityFlags::SCRATCH_STACK) as u32,
+            ),
+            event_queue_version: LeU32::new(
+                if flags.contains(CapabilityFlags::EVENT_QUEUE_V2) { 2 } else { 1 },
+            ),
+        }
+    }
+
+    pub const fn with_scratch_stack_version(mut self, version: u32) -> Self {
+        self.scratch_stack_version = LeU32::new(version);
+        self
+    }
+
+    pub const fn with_event_queue_version(mut self, version: u32) -> Self {
+        self.event_queue_version = LeU32::new(version);
+        self
+    }
+}
+
+/// Exports the guest's [`WasmCapabilities`] under [`CAPABILITIES_EXPORT`].
+///
+/// ```ignore
+/// crt_marshal::export_capabilities!(WasmCapabilities::new(CapabilityFlags::REALLOC));
+/// ```
+#[macro_export]
+macro_rules! export_capabilities {
+    ($capabilities:expr) => {
+        const _: () = {
+            static CAPABILITIES: $crate::WasmCapabilities = $capabilities;
+
+            #[cfg(target_arch = "wasm32")]
+            #[export_name = "crt_capabilities"]
+            extern "C" fn crt_capabilities() -> u32 {
+                $crate::WasmPtr::new_guest(&CAPABILITIES).addr().get()
+            }
+        };
+    };
+}
+
+// === Cancellation === //
+
+/// The name of the export through which guests expose the address of their cancellation flag.
+pub const CANCEL_FLAG_EXPORT: &str = "crt_cancel_flag";
+
+/// Set to a non-zero value by the host when it wants the guest to abandon its current work.
+static CANCEL_FLAG: AtomicU32 = AtomicU32::new(0);
+
+#[doc(hidden)]
+#[cfg(target_arch = "wasm32")]
+#[export_name = "crt_cancel_flag"]
+pub extern "C" fn __crt_cancel_flag() -> u32 {
+    WasmPtr::new_guest(&CANCEL_FLAG).addr().get()
+}
+
+/// The error returned by [`check_cancelled`] once the host has cancelled the current call.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the guest call was cancelled")
+    }
+}
+
+/// Whether the host has asked the guest to stop. Long-running guest work should poll this and
+/// return early once it is set.
+pub fn is_cancelled() -> bool {
+    CANCEL_FLAG.load(Ordering::Relaxed) != 0
+}
+
+pub fn check_cancelled() -> Result<(), Cancelled> {
+    if is_cancelled() {
+        Err(Cancelled)
+    } else {
+        Ok(())
+    }
+}
+
+/// Clears the cancellation flag. Hosts also clear it whenever they reset their cancellation.
+pub fn reset_cancelled() {
+    CANCEL_FLAG.store(0, Ordering::Relaxed);
+}
+
+// === Resource Handles === //
+
+/// The import module through which guests release the host resources they hold.
+pub const RESOURCE_MODULE: &str = "crt_resource";
+
+/// An opaque reference to a host object of type `T`. Handles are never zero.
+#[repr(transparent)]
+pub struct WasmHandle<T: 'static> {
+    _ty: PhantomData<fn() -> T>,
+    raw: LeU32,
+}
+
+impl<T> fmt::Debug for WasmHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WasmHandle<{}>({:#x})", type_name::<T>(), self.raw.get())
+    }
+}
+
+impl<T> Copy for WasmHandle<T> {}
+
+impl<T> Clone for WasmHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> PartialEq for WasmHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw.get() == other.raw.get()
+    }
+}
+
+impl<T> Eq for WasmHandle<T> {}
+
+impl<T> hash::Hash for WasmHandle<T> {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.raw.get().hash(state);
+    }
+}
+
+unsafe impl<T> Pod for WasmHandle<T> {}
+unsafe impl<T> Zeroable for WasmHandle<T> {}
+
+impl<T> WasmHandle<T> {
+    pub const fn from_raw(raw: u32) -> Option<Self> {
+        if raw == 0 {
+            return None;
+        }
+
+        Some(Self {
+            _ty: PhantomData,
+            raw: LeU32::new(raw),
+        })
+    }
+
+    pub fn raw(self) -> u32 {
+        self.raw.get()
+    }
+
+    /// Reinterprets the handle as referring to a `U`. The host checks the type of the object on
+    /// every access so this can't be used to confuse objects.
+    pub fn cast<U>(self) -> WasmHandle<U> {
+        WasmHandle {
+            _ty: PhantomData,
+            raw: self.raw,
+        }
+    }
+
+    /// Lets the host reclaim the object. The handle must not be used afterwards.
+    #[cfg(target_arch = "wasm32")]
+    pub fn release(self) {
+        unsafe { resource_imports::resource_drop(self.cast()) };
+    }
+}
+
+impl<T> MarshaledTy for WasmHandle<T> {
+    type Prim = u32;
+
+    fn into_prim(me: Self) -> Self::Prim {
+        me.raw.get()
+    }
+
+    fn from_prim(me: Self::Prim) -> Option<Self> {
+        Self::from_raw(me)
+    }
+}
+
+/// Passes `None` as zero.
+impl<T> MarshaledTy for Option<WasmHandle<T>> {
+    type Prim = u32;
+
+    fn into_prim(me: Self) -> Self::Prim {
+        me.map_or(0, WasmHandle::raw)
+    }
+
+    fn from_prim(me: Self::Prim) -> Option<Self> {
+        Some(WasmHandle::from_raw(me))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod resource_imports {
+    use super::*;
+
+    guest_import! {
+        pub fn "crt_resource".resource_drop(handle: WasmHandle<()>);
+    }
+}
+
+// === Logging === //
+
+/// The import module through which guests stream log messages to the host.
+pub const LOG_MODULE: &str = "crt_log";
+
+/// The size of the buffer [`GuestLogWriter`] formats into before passing a chunk to the host.
+pub const LOG_CHUNK_LEN: usize = 128;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u32)]
+pub enum LogLevel {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl LogLevel {
+    pub const fn from_u32(v: u32) -> Option<Self> {
+        match v {
+            1 => Some(Self::Error),
+            2 => Some(Self::Warn),
+            3 => Some(Self::Info),
+            4 => Some(Self::Debug),
+            5 => Some(Self::Trace),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Info => "INFO",
+            Self::Debug => "DEBUG",
+            Self::Trace => "TRACE",
+        })
+    }
+}
+
+impl MarshaledTy for LogLevel {
+    type Prim = u32;
+
+    fn into_prim(me: Self) -> Self::Prim {
+        me as u32
+    }
+
+    fn from_prim(me: Self::Prim) -> Option<Self> {
+        Self::from_u32(me)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod log_imports {
+    use super::*;
+
+    guest_import! {
+        pub fn "crt_log".log_write(chunk: WasmSlice<u8>);
+        pub fn "crt_log".log_flush(level: LogLevel);
+    }
+}
+
+/// A [`fmt::Write`] which streams a single log message to the host in chunks of at most
+/// [`LOG_CHUNK_LEN`] bytes, without allocating. The message is emitted when the writer is dropped.
+#[cfg(target_arch = "wasm32")]
+pub struct GuestLogWriter {
+    level: LogLevel,
+    len: usize,
+    buf: [u8; LOG_CHUNK_LEN],
+}
+
+#[cfg(target_arch = "wasm32")]
+impl GuestLogWriter {
+    pub const fn new(level: LogLevel) -> Self {
+        Self {
+            level,
+            len: 0,
+            buf: [0; LOG_CHUNK_LEN],
+        }
+    }
+
+    fn send(bytes: &[u8]) {
+        if !bytes.is_empty() {
+            unsafe {
+                log_imports::log_write(WasmSlice::new_guest(bytes));
+            }
+        }
+    }
+
+    fn flush_chunk(&mut self) {
+        Self::send(&self.buf[..self.len]);
+        self.len = 0;
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl fmt::Write for GuestLogWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Chunks may split characters so they're only ever handled as bytes. The host only decodes
+        // whole messages.
+        let mut bytes = s.as_bytes();
+
+        while !bytes.is_empty() {
+            // Strings which would fill the buffer on their own are sent without copying them.
+            if self.len == 0 && bytes.len() >= LOG_CHUNK_LEN {
+                Self::send(bytes);
+                return Ok(());
+            }
+
+            let (chunk, rest) = bytes.split_at(bytes.len().min(LOG_CHUNK_LEN - self.len));
+            self.buf[self.len..][..chunk.len()].copy_from_slice(chunk);
+            self.len += chunk.len();
+
+            if self.len == LOG_CHUNK_LEN {
+                self.flush_chunk();
+            }
+
+            bytes = rest;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Drop for GuestLogWriter {
+    fn drop(&mut self) {
+        self.flush_chunk();
+        unsafe { log_imports::log_flush(self.level) };
+    }
+}
+
+/// Sends a formatted message to the host's log without allocating.
+#[cfg(target_arch = "wasm32")]
+pub fn log_fmt(level: LogLevel, args: fmt::Arguments<'_>) {
+    let _ = fmt::write(&mut GuestLogWriter::new(level), args);
+}
+
+/// Formats a message straight into the host's log, like [`log_fmt`].
+///
+/// ```ignore
+/// crt_marshal::guest_log!(LogLevel::Info, "loaded {count} assets");
+/// ```
+#[macro_export]
+macro_rules! guest_log {
+    ($level:expr, $($arg:tt)*) => {
+        $crate::log_fmt($level, ::core::format_args!($($arg)*))
+    };
+}
+
 // === Guest Constructors === //
 
 // ...as per the suggestion of LegionMammal978 (https://github.com/LegionMammal978). Thanks!
@@ -872,12 +2070,22 @@ fn slice_len<T>(mut ptr: *const [T]) -> usize {
 }
 
 pub const fn guest_usize_to_u32(v: usize) -> u32 {
-    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg(all(not(target_arch = "wasm32"), not(feature = "stubs")))]
     {
         let _ = v;
         panic!("attempted to call guest function on non-guest platform");
     }
 
+    // Stubbed tests only need sizes and indices to survive the round-trip.
+    #[cfg(all(not(target_arch = "wasm32"), feature = "stubs"))]
+    {
+        if v > u32::MAX as usize {
+            panic!("value does not fit in a guest `usize`");
+        }
+
+        v as u32
+    }
+
     #[cfg(target_arch = "wasm32")]
     {
         v as u32
@@ -885,13 +2093,13 @@ pub const fn guest_usize_to_u32(v: usize) -> u32 {
 }
 
 pub const fn guest_u32_to_usize(v: u32) -> usize {
-    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg(all(not(target_arch = "wasm32"), not(feature = "stubs")))]
     {
         let _ = v;
         panic!("attempted to call guest function on non-guest platform");
     }
 
-    #[cfg(target_arch = "wasm32")]
+    #[cfg(any(target_arch = "wasm32", feature = "stubs"))]
     {
         v as usize
     }
@@ -987,7 +2195,7 @@ impl<V> WasmDynamic<V> {
         {
             guest_export! {
                 fn dtor<T2, V2>(ptr: WasmPtr<()>, _meta: WasmPtr<WasmVtable<V2>>)
-                where [T2: WasmContainer]
+                where [T2: WasmContainer, V2: 'static]
                 {
                     drop(unsafe { T2::from_raw(ptr.into_guest()) });
                 }
@@ -1009,32 +2217,111 @@ impl<V> WasmDynamic<V> {
 
 // === Generator === //
 
+/// Declares functions imported from the host.
+///
+/// Functions returning a tuple of two or more values are passed a return area as their first
+/// argument instead, a pointer to one eight-byte slot per primitive of the result, which the host
+/// fills before returning. Hosts bind such functions with `bind_ret_area_to_linker`.
 #[macro_export]
 macro_rules! guest_import {
+    () => {};
     (
-        $(
-            $(#[$fn_attr:meta])*
-            $vis:vis fn $module:literal.$fn_name:ident(
-                $($arg_name:ident: $arg_ty:ty),*
-                $(,)?
-            ) $( -> $res_ty:ty )?;
-        )*
-    ) => {$(
+        $(#[$fn_attr:meta])*
+        $vis:vis fn $module:literal.$fn_name:ident(
+            $($arg_name:ident: $arg_ty:ty),*
+            $(,)?
+        ) -> ($first_res_ty:ty, $($res_ty:ty),+ $(,)?);
+        $($rest:tt)*
+    ) => {
+        $(#[$fn_attr])*
+        $vis unsafe fn $fn_name($($arg_name: $arg_ty),*) -> ($first_res_ty, $($res_ty,)+) {
+            $crate::__guest_import_ret_area_body!(
+                $module,
+                $fn_name,
+                ($($arg_name: $arg_ty),*) -> ($first_res_ty, $($res_ty,)+)
+            )
+        }
+
+        $crate::guest_import! { $($rest)* }
+    };
+    (
+        $(#[$fn_attr:meta])*
+        $vis:vis fn $module:literal.$fn_name:ident(
+            $($arg_name:ident: $arg_ty:ty),*
+            $(,)?
+        ) $( -> $res_ty:ty )?;
+        $($rest:tt)*
+    ) => {
         $(#[$fn_attr])*
         $vis unsafe fn $fn_name($($arg_name: $arg_ty),*) $(-> $res_ty)? {
-            #[link(wasm_import_module = $module)]
-            extern "C" {
-                fn $fn_name(
-                    $($arg_name: <$arg_ty as $crate::MarshaledTy>::Prim),*
-                ) $(-> <$res_ty as $crate::MarshaledTy>::Prim)?;
-            }
+            $crate::__guest_import_body!($module, $fn_name, ($($arg_name: $arg_ty),*) $(-> $res_ty)?)
+        }
 
-            $crate::MarshaledTyList::from_prims($fn_name(
-                $($crate::MarshaledTy::into_prim($arg_name),)*
-            ))
-            .expect("failed to parse result")
+        $crate::guest_import! { $($rest)* }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(not(all(feature = "stubs", not(target_arch = "wasm32"))))]
+macro_rules! __guest_import_body {
+    ($module:literal, $fn_name:ident, ($($arg_name:ident: $arg_ty:ty),*) $(-> $res_ty:ty)?) => {{
+        #[link(wasm_import_module = $module)]
+        extern "C" {
+            fn $fn_name(
+                $($arg_name: <$arg_ty as $crate::MarshaledTy>::Prim),*
+            ) $(-> <$res_ty as $crate::MarshaledTy>::Prim)?;
         }
-    )*};
+
+        $crate::MarshaledTyList::from_prims($fn_name(
+            $($crate::MarshaledTy::into_prim($arg_name),)*
+        ))
+        .expect("failed to parse result")
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(not(all(feature = "stubs", not(target_arch = "wasm32"))))]
+macro_rules! __guest_import_ret_area_body {
+    ($module:literal, $fn_name:ident, ($($arg_name:ident: $arg_ty:ty),*) -> $res_ty:ty) => {{
+        #[link(wasm_import_module = $module)]
+        extern "C" {
+            fn $fn_name(
+                ret_area: *mut u64,
+                $($arg_name: <$arg_ty as $crate::MarshaledTy>::Prim),*
+            );
+        }
+
+        let mut ret_area = [0u64; $crate::prim_list_len::<<$res_ty as $crate::MarshaledTyList>::Prims>()];
+
+        $fn_name(ret_area.as_mut_ptr(), $($crate::MarshaledTy::into_prim($arg_name),)*);
+
+        <$res_ty as $crate::MarshaledTyList>::from_prims($crate::prims_from_slots(&ret_area))
+            .expect("failed to parse result")
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(all(feature = "stubs", not(target_arch = "wasm32")))]
+macro_rules! __guest_import_body {
+    ($module:literal, $fn_name:ident, ($($arg_name:ident: $arg_ty:ty),*) $(-> $res_ty:ty)?) => {
+        $crate::stubs::call_stub::<($($arg_ty,)*), ($($res_ty)?)>(
+            $module,
+            ::core::stringify!($fn_name),
+            ($($arg_name,)*),
+        )
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(all(feature = "stubs", not(target_arch = "wasm32")))]
+macro_rules! __guest_import_ret_area_body {
+    ($module:literal, $fn_name:ident, ($($arg_name:ident: $arg_ty:ty),*) -> $res_ty:ty) => {
+        $crate::__guest_import_body!($module, $fn_name, ($($arg_name: $arg_ty),*) -> $res_ty)
+    };
 }
 
 #[macro_export]
@@ -1070,3 +2357,162 @@ macro_rules! guest_export {
         }
     )*};
 }
+
+/// Like [`guest_export!`] but also exports the function from the module under its own name so the
+/// host can look it up with `WasmFuncRef::from_export`.
+#[macro_export]
+macro_rules! generate_guest_export {
+    ($(
+        $(#[$attr:meta])*
+        $vis:vis fn $fn_name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $res:ty)? {
+            $($body:tt)*
+        }
+    )*) => {$(
+        $(#[$attr])*
+        #[allow(unused_parens)]
+        $vis const fn $fn_name() -> $crate::WasmFunc<($($ty),*) $(, $res)?> {
+            #[no_mangle]
+            unsafe extern "C" fn $fn_name($($arg: <$ty as $crate::MarshaledTy>::Prim),*)
+                $(-> <$res as $crate::MarshaledTy>::Prim)?
+            {
+                fn inner($($arg: $ty),*) $(-> $res)? {
+                    $($body)*
+                }
+
+                $crate::MarshaledTyList::into_prims(inner(
+                    $($crate::MarshaledTy::from_prim($arg).expect("failed to parse argument"),)*
+                ))
+            }
+
+            $crate::WasmFunc::new($crate::WasmPtr::new_guest($fn_name as *const ()))
+        }
+    )*};
+}
+
+/// Declares the functions of an import module once for both sides of the boundary.
+///
+/// ```ignore
+/// crt_marshal::define_interface! {
+///     pub mod gfx = "crt_gfx" {
+///         fn draw(x: u32, y: u32) -> bool;
+///         fn clear();
+///     }
+/// }
+/// ```
+///
+/// Guests get an `unsafe fn` for each of the functions, like those of [`guest_import!`]. Hosts
+/// built with the `wasmtime` feature get a `Host` trait with a method for each of the functions,
+/// taking the caller before their arguments, and a `bind_all_to_linker::<Impl>` function which
+/// defines all of them in a linker.
+#[macro_export]
+macro_rules! define_interface {
+    ($(
+        $(#[$mod_attr:meta])*
+        $vis:vis mod $mod_name:ident = $module:literal {
+            $(
+                $(#[$fn_attr:meta])*
+                fn $fn_name:ident($($arg_name:ident: $arg_ty:ty),* $(,)?) $(-> $res_ty:ty)?;
+            )*
+        }
+    )*) => {$(
+        $(#[$mod_attr])*
+        $vis mod $mod_name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            pub const MODULE: &str = $module;
+
+            $crate::__define_interface_guest! {
+                $module;
+                $($(#[$fn_attr])* fn $fn_name($($arg_name: $arg_ty),*) $(-> $res_ty)?;)*
+            }
+
+            $crate::__define_interface_host! {
+                $module;
+                $($(#[$fn_attr])* fn $fn_name($($arg_name: $arg_ty),*) $(-> $res_ty)?;)*
+            }
+        }
+    )*};
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(any(target_arch = "wasm32", feature = "stubs"))]
+macro_rules! __define_interface_guest {
+    (
+        $module:literal;
+        $($(#[$fn_attr:meta])* fn $fn_name:ident($($arg_name:ident: $arg_ty:ty),*) $(-> $res_ty:ty)?;)*
+    ) => {
+        $crate::guest_import! {
+            $($(#[$fn_attr])* pub fn $module.$fn_name($($arg_name: $arg_ty),*) $(-> $res_ty)?;)*
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(not(any(target_arch = "wasm32", feature = "stubs")))]
+macro_rules! __define_interface_guest {
+    ($($tt:tt)*) => {};
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(all(feature = "wasmtime", not(target_arch = "wasm32")))]
+macro_rules! __define_interface_host {
+    (
+        $module:literal;
+        $($(#[$fn_attr:meta])* fn $fn_name:ident($($arg_name:ident: $arg_ty:ty),*) $(-> $res_ty:ty)?;)*
+    ) => {
+        #[allow(unused_parens)]
+        pub trait Host {
+            type Data: 'static;
+
+            $(
+                $(#[$fn_attr])*
+                fn $fn_name(
+                    caller: $crate::macro_rexp::wasmtime::Caller<'_, Self::Data>,
+                    $($arg_name: $arg_ty),*
+                ) -> $crate::macro_rexp::wasmtime::Result<($($res_ty)?)>;
+            )*
+        }
+
+        #[allow(unused_parens, unused_variables)]
+        pub fn bind_all_to_linker<I: Host>(
+            linker: &mut $crate::macro_rexp::wasmtime::Linker<I::Data>,
+        ) -> $crate::macro_rexp::wasmtime::Result<()> {
+            $(
+                linker.func_wrap(
+                    $module,
+                    ::core::stringify!($fn_name),
+                    |caller: $crate::macro_rexp::wasmtime::Caller<'_, I::Data>,
+                     $($arg_name: <$arg_ty as $crate::MarshaledTy>::Prim),*|
+                     -> $crate::macro_rexp::wasmtime::Result<
+                        <($($res_ty)?) as $crate::MarshaledTyList>::Prims,
+                    > {
+                        let res = I::$fn_name(caller, $(
+                            <$arg_ty as $crate::MarshaledTy>::from_prim($arg_name).ok_or_else(|| {
+                                $crate::macro_rexp::wasmtime::Error::msg(::core::concat!(
+                                    "failed to parse argument `",
+                                    ::core::stringify!($arg_name),
+                                    "`",
+                                ))
+                            })?,
+                        )*)?;
+
+                        Ok($crate::MarshaledTyList::into_prims(res))
+                    },
+                )?;
+            )*
+
+            Ok(())
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(not(all(feature = "wasmtime", not(target_arch = "wasm32"))))]
+macro_rules! __define_interface_host {
+    ($($tt:tt)*) => {};
+}
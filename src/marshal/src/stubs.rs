@@ -0,0 +1,68 @@
+//! Stand-ins for host imports, so that guest logic can be unit-tested natively.
+//!
+//! With the `stubs` feature enabled, functions declared with [`guest_import!`](crate::guest_import)
+//! call the stub registered for them on the current thread instead of linking against a wasm
+//! import when built for anything but `wasm32`. Stubs receive their arguments as a tuple of
+//! marshaled values.
+
+use alloc::boxed::Box;
+use core::any::{type_name, Any};
+use std::{cell::RefCell, collections::HashMap};
+
+type StubKey = (&'static str, &'static str);
+
+std::thread_local! {
+    static STUBS: RefCell<HashMap<StubKey, Box<dyn Any>>> = RefCell::default();
+}
+
+type Stub<A, R> = Box<dyn FnMut(A) -> R>;
+
+/// Routes calls to the import `module.name` on this thread to `stub`, replacing any stub it had.
+pub fn set_stub<A: 'static, R: 'static>(
+    module: &'static str,
+    name: &'static str,
+    stub: impl 'static + FnMut(A) -> R,
+) {
+    let stub: Stub<A, R> = Box::new(stub);
+    STUBS.with(|stubs| stubs.borrow_mut().insert((module, name), Box::new(stub)));
+}
+
+/// Removes the stub for `module.name`, returning whether there was one.
+pub fn remove_stub(module: &'static str, name: &'static str) -> bool {
+    STUBS.with(|stubs| stubs.borrow_mut().remove(&(module, name)).is_some())
+}
+
+/// Removes every stub registered on this thread.
+pub fn clear_stubs() {
+    STUBS.with(|stubs| stubs.borrow_mut().clear());
+}
+
+/// Calls the stub for `module.name`. This is what stubbed imports expand to.
+///
+/// # Panics
+///
+/// Panics if no stub is registered or if it was registered with a different signature.
+pub fn call_stub<A: 'static, R: 'static>(module: &'static str, name: &'static str, args: A) -> R {
+    // Take the stub out of the registry while it runs so that it may register other stubs or call
+    // itself recursively through another entry.
+    let Some(mut stub) = STUBS.with(|stubs| stubs.borrow_mut().remove(&(module, name))) else {
+        panic!("no stub registered for host import {module}.{name}");
+    };
+
+    let Some(typed) = stub.downcast_mut::<Stub<A, R>>() else {
+        panic!(
+            "stub for host import {module}.{name} does not have the signature fn{} -> {}",
+            type_name::<A>(),
+            type_name::<R>(),
+        );
+    };
+
+    let res = typed(args);
+
+    // Keep any stub registered in its place while it ran.
+    STUBS.with(|stubs| {
+        stubs.borrow_mut().entry((module, name)).or_insert(stub);
+    });
+
+    res
+}
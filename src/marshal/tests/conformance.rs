@@ -0,0 +1,231 @@
+use crt_marshal::{
+    marshal_conformance_tests, LeF32, LeF64, LeI16, LeI32, LeI64, LeU16, LeU32, LeU64, LogLevel,
+    Marshaled, WasmDynamic, WasmFunc, WasmHandle, WasmOption, WasmPtr, WasmPtr64, WasmResult,
+    WasmSlice, WasmStr, WasmWidePtrRaw,
+};
+
+marshal_conformance_tests!(mod u8_conformance: u8,
+    valid: [0, 1, 0x7F, u8::MAX],
+    invalid_prims: [0x100, 0x1_0000, u32::MAX],
+);
+
+marshal_conformance_tests!(mod u16_conformance: u16,
+    valid: [0, 1, 0xFF, u16::MAX],
+    invalid_prims: [0x1_0000, u32::MAX],
+);
+
+marshal_conformance_tests!(mod u32_conformance: u32,
+    valid: [0, 1, u32::MAX],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod i8_conformance: i8,
+    valid: [0, -1, i8::MIN, i8::MAX],
+    invalid_prims: [i8::MIN as i32 - 1, i8::MAX as i32 + 1, i32::MIN, i32::MAX],
+);
+
+marshal_conformance_tests!(mod i16_conformance: i16,
+    valid: [0, -1, i16::MIN, i16::MAX],
+    invalid_prims: [i16::MIN as i32 - 1, i16::MAX as i32 + 1, i32::MIN, i32::MAX],
+);
+
+marshal_conformance_tests!(mod i32_conformance: i32,
+    valid: [0, -1, i32::MIN, i32::MAX],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod u64_conformance: u64,
+    valid: [0, 1, u64::MAX],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod i64_conformance: i64,
+    valid: [0, -1, i64::MIN, i64::MAX],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod f32_conformance: f32,
+    valid: [0.0, -0.0, 1.5, f32::MIN_POSITIVE, f32::INFINITY, f32::NAN],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod f64_conformance: f64,
+    valid: [0.0, -0.0, -1.5, f64::MAX, f64::NEG_INFINITY, f64::NAN],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod char_conformance: char,
+    valid: ['\0', 'a', '\u{D7FF}', '\u{E000}', char::MAX],
+    invalid_prims: [0xD800, 0xDFFF, 0x11_0000, u32::MAX],
+);
+
+marshal_conformance_tests!(mod bool_conformance: bool,
+    valid: [false, true],
+    invalid_prims: [2, u32::MAX],
+);
+
+marshal_conformance_tests!(mod le_i16_conformance: LeI16,
+    valid: [LeI16::new(i16::MIN), LeI16::new(-1), LeI16::new(i16::MAX)],
+    invalid_prims: [i16::MIN as i32 - 1, i16::MAX as i32 + 1],
+);
+
+marshal_conformance_tests!(mod le_u16_conformance: LeU16,
+    valid: [LeU16::new(0), LeU16::new(u16::MAX)],
+    invalid_prims: [0x1_0000, u32::MAX],
+);
+
+marshal_conformance_tests!(mod le_i32_conformance: LeI32,
+    valid: [LeI32::new(i32::MIN), LeI32::new(-1), LeI32::new(i32::MAX)],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod le_u32_conformance: LeU32,
+    valid: [LeU32::new(0), LeU32::new(u32::MAX)],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod le_i64_conformance: LeI64,
+    valid: [LeI64::new(i64::MIN), LeI64::new(-1), LeI64::new(i64::MAX)],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod le_u64_conformance: LeU64,
+    valid: [LeU64::new(0), LeU64::new(u64::MAX)],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod le_f32_conformance: LeF32,
+    valid: [LeF32::new(-0.0), LeF32::new(f32::MAX), LeF32::new(f32::NAN)],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod le_f64_conformance: LeF64,
+    valid: [LeF64::new(f64::MIN), LeF64::new(f64::EPSILON), LeF64::new(f64::NAN)],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod wasm_ptr_conformance: WasmPtr<u64>,
+    valid: [WasmPtr::new(LeU32::new(0)), WasmPtr::new(LeU32::new(u32::MAX))],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod wasm_ptr64_conformance: WasmPtr64<u64>,
+    valid: [WasmPtr64::new(LeU64::new(0)), WasmPtr64::new(LeU64::new(u64::MAX))],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod opt_wasm_ptr_conformance: Option<WasmPtr<u64>>,
+    valid: [None, Some(WasmPtr::new(LeU32::new(8))), Some(WasmPtr::new(LeU32::new(u32::MAX)))],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod opt_wasm_ptr64_conformance: Option<WasmPtr64<u64>>,
+    valid: [None, Some(WasmPtr64::new(LeU64::new(u64::MAX)))],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod wasm_slice_conformance: WasmSlice<u16>,
+    valid: [
+        WasmSlice { base: WasmPtr::new(LeU32::new(0)), len: LeU32::new(0) },
+        WasmSlice { base: WasmPtr::new(LeU32::new(u32::MAX)), len: LeU32::new(1) },
+        WasmSlice { base: WasmPtr::new(LeU32::new(8)), len: LeU32::new(u32::MAX) },
+    ],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod wasm_str_conformance: WasmStr,
+    valid: [WasmStr(WasmSlice { base: WasmPtr::new(LeU32::new(16)), len: LeU32::new(5) })],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod wasm_func_conformance: WasmFunc<(u32, bool), u64>,
+    valid: [WasmFunc::new(WasmPtr::new(LeU32::new(0))), WasmFunc::new(WasmPtr::new(LeU32::new(42)))],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod opt_wasm_func_conformance: Option<WasmFunc<(u32,), ()>>,
+    valid: [None, Some(WasmFunc::new(WasmPtr::new(LeU32::new(1))))],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod wasm_wide_ptr_conformance: WasmWidePtrRaw<u8, u32>,
+    valid: [WasmWidePtrRaw { base: WasmPtr::new(LeU32::new(4)), meta: WasmPtr::new(LeU32::new(u32::MAX)) }],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod wasm_dynamic_conformance: WasmDynamic<()>,
+    valid: [WasmDynamic(WasmWidePtrRaw { base: WasmPtr::new(LeU32::new(4)), meta: WasmPtr::new(LeU32::new(8)) })],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod wasm_result_conformance: WasmResult<i32, LogLevel>,
+    valid: [WasmResult::ok(0), WasmResult::ok(-1), WasmResult::err(LogLevel::Error)],
+    invalid_prims: [1 << 32, 2 << 32, u64::MAX],
+);
+
+marshal_conformance_tests!(mod wasm_unit_result_conformance: WasmResult<(), bool>,
+    valid: [WasmResult::ok(()), WasmResult::err(false), WasmResult::err(true)],
+    invalid_prims: [1, (1 << 32) | 2],
+);
+
+marshal_conformance_tests!(mod wasm_option_conformance: WasmOption<u16>,
+    valid: [WasmOption::none(), WasmOption::some(0), WasmOption::some(u16::MAX)],
+    invalid_prims: [1, (1 << 32) | 0x1_0000, 2 << 32],
+);
+
+marshal_conformance_tests!(mod wasm_option_ptr_conformance: WasmOption<Option<WasmPtr<u8>>>,
+    valid: [WasmOption::none(), WasmOption::some(None), WasmOption::some(Some(WasmPtr::new(LeU32::new(4))))],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod wasm_handle_conformance: WasmHandle<u8>,
+    valid: [WasmHandle::from_raw(1).unwrap(), WasmHandle::from_raw(u32::MAX).unwrap()],
+    invalid_prims: [0],
+);
+
+marshal_conformance_tests!(mod opt_wasm_handle_conformance: Option<WasmHandle<u8>>,
+    valid: [None, WasmHandle::from_raw(0x100_0001)],
+    invalid_prims: [],
+);
+
+marshal_conformance_tests!(mod log_level_conformance: LogLevel,
+    valid: [LogLevel::Error, LogLevel::Warn, LogLevel::Info, LogLevel::Debug, LogLevel::Trace],
+    invalid_prims: [0, 6, u32::MAX],
+);
+
+#[derive(Debug, Copy, Clone, Marshaled)]
+#[repr(C)]
+struct Extent {
+    width: u16,
+    height: i16,
+    data: WasmPtr<u8>,
+}
+
+marshal_conformance_tests!(mod derived_struct_conformance: Extent,
+    valid: [
+        Extent { width: 0, height: 0, data: WasmPtr::new(LeU32::new(0)) },
+        Extent { width: u16::MAX, height: -1, data: WasmPtr::new(LeU32::new(u32::MAX)) },
+    ],
+    invalid_prims: [],
+);
+
+#[derive(Debug, Copy, Clone, Marshaled)]
+#[repr(C)]
+struct Handle(u32);
+
+marshal_conformance_tests!(mod derived_tuple_struct_conformance: Handle,
+    valid: [Handle(0), Handle(u32::MAX)],
+    invalid_prims: [1 << 32, u64::MAX],
+);
+
+#[derive(Debug, Copy, Clone, Marshaled)]
+#[repr(C)]
+struct Point {
+    x: f32,
+    y: f32,
+}
+
+marshal_conformance_tests!(mod derived_float_struct_conformance: Point,
+    valid: [Point { x: 0.0, y: -0.0 }, Point { x: f32::NAN, y: f32::INFINITY }],
+    invalid_prims: [],
+);
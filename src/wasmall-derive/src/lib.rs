@@ -0,0 +1,195 @@
+//! Derive macros for `wasmall`.
+
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use syn::{
+    parse_macro_input, spanned::Spanned, Data, DeriveInput, Fields, GenericParam, Lifetime,
+    LifetimeParam, LitStr, Path, Type,
+};
+
+/// Derives `ByteParse` for a struct whose fields are read one after the other.
+///
+/// Each field is parsed with its own type's `ByteParse` implementation, which must output the
+/// field's type. Fields can be annotated with `#[byte_parse(...)]` to change this:
+///
+/// - `leb` reads a LEB128-encoded `u32`, `i32`, `u64`, or `i64`.
+/// - `with = Parser` parses the field with `Parser`, whose output must be the field's type.
+/// - `context = "..."` describes the field in error messages. By default, the field's name is used.
+///
+/// The struct may have at most one lifetime parameter, which is used as the lifetime of the parsed
+/// buffer.
+#[proc_macro_derive(ByteParse, attributes(byte_parse))]
+pub fn derive_byte_parse(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match derive_byte_parse_inner(input) {
+        Ok(output) => output.into(),
+        Err(err) => err.into_compile_error().into(),
+    }
+}
+
+fn derive_byte_parse_inner(input: DeriveInput) -> syn::Result<TokenStream> {
+    let krate = quote! { ::wasmall::util };
+
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.span(),
+            "`ByteParse` can only be derived for structs",
+        ));
+    };
+
+    // Determine the lifetime of the parsed buffer.
+    let mut lifetimes = input.generics.lifetimes();
+    let buf_lifetime = match (lifetimes.next(), lifetimes.next()) {
+        (None, _) => None,
+        (Some(lifetime), None) => Some(lifetime.lifetime.clone()),
+        (Some(_), Some(extra)) => {
+            return Err(syn::Error::new(
+                extra.span(),
+                "`ByteParse` can only be derived for structs with at most one lifetime",
+            ))
+        }
+    };
+
+    let mut impl_generics = input.generics.clone();
+    let buf_lifetime = buf_lifetime.unwrap_or_else(|| {
+        let lifetime = Lifetime::new("'__buf", Span::call_site());
+        impl_generics.params.insert(
+            0,
+            GenericParam::Lifetime(LifetimeParam::new(lifetime.clone())),
+        );
+        lifetime
+    });
+
+    let (impl_generics, _, _) = impl_generics.split_for_impl();
+    let (_, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    // Generate the readers of each field.
+    let mut readers = Vec::new();
+    let mut names = Vec::new();
+
+    for (i, field) in data.fields.iter().enumerate() {
+        let options = FieldOptions::parse(field)?;
+
+        let name = match &field.ident {
+            Some(ident) => quote! { #ident },
+            None => {
+                let index = syn::Index::from(i);
+                quote! { #index }
+            }
+        };
+
+        let var = syn::Ident::new(&format!("__field_{i}"), field.span());
+
+        let description = match options.context {
+            Some(context) => context.value(),
+            None => match &field.ident {
+                Some(ident) => ident.to_string().trim_start_matches("r#").replace('_', " "),
+                None => format!("field {i}"),
+            },
+        };
+        let context = LitStr::new(&format!("failed to read {description}"), field.span());
+
+        let ty = &field.ty;
+        let read = match (options.leb, options.with) {
+            (true, Some(_)) => {
+                return Err(syn::Error::new(
+                    field.span(),
+                    "`leb` and `with` cannot be used together",
+                ))
+            }
+            (true, None) => {
+                let reader = leb_reader(ty)?;
+                quote! { buf.#reader() }
+            }
+            (false, Some(with)) => {
+                quote! { <#with as #krate::ByteParse<#buf_lifetime>>::parse(buf) }
+            }
+            (false, None) => quote! { <#ty as #krate::ByteParse<#buf_lifetime>>::parse(buf) },
+        };
+
+        readers.push(quote! {
+            let #var: #ty = #read.map_err(|err| err.context(#context))?;
+        });
+        names.push((name, var));
+    }
+
+    let ident = &input.ident;
+    let construct = match &data.fields {
+        Fields::Named(_) | Fields::Unnamed(_) => {
+            let fields = names.iter().map(|(name, var)| quote! { #name: #var });
+            quote! { Self { #(#fields),* } }
+        }
+        Fields::Unit => quote! { Self },
+    };
+
+    Ok(quote! {
+        impl #impl_generics #krate::ByteParse<#buf_lifetime> for #ident #ty_generics #where_clause {
+            type Out = Self;
+
+            fn parse_naked(
+                buf: &mut #krate::ByteCursor<#buf_lifetime>,
+            ) -> #krate::__private::anyhow::Result<Self::Out> {
+                #(#readers)*
+                ::core::result::Result::Ok(#construct)
+            }
+        }
+    })
+}
+
+#[derive(Default)]
+struct FieldOptions {
+    leb: bool,
+    with: Option<Path>,
+    context: Option<LitStr>,
+}
+
+impl FieldOptions {
+    fn parse(field: &syn::Field) -> syn::Result<Self> {
+        let mut options = Self::default();
+
+        for attr in &field.attrs {
+            if !attr.path().is_ident("byte_parse") {
+                continue;
+            }
+
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("leb") {
+                    options.leb = true;
+                } else if meta.path.is_ident("with") {
+                    options.with = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("context") {
+                    options.context = Some(meta.value()?.parse()?);
+                } else {
+                    return Err(meta.error("unknown `byte_parse` option"));
+                }
+
+                Ok(())
+            })?;
+        }
+
+        Ok(options)
+    }
+}
+
+fn leb_reader(ty: &Type) -> syn::Result<syn::Ident> {
+    let name = match ty {
+        Type::Path(path) if path.qself.is_none() => path.path.get_ident().map(|id| id.to_string()),
+        _ => None,
+    };
+
+    let reader = match name.as_deref() {
+        Some("u32") => "read_var_u32",
+        Some("i32") => "read_var_i32",
+        Some("u64") => "read_var_u64",
+        Some("i64") => "read_var_i64",
+        _ => {
+            return Err(syn::Error::new(
+                ty.span(),
+                "`leb` fields must be `u32`, `i32`, `u64`, or `i64`",
+            ))
+        }
+    };
+
+    Ok(syn::Ident::new(reader, ty.span()))
+}
@@ -0,0 +1,116 @@
+//! Compares `ByteCursor`'s LEB128 decoding against the byte-by-byte decoder it used before it
+//! gained its fast paths.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use wasmall::util::{ByteCursor, Leb128WriteExt};
+
+const COUNT: usize = 4096;
+
+/// `ByteCursor::read_var_u32` and `ByteCursor::read_var_i32` as they were before they gained
+/// their fast paths.
+fn byte_by_byte(buf: &mut &[u8], signed: bool) -> anyhow::Result<u32> {
+    let mut reader = &buf[..buf.len().min(5)];
+    let start_len = reader.len();
+
+    let value = if signed {
+        leb128::read::signed(&mut reader)
+            .ok()
+            .filter(|v| v >> 31 == 0 || v >> 31 == -1)
+            .map(|v| v as u32)
+    } else {
+        leb128::read::unsigned(&mut reader)
+            .ok()
+            .filter(|v| v >> 32 == 0)
+            .map(|v| v as u32)
+    };
+
+    let value = value.ok_or_else(|| anyhow::anyhow!("malformed LEB128"))?;
+    *buf = &buf[start_len - reader.len()..];
+    Ok(value)
+}
+
+fn inputs(signed: bool) -> Vec<(&'static str, Vec<u8>)> {
+    // A cheap xorshift so that the inputs are the same on every run.
+    let mut state = 0x2545_F491_4F6C_DD1Du64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let mut small = Vec::new();
+    let mut mixed = Vec::new();
+    let mut padded = Vec::new();
+
+    for _ in 0..COUNT {
+        let raw = next();
+
+        // Most LEBs in code sections are local indices, opcodes, and small immediates.
+        let value = match raw % 8 {
+            0..=4 => raw >> 58,
+            5 | 6 => raw >> 50,
+            _ => raw >> 32,
+        };
+
+        if signed {
+            small.write_var_i32((raw >> 58) as i32 - 32);
+            mixed.write_var_i32(value as i32);
+            padded.write_var_i32_full(value as i32);
+        } else {
+            small.write_var_u32((raw >> 58) as u32);
+            mixed.write_var_u32(value as u32);
+            padded.write_var_u32_full(value as u32);
+        }
+    }
+
+    vec![("small", small), ("mixed", mixed), ("padded", padded)]
+}
+
+fn bench_leb(c: &mut Criterion) {
+    for signed in [false, true] {
+        let mut group = c.benchmark_group(if signed {
+            "read_var_i32"
+        } else {
+            "read_var_u32"
+        });
+        group.throughput(Throughput::Elements(COUNT as u64));
+
+        for (name, input) in inputs(signed) {
+            group.bench_with_input(BenchmarkId::new("ByteCursor", name), &input, |b, input| {
+                b.iter(|| {
+                    let mut cursor = ByteCursor(black_box(input));
+                    let mut sum = 0u32;
+                    while !cursor.at_eof() {
+                        sum = sum.wrapping_add(if signed {
+                            cursor.read_var_i32().unwrap() as u32
+                        } else {
+                            cursor.read_var_u32().unwrap()
+                        });
+                    }
+                    sum
+                })
+            });
+
+            group.bench_with_input(
+                BenchmarkId::new("byte-by-byte", name),
+                &input,
+                |b, input| {
+                    b.iter(|| {
+                        let mut reader = &black_box(input)[..];
+                        let mut sum = 0u32;
+                        while !reader.is_empty() {
+                            sum = sum.wrapping_add(byte_by_byte(&mut reader, signed).unwrap());
+                        }
+                        sum
+                    })
+                },
+            );
+        }
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_leb);
+criterion_main!(benches);
@@ -0,0 +1,505 @@
+//! An append-only pack store which new blobs can be written into incrementally.
+//!
+//! Unlike the immutable packs in [`pack`](crate::pack), this store is meant to be written to during
+//! updates. It lives in a single directory containing:
+//!
+//! - Data files (`<id>.data`), each consisting of the [`DATA_MAGIC`] followed by records. Every
+//!   record is the blob's 32 byte hash, its little-endian `u64` length, and its payload.
+//! - An index file (`index`), consisting of the [`INDEX_MAGIC`] followed by fixed-size entries
+//!   mapping each hash to the little-endian `u32` id of its data file and the `u64` offset and
+//!   length of its payload within that file.
+//!
+//! Blobs are only ever appended. Space held by dead blobs is reclaimed with
+//! [`repack`](AppendPackStore::repack), which also folds in loose blobs from a [`DirBlobStore`].
+
+use std::{
+    borrow::Cow,
+    fs,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use blake3::Hash;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::{
+    filter::BlobFilter,
+    store::{BlobSource, BlobStore, DirBlobStore},
+    util::{write_atomic, ByteCursor, SliceExt},
+};
+
+// === Format === //
+
+pub const DATA_MAGIC: [u8; 8] = *b"WSMLADAT";
+
+pub const INDEX_MAGIC: [u8; 8] = *b"WSMLAIDX";
+
+const RECORD_HEADER_LEN: u64 = blake3::OUT_LEN as u64 + 8;
+
+const INDEX_ENTRY_LEN: usize = blake3::OUT_LEN + 4 + 8 + 8;
+
+/// Data files are rotated once they grow beyond this size by default.
+pub const DEFAULT_MAX_DATA_LEN: u64 = 256 << 20;
+
+#[derive(Debug, Copy, Clone)]
+struct Location {
+    file: u32,
+    offset: u64,
+    len: u64,
+}
+
+fn encode_index_entry(hash: Hash, loc: Location) -> [u8; INDEX_ENTRY_LEN] {
+    let mut entry = [0; INDEX_ENTRY_LEN];
+    entry[0..32].copy_from_slice(hash.as_bytes());
+    entry[32..36].copy_from_slice(&loc.file.to_le_bytes());
+    entry[36..44].copy_from_slice(&loc.offset.to_le_bytes());
+    entry[44..52].copy_from_slice(&loc.len.to_le_bytes());
+    entry
+}
+
+fn data_path(dir: &Path, id: u32) -> PathBuf {
+    dir.join(format!("{id:08}.data"))
+}
+
+fn index_path(dir: &Path) -> PathBuf {
+    dir.join("index")
+}
+
+// === AppendPackStore === //
+
+#[derive(Debug)]
+pub struct AppendPackStore {
+    dir: PathBuf,
+    max_data_len: u64,
+    entries: FxHashMap<Hash, Location>,
+
+    /// Read handles for every data file, keyed by id.
+    readers: FxHashMap<u32, Mutex<fs::File>>,
+
+    /// The data file currently being appended to along with its id and length.
+    writer: Option<(u32, fs::File, u64)>,
+
+    /// The append handle to the index file.
+    index: fs::File,
+
+    /// An optional filter over the stored hashes along with its target false positive rate.
+    filter: Option<(BlobFilter, f64)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RepackReport {
+    /// The number of live blobs kept in the new packs.
+    pub blobs_kept: usize,
+
+    /// The number of dead blobs dropped from the packs and the loose store.
+    pub blobs_dropped: usize,
+
+    /// The number of live loose blobs folded into the new packs.
+    pub loose_folded: usize,
+
+    /// The total size of the data files before and after repacking.
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+impl AppendPackStore {
+    /// Opens the store in `dir`, creating it if it doesn't exist yet. Index entries left
+    /// incomplete by an interrupted write are discarded.
+    pub fn open(dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create pack directory {dir:?}"))?;
+
+        // Open every data file
+        let mut readers = FxHashMap::default();
+        let mut data_lens = FxHashMap::default();
+
+        for entry in
+            fs::read_dir(&dir).with_context(|| format!("failed to read pack directory {dir:?}"))?
+        {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "data") {
+                continue;
+            }
+
+            let Some(id) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u32>().ok())
+            else {
+                continue;
+            };
+
+            let mut file =
+                fs::File::open(&path).with_context(|| format!("failed to open {path:?}"))?;
+
+            let mut magic = [0; DATA_MAGIC.len()];
+            file.read_exact(&mut magic)
+                .ok()
+                .filter(|_| magic == DATA_MAGIC)
+                .with_context(|| format!("{path:?} is not a pack data file"))?;
+
+            data_lens.insert(id, file.metadata()?.len());
+            readers.insert(id, Mutex::new(file));
+        }
+
+        // Load the index
+        let index_path = index_path(&dir);
+        let mut index = fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&index_path)
+            .with_context(|| format!("failed to open pack index {index_path:?}"))?;
+
+        let mut index_data = Vec::new();
+        index.read_to_end(&mut index_data)?;
+
+        if index_data.is_empty() {
+            index.write_all(&INDEX_MAGIC)?;
+            index_data.extend_from_slice(&INDEX_MAGIC);
+        }
+
+        anyhow::ensure!(
+            index_data.limit_len(INDEX_MAGIC.len()) == INDEX_MAGIC,
+            "{index_path:?} is not a pack index"
+        );
+
+        let entry_data = &index_data[INDEX_MAGIC.len()..];
+        let complete_len = entry_data.len() - entry_data.len() % INDEX_ENTRY_LEN;
+
+        if complete_len != entry_data.len() {
+            // Drop the torn entry so that future appends stay aligned.
+            index.set_len((INDEX_MAGIC.len() + complete_len) as u64)?;
+        }
+
+        let mut entries = FxHashMap::default();
+        let mut cursor = ByteCursor(&entry_data[..complete_len]);
+
+        while !cursor.at_eof() {
+            let hash = Hash::from_bytes(cursor.consume_arr()?);
+            let loc = Location {
+                file: cursor.read_u32()?,
+                offset: cursor.read_u64()?,
+                len: cursor.read_u64()?,
+            };
+
+            // Entries whose data never made it to disk are ignored.
+            let in_bounds = data_lens.get(&loc.file).is_some_and(|&data_len| {
+                loc.offset
+                    .checked_add(loc.len)
+                    .is_some_and(|end| end <= data_len)
+            });
+
+            if in_bounds {
+                entries.insert(hash, loc);
+            }
+        }
+
+        Ok(Self {
+            dir,
+            max_data_len: DEFAULT_MAX_DATA_LEN,
+            entries,
+            readers,
+            writer: None,
+            index,
+            filter: None,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn set_max_data_len(&mut self, max_data_len: u64) {
+        self.max_data_len = max_data_len;
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, hash: Hash) -> bool {
+        self.entries.contains_key(&hash)
+    }
+
+    pub fn hashes(&self) -> impl Iterator<Item = Hash> + '_ {
+        self.entries.keys().copied()
+    }
+
+    /// Starts maintaining a [`BlobFilter`] over the store's hashes with the specified target false
+    /// positive rate. The filter is grown automatically as blobs are added.
+    pub fn enable_filter(&mut self, false_positive_rate: f64) {
+        self.filter = Some((self.build_filter(false_positive_rate), false_positive_rate));
+    }
+
+    pub fn filter(&self) -> Option<&BlobFilter> {
+        self.filter.as_ref().map(|(filter, _)| filter)
+    }
+
+    fn build_filter(&self, false_positive_rate: f64) -> BlobFilter {
+        let mut filter = BlobFilter::with_capacity(self.len() * 2, false_positive_rate);
+        for hash in self.hashes() {
+            filter.insert(hash);
+        }
+        filter
+    }
+
+    fn next_file_id(&self) -> u32 {
+        self.readers.keys().max().map_or(0, |&id| id + 1)
+    }
+
+    fn append_record(&mut self, hash: Hash, data: &[u8]) -> anyhow::Result<Location> {
+        // Rotate the data file if needed
+        let record_len = RECORD_HEADER_LEN + data.len() as u64;
+
+        if self.writer.as_ref().is_none_or(|&(_, _, len)| {
+            len > DATA_MAGIC.len() as u64 && len + record_len > self.max_data_len
+        }) {
+            if let Some((_, file, _)) = &self.writer {
+                file.sync_data()?;
+            }
+
+            let id = self.next_file_id();
+            let path = data_path(&self.dir, id);
+            let mut file = fs::OpenOptions::new()
+                .append(true)
+                .create_new(true)
+                .open(&path)
+                .with_context(|| format!("failed to create pack data file {path:?}"))?;
+
+            file.write_all(&DATA_MAGIC)?;
+            self.readers.insert(id, Mutex::new(fs::File::open(&path)?));
+            self.writer = Some((id, file, DATA_MAGIC.len() as u64));
+        }
+
+        // Append the record
+        let (id, file, len) = self.writer.as_mut().unwrap();
+
+        let mut record = Vec::with_capacity(record_len as usize);
+        record.extend_from_slice(hash.as_bytes());
+        record.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        record.extend_from_slice(data);
+        file.write_all(&record)
+            .context("failed to append to pack data file")?;
+
+        let loc = Location {
+            file: *id,
+            offset: *len + RECORD_HEADER_LEN,
+            len: data.len() as u64,
+        };
+        *len += record_len;
+
+        Ok(loc)
+    }
+
+    /// Appends a blob to the store. Blobs which are already present are skipped.
+    pub fn put_blob(&mut self, hash: Hash, data: &[u8]) -> anyhow::Result<()> {
+        if self.contains(hash) {
+            return Ok(());
+        }
+
+        // The data is written before its index entry so the index never refers to missing data.
+        let loc = self.append_record(hash, data)?;
+        self.index
+            .write_all(&encode_index_entry(hash, loc))
+            .context("failed to append to pack index")?;
+
+        self.entries.insert(hash, loc);
+
+        match &mut self.filter {
+            Some((filter, _)) if self.entries.len() <= filter.capacity() => filter.insert(hash),
+            Some((_, false_positive_rate)) => {
+                let false_positive_rate = *false_positive_rate;
+                self.enable_filter(false_positive_rate);
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    /// Flushes all appended blobs to disk.
+    pub fn sync(&mut self) -> anyhow::Result<()> {
+        if let Some((_, file, _)) = &self.writer {
+            file.sync_data()?;
+        }
+        self.index.sync_data()?;
+        Ok(())
+    }
+
+    fn read(&self, hash: Hash, loc: Location) -> anyhow::Result<Vec<u8>> {
+        let mut data = vec![0; usize::try_from(loc.len)?];
+        {
+            let mut file = self.readers[&loc.file].lock().unwrap();
+            file.seek(SeekFrom::Start(loc.offset))?;
+            file.read_exact(&mut data).with_context(|| {
+                format!("failed to read blob {hash} from data file {}", loc.file)
+            })?;
+        }
+
+        let actual_hash = blake3::hash(&data);
+        anyhow::ensure!(
+            actual_hash == hash,
+            "blob {hash} in data file {} is corrupted; got hash {actual_hash}",
+            loc.file,
+        );
+
+        Ok(data)
+    }
+
+    pub fn get(&self, hash: Hash) -> anyhow::Result<Option<Vec<u8>>> {
+        self.entries
+            .get(&hash)
+            .map(|&loc| self.read(hash, loc))
+            .transpose()
+    }
+
+    /// Rewrites the store so it only contains the blobs for which `is_live` returns `true`,
+    /// folding in and then deleting every blob from `loose`. The old data files are deleted once
+    /// the new index has been written.
+    pub fn repack(
+        &mut self,
+        loose: Option<&DirBlobStore>,
+        mut is_live: impl FnMut(Hash) -> bool,
+    ) -> anyhow::Result<RepackReport> {
+        let mut report = RepackReport::default();
+
+        for file in self.readers.values() {
+            report.bytes_before += file.lock().unwrap().metadata()?.len();
+        }
+
+        let old_ids = self.readers.keys().copied().collect::<Vec<_>>();
+        let loose_hashes = loose
+            .map(DirBlobStore::hashes)
+            .transpose()?
+            .unwrap_or_default();
+
+        // Write every live blob into fresh data files. The old entries stay in place until the new
+        // index does so that a failed repack leaves the store as it was.
+        if let Some((_, file, _)) = self.writer.take() {
+            file.sync_data()?;
+        }
+
+        let old_entries = self.entries.clone();
+        let mut new_entries = FxHashMap::default();
+        let mut new_index = Vec::from(INDEX_MAGIC);
+
+        for (&hash, &loc) in &old_entries {
+            if !is_live(hash) {
+                report.blobs_dropped += 1;
+                continue;
+            }
+
+            let data = self.read(hash, loc)?;
+            let loc = self.append_record(hash, &data)?;
+            new_index.extend_from_slice(&encode_index_entry(hash, loc));
+            new_entries.insert(hash, loc);
+            report.blobs_kept += 1;
+        }
+
+        for &hash in &loose_hashes {
+            // Packed blobs were already kept or dropped above.
+            if old_entries.contains_key(&hash) {
+                continue;
+            }
+
+            if !is_live(hash) {
+                report.blobs_dropped += 1;
+                continue;
+            }
+
+            let data = loose
+                .unwrap()
+                .get_blob(hash)?
+                .with_context(|| format!("loose blob {hash} disappeared during repack"))?;
+
+            let loc = self.append_record(hash, &data)?;
+            new_index.extend_from_slice(&encode_index_entry(hash, loc));
+            new_entries.insert(hash, loc);
+            report.blobs_kept += 1;
+            report.loose_folded += 1;
+        }
+
+        if let Some((_, file, _)) = self.writer.take() {
+            file.sync_data()?;
+        }
+
+        // Swap in the new index.
+        let index_path = index_path(&self.dir);
+        write_atomic(&index_path, &new_index, "pack index", true)?;
+        self.entries = new_entries;
+
+        self.index = fs::OpenOptions::new()
+            .append(true)
+            .open(&index_path)
+            .with_context(|| format!("failed to reopen pack index {index_path:?}"))?;
+
+        // Now that nothing refers to them, delete the old data and the loose blobs.
+        for id in old_ids {
+            self.readers.remove(&id);
+            let path = data_path(&self.dir, id);
+            fs::remove_file(&path)
+                .with_context(|| format!("failed to remove old data file {path:?}"))?;
+        }
+
+        if let Some(loose) = loose {
+            for hash in loose_hashes {
+                loose.remove_blob(hash)?;
+            }
+        }
+
+        for file in self.readers.values() {
+            report.bytes_after += file.lock().unwrap().metadata()?.len();
+        }
+
+        // Dead blobs can't be removed from a bloom filter so we have to start over.
+        if let Some((_, false_positive_rate)) = self.filter {
+            self.enable_filter(false_positive_rate);
+        }
+
+        Ok(report)
+    }
+}
+
+impl BlobSource for AppendPackStore {
+    fn get_blob(&self, hash: Hash) -> anyhow::Result<Option<Cow<'_, [u8]>>> {
+        self.get(hash).map(|data| data.map(Cow::Owned))
+    }
+}
+
+impl BlobStore for AppendPackStore {
+    fn has_blob(&self, hash: Hash) -> anyhow::Result<bool> {
+        Ok(self.contains(hash))
+    }
+
+    fn put_blob(&mut self, hash: Hash, data: &[u8]) -> anyhow::Result<()> {
+        AppendPackStore::put_blob(self, hash, data)
+    }
+
+    fn list_blobs(&self) -> anyhow::Result<Vec<Hash>> {
+        Ok(self.hashes().collect())
+    }
+
+    /// Dead blobs are dropped by [repacking](Self::repack) the store, which is skipped if every
+    /// blob is still live.
+    fn retain_blobs(&mut self, is_live: &mut dyn FnMut(Hash) -> bool) -> anyhow::Result<usize> {
+        let dead = self
+            .hashes()
+            .filter(|&hash| !is_live(hash))
+            .collect::<FxHashSet<_>>();
+        if dead.is_empty() {
+            return Ok(0);
+        }
+
+        Ok(self
+            .repack(None, |hash| !dead.contains(&hash))?
+            .blobs_dropped)
+    }
+}
@@ -1,6 +1,5 @@
 use anyhow::Context;
-use rustc_hash::FxHashSet;
-use wasmall::splitter::split_module;
+use wasmall::{diff::ArchiveDiff, splitter::split_module};
 
 fn main() -> anyhow::Result<()> {
     let left_src = std::fs::read(std::env::args().nth(1).context("missing left path")?)?;
@@ -9,15 +8,35 @@ fn main() -> anyhow::Result<()> {
     let left_mod = split_module(&left_src)?;
     let right_mod = split_module(&right_src)?;
 
-    let left_set = FxHashSet::from_iter(left_mod.archive.hashes.keys().copied());
-    let right_set = FxHashSet::from_iter(right_mod.archive.hashes.keys().copied());
+    let diff = ArchiveDiff::new(&left_mod.archive, &right_mod.archive)?;
 
-    for diff in left_set.symmetric_difference(&right_set) {
-        if left_set.contains(diff) {
-            println!("- {diff}");
-        } else {
-            println!("+ {diff}");
-        }
+    for blob in &diff.removed {
+        println!("- {}", blob.hash);
+    }
+
+    for blob in &diff.added {
+        println!("+ {}", blob.hash);
+    }
+
+    println!(
+        "Shared blobs: {} ({} bytes), new blobs: {} ({} bytes), removed blobs: {} ({} bytes)",
+        diff.shared.len(),
+        diff.shared_bytes(),
+        diff.added.len(),
+        diff.added_bytes(),
+        diff.removed.len(),
+        diff.removed_bytes(),
+    );
+
+    println!(
+        "Transfer with left cached: {} / {} bytes ({}% cached)",
+        diff.transfer_bytes(),
+        diff.full_transfer_bytes(),
+        diff.cache_hit_ratio() * 100.,
+    );
+
+    if let Some(summary) = diff.function_summary() {
+        println!("Functions: {summary:?}");
     }
 
     let left_src_len_post_trunc = left_src.len() - left_mod.bytes_truncated;
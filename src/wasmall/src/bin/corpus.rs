@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use wasmall::corpus::{run_corpus, CorpusConfig};
+
+fn main() -> anyhow::Result<()> {
+    // Parse arguments
+    let mut dir = None;
+    let mut validate = true;
+
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--no-validate" => validate = false,
+            _ => dir = Some(PathBuf::from(arg)),
+        }
+    }
+
+    let dir = dir.context("missing corpus directory")?;
+
+    let mut configs = CorpusConfig::standard();
+    if !validate {
+        configs = CorpusConfig::without_validation(configs);
+    }
+
+    // Run the corpus
+    let report = run_corpus(&dir, &configs)?;
+    println!("{report}");
+
+    if !report.is_ok() {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use anyhow::Context;
+use wasmall::{
+    coder::WasmallMod,
+    graph::GraphExport,
+    splitter::split_module,
+    util::{ByteCursor, ByteParse},
+};
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1).peekable();
+    let dot = args.next_if(|arg| arg == "--dot").is_some();
+
+    let mut graph = GraphExport::new();
+
+    for path in args {
+        let src = std::fs::read(&path).with_context(|| format!("failed to read {path}"))?;
+        let archive = split_module(&src)?.archive;
+        let module = WasmallMod::parse(&mut ByteCursor(&archive.out_buf))?;
+        graph.add_module(path, &module, &archive)?;
+    }
+
+    if dot {
+        print!("{}", graph.to_graphviz());
+    } else {
+        println!("{}", graph.to_json());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use std::{net::TcpListener, path::PathBuf};
+
+use anyhow::Context;
+use wasmall::{
+    live::{publish, LiveServer},
+    splitter::split_module,
+    store::DirBlobStore,
+};
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let command = args
+        .next()
+        .context("missing command; expected `serve` or `publish`")?;
+
+    match command.as_str() {
+        "serve" => {
+            let index_dir = PathBuf::from(args.next().context("missing index directory")?);
+            let store = DirBlobStore::new(args.next().context("missing blob directory")?);
+            let addr = args.next().unwrap_or_else(|| "127.0.0.1:8437".to_string());
+
+            let listener =
+                TcpListener::bind(&addr).with_context(|| format!("failed to bind to {addr}"))?;
+
+            println!("Serving {index_dir:?} on ws://{addr}");
+            let mut server = LiveServer::new(index_dir, store);
+            server.set_error_handler(|err| eprintln!("{err}"));
+            server.serve(listener)?;
+        }
+        "publish" => {
+            let path = PathBuf::from(args.next().context("missing module path")?);
+            let index_dir = PathBuf::from(args.next().context("missing index directory")?);
+            let store = DirBlobStore::new(args.next().context("missing blob directory")?);
+
+            let name = path
+                .file_stem()
+                .and_then(|name| name.to_str())
+                .context("module path has no usable name")?;
+
+            let code = std::fs::read(&path)?;
+            let archive = split_module(&code)?.archive;
+            let index_path = publish(&archive, name, &index_dir, &store)?;
+
+            println!("Published {index_path:?}");
+        }
+        _ => anyhow::bail!("unknown command {command:?}; expected `serve` or `publish`"),
+    }
+
+    Ok(())
+}
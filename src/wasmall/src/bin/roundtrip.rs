@@ -2,37 +2,54 @@ use std::io::Write;
 
 use anyhow::Context;
 use wasmall::{
-    coder::{WasmallBlob, WasmallMod, WasmallModSeg},
-    splitter::split_module,
+    chunker::ChunkingOptions,
+    coder::WasmallMod,
+    features::set_feature,
+    splitter::{split_module_with, SplitOptions},
     util::{ByteCursor, ByteParse, OffsetTracker},
 };
 
 fn main() -> anyhow::Result<()> {
-    // Compress it
-    let code = std::fs::read(std::env::args().nth(1).context("missing path")?)?;
-    let archive = split_module(&code)?.archive;
-
-    // Decompress it
-    let _guard = OffsetTracker::new(&archive.out_buf);
-    let parsed = WasmallMod::parse(&mut ByteCursor(&archive.out_buf))?;
-    let mut writer = Vec::new();
+    // Parse arguments
+    let mut options = SplitOptions::default();
+    let mut path = None;
+    let mut parallel = false;
+    let mut args = std::env::args().skip(1);
 
-    for segment in parsed.segments() {
-        match segment? {
-            WasmallModSeg::Verbatim(segment) => {
-                writer.extend_from_slice(segment.data());
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--enable" | "--disable" => {
+                let name = args.next().context("missing feature name")?;
+                set_feature(&mut options.features, &name, arg == "--enable")?;
             }
-            WasmallModSeg::Blob(segment) => {
-                segment.write(
-                    &WasmallBlob::parse(&mut ByteCursor(
-                        &archive.blob_buf[archive.hashes[&segment.hash()].clone()],
-                    ))?,
-                    &mut writer,
-                )?;
+            "--no-validate" => options.validate = false,
+            "--normalize" => options.normalize = true,
+            "--no-prioritize" => options.prioritize = false,
+            "--share-sections" => options.share_sections = true,
+            "--chunk" => options.chunking = Some(ChunkingOptions::default()),
+            "--no-chunk-data" => options.data_chunking = None,
+            "--dictionary" => options.writer.compression.dictionary_size = 64 * 1024,
+            "--parallel" => {
+                options.writer.parallel = true;
+                parallel = true;
             }
+            _ => path = Some(arg),
         }
     }
 
+    // Compress it
+    let code = std::fs::read(path.context("missing path")?)?;
+    let archive = split_module_with(&code, &options)?.archive;
+
+    // Decompress it
+    let _guard = OffsetTracker::new(&archive.out_buf);
+    let parsed = WasmallMod::parse(&mut ByteCursor(&archive.out_buf))?;
+    let writer = if parallel {
+        parsed.assemble_parallel_verified(&archive)?
+    } else {
+        parsed.assemble_prioritized(&archive)?
+    };
+
     std::io::stdout().write_all(&writer)?;
 
     Ok(())
@@ -0,0 +1,70 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use wasmall::smith::{replay, run, Target};
+
+fn main() -> anyhow::Result<()> {
+    // Parse arguments
+    let mut targets = Target::ALL.to_vec();
+    let mut seed = 0;
+    let mut cases = 1000;
+    let mut fixtures = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/smith"));
+    let mut replay_only = false;
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--target" => {
+                targets = vec![Target::from_name(&args.next().context("missing target")?)?]
+            }
+            "--seed" => seed = args.next().context("missing seed")?.parse()?,
+            "--cases" => cases = args.next().context("missing case count")?.parse()?,
+            "--fixtures" => fixtures = args.next().context("missing fixture directory")?.into(),
+            "--replay" => replay_only = true,
+            _ => anyhow::bail!("unknown argument {arg:?}"),
+        }
+    }
+
+    let mut failed = false;
+
+    // Replay previously persisted failures
+    if fixtures.exists() {
+        for (path, message) in replay(&fixtures)? {
+            println!("{}: {message}", path.display());
+            failed = true;
+        }
+    }
+
+    // Generate new cases
+    if !replay_only {
+        for target in targets {
+            let report = run(target, seed, cases, Some(&fixtures))?;
+
+            for failure in &report.failures {
+                println!(
+                    "{} ({} byte input): {}",
+                    failure.fixture.as_ref().map_or_else(
+                        || target.name().to_string(),
+                        |path| path.display().to_string()
+                    ),
+                    failure.input.len(),
+                    failure.message,
+                );
+            }
+
+            println!(
+                "{}: {} case(s), {} failure(s)",
+                target.name(),
+                report.cases,
+                report.failures.len()
+            );
+            failed |= !report.is_ok();
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,546 @@
+use std::{io::Write, path::PathBuf};
+
+use anyhow::Context;
+use rustc_hash::FxHashMap;
+use wasmall::{
+    chunker::ChunkingOptions,
+    coder::{CompressionOptions, CompressionStats, WasmallArchive, WasmallMod, WasmallModSeg},
+    corpus::canonicalize,
+    diff::{ArchiveDiff, FunctionChangeKind},
+    features::set_feature,
+    incremental::FunctionLayout,
+    link::{link_objects, LinkOptions},
+    splitter::{
+        is_component, split_module_with, SplitOptions, COMPONENT_SECTION_NAMES, CORE_SECTION_NAMES,
+    },
+    store::{store_archive, verify_archive, BlobSource, DirBlobStore},
+    util::{ByteCursor, ByteParse, OffsetTracker, SectionWalker},
+};
+
+const USAGE: &str = "\
+usage:
+    wasmall split <module> -o <index> [--blobs <dir>] [split options]
+    wasmall inspect <index> [--blobs <dir>]
+    wasmall reassemble <index> [--blobs <dir>] [-o <module>]
+    wasmall verify <index> [--blobs <dir>] [--against <module>]
+    wasmall diff <old> <new> [--blobs <dir>] [split options]
+    wasmall link <object>... -o <index> [--blobs <dir>] [link options] [split options]
+
+Blobs are kept in a content-addressed directory, `blobs` by default, which any number of indices
+can share. `diff` accepts both indices and modules, which are split in memory. `link` links
+relocatable objects into a single module and splits it, so relinking after rebuilding one object
+only stores the blobs of the functions and data which changed.
+
+link options:
+    --module <path>, --export <symbol>, --stack-size <bytes>, --global-base <address>
+    --no-export-memory
+
+split options:
+    --enable <feature>, --disable <feature>
+    --no-validate, --normalize, --no-prioritize, --share-sections, --merkle, --parallel
+    --no-compress, --dictionary, --chunk, --no-chunk-data, --symbol-names";
+
+// === Arguments === //
+
+const LINK_OPTIONS: [&str; 5] = [
+    "--module",
+    "--export",
+    "--stack-size",
+    "--global-base",
+    "--no-export-memory",
+];
+
+struct Args {
+    positional: Vec<String>,
+    output: Option<PathBuf>,
+    against: Option<PathBuf>,
+    module: Option<PathBuf>,
+    store: DirBlobStore,
+    options: SplitOptions,
+    link: LinkOptions,
+    has_split_options: bool,
+    has_link_options: bool,
+}
+
+impl Args {
+    fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut me = Self {
+            positional: Vec::new(),
+            output: None,
+            against: None,
+            module: None,
+            store: DirBlobStore::new("blobs"),
+            options: SplitOptions::default(),
+            link: LinkOptions::default(),
+            has_split_options: false,
+            has_link_options: false,
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("missing value for {arg}"))
+            };
+
+            match arg.as_str() {
+                "-o" | "--output" => me.output = Some(value()?.into()),
+                "--against" => me.against = Some(value()?.into()),
+                "--blobs" => me.store = DirBlobStore::new(value()?),
+                "--module" => me.module = Some(value()?.into()),
+                "--export" => me.link.exports.push(value()?),
+                "--stack-size" => me.link.stack_size = parse_u32(&arg, &value()?)?,
+                "--global-base" => me.link.global_base = parse_u32(&arg, &value()?)?,
+                "--no-export-memory" => me.link.export_memory = false,
+                "--enable" | "--disable" => {
+                    let name = value()?;
+                    set_feature(&mut me.options.features, &name, arg == "--enable")?;
+                }
+                "--no-validate" => me.options.validate = false,
+                "--normalize" => me.options.normalize = true,
+                "--no-prioritize" => me.options.prioritize = false,
+                "--share-sections" => me.options.share_sections = true,
+                "--merkle" => me.options.writer.merkle = true,
+                "--parallel" => me.options.writer.parallel = true,
+                "--no-compress" => me.options.writer.compression = CompressionOptions::disabled(),
+                "--dictionary" => me.options.writer.compression.dictionary_size = 64 * 1024,
+                "--chunk" => me.options.chunking = Some(ChunkingOptions::default()),
+                "--no-chunk-data" => me.options.data_chunking = None,
+                "--symbol-names" => me.options.symbol_names = true,
+                _ if arg.starts_with('-') => anyhow::bail!("unknown option {arg}\n\n{USAGE}"),
+                _ => {
+                    me.positional.push(arg);
+                    continue;
+                }
+            }
+
+            if LINK_OPTIONS.contains(&arg.as_str()) {
+                me.has_link_options = true;
+            } else {
+                me.has_split_options |=
+                    !matches!(arg.as_str(), "-o" | "--output" | "--against" | "--blobs");
+            }
+        }
+
+        Ok(me)
+    }
+
+    /// Takes exactly `N` positional arguments.
+    fn expect<const N: usize>(&self, command: &str) -> anyhow::Result<[&str; N]> {
+        let positional = self
+            .positional
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        positional
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("{command} expects {N} path(s)\n\n{USAGE}"))
+    }
+}
+
+fn parse_u32(arg: &str, value: &str) -> anyhow::Result<u32> {
+    value
+        .parse()
+        .with_context(|| format!("invalid value {value:?} for {arg}"))
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let command = args.next().context(USAGE)?;
+    let args = Args::parse(args)?;
+
+    anyhow::ensure!(
+        !args.has_split_options || matches!(command.as_str(), "split" | "diff" | "link"),
+        "{command} does not take split options\n\n{USAGE}"
+    );
+    anyhow::ensure!(
+        !args.has_link_options || command == "link",
+        "{command} does not take link options\n\n{USAGE}"
+    );
+
+    match command.as_str() {
+        "split" => split(&args),
+        "inspect" => inspect(&args),
+        "reassemble" => reassemble(&args),
+        "verify" => verify(&args),
+        "diff" => diff(&args),
+        "link" => link(&args),
+        "help" | "--help" | "-h" => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        _ => anyhow::bail!("unknown command {command:?}\n\n{USAGE}"),
+    }
+}
+
+fn read(path: &str) -> anyhow::Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("failed to read {path:?}"))
+}
+
+// === Commands === //
+
+fn split(args: &Args) -> anyhow::Result<()> {
+    let [path] = args.expect("split")?;
+    let output = args
+        .output
+        .as_ref()
+        .context("split expects an output path (-o)")?;
+
+    let src = read(path)?;
+    let result = split_module_with(&src, &args.options)
+        .with_context(|| format!("failed to split {path:?}"))?;
+    let archive = &result.archive;
+
+    let mut store = args.store.clone();
+    let report = store_archive(&mut store, archive)?;
+
+    std::fs::write(output, &archive.out_buf)
+        .with_context(|| format!("failed to write index to {output:?}"))?;
+
+    println!("module {}", archive.module_hash);
+    println!(
+        "index: {} bytes, stripped {} bytes of custom sections",
+        archive.out_buf.len(),
+        result.bytes_truncated,
+    );
+    println!(
+        "blobs: {} written ({} bytes), {} already stored",
+        report.blobs_written, report.bytes_written, report.blobs_skipped,
+    );
+
+    Ok(())
+}
+
+fn inspect(args: &Args) -> anyhow::Result<()> {
+    let [path] = args.expect("inspect")?;
+    let index = read(path)?;
+    let _guard = OffsetTracker::new(&index);
+    let module = WasmallMod::parse(&mut ByteCursor(&index)).context("failed to parse index")?;
+
+    println!("module hash:    {}", module.module_hash());
+    if let Some(root) = module.merkle_root() {
+        println!("merkle root:    {root}");
+    }
+    if let Some(key_id) = module.key_id() {
+        println!("encrypted with: key ID {key_id:x?}");
+    }
+    if let Some(dictionary) = module.dictionary_hash() {
+        println!("dictionary:     {dictionary}");
+    }
+    println!("index size:     {} bytes", index.len());
+    println!("assembled size: {} bytes", module.assembled_len()?);
+    println!("prioritized:    {} blob(s)", module.priority().count());
+    println!("symbols:        {} name(s)", module.symbols().count());
+    println!();
+
+    // Only indices storing one blob per function body can attribute blobs to functions.
+    let func_imports = FunctionLayout::new(&module)
+        .ok()
+        .map(|layout| layout.func_imports);
+    let symbols = module.symbols_by_segment()?;
+
+    let mut names = &CORE_SECTION_NAMES[..];
+    let mut walker = SectionWalker::default();
+    let mut offset = 0;
+    let mut bodies = 0;
+
+    println!("segment  offset      length  contents");
+
+    for (i, segment) in module.segments().enumerate() {
+        let segment = segment?;
+        let len = segment.out_len();
+        print!("{i:>7}  {offset:#010x}  {len:>8}  ");
+        offset += len;
+
+        let section_name = |id: Option<u8>| match id {
+            None => "header".to_string(),
+            Some(id) => match names.get(id as usize) {
+                Some(name) => format!("{name} section"),
+                None => format!("section {id}"),
+            },
+        };
+
+        let (blob, reloc_values) = match &segment {
+            WasmallModSeg::Verbatim(segment) => {
+                if offset == len && is_component(segment.data()) {
+                    names = &COMPONENT_SECTION_NAMES[..];
+                }
+
+                let mut data = segment.data();
+                let mut sections = Vec::new();
+                while !data.is_empty() {
+                    let (id, _) = walker.take(&mut data)?;
+                    let name = section_name(id);
+                    if sections.last() != Some(&name) {
+                        sections.push(name);
+                    }
+                }
+
+                println!("verbatim: {}", sections.join(", "));
+                continue;
+            }
+            WasmallModSeg::Blob(segment) => (Some(segment), segment.reloc_values()),
+            WasmallModSeg::InlineBlob(segment) => (None, segment.reloc_values()),
+        };
+
+        let section = walker.take_blob(len)?;
+        let mut location = match (section, func_imports) {
+            (Some(10), Some(func_imports)) => {
+                bodies += 1;
+                format!("function {}", func_imports + bodies - 1)
+            }
+            _ => section_name(section),
+        };
+
+        if let Some(symbols) = symbols.get(&(i as u32)) {
+            let names = symbols.iter().map(|symbol| symbol.name).collect::<Vec<_>>();
+            location += &describe_names(&names);
+        }
+
+        let Some(segment) = blob else {
+            println!(
+                "inline blob: {location}, {} relocation value(s)",
+                reloc_values.count(),
+            );
+            continue;
+        };
+
+        let hash = segment.hash();
+        let stored = match args.store.get_blob(hash)? {
+            Some(stored) => format!("{} bytes stored", stored.len()),
+            None => "not stored".to_string(),
+        };
+
+        // Relocation entries live in the blob itself so they can only be counted if it is stored
+        // and can be decrypted.
+        let entries = module
+            .with_blob(segment, &args.store, |blob| Ok(blob.relocations().count()))
+            .map_or("?".to_string(), |count| count.to_string());
+
+        println!(
+            "blob {hash}: {location}, {:?}, {stored}, {entries} relocation(s) over {} value(s)",
+            segment.encoding(),
+            reloc_values.count(),
+        );
+    }
+
+    Ok(())
+}
+
+fn reassemble(args: &Args) -> anyhow::Result<()> {
+    let [path] = args.expect("reassemble")?;
+    let index = read(path)?;
+    let _guard = OffsetTracker::new(&index);
+    let module = WasmallMod::parse(&mut ByteCursor(&index)).context("failed to parse index")?;
+    let assembled = module.assemble_verified(&args.store)?;
+
+    match &args.output {
+        Some(output) => std::fs::write(output, &assembled)
+            .with_context(|| format!("failed to write module to {output:?}"))?,
+        None => std::io::stdout().write_all(&assembled)?,
+    }
+
+    Ok(())
+}
+
+fn verify(args: &Args) -> anyhow::Result<()> {
+    let [path] = args.expect("verify")?;
+    let index = read(path)?;
+    let _guard = OffsetTracker::new(&index);
+    let module = WasmallMod::parse(&mut ByteCursor(&index)).context("failed to parse index")?;
+
+    // Audit every blob up front so all of the problems are reported at once.
+    verify_archive(&module, &args.store)?
+        .into_result()
+        .with_context(|| format!("{path:?} failed verification"))?;
+
+    let assembled = module.assemble_verified(&args.store)?;
+
+    if let Some(against) = &args.against {
+        let src = std::fs::read(against).with_context(|| format!("failed to read {against:?}"))?;
+        anyhow::ensure!(
+            assembled == canonicalize(&src)?,
+            "{path:?} does not reassemble into {against:?}"
+        );
+    }
+
+    println!(
+        "ok: module {} ({} bytes)",
+        module.module_hash(),
+        assembled.len()
+    );
+
+    Ok(())
+}
+
+fn diff(args: &Args) -> anyhow::Result<()> {
+    let [old_path, new_path] = args.expect("diff")?;
+    let old = load_archive(args, old_path)?;
+    let new = load_archive(args, new_path)?;
+    let diff = ArchiveDiff::new(&old, &new)?;
+
+    for blob in &diff.removed {
+        let names = describe_names(&blob.names);
+        println!("- {} ({} bytes){names}", blob.hash, blob.stored_len);
+    }
+
+    for blob in &diff.added {
+        let names = describe_names(&blob.names);
+        println!("+ {} ({} bytes){names}", blob.hash, blob.stored_len);
+    }
+
+    // Functions whose blobs are still cached aren't worth listing.
+    for change in diff.functions.iter().flatten() {
+        let kind = match change.kind {
+            FunctionChangeKind::Code => "changed",
+            FunctionChangeKind::Added => "added",
+            FunctionChangeKind::Removed => "removed",
+            _ => continue,
+        };
+
+        let names = describe_names(&change.names);
+        println!("{kind} function {}{names}", change.index);
+    }
+
+    println!(
+        "shared: {} blob(s) ({} bytes), added: {} ({} bytes), removed: {} ({} bytes)",
+        diff.shared.len(),
+        diff.shared_bytes(),
+        diff.added.len(),
+        diff.added_bytes(),
+        diff.removed.len(),
+        diff.removed_bytes(),
+    );
+
+    println!(
+        "transfer with old cached: {} / {} bytes ({:.1}% cached)",
+        diff.transfer_bytes(),
+        diff.full_transfer_bytes(),
+        diff.cache_hit_ratio() * 100.,
+    );
+
+    if let Some(summary) = diff.function_summary() {
+        println!(
+            "functions: {} unchanged, {} relocations only, {} shifted, {} changed, {} added, {} \
+             removed",
+            summary.unchanged,
+            summary.relocations_only,
+            summary.shifted,
+            summary.code,
+            summary.added,
+            summary.removed,
+        );
+    }
+
+    Ok(())
+}
+
+fn link(args: &Args) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !args.positional.is_empty(),
+        "link expects at least one object\n\n{USAGE}"
+    );
+    let output = args
+        .output
+        .as_ref()
+        .context("link expects an output path (-o)")?;
+
+    let objects = args
+        .positional
+        .iter()
+        .map(|path| read(path))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let objects = objects.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+    let options = LinkOptions {
+        split: args.options.clone(),
+        ..args.link.clone()
+    };
+    let result = link_objects(&objects, &options)?;
+    let archive = &result.archive;
+
+    let mut store = args.store.clone();
+    let report = store_archive(&mut store, archive)?;
+
+    std::fs::write(output, &archive.out_buf)
+        .with_context(|| format!("failed to write index to {output:?}"))?;
+
+    if let Some(module) = &args.module {
+        std::fs::write(module, &result.module)
+            .with_context(|| format!("failed to write module to {module:?}"))?;
+    }
+
+    println!(
+        "module {} ({} bytes)",
+        archive.module_hash,
+        result.module.len()
+    );
+    println!(
+        "index: {} bytes, linked {} object(s)",
+        archive.out_buf.len(),
+        objects.len(),
+    );
+    println!(
+        "blobs: {} written ({} bytes), {} already stored",
+        report.blobs_written, report.bytes_written, report.blobs_skipped,
+    );
+
+    Ok(())
+}
+
+/// Formats the names of a blob's symbols for appending to a description of it.
+fn describe_names(names: &[impl AsRef<str>]) -> String {
+    if names.is_empty() {
+        return String::new();
+    }
+
+    let names = names.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+    format!(" [{}]", names.join(", "))
+}
+
+/// Loads an index along with its blobs, or splits a module in memory.
+fn load_archive(args: &Args, path: &str) -> anyhow::Result<WasmallArchive> {
+    let data = read(path)?;
+
+    if data.starts_with(b"\0asm") {
+        return Ok(split_module_with(&data, &args.options)
+            .with_context(|| format!("failed to split {path:?}"))?
+            .archive);
+    }
+
+    let _guard = OffsetTracker::new(&data);
+    let (module_hash, merkle_root, blobs) = {
+        let module = WasmallMod::parse(&mut ByteCursor(&data))
+            .with_context(|| format!("failed to parse index {path:?}"))?;
+
+        let hashes = module.blob_hashes().collect::<anyhow::Result<Vec<_>>>()?;
+        (module.module_hash(), module.merkle_root(), hashes)
+    };
+
+    let mut blob_buf = Vec::new();
+    let mut hashes = FxHashMap::default();
+
+    for hash in blobs {
+        if hashes.contains_key(&hash) {
+            continue;
+        }
+
+        let blob = args
+            .store
+            .get_blob(hash)?
+            .with_context(|| format!("{path:?} needs blob {hash}, which is not stored"))?;
+
+        let start = blob_buf.len();
+        blob_buf.extend_from_slice(&blob);
+        hashes.insert(hash, start..blob_buf.len());
+    }
+
+    Ok(WasmallArchive {
+        module_hash,
+        merkle_root,
+        out_buf: data,
+        blob_buf,
+        hashes,
+        compression_stats: CompressionStats::default(),
+    })
+}
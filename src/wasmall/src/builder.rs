@@ -0,0 +1,203 @@
+//! Helpers for emitting wasm binaries one section at a time.
+//!
+//! Section sizes are always encoded minimally so that builders produce the same bytes a
+//! round-trip through the splitter does. Sections whose size isn't known up-front are buffered by
+//! a [`SectionBuilder`] until they're finished.
+
+use anyhow::Context;
+
+use crate::util::{len_of, BufWriter, Leb128WriteExt};
+
+/// The magic number and version every core module begins with.
+pub const CORE_MODULE_HEADER: [u8; 8] = *b"\0asm\x01\0\0\0";
+
+/// The ID of custom sections, which are named by a prefix of their contents.
+pub const CUSTOM_SECTION_ID: u8 = 0;
+
+// === Section Writing === //
+
+pub trait SectionWriteExt: BufWriter {
+    /// Writes the ID and size of a section whose `len` bytes of contents will be written next.
+    fn write_section_header(&mut self, id: u8, len: usize) -> anyhow::Result<()> {
+        self.push(id);
+        self.write_var_u32(u32::try_from(len).context("section is too big")?);
+        Ok(())
+    }
+
+    fn write_section(&mut self, id: u8, data: &[u8]) -> anyhow::Result<()> {
+        self.write_section_header(id, data.len())?;
+        self.extend(data);
+        Ok(())
+    }
+
+    fn write_custom_section(&mut self, name: &str, data: &[u8]) -> anyhow::Result<()> {
+        let name_len = u32::try_from(name.len()).context("custom section name is too long")?;
+        let header_len = len_of(|c| c.write_var_u32(name_len)) + name.len();
+
+        self.write_section_header(CUSTOM_SECTION_ID, header_len + data.len())?;
+        self.write_var_u32(name_len);
+        self.extend(name.as_bytes());
+        self.extend(data);
+        Ok(())
+    }
+}
+
+impl<W: ?Sized + BufWriter> SectionWriteExt for W {}
+
+// === SectionBuilder === //
+
+/// Buffers the contents of a section until it is finished so that its size can be written
+/// minimally. Sections made of a vector of items can be written with [`item`](Self::item), in
+/// which case the item count is written ahead of the items. Such sections should be started with
+/// [`vec`](Self::vec) so that the count is written even if no items are.
+#[derive(Debug, Clone)]
+pub struct SectionBuilder {
+    id: u8,
+    name: Option<String>,
+    items: Option<u32>,
+    data: Vec<u8>,
+}
+
+impl SectionBuilder {
+    pub fn new(id: u8) -> Self {
+        Self {
+            id,
+            name: None,
+            items: None,
+            data: Vec::new(),
+        }
+    }
+
+    /// Starts a section made of a vector of items, whose item count is always written.
+    pub fn vec(id: u8) -> Self {
+        Self {
+            items: Some(0),
+            ..Self::new(id)
+        }
+    }
+
+    pub fn custom(name: impl Into<String>) -> Self {
+        Self {
+            name: Some(name.into()),
+            ..Self::new(CUSTOM_SECTION_ID)
+        }
+    }
+
+    /// The section's contents, excluding its custom section name and item count.
+    pub fn data(&mut self) -> &mut Vec<u8> {
+        &mut self.data
+    }
+
+    /// Starts a new item, returning the buffer it should be written into.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the section has more than `u32::MAX` items.
+    pub fn item(&mut self) -> &mut Vec<u8> {
+        let items = self.items.get_or_insert(0);
+        *items = items.checked_add(1).expect("section has too many items");
+        &mut self.data
+    }
+
+    /// The number of items written so far.
+    pub fn item_count(&self) -> u32 {
+        self.items.unwrap_or(0)
+    }
+
+    /// Writes the section out.
+    pub fn finish(self, out: &mut (impl ?Sized + BufWriter)) -> anyhow::Result<()> {
+        let mut contents = Vec::new();
+        if let Some(items) = self.items {
+            contents.write_var_u32(items);
+        }
+        contents.extend_from_slice(&self.data);
+
+        match &self.name {
+            Some(name) => out.write_custom_section(name, &contents),
+            None => out.write_section(self.id, &contents),
+        }
+    }
+}
+
+// === ModuleBuilder === //
+
+/// Writes a module or component section by section.
+#[derive(Debug)]
+pub struct ModuleBuilder<W> {
+    out: W,
+}
+
+impl<W: BufWriter> ModuleBuilder<W> {
+    /// Starts a core module.
+    pub fn new(out: W) -> Self {
+        Self::with_header(out, &CORE_MODULE_HEADER)
+    }
+
+    /// Starts a binary with an arbitrary header, such as that of a component.
+    pub fn with_header(mut out: W, header: &[u8; 8]) -> Self {
+        out.extend(header);
+        Self { out }
+    }
+
+    pub fn section(&mut self, id: u8, data: &[u8]) -> anyhow::Result<&mut Self> {
+        self.out.write_section(id, data)?;
+        Ok(self)
+    }
+
+    pub fn custom_section(&mut self, name: &str, data: &[u8]) -> anyhow::Result<&mut Self> {
+        self.out.write_custom_section(name, data)?;
+        Ok(self)
+    }
+
+    pub fn build_section(&mut self, section: SectionBuilder) -> anyhow::Result<&mut Self> {
+        section.finish(&mut self.out)?;
+        Ok(self)
+    }
+
+    /// The underlying writer, for writing sections piecewise with
+    /// [`write_section_header`](SectionWriteExt::write_section_header).
+    pub fn writer(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    pub fn finish(self) -> W {
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finished(section: SectionBuilder) -> Vec<u8> {
+        let mut out = Vec::new();
+        section.finish(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn vec_section_writes_count() {
+        assert_eq!(finished(SectionBuilder::vec(3)), [3, 1, 0]);
+
+        let mut section = SectionBuilder::vec(3);
+        section.item().push(0);
+        section.item().push(1);
+        assert_eq!(finished(section), [3, 3, 2, 0, 1]);
+    }
+
+    #[test]
+    fn raw_section_has_no_count() {
+        assert_eq!(finished(SectionBuilder::new(12)), [12, 0]);
+
+        let mut section = SectionBuilder::new(12);
+        section.data().push(5);
+        assert_eq!(finished(section), [12, 1, 5]);
+    }
+
+    #[test]
+    fn custom_section_is_named() {
+        let mut section = SectionBuilder::custom("hi");
+        section.data().push(5);
+        assert_eq!(finished(section), [0, 4, 2, b'h', b'i', 5]);
+    }
+}
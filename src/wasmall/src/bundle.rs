@@ -0,0 +1,132 @@
+//! Bundles of modules sharing a single blob store.
+//!
+//! Modules built by the same toolchain tend to declare nearly identical type, import, and global
+//! sections, which would otherwise be stored once per module. Bundles split their modules with
+//! [`SplitOptions::share_sections`] so those sections become blobs just like function bodies, at
+//! which point byte-identical sections and functions are only stored once across the entire bundle.
+//! Each module keeps its own index and reassembles to exactly what splitting it alone would have
+//! produced. Runs of bytes shared between blobs which differ elsewhere can be deduplicated further
+//! with a [`RunStore`](crate::runs::RunStore).
+
+use std::{borrow::Cow, ops::Range};
+
+use anyhow::Context;
+use blake3::Hash;
+use rustc_hash::FxHashMap;
+
+use crate::{
+    coder::{WasmallArchive, WasmallMod},
+    splitter::{split_module_with, SplitOptions},
+    store::BlobSource,
+    util::{ByteCursor, ByteParse},
+};
+
+// === WasmallBundle === //
+
+#[derive(Debug, Clone)]
+pub struct BundleModule {
+    /// The hash of the fully assembled module.
+    pub module_hash: Hash,
+    pub index: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+pub struct WasmallBundle {
+    /// The modules of the bundle, in the order they were added.
+    pub modules: Vec<BundleModule>,
+    pub blob_buf: Vec<u8>,
+    pub hashes: FxHashMap<Hash, Range<usize>>,
+}
+
+impl WasmallBundle {
+    /// Adds a module's archive to the bundle, storing only the blobs the bundle doesn't have yet.
+    /// Returns the number of blob bytes which were already present.
+    pub fn push(&mut self, archive: WasmallArchive) -> usize {
+        let mut bytes_shared = 0;
+
+        for (hash, range) in archive.hashes {
+            if self.hashes.contains_key(&hash) {
+                bytes_shared += range.len();
+                continue;
+            }
+
+            let start = self.blob_buf.len();
+            self.blob_buf.extend_from_slice(&archive.blob_buf[range]);
+            self.hashes.insert(hash, start..self.blob_buf.len());
+        }
+
+        self.modules.push(BundleModule {
+            module_hash: archive.module_hash,
+            index: archive.out_buf,
+        });
+
+        bytes_shared
+    }
+
+    /// Parses the index of the `i`th module.
+    pub fn module(&self, i: usize) -> anyhow::Result<WasmallMod<'_>> {
+        let module = self
+            .modules
+            .get(i)
+            .with_context(|| format!("bundle has no module {i}"))?;
+
+        WasmallMod::parse(&mut ByteCursor(&module.index))
+            .with_context(|| format!("failed to parse the index of module {i}"))
+    }
+
+    /// Assembles the `i`th module, checking it against its recorded hash. Modules with encrypted
+    /// blobs must be unlocked through [`module`](Self::module) instead.
+    pub fn assemble(&self, i: usize) -> anyhow::Result<Vec<u8>> {
+        self.module(i)?.assemble_verified(self)
+    }
+}
+
+impl BlobSource for WasmallBundle {
+    fn get_blob(&self, hash: Hash) -> anyhow::Result<Option<Cow<'_, [u8]>>> {
+        Ok(self
+            .hashes
+            .get(&hash)
+            .map(|range| Cow::Borrowed(&self.blob_buf[range.clone()])))
+    }
+}
+
+// === Splitting === //
+
+#[derive(Debug)]
+pub struct SplitBundleResult {
+    pub bundle: WasmallBundle,
+    pub bytes_truncated: usize,
+
+    /// The number of blob bytes which didn't have to be stored because another module of the
+    /// bundle already stored them.
+    pub bytes_shared: usize,
+}
+
+/// Splits every module into a single bundle. Section sharing is enabled regardless of `options`.
+pub fn split_bundle(
+    modules: &[&[u8]],
+    options: &SplitOptions,
+) -> anyhow::Result<SplitBundleResult> {
+    let options = SplitOptions {
+        share_sections: true,
+        ..options.clone()
+    };
+
+    let mut bundle = WasmallBundle::default();
+    let mut bytes_truncated = 0;
+    let mut bytes_shared = 0;
+
+    for (i, src) in modules.iter().enumerate() {
+        let result = split_module_with(src, &options)
+            .with_context(|| format!("failed to split module {i}"))?;
+
+        bytes_truncated += result.bytes_truncated;
+        bytes_shared += bundle.push(result.archive);
+    }
+
+    Ok(SplitBundleResult {
+        bundle,
+        bytes_truncated,
+        bytes_shared,
+    })
+}
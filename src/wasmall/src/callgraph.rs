@@ -0,0 +1,145 @@
+//! An approximate call graph used to decide which function bodies clients should fetch first.
+//!
+//! Clients typically start executing a module's start function or one of its exports right after
+//! instantiating it, so the bodies reachable from those are needed first. Reachability is
+//! approximated by following direct calls and `ref.func` references; indirect calls could target
+//! any function placed in a table, so functions in element segments are treated as reachable once
+//! everything reachable directly has been visited.
+//!
+//! Relocatable objects have neither a start function nor exports so their entry points are taken
+//! from the `linking` section instead: init functions and every defined function with a symbol
+//! visible to other objects.
+
+use std::collections::VecDeque;
+
+use wasmparser::{
+    ElementItems, ExternalKind, Operator, Parser, Payload, SymbolFlags, SymbolInfo, TypeRef,
+};
+
+use crate::{reloc::LinkingSection, splitter::is_component};
+
+/// Determines the order in which a module's function bodies should be fetched, as indices into
+/// its code section. Bodies which are unreachable from the module's entry points are omitted.
+pub fn startup_order(src: &[u8]) -> anyhow::Result<Vec<u32>> {
+    anyhow::ensure!(
+        !is_component(src),
+        "call graphs can only be derived for core modules"
+    );
+
+    let mut func_imports = 0;
+    let mut roots = Vec::new();
+    let mut table_funcs = Vec::new();
+    let mut callees = Vec::<Vec<u32>>::new();
+
+    for payload in Parser::new(0).parse_all(src) {
+        match payload? {
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    if matches!(import?.ty, TypeRef::Func(_)) {
+                        func_imports += 1;
+                    }
+                }
+            }
+            Payload::StartSection { func, .. } => roots.insert(0, func),
+            Payload::ExportSection(reader) => {
+                for export in reader {
+                    let export = export?;
+                    if export.kind == ExternalKind::Func {
+                        roots.push(export.index);
+                    }
+                }
+            }
+            Payload::ElementSection(reader) => {
+                for element in reader {
+                    match element?.items {
+                        ElementItems::Functions(funcs) => {
+                            for func in funcs {
+                                table_funcs.push(func?);
+                            }
+                        }
+                        ElementItems::Expressions(_, exprs) => {
+                            for expr in exprs {
+                                for op in expr?.get_operators_reader() {
+                                    if let Operator::RefFunc { function_index } = op? {
+                                        table_funcs.push(function_index);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            Payload::CustomSection(reader) if reader.name() == "linking" => {
+                let linking = LinkingSection::parse(reader.data(), reader.data_offset())?;
+
+                for symbol in &linking.symbols {
+                    let SymbolInfo::Func { flags, index, .. } = *symbol else {
+                        continue;
+                    };
+
+                    let visible = flags.contains(SymbolFlags::EXPORTED)
+                        || !flags.intersects(
+                            SymbolFlags::BINDING_LOCAL | SymbolFlags::VISIBILITY_HIDDEN,
+                        );
+
+                    if visible && !flags.contains(SymbolFlags::UNDEFINED) {
+                        roots.push(index);
+                    }
+                }
+
+                // Init functions run before anything else.
+                for init in linking.init_funcs.iter().rev() {
+                    if let Some(SymbolInfo::Func { index, .. }) =
+                        linking.symbols.get(init.symbol_index as usize)
+                    {
+                        roots.insert(0, *index);
+                    }
+                }
+            }
+            Payload::CodeSectionEntry(mut body) => {
+                let mut body_callees = Vec::new();
+
+                // Offsets are read as 64-bit regardless of memory types since only calls matter.
+                body.allow_memarg64(true);
+
+                for op in body.get_operators_reader()? {
+                    match op? {
+                        Operator::Call { function_index }
+                        | Operator::ReturnCall { function_index }
+                        | Operator::RefFunc { function_index } => body_callees.push(function_index),
+                        _ => {}
+                    }
+                }
+
+                callees.push(body_callees);
+            }
+            _ => {}
+        }
+    }
+
+    // Traverse the graph breadth-first so that functions closer to the entry points come first.
+    let mut visited = vec![false; callees.len()];
+    let mut order = Vec::new();
+    let mut queue = VecDeque::new();
+
+    for root_set in [roots, table_funcs] {
+        queue.extend(root_set);
+
+        while let Some(func) = queue.pop_front() {
+            let Some(body) = func.checked_sub(func_imports) else {
+                continue;
+            };
+
+            let Some(seen) = visited.get_mut(body as usize) else {
+                continue;
+            };
+
+            if !std::mem::replace(seen, true) {
+                order.push(body);
+                queue.extend(&callees[body as usize]);
+            }
+        }
+    }
+
+    Ok(order)
+}
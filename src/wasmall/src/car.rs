@@ -0,0 +1,331 @@
+//! Import and export of [CARv1](https://ipld.io/specs/transport/car/carv1/) archives so blobs and
+//! indices can be served by existing content-addressed infrastructure.
+//!
+//! Every block is identified by a CIDv1 using the `raw` codec and a `blake3` multihash. Since both
+//! blobs and indices are addressed by their blake3 hash, a blob's CID is derived directly from its
+//! hash and gateways can serve it unmodified. The archive's roots are the indices it contains.
+
+use std::{borrow::Cow, io::Write};
+
+use anyhow::Context;
+use blake3::Hash;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::{
+    coder::WasmallArchive,
+    store::BlobSource,
+    util::{ByteCursor, Leb128WriteExt},
+};
+
+// === CIDs === //
+
+const CID_VERSION: u8 = 1;
+
+/// The multicodec for raw binary data.
+const CODEC_RAW: u8 = 0x55;
+
+/// The multicodec for a 32 byte blake3 multihash.
+const MULTIHASH_BLAKE3: u8 = 0x1e;
+
+const CID_LEN: usize = 4 + blake3::OUT_LEN;
+
+/// Encodes the binary CID of the block with the specified hash.
+pub fn cid_for_hash(hash: Hash) -> [u8; CID_LEN] {
+    let mut cid = [0; CID_LEN];
+    cid[..4].copy_from_slice(&[
+        CID_VERSION,
+        CODEC_RAW,
+        MULTIHASH_BLAKE3,
+        blake3::OUT_LEN as u8,
+    ]);
+    cid[4..].copy_from_slice(hash.as_bytes());
+    cid
+}
+
+/// Decodes a binary CID produced by [`cid_for_hash`]. CIDs using other codecs or hash functions are
+/// rejected.
+pub fn hash_for_cid(cid: &[u8]) -> anyhow::Result<Hash> {
+    anyhow::ensure!(cid.len() == CID_LEN, "unsupported CID length {}", cid.len());
+    anyhow::ensure!(cid[0] == CID_VERSION, "unsupported CID version {}", cid[0]);
+    anyhow::ensure!(cid[1] == CODEC_RAW, "unsupported CID codec {:#x}", cid[1]);
+    anyhow::ensure!(
+        cid[2] == MULTIHASH_BLAKE3 && cid[3] == blake3::OUT_LEN as u8,
+        "unsupported CID multihash {:#x}",
+        cid[2],
+    );
+
+    Ok(Hash::from_bytes(cid[4..].try_into().unwrap()))
+}
+
+/// Formats the CID of the block with the specified hash in its canonical base32 string form, as
+/// accepted by IPFS gateways.
+pub fn cid_string(hash: Hash) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+    let mut out = String::from("b");
+    let mut acc = 0u32;
+    let mut bits = 0;
+
+    for byte in cid_for_hash(hash) {
+        acc = (acc << 8) | byte as u32;
+        bits += 8;
+
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ALPHABET[((acc >> bits) & 31) as usize] as char);
+        }
+    }
+
+    if bits > 0 {
+        out.push(ALPHABET[((acc << (5 - bits)) & 31) as usize] as char);
+    }
+
+    out
+}
+
+// === CBOR === //
+
+// The CAR header is a tiny DAG-CBOR map so we encode and decode it by hand.
+
+fn write_cbor_head(out: &mut Vec<u8>, major: u8, arg: u64) {
+    let major = major << 5;
+
+    match arg {
+        0..=23 => out.push(major | arg as u8),
+        24..=0xFF => out.extend_from_slice(&[major | 24, arg as u8]),
+        0x100..=0xFFFF => {
+            out.push(major | 25);
+            out.extend_from_slice(&(arg as u16).to_be_bytes());
+        }
+        0x10000..=0xFFFF_FFFF => {
+            out.push(major | 26);
+            out.extend_from_slice(&(arg as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&arg.to_be_bytes());
+        }
+    }
+}
+
+fn read_cbor_head(buf: &mut ByteCursor<'_>) -> anyhow::Result<(u8, u64)> {
+    let initial = buf.read_u8()?;
+    let major = initial >> 5;
+
+    let arg = match initial & 31 {
+        arg @ 0..=23 => arg as u64,
+        24 => buf.read_u8()? as u64,
+        25 => u16::from_be_bytes(buf.consume_arr()?) as u64,
+        26 => u32::from_be_bytes(buf.consume_arr()?) as u64,
+        27 => u64::from_be_bytes(buf.consume_arr()?),
+        info => anyhow::bail!("unsupported CBOR additional info {info}"),
+    };
+
+    Ok((major, arg))
+}
+
+fn read_cbor_bytes<'a>(buf: &mut ByteCursor<'a>, expected_major: u8) -> anyhow::Result<&'a [u8]> {
+    let (major, len) = read_cbor_head(buf)?;
+    anyhow::ensure!(
+        major == expected_major,
+        "unexpected CBOR major type {major}"
+    );
+    buf.consume(usize::try_from(len)?)
+}
+
+fn encode_header(roots: &[Hash]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_cbor_head(&mut out, 5, 2);
+
+    // DAG-CBOR sorts map keys by length first so "roots" comes before "version".
+    write_cbor_head(&mut out, 3, 5);
+    out.extend_from_slice(b"roots");
+    write_cbor_head(&mut out, 4, roots.len() as u64);
+
+    for &root in roots {
+        // CIDs are tag 42 wrapping the binary CID prefixed by the identity multibase.
+        write_cbor_head(&mut out, 6, 42);
+        write_cbor_head(&mut out, 2, CID_LEN as u64 + 1);
+        out.push(0);
+        out.extend_from_slice(&cid_for_hash(root));
+    }
+
+    write_cbor_head(&mut out, 3, 7);
+    out.extend_from_slice(b"version");
+    write_cbor_head(&mut out, 0, 1);
+
+    out
+}
+
+fn decode_header(buf: &mut ByteCursor<'_>) -> anyhow::Result<Vec<Hash>> {
+    let (major, entries) = read_cbor_head(buf)?;
+    anyhow::ensure!(major == 5, "CAR header is not a map");
+
+    let mut roots = None;
+    let mut version = None;
+
+    for _ in 0..entries {
+        match read_cbor_bytes(buf, 3)? {
+            b"roots" => {
+                let (major, count) = read_cbor_head(buf)?;
+                anyhow::ensure!(major == 4, "CAR roots are not an array");
+
+                let mut list = Vec::new();
+                for _ in 0..count {
+                    anyhow::ensure!(read_cbor_head(buf)? == (6, 42), "CAR root is not a CID");
+
+                    let cid = read_cbor_bytes(buf, 2)?;
+                    anyhow::ensure!(cid.first() == Some(&0), "CAR root has an unknown multibase");
+                    list.push(hash_for_cid(&cid[1..])?);
+                }
+                roots = Some(list);
+            }
+            b"version" => {
+                let (major, value) = read_cbor_head(buf)?;
+                anyhow::ensure!(major == 0, "CAR version is not an integer");
+                version = Some(value);
+            }
+            key => anyhow::bail!("unknown CAR header key {:?}", String::from_utf8_lossy(key)),
+        }
+    }
+
+    anyhow::ensure!(version == Some(1), "unsupported CAR version {version:?}");
+
+    roots.context("CAR header is missing its roots")
+}
+
+// === Export === //
+
+/// Writes a CAR archive whose roots are the specified indices and whose remaining blocks are the
+/// specified blobs. Duplicate blocks are only written once.
+pub fn write_car<'a>(
+    out: &mut impl Write,
+    indices: &[&'a [u8]],
+    blobs: impl IntoIterator<Item = (Hash, &'a [u8])>,
+) -> anyhow::Result<()> {
+    let roots = indices
+        .iter()
+        .map(|index| blake3::hash(index))
+        .collect::<Vec<_>>();
+
+    let mut buf = Vec::new();
+    let header = encode_header(&roots);
+    buf.write_var_u64(header.len() as u64);
+    buf.extend_from_slice(&header);
+    out.write_all(&buf)?;
+
+    let mut seen = FxHashSet::default();
+    let blocks = roots
+        .iter()
+        .copied()
+        .zip(indices.iter().copied())
+        .chain(blobs);
+
+    for (hash, data) in blocks {
+        if !seen.insert(hash) {
+            continue;
+        }
+
+        buf.clear();
+        buf.write_var_u64((CID_LEN + data.len()) as u64);
+        buf.extend_from_slice(&cid_for_hash(hash));
+        out.write_all(&buf)?;
+        out.write_all(data)?;
+    }
+
+    Ok(())
+}
+
+/// Writes an archive's index and all of its blobs as a CAR archive.
+pub fn export_archive(out: &mut impl Write, archive: &WasmallArchive) -> anyhow::Result<()> {
+    write_car(
+        out,
+        &[&archive.out_buf],
+        archive
+            .hashes
+            .iter()
+            .map(|(&hash, range)| (hash, &archive.blob_buf[range.clone()])),
+    )
+}
+
+// === Import === //
+
+/// A parsed CAR archive. Every block is verified against its CID while parsing.
+#[derive(Debug, Clone)]
+pub struct CarArchive<'a> {
+    roots: Vec<Hash>,
+    blocks: FxHashMap<Hash, &'a [u8]>,
+}
+
+impl<'a> CarArchive<'a> {
+    pub fn parse(data: &'a [u8]) -> anyhow::Result<Self> {
+        let mut cursor = ByteCursor(data);
+
+        let header_len = cursor
+            .read_var_u64()
+            .context("failed to read CAR header length")?;
+        let mut header = ByteCursor(cursor.consume(usize::try_from(header_len)?)?);
+        let roots = decode_header(&mut header).context("failed to parse CAR header")?;
+        anyhow::ensure!(header.at_eof(), "trailing bytes after CAR header");
+
+        let mut blocks = FxHashMap::default();
+
+        while !cursor.at_eof() {
+            let len = cursor
+                .read_var_u64()
+                .context("failed to read CAR block length")?;
+            let block = cursor
+                .consume(usize::try_from(len)?)
+                .context("CAR block is truncated")?;
+
+            anyhow::ensure!(
+                block.len() >= CID_LEN,
+                "CAR block is too short to contain its CID"
+            );
+            let (cid, data) = block.split_at(CID_LEN);
+            let hash = hash_for_cid(cid)?;
+
+            let actual_hash = blake3::hash(data);
+            anyhow::ensure!(
+                actual_hash == hash,
+                "CAR block {} is corrupted; got hash {actual_hash}",
+                cid_string(hash),
+            );
+
+            blocks.insert(hash, data);
+        }
+
+        for root in &roots {
+            anyhow::ensure!(
+                blocks.contains_key(root),
+                "CAR archive is missing its root {}",
+                cid_string(*root),
+            );
+        }
+
+        Ok(Self { roots, blocks })
+    }
+
+    pub fn roots(&self) -> &[Hash] {
+        &self.roots
+    }
+
+    /// Iterates over the index stored at each root.
+    pub fn indices(&self) -> impl Iterator<Item = &'a [u8]> + '_ {
+        self.roots.iter().map(|root| self.blocks[root])
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = (Hash, &'a [u8])> + '_ {
+        self.blocks.iter().map(|(&hash, &data)| (hash, data))
+    }
+
+    pub fn get(&self, hash: Hash) -> Option<&'a [u8]> {
+        self.blocks.get(&hash).copied()
+    }
+}
+
+impl BlobSource for CarArchive<'_> {
+    fn get_blob(&self, hash: Hash) -> anyhow::Result<Option<Cow<'_, [u8]>>> {
+        Ok(self.get(hash).map(Cow::Borrowed))
+    }
+}
@@ -0,0 +1,150 @@
+//! Content-defined chunking for modules whose structure can't be recovered.
+//!
+//! Modules built without relocation sections can't be split into relocatable function bodies, so
+//! the splitter can instead cut their code and data sections into chunks at boundaries chosen by
+//! their contents using [FastCDC]. An edit only moves the boundaries near it, so most chunks of two
+//! similar builds still deduplicate, albeit far less reliably than relocated function bodies.
+//!
+//! [FastCDC]: https://www.usenix.org/conference/atc16/technical-sessions/presentation/xia
+
+use std::ops::Range;
+
+// === Gear Table === //
+
+/// The values rolled into the fingerprint for each byte, generated with SplitMix64. Chunk
+/// boundaries, and therefore blob hashes, depend on these so they must never change.
+const GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut state = 0x6A09_E667_F3BC_C908u64;
+    let mut i = 0;
+
+    while i < table.len() {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+
+        i += 1;
+    }
+
+    table
+};
+
+/// A mask of the `bits` most significant bits of the fingerprint, which depend on the most bytes.
+fn high_mask(bits: u32) -> u64 {
+    match bits {
+        0 => 0,
+        bits => u64::MAX << (64 - bits.min(64)),
+    }
+}
+
+// === ChunkingOptions === //
+
+/// Options controlling where content-defined chunk boundaries fall.
+#[derive(Debug, Clone)]
+pub struct ChunkingOptions {
+    /// Chunks are never shorter than this, except for the last chunk of the input.
+    pub min_size: usize,
+
+    /// The size chunks are normalized towards. Boundaries are harder to find before this point
+    /// and easier after it.
+    pub avg_size: usize,
+
+    /// Chunks are cut at this size if no boundary was found.
+    pub max_size: usize,
+}
+
+impl Default for ChunkingOptions {
+    fn default() -> Self {
+        Self {
+            min_size: 1024,
+            avg_size: 4096,
+            max_size: 16384,
+        }
+    }
+}
+
+impl ChunkingOptions {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            0 < self.min_size && self.min_size <= self.avg_size && self.avg_size <= self.max_size,
+            "chunk sizes must satisfy 0 < min ({}) <= average ({}) <= max ({})",
+            self.min_size,
+            self.avg_size,
+            self.max_size,
+        );
+
+        Ok(())
+    }
+
+    /// Determines the length of the chunk at the start of `data`, which is only zero if `data` is
+    /// empty. The options must be [valid](Self::validate).
+    pub fn next_chunk_len(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min_size {
+            return data.len();
+        }
+
+        let bits = self.avg_size.ilog2();
+        let mask_small = high_mask(bits + 1);
+        let mask_large = high_mask(bits.saturating_sub(1));
+
+        let end = data.len().min(self.max_size);
+        let normal = self.avg_size.min(end);
+        let mut fingerprint = 0u64;
+
+        for (i, &byte) in data.iter().enumerate().take(end).skip(self.min_size) {
+            fingerprint = (fingerprint << 1).wrapping_add(GEAR[byte as usize]);
+
+            let mask = if i < normal { mask_small } else { mask_large };
+            if fingerprint & mask == 0 {
+                return i + 1;
+            }
+        }
+
+        end
+    }
+
+    /// Splits `data` into consecutive chunks covering all of it. The options must be
+    /// [valid](Self::validate).
+    pub fn chunks<'a>(&'a self, data: &'a [u8]) -> impl Iterator<Item = Range<usize>> + 'a {
+        let mut start = 0;
+
+        std::iter::from_fn(move || {
+            (start < data.len()).then(|| {
+                let chunk = start..start + self.next_chunk_len(&data[start..]);
+                start = chunk.end;
+                chunk
+            })
+        })
+    }
+
+    /// Like [`chunks`](Self::chunks) but never ends a chunk inside one of `spans`, which must be
+    /// sorted and disjoint. Boundaries which would split a span are moved to its end, so chunks
+    /// holding spans may exceed the maximum size.
+    pub fn chunks_avoiding(
+        &self,
+        data: &[u8],
+        spans: impl IntoIterator<Item = Range<usize>>,
+    ) -> Vec<Range<usize>> {
+        let mut spans = spans.into_iter().peekable();
+        let mut chunks = Vec::new();
+        let mut start = 0;
+
+        for chunk in self.chunks(data) {
+            let mut end = chunk.end;
+            while let Some(span) = spans.next_if(|span| span.start < end) {
+                end = end.max(span.end);
+            }
+
+            // The chunks this one swallowed are skipped.
+            if end > start {
+                chunks.push(start..end);
+                start = end;
+            }
+        }
+
+        chunks
+    }
+}
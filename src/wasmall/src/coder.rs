@@ -1,23 +1,48 @@
-use std::{collections::hash_map, ops::Range};
+use std::{
+    borrow::Cow,
+    collections::hash_map,
+    fmt,
+    ops::Range,
+    sync::{Arc, OnceLock},
+};
 
 use anyhow::Context;
-use blake3::{hash, Hash};
-use rustc_hash::FxHashMap;
+use blake3::{hash, Hash, Hasher};
+#[cfg(feature = "parallel")]
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
 
+#[cfg(feature = "encryption")]
+use crate::crypt::{BlobCipher, KeyProvider};
 use crate::{
-    reloc::{rewrite_relocated, RelocEntry, Rewriter},
+    merkle::{leaf_hash, MerkleProof, MerkleTree},
+    reloc::{
+        estimate_rewritten_len, rewrite_relocated, validate_relocations, RelocEntry, RewriteError,
+        Rewriter,
+    },
+    store::{verify_blob, BlobSource, MemoryBlobSource},
     util::{
-        len_of, ByteCursor, ByteParse, ByteParseList, Leb128WriteExt, LenCounter, SliceExt,
+        len_of, map_ordered, BufWriter, ByteCursor, ByteParse, ByteParseList, CountingWriter,
+        Leb128WriteExt, LenCounter, OffsetTracker, SectionTracker, SliceExt, SliceWriter,
         VarByteVec, VarU32,
     },
 };
 
 // === Common === //
 
+/// The magic number every wasmall index begins with.
+pub const INDEX_MAGIC: [u8; 4] = *b"WSML";
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum SegmentKind {
     Verbatim = 0,
     Blob = 1,
+    InlineBlob = 2,
+    HeaderedBlob = 3,
+    EditedBlob = 4,
 }
 
 impl SegmentKind {
@@ -25,18 +50,341 @@ impl SegmentKind {
         match v {
             0 => Ok(Self::Verbatim),
             1 => Ok(Self::Blob),
+            2 => Ok(Self::InlineBlob),
+            3 => Ok(Self::HeaderedBlob),
+            4 => Ok(Self::EditedBlob),
             _ => Err(anyhow::anyhow!("unknown segment kind {v}")),
         }
     }
 }
 
+/// Set in the index's flags byte when a merkle root over its segments follows the module hash.
+const INDEX_FLAG_MERKLE: u8 = 1 << 0;
+
+/// Set in the index's flags byte when its blobs are encrypted. The ID of the key follows the merkle
+/// root.
+const INDEX_FLAG_ENCRYPTED: u8 = 1 << 1;
+
+/// Set in the index's flags byte when it records the order in which its blobs should be fetched.
+/// The list of segment indices follows the key ID.
+const INDEX_FLAG_PRIORITY: u8 = 1 << 2;
+
+/// Set in the index's flags byte when some of its blobs are compressed with a shared dictionary. The
+/// hash of the blob storing the dictionary follows the priority list.
+const INDEX_FLAG_DICTIONARY: u8 = 1 << 3;
+
+/// Set in the index's flags byte when it records the names of the symbols its segments hold. The
+/// list of [`SegmentSymbol`]s follows the dictionary hash.
+const INDEX_FLAG_SYMBOLS: u8 = 1 << 4;
+
+const KNOWN_INDEX_FLAGS: u8 = INDEX_FLAG_MERKLE
+    | INDEX_FLAG_ENCRYPTED
+    | INDEX_FLAG_PRIORITY
+    | INDEX_FLAG_DICTIONARY
+    | INDEX_FLAG_SYMBOLS;
+
+/// The upper bound on the size of a decompressed blob. This protects decoders from compressed blobs
+/// claiming absurd content sizes.
+pub const MAX_DECODED_BLOB_LEN: usize = 1 << 30;
+
+/// The most bytes reserved for an assembled module before any of it is written. The lengths an index
+/// declares aren't checked until its blobs are fetched, so larger modules grow their output as they
+/// go.
+const MAX_RESERVED_LEN: usize = 1 << 28;
+
+/// The representation a blob is stored in. Blob hashes always refer to the stored representation.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BlobEncoding {
+    Raw = 0,
+    Zstd = 1,
+
+    /// Compressed with `zstd` using the index's shared dictionary.
+    ZstdDict = 2,
+}
+
+impl BlobEncoding {
+    pub fn from_byte(v: u8) -> anyhow::Result<Self> {
+        match v {
+            0 => Ok(Self::Raw),
+            1 => Ok(Self::Zstd),
+            2 => Ok(Self::ZstdDict),
+            _ => Err(anyhow::anyhow!("unknown blob encoding {v}")),
+        }
+    }
+
+    pub fn decode<'a>(self, stored: &'a [u8]) -> anyhow::Result<Cow<'a, [u8]>> {
+        self.decode_with(stored, None)
+    }
+
+    /// Decodes a blob which may be compressed with the index's shared `dictionary`.
+    pub fn decode_with<'a>(
+        self,
+        stored: &'a [u8],
+        dictionary: Option<&DecoderDictionary<'_>>,
+    ) -> anyhow::Result<Cow<'a, [u8]>> {
+        match self {
+            BlobEncoding::Raw => Ok(Cow::Borrowed(stored)),
+            BlobEncoding::Zstd | BlobEncoding::ZstdDict => {
+                let len = zstd::zstd_safe::get_frame_content_size(stored)
+                    .ok()
+                    .flatten()
+                    .context("compressed blob does not specify its decompressed size")?;
+
+                anyhow::ensure!(
+                    len <= MAX_DECODED_BLOB_LEN as u64,
+                    "compressed blob claims to decompress to {len} bytes, which is too big",
+                );
+
+                let mut decompressor = match (self, dictionary) {
+                    (BlobEncoding::ZstdDict, Some(dictionary)) => {
+                        zstd::bulk::Decompressor::with_prepared_dictionary(dictionary)
+                    }
+                    (BlobEncoding::ZstdDict, None) => {
+                        anyhow::bail!("blob is compressed with a dictionary but none was provided")
+                    }
+                    _ => zstd::bulk::Decompressor::new(),
+                }
+                .context("failed to create decompressor")?;
+
+                decompressor
+                    .decompress(stored, len as usize)
+                    .map(Cow::Owned)
+                    .context("failed to decompress blob")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SymbolKind {
+    Function = 0,
+    Data = 1,
+}
+
+impl SymbolKind {
+    pub fn from_byte(v: u8) -> anyhow::Result<Self> {
+        match v {
+            0 => Ok(Self::Function),
+            1 => Ok(Self::Data),
+            _ => Err(anyhow::anyhow!("unknown symbol kind {v}")),
+        }
+    }
+}
+
+/// The name of a symbol held by one of the index's segments, as given by the `linking` section of
+/// the object it was split from. A segment may hold any number of symbols.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SegmentSymbol<'a> {
+    pub segment: u32,
+    pub kind: SymbolKind,
+    pub name: &'a str,
+}
+
+impl<'a> ByteParse<'a> for SegmentSymbol<'a> {
+    type Out = Self;
+
+    fn parse_naked(buf: &mut ByteCursor<'a>) -> anyhow::Result<Self::Out> {
+        let segment = buf.read_var_u32().context("failed to read segment index")?;
+        let kind = SymbolKind::from_byte(buf.read_u8()?)?;
+        let name = VarByteVec::parse(buf).context("failed to read symbol name")?;
+        let name = std::str::from_utf8(name).context("symbol name is not valid UTF-8")?;
+
+        Ok(Self {
+            segment,
+            kind,
+            name,
+        })
+    }
+}
+
 // === Writer === //
 
+/// Options controlling how the [`WasmallWriter`] decides whether to compress a blob.
+#[derive(Debug, Clone)]
+pub struct CompressionOptions {
+    /// Whether compression should be attempted at all.
+    pub enabled: bool,
+
+    /// The `zstd` compression level to use.
+    pub level: i32,
+
+    /// Blobs smaller than this are always stored raw since they rarely benefit from compression.
+    pub min_len: usize,
+
+    /// The minimum fraction of a blob's size that compression must save for the compressed form to
+    /// be kept. Blobs whose savings fall below this threshold are stored raw.
+    pub min_savings: f64,
+
+    /// The maximum size of a dictionary trained over the archive's blobs and shared between them,
+    /// or zero to compress every blob on its own. Small blobs compress far better with a dictionary
+    /// but it is stored as a blob of its own and is only kept if it saves more than it costs.
+    pub dictionary_size: usize,
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            min_len: 64,
+            min_savings: 0.1,
+            dictionary_size: 0,
+        }
+    }
+}
+
+impl CompressionOptions {
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::default()
+        }
+    }
+
+    /// Picks the representation a blob should be stored in, returning the encoding and the stored
+    /// bytes.
+    pub fn encode<'a>(&self, raw: &'a [u8]) -> (BlobEncoding, Cow<'a, [u8]>) {
+        self.encode_with(raw, None)
+    }
+
+    /// Picks the representation a blob should be stored in like [`encode`](Self::encode) but
+    /// compresses it with a shared `dictionary` if one is provided.
+    pub fn encode_with<'a>(
+        &self,
+        raw: &'a [u8],
+        dictionary: Option<&EncoderDictionary<'_>>,
+    ) -> (BlobEncoding, Cow<'a, [u8]>) {
+        if !self.enabled || raw.len() < self.min_len {
+            return (BlobEncoding::Raw, Cow::Borrowed(raw));
+        }
+
+        let (encoding, compressed) = match dictionary {
+            Some(dictionary) => (
+                BlobEncoding::ZstdDict,
+                zstd::bulk::Compressor::with_prepared_dictionary(dictionary)
+                    .and_then(|mut compressor| compressor.compress(raw)),
+            ),
+            None => (BlobEncoding::Zstd, zstd::bulk::compress(raw, self.level)),
+        };
+
+        let Ok(compressed) = compressed else {
+            return (BlobEncoding::Raw, Cow::Borrowed(raw));
+        };
+
+        let savings = 1. - compressed.len() as f64 / raw.len() as f64;
+
+        if savings >= self.min_savings {
+            (encoding, Cow::Owned(compressed))
+        } else {
+            (BlobEncoding::Raw, Cow::Borrowed(raw))
+        }
+    }
+}
+
+/// Options controlling how the [`WasmallWriter`] lays out its archive.
+#[derive(Debug, Clone)]
+pub struct WriterOptions {
+    pub compression: CompressionOptions,
+
+    /// Blobs whose raw size is below this many bytes are embedded directly into the index rather
+    /// than being stored separately, avoiding a separate fetch for each of them. Set this to zero to
+    /// disable inlining.
+    pub inline_threshold: usize,
+
+    /// Whether to record a merkle root over the index's segments so they can be verified one at a
+    /// time. See [`merkle`](crate::merkle).
+    pub merkle: bool,
+
+    /// The cipher used to encrypt every stored blob, if any. Inlining is disabled for encrypted
+    /// archives since inline blobs would otherwise end up in the index in plaintext. See
+    /// [`crypt`](crate::crypt).
+    #[cfg(feature = "encryption")]
+    pub encryption: Option<BlobCipher>,
+
+    /// Whether to zero, hash, compress, and encrypt blobs in parallel on the rayon thread pool.
+    /// The archive is identical either way. This is ignored without the `parallel` feature.
+    pub parallel: bool,
+}
+
+impl Default for WriterOptions {
+    fn default() -> Self {
+        Self {
+            compression: CompressionOptions::default(),
+            inline_threshold: 100,
+            merkle: false,
+            #[cfg(feature = "encryption")]
+            encryption: None,
+            parallel: false,
+        }
+    }
+}
+
+impl WriterOptions {
+    fn is_encrypted(&self) -> bool {
+        #[cfg(feature = "encryption")]
+        return self.encryption.is_some();
+
+        #[cfg(not(feature = "encryption"))]
+        false
+    }
+
+    /// Encrypts a blob's stored form if the archive is encrypted.
+    fn seal<'a>(&self, stored: Cow<'a, [u8]>) -> Cow<'a, [u8]> {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.encryption {
+            return Cow::Owned(cipher.seal(&stored));
+        }
+
+        stored
+    }
+}
+
+/// Aggregate statistics about the compression decisions made for an archive's unique blobs.
+#[derive(Debug, Clone, Default)]
+pub struct CompressionStats {
+    /// The number of blobs stored raw.
+    pub raw_blobs: usize,
+
+    /// The number of blobs stored compressed.
+    pub compressed_blobs: usize,
+
+    /// The total size of the blobs before compression.
+    pub raw_bytes: usize,
+
+    /// The total size of the blobs as stored.
+    pub stored_bytes: usize,
+
+    /// The size of the shared dictionary as stored, or zero if there isn't one. This is not included
+    /// in `stored_bytes`.
+    pub dictionary_bytes: usize,
+}
+
+impl CompressionStats {
+    pub fn total_blobs(&self) -> usize {
+        self.raw_blobs + self.compressed_blobs
+    }
+
+    /// The fraction of the raw size saved by compression, net of the shared dictionary.
+    pub fn savings(&self) -> f64 {
+        if self.raw_bytes == 0 {
+            0.
+        } else {
+            1. - (self.stored_bytes + self.dictionary_bytes) as f64 / self.raw_bytes as f64
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct WasmallArchive {
+    /// The hash of the fully assembled module. This is the canonical identity of the module and is
+    /// also recorded in the index.
+    pub module_hash: Hash,
+    /// The root of the merkle tree over the index's segments, if one was requested.
+    pub merkle_root: Option<Hash>,
     pub out_buf: Vec<u8>,
     pub blob_buf: Vec<u8>,
     pub hashes: FxHashMap<Hash, Range<usize>>,
+    pub compression_stats: CompressionStats,
 }
 
 #[derive(Debug, Default)]
@@ -47,21 +395,59 @@ pub struct WasmallWriter {
 
     /// A vector of all segments to be written.
     segments: Vec<Segment>,
+
+    /// The indices into `segments` of every blob, in the order they were pushed.
+    blob_segments: Vec<usize>,
+
+    /// The blobs to fetch first, as indices into `blob_segments`.
+    priority: Vec<usize>,
+
+    /// The names of the symbols held by blobs, as indices into `blob_segments`.
+    symbols: Vec<(usize, SymbolKind, String)>,
+
+    /// The options used to decide how each blob is stored.
+    options: WriterOptions,
 }
 
 #[derive(Debug)]
 enum Segment {
     Verbatim(Range<usize>),
     Blob {
-        /// The byte range in the `blob_buf` of the blob's data.
-        blob_range: Range<usize>,
+        /// The byte range in the `main_buf` of the blob's data, before its relocations are zeroed.
+        data: Range<usize>,
+
+        /// The relocations affecting the blob's data, which are zeroed once the writer finishes.
+        relocations: Vec<RelocEntry>,
 
         /// The byte range in the `main_buf` corresponding to the blob expansion's compressed parameters.
         concretes: Range<usize>,
+
+        /// The number of bytes the blob expands to once its relocations have been applied.
+        out_len: u32,
+
+        /// The kind of segment restoring the blob's original bytes along with the byte range in the
+        /// `main_buf` of the encoded restoration, if the blob was normalized.
+        restore: Option<(SegmentKind, Range<usize>)>,
     },
 }
 
+/// Replaces `len` bytes at `offset` of a normalized blob's expanded data with its `original` bytes
+/// upon assembly. See [`WasmallWriter::push_blob_with_edits`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BlobEdit {
+    pub offset: usize,
+    pub len: usize,
+    pub original: Vec<u8>,
+}
+
 impl WasmallWriter {
+    pub fn new(options: WriterOptions) -> Self {
+        Self {
+            options,
+            ..Self::default()
+        }
+    }
+
     pub fn push_verbatim<R>(&mut self, f: impl FnOnce(&mut Vec<u8>) -> R) -> R {
         let start = self.buf.len();
         let res = f(&mut self.buf);
@@ -84,165 +470,1170 @@ impl WasmallWriter {
         relocation_values: &[u32],
         data: &[u8],
     ) {
-        // Create the blob's data
-        let blob_range = {
-            let blob_start = self.buf.len();
-
-            // Write relocations
-            self.buf.write_var_u32(relocations.len() as u32);
-
-            rewrite_relocated(
-                data,
-                &mut LenCounter::default(),
-                self,
-                relocations.iter().map(|reloc| {
-                    (
-                        reloc.offset as usize,
-                        move |reader: &mut ByteCursor, writer: &mut LenCounter, cx: &mut Self| {
-                            // Write relocation type.
-                            cx.buf.push(reloc.ty as u8);
-
-                            // Write relocation offset
-                            cx.buf.write_var_u32(writer.0 as u32);
-
-                            // Write relocation index
-                            cx.buf.write_var_u32(reloc.index);
-
-                            // Write relocation addend
-                            if let Some(addend) = reloc.addend {
-                                cx.buf.write_var_i32(addend);
-                            }
-
-                            // Sanity check
-                            debug_assert_eq!(
-                                reloc
-                                    .ty
-                                    .rewrite_kind()
-                                    .read(&mut reader.clone())
-                                    .unwrap()
-                                    .as_u32(),
-                                relocation_values[reloc.index as usize]
-                                    .wrapping_add(reloc.addend.unwrap_or(0) as u32),
-                            );
-
-                            reloc
-                                .ty
-                                .rewrite_kind()
-                                .with_zeroed()
-                                .rewrite(reader, writer, cx)
-                                .unwrap();
-
-                            Ok(())
-                        },
-                    )
-                }),
-            )
-            .unwrap();
+        self.push_blob_with_header(relocations, relocation_values, data, None);
+    }
 
-            // Push zeroed blob body data into blob buffer
-            rewrite_relocated(
-                data,
-                &mut self.buf,
-                &mut (),
-                relocations
-                    .iter()
-                    .map(|reloc| (reloc.offset as usize, reloc.ty.rewrite_kind().with_zeroed())),
-            )
-            .unwrap();
+    /// Pushes a blob whose data begins with a normalized header. Upon assembly, the first
+    /// `normalized_len` bytes of the blob are replaced with `original`, allowing equivalent blobs
+    /// with differently encoded headers to be deduplicated while still reassembling bit-exactly.
+    /// Such blobs are never inlined.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `normalized_len` exceeds the length of `data`.
+    pub fn push_blob_with_header(
+        &mut self,
+        relocations: &[RelocEntry],
+        relocation_values: &[u32],
+        data: &[u8],
+        header: Option<(&[u8], usize)>,
+    ) {
+        let restore = header.map(|(original, normalized_len)| {
+            assert!(
+                normalized_len <= data.len(),
+                "normalized blob header is longer than the blob"
+            );
 
-            blob_start..self.buf.len()
-        };
+            let restore = self.buf.with_span(|buf| {
+                buf.write_var_u32(u32::try_from(original.len()).unwrap());
+                buf.extend_from_slice(original);
+                buf.write_var_u32(u32::try_from(normalized_len).unwrap());
+            });
 
-        // Write the concretes
-        let concretes = {
-            let concretes_start = self.buf.len();
+            (SegmentKind::HeaderedBlob, restore)
+        });
+
+        self.push_blob_inner(relocations, relocation_values, data, restore);
+    }
+
+    /// Pushes a normalized blob along with the `edits` restoring its original bytes, which must be
+    /// sorted by offset and may not overlap each other or the blob's relocations. Upon assembly,
+    /// every edit is applied to the blob once its relocations have been expanded. Edits consisting
+    /// of a single header are stored as compactly as with
+    /// [`push_blob_with_header`](Self::push_blob_with_header). Such blobs are never inlined.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the edits are out of order, overlap, or extend past the end of `data`.
+    pub fn push_blob_with_edits(
+        &mut self,
+        relocations: &[RelocEntry],
+        relocation_values: &[u32],
+        data: &[u8],
+        edits: &[BlobEdit],
+    ) {
+        if let [edit] = edits {
+            if edit.offset == 0 {
+                self.push_blob_with_header(
+                    relocations,
+                    relocation_values,
+                    data,
+                    Some((&edit.original, edit.len)),
+                );
+                return;
+            }
+        }
+
+        let mut prev_end = 0;
+        for edit in edits {
+            assert!(
+                edit.offset >= prev_end && edit.offset + edit.len <= data.len(),
+                "blob edits are out of order or lie outside of the blob"
+            );
+
+            debug_assert!(relocations.iter().all(|reloc| {
+                let start = reloc.offset as usize;
+                start + reloc.ty.rewrite_kind().width() <= edit.offset
+                    || edit.offset + edit.len <= start
+            }));
+
+            prev_end = edit.offset + edit.len;
+        }
+
+        let restore = (!edits.is_empty()).then(|| {
+            let restore = self.buf.with_span(|buf| {
+                buf.write_var_u32(u32::try_from(edits.len()).unwrap());
+
+                let mut prev_end = 0;
+                for edit in edits {
+                    buf.write_var_u32(u32::try_from(edit.offset - prev_end).unwrap());
+                    buf.write_var_u32(u32::try_from(edit.len).unwrap());
+                    buf.write_var_u32(u32::try_from(edit.original.len()).unwrap());
+                    buf.extend_from_slice(&edit.original);
+                    prev_end = edit.offset + edit.len;
+                }
+            });
+
+            (SegmentKind::EditedBlob, restore)
+        });
+
+        self.push_blob_inner(relocations, relocation_values, data, restore);
+    }
+
+    fn push_blob_inner(
+        &mut self,
+        relocations: &[RelocEntry],
+        relocation_values: &[u32],
+        data: &[u8],
+        restore: Option<(SegmentKind, Range<usize>)>,
+    ) {
+        // Sanity check
+        for reloc in relocations {
+            debug_assert_eq!(
+                reloc
+                    .ty
+                    .rewrite_kind()
+                    .read(&mut ByteCursor(&data[reloc.offset as usize..]))
+                    .unwrap()
+                    .as_u64(),
+                reloc
+                    .ty
+                    .rewrite_kind()
+                    .with_value_u64(
+                        u64::from(relocation_values[reloc.index as usize])
+                            .wrapping_add_signed(reloc.addend.unwrap_or(0)),
+                    )
+                    .as_u64(),
+            );
+        }
 
+        // Zeroing the relocations is deferred to `finish` so that it can be done in parallel.
+        let data_range = self.buf.with_span(|buf| buf.extend_from_slice(data));
+
+        // Write the concretes
+        let concretes = self.buf.with_span(|buf| {
             // Write count
             let byte_size = len_of(|c| {
                 for v in relocation_values {
                     c.write_var_u32(*v);
                 }
             });
-            self.buf.write_var_u32(byte_size as u32);
+            buf.write_var_u32(byte_size as u32);
 
             // Write values
             for v in relocation_values {
-                self.buf.write_var_u32(*v);
+                buf.write_var_u32(*v);
             }
+        });
 
-            concretes_start..self.buf.len()
-        };
-
+        self.blob_segments.push(self.segments.len());
         self.segments.push(Segment::Blob {
-            blob_range,
+            data: data_range,
+            relocations: relocations.to_vec(),
             concretes,
+            out_len: u32::try_from(data.len()).unwrap(),
+            restore,
         });
     }
 
-    pub fn finish(self) -> WasmallArchive {
-        let mut out_buf = Vec::new();
+    /// The number of blobs pushed so far. Blobs are identified by the order in which they were
+    /// pushed.
+    pub fn blob_count(&self) -> usize {
+        self.blob_segments.len()
+    }
+
+    /// Marks the specified blobs as needed first, in order. Consumers fetch prioritized blobs
+    /// before the remaining ones, which are fetched in module order.
+    pub fn prioritize(&mut self, blobs: impl IntoIterator<Item = usize>) {
+        self.priority.extend(blobs);
+    }
+
+    /// Records that the specified blob holds the symbol `name` so that tools can refer to it by
+    /// name.
+    pub fn name_blob(&mut self, blob: usize, kind: SymbolKind, name: &str) {
+        self.symbols.push((blob, kind, name.to_string()));
+    }
+
+    pub fn finish(self) -> anyhow::Result<WasmallArchive> {
+        let parallel = self.options.parallel;
+        let mut seg_buf = Vec::new();
         let mut blob_buf = Vec::new();
         let mut hashes = FxHashMap::default();
+        let mut compression_stats = CompressionStats::default();
+
+        let is_inlined = |raw: &[u8], is_normalized: bool| {
+            // Tiny blobs cost more to fetch than to embed.
+            raw.len() < self.options.inline_threshold
+                && !self.options.is_encrypted()
+                && !is_normalized
+        };
+
+        // Preparing each blob is independent of every other blob so it's done up front, spreading
+        // the work across threads if requested. The archive is then laid out serially so it doesn't
+        // depend on how the work was scheduled.
+        let blob_segments = self
+            .blob_segments
+            .iter()
+            .map(|&index| &self.segments[index])
+            .collect::<Vec<_>>();
+
+        let raws = map_ordered(parallel, &blob_segments, |segment| {
+            let Segment::Blob {
+                data, relocations, ..
+            } = segment
+            else {
+                unreachable!();
+            };
+
+            let raw = zero_blob_relocations(&self.buf[data.clone()], relocations);
+            let raw_hash = hash(&raw);
+            (raw, raw_hash)
+        });
+
+        // Identical blobs are only encoded once. Maps the hashes of raw blobs to their index in
+        // `encoded`.
+        let mut unique = FxHashMap::<Hash, usize>::default();
+        let mut to_encode = Vec::new();
+
+        for (segment, (raw, raw_hash)) in blob_segments.iter().zip(&raws) {
+            let Segment::Blob { restore, .. } = segment else {
+                unreachable!();
+            };
+
+            if !is_inlined(raw, restore.is_some()) {
+                unique.entry(*raw_hash).or_insert_with(|| {
+                    to_encode.push(&raw[..]);
+                    to_encode.len() - 1
+                });
+            }
+        }
+
+        let encode_all = |dictionary: Option<&EncoderDictionary<'_>>| {
+            map_ordered(parallel, &to_encode, |raw| {
+                let (encoding, stored) = self.options.compression.encode_with(raw, dictionary);
+                let stored = self.options.seal(stored);
+                let stored_hash = hash(&stored);
+
+                (encoding, stored, stored_hash)
+            })
+        };
+
+        let mut encoded = encode_all(None);
+
+        // Try compressing with a dictionary trained over the blobs, keeping it only if it saves more
+        // than it costs to store.
+        let mut dictionary = None;
+        let compression = &self.options.compression;
+
+        if compression.enabled && compression.dictionary_size > 0 {
+            if let Ok(trained) = zstd::dict::from_samples(&to_encode, compression.dictionary_size) {
+                let prepared = EncoderDictionary::copy(&trained, compression.level);
+                let with_dictionary = encode_all(Some(&prepared));
+                let stored = self.options.seal(Cow::Owned(trained));
+
+                let total_len = |encoded: &[(BlobEncoding, Cow<'_, [u8]>, Hash)]| {
+                    encoded
+                        .iter()
+                        .map(|(_, stored, _)| stored.len())
+                        .sum::<usize>()
+                };
+
+                if total_len(&with_dictionary) + stored.len() < total_len(&encoded) {
+                    encoded = with_dictionary;
+                    dictionary = Some((hash(&stored), stored));
+                }
+            }
+        }
+
+        // The dictionary is stored first, followed by the blobs in the order they're first used.
+        if let Some((dictionary_hash, stored)) = &dictionary {
+            hashes.insert(
+                *dictionary_hash,
+                blob_buf.with_span(|buf| buf.extend_from_slice(stored)),
+            );
+            compression_stats.dictionary_bytes = stored.len();
+        }
+
+        for (raw, (encoding, stored, stored_hash)) in to_encode.iter().zip(&encoded) {
+            if let hash_map::Entry::Vacant(entry) = hashes.entry(*stored_hash) {
+                entry.insert(blob_buf.with_span(|buf| buf.extend_from_slice(stored)));
+
+                match encoding {
+                    BlobEncoding::Raw => compression_stats.raw_blobs += 1,
+                    BlobEncoding::Zstd | BlobEncoding::ZstdDict => {
+                        compression_stats.compressed_blobs += 1
+                    }
+                }
+                compression_stats.raw_bytes += raw.len();
+                compression_stats.stored_bytes += stored.len();
+            }
+        }
+
+        let mut raws = raws.iter();
 
-        for segment in self.segments {
+        for segment in &self.segments {
             match segment {
                 Segment::Verbatim(range) => {
+                    let out_buf = &mut seg_buf;
                     out_buf.push(0);
                     out_buf.write_var_u32(u32::try_from(range.len()).unwrap());
-                    out_buf.extend_from_slice(&self.buf[range]);
+                    out_buf.extend_from_slice(&self.buf[range.clone()]);
                 }
                 Segment::Blob {
-                    blob_range,
                     concretes,
+                    out_len,
+                    restore,
+                    ..
                 } => {
-                    out_buf.push(1);
-                    let hash = hash(&self.buf[blob_range.clone()]);
+                    let (raw, raw_hash) = raws.next().unwrap();
+
+                    if is_inlined(raw, restore.is_some()) {
+                        let out_buf = &mut seg_buf;
+                        out_buf.push(2);
+                        out_buf.write_var_u32(*out_len);
+                        out_buf.write_var_u32(u32::try_from(raw.len()).unwrap());
+                        out_buf.extend_from_slice(raw);
+                        out_buf.extend_from_slice(&self.buf[concretes.clone()]);
+                        continue;
+                    }
+
+                    let (encoding, _, hash) = &encoded[unique[raw_hash]];
+
+                    let out_buf = &mut seg_buf;
+                    out_buf.push(
+                        restore
+                            .as_ref()
+                            .map_or(SegmentKind::Blob, |(kind, _)| *kind)
+                            as u8,
+                    );
                     out_buf.extend_from_slice(hash.as_bytes());
-                    out_buf.extend_from_slice(&self.buf[concretes]);
+                    out_buf.push(*encoding as u8);
+                    out_buf.write_var_u32(*out_len);
+                    out_buf.extend_from_slice(&self.buf[concretes.clone()]);
 
-                    if let hash_map::Entry::Vacant(entry) = hashes.entry(hash) {
-                        let start = blob_buf.len();
-                        blob_buf.extend_from_slice(&self.buf[blob_range]);
-                        entry.insert(start..blob_buf.len());
+                    if let Some((_, restore)) = restore {
+                        out_buf.extend_from_slice(&self.buf[restore.clone()]);
                     }
                 }
             }
         }
 
-        WasmallArchive {
-            out_buf,
+        let mut archive = WasmallArchive {
+            module_hash: Hash::from_bytes([0; blake3::OUT_LEN]),
+            merkle_root: None,
+            out_buf: Vec::new(),
             blob_buf,
             hashes,
+            compression_stats,
+        };
+
+        // Determine the module's hash by running it through the same assembly process consumers
+        // will use.
+        let dictionary_hash = dictionary.map(|(hash, _)| hash);
+        let module = WasmallMod {
+            #[cfg(feature = "encryption")]
+            cipher: self.options.encryption.clone(),
+            ..WasmallMod::from_segments(&seg_buf, dictionary_hash)
+        };
+        archive.module_hash = if parallel {
+            hash(
+                &module
+                    .assemble_parallel(&archive)
+                    .context("failed to assemble the module to hash it")?,
+            )
+        } else {
+            let mut hasher = Hasher::new();
+            module
+                .assemble_into(&archive, &mut hasher)
+                .context("failed to assemble the module to hash it")?;
+            hasher.finalize()
+        };
+
+        if self.options.merkle {
+            archive.merkle_root = Some(module.merkle_tree()?.root());
+        }
+
+        // Write out the index
+        archive.out_buf.extend_from_slice(&INDEX_MAGIC);
+        archive
+            .out_buf
+            .extend_from_slice(archive.module_hash.as_bytes());
+
+        let mut flags = 0;
+        if archive.merkle_root.is_some() {
+            flags |= INDEX_FLAG_MERKLE;
+        }
+        if self.options.is_encrypted() {
+            flags |= INDEX_FLAG_ENCRYPTED;
+        }
+        if !self.priority.is_empty() {
+            flags |= INDEX_FLAG_PRIORITY;
+        }
+        if dictionary_hash.is_some() {
+            flags |= INDEX_FLAG_DICTIONARY;
+        }
+        if !self.symbols.is_empty() {
+            flags |= INDEX_FLAG_SYMBOLS;
         }
+        archive.out_buf.push(flags);
+
+        if let Some(merkle_root) = archive.merkle_root {
+            archive.out_buf.extend_from_slice(merkle_root.as_bytes());
+        }
+
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.options.encryption {
+            let key_id = cipher.key_id();
+            archive
+                .out_buf
+                .write_var_u32(u32::try_from(key_id.len()).unwrap());
+            archive.out_buf.extend_from_slice(key_id);
+        }
+
+        if !self.priority.is_empty() {
+            archive
+                .out_buf
+                .write_var_u32(u32::try_from(self.priority.len()).unwrap());
+
+            for &blob in &self.priority {
+                archive
+                    .out_buf
+                    .write_var_u32(u32::try_from(self.blob_segments[blob]).unwrap());
+            }
+        }
+
+        if let Some(dictionary_hash) = dictionary_hash {
+            archive
+                .out_buf
+                .extend_from_slice(dictionary_hash.as_bytes());
+        }
+
+        if !self.symbols.is_empty() {
+            let mut symbols = self
+                .symbols
+                .iter()
+                .map(|(blob, kind, name)| (self.blob_segments[*blob], kind, name))
+                .collect::<Vec<_>>();
+            symbols.sort_by_key(|&(segment, ..)| segment);
+
+            let out_buf = &mut archive.out_buf;
+            out_buf.write_var_u32(u32::try_from(symbols.len()).unwrap());
+
+            for (segment, kind, name) in symbols {
+                out_buf.write_var_u32(u32::try_from(segment).unwrap());
+                out_buf.push(*kind as u8);
+                out_buf.write_var_u32(u32::try_from(name.len()).unwrap());
+                out_buf.extend_from_slice(name.as_bytes());
+            }
+        }
+
+        archive.out_buf.extend_from_slice(&seg_buf);
+
+        Ok(archive)
     }
 }
 
+/// Encodes a blob's relocations, rebased onto the relocated data, followed by its data with every
+/// relocation zeroed.
+fn zero_blob_relocations(data: &[u8], relocations: &[RelocEntry]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    // Write relocations
+    buf.write_var_u32(relocations.len() as u32);
+
+    rewrite_relocated(
+        data,
+        &mut LenCounter::default(),
+        &mut buf,
+        relocations.iter().map(|reloc| {
+            (
+                reloc.offset as usize,
+                move |reader: &mut ByteCursor, writer: &mut LenCounter, buf: &mut Vec<u8>| {
+                    // Write the relocation, rebased onto the relocated data.
+                    RelocEntry {
+                        offset: writer.0 as u32,
+                        ..*reloc
+                    }
+                    .write(buf);
+
+                    reloc
+                        .ty
+                        .rewrite_kind()
+                        .with_zeroed()
+                        .rewrite(reader, writer, buf)
+                        .unwrap();
+
+                    Ok(())
+                },
+            )
+        }),
+    )
+    .unwrap();
+
+    // Write the zeroed data
+    buf.reserve(estimate_rewritten_len(
+        data,
+        relocations
+            .iter()
+            .map(|reloc| (reloc.offset as usize, reloc.ty.rewrite_kind())),
+    ));
+
+    rewrite_relocated(
+        data,
+        &mut buf,
+        &mut (),
+        relocations
+            .iter()
+            .map(|reloc| (reloc.offset as usize, reloc.ty.rewrite_kind().with_zeroed())),
+    )
+    .unwrap();
+
+    buf
+}
+
 // === Reader === //
 
 // Module
 #[derive(Debug, Clone)]
 pub struct WasmallMod<'a> {
+    module_hash: Hash,
+    merkle_root: Option<Hash>,
+    key_id: Option<&'a [u8]>,
+    #[cfg(feature = "encryption")]
+    cipher: Option<BlobCipher>,
+    priority: &'a [u8],
+    dictionary: Option<Hash>,
+    dictionary_cache: DictionaryCache,
+    symbols: &'a [u8],
     segments: &'a [u8],
+
+    /// The entire index, used to report offsets in diagnostics.
+    raw: &'a [u8],
 }
 
-impl<'a> ByteParse<'a> for WasmallMod<'a> {
-    type Out = Self;
+/// The index's shared dictionary, prepared for decompression when it is first needed. Clones of an
+/// index share the prepared dictionary.
+#[derive(Clone, Default)]
+struct DictionaryCache(Arc<OnceLock<DecoderDictionary<'static>>>);
 
-    fn parse_naked(buf: &mut ByteCursor<'a>) -> anyhow::Result<Self::Out> {
-        Ok(Self { segments: buf.0 })
+impl fmt::Debug for DictionaryCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DictionaryCache")
+            .field("loaded", &self.0.get().is_some())
+            .finish()
     }
 }
 
-impl<'a> WasmallMod<'a> {
-    pub fn segments(&self) -> ByteParseList<'a, WasmallModSeg<'a>> {
-        ByteParseList::new(ByteCursor(self.segments))
-    }
-}
+impl<'a> ByteParse<'a> for WasmallMod<'a> {
+    type Out = Self;
+
+    fn parse_naked(buf: &mut ByteCursor<'a>) -> anyhow::Result<Self::Out> {
+        let raw = buf.0;
+        let _guard = OffsetTracker::new_if_untracked(raw);
+
+        let magic = buf
+            .consume_arr::<4>()
+            .context("failed to read index magic number")?;
+
+        anyhow::ensure!(magic == INDEX_MAGIC, "not a wasmall index");
+
+        let module_hash = buf
+            .consume_arr()
+            .map(Hash::from_bytes)
+            .context("failed to read module hash")?;
+
+        let flags = buf.read_u8().context("failed to read index flags")?;
+        anyhow::ensure!(
+            flags & !KNOWN_INDEX_FLAGS == 0,
+            "unknown index flags {flags:#x}"
+        );
+
+        let merkle_root = if flags & INDEX_FLAG_MERKLE != 0 {
+            Some(
+                buf.consume_arr()
+                    .map(Hash::from_bytes)
+                    .context("failed to read merkle root")?,
+            )
+        } else {
+            None
+        };
+
+        let key_id = if flags & INDEX_FLAG_ENCRYPTED != 0 {
+            Some(VarByteVec::parse(buf).context("failed to read key ID")?)
+        } else {
+            None
+        };
+
+        let priority = if flags & INDEX_FLAG_PRIORITY != 0 {
+            buf.lookahead_annotated("fetch priority list", |c| {
+                let _section = SectionTracker::new("the fetch priority list", c.0);
+                let count = c.read_var_u32()?;
+                c.get_slice_read(|c| {
+                    for _ in 0..count {
+                        c.read_var_u32()?;
+                    }
+
+                    Ok(())
+                })
+                .map(|(_, priority)| priority)
+            })?
+        } else {
+            &[]
+        };
+
+        let dictionary = if flags & INDEX_FLAG_DICTIONARY != 0 {
+            Some(
+                buf.consume_arr()
+                    .map(Hash::from_bytes)
+                    .context("failed to read dictionary hash")?,
+            )
+        } else {
+            None
+        };
+
+        let symbols = if flags & INDEX_FLAG_SYMBOLS != 0 {
+            buf.lookahead_annotated("symbol list", |c| {
+                let _section = SectionTracker::new("the symbol list", c.0);
+                let count = c.read_var_u32()?;
+                c.get_slice_read(|c| {
+                    for _ in 0..count {
+                        SegmentSymbol::parse(c)?;
+                    }
+
+                    Ok(())
+                })
+                .map(|(_, symbols)| symbols)
+            })?
+        } else {
+            &[]
+        };
+
+        Ok(Self {
+            module_hash,
+            merkle_root,
+            key_id,
+            #[cfg(feature = "encryption")]
+            cipher: None,
+            priority,
+            dictionary,
+            dictionary_cache: DictionaryCache::default(),
+            symbols,
+            segments: buf.0,
+            raw,
+        })
+    }
+}
+
+impl<'a> WasmallMod<'a> {
+    fn from_segments(segments: &'a [u8], dictionary: Option<Hash>) -> Self {
+        Self {
+            module_hash: Hash::from_bytes([0; blake3::OUT_LEN]),
+            merkle_root: None,
+            key_id: None,
+            #[cfg(feature = "encryption")]
+            cipher: None,
+            priority: &[],
+            dictionary,
+            dictionary_cache: DictionaryCache::default(),
+            symbols: &[],
+            segments,
+            raw: segments,
+        }
+    }
+
+    /// Registers the index with the [`OffsetTracker`] for as long as the returned guards live so
+    /// that parse errors report where in the index they occurred.
+    fn track_offsets(&self) -> (Option<OffsetTracker<'a>>, SectionTracker<'a>) {
+        (
+            OffsetTracker::new_if_untracked(self.raw),
+            SectionTracker::new("the segment list", self.segments),
+        )
+    }
+
+    /// The hash of the fully assembled module.
+    pub fn module_hash(&self) -> Hash {
+        self.module_hash
+    }
+
+    /// The ID of the key the index's blobs are encrypted with, if they are encrypted.
+    pub fn key_id(&self) -> Option<&'a [u8]> {
+        self.key_id
+    }
+
+    /// Whether the index's blobs are encrypted and it hasn't been unlocked yet. Indices can only be
+    /// unlocked with the `encryption` feature.
+    pub fn is_locked(&self) -> bool {
+        #[cfg(feature = "encryption")]
+        return self.key_id.is_some() && self.cipher.is_none();
+
+        #[cfg(not(feature = "encryption"))]
+        self.key_id.is_some()
+    }
+
+    /// Fetches the key the index's blobs are encrypted with from `keys` so that they can be
+    /// decrypted during assembly. This does nothing for indices which aren't encrypted.
+    #[cfg(feature = "encryption")]
+    pub fn unlock(&mut self, keys: &(impl ?Sized + KeyProvider)) -> anyhow::Result<()> {
+        let Some(key_id) = self.key_id else {
+            return Ok(());
+        };
+
+        let key = keys
+            .get_key(key_id)?
+            .with_context(|| format!("no key available for key ID {key_id:x?}"))?;
+
+        self.cipher = Some(BlobCipher::new(key_id, key));
+        Ok(())
+    }
+
+    /// The merkle root over the index's segments, if the index records one.
+    pub fn merkle_root(&self) -> Option<Hash> {
+        self.merkle_root
+    }
+
+    pub fn segments(&self) -> ByteParseList<'a, WasmallModSeg<'a>> {
+        ByteParseList::new(ByteCursor(self.segments))
+    }
+
+    /// The hash of the blob storing the shared dictionary some of the index's blobs are compressed
+    /// with, if there is one.
+    pub fn dictionary_hash(&self) -> Option<Hash> {
+        self.dictionary
+    }
+
+    /// Iterates over the hashes of every blob the index refers to, including duplicates. The
+    /// dictionary comes first, if there is one. Inline blobs are not included.
+    pub fn blob_hashes(&self) -> impl Iterator<Item = anyhow::Result<Hash>> + 'a {
+        let blobs = self.segments().filter_map(|segment| match segment {
+            Ok(WasmallModSeg::Blob(segment)) => Some(Ok(segment.hash())),
+            Ok(_) => None,
+            Err(err) => Some(Err(err)),
+        });
+
+        self.dictionary.map(Ok).into_iter().chain(blobs)
+    }
+
+    /// Iterates over the indices of the segments whose blobs should be fetched first, in order.
+    pub fn priority(&self) -> ByteParseList<'a, VarU32> {
+        ByteParseList::new(ByteCursor(self.priority))
+    }
+
+    /// Iterates over the names of the symbols held by the index's segments, ordered by segment.
+    pub fn symbols(&self) -> ByteParseList<'a, SegmentSymbol<'a>> {
+        ByteParseList::new(ByteCursor(self.symbols))
+    }
+
+    /// Groups the names of the symbols held by the index's segments by segment index.
+    pub fn symbols_by_segment(&self) -> anyhow::Result<FxHashMap<u32, Vec<SegmentSymbol<'a>>>> {
+        let mut symbols = FxHashMap::<u32, Vec<_>>::default();
+
+        for symbol in self.symbols() {
+            let symbol = symbol?;
+            symbols.entry(symbol.segment).or_default().push(symbol);
+        }
+
+        Ok(symbols)
+    }
+
+    /// Determines the order in which the index's blobs should be fetched: the dictionary, every
+    /// prioritized blob, and then the remaining ones in module order. Each blob is listed once.
+    pub fn fetch_order(&self) -> anyhow::Result<Vec<Hash>> {
+        let _guard = self.track_offsets();
+        let segments = self.segments().collect::<anyhow::Result<Vec<_>>>()?;
+        let prioritized = self.priority().map(|index| {
+            let index = index?;
+            let segment = segments
+                .get(index as usize)
+                .with_context(|| format!("prioritized segment {index} does not exist"))?;
+
+            anyhow::Ok(match segment {
+                WasmallModSeg::Blob(segment) => Some(segment.hash()),
+                _ => None,
+            })
+        });
+
+        let mut seen = FxHashSet::default();
+        let mut order = Vec::new();
+
+        for hash in self
+            .dictionary
+            .map(Ok)
+            .into_iter()
+            .chain(prioritized.filter_map(Result::transpose))
+            .chain(self.blob_hashes())
+        {
+            let hash = hash?;
+            if seen.insert(hash) {
+                order.push(hash);
+            }
+        }
+
+        Ok(order)
+    }
+
+    /// Fetches every blob the index refers to from `source` in [fetch order](Self::fetch_order).
+    pub fn prefetch<'s>(
+        &self,
+        source: &'s (impl ?Sized + BlobSource),
+    ) -> anyhow::Result<MemoryBlobSource<'s>> {
+        let mut blobs = MemoryBlobSource::default();
+
+        for hash in self.fetch_order()? {
+            let blob = source
+                .get_blob(hash)?
+                .with_context(|| format!("missing blob {hash}"))?;
+
+            blobs.insert(hash, blob);
+        }
+
+        Ok(blobs)
+    }
+
+    /// Assembles the module like [`assemble_verified`](Self::assemble_verified) but fetches every
+    /// blob up-front in [fetch order](Self::fetch_order) so that the blobs needed to start the
+    /// module arrive first.
+    pub fn assemble_prioritized(
+        &self,
+        source: &(impl ?Sized + BlobSource),
+    ) -> anyhow::Result<Vec<u8>> {
+        self.assemble_verified(&self.prefetch(source)?)
+    }
+
+    /// Iterates over the encoded bytes of each segment. These are the leaves of the index's merkle
+    /// tree.
+    pub fn raw_segments(&self) -> impl Iterator<Item = anyhow::Result<&'a [u8]>> {
+        let mut cursor = ByteCursor(self.segments);
+
+        std::iter::from_fn(move || {
+            (!cursor.at_eof()).then(|| {
+                cursor
+                    .get_slice_read(WasmallModSeg::parse)
+                    .map(|(_, raw)| raw)
+            })
+        })
+    }
+
+    /// Builds the merkle tree over the index's segments.
+    pub fn merkle_tree(&self) -> anyhow::Result<MerkleTree> {
+        let _guard = self.track_offsets();
+        Ok(MerkleTree::from_leaves(
+            self.raw_segments()
+                .map(|raw| raw.map(leaf_hash))
+                .collect::<anyhow::Result<_>>()?,
+        ))
+    }
+
+    /// Ensures that the index's segments match the merkle root it records.
+    pub fn verify_merkle_root(&self) -> anyhow::Result<()> {
+        let expected = self
+            .merkle_root
+            .context("index does not record a merkle root")?;
+
+        let actual = self.merkle_tree()?.root();
+        anyhow::ensure!(
+            actual == expected,
+            "merkle root mismatch; expected {expected}, got {actual}",
+        );
+        Ok(())
+    }
+
+    /// Ensures that an assembled module matches the hash recorded in the index.
+    pub fn verify_module(&self, module: &[u8]) -> anyhow::Result<()> {
+        let actual_hash = blake3::hash(module);
+        anyhow::ensure!(
+            actual_hash == self.module_hash,
+            "assembled module hash mismatch; expected {}, got {actual_hash}",
+            self.module_hash,
+        );
+        Ok(())
+    }
+
+    /// Computes the exact size of the assembled module without fetching any of its blobs.
+    pub fn assembled_len(&self) -> anyhow::Result<usize> {
+        let _guard = self.track_offsets();
+        let mut len = 0usize;
+
+        for segment in self.segments() {
+            len = len
+                .checked_add(segment?.out_len())
+                .context("assembled module is too big")?;
+        }
+
+        Ok(len)
+    }
+
+    /// Assembles the module into an arbitrary [`BufWriter`], fetching blobs from `source` as they
+    /// are needed and checking each against its hash. Use [`verify_module`](Self::verify_module)
+    /// to check the result against the module hash.
+    pub fn assemble_into(
+        &self,
+        source: &(impl ?Sized + BlobSource),
+        out: &mut impl BufWriter,
+    ) -> anyhow::Result<()> {
+        let _guard = self.track_offsets();
+
+        for segment in self.segments() {
+            self.assemble_segment(&segment?, source, out)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes out a single segment's contribution to the assembled module, fetching its blob from
+    /// `source` if it needs one.
+    pub fn assemble_segment(
+        &self,
+        segment: &WasmallModSeg<'_>,
+        source: &(impl ?Sized + BlobSource),
+        out: &mut impl BufWriter,
+    ) -> anyhow::Result<()> {
+        let mut out = CountingWriter::new(out);
+
+        match segment {
+            WasmallModSeg::Verbatim(segment) => {
+                out.extend(segment.data());
+            }
+            WasmallModSeg::Blob(segment) => {
+                self.with_blob(segment, source, |blob| segment.write(&blob, &mut out))?;
+            }
+            WasmallModSeg::InlineBlob(segment) => {
+                segment.write(&mut out)?;
+            }
+        }
+
+        // Segments are laid out using their declared lengths so one which writes anything else would
+        // corrupt the rest of the module.
+        anyhow::ensure!(
+            out.written() == segment.out_len(),
+            "segment declares {} byte(s) of output but wrote {}",
+            segment.out_len(),
+            out.written(),
+        );
+
+        Ok(())
+    }
+
+    /// Fetches a segment's blob from `source`, checks it against its hash unless the source already
+    /// did, and passes it to `f` once it has been decrypted and decompressed.
+    pub fn with_blob<R>(
+        &self,
+        segment: &WasmallModSegBlob<'_>,
+        source: &(impl ?Sized + BlobSource),
+        f: impl FnOnce(WasmallBlob<'_>) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        let hash = segment.hash();
+        let stored = source
+            .get_blob(hash)?
+            .with_context(|| format!("missing blob {hash}"))?;
+
+        if !source.verifies_blobs() {
+            verify_blob(hash, &stored)?;
+        }
+
+        self.with_stored_blob(segment, stored, source, f)
+    }
+
+    /// Like [`with_blob`](Self::with_blob) but takes the stored form of the blob, which is trusted
+    /// to match its hash. `source` is only used to fetch the dictionary.
+    pub fn with_stored_blob<R>(
+        &self,
+        segment: &WasmallModSegBlob<'_>,
+        stored: Cow<'_, [u8]>,
+        source: &(impl ?Sized + BlobSource),
+        f: impl FnOnce(WasmallBlob<'_>) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        let stored = self.open_blob(segment.hash(), stored)?;
+        let dictionary = match segment.encoding() {
+            BlobEncoding::ZstdDict => Some(self.load_dictionary(source)?),
+            _ => None,
+        };
+
+        let blob = segment.encoding().decode_with(&stored, dictionary)?;
+        f(WasmallBlob::parse(&mut ByteCursor(&blob))?)
+    }
+
+    /// Decrypts a stored blob if the index is encrypted.
+    fn open_blob<'b>(&self, hash: Hash, stored: Cow<'b, [u8]>) -> anyhow::Result<Cow<'b, [u8]>> {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            return Ok(Cow::Owned(
+                cipher
+                    .open(&stored)
+                    .with_context(|| format!("failed to open blob {hash}"))?,
+            ));
+        }
+
+        if let Some(key_id) = self.key_id {
+            anyhow::bail!(
+                "can't open blob {hash}: index is encrypted with key ID {key_id:x?} but has not \
+                 been unlocked"
+            );
+        }
+
+        Ok(stored)
+    }
+
+    /// Fetches and prepares the index's shared dictionary the first time it is needed.
+    fn load_dictionary(
+        &self,
+        source: &(impl ?Sized + BlobSource),
+    ) -> anyhow::Result<&DecoderDictionary<'static>> {
+        if let Some(dictionary) = self.dictionary_cache.0.get() {
+            return Ok(dictionary);
+        }
+
+        let hash = self
+            .dictionary
+            .context("blob is compressed with a dictionary but the index doesn't have one")?;
+
+        let stored = source
+            .get_blob(hash)?
+            .with_context(|| format!("missing dictionary blob {hash}"))?;
+
+        // The prepared dictionary outlives this source so make sure it isn't serving garbage.
+        verify_blob(hash, &stored).context("failed to load dictionary")?;
+
+        let dictionary = self.open_blob(hash, stored)?;
+        Ok(self
+            .dictionary_cache
+            .0
+            .get_or_init(|| DecoderDictionary::copy(&dictionary)))
+    }
+
+    /// Assembles the module into a freshly allocated buffer of exactly the right size.
+    ///
+    /// This always assembles serially, even with the `parallel` feature, since it accepts sources
+    /// which can't be shared across threads. Use [`assemble_parallel`](Self::assemble_parallel) for
+    /// sources which can.
+    pub fn assemble(&self, source: &(impl ?Sized + BlobSource)) -> anyhow::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(self.assembled_len()?.min(MAX_RESERVED_LEN));
+        self.assemble_into(source, &mut out)?;
+        Ok(out)
+    }
+
+    /// Assembles the module like [`assemble`](Self::assemble) but additionally checks the result
+    /// against the module hash recorded in the index.
+    pub fn assemble_verified(
+        &self,
+        source: &(impl ?Sized + BlobSource),
+    ) -> anyhow::Result<Vec<u8>> {
+        let module = self.assemble(source)?;
+        self.verify_module(&module)?;
+        Ok(module)
+    }
+
+    /// Assembles the module like [`assemble`](Self::assemble) but expands its segments in parallel on
+    /// the rayon thread pool. Each segment writes directly into its own region of the output so the
+    /// result is identical to a serial assembly. Without the `parallel` feature, or for modules too
+    /// large to allocate up front, this just assembles the module serially.
+    pub fn assemble_parallel(
+        &self,
+        source: &(impl ?Sized + BlobSource + Sync),
+    ) -> anyhow::Result<Vec<u8>> {
+        #[cfg(not(feature = "parallel"))]
+        return self.assemble(source);
+
+        #[cfg(feature = "parallel")]
+        self.assemble_parallel_inner(source)
+    }
+
+    /// Assembles the module like [`assemble_parallel`](Self::assemble_parallel) but additionally
+    /// checks the result against the module hash recorded in the index.
+    pub fn assemble_parallel_verified(
+        &self,
+        source: &(impl ?Sized + BlobSource + Sync),
+    ) -> anyhow::Result<Vec<u8>> {
+        let module = self.assemble_parallel(source)?;
+        self.verify_module(&module)?;
+        Ok(module)
+    }
+
+    #[cfg(feature = "parallel")]
+    fn assemble_parallel_inner(
+        &self,
+        source: &(impl ?Sized + BlobSource + Sync),
+    ) -> anyhow::Result<Vec<u8>> {
+        let _guard = self.track_offsets();
+        let segments = self.segments().collect::<anyhow::Result<Vec<_>>>()?;
+
+        let len = segments
+            .iter()
+            .try_fold(0usize, |len, segment| len.checked_add(segment.out_len()))
+            .context("assembled module is too big")?;
+
+        // The output can't be carved up without allocating all of it up front, which an index
+        // declaring absurd lengths shouldn't be able to make us do.
+        if len > MAX_RESERVED_LEN {
+            return self.assemble(source);
+        }
+
+        // Carve the output into one region per segment.
+        let mut out = vec![0; len];
+        let mut regions = Vec::with_capacity(segments.len());
+        let mut rest = &mut out[..];
+
+        for segment in &segments {
+            let (region, tail) = rest.split_at_mut(segment.out_len());
+            regions.push(region);
+            rest = tail;
+        }
+
+        segments
+            .par_iter()
+            .zip(regions.into_par_iter())
+            .try_for_each(|(segment, region)| {
+                let mut writer = SliceWriter::new(region);
+                self.assemble_segment(segment, source, &mut writer)?;
+                anyhow::ensure!(
+                    writer.remaining() == 0,
+                    "segment produced fewer bytes than it promised"
+                );
+                Ok(())
+            })?;
+
+        Ok(out)
+    }
+
+    /// Assembles the module into a caller-provided buffer, returning the number of bytes written.
+    /// The buffer must be at least [`assembled_len`](Self::assembled_len) bytes long.
+    pub fn assemble_into_slice(
+        &self,
+        source: &(impl ?Sized + BlobSource),
+        buf: &mut [u8],
+    ) -> anyhow::Result<usize> {
+        let len = self.assembled_len()?;
+        anyhow::ensure!(
+            buf.len() >= len,
+            "output buffer is too small to hold the assembled module (buffer length: {}, module length: {len})",
+            buf.len(),
+        );
+
+        let mut writer = SliceWriter::new(buf);
+        self.assemble_into(source, &mut writer)?;
+        debug_assert_eq!(writer.len(), len);
+        Ok(writer.len())
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum WasmallModSeg<'a> {
     Verbatim(WasmallModSegVerbatim<'a>),
     Blob(WasmallModSegBlob<'a>),
+    InlineBlob(WasmallModSegInlineBlob<'a>),
+}
+
+impl WasmallModSeg<'_> {
+    /// The number of bytes this segment contributes to the assembled module.
+    pub fn out_len(&self) -> usize {
+        match self {
+            WasmallModSeg::Verbatim(segment) => segment.data().len(),
+            WasmallModSeg::Blob(segment) => segment.out_len() as usize,
+            WasmallModSeg::InlineBlob(segment) => segment.out_len() as usize,
+        }
+    }
+}
+
+impl<'a> WasmallModSeg<'a> {
+    /// Parses a single encoded segment received independently of the rest of its index, checking
+    /// it against `proof` and a trusted merkle `root` before trusting any of its contents.
+    pub fn parse_proven(raw: &'a [u8], proof: &MerkleProof, root: Hash) -> anyhow::Result<Self> {
+        proof.verify(leaf_hash(raw), root)?;
+
+        let mut cursor = ByteCursor(raw);
+        let segment = Self::parse(&mut cursor)?;
+        anyhow::ensure!(cursor.at_eof(), "trailing bytes after proven segment");
+
+        Ok(segment)
+    }
 }
 
 impl<'a> ByteParse<'a> for WasmallModSeg<'a> {
@@ -255,26 +1646,20 @@ impl<'a> ByteParse<'a> for WasmallModSeg<'a> {
             {
                 SegmentKind::Verbatim => Self::Verbatim(WasmallModSegVerbatim::parse(buf)?),
                 SegmentKind::Blob => Self::Blob(WasmallModSegBlob::parse(buf)?),
+                SegmentKind::HeaderedBlob => Self::Blob(WasmallModSegBlob::parse_headered(buf)?),
+                SegmentKind::EditedBlob => Self::Blob(WasmallModSegBlob::parse_edited(buf)?),
+                SegmentKind::InlineBlob => Self::InlineBlob(WasmallModSegInlineBlob::parse(buf)?),
             },
         )
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, ByteParse)]
 pub struct WasmallModSegVerbatim<'a> {
+    #[byte_parse(with = VarByteVec, context = "verbatim segment data")]
     data: &'a [u8],
 }
 
-impl<'a> ByteParse<'a> for WasmallModSegVerbatim<'a> {
-    type Out = Self;
-
-    fn parse_naked(buf: &mut ByteCursor<'a>) -> anyhow::Result<Self::Out> {
-        Ok(WasmallModSegVerbatim {
-            data: VarByteVec::parse(buf).context("failed to read verbatim segment data")?,
-        })
-    }
-}
-
 impl<'a> WasmallModSegVerbatim<'a> {
     pub fn data(&self) -> &'a [u8] {
         self.data
@@ -284,7 +1669,31 @@ impl<'a> WasmallModSegVerbatim<'a> {
 #[derive(Debug, Clone)]
 pub struct WasmallModSegBlob<'a> {
     hash: &'a [u8],
+    encoding: BlobEncoding,
+    out_len: u32,
     reloc_values: &'a [u8],
+    restore: Restore<'a>,
+
+    /// The encoded form of `restore`.
+    restore_raw: &'a [u8],
+}
+
+/// How the original bytes of a normalized blob are restored.
+#[derive(Debug, Copy, Clone)]
+enum Restore<'a> {
+    None,
+
+    /// The original header replacing the blob's normalized header and the length of the latter.
+    Header(&'a [u8], u32),
+
+    /// The encoded list of edits to apply to the expanded blob and the length of the result.
+    Edits(&'a [u8], u32),
+}
+
+impl Restore<'_> {
+    fn is_none(&self) -> bool {
+        matches!(self, Self::None)
+    }
 }
 
 impl<'a> ByteParse<'a> for WasmallModSegBlob<'a> {
@@ -295,68 +1704,228 @@ impl<'a> ByteParse<'a> for WasmallModSegBlob<'a> {
             .consume(blake3::OUT_LEN)
             .context("failed to read blob hash")?;
 
+        let encoding =
+            buf.lookahead_annotated("blob encoding", |c| BlobEncoding::from_byte(c.read_u8()?))?;
+
+        let out_len = buf
+            .read_var_u32()
+            .context("failed to read blob output length")?;
+
         let reloc_values =
             VarByteVec::parse(buf).context("failed to read blob relocation values")?;
 
-        Ok(Self { hash, reloc_values })
+        Ok(Self {
+            hash,
+            encoding,
+            out_len,
+            reloc_values,
+            restore: Restore::None,
+            restore_raw: &[],
+        })
     }
 }
 
 impl<'a> WasmallModSegBlob<'a> {
+    fn parse_headered(buf: &mut ByteCursor<'a>) -> anyhow::Result<Self> {
+        let mut segment = Self::parse_naked(buf)?;
+
+        let ((original, normalized_len), raw) = buf.get_slice_read(|c| {
+            let original = VarByteVec::parse(c).context("failed to read original blob header")?;
+            let normalized_len = c
+                .read_var_u32()
+                .context("failed to read normalized blob header length")?;
+
+            Ok((original, normalized_len))
+        })?;
+
+        anyhow::ensure!(
+            normalized_len <= segment.out_len,
+            "normalized blob header is longer than the blob"
+        );
+
+        // Make sure `out_len` can't overflow.
+        u32::try_from(original.len())
+            .ok()
+            .and_then(|len| (segment.out_len - normalized_len).checked_add(len))
+            .context("blob is too long once its original header is restored")?;
+
+        segment.restore = Restore::Header(original, normalized_len);
+        segment.restore_raw = raw;
+        Ok(segment)
+    }
+
+    fn parse_edited(buf: &mut ByteCursor<'a>) -> anyhow::Result<Self> {
+        let mut segment = Self::parse_naked(buf)?;
+        let blob_len = segment.out_len;
+
+        // Make sure every edit lies within the blob and that `out_len` can't overflow.
+        let (out_len, edits) = buf.get_slice_read(|c| {
+            let _section = SectionTracker::new("the blob edit list", c.0);
+            let count = c.read_var_u32().context("failed to read blob edit count")?;
+            let (mut pos, mut out_len) = (0u32, 0u32);
+
+            for i in 0..count {
+                let skip = c.read_var_u32()?;
+                let len = c.read_var_u32()?;
+                let original = VarByteVec::parse(c)?;
+
+                pos = pos
+                    .checked_add(skip)
+                    .and_then(|start| start.checked_add(len))
+                    .filter(|&end| end <= blob_len)
+                    .with_context(|| format!("blob edit {i} lies outside of the blob"))?;
+
+                out_len = u32::try_from(original.len())
+                    .ok()
+                    .and_then(|len| out_len.checked_add(skip)?.checked_add(len))
+                    .context("blob is too long once its original bytes are restored")?;
+            }
+
+            out_len
+                .checked_add(blob_len - pos)
+                .context("blob is too long once its original bytes are restored")
+        })?;
+
+        segment.restore = Restore::Edits(edits, out_len);
+        segment.restore_raw = edits;
+        Ok(segment)
+    }
+
     pub fn hash(&self) -> Hash {
         Hash::from_bytes(self.hash.to_array())
     }
 
+    pub fn encoding(&self) -> BlobEncoding {
+        self.encoding
+    }
+
+    /// The number of bytes this segment contributes to the assembled module.
+    pub fn out_len(&self) -> u32 {
+        match self.restore {
+            // Checked when the segment is parsed.
+            Restore::Header(original, normalized_len) => {
+                self.out_len - normalized_len + original.len() as u32
+            }
+            Restore::Edits(_, out_len) => out_len,
+            Restore::None => self.out_len,
+        }
+    }
+
+    /// The number of bytes the blob itself expands to.
+    pub fn blob_len(&self) -> u32 {
+        self.out_len
+    }
+
+    /// The original header replacing the blob's normalized header, if only the blob's header was
+    /// normalized.
+    pub fn original_header(&self) -> Option<&'a [u8]> {
+        match self.restore {
+            Restore::Header(original, _) => Some(original),
+            _ => None,
+        }
+    }
+
+    /// Whether the blob was normalized, in which case it differs from the bytes it contributes to
+    /// the assembled module.
+    pub fn is_normalized(&self) -> bool {
+        !self.restore.is_none()
+    }
+
+    /// The encoded data restoring the original bytes of a normalized blob, which is empty for blobs
+    /// which weren't normalized.
+    pub fn restoration(&self) -> &'a [u8] {
+        self.restore_raw
+    }
+
     pub fn reloc_values(&self) -> ByteParseList<'a, VarU32> {
         ByteParseList::new(ByteCursor(self.reloc_values))
     }
 
-    pub fn write(&self, blob: &WasmallBlob<'_>, out: &mut Vec<u8>) -> anyhow::Result<()> {
-        // Collect the relocation values needed for this blob
-        let mut reloc_values = Vec::new();
+    pub fn write(&self, blob: &WasmallBlob<'_>, out: &mut impl BufWriter) -> anyhow::Result<()> {
+        // Ensure that the blob expands to the size the index promised. Relocations are always
+        // rewritten at full width so the expanded size is just the size of the blob's data.
+        anyhow::ensure!(
+            blob.data.len() == self.out_len as usize,
+            "blob {} has length {} but the index expects length {}",
+            self.hash(),
+            blob.data.len(),
+            self.out_len,
+        );
+
+        let mut expanded = Vec::with_capacity(self.out_len as usize);
+
+        match self.restore {
+            Restore::None => blob.expand(self.reloc_values(), out)?,
+            Restore::Header(original, normalized_len) => {
+                blob.expand(self.reloc_values(), &mut expanded)?;
+                out.extend(original);
+                out.extend(&expanded[normalized_len as usize..]);
+            }
+            Restore::Edits(edits, _) => {
+                blob.expand(self.reloc_values(), &mut expanded)?;
 
-        for reloc in self.reloc_values() {
-            reloc_values.push(reloc?);
-        }
+                // The edits were validated when the segment was parsed.
+                let mut cursor = ByteCursor(edits);
+                let mut pos = 0;
 
-        // Validate each relocation entry.
-        for reloc in blob.relocations() {
-            anyhow::ensure!((reloc?.index as usize) < reloc_values.len());
-        }
+                for _ in 0..cursor.read_var_u32()? {
+                    let skip = cursor.read_var_u32()? as usize;
+                    let len = cursor.read_var_u32()? as usize;
+                    let original = VarByteVec::parse(&mut cursor)?;
 
-        // Sanity checks
-        for reloc in blob.relocations() {
-            let reloc = reloc.unwrap();
+                    out.extend(&expanded[pos..pos + skip]);
+                    out.extend(original);
+                    pos += skip + len;
+                }
 
-            // Sanity check
-            debug_assert_eq!(
-                reloc
-                    .ty
-                    .rewrite_kind()
-                    .read(&mut ByteCursor(&blob.data[reloc.offset as usize..]))
-                    .unwrap()
-                    .as_u32(),
-                0,
-            );
+                out.extend(&expanded[pos..]);
+            }
         }
 
-        rewrite_relocated(
-            blob.data,
-            out,
-            &mut (),
-            blob.relocations().map(|reloc| {
-                let reloc = reloc.unwrap(); // relocations are pre-validated
+        Ok(())
+    }
+}
 
-                let val = reloc_values[reloc.index as usize]
-                    .wrapping_add(reloc.addend.unwrap_or(0) as u32);
+#[derive(Debug, Clone, ByteParse)]
+pub struct WasmallModSegInlineBlob<'a> {
+    #[byte_parse(leb, context = "blob output length")]
+    out_len: u32,
 
-                (
-                    reloc.offset as usize,
-                    reloc.ty.rewrite_kind().with_value(val),
-                )
-            }),
-        )?;
-        Ok(())
+    #[byte_parse(with = VarByteVec, context = "inline blob data")]
+    blob: &'a [u8],
+
+    #[byte_parse(with = VarByteVec, context = "blob relocation values")]
+    reloc_values: &'a [u8],
+}
+
+impl<'a> WasmallModSegInlineBlob<'a> {
+    pub fn out_len(&self) -> u32 {
+        self.out_len
+    }
+
+    pub fn blob(&self) -> anyhow::Result<WasmallBlob<'a>> {
+        WasmallBlob::parse(&mut ByteCursor(self.blob))
+    }
+
+    pub fn blob_bytes(&self) -> &'a [u8] {
+        self.blob
+    }
+
+    pub fn reloc_values(&self) -> ByteParseList<'a, VarU32> {
+        ByteParseList::new(ByteCursor(self.reloc_values))
+    }
+
+    pub fn write(&self, out: &mut impl BufWriter) -> anyhow::Result<()> {
+        let blob = self.blob()?;
+
+        anyhow::ensure!(
+            blob.data.len() == self.out_len as usize,
+            "inline blob has length {} but the index expects length {}",
+            blob.data.len(),
+            self.out_len,
+        );
+
+        blob.expand(self.reloc_values(), out)
     }
 }
 
@@ -400,4 +1969,389 @@ impl<'a> WasmallBlob<'a> {
     pub fn data(&self) -> &'a [u8] {
         self.data
     }
+
+    /// Writes out the blob's data with its relocations substituted for the specified values.
+    pub fn expand(
+        &self,
+        reloc_values_list: ByteParseList<'_, VarU32>,
+        out: &mut impl BufWriter,
+    ) -> anyhow::Result<()> {
+        // Collect the relocation values needed for this blob
+        let mut reloc_values = Vec::new();
+
+        for reloc in reloc_values_list {
+            reloc_values.push(reloc?);
+        }
+
+        // Validate each relocation entry.
+        let mut relocations = Vec::new();
+
+        for (entry, reloc) in self.relocations().enumerate() {
+            let reloc = reloc?;
+
+            if reloc.index as usize >= reloc_values.len() {
+                return Err(RewriteError::BadIndex {
+                    entry,
+                    index: reloc.index,
+                    value_count: reloc_values.len(),
+                }
+                .into());
+            }
+
+            relocations.push((reloc.offset as usize, reloc.ty.rewrite_kind()));
+        }
+
+        validate_relocations(relocations.iter().copied(), self.data.len())?;
+        out.reserve(estimate_rewritten_len(self.data, relocations));
+
+        // Blobs come from untrusted stores so we can't assume they were zeroed by a `WasmallWriter`.
+        for (entry, reloc) in self.relocations().enumerate() {
+            let reloc = reloc?;
+            let value = reloc
+                .ty
+                .rewrite_kind()
+                .read(&mut ByteCursor(&self.data[reloc.offset as usize..]))?
+                .as_u64();
+
+            anyhow::ensure!(
+                value == 0,
+                "relocation {entry} targets offset {:#x}, which holds {value} rather than zero",
+                reloc.offset,
+            );
+        }
+
+        rewrite_relocated(
+            self.data,
+            out,
+            &mut (),
+            self.relocations().map(|reloc| {
+                let reloc = reloc.unwrap(); // relocations are pre-validated
+
+                let val = u64::from(reloc_values[reloc.index as usize])
+                    .wrapping_add_signed(reloc.addend.unwrap_or(0));
+
+                (
+                    reloc.offset as usize,
+                    reloc.ty.rewrite_kind().with_value_u64(val),
+                )
+            }),
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reloc::RelocEntryType;
+
+    /// Encodes a headered blob segment with no relocations.
+    fn headered_segment(out_len: u32, original: &[u8], normalized_len: u32) -> Vec<u8> {
+        let mut buf = vec![SegmentKind::HeaderedBlob as u8];
+        buf.extend_from_slice(&[0; blake3::OUT_LEN]);
+        buf.push(BlobEncoding::Raw as u8);
+        buf.write_var_u32(out_len);
+        buf.write_var_u32(0);
+        buf.write_var_u32(original.len() as u32);
+        buf.extend_from_slice(original);
+        buf.write_var_u32(normalized_len);
+        buf
+    }
+
+    #[test]
+    fn headered_blob_out_len() {
+        let raw = headered_segment(10, &[1, 2, 3], 2);
+        let segment = WasmallModSeg::parse(&mut ByteCursor(&raw)).unwrap();
+        assert_eq!(segment.out_len(), 11);
+    }
+
+    #[test]
+    fn headered_blob_rejects_long_normalized_header() {
+        let raw = headered_segment(10, &[], 11);
+        assert!(WasmallModSeg::parse(&mut ByteCursor(&raw)).is_err());
+    }
+
+    #[test]
+    fn headered_blob_rejects_overflowing_out_len() {
+        let raw = headered_segment(u32::MAX, &[1, 2], 1);
+        assert!(WasmallModSeg::parse(&mut ByteCursor(&raw)).is_err());
+    }
+
+    /// Encodes an edited blob segment with no relocations. Each edit is given as its distance from
+    /// the end of the previous one, its length, and the original bytes it replaces.
+    fn edited_segment(out_len: u32, edits: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let mut buf = vec![SegmentKind::EditedBlob as u8];
+        buf.extend_from_slice(&[0; blake3::OUT_LEN]);
+        buf.push(BlobEncoding::Raw as u8);
+        buf.write_var_u32(out_len);
+        buf.write_var_u32(0);
+        buf.write_var_u32(edits.len() as u32);
+        for &(skip, len, original) in edits {
+            buf.write_var_u32(skip);
+            buf.write_var_u32(len);
+            buf.write_var_u32(original.len() as u32);
+            buf.extend_from_slice(original);
+        }
+        buf
+    }
+
+    #[test]
+    fn edited_blob_out_len() {
+        let raw = edited_segment(10, &[(0, 1, &[1, 2, 3]), (4, 2, &[])]);
+        let segment = WasmallModSeg::parse(&mut ByteCursor(&raw)).unwrap();
+        assert_eq!(segment.out_len(), 10);
+    }
+
+    #[test]
+    fn edited_blob_rejects_edit_past_end() {
+        let raw = edited_segment(10, &[(2, 3, &[]), (4, 2, &[])]);
+        assert!(WasmallModSeg::parse(&mut ByteCursor(&raw)).is_err());
+    }
+
+    #[test]
+    fn edited_blob_rejects_overflowing_out_len() {
+        let raw = edited_segment(u32::MAX, &[(0, 1, &[1, 2])]);
+        assert!(WasmallModSeg::parse(&mut ByteCursor(&raw)).is_err());
+    }
+
+    #[test]
+    fn edited_blob_restores_original() {
+        let mut writer = WasmallWriter::new(WriterOptions::default());
+        let data = (0..200).map(|i| i as u8).collect::<Vec<_>>();
+        let edits = [
+            BlobEdit {
+                offset: 0,
+                len: 2,
+                original: vec![0xAA],
+            },
+            BlobEdit {
+                offset: 100,
+                len: 1,
+                original: vec![0xBB, 0xCC, 0xDD],
+            },
+        ];
+        writer.push_blob_with_edits(&[], &[], &data, &edits);
+
+        let archive = writer.finish().unwrap();
+        let module = WasmallMod::parse(&mut ByteCursor(&archive.out_buf)).unwrap();
+
+        let mut expected = vec![0xAA];
+        expected.extend_from_slice(&data[2..100]);
+        expected.extend_from_slice(&[0xBB, 0xCC, 0xDD]);
+        expected.extend_from_slice(&data[101..]);
+        assert_eq!(module.assemble(&archive).unwrap(), expected);
+    }
+
+    /// Encodes a blob holding `data` with `relocations` applied to it, along with the list of
+    /// `values` to expand it with.
+    fn encoded_blob(relocations: &[RelocEntry], data: &[u8], values: &[u32]) -> (Vec<u8>, Vec<u8>) {
+        let mut blob = Vec::new();
+        blob.write_var_u32(relocations.len() as u32);
+        for reloc in relocations {
+            reloc.write(&mut blob);
+        }
+        blob.extend_from_slice(data);
+
+        let mut value_buf = Vec::new();
+        for &value in values {
+            value_buf.write_var_u32(value);
+        }
+
+        (blob, value_buf)
+    }
+
+    fn expand_blob(
+        relocations: &[RelocEntry],
+        data: &[u8],
+        values: &[u32],
+    ) -> anyhow::Result<Vec<u8>> {
+        let (blob, values) = encoded_blob(relocations, data, values);
+        let mut out = Vec::new();
+        WasmallBlob::parse(&mut ByteCursor(&blob))?
+            .expand(ByteParseList::new(ByteCursor(&values)), &mut out)?;
+        Ok(out)
+    }
+
+    fn func_reloc(offset: u32, index: u32) -> RelocEntry {
+        RelocEntry {
+            ty: RelocEntryType::FunctionIndexLeb,
+            offset,
+            index,
+            addend: None,
+        }
+    }
+
+    const ZEROED_LEB: [u8; 5] = [0x80, 0x80, 0x80, 0x80, 0x00];
+
+    #[test]
+    fn blob_expands_relocations() {
+        let out = expand_blob(&[func_reloc(0, 1)], &ZEROED_LEB, &[7, 3]).unwrap();
+        assert_eq!(out, [0x83, 0x80, 0x80, 0x80, 0x00]);
+    }
+
+    #[test]
+    fn blob_rejects_bad_reloc_index() {
+        let err = expand_blob(&[func_reloc(0, 1)], &ZEROED_LEB, &[7]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&RewriteError::BadIndex {
+                entry: 0,
+                index: 1,
+                value_count: 1,
+            }),
+        );
+    }
+
+    #[test]
+    fn blob_rejects_overlapping_relocations() {
+        let err =
+            expand_blob(&[func_reloc(0, 0), func_reloc(3, 0)], &[0x80; 10], &[7]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&RewriteError::Overlapping {
+                entry: 1,
+                offset: 3,
+                prev_end: 5,
+            }),
+        );
+    }
+
+    #[test]
+    fn blob_rejects_nonzero_relocations() {
+        let data = [0x81, 0x80, 0x80, 0x80, 0x00];
+        assert!(expand_blob(&[func_reloc(0, 0)], &data, &[7]).is_err());
+    }
+
+    /// Builds an index with a merkle root and `count` segments, alternating between verbatim and
+    /// blob segments.
+    fn merkle_archive(count: usize) -> WasmallArchive {
+        let mut writer = WasmallWriter::new(WriterOptions {
+            merkle: true,
+            ..WriterOptions::default()
+        });
+
+        for i in 0..count {
+            if i % 2 == 0 {
+                writer.push_verbatim(|sink| sink.extend_from_slice(&[i as u8; 3]));
+            } else {
+                writer.push_blob(&[], &[], &[i as u8; 200]);
+            }
+        }
+
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn proven_segments_parse() {
+        for count in [1, 2, 3, 6, 7] {
+            let archive = merkle_archive(count);
+            let module = WasmallMod::parse(&mut ByteCursor(&archive.out_buf)).unwrap();
+            let (tree, root) = (module.merkle_tree().unwrap(), archive.merkle_root.unwrap());
+            assert_eq!(tree.root(), root);
+
+            let raw = module
+                .raw_segments()
+                .collect::<anyhow::Result<Vec<_>>>()
+                .unwrap();
+            assert_eq!(raw.len(), count);
+
+            for (i, raw) in raw.iter().enumerate() {
+                let proof = tree.proof(i).unwrap();
+                let segment = WasmallModSeg::parse_proven(raw, &proof, root).unwrap();
+                assert_eq!(
+                    matches!(segment, WasmallModSeg::Blob(_)),
+                    i % 2 == 1,
+                    "segment {i} of {count}"
+                );
+
+                // The segment doesn't belong at any other index.
+                for index in (0..count).filter(|&index| index != i) {
+                    let proof = MerkleProof {
+                        index,
+                        ..proof.clone()
+                    };
+                    assert!(WasmallModSeg::parse_proven(raw, &proof, root).is_err());
+                }
+
+                // Nor does it verify with a tampered sibling.
+                for j in 0..proof.siblings.len() {
+                    let mut flipped = proof.clone();
+                    let mut sibling = *flipped.siblings[j].as_bytes();
+                    sibling[31] ^= 0x80;
+                    flipped.siblings[j] = Hash::from_bytes(sibling);
+                    assert!(WasmallModSeg::parse_proven(raw, &flipped, root).is_err());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn proven_segments_reject_tampering() {
+        let archive = merkle_archive(4);
+        let module = WasmallMod::parse(&mut ByteCursor(&archive.out_buf)).unwrap();
+        let tree = module.merkle_tree().unwrap();
+        let root = tree.root();
+        let raw = module.raw_segments().nth(1).unwrap().unwrap();
+        let proof = tree.proof(1).unwrap();
+
+        let mut tampered = raw.to_vec();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(WasmallModSeg::parse_proven(&tampered, &proof, root).is_err());
+
+        // Trailing bytes are rejected even when the proof covers them.
+        let mut trailing = raw.to_vec();
+        trailing.push(0);
+        let mut leaves = tree.leaves().to_vec();
+        leaves[1] = leaf_hash(&trailing);
+        let tree = MerkleTree::from_leaves(leaves);
+        assert!(
+            WasmallModSeg::parse_proven(&trailing, &tree.proof(1).unwrap(), tree.root())
+                .unwrap_err()
+                .to_string()
+                .contains("trailing bytes")
+        );
+    }
+
+    #[test]
+    fn parallel_assembly_is_verified() {
+        let mut writer = WasmallWriter::new(WriterOptions::default());
+        writer.push_blob(&[], &[], &[7; 200]);
+        let archive = writer.finish().unwrap();
+
+        let mut module = WasmallMod::parse(&mut ByteCursor(&archive.out_buf)).unwrap();
+        assert_eq!(
+            module.assemble_parallel_verified(&archive).unwrap(),
+            [7; 200]
+        );
+
+        module.module_hash = blake3::hash(b"some other module");
+        assert!(module.assemble_parallel_verified(&archive).is_err());
+    }
+
+    #[test]
+    fn assembly_does_not_trust_declared_lengths() {
+        let archive = WasmallWriter::new(WriterOptions::default())
+            .finish()
+            .unwrap();
+        let mut module = WasmallMod::parse(&mut ByteCursor(&archive.out_buf)).unwrap();
+
+        let mut segments = Vec::new();
+        for _ in 0..4000 {
+            segments.push(SegmentKind::InlineBlob as u8);
+            segments.write_var_u32(u32::MAX);
+            segments.extend_from_slice(&[1, 0, 0]);
+        }
+        module.segments = &segments;
+
+        assert!(module.assembled_len().unwrap() > MAX_RESERVED_LEN);
+        assert!(module.assemble(&archive).is_err());
+        assert!(module.assemble_parallel(&archive).is_err());
+    }
+
+    #[test]
+    #[should_panic = "normalized blob header is longer than the blob"]
+    fn writer_rejects_long_normalized_header() {
+        let mut writer = WasmallWriter::new(WriterOptions::default());
+        writer.push_blob_with_header(&[], &[], &[0; 4], Some((&[1], 5)));
+    }
 }
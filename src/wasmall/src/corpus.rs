@@ -0,0 +1,533 @@
+//! A harness for checking that modules survive being split and reassembled.
+//!
+//! Each module is split and assembled under every configured [`CorpusConfig`] and compared against
+//! its [canonical form](canonicalize). Splitting drops custom sections and re-encodes section sizes
+//! minimally, so this is the exact output a correct round-trip must produce. Header normalization
+//! restores the original headers during assembly and is held to the same standard. Relocatable
+//! modules additionally have their `linking` and `reloc.*` sections checked to re-encode stably.
+//!
+//! The harness is driven by the `corpus` binary in CI but is also usable as a library for running
+//! downstream corpora.
+
+use std::{
+    borrow::Cow,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+#[cfg(feature = "encryption")]
+use crate::crypt::{BlobCipher, BlobKey, SingleKey};
+use crate::{
+    builder::SectionWriteExt,
+    chunker::ChunkingOptions,
+    coder::{CompressionOptions, WasmallMod, WasmallModSeg, WriterOptions},
+    reloc::check_metadata_round_trip,
+    splitter::{is_component, split_module_with, SplitOptions},
+    store::{verify_archive, BlobSource, MemoryBlobSource},
+    stream::StreamingDecoder,
+    util::{ByteCursor, ByteParse},
+};
+
+/// The number of bytes shown on either side of the first differing offset in a mismatch report.
+const DIFF_CONTEXT: usize = 8;
+
+// === CorpusConfig === //
+
+/// A pipeline configuration modules are round-tripped through.
+#[derive(Debug, Clone)]
+pub struct CorpusConfig {
+    pub name: String,
+    pub options: SplitOptions,
+
+    /// Whether to assemble with [`WasmallMod::assemble_parallel`].
+    pub parallel: bool,
+
+    /// Whether to assemble with a [`StreamingDecoder`] which is fed every other blob before the
+    /// rest, so that segments are expanded both out of order and in several batches.
+    pub streaming: bool,
+
+    /// The key used to unlock the index if `options` encrypts its blobs.
+    #[cfg(feature = "encryption")]
+    pub keys: Option<SingleKey>,
+}
+
+impl CorpusConfig {
+    pub fn new(name: impl Into<String>, options: SplitOptions) -> Self {
+        Self {
+            name: name.into(),
+            options,
+            parallel: false,
+            streaming: false,
+            #[cfg(feature = "encryption")]
+            keys: None,
+        }
+    }
+
+    /// Creates a configuration encrypting every blob with `key`.
+    #[cfg(feature = "encryption")]
+    pub fn encrypted(name: impl Into<String>, mut options: SplitOptions, key: BlobKey) -> Self {
+        let key_id = b"corpus".to_vec();
+        options.writer.encryption = Some(BlobCipher::new(key_id.clone(), key.clone()));
+
+        Self {
+            keys: Some(SingleKey { key_id, key }),
+            ..Self::new(name, options)
+        }
+    }
+
+    /// A set of configurations exercising every major feature of the pipeline.
+    pub fn standard() -> Vec<Self> {
+        let uncompressed = SplitOptions {
+            writer: WriterOptions {
+                compression: CompressionOptions::disabled(),
+                inline_threshold: 0,
+                ..WriterOptions::default()
+            },
+            ..SplitOptions::default()
+        };
+
+        let shared = SplitOptions {
+            share_sections: true,
+            ..SplitOptions::default()
+        };
+
+        let dictionary = SplitOptions {
+            writer: WriterOptions {
+                compression: CompressionOptions {
+                    dictionary_size: 4 * 1024,
+                    ..CompressionOptions::default()
+                },
+                ..WriterOptions::default()
+            },
+            ..SplitOptions::default()
+        };
+
+        let chunked = SplitOptions {
+            chunking: Some(ChunkingOptions::default()),
+            ..SplitOptions::default()
+        };
+
+        // Small enough to chunk the data segments of small modules too.
+        let data_chunked = SplitOptions {
+            data_chunking: Some(ChunkingOptions {
+                min_size: 64,
+                avg_size: 256,
+                max_size: 1024,
+            }),
+            ..SplitOptions::default()
+        };
+
+        let symbols = SplitOptions {
+            symbol_names: true,
+            ..data_chunked.clone()
+        };
+
+        let parallel = SplitOptions {
+            writer: WriterOptions {
+                parallel: true,
+                ..WriterOptions::default()
+            },
+            ..SplitOptions::default()
+        };
+
+        let normalized = SplitOptions {
+            writer: WriterOptions {
+                merkle: true,
+                ..WriterOptions::default()
+            },
+            normalize: true,
+            ..SplitOptions::default()
+        };
+
+        #[cfg_attr(not(feature = "encryption"), allow(unused_mut))]
+        let mut configs = vec![
+            Self::new("default", SplitOptions::default()),
+            Self::new("uncompressed", uncompressed),
+            Self::new("normalized", normalized),
+            Self::new("shared-sections", shared),
+            Self::new("dictionary", dictionary),
+            Self::new("chunked", chunked),
+            Self::new("data-chunked", data_chunked),
+            Self::new("symbols", symbols),
+            Self {
+                parallel: true,
+                ..Self::new("parallel", parallel)
+            },
+            Self {
+                streaming: true,
+                ..Self::new("streaming", SplitOptions::default())
+            },
+        ];
+
+        #[cfg(feature = "encryption")]
+        configs.push(Self::encrypted(
+            "encrypted",
+            SplitOptions::default(),
+            BlobKey::from_bytes([0x5A; 32]),
+        ));
+
+        configs
+    }
+
+    /// Configurations which skip validation, for corpora containing modules [`wasmparser`] can't
+    /// validate.
+    pub fn without_validation(configs: impl IntoIterator<Item = Self>) -> Vec<Self> {
+        configs
+            .into_iter()
+            .map(|mut config| {
+                config.options.validate = false;
+                config
+            })
+            .collect()
+    }
+}
+
+// === Round-trips === //
+
+/// Strips the custom sections of a module and re-encodes its section sizes minimally, producing
+/// exactly what a round-trip through the splitter should yield. Components keep their own custom
+/// sections but have the core modules they embed canonicalized.
+pub fn canonicalize(src: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let component = is_component(src);
+    let mut cursor = ByteCursor(src);
+    let header = cursor.consume(8).context("module is missing its header")?;
+
+    let mut out = header.to_vec();
+
+    while !cursor.at_eof() {
+        let id = cursor.read_u8()?;
+        let len = cursor.read_var_u32()?;
+        let data = cursor
+            .consume(len as usize)
+            .context("section extends past the end of the module")?;
+
+        let data = match id {
+            0 if !component => continue,
+            1 | 4 if component => Cow::Owned(canonicalize(data)?),
+            _ => Cow::Borrowed(data),
+        };
+
+        out.write_section(id, &data)?;
+    }
+
+    Ok(out)
+}
+
+/// Splits and reassembles a module under the specified configuration.
+pub fn round_trip(src: &[u8], config: &CorpusConfig) -> anyhow::Result<Vec<u8>> {
+    let archive = split_module_with(src, &config.options)
+        .context("failed to split module")?
+        .archive;
+
+    // Parallel encoding must produce exactly what a serial one would.
+    if config.options.writer.parallel {
+        let mut serial = config.options.clone();
+        serial.writer.parallel = false;
+
+        let expected = split_module_with(src, &serial)
+            .context("failed to split module serially")?
+            .archive;
+
+        anyhow::ensure!(
+            archive.out_buf == expected.out_buf && archive.blob_buf == expected.blob_buf,
+            "parallel encoding produced a different archive than serial encoding",
+        );
+    }
+
+    #[cfg_attr(not(feature = "encryption"), allow(unused_mut))]
+    let mut module =
+        WasmallMod::parse(&mut ByteCursor(&archive.out_buf)).context("failed to parse index")?;
+
+    #[cfg(feature = "encryption")]
+    if let Some(keys) = &config.keys {
+        module.unlock(keys)?;
+    }
+
+    if config.options.writer.merkle {
+        module.verify_merkle_root()?;
+    }
+
+    verify_archive(&module, &archive)?.into_result()?;
+
+    if config.options.symbol_names {
+        let segments = module.segments().collect::<anyhow::Result<Vec<_>>>()?;
+
+        for symbol in module.symbols() {
+            let symbol = symbol?;
+            anyhow::ensure!(
+                matches!(
+                    segments.get(symbol.segment as usize),
+                    Some(WasmallModSeg::Blob(_) | WasmallModSeg::InlineBlob(_)),
+                ),
+                "symbol {:?} names segment {}, which is not a blob",
+                symbol.name,
+                symbol.segment,
+            );
+        }
+    }
+
+    // A single flipped bit in a blob must be caught by both assembly and auditing. The dictionary
+    // is cached once the audit has checked it, so only regular blobs are tampered with.
+    let victim = module
+        .fetch_order()?
+        .into_iter()
+        .find(|&hash| module.dictionary_hash() != Some(hash));
+
+    if let Some(hash) = victim {
+        let mut tampered = MemoryBlobSource::default();
+        for (&hash, range) in &archive.hashes {
+            tampered.insert(hash, Cow::Borrowed(&archive.blob_buf[range.clone()]));
+        }
+
+        let mut blob = archive.get_blob(hash)?.unwrap().into_owned();
+        blob[0] ^= 1;
+        tampered.insert(hash, Cow::Owned(blob));
+
+        anyhow::ensure!(
+            module.assemble(&tampered).is_err(),
+            "assembly accepted tampered blob {hash}",
+        );
+
+        let audit = verify_archive(&module, &tampered)?;
+        anyhow::ensure!(
+            audit.missing.is_empty() && audit.damaged.len() == 1,
+            "auditing tampered blob {hash} reported {} missing and {} damaged blob(s)",
+            audit.missing.len(),
+            audit.damaged.len(),
+        );
+    }
+
+    let out = if config.streaming {
+        let mut decoder = StreamingDecoder::new(module.clone())?;
+        let required = decoder.required_blobs().to_vec();
+        let (evens, odds) = required
+            .iter()
+            .enumerate()
+            .partition::<Vec<_>, _>(|(i, _)| i % 2 == 0);
+
+        for (_, &hash) in evens.into_iter().chain(odds) {
+            let blob = archive
+                .get_blob(hash)?
+                .with_context(|| format!("missing blob {hash}"))?;
+            decoder.feed_blob(hash, blob.into_owned())?;
+        }
+
+        decoder.finish()?
+    } else if config.parallel {
+        module.assemble_parallel(&archive)?
+    } else {
+        module.assemble(&archive)?
+    };
+
+    module.verify_module(&out)?;
+    Ok(out)
+}
+
+/// The way in which a round-trip failed.
+#[derive(Debug)]
+pub enum MismatchKind {
+    /// The module could not be canonicalized.
+    Unreadable(anyhow::Error),
+
+    /// The pipeline reported an error.
+    Failed(anyhow::Error),
+
+    /// The module's linking or relocation sections failed to re-encode stably.
+    Metadata(anyhow::Error),
+
+    /// The pipeline produced different bytes. Either side may simply end at `offset`.
+    Differs {
+        offset: usize,
+        expected_len: usize,
+        actual_len: usize,
+        expected: Vec<u8>,
+        actual: Vec<u8>,
+    },
+}
+
+impl MismatchKind {
+    fn compare(expected: &[u8], actual: &[u8]) -> Option<Self> {
+        let offset = expected
+            .iter()
+            .zip(actual)
+            .position(|(a, b)| a != b)
+            .or_else(|| {
+                (expected.len() != actual.len()).then(|| expected.len().min(actual.len()))
+            })?;
+
+        let window = |data: &[u8]| {
+            data[offset.saturating_sub(DIFF_CONTEXT)..(offset + DIFF_CONTEXT).min(data.len())]
+                .to_vec()
+        };
+
+        Some(Self::Differs {
+            offset,
+            expected_len: expected.len(),
+            actual_len: actual.len(),
+            expected: window(expected),
+            actual: window(actual),
+        })
+    }
+}
+
+impl fmt::Display for MismatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unreadable(err) => write!(f, "unreadable module: {err:#}"),
+            Self::Failed(err) => write!(f, "pipeline failed: {err:#}"),
+            Self::Metadata(err) => write!(f, "linking metadata failed to round-trip: {err:#}"),
+            Self::Differs {
+                offset,
+                expected_len,
+                actual_len,
+                expected,
+                actual,
+            } => {
+                write!(
+                    f,
+                    "first difference at offset {offset:#x} (expected {expected_len} bytes, got \
+                     {actual_len}); starting at {:#x}, expected {expected:02x?} but got {actual:02x?}",
+                    offset.saturating_sub(DIFF_CONTEXT),
+                )
+            }
+        }
+    }
+}
+
+/// Round-trips a module under the specified configuration, returning the way it failed, if any.
+pub fn check_module(src: &[u8], config: &CorpusConfig) -> Option<MismatchKind> {
+    let expected = match canonicalize(src) {
+        Ok(expected) => expected,
+        Err(err) => return Some(MismatchKind::Unreadable(err)),
+    };
+
+    match round_trip(src, config) {
+        Ok(actual) => MismatchKind::compare(&expected, &actual),
+        Err(err) => Some(MismatchKind::Failed(err)),
+    }
+}
+
+// === CorpusReport === //
+
+#[derive(Debug)]
+pub struct Mismatch {
+    pub path: PathBuf,
+    pub config: String,
+    pub kind: MismatchKind,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} [{}]: {}",
+            self.path.display(),
+            self.config,
+            self.kind
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CorpusReport {
+    /// The number of modules checked.
+    pub modules: usize,
+
+    /// The number of round-trips performed.
+    pub runs: usize,
+
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl CorpusReport {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+
+    /// Checks a single module under every configuration, recording any mismatches. Linking
+    /// metadata is checked once per module and reported under the `metadata` configuration.
+    pub fn check(&mut self, path: &Path, src: &[u8], configs: &[CorpusConfig]) {
+        self.modules += 1;
+
+        if let Err(err) = check_metadata_round_trip(src) {
+            self.mismatches.push(Mismatch {
+                path: path.to_path_buf(),
+                config: "metadata".to_string(),
+                kind: MismatchKind::Metadata(err),
+            });
+        }
+
+        for config in configs {
+            self.runs += 1;
+
+            if let Some(kind) = check_module(src, config) {
+                self.mismatches.push(Mismatch {
+                    path: path.to_path_buf(),
+                    config: config.name.clone(),
+                    kind,
+                });
+            }
+        }
+    }
+
+    /// Fails with a description of every mismatch if there were any.
+    pub fn ensure_ok(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.is_ok(), "{self}");
+        Ok(())
+    }
+}
+
+impl fmt::Display for CorpusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for mismatch in &self.mismatches {
+            writeln!(f, "{mismatch}")?;
+        }
+
+        write!(
+            f,
+            "{} module(s), {} run(s), {} mismatch(es)",
+            self.modules,
+            self.runs,
+            self.mismatches.len()
+        )
+    }
+}
+
+/// Finds every `.wasm` file under `dir`, recursively and in a stable order.
+pub fn find_modules(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut modules = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let read_dir =
+            std::fs::read_dir(&dir).with_context(|| format!("failed to list directory {dir:?}"))?;
+
+        for dirent in read_dir {
+            let dirent = dirent?;
+            let path = dirent.path();
+
+            if dirent.file_type()?.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "wasm") {
+                modules.push(path);
+            }
+        }
+    }
+
+    modules.sort();
+    Ok(modules)
+}
+
+/// Round-trips every `.wasm` file under `dir` through every configuration.
+pub fn run_corpus(dir: &Path, configs: &[CorpusConfig]) -> anyhow::Result<CorpusReport> {
+    let mut report = CorpusReport::default();
+
+    for path in find_modules(dir)? {
+        let src = std::fs::read(&path).with_context(|| format!("failed to read {path:?}"))?;
+        report.check(&path, &src, configs);
+    }
+
+    Ok(report)
+}
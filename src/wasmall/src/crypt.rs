@@ -0,0 +1,139 @@
+//! Optional authenticated encryption of stored blobs.
+//!
+//! Every blob referenced by an encrypted index is sealed with XChaCha20-Poly1305 under a single
+//! per-bundle key whose ID is recorded in the index. Nonces are derived from the plaintext with a
+//! keyed hash so identical blobs still encrypt to identical bytes and continue to deduplicate
+//! across module versions. This only reveals whether two blobs sealed under the same key are
+//! equal, which their hashes already did.
+//!
+//! Verbatim segments and the index itself are never encrypted.
+
+use chacha20poly1305::{
+    aead::{Aead, Payload},
+    KeyInit, XChaCha20Poly1305, XNonce,
+};
+
+const NONCE_LEN: usize = 24;
+
+const NONCE_CONTEXT: &str = "wasmall 2024 blob nonce";
+
+// === BlobKey === //
+
+/// A 256-bit key used to seal the blobs of a bundle.
+#[derive(Clone)]
+pub struct BlobKey([u8; 32]);
+
+impl BlobKey {
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for BlobKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BlobKey(..)")
+    }
+}
+
+/// Resolves the key IDs recorded in indices to their keys.
+pub trait KeyProvider {
+    fn get_key(&self, key_id: &[u8]) -> anyhow::Result<Option<BlobKey>>;
+}
+
+impl<T: ?Sized + KeyProvider> KeyProvider for &'_ T {
+    fn get_key(&self, key_id: &[u8]) -> anyhow::Result<Option<BlobKey>> {
+        (**self).get_key(key_id)
+    }
+}
+
+/// A provider with exactly one key.
+#[derive(Debug, Clone)]
+pub struct SingleKey {
+    pub key_id: Vec<u8>,
+    pub key: BlobKey,
+}
+
+impl KeyProvider for SingleKey {
+    fn get_key(&self, key_id: &[u8]) -> anyhow::Result<Option<BlobKey>> {
+        Ok((key_id == self.key_id).then(|| self.key.clone()))
+    }
+}
+
+// === BlobCipher === //
+
+#[derive(Clone)]
+pub struct BlobCipher {
+    key: BlobKey,
+    key_id: Vec<u8>,
+    aead: XChaCha20Poly1305,
+}
+
+impl std::fmt::Debug for BlobCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlobCipher")
+            .field("key_id", &self.key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BlobCipher {
+    pub fn new(key_id: impl Into<Vec<u8>>, key: BlobKey) -> Self {
+        Self {
+            aead: XChaCha20Poly1305::new(key.as_bytes().into()),
+            key,
+            key_id: key_id.into(),
+        }
+    }
+
+    pub fn key_id(&self) -> &[u8] {
+        &self.key_id
+    }
+
+    /// Seals a blob, producing the nonce followed by the ciphertext and its tag.
+    pub fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce_key = blake3::derive_key(NONCE_CONTEXT, self.key.as_bytes());
+        let nonce = blake3::keyed_hash(&nonce_key, plaintext);
+        let nonce = XNonce::from_slice(&nonce.as_bytes()[..NONCE_LEN]);
+
+        let ciphertext = self
+            .aead
+            .encrypt(
+                nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: &self.key_id,
+                },
+            )
+            .expect("failed to encrypt blob");
+
+        let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(nonce);
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    /// Opens a blob produced by [`seal`](Self::seal), failing if it was tampered with or sealed
+    /// under a different key.
+    pub fn open(&self, sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+        anyhow::ensure!(sealed.len() >= NONCE_LEN, "encrypted blob is truncated");
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+
+        self.aead
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &self.key_id,
+                },
+            )
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "failed to decrypt blob; the key is wrong or the blob was tampered with"
+                )
+            })
+    }
+}
@@ -0,0 +1,351 @@
+//! Comparison of two archives, for measuring how much of a new build can be served from the blobs
+//! cached for an old one.
+//!
+//! Blobs are compared by hash, so the sharing statistics hold for any pair of archives. The
+//! per-function breakdown additionally needs both archives to store one blob per function body,
+//! which rules out components and chunked archives. Like [`FunctionDiff`], it pairs functions up
+//! by their index, so inserting a function shifts every body after it. Such bodies are reported as
+//! [`Shifted`](FunctionChangeKind::Shifted) rather than as code changes since their blobs are still
+//! cached.
+//!
+//! Blobs and functions are reported along with the names of their symbols if the archives were
+//! split with [`symbol_names`](crate::splitter::SplitOptions::symbol_names) enabled.
+//!
+//! [`FunctionDiff`]: crate::incremental::FunctionDiff
+
+use std::collections::hash_map;
+
+use anyhow::Context;
+use blake3::Hash;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::{
+    coder::{WasmallArchive, WasmallMod, WasmallModSeg},
+    incremental::FunctionLayout,
+    util::{ByteCursor, ByteParse},
+};
+
+// === ArchiveDiff === //
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BlobInfo {
+    pub hash: Hash,
+
+    /// The size of the blob as stored, which is what fetching it transfers.
+    pub stored_len: usize,
+
+    /// The names of the symbols the blob holds, in index order, if the index records them.
+    pub names: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ArchiveDiff {
+    /// Blobs referenced by both indices, in the order the new index first references them.
+    pub shared: Vec<BlobInfo>,
+
+    /// Blobs only referenced by the new index, in the order it first references them.
+    pub added: Vec<BlobInfo>,
+
+    /// Blobs only referenced by the old index, in the order it first references them.
+    pub removed: Vec<BlobInfo>,
+
+    /// The size of the old index, including its inline blobs.
+    pub old_index_len: usize,
+
+    /// The size of the new index, including its inline blobs. The index is always transferred.
+    pub new_index_len: usize,
+
+    /// How each function changed, ordered by function index, or `None` if either archive doesn't
+    /// store one blob per function body.
+    pub functions: Option<Vec<FunctionChange>>,
+}
+
+impl ArchiveDiff {
+    pub fn new(old: &WasmallArchive, new: &WasmallArchive) -> anyhow::Result<Self> {
+        let old_mod = WasmallMod::parse(&mut ByteCursor(&old.out_buf))
+            .context("failed to parse old index")?;
+        let new_mod = WasmallMod::parse(&mut ByteCursor(&new.out_buf))
+            .context("failed to parse new index")?;
+
+        let old_blobs = referenced_blobs(&old_mod, old).context("failed to list old blobs")?;
+        let new_blobs = referenced_blobs(&new_mod, new).context("failed to list new blobs")?;
+
+        let old_set = old_blobs
+            .iter()
+            .map(|blob| blob.hash)
+            .collect::<FxHashSet<_>>();
+        let new_set = new_blobs
+            .iter()
+            .map(|blob| blob.hash)
+            .collect::<FxHashSet<_>>();
+
+        let (shared, added) = new_blobs
+            .into_iter()
+            .partition::<Vec<_>, _>(|blob| old_set.contains(&blob.hash));
+
+        let removed = old_blobs
+            .iter()
+            .filter(|blob| !new_set.contains(&blob.hash))
+            .cloned()
+            .collect();
+
+        // Components and chunked archives don't have a function layout, which isn't an error here.
+        let functions = match (FunctionLayout::new(&old_mod), FunctionLayout::new(&new_mod)) {
+            (Ok(old_layout), Ok(new_layout)) => Some(diff_functions(
+                &old_layout,
+                &new_layout,
+                function_blobs(&old_mod, old_layout.bodies.len())?,
+                function_blobs(&new_mod, new_layout.bodies.len())?,
+            )),
+            _ => None,
+        };
+
+        Ok(Self {
+            shared,
+            added,
+            removed,
+            old_index_len: old.out_buf.len(),
+            new_index_len: new.out_buf.len(),
+            functions,
+        })
+    }
+
+    /// The number of stored bytes of blobs both archives reference.
+    pub fn shared_bytes(&self) -> usize {
+        self.shared.iter().map(|blob| blob.stored_len).sum()
+    }
+
+    /// The number of stored bytes of blobs only the new archive references.
+    pub fn added_bytes(&self) -> usize {
+        self.added.iter().map(|blob| blob.stored_len).sum()
+    }
+
+    /// The number of stored bytes of blobs only the old archive references.
+    pub fn removed_bytes(&self) -> usize {
+        self.removed.iter().map(|blob| blob.stored_len).sum()
+    }
+
+    /// The number of bytes needed to fetch the new archive with the old one's blobs cached.
+    pub fn transfer_bytes(&self) -> usize {
+        self.new_index_len + self.added_bytes()
+    }
+
+    /// The number of bytes needed to fetch the new archive from scratch.
+    pub fn full_transfer_bytes(&self) -> usize {
+        self.new_index_len + self.shared_bytes() + self.added_bytes()
+    }
+
+    /// The fraction of the new archive's bytes served from the cache, between zero and one.
+    pub fn cache_hit_ratio(&self) -> f64 {
+        match self.full_transfer_bytes() {
+            0 => 0.,
+            full => self.shared_bytes() as f64 / full as f64,
+        }
+    }
+
+    /// Counts the functions which changed in each way, if there is a per-function breakdown.
+    pub fn function_summary(&self) -> Option<FunctionSummary> {
+        let mut summary = FunctionSummary::default();
+
+        for change in self.functions.as_ref()? {
+            let count = match change.kind {
+                FunctionChangeKind::Unchanged => &mut summary.unchanged,
+                FunctionChangeKind::RelocationsOnly => &mut summary.relocations_only,
+                FunctionChangeKind::Shifted => &mut summary.shifted,
+                FunctionChangeKind::Code => &mut summary.code,
+                FunctionChangeKind::Added => &mut summary.added,
+                FunctionChangeKind::Removed => &mut summary.removed,
+            };
+            *count += 1;
+        }
+
+        Some(summary)
+    }
+}
+
+/// Lists the distinct blobs `module` references along with their sizes in `archive` and the names
+/// of every symbol they hold.
+fn referenced_blobs(
+    module: &WasmallMod<'_>,
+    archive: &WasmallArchive,
+) -> anyhow::Result<Vec<BlobInfo>> {
+    let symbols = module.symbols_by_segment()?;
+    let mut indices = FxHashMap::<Hash, usize>::default();
+    let mut blobs = Vec::<BlobInfo>::new();
+
+    let segments = module
+        .segments()
+        .enumerate()
+        .filter_map(|(i, segment)| match segment {
+            Ok(WasmallModSeg::Blob(segment)) => Some(Ok((Some(i as u32), segment.hash()))),
+            Ok(_) => None,
+            Err(err) => Some(Err(err)),
+        });
+
+    for blob in module
+        .dictionary_hash()
+        .map(|hash| Ok((None, hash)))
+        .into_iter()
+        .chain(segments)
+    {
+        let (segment, hash) = blob?;
+
+        let blob = match indices.entry(hash) {
+            hash_map::Entry::Occupied(entry) => &mut blobs[*entry.get()],
+            hash_map::Entry::Vacant(entry) => {
+                let range = archive
+                    .hashes
+                    .get(&hash)
+                    .with_context(|| format!("archive is missing blob {hash}"))?;
+
+                entry.insert(blobs.len());
+                blobs.push(BlobInfo {
+                    hash,
+                    stored_len: range.len(),
+                    names: Vec::new(),
+                });
+                blobs.last_mut().unwrap()
+            }
+        };
+
+        // Identical blobs may hold differently named symbols.
+        for symbol in segment
+            .and_then(|segment| symbols.get(&segment))
+            .into_iter()
+            .flatten()
+        {
+            if !blob.names.iter().any(|name| name == symbol.name) {
+                blob.names.push(symbol.name.to_string());
+            }
+        }
+    }
+
+    Ok(blobs)
+}
+
+// === Function Changes === //
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FunctionChangeKind {
+    /// The body is identical in both versions.
+    Unchanged,
+
+    /// The body's code is identical but some of its relocations resolved to different values,
+    /// typically because something it refers to moved. Its blob is still cached.
+    RelocationsOnly,
+
+    /// The body's code differs from the old body at the same index but matches another old body,
+    /// typically because functions were inserted or removed before it. Its blob is still cached.
+    Shifted,
+
+    /// The body's code is new. Unless it is stored inline, its blob must be fetched.
+    Code,
+
+    /// The function only exists in the new version.
+    Added,
+
+    /// The function only exists in the old version.
+    Removed,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FunctionChange {
+    /// The function's index in the new module, or in the old module if it was removed. Imported
+    /// functions are included so this is an index into the function index space.
+    pub index: u32,
+
+    pub kind: FunctionChangeKind,
+
+    /// The blob storing the function's new body, or `None` if it was removed or is stored inline.
+    pub blob: Option<Hash>,
+
+    /// The names of the function's symbols, taken from the old index if it was removed and from
+    /// the new one otherwise. This is empty if the index doesn't record them.
+    pub names: Vec<String>,
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct FunctionSummary {
+    pub unchanged: usize,
+    pub relocations_only: usize,
+    pub shifted: usize,
+    pub code: usize,
+    pub added: usize,
+    pub removed: usize,
+}
+
+/// Lists the blob storing each of a module's `count` function bodies, in order, along with the
+/// names of the function's symbols. A module with a function layout stores its bodies before any
+/// other blobs, such as the chunks of its data segments.
+fn function_blobs(
+    module: &WasmallMod<'_>,
+    count: usize,
+) -> anyhow::Result<Vec<(Option<Hash>, Vec<String>)>> {
+    let symbols = module.symbols_by_segment()?;
+    let names = |segment: usize| {
+        symbols
+            .get(&(segment as u32))
+            .into_iter()
+            .flatten()
+            .map(|symbol| symbol.name.to_string())
+            .collect()
+    };
+
+    module
+        .segments()
+        .enumerate()
+        .filter_map(|(i, segment)| match segment {
+            Ok(WasmallModSeg::Verbatim(_)) => None,
+            Ok(WasmallModSeg::Blob(segment)) => Some(Ok((Some(segment.hash()), names(i)))),
+            Ok(WasmallModSeg::InlineBlob(_)) => Some(Ok((None, names(i)))),
+            Err(err) => Some(Err(err)),
+        })
+        .take(count)
+        .collect()
+}
+
+fn diff_functions(
+    old: &FunctionLayout,
+    new: &FunctionLayout,
+    old_blobs: Vec<(Option<Hash>, Vec<String>)>,
+    new_blobs: Vec<(Option<Hash>, Vec<String>)>,
+) -> Vec<FunctionChange> {
+    let old_code = old.code.iter().collect::<FxHashSet<_>>();
+    let mut changes = Vec::new();
+
+    for (i, (blob, names)) in new_blobs.into_iter().enumerate() {
+        let kind = if i >= old.bodies.len() {
+            FunctionChangeKind::Added
+        } else if old.bodies[i] == new.bodies[i] {
+            FunctionChangeKind::Unchanged
+        } else if old.code[i] == new.code[i] {
+            FunctionChangeKind::RelocationsOnly
+        } else if old_code.contains(&new.code[i]) {
+            FunctionChangeKind::Shifted
+        } else {
+            FunctionChangeKind::Code
+        };
+
+        changes.push(FunctionChange {
+            index: new.func_imports + i as u32,
+            kind,
+            blob,
+            names,
+        });
+    }
+
+    changes.extend(
+        old_blobs
+            .into_iter()
+            .enumerate()
+            .skip(new.bodies.len())
+            .map(|(i, (_, names))| FunctionChange {
+                index: old.func_imports + i as u32,
+                kind: FunctionChangeKind::Removed,
+                blob: None,
+                names,
+            }),
+    );
+
+    changes
+}
@@ -0,0 +1,407 @@
+//! Embedding guest modules into the binaries which host them.
+//!
+//! A host's build script compiles each guest crate with [`GuestBuild`], which writes the module
+//! (or, optionally, its split index and blobs) to `OUT_DIR` alongside its digest. The host then
+//! embeds it with [`include_guest_wasm!`](crate::include_guest_wasm), naming the same crate:
+//!
+//! ```ignore
+//! // build.rs
+//! wasmall::embed::GuestBuild::new("example-guest").build()?;
+//!
+//! // main.rs
+//! static GUEST: wasmall::embed::EmbeddedGuest = wasmall::include_guest_wasm!("example-guest");
+//! ```
+//!
+//! The guest is built into its own target directory under `OUT_DIR` so that it doesn't contend
+//! with the host's build for the lock on the host's.
+
+use std::{
+    borrow::Cow,
+    env,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use blake3::Hash;
+use rustc_hash::FxHashMap;
+
+use crate::{
+    coder::WasmallMod,
+    splitter::{split_module_with, SplitOptions},
+    store::BlobSource,
+    util::{ByteCursor, ByteParse},
+};
+
+/// The target guests are built for unless specified otherwise.
+pub const DEFAULT_GUEST_TARGET: &str = "wasm32-unknown-unknown";
+
+/// The directory under `OUT_DIR` embedded guests are written to.
+pub const GUEST_OUT_DIR: &str = "guests";
+
+// === GuestBuild === //
+
+/// Builds a guest crate from a build script and prepares it for embedding.
+#[derive(Debug, Clone)]
+pub struct GuestBuild {
+    package: String,
+    manifest_path: Option<PathBuf>,
+    target: String,
+    profile: String,
+    split: Option<SplitOptions>,
+    cargo_args: Vec<String>,
+}
+
+impl GuestBuild {
+    /// Builds the package named `package`, which must be part of the host's workspace unless a
+    /// [`manifest_path`](Self::manifest_path) is given.
+    pub fn new(package: impl Into<String>) -> Self {
+        Self {
+            package: package.into(),
+            manifest_path: None,
+            target: DEFAULT_GUEST_TARGET.to_string(),
+            profile: "release".to_string(),
+            split: None,
+            cargo_args: Vec::new(),
+        }
+    }
+
+    /// Builds the package within the workspace of the specified `Cargo.toml`. The package's sources
+    /// are also watched for changes.
+    pub fn manifest_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.manifest_path = Some(path.into());
+        self
+    }
+
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = target.into();
+        self
+    }
+
+    pub fn profile(mut self, profile: impl Into<String>) -> Self {
+        self.profile = profile.into();
+        self
+    }
+
+    /// Splits the module before embedding it, so that it's embedded as an index and blobs.
+    pub fn split(mut self, options: SplitOptions) -> Self {
+        self.split = Some(options);
+        self
+    }
+
+    /// Passes an extra argument to `cargo build`.
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.cargo_args.push(arg.into());
+        self
+    }
+
+    /// Builds the guest and writes everything [`include_guest_wasm!`](crate::include_guest_wasm)
+    /// needs to `OUT_DIR`, returning the path to the built module.
+    pub fn build(&self) -> anyhow::Result<PathBuf> {
+        let out_dir = PathBuf::from(env::var_os("OUT_DIR").context("`OUT_DIR` is not set")?);
+        let target_dir = out_dir.join("guest-target");
+        let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+
+        let mut cmd = Command::new(cargo);
+        cmd.arg("build")
+            .args(["--package", &self.package])
+            .args(["--target", &self.target])
+            .args(["--profile", &self.profile])
+            .arg("--target-dir")
+            .arg(&target_dir)
+            .args(&self.cargo_args)
+            // The host's flags are meant for the host's target.
+            .env_remove("CARGO_ENCODED_RUSTFLAGS")
+            .env_remove("RUSTFLAGS");
+
+        if let Some(manifest_path) = &self.manifest_path {
+            cmd.arg("--manifest-path").arg(manifest_path);
+
+            let package_dir = manifest_path.parent().unwrap_or(Path::new("."));
+            println!("cargo:rerun-if-changed={}", manifest_path.display());
+            println!(
+                "cargo:rerun-if-changed={}",
+                package_dir.join("src").display()
+            );
+        }
+
+        let status = cmd
+            .status()
+            .with_context(|| format!("failed to run cargo to build {}", self.package))?;
+
+        anyhow::ensure!(
+            status.success(),
+            "failed to build {} ({status})",
+            self.package
+        );
+
+        let module_path = self.artifact_path(&target_dir)?;
+        let module = fs::read(&module_path)
+            .with_context(|| format!("failed to read the built module {module_path:?}"))?;
+
+        self.write_embedding(&out_dir.join(GUEST_OUT_DIR), &module)?;
+        Ok(module_path)
+    }
+
+    fn artifact_path(&self, target_dir: &Path) -> anyhow::Result<PathBuf> {
+        let profile_dir = match self.profile.as_str() {
+            "dev" | "test" => "debug",
+            "bench" => "release",
+            profile => profile,
+        };
+
+        let dir = target_dir.join(&self.target).join(profile_dir);
+
+        // Binaries keep their package's name but libraries have their dashes replaced.
+        [self.package.clone(), self.package.replace('-', "_")]
+            .into_iter()
+            .map(|name| dir.join(name).with_extension("wasm"))
+            .find(|path| path.exists())
+            .with_context(|| format!("cargo did not produce a module for {}", self.package))
+    }
+
+    fn write_embedding(&self, dir: &Path, module: &[u8]) -> anyhow::Result<()> {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {dir:?}"))?;
+
+        let mut digest = blake3::hash(module);
+        let file = |ext: &str| dir.join(&self.package).with_extension(ext);
+
+        // The generated expression is included into the host's crate, where `$crate` isn't available
+        // and relative paths would resolve against the host's sources.
+        let payload = match &self.split {
+            None => {
+                fs::write(file("wasm"), module)?;
+                format!(
+                    "::wasmall::embed::EmbeddedPayload::Module(include_bytes!({:?}))",
+                    file("wasm"),
+                )
+            }
+            Some(options) => {
+                let archive = split_module_with(module, options)
+                    .with_context(|| format!("failed to split {}", self.package))?
+                    .archive;
+
+                // Splitting drops custom sections, so the assembled module is what gets digested.
+                digest = archive.module_hash;
+
+                fs::write(file("index"), &archive.out_buf)?;
+                fs::write(file("blobs"), &archive.blob_buf)?;
+
+                // Sorted so that blobs can be found with a binary search.
+                let mut table = archive.hashes.into_iter().collect::<Vec<_>>();
+                table.sort_by_key(|(hash, _)| *hash.as_bytes());
+
+                let mut entries = String::new();
+                for (hash, range) in table {
+                    write!(
+                        entries,
+                        "({:?}, {}, {}),",
+                        hash.as_bytes(),
+                        range.start,
+                        range.end
+                    )?;
+                }
+
+                format!(
+                    "::wasmall::embed::EmbeddedPayload::Split {{ \
+                        index: include_bytes!({:?}), \
+                        blobs: include_bytes!({:?}), \
+                        table: &[{entries}] \
+                    }}",
+                    file("index"),
+                    file("blobs"),
+                )
+            }
+        };
+
+        let expr = format!(
+            "::wasmall::embed::EmbeddedGuest {{ name: {:?}, digest: {:?}, payload: {payload} }}",
+            self.package,
+            digest.as_bytes(),
+        );
+
+        fs::write(file("rs"), expr)?;
+        Ok(())
+    }
+}
+
+// === EmbeddedGuest === //
+
+/// How an embedded guest's module is stored.
+#[derive(Debug, Copy, Clone)]
+pub enum EmbeddedPayload {
+    Module(&'static [u8]),
+
+    /// A split module, along with the hash and range in `blobs` of each blob, sorted by hash.
+    Split {
+        index: &'static [u8],
+        blobs: &'static [u8],
+        table: &'static [([u8; 32], usize, usize)],
+    },
+}
+
+/// A guest module embedded with [`include_guest_wasm!`](crate::include_guest_wasm).
+#[derive(Debug, Copy, Clone)]
+pub struct EmbeddedGuest {
+    /// The name of the guest's package.
+    pub name: &'static str,
+
+    /// The blake3 hash of the guest's module, as assembled if it was embedded split.
+    pub digest: [u8; 32],
+    pub payload: EmbeddedPayload,
+}
+
+impl EmbeddedGuest {
+    pub fn digest(&self) -> Hash {
+        Hash::from_bytes(self.digest)
+    }
+
+    /// The guest's module, assembling it first if it was embedded split. Either way, the module is
+    /// checked against its digest.
+    pub fn module(&self) -> anyhow::Result<Cow<'static, [u8]>> {
+        let module = match self.payload {
+            EmbeddedPayload::Module(module) => Cow::Borrowed(module),
+            EmbeddedPayload::Split {
+                index,
+                blobs,
+                table,
+            } => {
+                let index = WasmallMod::parse(&mut ByteCursor(index))
+                    .with_context(|| format!("failed to parse the index of {}", self.name))?;
+
+                Cow::Owned(index.assemble_verified(&EmbeddedBlobs { blobs, table })?)
+            }
+        };
+
+        anyhow::ensure!(
+            blake3::hash(&module) == self.digest(),
+            "embedded guest {} does not match its digest",
+            self.name,
+        );
+
+        Ok(module)
+    }
+}
+
+struct EmbeddedBlobs {
+    blobs: &'static [u8],
+    table: &'static [([u8; 32], usize, usize)],
+}
+
+impl BlobSource for EmbeddedBlobs {
+    fn get_blob(&self, hash: Hash) -> anyhow::Result<Option<Cow<'_, [u8]>>> {
+        let Ok(i) = self
+            .table
+            .binary_search_by_key(hash.as_bytes(), |(hash, ..)| *hash)
+        else {
+            return Ok(None);
+        };
+
+        let (_, start, end) = self.table[i];
+        let blob = self
+            .blobs
+            .get(start..end)
+            .context("embedded blob extends past the end of the blobs")?;
+
+        Ok(Some(Cow::Borrowed(blob)))
+    }
+}
+
+/// Embeds a guest built by [`GuestBuild`] as an [`EmbeddedGuest`].
+#[macro_export]
+macro_rules! include_guest_wasm {
+    ($package:literal) => {
+        include!(concat!(env!("OUT_DIR"), "/guests/", $package, ".rs"))
+    };
+}
+
+/// Embeds several guests built by [`GuestBuild`] into an [`EmbeddedModuleRegistry`].
+#[macro_export]
+macro_rules! include_guest_registry {
+    ($($package:literal),* $(,)?) => {
+        $crate::embed::EmbeddedModuleRegistry::new([$($crate::include_guest_wasm!($package)),*])
+    };
+}
+
+// === EmbeddedModuleRegistry === //
+
+/// A set of embedded guests which are assembled the first time they're needed.
+///
+/// Assembled modules, and whatever they're compiled into, are cached by digest, so guests which
+/// embed the same module share both.
+pub struct EmbeddedModuleRegistry<C = ()> {
+    guests: FxHashMap<&'static str, EmbeddedGuest>,
+    assembled: Mutex<FxHashMap<Hash, Arc<[u8]>>>,
+    compiled: Mutex<FxHashMap<Hash, C>>,
+}
+
+impl<C: Clone> EmbeddedModuleRegistry<C> {
+    pub fn new(guests: impl IntoIterator<Item = EmbeddedGuest>) -> Self {
+        Self {
+            guests: guests
+                .into_iter()
+                .map(|guest| (guest.name, guest))
+                .collect(),
+            assembled: Mutex::default(),
+            compiled: Mutex::default(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> anyhow::Result<&EmbeddedGuest> {
+        self.guests
+            .get(name)
+            .with_context(|| format!("no guest named {name} is embedded"))
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.guests.keys().copied()
+    }
+
+    /// The module of the guest named `name`, assembling and verifying it if it hasn't been yet.
+    pub fn module(&self, name: &str) -> anyhow::Result<Arc<[u8]>> {
+        let guest = self.get(name)?;
+        let mut assembled = self.assembled.lock().unwrap();
+
+        if let Some(module) = assembled.get(&guest.digest()) {
+            return Ok(module.clone());
+        }
+
+        let module = Arc::<[u8]>::from(guest.module()?);
+        assembled.insert(guest.digest(), module.clone());
+        Ok(module)
+    }
+
+    /// The compiled form of the guest named `name`, compiling its module with `compile` if it
+    /// hasn't been yet.
+    pub fn compiled(
+        &self,
+        name: &str,
+        compile: impl FnOnce(&[u8]) -> anyhow::Result<C>,
+    ) -> anyhow::Result<C> {
+        let digest = self.get(name)?.digest();
+
+        if let Some(compiled) = self.compiled.lock().unwrap().get(&digest) {
+            return Ok(compiled.clone());
+        }
+
+        // Compilation can take a while, so other guests shouldn't have to wait on it.
+        let compiled = compile(&self.module(name)?)
+            .with_context(|| format!("failed to compile guest {name}"))?;
+
+        Ok(self
+            .compiled
+            .lock()
+            .unwrap()
+            .entry(digest)
+            .or_insert(compiled)
+            .clone())
+    }
+
+    /// Drops every cached assembled module. Compiled modules are kept.
+    pub fn evict_assembled(&self) {
+        self.assembled.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,251 @@
+//! Configuration of the WebAssembly proposals modules are allowed to use.
+//!
+//! Modules are validated against the configured features before they are split so modules using a
+//! proposal the embedder hasn't opted into are rejected with the offending feature and offset rather
+//! than failing somewhere in the middle of parsing. Modules which skip validation are still checked
+//! for the proposals they use by [`check_features`].
+
+use anyhow::Context;
+use wasmparser::{
+    for_each_operator, BinaryReaderError, CompositeType, Encoding, MemoryType, Parser, Payload,
+    TypeRef, Validator, VisitOperator,
+};
+
+pub use wasmparser::WasmFeatures;
+
+/// The names accepted by [`set_feature`], matching those used by `wasm-tools`.
+pub const FEATURE_NAMES: &[&str] = &[
+    "mutable-global",
+    "saturating-float-to-int",
+    "sign-extension",
+    "reference-types",
+    "multi-value",
+    "bulk-memory",
+    "simd",
+    "relaxed-simd",
+    "threads",
+    "tail-call",
+    "floats",
+    "multi-memory",
+    "exceptions",
+    "memory64",
+    "extended-const",
+    "function-references",
+    "memory-control",
+    "gc",
+    "component-model",
+];
+
+/// Enables or disables the proposal with the specified name. See [`FEATURE_NAMES`].
+pub fn set_feature(features: &mut WasmFeatures, name: &str, enabled: bool) -> anyhow::Result<()> {
+    let flag = match name {
+        "mutable-global" => &mut features.mutable_global,
+        "saturating-float-to-int" => &mut features.saturating_float_to_int,
+        "sign-extension" => &mut features.sign_extension,
+        "reference-types" => &mut features.reference_types,
+        "multi-value" => &mut features.multi_value,
+        "bulk-memory" => &mut features.bulk_memory,
+        "simd" => &mut features.simd,
+        "relaxed-simd" => &mut features.relaxed_simd,
+        "threads" => &mut features.threads,
+        "tail-call" => &mut features.tail_call,
+        "floats" => &mut features.floats,
+        "multi-memory" => &mut features.multi_memory,
+        "exceptions" => &mut features.exceptions,
+        "memory64" => &mut features.memory64,
+        "extended-const" => &mut features.extended_const,
+        "function-references" => &mut features.function_references,
+        "memory-control" => &mut features.memory_control,
+        "gc" => &mut features.gc,
+        "component-model" => &mut features.component_model,
+        _ => anyhow::bail!(
+            "unknown WebAssembly feature {name:?}; expected one of {}",
+            FEATURE_NAMES.join(", ")
+        ),
+    };
+
+    *flag = enabled;
+    Ok(())
+}
+
+fn describe_error(err: &BinaryReaderError) -> String {
+    let message = err.message();
+    let offset = err.offset();
+
+    // The validator's wording for disabled proposals varies but always mentions enabling them.
+    if message.contains("not enabled") || message.contains("to be enabled") {
+        format!("unsupported feature at offset {offset:#x}: {message}")
+    } else {
+        format!("invalid module at offset {offset:#x}: {message}")
+    }
+}
+
+/// Validates a module, only accepting the proposals enabled in `features`.
+pub fn validate(src: &[u8], features: WasmFeatures) -> anyhow::Result<()> {
+    Validator::new_with_features(features)
+        .validate_all(src)
+        .map_err(|err| anyhow::anyhow!(describe_error(&err)))
+        .context("failed to validate module")?;
+
+    Ok(())
+}
+
+/// Returns whether the proposal named like the corresponding [`WasmFeatures`] field, or `mvp`, is
+/// enabled.
+fn is_enabled(features: &WasmFeatures, proposal: &str) -> bool {
+    match proposal {
+        "mvp" => true,
+        "mutable_global" => features.mutable_global,
+        "saturating_float_to_int" => features.saturating_float_to_int,
+        "sign_extension" => features.sign_extension,
+        "reference_types" => features.reference_types,
+        "multi_value" => features.multi_value,
+        "bulk_memory" => features.bulk_memory,
+        "simd" => features.simd,
+        "relaxed_simd" => features.relaxed_simd,
+        "threads" => features.threads,
+        "tail_call" => features.tail_call,
+        "floats" => features.floats,
+        "multi_memory" => features.multi_memory,
+        "exceptions" => features.exceptions,
+        "memory64" => features.memory64,
+        "extended_const" => features.extended_const,
+        "function_references" => features.function_references,
+        "memory_control" => features.memory_control,
+        "gc" => features.gc,
+        "component_model" => features.component_model,
+        _ => false,
+    }
+}
+
+/// Determines the proposal introducing each operator.
+struct OperatorProposal;
+
+macro_rules! define_visit_proposal {
+    ($( @$proposal:ident $op:ident $({ $($arg:ident: $argty:ty),* })? => $visit:ident)*) => {
+        $(
+            fn $visit(&mut self $($(, $arg: $argty)*)?) -> &'static str {
+                $($(let _ = $arg;)*)?
+                stringify!($proposal)
+            }
+        )*
+    };
+}
+
+impl<'a> VisitOperator<'a> for OperatorProposal {
+    type Output = &'static str;
+
+    for_each_operator!(define_visit_proposal);
+}
+
+/// Rejects modules using proposals which aren't enabled in `features` without validating them
+/// otherwise. This recognizes proposals by the operators, memories, tables, tags, and types a module
+/// uses, which lets modules [`validate`] can't handle, such as those using the legacy exception
+/// handling opcodes, still honor `features`.
+pub fn check_features(src: &[u8], features: WasmFeatures) -> anyhow::Result<()> {
+    let require = |proposal: &str, offset: usize| {
+        anyhow::ensure!(
+            is_enabled(&features, proposal),
+            "unsupported feature at offset {offset:#x}: {} support is not enabled",
+            proposal.replace('_', "-"),
+        );
+        Ok(())
+    };
+
+    let check_memory = |ty: MemoryType, index: usize, offset: usize| {
+        if index > 0 {
+            require("multi_memory", offset)?;
+        }
+        if ty.memory64 {
+            require("memory64", offset)?;
+        }
+        if ty.shared {
+            require("threads", offset)?;
+        }
+        anyhow::Ok(())
+    };
+
+    let check = || {
+        // The number of memories and tables of the module being parsed.
+        let (mut memories, mut tables) = (0, 0);
+
+        for payload in Parser::new(0).parse_all(src) {
+            match payload? {
+                Payload::Version {
+                    encoding, range, ..
+                } => {
+                    if encoding == Encoding::Component {
+                        require("component_model", range.start)?;
+                    }
+                    (memories, tables) = (0, 0);
+                }
+                Payload::TypeSection(reader) => {
+                    for group in reader.into_iter_with_offsets() {
+                        let (offset, group) = group?;
+
+                        for ty in group.into_types() {
+                            match &ty.composite_type {
+                                CompositeType::Func(func) if func.results().len() > 1 => {
+                                    require("multi_value", offset)?
+                                }
+                                CompositeType::Func(_) => {}
+                                _ => require("gc", offset)?,
+                            }
+                        }
+                    }
+                }
+                Payload::ImportSection(reader) => {
+                    for import in reader.into_iter_with_offsets() {
+                        let (offset, import) = import?;
+
+                        match import.ty {
+                            TypeRef::Memory(ty) => {
+                                check_memory(ty, memories, offset)?;
+                                memories += 1;
+                            }
+                            TypeRef::Table(_) => {
+                                if tables > 0 {
+                                    require("reference_types", offset)?;
+                                }
+                                tables += 1;
+                            }
+                            TypeRef::Tag(_) => require("exceptions", offset)?,
+                            _ => {}
+                        }
+                    }
+                }
+                Payload::TableSection(reader) => {
+                    for table in reader.into_iter_with_offsets() {
+                        let (offset, _) = table?;
+                        if tables > 0 {
+                            require("reference_types", offset)?;
+                        }
+                        tables += 1;
+                    }
+                }
+                Payload::MemorySection(reader) => {
+                    for memory in reader.into_iter_with_offsets() {
+                        let (offset, ty) = memory?;
+                        check_memory(ty, memories, offset)?;
+                        memories += 1;
+                    }
+                }
+                Payload::TagSection(reader) => require("exceptions", reader.range().start)?,
+                Payload::CodeSectionEntry(body) => {
+                    let mut reader = body.get_operators_reader()?;
+
+                    while !reader.eof() {
+                        let offset = reader.original_position();
+                        let proposal = reader.visit_operator(&mut OperatorProposal)?;
+                        require(proposal, offset)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        anyhow::Ok(())
+    };
+
+    check().context("failed to check the proposals used by the module")
+}
@@ -0,0 +1,149 @@
+//! A bloom filter over blob hashes for answering "definitely missing" queries without touching the
+//! underlying store.
+
+use anyhow::Context;
+use blake3::Hash;
+
+use crate::util::{ByteCursor, ByteParse};
+
+pub const FILTER_MAGIC: [u8; 8] = *b"WSMLBLOM";
+
+/// The largest number of hash functions a filter may use.
+const MAX_HASHES: u8 = 32;
+
+/// A bloom filter over blob hashes.
+///
+/// Since blob hashes are already uniformly distributed, the filter derives its bit indices directly
+/// from the hash bytes rather than rehashing them.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BlobFilter {
+    hash_count: u8,
+    bit_count: u64,
+    words: Vec<u64>,
+    capacity: usize,
+}
+
+impl BlobFilter {
+    /// Creates an empty filter which will have roughly the specified false positive rate once
+    /// `capacity` blobs have been inserted.
+    pub fn with_capacity(capacity: usize, false_positive_rate: f64) -> Self {
+        let false_positive_rate = false_positive_rate.clamp(1e-9, 0.5);
+        let n = capacity.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+
+        let bit_count = (-n * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.) as u64;
+        let hash_count = ((bit_count as f64 / n) * ln2)
+            .round()
+            .clamp(1., MAX_HASHES as f64) as u8;
+
+        Self {
+            hash_count,
+            bit_count,
+            words: vec![0; bit_count.div_ceil(64) as usize],
+            capacity,
+        }
+    }
+
+    pub fn from_hashes(
+        hashes: impl IntoIterator<Item = Hash, IntoIter: ExactSizeIterator>,
+        false_positive_rate: f64,
+    ) -> Self {
+        let hashes = hashes.into_iter();
+        let mut filter = Self::with_capacity(hashes.len(), false_positive_rate);
+        for hash in hashes {
+            filter.insert(hash);
+        }
+        filter
+    }
+
+    /// The number of blobs the filter was sized for. Inserting more than this degrades the false
+    /// positive rate.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn bit_count(&self) -> u64 {
+        self.bit_count
+    }
+
+    pub fn hash_count(&self) -> u8 {
+        self.hash_count
+    }
+
+    fn bit_indices(&self, hash: Hash) -> impl Iterator<Item = u64> {
+        let bytes = hash.as_bytes();
+        let h1 = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(bytes[8..16].try_into().unwrap()) | 1;
+        let bit_count = self.bit_count;
+
+        (0..self.hash_count as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % bit_count)
+    }
+
+    pub fn insert(&mut self, hash: Hash) {
+        for bit in self.bit_indices(hash) {
+            self.words[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Returns `false` if the blob is definitely not in the set and `true` if it may be.
+    pub fn may_contain(&self, hash: Hash) -> bool {
+        self.bit_indices(hash)
+            .all(|bit| self.words[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(FILTER_MAGIC.len() + 17 + self.words.len() * 8);
+        buf.extend_from_slice(&FILTER_MAGIC);
+        buf.push(self.hash_count);
+        buf.extend_from_slice(&self.bit_count.to_le_bytes());
+        buf.extend_from_slice(&(self.capacity as u64).to_le_bytes());
+
+        for word in &self.words {
+            buf.extend_from_slice(&word.to_le_bytes());
+        }
+
+        buf
+    }
+}
+
+impl ByteParse<'_> for BlobFilter {
+    type Out = Self;
+
+    fn parse_naked(buf: &mut ByteCursor<'_>) -> anyhow::Result<Self::Out> {
+        anyhow::ensure!(
+            buf.consume(FILTER_MAGIC.len()).ok() == Some(&FILTER_MAGIC[..]),
+            "not a blob filter"
+        );
+
+        let hash_count = buf.read_u8().context("failed to read hash count")?;
+        anyhow::ensure!(
+            (1..=MAX_HASHES).contains(&hash_count),
+            "invalid hash count {hash_count}"
+        );
+
+        let bit_count = buf.read_u64().context("failed to read bit count")?;
+        anyhow::ensure!(bit_count > 0, "filter has no bits");
+
+        let capacity = usize::try_from(buf.read_u64().context("failed to read capacity")?)?;
+
+        let word_count = usize::try_from(bit_count.div_ceil(64))?;
+        anyhow::ensure!(
+            buf.0.len() / 8 >= word_count,
+            "filter claims {bit_count} bits but only has {} bytes of data",
+            buf.0.len(),
+        );
+
+        let words = (0..word_count)
+            .map(|_| buf.read_u64())
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            hash_count,
+            bit_count,
+            words,
+            capacity,
+        })
+    }
+}
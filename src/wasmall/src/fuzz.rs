@@ -0,0 +1,432 @@
+//! Fuzzing entry points and [`Arbitrary`] implementations for configuration types.
+//!
+//! Each entry point takes a single buffer of arbitrary bytes, never panics unless it finds a bug,
+//! and bounds the work it does internally so that fuzzers don't mistake large declared lengths for
+//! hangs. The `cargo-fuzz` targets in `fuzz/` are thin wrappers around these, and the
+//! [`smith`](crate::smith) parser target runs [`fuzz_parsers`] too.
+
+use std::borrow::Cow;
+
+use arbitrary::{Arbitrary, Unstructured};
+use blake3::Hash;
+use wasmparser::LinkingSectionReader;
+
+#[cfg(feature = "encryption")]
+use crate::crypt::{BlobCipher, BlobKey};
+use crate::{
+    chunker::ChunkingOptions,
+    coder::{CompressionOptions, WasmallBlob, WasmallMod, WasmallModSeg, WriterOptions},
+    corpus::{check_module, round_trip, CorpusConfig, MismatchKind},
+    features::{set_feature, validate, WasmFeatures, FEATURE_NAMES},
+    reloc::{LinkingSectionWriter, RelocEntry, RelocIndex, RelocSection, RelocSectionWriter},
+    splitter::SplitOptions,
+    store::BlobSource,
+    util::{ByteCursor, ByteParse, ByteParseList, Leb128WriteExt, LenCounter},
+};
+
+/// The maximum number of items decoded from any list.
+const MAX_ITEMS: usize = 1 << 16;
+
+/// The maximum number of operations [`fuzz_cursor`] runs.
+const MAX_CURSOR_OPS: usize = 256;
+
+// === Entry Points === //
+
+/// Runs the input through every entry point, using the first byte to pick which one.
+pub fn fuzz_any(data: &[u8]) {
+    let Some((&selector, data)) = data.split_first() else {
+        return;
+    };
+
+    match selector % 5 {
+        0 => fuzz_cursor(data),
+        1 => fuzz_index(data),
+        2 => fuzz_reloc_section(data),
+        3 => fuzz_linking_section(data),
+        _ => fuzz_round_trip(data),
+    }
+}
+
+/// Runs every parser over the input. This is what the [`smith`](crate::smith) parser target
+/// checks.
+pub fn fuzz_parsers(data: &[u8]) {
+    fuzz_cursor(data);
+    fuzz_index(data);
+    fuzz_reloc_section(data);
+    fuzz_linking_section(data);
+}
+
+/// Interprets the input as a sequence of [`ByteCursor`] reads over itself. The first byte gives
+/// the number of operation bytes which follow, and the rest of the input is read from.
+///
+/// Failed reads must leave the cursor in place, and successful LEB128 reads must re-encode to
+/// exactly the bytes they consumed when written at the same width.
+pub fn fuzz_cursor(data: &[u8]) {
+    let Some((&op_count, data)) = data.split_first() else {
+        return;
+    };
+
+    let (ops, data) = data.split_at((op_count as usize).min(data.len()));
+    let mut cursor = ByteCursor(data);
+
+    for &op in ops.iter().take(MAX_CURSOR_OPS) {
+        let before = cursor.clone();
+        let ok = match op % 20 {
+            0 => cursor.read_u8().is_ok(),
+            1 => cursor.read_u32().is_ok(),
+            2 => cursor.read_u64().is_ok(),
+            3 => cursor.read_f32().is_ok(),
+            4 => cursor.read_f64().is_ok(),
+            5 => check_leb(&mut cursor, ByteCursor::read_var_u32, |out, v, width| {
+                out.write_var_u32_with_width(v, Some(width))
+            }),
+            6 => check_leb(&mut cursor, ByteCursor::read_var_i32, |out, v, width| {
+                out.write_var_i32_with_width(v, Some(width))
+            }),
+            7 => check_leb(&mut cursor, ByteCursor::read_var_u64, |out, v, width| {
+                out.write_var_u64_with_width(v, Some(width))
+            }),
+            8 => check_leb(&mut cursor, ByteCursor::read_var_i64, |out, v, width| {
+                out.write_var_i64_with_width(v, Some(width))
+            }),
+            9 => check_leb(&mut cursor, ByteCursor::read_var_s33, |out, v, width| {
+                out.write_var_s33_with_width(v, Some(width))
+            }),
+            10 => cursor.read_var_u32_full().is_ok(),
+            11 => cursor.read_var_i32_full().is_ok(),
+            12 => cursor.read_var_u64_full().is_ok(),
+            13 => cursor.read_var_i64_full().is_ok(),
+            14 => cursor.read_var_s33_full().is_ok(),
+            15 => cursor.consume((op / 20) as usize).is_ok(),
+            16 => cursor.peek((op / 20) as usize).is_ok(),
+            17 => {
+                // Reads which are rolled back must leave no trace.
+                let savepoint = cursor.savepoint();
+                let _ = cursor.read_var_u64();
+                cursor.rollback(savepoint);
+                assert_eq!(cursor.0, before.0, "rollback didn't restore the cursor");
+                true
+            }
+            18 => {
+                let savepoint = cursor.savepoint();
+                let _ = cursor.read_var_u32();
+                let committed = cursor.commit(savepoint);
+                assert_eq!(
+                    committed.len() + cursor.0.len(),
+                    before.0.len(),
+                    "committed span doesn't match the bytes consumed"
+                );
+                true
+            }
+            _ => cursor
+                .attempt(|c| {
+                    c.read_var_u32()?;
+                    c.read_var_u32()
+                })
+                .is_some(),
+        };
+
+        if !ok {
+            assert_eq!(cursor.0, before.0, "failed read {op} moved the cursor");
+        }
+    }
+}
+
+fn check_leb<'a, T: Copy + std::fmt::Debug>(
+    cursor: &mut ByteCursor<'a>,
+    read: impl FnOnce(&mut ByteCursor<'a>) -> anyhow::Result<T>,
+    write: impl FnOnce(&mut Vec<u8>, T, usize),
+) -> bool {
+    let start = cursor.0;
+    let Ok(value) = read(cursor) else {
+        return false;
+    };
+
+    let consumed = &start[..start.len() - cursor.0.len()];
+    let mut encoded = Vec::new();
+    write(&mut encoded, value, consumed.len());
+    assert_eq!(
+        encoded, consumed,
+        "{value:?} was read from {consumed:02x?} but re-encodes to {encoded:02x?}"
+    );
+
+    true
+}
+
+/// Parses the input as a wasmall index and assembles it against a source which answers every
+/// request with the input itself. The input is also parsed as a standalone blob.
+pub fn fuzz_index(data: &[u8]) {
+    if let Ok(module) = WasmallMod::parse(&mut ByteCursor(data)) {
+        let _ = module
+            .priority()
+            .take(MAX_ITEMS)
+            .take_while(Result::is_ok)
+            .count();
+        let _ = module
+            .symbols()
+            .take(MAX_ITEMS)
+            .take_while(Result::is_ok)
+            .count();
+        let _ = module.fetch_order();
+        let _ = module.verify_merkle_root();
+
+        for segment in module.segments().take(MAX_ITEMS) {
+            let Ok(segment) = segment else {
+                break;
+            };
+
+            match segment {
+                WasmallModSeg::Verbatim(_) => {}
+                WasmallModSeg::Blob(segment) => {
+                    let _ = segment
+                        .reloc_values()
+                        .take(MAX_ITEMS)
+                        .take_while(Result::is_ok)
+                        .count();
+                }
+                WasmallModSeg::InlineBlob(segment) => {
+                    let _ = segment.write(&mut LenCounter::default());
+                }
+            }
+        }
+
+        let _ = module.assemble(&EchoSource(data));
+    }
+
+    if let Ok(blob) = WasmallBlob::parse(&mut ByteCursor(data)) {
+        let _ = blob
+            .relocations()
+            .take(MAX_ITEMS)
+            .take_while(Result::is_ok)
+            .count();
+
+        // Reuse the data as relocation values so mismatched counts are exercised too.
+        let _ = blob.expand(
+            ByteParseList::new(ByteCursor(data)),
+            &mut LenCounter::default(),
+        );
+    }
+}
+
+/// A blob source which serves the same bytes for every hash.
+struct EchoSource<'a>(&'a [u8]);
+
+impl BlobSource for EchoSource<'_> {
+    fn get_blob(&self, _hash: Hash) -> anyhow::Result<Option<Cow<'_, [u8]>>> {
+        Ok(Some(Cow::Borrowed(self.0)))
+    }
+
+    // Claiming the bytes match every hash lets them reach the decoder.
+    fn verifies_blobs(&self) -> bool {
+        true
+    }
+}
+
+/// Parses the input as the contents of a relocation section. Every entry which parses must
+/// re-encode to an entry with the same fields.
+pub fn fuzz_reloc_section(data: &[u8]) {
+    let Ok(section) = RelocSection::parse(&mut ByteCursor(data)) else {
+        return;
+    };
+
+    let mut entries = Vec::new();
+    for entry in section.entries().take(MAX_ITEMS) {
+        let Ok(entry) = entry else {
+            break;
+        };
+
+        let mut encoded = Vec::new();
+        entry.write(&mut encoded);
+
+        let decoded =
+            RelocEntry::parse(&mut ByteCursor(&encoded)).expect("relocation entries must re-parse");
+
+        assert_eq!(
+            (decoded.ty, decoded.offset, decoded.index, decoded.addend),
+            (entry.ty, entry.offset, entry.index, entry.addend),
+            "relocation entry changed when re-encoded"
+        );
+
+        entries.push(entry);
+    }
+
+    // Recovery must keep every entry which parses on its own.
+    let count = (section.entry_count as usize).min(MAX_ITEMS);
+    let (recovered, _) =
+        ByteParseList::<RelocEntry>::with_count(ByteCursor(section.entries), count)
+            .parse_recovering();
+    assert!(recovered.len() >= entries.len(), "recovery dropped entries");
+
+    // The entries which parsed must survive being written out as a section of their own.
+    let mut writer = RelocSectionWriter::new(section.target_section);
+    for entry in &entries {
+        writer.push(entry);
+    }
+
+    let rewritten = writer.as_section();
+    assert_eq!(rewritten.entry_count as usize, entries.len());
+
+    for (entry, decoded) in entries.iter().zip(rewritten.entries()) {
+        let decoded = decoded.expect("rewritten relocation entries must re-parse");
+        assert_eq!(
+            (decoded.ty, decoded.offset, decoded.index, decoded.addend),
+            (entry.ty, entry.offset, entry.index, entry.addend),
+            "relocation entry changed when its section was rewritten"
+        );
+    }
+
+    let index = RelocIndex::new(entries);
+    let _ = index.range(0..u32::MAX);
+}
+
+/// Parses the input as the contents of a `linking` section. Sections which parse must re-encode
+/// to a section which parses and re-encodes to the same bytes.
+pub fn fuzz_linking_section(data: &[u8]) {
+    let Ok(reader) = LinkingSectionReader::new(data, 0) else {
+        return;
+    };
+
+    let Ok(writer) = LinkingSectionWriter::from_reader(reader) else {
+        return;
+    };
+
+    let mut first = writer.finish();
+    let first = std::mem::take(first.data());
+
+    let reader =
+        LinkingSectionReader::new(&first, 0).expect("re-encoded linking sections must re-parse");
+
+    let second = LinkingSectionWriter::from_reader(reader)
+        .expect("re-encoded linking sections must re-encode");
+
+    assert_eq!(
+        &first,
+        second.finish().data(),
+        "linking section changed when re-encoded"
+    );
+}
+
+/// Splits the input with an arbitrary configuration decoded from its start and assembles it back.
+/// Valid modules which split successfully must round-trip to their canonical form.
+pub fn fuzz_round_trip(data: &[u8]) {
+    let mut u = Unstructured::new(data);
+    let Ok(config) = CorpusConfig::arbitrary(&mut u) else {
+        return;
+    };
+
+    // Only valid modules are guaranteed to round-trip. Anything else must merely not panic.
+    let src = u.take_rest();
+    if !config.options.validate && validate(src, config.options.features).is_err() {
+        let _ = round_trip(src, &config);
+        return;
+    }
+
+    if let Some(kind @ MismatchKind::Differs { .. }) = check_module(src, &config) {
+        panic!("[{}]: {kind}", config.name);
+    }
+}
+
+// === Arbitrary Implementations === //
+
+impl<'a> Arbitrary<'a> for CompressionOptions {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self {
+            enabled: u.arbitrary()?,
+            // High levels are slow enough to starve the fuzzer without exercising anything new.
+            level: u.int_in_range(-5..=9)?,
+            min_len: u.arbitrary::<u16>()?.into(),
+            min_savings: f64::from(u.arbitrary::<u8>()?) / 255.0,
+            // Training is far slower than compressing so dictionaries are kept rare and small.
+            dictionary_size: if u.ratio(1, 8)? {
+                u.int_in_range(256..=4096)?
+            } else {
+                0
+            },
+        })
+    }
+}
+
+/// Chunk sizes are kept small so the fuzzer's modules span several chunks, and always satisfy
+/// [`ChunkingOptions::validate`].
+impl<'a> Arbitrary<'a> for ChunkingOptions {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let min_size = u.int_in_range(1..=256)?;
+        let avg_size = min_size + u.int_in_range(0..=256)?;
+        let max_size = avg_size + u.int_in_range(0..=1024)?;
+
+        Ok(Self {
+            min_size,
+            avg_size,
+            max_size,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for WriterOptions {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        #[cfg(feature = "encryption")]
+        let encryption = if u.arbitrary()? {
+            Some(BlobCipher::new(
+                u.arbitrary::<Vec<u8>>()?,
+                BlobKey::from_bytes(u.arbitrary()?),
+            ))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            compression: u.arbitrary()?,
+            inline_threshold: u.arbitrary::<u16>()?.into(),
+            merkle: u.arbitrary()?,
+            #[cfg(feature = "encryption")]
+            encryption,
+            parallel: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for SplitOptions {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut features = WasmFeatures::default();
+        for name in FEATURE_NAMES {
+            set_feature(&mut features, name, u.arbitrary()?).unwrap();
+        }
+
+        Ok(Self {
+            writer: u.arbitrary()?,
+            features,
+            validate: u.arbitrary()?,
+            normalize: u.arbitrary()?,
+            prioritize: u.arbitrary()?,
+            share_sections: u.arbitrary()?,
+            chunking: u.arbitrary()?,
+            data_chunking: u.arbitrary()?,
+            symbol_names: u.arbitrary()?,
+        })
+    }
+}
+
+/// Configurations are named `fuzz`. Encrypted configurations always come with the key needed to
+/// unlock them.
+impl<'a> Arbitrary<'a> for CorpusConfig {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        #[cfg_attr(not(feature = "encryption"), allow(unused_mut))]
+        let mut options = SplitOptions::arbitrary(u)?;
+
+        #[cfg(feature = "encryption")]
+        let config = if options.writer.encryption.take().is_some() {
+            Self::encrypted("fuzz", options, BlobKey::from_bytes(u.arbitrary()?))
+        } else {
+            Self::new("fuzz", options)
+        };
+
+        #[cfg(not(feature = "encryption"))]
+        let config = Self::new("fuzz", options);
+
+        Ok(Self {
+            parallel: u.arbitrary()?,
+            streaming: u.arbitrary()?,
+            ..config
+        })
+    }
+}
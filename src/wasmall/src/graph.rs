@@ -0,0 +1,423 @@
+//! Exports descriptions of how indices relate to the blobs they reference for use by external
+//! analysis tooling.
+
+use std::{collections::BTreeMap, fmt::Write, ops::Range};
+
+use anyhow::Context;
+use blake3::Hash;
+use wasmparser::{Parser, Payload, TypeRef};
+
+use crate::{
+    coder::{BlobEncoding, WasmallMod, WasmallModSeg},
+    store::BlobSource,
+};
+
+// === Model === //
+
+#[derive(Debug, Clone, Default)]
+pub struct GraphExport {
+    pub modules: Vec<ModuleNode>,
+    pub blobs: BTreeMap<[u8; blake3::OUT_LEN], BlobNode>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ModuleNode {
+    pub name: String,
+    pub module_hash: Hash,
+    pub assembled_len: usize,
+    pub sections: Vec<SectionNode>,
+    pub segments: Vec<SegmentNode>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SectionNode {
+    pub id: u8,
+    pub name: String,
+
+    /// The range of the section in the assembled module, including its header.
+    pub range: Range<usize>,
+
+    /// The number of bytes in the section produced by blobs rather than by verbatim data.
+    pub blob_bytes: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct SegmentNode {
+    pub kind: SegmentNodeKind,
+
+    /// The range of the segment in the assembled module.
+    pub range: Range<usize>,
+
+    /// The index of the section containing the entire segment, if there is one.
+    pub section: Option<usize>,
+
+    /// The index of the function whose body this segment produces, if it produces one.
+    pub function: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub enum SegmentNodeKind {
+    Verbatim,
+    Blob(Hash),
+    InlineBlob,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlobNode {
+    pub hash: Hash,
+    pub encoding: BlobEncoding,
+    pub stored_len: usize,
+    pub expanded_len: usize,
+
+    /// The indices of the modules referencing this blob.
+    pub modules: Vec<usize>,
+}
+
+impl BlobNode {
+    pub fn is_shared(&self) -> bool {
+        self.modules.len() > 1
+    }
+}
+
+// === Construction === //
+
+fn section_name(id: u8, payload: &Payload<'_>) -> String {
+    match payload {
+        Payload::CustomSection(reader) => return format!("custom:{}", reader.name()),
+        Payload::CodeSectionStart { .. } => return "code".to_string(),
+        _ => {}
+    }
+
+    match id {
+        1 => "type",
+        2 => "import",
+        3 => "function",
+        4 => "table",
+        5 => "memory",
+        6 => "global",
+        7 => "export",
+        8 => "start",
+        9 => "element",
+        10 => "code",
+        11 => "data",
+        12 => "data_count",
+        13 => "tag",
+        _ => "unknown",
+    }
+    .to_string()
+}
+
+impl GraphExport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a module to the graph. The module is assembled from `source` to determine how its
+    /// segments map onto its sections and functions.
+    pub fn add_module(
+        &mut self,
+        name: impl Into<String>,
+        module: &WasmallMod<'_>,
+        source: &(impl ?Sized + BlobSource),
+    ) -> anyhow::Result<()> {
+        let module_idx = self.modules.len();
+        let assembled = module.assemble(source)?;
+
+        // Determine the module's layout.
+        let mut sections = Vec::<SectionNode>::new();
+        let mut functions = Vec::<(u32, Range<usize>)>::new();
+        let mut func_imports = 0;
+        let mut next_func = None;
+        let mut prev_end = 8;
+
+        for payload in Parser::new(0).parse_all(&assembled) {
+            let payload = payload.context("failed to parse assembled module")?;
+
+            match &payload {
+                Payload::ImportSection(reader) => {
+                    for import in reader.clone() {
+                        if matches!(import?.ty, TypeRef::Func(_)) {
+                            func_imports += 1;
+                        }
+                    }
+                }
+                Payload::CodeSectionEntry(body) => {
+                    let func = next_func.get_or_insert(func_imports);
+                    functions.push((*func, body.range()));
+                    *func += 1;
+                    continue;
+                }
+                _ => {}
+            }
+
+            if let Some((id, range)) = payload.as_section() {
+                sections.push(SectionNode {
+                    id,
+                    name: section_name(id, &payload),
+                    range: prev_end..range.end,
+                    blob_bytes: 0,
+                });
+                prev_end = range.end;
+            }
+        }
+
+        // Annotate each segment.
+        let mut segments = Vec::new();
+        let mut offset = 0;
+
+        for segment in module.segments() {
+            let segment = segment?;
+            let range = offset..(offset + segment.out_len());
+            offset = range.end;
+
+            let kind = match &segment {
+                WasmallModSeg::Verbatim(_) => SegmentNodeKind::Verbatim,
+                WasmallModSeg::InlineBlob(_) => SegmentNodeKind::InlineBlob,
+                WasmallModSeg::Blob(segment) => {
+                    let hash = segment.hash();
+                    let stored_len = source
+                        .get_blob(hash)?
+                        .with_context(|| format!("missing blob {hash}"))?
+                        .len();
+
+                    let blob = self
+                        .blobs
+                        .entry(*hash.as_bytes())
+                        .or_insert_with(|| BlobNode {
+                            hash,
+                            encoding: segment.encoding(),
+                            stored_len,
+                            expanded_len: segment.blob_len() as usize,
+                            modules: Vec::new(),
+                        });
+
+                    if blob.modules.last() != Some(&module_idx) {
+                        blob.modules.push(module_idx);
+                    }
+
+                    SegmentNodeKind::Blob(hash)
+                }
+            };
+
+            if !matches!(kind, SegmentNodeKind::Verbatim) {
+                for section in &mut sections {
+                    let overlap = range
+                        .end
+                        .min(section.range.end)
+                        .saturating_sub(range.start.max(section.range.start));
+                    section.blob_bytes += overlap;
+                }
+            }
+
+            let section = sections.iter().position(|section| {
+                section.range.start <= range.start && range.end <= section.range.end
+            });
+
+            // Function bodies include their size prefix in the segment but not in the parser's
+            // reported range so we only require the body to be contained in the segment.
+            let function = functions
+                .iter()
+                .find(|(_, body)| range.start <= body.start && body.end == range.end)
+                .map(|&(func, _)| func)
+                .filter(|_| !matches!(kind, SegmentNodeKind::Verbatim));
+
+            segments.push(SegmentNode {
+                kind,
+                range,
+                section,
+                function,
+            });
+        }
+
+        self.modules.push(ModuleNode {
+            name: name.into(),
+            module_hash: module.module_hash(),
+            assembled_len: assembled.len(),
+            sections,
+            segments,
+        });
+
+        Ok(())
+    }
+
+    pub fn shared_blobs(&self) -> impl Iterator<Item = &BlobNode> + '_ {
+        self.blobs.values().filter(|blob| blob.is_shared())
+    }
+}
+
+// === JSON === //
+
+fn write_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_json_opt(out: &mut String, v: Option<impl std::fmt::Display>) {
+    match v {
+        Some(v) => write!(out, "{v}").unwrap(),
+        None => out.push_str("null"),
+    }
+}
+
+impl GraphExport {
+    /// Serializes the graph as a single JSON object with `modules` and `blobs` arrays. Blobs are
+    /// referred to by their hex-encoded hash and modules by their position in `modules`.
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        out.push_str("{\"modules\":[");
+
+        for (i, module) in self.modules.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+
+            out.push_str("{\"name\":");
+            write_json_str(&mut out, &module.name);
+            write!(
+                out,
+                ",\"module_hash\":\"{}\",\"assembled_len\":{},\"sections\":[",
+                module.module_hash, module.assembled_len,
+            )
+            .unwrap();
+
+            for (i, section) in module.sections.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+
+                write!(out, "{{\"id\":{},\"name\":", section.id).unwrap();
+                write_json_str(&mut out, &section.name);
+                write!(
+                    out,
+                    ",\"offset\":{},\"len\":{},\"blob_bytes\":{}}}",
+                    section.range.start,
+                    section.range.len(),
+                    section.blob_bytes,
+                )
+                .unwrap();
+            }
+
+            out.push_str("],\"segments\":[");
+
+            for (i, segment) in module.segments.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+
+                match &segment.kind {
+                    SegmentNodeKind::Verbatim => out.push_str("{\"kind\":\"verbatim\""),
+                    SegmentNodeKind::InlineBlob => out.push_str("{\"kind\":\"inline_blob\""),
+                    SegmentNodeKind::Blob(hash) => {
+                        write!(out, "{{\"kind\":\"blob\",\"blob\":\"{hash}\"").unwrap()
+                    }
+                }
+
+                write!(
+                    out,
+                    ",\"offset\":{},\"len\":{},\"section\":",
+                    segment.range.start,
+                    segment.range.len(),
+                )
+                .unwrap();
+                write_json_opt(&mut out, segment.section);
+                out.push_str(",\"function\":");
+                write_json_opt(&mut out, segment.function);
+                out.push('}');
+            }
+
+            out.push_str("]}");
+        }
+
+        out.push_str("],\"blobs\":[");
+
+        for (i, blob) in self.blobs.values().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+
+            write!(
+                out,
+                "{{\"hash\":\"{}\",\"encoding\":\"{}\",\"stored_len\":{},\"expanded_len\":{},\"modules\":{:?}}}",
+                blob.hash,
+                match blob.encoding {
+                    BlobEncoding::Raw => "raw",
+                    BlobEncoding::Zstd => "zstd",
+                    BlobEncoding::ZstdDict => "zstd-dict",
+                },
+                blob.stored_len,
+                blob.expanded_len,
+                blob.modules,
+            )
+            .unwrap();
+        }
+
+        out.push_str("]}");
+        out
+    }
+}
+
+// === Graphviz === //
+
+impl GraphExport {
+    /// Renders the graph in the graphviz `dot` language. Each module is a box linked to the blobs
+    /// it references, with blobs shared between modules highlighted.
+    pub fn to_graphviz(&self) -> String {
+        let mut out = String::new();
+        out.push_str("digraph wasmall {\n    rankdir=LR;\n    node [fontname=monospace];\n");
+
+        for (i, module) in self.modules.iter().enumerate() {
+            writeln!(
+                out,
+                "    m{i} [shape=box, label=\"{}\\n{} bytes\"];",
+                module.name.replace('\\', "\\\\").replace('"', "\\\""),
+                module.assembled_len,
+            )
+            .unwrap();
+        }
+
+        for blob in self.blobs.values() {
+            writeln!(
+                out,
+                "    b{} [shape=ellipse, label=\"{}\\n{} / {} bytes\"{}];",
+                blob.hash.to_hex(),
+                &blob.hash.to_hex()[..12],
+                blob.stored_len,
+                blob.expanded_len,
+                if blob.is_shared() {
+                    ", style=filled, fillcolor=lightblue"
+                } else {
+                    ""
+                },
+            )
+            .unwrap();
+        }
+
+        for (i, module) in self.modules.iter().enumerate() {
+            for segment in &module.segments {
+                let SegmentNodeKind::Blob(hash) = &segment.kind else {
+                    continue;
+                };
+
+                write!(out, "    m{i} -> b{}", hash.to_hex()).unwrap();
+                if let Some(func) = segment.function {
+                    write!(out, " [label=\"fn {func}\"]").unwrap();
+                }
+                out.push_str(";\n");
+            }
+        }
+
+        out.push_str("}\n");
+        out
+    }
+}
@@ -0,0 +1,310 @@
+//! Determines which function bodies changed between two versions of a module so hot-reloading
+//! engines only need to recompile those.
+//!
+//! This works entirely off of the indices: the splitter produces one blob per function body, so a
+//! body is unchanged exactly when its blob, the data restoring its original bytes if it was
+//! [normalized](crate::normalize), and the values of its relocations are unchanged. The rest
+//! of the module is compared directly, or by blob for the chunks of large data segments. Changes to
+//! it, such as an added import, can shift or reinterpret every function and so always require a
+//! full recompilation. The data section is the exception since it only affects instantiation.
+
+use anyhow::Context;
+use blake3::{Hash, Hasher};
+use wasmparser::{Chunk, Parser, Payload, TypeRef};
+
+use crate::{
+    coder::{WasmallMod, WasmallModSeg},
+    splitter::is_component,
+    util::SectionWalker,
+};
+
+const CODE_SECTION_ID: u8 = 10;
+const DATA_SECTION_ID: u8 = 11;
+
+// === FunctionLayout === //
+
+/// The function bodies of a module, as described by its index.
+#[derive(Debug, Clone)]
+pub struct FunctionLayout {
+    /// The number of imported functions, which is also the index of the first function body.
+    pub func_imports: u32,
+
+    /// A hash identifying each function body's code and relocation values, in order.
+    pub bodies: Vec<Hash>,
+
+    /// A hash identifying just each function body's relocation-zeroed code, in order. Bodies with
+    /// the same code hash but different body hashes only differ in their relocation values.
+    pub code: Vec<Hash>,
+
+    /// The hash of the module's verbatim prefix, excluding the code section's header. The header
+    /// encodes the section's length and therefore changes whenever any body changes size.
+    pub prefix_hash: Hash,
+
+    /// The hash of the non-data sections following the function bodies.
+    pub suffix_hash: Hash,
+
+    /// The hash of the data section.
+    pub data_hash: Hash,
+}
+
+impl FunctionLayout {
+    pub fn new(module: &WasmallMod<'_>) -> anyhow::Result<Self> {
+        // The module header is always stored verbatim.
+        if let Some(WasmallModSeg::Verbatim(header)) = module.segments().next().transpose()? {
+            anyhow::ensure!(
+                !is_component(header.data()),
+                "function layouts of components are not supported"
+            );
+        }
+
+        let mut prefix = Vec::new();
+        let mut bodies = Vec::new();
+        let mut code = Vec::new();
+        let mut suffix_hasher = Hasher::new();
+        let mut data_hasher = Hasher::new();
+
+        // Blobs are told apart by the section they are assembled into since large data segments
+        // are stored as blobs too. The data section is hashed by the identities of its blobs.
+        let mut walker = SectionWalker::default();
+        let mut code_seen = false;
+
+        for segment in module.segments() {
+            let segment = segment?;
+            let mut hasher = Hasher::new();
+            let reloc_values = match &segment {
+                WasmallModSeg::Verbatim(segment) => {
+                    let mut data = segment.data();
+                    while !data.is_empty() {
+                        let (section, bytes) = walker.take(&mut data)?;
+                        code_seen |= section == Some(CODE_SECTION_ID);
+
+                        if !code_seen || section == Some(CODE_SECTION_ID) {
+                            anyhow::ensure!(
+                                section != Some(CODE_SECTION_ID) || bodies.is_empty(),
+                                "index does not store its function bodies contiguously"
+                            );
+                            prefix.extend_from_slice(bytes);
+                        } else if section == Some(DATA_SECTION_ID) {
+                            data_hasher.update(bytes);
+                        } else {
+                            suffix_hasher.update(bytes);
+                        }
+                    }
+                    continue;
+                }
+                WasmallModSeg::Blob(segment) => {
+                    hasher.update(&[0]);
+                    hasher.update(segment.hash().as_bytes());
+                    hasher.update(&(segment.restoration().len() as u64).to_le_bytes());
+                    hasher.update(segment.restoration());
+                    segment.reloc_values()
+                }
+                WasmallModSeg::InlineBlob(segment) => {
+                    hasher.update(&[1]);
+                    hasher.update(&(segment.blob_bytes().len() as u64).to_le_bytes());
+                    hasher.update(segment.blob_bytes());
+                    segment.reloc_values()
+                }
+            };
+
+            let code_hash = hasher.finalize();
+            let body = hasher.update(reloc_values.cursor().0).finalize();
+
+            match walker.take_blob(segment.out_len())? {
+                Some(CODE_SECTION_ID) => {
+                    code.push(code_hash);
+                    bodies.push(body);
+                }
+                Some(DATA_SECTION_ID) => {
+                    data_hasher.update(body.as_bytes());
+                }
+                _ => anyhow::bail!("index contains blobs outside of the code and data sections"),
+            }
+        }
+
+        let (func_imports, code_section) =
+            Self::parse_prefix(&prefix).context("failed to parse module prefix")?;
+
+        // Chunked code sections are stored as blobs too, just not as one blob per body.
+        let count = code_section.map_or(0, |(_, count)| count as usize);
+        anyhow::ensure!(
+            bodies.len() == count,
+            "index stores {} blob(s) for {count} function bodies",
+            bodies.len(),
+        );
+
+        Ok(Self {
+            func_imports,
+            bodies,
+            code,
+            prefix_hash: blake3::hash(
+                &prefix[..code_section.map_or(prefix.len(), |(offset, _)| offset)],
+            ),
+            suffix_hash: suffix_hasher.finalize(),
+            data_hash: data_hasher.finalize(),
+        })
+    }
+
+    /// Determines the number of imported functions along with the offset and body count of the
+    /// code section, if the prefix contains it.
+    fn parse_prefix(prefix: &[u8]) -> anyhow::Result<(u32, Option<(usize, u32)>)> {
+        let mut parser = Parser::new(0);
+        let mut offset = 0;
+        let mut func_imports = 0;
+
+        loop {
+            let (consumed, payload) = match parser.parse(&prefix[offset..], true)? {
+                Chunk::Parsed { consumed, payload } => (consumed, payload),
+                Chunk::NeedMoreData(_) => unreachable!(),
+            };
+
+            match payload {
+                Payload::ImportSection(reader) => {
+                    for import in reader {
+                        if matches!(import?.ty, TypeRef::Func(_)) {
+                            func_imports += 1;
+                        }
+                    }
+                }
+                Payload::CodeSectionStart { count, .. } => {
+                    return Ok((func_imports, Some((offset, count))));
+                }
+                Payload::End(_) => return Ok((func_imports, None)),
+                _ => {}
+            }
+
+            offset += consumed;
+        }
+    }
+}
+
+// === FunctionDiff === //
+
+/// The differences between the function bodies of two versions of a module. Functions are
+/// identified by their index in the module's function index space.
+#[derive(Debug, Clone, Default)]
+pub struct FunctionDiff {
+    /// Whether anything other than the function bodies changed. If so, the individual function
+    /// lists cannot be relied upon and the new module must be compiled in full.
+    pub layout_changed: bool,
+
+    /// Whether the data section changed. This requires reinstantiating the module but doesn't
+    /// invalidate any compiled code.
+    pub data_changed: bool,
+
+    /// Functions present in both versions whose bodies differ.
+    pub changed: Vec<u32>,
+
+    /// Functions which only exist in the new version.
+    pub added: Vec<u32>,
+
+    /// Functions which only exist in the old version.
+    pub removed: Vec<u32>,
+}
+
+impl FunctionDiff {
+    pub fn new(old: &FunctionLayout, new: &FunctionLayout) -> Self {
+        let mut diff = Self {
+            layout_changed: old.func_imports != new.func_imports
+                || old.prefix_hash != new.prefix_hash
+                || old.suffix_hash != new.suffix_hash,
+            data_changed: old.data_hash != new.data_hash,
+            ..Self::default()
+        };
+
+        let base = new.func_imports;
+
+        for (i, (old_body, new_body)) in old.bodies.iter().zip(&new.bodies).enumerate() {
+            if old_body != new_body {
+                diff.changed.push(base + i as u32);
+            }
+        }
+
+        let common = old.bodies.len().min(new.bodies.len()) as u32;
+        diff.added
+            .extend((common..new.bodies.len() as u32).map(|i| base + i));
+        diff.removed
+            .extend((common..old.bodies.len() as u32).map(|i| old.func_imports + i));
+
+        diff
+    }
+
+    /// Diffs the function bodies of two indices.
+    pub fn between(old: &WasmallMod<'_>, new: &WasmallMod<'_>) -> anyhow::Result<Self> {
+        Ok(Self::new(
+            &FunctionLayout::new(old).context("failed to determine old function layout")?,
+            &FunctionLayout::new(new).context("failed to determine new function layout")?,
+        ))
+    }
+
+    /// Whether the two versions are identical.
+    pub fn is_empty(&self) -> bool {
+        !self.layout_changed
+            && !self.data_changed
+            && self.changed.is_empty()
+            && self.added.is_empty()
+            && self.removed.is_empty()
+    }
+
+    /// Whether a partial recompilation suffices to bring the old version up to date.
+    pub fn allows_partial(&self) -> bool {
+        !self.layout_changed && self.removed.is_empty()
+    }
+
+    /// Every function whose compiled code must be discarded.
+    pub fn invalidated(&self) -> impl Iterator<Item = u32> + '_ {
+        self.changed
+            .iter()
+            .chain(&self.added)
+            .chain(&self.removed)
+            .copied()
+    }
+}
+
+// === Code Caches === //
+
+/// A cache of compiled functions maintained by an engine.
+pub trait FunctionCodeCache {
+    /// Discards the compiled code of the specified functions, returning `false` if the engine
+    /// cannot invalidate individual functions. The default implementation always returns `false`.
+    fn invalidate_functions(&mut self, funcs: &[u32]) -> anyhow::Result<bool> {
+        let _ = funcs;
+        Ok(false)
+    }
+
+    /// Discards all compiled code.
+    fn invalidate_all(&mut self) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Invalidation {
+    /// No compiled code was affected so nothing was invalidated.
+    None,
+
+    /// Only the specified number of functions were invalidated.
+    Partial(usize),
+
+    /// Everything was invalidated and the module must be compiled in full.
+    Full,
+}
+
+/// Invalidates the parts of `cache` made stale by `diff`, falling back to invalidating everything
+/// when the diff or the engine doesn't permit a partial invalidation.
+pub fn invalidate(
+    cache: &mut (impl ?Sized + FunctionCodeCache),
+    diff: &FunctionDiff,
+) -> anyhow::Result<Invalidation> {
+    if diff.allows_partial() {
+        let funcs = diff.invalidated().collect::<Vec<_>>();
+        if funcs.is_empty() {
+            return Ok(Invalidation::None);
+        }
+
+        if cache.invalidate_functions(&funcs)? {
+            return Ok(Invalidation::Partial(funcs.len()));
+        }
+    }
+
+    cache.invalidate_all()?;
+    Ok(Invalidation::Full)
+}
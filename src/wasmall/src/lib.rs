@@ -1,4 +1,39 @@
+// Lets `wasmall-derive` refer to this crate as `::wasmall` from within it too.
+extern crate self as wasmall;
+
+pub mod append;
+pub mod builder;
+pub mod bundle;
+pub mod callgraph;
+pub mod car;
+pub mod chunker;
 pub mod coder;
+pub mod corpus;
+#[cfg(feature = "encryption")]
+pub mod crypt;
+pub mod diff;
+pub mod embed;
+pub mod features;
+pub mod filter;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+pub mod graph;
+pub mod incremental;
+pub mod link;
+#[cfg(feature = "live")]
+pub mod live;
+pub mod merkle;
+pub mod normalize;
+#[cfg(feature = "oci")]
+pub mod oci;
+pub mod pack;
 pub mod reloc;
+pub mod resume;
+pub mod runs;
+pub mod simulate;
+#[cfg(feature = "smith")]
+pub mod smith;
 pub mod splitter;
+pub mod store;
+pub mod stream;
 pub mod util;
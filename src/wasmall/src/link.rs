@@ -0,0 +1,1045 @@
+//! Static linking of several relocatable object modules into a single module.
+//!
+//! Split archives drop their custom sections so the `linking` and `reloc.*` metadata needed to
+//! link them is only available in the object modules they were split from. The linker therefore
+//! takes those objects, merges their code and data, resolves their symbols against one another,
+//! applies their relocations, and splits the resulting module into a combined archive.
+//!
+//! Only the subset of the [linking conventions] emitted by LLVM for non-PIC, single-threaded
+//! objects is supported:
+//!
+//! - Objects may import functions and globals, which are resolved against the symbols defined by
+//!   the other objects or left as imports of the linked module. Their memory and table imports are
+//!   replaced by a memory and table the linker defines itself.
+//! - Data segments are laid out one after another starting at the [global base], followed by the
+//!   stack. `__stack_pointer`, `__data_end`, and `__heap_base` are defined accordingly.
+//! - Functions whose address is taken are placed in the table in the order their `TABLE_INDEX`
+//!   relocations are applied, and the element segments of the objects are ignored. Init functions
+//!   are called by a synthesized `__wasm_call_ctors`.
+//! - Objects may not define their own memories, tables, tags, or passive data segments, and weak
+//!   definitions are deduplicated only by name rather than by COMDAT.
+//!
+//! [linking conventions]: https://github.com/WebAssembly/tool-conventions/blob/main/Linking.md
+//! [global base]: LinkOptions::global_base
+
+use std::ops::Range;
+
+use anyhow::Context;
+use rustc_hash::FxHashMap;
+use wasmparser::{
+    CompositeType, DataKind, DefinedDataSymbol, Encoding, FuncType, GlobalType, Operator, Parser,
+    Payload, RefType, SymbolFlags, SymbolInfo, TypeRef, ValType,
+};
+
+use crate::{
+    builder::{ModuleBuilder, SectionBuilder},
+    coder::WasmallArchive,
+    reloc::{
+        rewrite_relocated, LinkingSection, RelocEntry, RelocEntryType, RelocIndex, RelocSection,
+    },
+    splitter::{split_module_with, SplitOptions},
+    util::{ByteCursor, ByteParse, Leb128WriteExt},
+};
+
+// === Options === //
+
+#[derive(Debug, Clone)]
+pub struct LinkOptions {
+    /// The options with which the linked module is split.
+    pub split: SplitOptions,
+
+    /// The address at which the first data segment is placed.
+    pub global_base: u32,
+
+    /// The size of the stack placed after the data segments, in bytes.
+    pub stack_size: u32,
+
+    /// The symbols to export in addition to those the objects mark as exported.
+    pub exports: Vec<String>,
+
+    /// Whether to export the linked module's memory as `memory`.
+    pub export_memory: bool,
+}
+
+impl Default for LinkOptions {
+    fn default() -> Self {
+        Self {
+            split: SplitOptions::default(),
+            global_base: 1024,
+            stack_size: 64 * 1024,
+            exports: Vec::new(),
+            export_memory: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LinkResult {
+    /// The linked module, without any custom sections.
+    pub module: Vec<u8>,
+
+    /// The linked module, split.
+    pub archive: WasmallArchive,
+    pub bytes_truncated: usize,
+}
+
+const STACK_POINTER: &str = "__stack_pointer";
+const DATA_END: &str = "__data_end";
+const HEAP_BASE: &str = "__heap_base";
+const CALL_CTORS: &str = "__wasm_call_ctors";
+
+const PAGE_SIZE: u32 = 64 * 1024;
+
+/// The alignment of the stack pointer mandated by the C ABI.
+const STACK_ALIGN: u32 = 16;
+
+// === Objects === //
+
+#[derive(Debug, Copy, Clone)]
+struct Import<'a, T> {
+    module: &'a str,
+    name: &'a str,
+    ty: T,
+}
+
+/// The parts of an object module the linker cares about. Ranges index into `src`.
+#[derive(Debug, Default)]
+struct ObjectFile<'a> {
+    src: &'a [u8],
+    types: Vec<Vec<u8>>,
+    func_imports: Vec<Import<'a, u32>>,
+    global_imports: Vec<Import<'a, GlobalType>>,
+    func_types: Vec<u32>,
+    globals: Vec<(GlobalType, &'a [u8])>,
+    imports_table: bool,
+    has_data_count: bool,
+
+    code_section: Option<(usize, usize)>,
+    bodies: Vec<Range<usize>>,
+    data_section: Option<(usize, usize)>,
+    segments: Vec<Range<usize>>,
+
+    linking: LinkingSection<'a>,
+    relocs: FxHashMap<usize, RelocIndex>,
+}
+
+impl<'a> ObjectFile<'a> {
+    fn parse(src: &'a [u8]) -> anyhow::Result<Self> {
+        let mut obj = Self {
+            src,
+            ..Self::default()
+        };
+
+        let mut has_linking = false;
+        let mut section_idx = 0;
+
+        for payload in Parser::new(0).parse_all(src) {
+            let payload = payload?;
+            let idx = section_idx;
+            if payload.as_section().is_some() {
+                section_idx += 1;
+            }
+
+            match payload {
+                Payload::Version {
+                    encoding: Encoding::Component,
+                    ..
+                } => anyhow::bail!("components can't be linked"),
+                Payload::TypeSection(reader) => {
+                    for group in reader {
+                        for ty in group?.into_types() {
+                            let CompositeType::Func(func) = &ty.composite_type else {
+                                anyhow::bail!("only function types are supported");
+                            };
+
+                            obj.types.push(encode_func_type(func)?);
+                        }
+                    }
+                }
+                Payload::ImportSection(reader) => {
+                    for import in reader {
+                        let import = import?;
+                        let (module, name) = (import.module, import.name);
+
+                        match import.ty {
+                            TypeRef::Func(ty) => obj.func_imports.push(Import { module, name, ty }),
+                            TypeRef::Global(ty) => {
+                                obj.global_imports.push(Import { module, name, ty })
+                            }
+                            TypeRef::Table(_) => obj.imports_table = true,
+                            TypeRef::Memory(_) => {}
+                            TypeRef::Tag(_) => anyhow::bail!("tags are not supported"),
+                        }
+                    }
+                }
+                Payload::FunctionSection(reader) => {
+                    for ty in reader {
+                        obj.func_types.push(ty?);
+                    }
+                }
+                Payload::GlobalSection(reader) => {
+                    for global in reader {
+                        let global = global?;
+
+                        for op in global.init_expr.get_operators_reader() {
+                            if let Operator::GlobalGet { .. } = op? {
+                                anyhow::bail!("global initializers may not refer to other globals");
+                            }
+                        }
+
+                        let mut init = global.init_expr.get_binary_reader();
+                        let init = init.read_bytes(init.bytes_remaining())?;
+                        obj.globals.push((global.ty, init));
+                    }
+                }
+                Payload::DataCountSection { .. } => obj.has_data_count = true,
+                Payload::DataSection(reader) => {
+                    obj.data_section = Some((idx, reader.range().start));
+
+                    for data in reader {
+                        let data = data?;
+
+                        anyhow::ensure!(
+                            matches!(
+                                data.kind,
+                                DataKind::Active {
+                                    memory_index: 0,
+                                    ..
+                                }
+                            ),
+                            "only active segments of the first memory can be linked",
+                        );
+
+                        // The segment's bytes make up the end of its entry.
+                        let end = data.range.end;
+                        obj.segments.push(end - data.data.len()..end);
+                    }
+                }
+                Payload::CodeSectionStart { range, .. } => {
+                    obj.code_section = Some((idx, range.start));
+                }
+                Payload::CodeSectionEntry(body) => obj.bodies.push(body.range()),
+                Payload::CustomSection(reader) if reader.name() == "linking" => {
+                    has_linking = true;
+                    obj.linking = LinkingSection::parse(reader.data(), reader.data_offset())?;
+                }
+                Payload::CustomSection(reader) if reader.name().starts_with("reloc.") => {
+                    let relocs = RelocSection::parse(&mut ByteCursor(reader.data()))?;
+                    let entries = relocs
+                        .entries()
+                        .collect::<anyhow::Result<Vec<_>>>()
+                        .with_context(|| format!("failed to parse {:?}", reader.name()))?;
+
+                    obj.relocs
+                        .insert(relocs.target_section as usize, RelocIndex::new(entries));
+                }
+                Payload::TableSection(_) => anyhow::bail!("objects may not define tables"),
+                Payload::MemorySection(_) => anyhow::bail!("objects may not define memories"),
+                Payload::TagSection(_) => anyhow::bail!("tags are not supported"),
+                Payload::StartSection { .. } => {
+                    anyhow::bail!("objects may not have start functions")
+                }
+                // LLVM lists the functions whose address is taken in an element segment, but the
+                // table is built from the relocations which take their addresses instead.
+                Payload::ElementSection(_) => {}
+                _ => {}
+            }
+        }
+
+        anyhow::ensure!(
+            has_linking,
+            "module is not a relocatable object since it lacks a \"linking\" section",
+        );
+
+        obj.check_indices()?;
+
+        Ok(obj)
+    }
+
+    /// Ensures that the indices the linker looks things up by refer to things the object has.
+    fn check_indices(&self) -> anyhow::Result<()> {
+        for (i, import) in self.func_imports.iter().enumerate() {
+            anyhow::ensure!(
+                (import.ty as usize) < self.types.len(),
+                "function import {i} has the missing type {}",
+                import.ty,
+            );
+        }
+
+        for (i, symbol) in self.linking.symbols.iter().enumerate() {
+            let undefined = symbol_flags(symbol).contains(SymbolFlags::UNDEFINED);
+            let check = |kind: &str, index: u32, imports: usize, defined: usize| {
+                let index = index as usize;
+                let (valid, what) = if undefined {
+                    (index < imports, "import")
+                } else {
+                    ((imports..imports + defined).contains(&index), "define")
+                };
+
+                anyhow::ensure!(
+                    valid,
+                    "{kind} symbol {i} refers to {kind} {index}, which the object doesn't {what}",
+                );
+                Ok(())
+            };
+
+            match *symbol {
+                SymbolInfo::Func { index, .. } => check(
+                    "function",
+                    index,
+                    self.func_imports.len(),
+                    self.func_types.len(),
+                )?,
+                SymbolInfo::Global { index, .. } => check(
+                    "global",
+                    index,
+                    self.global_imports.len(),
+                    self.globals.len(),
+                )?,
+                SymbolInfo::Data {
+                    symbol: Some(data), ..
+                } => {
+                    let segment = self.segments.get(data.index as usize).with_context(|| {
+                        format!(
+                            "data symbol {i} refers to the missing segment {}",
+                            data.index
+                        )
+                    })?;
+
+                    anyhow::ensure!(
+                        data.offset
+                            .checked_add(data.size)
+                            .is_some_and(|end| end as usize <= segment.len()),
+                        "data symbol {i} lies outside of segment {}",
+                        data.index,
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn relocs_in(&self, section: Option<(usize, usize)>, range: &Range<usize>) -> Vec<RelocEntry> {
+        let Some((idx, start)) = section else {
+            return Vec::new();
+        };
+
+        let Some(relocs) = self.relocs.get(&idx) else {
+            return Vec::new();
+        };
+
+        relocs
+            .entries_in_range((range.start - start) as u32..(range.end - start) as u32)
+            .collect()
+    }
+
+    /// The name a function or global symbol is resolved by. Undefined symbols without an explicit
+    /// name take the name of their import.
+    fn symbol_name(&self, symbol: &SymbolInfo<'a>) -> Option<&'a str> {
+        match *symbol {
+            SymbolInfo::Func { name, index, .. } => {
+                name.or_else(|| Some(self.func_imports.get(index as usize)?.name))
+            }
+            SymbolInfo::Global { name, index, .. } => {
+                name.or_else(|| Some(self.global_imports.get(index as usize)?.name))
+            }
+            SymbolInfo::Data { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+}
+
+fn symbol_flags(symbol: &SymbolInfo<'_>) -> SymbolFlags {
+    match *symbol {
+        SymbolInfo::Func { flags, .. }
+        | SymbolInfo::Data { flags, .. }
+        | SymbolInfo::Global { flags, .. }
+        | SymbolInfo::Section { flags, .. }
+        | SymbolInfo::Event { flags, .. }
+        | SymbolInfo::Table { flags, .. } => flags,
+    }
+}
+
+fn encode_val_type(ty: ValType, out: &mut Vec<u8>) -> anyhow::Result<()> {
+    out.push(match ty {
+        ValType::I32 => 0x7F,
+        ValType::I64 => 0x7E,
+        ValType::F32 => 0x7D,
+        ValType::F64 => 0x7C,
+        ValType::V128 => 0x7B,
+        ValType::Ref(ty) if ty == RefType::FUNCREF => 0x70,
+        ValType::Ref(ty) if ty == RefType::EXTERNREF => 0x6F,
+        ty => anyhow::bail!("unsupported value type {ty:?}"),
+    });
+
+    Ok(())
+}
+
+fn encode_func_type(func: &FuncType) -> anyhow::Result<Vec<u8>> {
+    let mut out = vec![0x60];
+
+    for list in [func.params(), func.results()] {
+        out.write_var_u32(list.len() as u32);
+        for &ty in list {
+            encode_val_type(ty, &mut out)?;
+        }
+    }
+
+    Ok(out)
+}
+
+fn encode_global_type(ty: GlobalType, out: &mut Vec<u8>) -> anyhow::Result<()> {
+    encode_val_type(ty.content_type, out)?;
+    out.push(ty.mutable as u8);
+    Ok(())
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    out.write_var_u32(name.len() as u32);
+    out.extend_from_slice(name.as_bytes());
+}
+
+// === Resolution === //
+
+#[derive(Debug, Copy, Clone)]
+enum Definition {
+    Func {
+        object: usize,
+        index: u32,
+    },
+    Data {
+        object: usize,
+        symbol: DefinedDataSymbol,
+    },
+    Global {
+        object: usize,
+        index: u32,
+    },
+}
+
+/// The value a symbol of an object takes on in the linked module.
+#[derive(Debug, Copy, Clone)]
+enum Resolved {
+    Func(u32),
+    Data(u32),
+    Global(u32),
+    Table(u32),
+    Unsupported,
+}
+
+/// The deduplicated imports of the linked module.
+#[derive(Debug)]
+struct ImportSet<'a, T> {
+    imports: Vec<Import<'a, T>>,
+    indices: FxHashMap<(&'a str, &'a str), u32>,
+}
+
+impl<T> Default for ImportSet<'_, T> {
+    fn default() -> Self {
+        Self {
+            imports: Vec::new(),
+            indices: FxHashMap::default(),
+        }
+    }
+}
+
+impl<'a, T: PartialEq> ImportSet<'a, T> {
+    fn insert(&mut self, import: Import<'a, T>) -> anyhow::Result<()> {
+        if let Some(&idx) = self.indices.get(&(import.module, import.name)) {
+            anyhow::ensure!(
+                self.imports[idx as usize].ty == import.ty,
+                "objects import {:?} from {:?} with different types",
+                import.name,
+                import.module,
+            );
+
+            return Ok(());
+        }
+
+        self.indices
+            .insert((import.module, import.name), self.imports.len() as u32);
+        self.imports.push(import);
+        Ok(())
+    }
+
+    fn get(&self, module: &str, name: &str) -> anyhow::Result<u32> {
+        self.indices
+            .get(&(module, name))
+            .copied()
+            .with_context(|| format!("{name:?} is not imported from {module:?}"))
+    }
+}
+
+/// The address-taken functions placed in the table, starting at index 1.
+#[derive(Debug, Default)]
+struct TableSlots {
+    funcs: Vec<u32>,
+    slots: FxHashMap<u32, u32>,
+}
+
+impl TableSlots {
+    fn slot(&mut self, func: u32) -> u32 {
+        *self.slots.entry(func).or_insert_with(|| {
+            self.funcs.push(func);
+            self.funcs.len() as u32
+        })
+    }
+}
+
+fn relocation_value(
+    entry: &RelocEntry,
+    symbols: &[Resolved],
+    type_map: &[u32],
+    table: &mut TableSlots,
+) -> anyhow::Result<u64> {
+    use RelocEntryType::*;
+
+    let symbol = || {
+        symbols
+            .get(entry.index as usize)
+            .copied()
+            .with_context(|| format!("relocation refers to the missing symbol {}", entry.index))
+    };
+
+    Ok(match (entry.ty, symbol()) {
+        (TypeIndexLeb, _) => type_map
+            .get(entry.index as usize)
+            .map(|&idx| idx.into())
+            .with_context(|| format!("relocation refers to the missing type {}", entry.index))?,
+        (FunctionIndexLeb | FunctionIndexI32, Ok(Resolved::Func(idx))) => idx.into(),
+        (
+            TableIndexSleb | TableIndexI32 | TableIndexSleb64 | TableIndexI64,
+            Ok(Resolved::Func(idx)),
+        ) => table.slot(idx).into(),
+        (
+            MemoryAddrLeb | MemoryAddrSleb | MemoryAddrI32 | MemoryAddrLeb64 | MemoryAddrSleb64
+            | MemoryAddrI64,
+            Ok(Resolved::Data(addr)),
+        ) => u64::from(addr).wrapping_add_signed(entry.addend.unwrap_or(0)),
+        (GlobalIndexLeb | GlobalIndexI32, Ok(Resolved::Global(idx))) => idx.into(),
+        (TableNumberLeb, Ok(Resolved::Table(idx))) => idx.into(),
+        (
+            FunctionOffsetI32 | FunctionOffsetI64 | SectionOffsetI32 | TagIndexLeb
+            | MemoryAddrRelSleb | MemoryAddrRelSleb64 | MemoryAddrTlsSleb | MemoryAddrTlsSleb64
+            | MemoryAddrLocrelI32 | TableIndexRelSleb | TableIndexRelSleb64,
+            _,
+        ) => {
+            anyhow::bail!("{:?} relocations are not supported", entry.ty)
+        }
+        (_, Err(err)) => return Err(err),
+        (ty, Ok(resolved)) => {
+            anyhow::bail!(
+                "{ty:?} relocation refers to symbol {}, which resolved to {resolved:?}",
+                entry.index,
+            )
+        }
+    })
+}
+
+fn relocate(
+    data: &[u8],
+    entries: &[RelocEntry],
+    symbols: &[Resolved],
+    type_map: &[u32],
+    table: &mut TableSlots,
+) -> anyhow::Result<Vec<u8>> {
+    let rewrites = entries
+        .iter()
+        .map(|entry| {
+            let value = relocation_value(entry, symbols, type_map, table)
+                .with_context(|| format!("failed to relocate offset {:#x}", entry.offset))?;
+
+            Ok((
+                entry.offset as usize,
+                entry.ty.rewrite_kind().with_value_u64(value),
+            ))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut out = Vec::with_capacity(data.len());
+    rewrite_relocated(data, &mut out, &mut (), rewrites)?;
+    Ok(out)
+}
+
+// === Linking === //
+
+/// Links the relocatable `objects` into a single module and splits it.
+pub fn link_objects(objects: &[&[u8]], options: &LinkOptions) -> anyhow::Result<LinkResult> {
+    let objects = objects
+        .iter()
+        .enumerate()
+        .map(|(i, src)| {
+            ObjectFile::parse(src).with_context(|| format!("failed to parse object {i}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    // Deduplicate the types of every object.
+    let mut types = Vec::<Vec<u8>>::new();
+    let mut type_indices = FxHashMap::<Vec<u8>, u32>::default();
+    let mut intern_type = |ty: &Vec<u8>| {
+        *type_indices.entry(ty.clone()).or_insert_with(|| {
+            types.push(ty.clone());
+            types.len() as u32 - 1
+        })
+    };
+
+    let type_maps = objects
+        .iter()
+        .map(|obj| obj.types.iter().map(&mut intern_type).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    let has_ctors = objects.iter().any(|obj| !obj.linking.init_funcs.is_empty());
+    let ctors_type = has_ctors.then(|| intern_type(&vec![0x60, 0, 0]));
+
+    // Collect the definitions of every non-local symbol. Strong definitions take precedence over
+    // weak ones and the first of several weak definitions wins.
+    let mut definitions = FxHashMap::<&str, (Definition, bool)>::default();
+
+    for (object, obj) in objects.iter().enumerate() {
+        for symbol in &obj.linking.symbols {
+            let flags = symbol_flags(symbol);
+            if flags.intersects(SymbolFlags::UNDEFINED | SymbolFlags::BINDING_LOCAL) {
+                continue;
+            }
+
+            let definition = match *symbol {
+                SymbolInfo::Func { index, .. } => Definition::Func { object, index },
+                SymbolInfo::Data {
+                    symbol: Some(symbol),
+                    ..
+                } => Definition::Data { object, symbol },
+                SymbolInfo::Global { index, .. } => Definition::Global { object, index },
+                _ => continue,
+            };
+
+            let Some(name) = obj.symbol_name(symbol) else {
+                continue;
+            };
+
+            let weak = flags.contains(SymbolFlags::BINDING_WEAK);
+            match definitions.get(name) {
+                Some((_, false)) if !weak => anyhow::bail!("symbol {name:?} is defined twice"),
+                Some((_, prev_weak)) if weak || !prev_weak => {}
+                _ => {
+                    definitions.insert(name, (definition, weak));
+                }
+            }
+        }
+    }
+
+    // Determine which undefined symbols remain imports of the linked module.
+    let mut func_imports = ImportSet::<u32>::default();
+    let mut global_imports = ImportSet::<GlobalType>::default();
+    let mut uses_stack_pointer = false;
+
+    for (object, obj) in objects.iter().enumerate() {
+        for symbol in &obj.linking.symbols {
+            if !symbol_flags(symbol).contains(SymbolFlags::UNDEFINED) {
+                continue;
+            }
+
+            let name = obj.symbol_name(symbol);
+            if name.is_some_and(|name| definitions.contains_key(name)) {
+                continue;
+            }
+
+            match *symbol {
+                SymbolInfo::Func { index, .. } if !(has_ctors && name == Some(CALL_CTORS)) => {
+                    let import = obj
+                        .func_imports
+                        .get(index as usize)
+                        .context("undefined function symbol refers to a missing import")?;
+
+                    func_imports.insert(Import {
+                        ty: type_maps[object][import.ty as usize],
+                        ..*import
+                    })?;
+                }
+                SymbolInfo::Global { .. } if name == Some(STACK_POINTER) => {
+                    uses_stack_pointer = true;
+                }
+                SymbolInfo::Global { index, .. } => {
+                    let import = obj
+                        .global_imports
+                        .get(index as usize)
+                        .context("undefined global symbol refers to a missing import")?;
+
+                    global_imports.insert(*import)?;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Assign the indices of everything the objects define.
+    let mut func_bases = Vec::with_capacity(objects.len());
+    let mut next_func = func_imports.imports.len() as u32;
+    for obj in &objects {
+        func_bases.push(next_func);
+        next_func += obj.func_types.len() as u32;
+    }
+    let ctors_index = has_ctors.then_some(next_func);
+
+    let stack_pointer_index = global_imports.imports.len() as u32;
+    let mut global_bases = Vec::with_capacity(objects.len());
+    let mut next_global = stack_pointer_index + uses_stack_pointer as u32;
+    for obj in &objects {
+        global_bases.push(next_global);
+        next_global += obj.globals.len() as u32;
+    }
+
+    // Lay out the data segments, followed by the stack.
+    let mut segment_addrs = Vec::with_capacity(objects.len());
+    let mut data_end = options.global_base;
+
+    for (object, obj) in objects.iter().enumerate() {
+        let mut addrs = Vec::with_capacity(obj.segments.len());
+
+        for (i, segment) in obj.segments.iter().enumerate() {
+            let align = obj
+                .linking
+                .segments
+                .get(i)
+                .map_or(0, |info| info.alignment)
+                .min(31);
+
+            let addr = data_end
+                .checked_next_multiple_of(1 << align)
+                .and_then(|addr| Some((addr, addr.checked_add(segment.len() as u32)?)))
+                .with_context(|| format!("data segment {i} of object {object} doesn't fit"))?;
+
+            addrs.push(addr.0);
+            data_end = addr.1;
+        }
+
+        segment_addrs.push(addrs);
+    }
+
+    let heap_base = data_end
+        .checked_next_multiple_of(STACK_ALIGN)
+        .and_then(|stack| stack.checked_add(options.stack_size))
+        .and_then(|top| top.checked_next_multiple_of(STACK_ALIGN))
+        .context("stack doesn't fit in memory")?;
+
+    let min_pages = heap_base.div_ceil(PAGE_SIZE).max(1);
+
+    // Resolve every symbol of every object.
+    let resolve_definition = |definition: Definition| match definition {
+        Definition::Func { object, index } => {
+            let imports = objects[object].func_imports.len() as u32;
+            Resolved::Func(func_bases[object] + index - imports)
+        }
+        Definition::Data { object, symbol } => {
+            Resolved::Data(segment_addrs[object][symbol.index as usize] + symbol.offset)
+        }
+        Definition::Global { object, index } => {
+            let imports = objects[object].global_imports.len() as u32;
+            Resolved::Global(global_bases[object] + index - imports)
+        }
+    };
+
+    let mut resolved = Vec::with_capacity(objects.len());
+
+    for (object, obj) in objects.iter().enumerate() {
+        let mut symbols = Vec::with_capacity(obj.linking.symbols.len());
+
+        for symbol in &obj.linking.symbols {
+            let flags = symbol_flags(symbol);
+            let name = obj.symbol_name(symbol);
+
+            let definition = match name.and_then(|name| definitions.get(name)) {
+                Some(&(definition, _)) if !flags.contains(SymbolFlags::BINDING_LOCAL) => {
+                    Some(definition)
+                }
+                _ if flags.contains(SymbolFlags::UNDEFINED) => None,
+                _ => match *symbol {
+                    SymbolInfo::Func { index, .. } => Some(Definition::Func { object, index }),
+                    SymbolInfo::Data {
+                        symbol: Some(symbol),
+                        ..
+                    } => Some(Definition::Data { object, symbol }),
+                    SymbolInfo::Global { index, .. } => Some(Definition::Global { object, index }),
+                    _ => None,
+                },
+            };
+
+            symbols.push(match (definition, *symbol) {
+                (Some(definition), _) => resolve_definition(definition),
+                (None, SymbolInfo::Func { index, .. }) => match ctors_index {
+                    Some(ctors) if name == Some(CALL_CTORS) => Resolved::Func(ctors),
+                    _ => {
+                        // Symbol indices were checked when the object was parsed.
+                        let import = &obj.func_imports[index as usize];
+                        Resolved::Func(func_imports.get(import.module, import.name)?)
+                    }
+                },
+                (None, SymbolInfo::Global { .. }) if name == Some(STACK_POINTER) => {
+                    Resolved::Global(stack_pointer_index)
+                }
+                (None, SymbolInfo::Global { index, .. }) => {
+                    let import = &obj.global_imports[index as usize];
+                    Resolved::Global(global_imports.get(import.module, import.name)?)
+                }
+                // Objects import the table the linker defines, which is the only one.
+                (None, SymbolInfo::Table { .. }) => Resolved::Table(0),
+                (None, SymbolInfo::Data { name: DATA_END, .. }) => Resolved::Data(data_end),
+                (
+                    None,
+                    SymbolInfo::Data {
+                        name: HEAP_BASE, ..
+                    },
+                ) => Resolved::Data(heap_base),
+                (None, SymbolInfo::Data { .. }) if flags.contains(SymbolFlags::BINDING_WEAK) => {
+                    Resolved::Data(0)
+                }
+                (None, SymbolInfo::Data { name, .. }) => {
+                    anyhow::bail!("object {object} refers to the undefined data symbol {name:?}")
+                }
+                _ => Resolved::Unsupported,
+            });
+        }
+
+        resolved.push(symbols);
+    }
+
+    // Apply the relocations of every function body and data segment.
+    let mut table = TableSlots::default();
+
+    let mut code = SectionBuilder::vec(10);
+    let mut data = SectionBuilder::vec(11);
+
+    for (object, obj) in objects.iter().enumerate() {
+        let (symbols, type_map) = (&resolved[object], &type_maps[object]);
+
+        for (i, body) in obj.bodies.iter().enumerate() {
+            let relocs = obj.relocs_in(obj.code_section, body);
+            let body = relocate(
+                &obj.src[body.clone()],
+                &relocs,
+                symbols,
+                type_map,
+                &mut table,
+            )
+            .with_context(|| format!("failed to link function {i} of object {object}"))?;
+
+            let item = code.item();
+            item.write_var_u32(body.len() as u32);
+            item.extend_from_slice(&body);
+        }
+
+        for (i, segment) in obj.segments.iter().enumerate() {
+            let relocs = obj.relocs_in(obj.data_section, segment);
+            let bytes = relocate(
+                &obj.src[segment.clone()],
+                &relocs,
+                symbols,
+                type_map,
+                &mut table,
+            )
+            .with_context(|| format!("failed to link data segment {i} of object {object}"))?;
+
+            let item = data.item();
+            item.push(0x00);
+            item.push(0x41);
+            item.write_var_i32(segment_addrs[object][i] as i32);
+            item.push(0x0B);
+            item.write_var_u32(bytes.len() as u32);
+            item.extend_from_slice(&bytes);
+        }
+    }
+
+    if has_ctors {
+        let mut inits = objects
+            .iter()
+            .enumerate()
+            .flat_map(|(object, obj)| {
+                obj.linking
+                    .init_funcs
+                    .iter()
+                    .map(move |init| (object, init))
+            })
+            .collect::<Vec<_>>();
+
+        inits.sort_by_key(|(_, init)| init.priority);
+
+        let mut body = vec![0x00];
+        for (object, init) in inits {
+            let Some(Resolved::Func(func)) = resolved[object].get(init.symbol_index as usize)
+            else {
+                anyhow::bail!("init function of object {object} is not a function");
+            };
+
+            body.push(0x10);
+            body.write_var_u32(*func);
+        }
+        body.push(0x0B);
+
+        let item = code.item();
+        item.write_var_u32(body.len() as u32);
+        item.extend_from_slice(&body);
+    }
+
+    // Determine the exports.
+    let mut exports = Vec::<(&str, u8, u32)>::new();
+
+    if options.export_memory {
+        exports.push(("memory", 0x02, 0));
+    }
+
+    let exported = objects.iter().zip(&resolved).flat_map(|(obj, resolved)| {
+        obj.linking
+            .symbols
+            .iter()
+            .zip(resolved)
+            .filter_map(|(symbol, &resolved)| {
+                let flags = symbol_flags(symbol);
+                (flags.contains(SymbolFlags::EXPORTED) && !flags.contains(SymbolFlags::UNDEFINED))
+                    .then(|| Some((obj.symbol_name(symbol)?, resolved)))
+                    .flatten()
+            })
+    });
+
+    let requested = options.exports.iter().map(|name| {
+        let resolved = match definitions.get(name.as_str()) {
+            Some(&(definition, _)) => resolve_definition(definition),
+            None => match ctors_index {
+                Some(ctors) if name == CALL_CTORS => Resolved::Func(ctors),
+                _ => anyhow::bail!("no object defines the exported symbol {name:?}"),
+            },
+        };
+
+        Ok((name.as_str(), resolved))
+    });
+
+    for export in requested
+        .collect::<anyhow::Result<Vec<_>>>()?
+        .into_iter()
+        .chain(exported)
+    {
+        let (name, kind, index) = match export {
+            (name, Resolved::Func(idx)) => (name, 0x00, idx),
+            (name, Resolved::Global(idx)) => (name, 0x03, idx),
+            (name, _) => anyhow::bail!("symbol {name:?} can't be exported"),
+        };
+
+        if !exports.iter().any(|&(other, ..)| other == name) {
+            exports.push((name, kind, index));
+        }
+    }
+
+    // Emit the module.
+    let mut module = ModuleBuilder::new(Vec::new());
+
+    let mut section = SectionBuilder::vec(1);
+    for ty in &types {
+        section.item().extend_from_slice(ty);
+    }
+    module.build_section(section)?;
+
+    let mut section = SectionBuilder::vec(2);
+    for import in &func_imports.imports {
+        let item = section.item();
+        write_name(item, import.module);
+        write_name(item, import.name);
+        item.push(0x00);
+        item.write_var_u32(import.ty);
+    }
+    for import in &global_imports.imports {
+        let item = section.item();
+        write_name(item, import.module);
+        write_name(item, import.name);
+        item.push(0x03);
+        encode_global_type(import.ty, item)?;
+    }
+    module.build_section(section)?;
+
+    let mut section = SectionBuilder::vec(3);
+    for (object, obj) in objects.iter().enumerate() {
+        for &ty in &obj.func_types {
+            let ty = *type_maps[object]
+                .get(ty as usize)
+                .with_context(|| format!("function of object {object} has a missing type"))?;
+
+            section.item().write_var_u32(ty);
+        }
+    }
+    if let Some(ty) = ctors_type {
+        section.item().write_var_u32(ty);
+    }
+    module.build_section(section)?;
+
+    if !table.funcs.is_empty() || objects.iter().any(|obj| obj.imports_table) {
+        let size = table.funcs.len() as u32 + 1;
+
+        let mut section = SectionBuilder::vec(4);
+        let item = section.item();
+        item.extend_from_slice(&[0x70, 0x01]);
+        item.write_var_u32(size);
+        item.write_var_u32(size);
+        module.build_section(section)?;
+    }
+
+    let mut section = SectionBuilder::vec(5);
+    let item = section.item();
+    item.push(0x00);
+    item.write_var_u32(min_pages);
+    module.build_section(section)?;
+
+    let mut section = SectionBuilder::vec(6);
+    if uses_stack_pointer {
+        let item = section.item();
+        item.extend_from_slice(&[0x7F, 0x01, 0x41]);
+        item.write_var_i32(heap_base as i32);
+        item.push(0x0B);
+    }
+    for obj in &objects {
+        for &(ty, init) in &obj.globals {
+            let item = section.item();
+            encode_global_type(ty, item)?;
+            item.extend_from_slice(init);
+        }
+    }
+    module.build_section(section)?;
+
+    let mut section = SectionBuilder::vec(7);
+    for &(name, kind, index) in &exports {
+        let item = section.item();
+        write_name(item, name);
+        item.push(kind);
+        item.write_var_u32(index);
+    }
+    module.build_section(section)?;
+
+    if !table.funcs.is_empty() {
+        let mut section = SectionBuilder::vec(9);
+        let item = section.item();
+        item.extend_from_slice(&[0x00, 0x41, 0x01, 0x0B]);
+        item.write_var_u32(table.funcs.len() as u32);
+        for &func in &table.funcs {
+            item.write_var_u32(func);
+        }
+        module.build_section(section)?;
+    }
+
+    if objects.iter().any(|obj| obj.has_data_count) {
+        let mut section = SectionBuilder::new(12);
+        section.data().write_var_u32(data.item_count());
+        module.build_section(section)?;
+    }
+
+    module.build_section(code)?;
+    module.build_section(data)?;
+
+    let module = module.finish();
+
+    let split =
+        split_module_with(&module, &options.split).context("failed to split linked module")?;
+
+    Ok(LinkResult {
+        module,
+        archive: split.archive,
+        bytes_truncated: split.bytes_truncated,
+    })
+}
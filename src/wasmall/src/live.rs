@@ -0,0 +1,516 @@
+//! Live pushing of freshly built modules to running clients over WebSocket.
+//!
+//! The [`LiveServer`] watches a directory for index files, named `<module>.wsml`, and pushes every
+//! new version of them to its subscribers. A [`LiveClient`] then requests whichever blobs it hasn't
+//! seen before, assembles the module, and hands it to a hot-reload callback. Since builds usually
+//! only change a handful of functions, a reload typically transfers little more than the index.
+//!
+//! Every WebSocket message is a single binary [`LiveMessage`].
+
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    fmt, fs,
+    io::ErrorKind,
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::Context;
+use blake3::Hash;
+use rustc_hash::{FxHashMap, FxHashSet};
+use tungstenite::{error::ProtocolError, stream::MaybeTlsStream, Message, WebSocket};
+
+use crate::{
+    coder::{WasmallArchive, WasmallMod},
+    store::{BlobSource, DirBlobStore},
+    util::{write_atomic, ByteCursor, ByteParse, Leb128WriteExt, VarByteVec},
+};
+
+/// The extension of the index files watched by the [`LiveServer`].
+pub const INDEX_EXTENSION: &str = "wsml";
+
+/// How long sessions block on the socket before checking for pushes.
+const SESSION_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// === Protocol === //
+
+const TAG_INDEX: u8 = 0;
+const TAG_WANT: u8 = 1;
+const TAG_BLOBS: u8 = 2;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LiveMessage {
+    /// Sent by the server whenever a module is published, and for every known module once a
+    /// client connects.
+    Index { name: String, index: Vec<u8> },
+
+    /// Sent by the client to request the blobs it is missing.
+    Want(Vec<Hash>),
+
+    /// Sent by the server in response to a [`LiveMessage::Want`]. Blobs the server doesn't have are
+    /// omitted.
+    Blobs(Vec<(Hash, Vec<u8>)>),
+}
+
+impl LiveMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        match self {
+            Self::Index { name, index } => {
+                out.push(TAG_INDEX);
+                out.write_var_u32(name.len() as u32);
+                out.extend_from_slice(name.as_bytes());
+                out.write_var_u32(index.len() as u32);
+                out.extend_from_slice(index);
+            }
+            Self::Want(hashes) => {
+                out.push(TAG_WANT);
+                out.write_var_u32(hashes.len() as u32);
+                for hash in hashes {
+                    out.extend_from_slice(hash.as_bytes());
+                }
+            }
+            Self::Blobs(blobs) => {
+                out.push(TAG_BLOBS);
+                out.write_var_u32(blobs.len() as u32);
+                for (hash, data) in blobs {
+                    out.extend_from_slice(hash.as_bytes());
+                    out.write_var_u32(data.len() as u32);
+                    out.extend_from_slice(data);
+                }
+            }
+        }
+
+        out
+    }
+
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        let mut cursor = ByteCursor(data);
+
+        let message = match cursor.read_u8().context("failed to read message tag")? {
+            TAG_INDEX => {
+                let name = VarByteVec::parse(&mut cursor).context("failed to read module name")?;
+                let name = std::str::from_utf8(name).context("module name is not UTF-8")?;
+                let index = VarByteVec::parse(&mut cursor).context("failed to read index")?;
+
+                Self::Index {
+                    name: name.to_string(),
+                    index: index.to_vec(),
+                }
+            }
+            TAG_WANT => {
+                let count = cursor.read_var_u32()?;
+                let mut hashes = Vec::new();
+                for _ in 0..count {
+                    hashes.push(Hash::from_bytes(cursor.consume_arr()?));
+                }
+
+                Self::Want(hashes)
+            }
+            TAG_BLOBS => {
+                let count = cursor.read_var_u32()?;
+                let mut blobs = Vec::new();
+                for _ in 0..count {
+                    let hash = Hash::from_bytes(cursor.consume_arr()?);
+                    let data = VarByteVec::parse(&mut cursor)?;
+                    blobs.push((hash, data.to_vec()));
+                }
+
+                Self::Blobs(blobs)
+            }
+            tag => anyhow::bail!("unknown message tag {tag}"),
+        };
+
+        anyhow::ensure!(cursor.at_eof(), "trailing data after message");
+        Ok(message)
+    }
+}
+
+// === Publishing === //
+
+/// Writes an archive's blobs into `store` and its index into `index_dir` under `name`, where a
+/// [`LiveServer`] watching the directory will pick it up. The index is written last and atomically
+/// so the server never observes an index whose blobs are missing.
+pub fn publish(
+    archive: &WasmallArchive,
+    name: &str,
+    index_dir: &Path,
+    store: &DirBlobStore,
+) -> anyhow::Result<PathBuf> {
+    for (&hash, range) in &archive.hashes {
+        store.put_blob(hash, &archive.blob_buf[range.clone()])?;
+    }
+
+    fs::create_dir_all(index_dir)
+        .with_context(|| format!("failed to create index directory {index_dir:?}"))?;
+
+    let path = index_dir.join(format!("{name}.{INDEX_EXTENSION}"));
+    write_atomic(&path, &archive.out_buf, "index", false)?;
+
+    Ok(path)
+}
+
+// === LiveServer === //
+
+#[derive(Debug, Default)]
+struct ServerState {
+    /// The encoded [`LiveMessage::Index`] of the latest version of every module.
+    latest: FxHashMap<String, Arc<Vec<u8>>>,
+
+    subscribers: Vec<Sender<Arc<Vec<u8>>>>,
+}
+
+/// An error which [`LiveServer::serve`] recovers from, reported to its
+/// [error handler](LiveServer::set_error_handler).
+#[derive(Debug)]
+pub enum ServeError {
+    /// Scanning the index directory failed. It is scanned again after the poll interval.
+    Scan(anyhow::Error),
+
+    /// Serving a client failed, ending its session.
+    Session {
+        peer: Option<SocketAddr>,
+        error: anyhow::Error,
+    },
+}
+
+impl fmt::Display for ServeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Scan(error) => write!(f, "failed to scan index directory: {error:#}"),
+            Self::Session { peer, error } => {
+                write!(f, "live session with {peer:?} failed: {error:#}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ServeError {}
+
+#[derive(Clone)]
+struct ErrorHandler(Arc<dyn Fn(ServeError) + Send + Sync>);
+
+impl fmt::Debug for ErrorHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ErrorHandler").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LiveServer {
+    index_dir: PathBuf,
+    store: DirBlobStore,
+    poll_interval: Duration,
+    on_error: ErrorHandler,
+    state: Arc<Mutex<ServerState>>,
+}
+
+impl LiveServer {
+    /// Creates a server pushing the indices in `index_dir` and serving blobs from `store`. The
+    /// directory is polled every 250ms by default and errors the server recovers from are ignored.
+    pub fn new(index_dir: impl Into<PathBuf>, store: DirBlobStore) -> Self {
+        Self {
+            index_dir: index_dir.into(),
+            store,
+            poll_interval: Duration::from_millis(250),
+            on_error: ErrorHandler(Arc::new(|_| {})),
+            state: Arc::default(),
+        }
+    }
+
+    pub fn set_poll_interval(&mut self, poll_interval: Duration) {
+        self.poll_interval = poll_interval;
+    }
+
+    /// Sets the callback [`serve`](Self::serve) reports the errors it recovers from to. It is
+    /// called from the threads watching the directory and serving clients.
+    pub fn set_error_handler(&mut self, on_error: impl Fn(ServeError) + Send + Sync + 'static) {
+        self.on_error = ErrorHandler(Arc::new(on_error));
+    }
+
+    /// Scans the index directory once, pushing every index which changed since the last scan.
+    /// Returns the names of the modules which were pushed.
+    pub fn scan(&self) -> anyhow::Result<Vec<String>> {
+        let mut pushed = Vec::new();
+
+        let read_dir = match fs::read_dir(&self.index_dir) {
+            Ok(read_dir) => read_dir,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(pushed),
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("failed to list index directory {:?}", self.index_dir)
+                })
+            }
+        };
+
+        for dirent in read_dir {
+            let path = dirent?.path();
+            if path.extension().is_none_or(|ext| ext != INDEX_EXTENSION) {
+                continue;
+            }
+
+            let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+                continue;
+            };
+
+            let index = match fs::read(&path) {
+                Ok(index) => index,
+                // The index may have been replaced in the meantime. We'll catch the new one on the
+                // next scan.
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => {
+                    return Err(err).with_context(|| format!("failed to read index {path:?}"))
+                }
+            };
+
+            let message = LiveMessage::Index {
+                name: name.to_string(),
+                index,
+            }
+            .encode();
+
+            let mut state = self.state.lock().unwrap();
+            if state
+                .latest
+                .get(name)
+                .is_some_and(|latest| **latest == message)
+            {
+                continue;
+            }
+
+            let message = Arc::new(message);
+            state.latest.insert(name.to_string(), message.clone());
+            state
+                .subscribers
+                .retain(|subscriber| subscriber.send(message.clone()).is_ok());
+
+            pushed.push(name.to_string());
+        }
+
+        Ok(pushed)
+    }
+
+    /// Watches the index directory and serves clients connecting to `listener` until an error
+    /// occurs. Each client is served on its own thread. Failed scans and sessions are reported to
+    /// the [error handler](Self::set_error_handler) instead.
+    pub fn serve(&self, listener: TcpListener) -> anyhow::Result<()> {
+        // Scan once up-front so the first clients immediately receive every module.
+        self.scan()?;
+
+        let watcher = self.clone();
+        thread::spawn(move || loop {
+            thread::sleep(watcher.poll_interval);
+            if let Err(err) = watcher.scan() {
+                (watcher.on_error.0)(ServeError::Scan(err));
+            }
+        });
+
+        for stream in listener.incoming() {
+            let stream = stream.context("failed to accept connection")?;
+            let server = self.clone();
+
+            thread::spawn(move || {
+                let peer = stream.peer_addr().ok();
+                if let Err(error) = server.serve_client(stream) {
+                    (server.on_error.0)(ServeError::Session { peer, error });
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    fn subscribe(&self) -> Receiver<Arc<Vec<u8>>> {
+        let (sender, receiver) = mpsc::channel();
+        let mut state = self.state.lock().unwrap();
+
+        for message in state.latest.values() {
+            let _ = sender.send(message.clone());
+        }
+
+        state.subscribers.push(sender);
+        receiver
+    }
+
+    /// Serves a single client until it disconnects.
+    pub fn serve_client(&self, stream: TcpStream) -> anyhow::Result<()> {
+        let mut socket = tungstenite::accept(stream)
+            .map_err(|err| anyhow::anyhow!("WebSocket handshake failed: {err}"))?;
+
+        socket
+            .get_ref()
+            .set_read_timeout(Some(SESSION_POLL_INTERVAL))?;
+
+        let pushes = self.subscribe();
+
+        loop {
+            for message in pushes.try_iter() {
+                socket.send(Message::Binary((*message).clone()))?;
+            }
+
+            let message = match socket.read() {
+                Ok(Message::Binary(message)) => message,
+                Ok(Message::Close(_)) => break,
+                Ok(_) => continue,
+                Err(tungstenite::Error::Io(err))
+                    if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    continue
+                }
+                Err(
+                    tungstenite::Error::ConnectionClosed
+                    | tungstenite::Error::Protocol(ProtocolError::ResetWithoutClosingHandshake),
+                ) => break,
+                Err(err) => return Err(err.into()),
+            };
+
+            let LiveMessage::Want(hashes) = LiveMessage::decode(&message)? else {
+                anyhow::bail!("client sent an unexpected message");
+            };
+
+            let mut blobs = Vec::new();
+            for hash in hashes {
+                if let Some(data) = self.store.get_blob(hash)? {
+                    blobs.push((hash, data.into_owned()));
+                }
+            }
+
+            socket.send(Message::Binary(LiveMessage::Blobs(blobs).encode()))?;
+        }
+
+        Ok(())
+    }
+}
+
+// === LiveClient === //
+
+#[derive(Debug, Default)]
+struct BlobCache(FxHashMap<Hash, Vec<u8>>);
+
+impl BlobSource for BlobCache {
+    fn get_blob(&self, hash: Hash) -> anyhow::Result<Option<Cow<'_, [u8]>>> {
+        Ok(self.0.get(&hash).map(|data| Cow::Borrowed(&data[..])))
+    }
+}
+
+/// A freshly assembled version of a module.
+#[derive(Debug, Clone)]
+pub struct LiveModule {
+    pub name: String,
+    pub code: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct LiveClient {
+    socket: WebSocket<MaybeTlsStream<TcpStream>>,
+    blobs: BlobCache,
+
+    /// Indices pushed while we were waiting for blobs.
+    pending: VecDeque<(String, Vec<u8>)>,
+}
+
+impl LiveClient {
+    /// Connects to the server at `url`, e.g. `ws://localhost:8437`.
+    pub fn connect(url: &str) -> anyhow::Result<Self> {
+        let (socket, _) = tungstenite::connect(url)
+            .map_err(|err| anyhow::anyhow!("failed to connect to {url}: {err}"))?;
+
+        Ok(Self {
+            socket,
+            blobs: BlobCache::default(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Receives the next message, returning `None` once the server disconnects.
+    fn recv(&mut self) -> anyhow::Result<Option<LiveMessage>> {
+        loop {
+            match self.socket.read() {
+                Ok(Message::Binary(message)) => return LiveMessage::decode(&message).map(Some),
+                Ok(Message::Close(_)) | Err(tungstenite::Error::ConnectionClosed) => {
+                    return Ok(None)
+                }
+                Ok(_) => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Waits for the next version of any module and assembles it, returning `None` once the server
+    /// disconnects.
+    pub fn next_module(&mut self) -> anyhow::Result<Option<LiveModule>> {
+        let (name, index) = match self.pending.pop_front() {
+            Some(pending) => pending,
+            None => match self.recv()? {
+                Some(LiveMessage::Index { name, index }) => (name, index),
+                Some(_) => anyhow::bail!("server sent an unexpected message"),
+                None => return Ok(None),
+            },
+        };
+
+        let module = WasmallMod::parse(&mut ByteCursor(&index))
+            .with_context(|| format!("failed to parse index for {name:?}"))?;
+
+        // Request the blobs we haven't seen yet, in the order the index wants them fetched.
+        let wanted = module
+            .fetch_order()?
+            .into_iter()
+            .filter(|hash| !self.blobs.0.contains_key(hash))
+            .collect::<Vec<_>>();
+
+        let missing = wanted.iter().copied().collect::<FxHashSet<_>>();
+
+        if !missing.is_empty() {
+            self.socket
+                .send(Message::Binary(LiveMessage::Want(wanted).encode()))?;
+
+            let blobs = loop {
+                match self.recv()? {
+                    Some(LiveMessage::Blobs(blobs)) => break blobs,
+                    Some(LiveMessage::Index { name, index }) => {
+                        self.pending.push_back((name, index))
+                    }
+                    Some(LiveMessage::Want(_)) => {
+                        anyhow::bail!("server sent an unexpected message")
+                    }
+                    None => return Ok(None),
+                }
+            };
+
+            for (hash, data) in blobs {
+                let actual_hash = blake3::hash(&data);
+                anyhow::ensure!(
+                    actual_hash == hash && missing.contains(&hash),
+                    "server sent an unrequested or corrupted blob {actual_hash}"
+                );
+                self.blobs.0.insert(hash, data);
+            }
+        }
+
+        let code = module
+            .assemble_verified(&self.blobs)
+            .with_context(|| format!("failed to assemble {name:?}"))?;
+
+        Ok(Some(LiveModule { name, code }))
+    }
+
+    /// Hands every new version of a module to `on_reload` until the server disconnects.
+    pub fn run(
+        &mut self,
+        mut on_reload: impl FnMut(LiveModule) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        while let Some(module) = self.next_module()? {
+            on_reload(module)?;
+        }
+
+        Ok(())
+    }
+}
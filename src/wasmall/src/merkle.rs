@@ -0,0 +1,295 @@
+//! Merkle trees over the segments of a wasmall index.
+//!
+//! Each leaf is the hash of a single encoded index segment. Since blob segments record the hash of
+//! the blob they reference, a client holding a trusted root can verify a segment using a small
+//! [`MerkleProof`] and then verify the blob against that segment, all before the rest of the index
+//! has arrived.
+
+use anyhow::Context;
+use blake3::{Hash, Hasher};
+
+use crate::util::{BufWriter, ByteCursor, ByteParse, Leb128WriteExt};
+
+// === Hashing === //
+
+const LEAF_TAG: u8 = 0;
+const NODE_TAG: u8 = 1;
+
+/// Hashes an encoded segment into a leaf. Leaves and interior nodes are domain-separated so a node
+/// can never be passed off as a segment.
+pub fn leaf_hash(segment: &[u8]) -> Hash {
+    let mut hasher = Hasher::new();
+    hasher.update(&[LEAF_TAG]);
+    hasher.update(segment);
+    hasher.finalize()
+}
+
+pub fn node_hash(left: Hash, right: Hash) -> Hash {
+    let mut hasher = Hasher::new();
+    hasher.update(&[NODE_TAG]);
+    hasher.update(left.as_bytes());
+    hasher.update(right.as_bytes());
+    hasher.finalize()
+}
+
+/// The root of a tree without any leaves.
+pub fn empty_root() -> Hash {
+    blake3::hash(&[])
+}
+
+// === MerkleTree === //
+
+/// A binary Merkle tree. Nodes without a sibling are promoted to the next level unchanged.
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    /// Every level of the tree, starting with the leaves and ending with the single root.
+    levels: Vec<Vec<Hash>>,
+}
+
+impl MerkleTree {
+    pub fn from_leaves(leaves: Vec<Hash>) -> Self {
+        let mut levels = vec![leaves];
+
+        while levels.last().unwrap().len() > 1 {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match *pair {
+                    [left, right] => node_hash(left, right),
+                    [single] => single,
+                    _ => unreachable!(),
+                })
+                .collect();
+
+            levels.push(next);
+        }
+
+        Self { levels }
+    }
+
+    pub fn leaves(&self) -> &[Hash] {
+        &self.levels[0]
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaves().len()
+    }
+
+    pub fn root(&self) -> Hash {
+        self.levels
+            .last()
+            .unwrap()
+            .first()
+            .copied()
+            .unwrap_or_else(empty_root)
+    }
+
+    /// Produces a proof that the leaf at `index` is part of this tree.
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.leaf_count() {
+            return None;
+        }
+
+        let mut siblings = Vec::new();
+        let mut node = index;
+
+        for level in &self.levels[..self.levels.len() - 1] {
+            if let Some(&sibling) = level.get(node ^ 1) {
+                siblings.push(sibling);
+            }
+            node /= 2;
+        }
+
+        Some(MerkleProof {
+            index,
+            leaf_count: self.leaf_count(),
+            siblings,
+        })
+    }
+}
+
+// === MerkleProof === //
+
+/// The path of sibling hashes linking a single leaf to the root of its tree.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MerkleProof {
+    pub index: usize,
+    pub leaf_count: usize,
+    pub siblings: Vec<Hash>,
+}
+
+impl MerkleProof {
+    /// Computes the root implied by this proof for the given leaf, returning `None` if the proof is
+    /// malformed.
+    pub fn compute_root(&self, leaf: Hash) -> Option<Hash> {
+        if self.index >= self.leaf_count {
+            return None;
+        }
+
+        let mut siblings = self.siblings.iter().copied();
+        let mut node = self.index;
+        let mut width = self.leaf_count;
+        let mut hash = leaf;
+
+        while width > 1 {
+            if node ^ 1 < width {
+                let sibling = siblings.next()?;
+                hash = if node & 1 == 0 {
+                    node_hash(hash, sibling)
+                } else {
+                    node_hash(sibling, hash)
+                };
+            }
+
+            node /= 2;
+            width = width.div_ceil(2);
+        }
+
+        siblings.next().is_none().then_some(hash)
+    }
+
+    pub fn verify(&self, leaf: Hash, root: Hash) -> anyhow::Result<()> {
+        let actual_root = self.compute_root(leaf).context("malformed merkle proof")?;
+
+        anyhow::ensure!(
+            actual_root == root,
+            "merkle proof for leaf {} does not match the root; expected {root}, got {actual_root}",
+            self.index,
+        );
+
+        Ok(())
+    }
+
+    pub fn write(&self, out: &mut impl BufWriter) {
+        out.write_var_u32(u32::try_from(self.index).unwrap());
+        out.write_var_u32(u32::try_from(self.leaf_count).unwrap());
+        out.write_var_u32(u32::try_from(self.siblings.len()).unwrap());
+
+        for sibling in &self.siblings {
+            out.extend(sibling.as_bytes());
+        }
+    }
+}
+
+impl ByteParse<'_> for MerkleProof {
+    type Out = Self;
+
+    fn parse_naked(buf: &mut ByteCursor<'_>) -> anyhow::Result<Self::Out> {
+        let index = buf.read_var_u32().context("failed to read leaf index")? as usize;
+        let leaf_count = buf.read_var_u32().context("failed to read leaf count")? as usize;
+        let sibling_count = buf.read_var_u32().context("failed to read sibling count")?;
+
+        // A proof never needs more siblings than the tree has levels.
+        anyhow::ensure!(sibling_count <= usize::BITS, "merkle proof is too long");
+
+        let siblings = (0..sibling_count)
+            .map(|_| buf.consume_arr().map(Hash::from_bytes))
+            .collect::<anyhow::Result<_>>()
+            .context("failed to read sibling hashes")?;
+
+        Ok(Self {
+            index,
+            leaf_count,
+            siblings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(leaf_count: usize) -> MerkleTree {
+        MerkleTree::from_leaves(
+            (0..leaf_count)
+                .map(|i| leaf_hash(&i.to_le_bytes()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn proofs_verify_for_every_leaf() {
+        for leaf_count in 1..=17 {
+            let tree = tree(leaf_count);
+
+            for (i, &leaf) in tree.leaves().iter().enumerate() {
+                let proof = tree.proof(i).unwrap();
+                proof.verify(leaf, tree.root()).unwrap();
+
+                // Proofs survive being encoded.
+                let mut encoded = Vec::new();
+                proof.write(&mut encoded);
+                assert_eq!(
+                    MerkleProof::parse(&mut ByteCursor(&encoded)).unwrap(),
+                    proof
+                );
+            }
+
+            assert!(tree.proof(leaf_count).is_none());
+        }
+    }
+
+    #[test]
+    fn proofs_reject_flipped_siblings() {
+        for leaf_count in [2, 3, 5, 8, 13] {
+            let tree = tree(leaf_count);
+
+            for (i, &leaf) in tree.leaves().iter().enumerate() {
+                let proof = tree.proof(i).unwrap();
+
+                for j in 0..proof.siblings.len() {
+                    let mut flipped = proof.clone();
+                    let mut sibling = *flipped.siblings[j].as_bytes();
+                    sibling[0] ^= 1;
+                    flipped.siblings[j] = Hash::from_bytes(sibling);
+
+                    assert!(flipped.verify(leaf, tree.root()).is_err());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn proofs_reject_wrong_indices() {
+        for leaf_count in [2, 3, 5, 8, 13] {
+            let tree = tree(leaf_count);
+
+            for (i, &leaf) in tree.leaves().iter().enumerate() {
+                for index in (0..leaf_count + 2).filter(|&index| index != i) {
+                    let proof = MerkleProof {
+                        index,
+                        ..tree.proof(i).unwrap()
+                    };
+                    assert!(proof.verify(leaf, tree.root()).is_err());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn proofs_reject_wrong_leaves_and_shapes() {
+        let tree = tree(5);
+        let proof = tree.proof(4).unwrap();
+
+        assert!(proof.verify(tree.leaves()[3], tree.root()).is_err());
+        assert!(proof.verify(tree.leaves()[4], empty_root()).is_err());
+
+        let mut extended = proof.clone();
+        extended.siblings.push(tree.leaves()[0]);
+        assert!(extended.compute_root(tree.leaves()[4]).is_none());
+
+        let mut truncated = proof;
+        truncated.siblings.pop();
+        assert!(truncated.compute_root(tree.leaves()[4]).is_none());
+    }
+
+    #[test]
+    fn single_and_empty_trees() {
+        let tree = tree(1);
+        assert_eq!(tree.root(), tree.leaves()[0]);
+        assert!(tree.proof(0).unwrap().siblings.is_empty());
+
+        assert_eq!(MerkleTree::from_leaves(Vec::new()).root(), empty_root());
+    }
+}
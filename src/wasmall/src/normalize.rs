@@ -0,0 +1,485 @@
+//! Canonicalization of function bodies so equivalent bodies emitted by different compiler versions
+//! deduplicate.
+//!
+//! Toolchains disagree on encoding details which don't affect what a body does: how wide LEBs
+//! should be (relocatable objects pad them to five bytes), whether runs of locals with the same
+//! type are declared as one group or several, and which index each local is assigned. A normalized
+//! body encodes every immediate which isn't relocated with a minimal LEB, declares one group of
+//! locals per type, and numbers its locals by type and then by first use. Relocated immediates are
+//! left alone since they are rewritten at full width upon assembly anyways.
+//!
+//! Normalization also produces the [`BlobEdit`]s turning the normalized body back into the original
+//! one, which the index stores alongside the blob so that the module reassembles bit-exactly. Bodies
+//! using instructions whose immediates the normalizer doesn't know only have their header
+//! normalized.
+
+use std::ops::Range;
+
+use crate::{
+    coder::BlobEdit,
+    util::{ByteCursor, Leb128WriteExt},
+};
+
+/// The single-byte value types which may appear in a normalized local declaration, in the order
+/// normalized bodies declare them. Bodies declaring locals of any other type are left untouched.
+const SIMPLE_VAL_TYPES: [u8; 7] = [0x7F, 0x7E, 0x7D, 0x7C, 0x7B, 0x70, 0x6F];
+
+/// The most locals a body may declare, as limited by every major engine.
+const MAX_LOCALS: usize = 50_000;
+
+// === Headers === //
+
+/// A function header in its normalized form.
+#[derive(Debug, Clone)]
+pub struct NormalizedHeader {
+    /// The normalized header, including the function's size.
+    pub header: Vec<u8>,
+
+    /// The length of the header it replaces.
+    pub original_len: usize,
+}
+
+/// Normalizes the header of a function body `entry`, which includes its size field. Returns `None`
+/// if the header is already normal or can't be normalized.
+pub fn normalize_func_header(entry: &[u8]) -> Option<NormalizedHeader> {
+    let mut cursor = ByteCursor(entry);
+    let size = cursor.read_var_u32().ok()?;
+    if cursor.0.len() != size as usize {
+        return None;
+    }
+
+    // Parse and merge the local declarations.
+    let group_count = cursor.read_var_u32().ok()?;
+    let mut groups = Vec::<(u32, u8)>::new();
+
+    for _ in 0..group_count {
+        let count = cursor.read_var_u32().ok()?;
+        let ty = cursor.read_u8().ok()?;
+
+        if !SIMPLE_VAL_TYPES.contains(&ty) {
+            return None;
+        }
+
+        match groups.last_mut() {
+            _ if count == 0 => {}
+            Some((last_count, last_ty)) if *last_ty == ty => {
+                *last_count = last_count.checked_add(count)?;
+            }
+            _ => groups.push((count, ty)),
+        }
+    }
+
+    let original_len = entry.len() - cursor.0.len();
+    let code_len = cursor.0.len();
+
+    // Encode the normalized header.
+    let locals = encode_locals(&groups);
+    let mut header = Vec::new();
+    header.write_var_u32(u32::try_from(locals.len() + code_len).ok()?);
+    header.extend_from_slice(&locals);
+
+    (header != entry[..original_len]).then_some(NormalizedHeader {
+        header,
+        original_len,
+    })
+}
+
+fn encode_locals(groups: &[(u32, u8)]) -> Vec<u8> {
+    let mut locals = Vec::new();
+    locals.write_var_u32(groups.len() as u32);
+    for &(count, ty) in groups {
+        locals.write_var_u32(count);
+        locals.push(ty);
+    }
+    locals
+}
+
+// === Immediates === //
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Leb {
+    U32,
+    U64,
+    I32,
+    I64,
+    S33,
+
+    /// A `u32` local index.
+    Local,
+}
+
+/// Finds the LEB immediates of every instruction in a function's code.
+struct ImmediateScanner<'a> {
+    code: &'a [u8],
+    cursor: ByteCursor<'a>,
+    immediates: Vec<(Leb, Range<usize>)>,
+}
+
+impl<'a> ImmediateScanner<'a> {
+    /// Returns the kind and range of every LEB immediate in `code`, or `None` if it uses an
+    /// instruction whose immediates aren't known.
+    fn scan(code: &'a [u8]) -> Option<Vec<(Leb, Range<usize>)>> {
+        let mut scanner = Self {
+            code,
+            cursor: ByteCursor(code),
+            immediates: Vec::new(),
+        };
+
+        while !scanner.cursor.at_eof() {
+            scanner.instruction()?;
+        }
+
+        Some(scanner.immediates)
+    }
+
+    fn pos(&self) -> usize {
+        self.code.len() - self.cursor.0.len()
+    }
+
+    /// Reads an LEB immediate, returning its value. Signed values are sign-extended.
+    fn leb(&mut self, kind: Leb) -> Option<u64> {
+        let start = self.pos();
+        let value = match kind {
+            Leb::U32 | Leb::Local => self.cursor.read_var_u32().map(u64::from),
+            Leb::U64 => self.cursor.read_var_u64(),
+            Leb::I32 => self.cursor.read_var_i32().map(|v| i64::from(v) as u64),
+            Leb::I64 => self.cursor.read_var_i64().map(|v| v as u64),
+            Leb::S33 => self.cursor.read_var_s33().map(|v| v as u64),
+        }
+        .ok()?;
+
+        self.immediates.push((kind, start..self.pos()));
+        Some(value)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.cursor.consume(len).ok().map(drop)
+    }
+
+    fn value_type(&mut self) -> Option<()> {
+        // Reference types taking a heap type operand aren't supported.
+        match self.cursor.read_u8().ok()? {
+            0x63 | 0x64 => None,
+            _ => Some(()),
+        }
+    }
+
+    fn block_type(&mut self) -> Option<()> {
+        match self.cursor.peek(1).ok()?[0] {
+            0x63 | 0x64 => None,
+            // Empty block types and value types are single-byte negative `s33`s.
+            _ => self.leb(Leb::S33).map(drop),
+        }
+    }
+
+    fn memarg(&mut self) -> Option<()> {
+        // Bit 6 of the alignment indicates an explicit memory index.
+        if self.leb(Leb::U32)? & 0x40 != 0 {
+            self.leb(Leb::U32)?;
+        }
+
+        self.leb(Leb::U64).map(drop)
+    }
+
+    fn instruction(&mut self) -> Option<()> {
+        match self.cursor.read_u8().ok()? {
+            0x00 | 0x01 | 0x05 | 0x0A | 0x0B | 0x0F | 0x19 | 0x1A | 0x1B => {}
+            0x02..=0x04 | 0x06 => self.block_type()?,
+            0x07..=0x09 | 0x0C | 0x0D | 0x10 | 0x12 | 0x14 | 0x15 | 0x18 => {
+                self.leb(Leb::U32)?;
+            }
+            0x0E => {
+                for _ in 0..=self.leb(Leb::U32)? {
+                    self.leb(Leb::U32)?;
+                }
+            }
+            0x11 | 0x13 => {
+                self.leb(Leb::U32)?;
+                self.leb(Leb::U32)?;
+            }
+            0x1C => {
+                for _ in 0..self.leb(Leb::U32)? {
+                    self.value_type()?;
+                }
+            }
+            0x1F => {
+                self.block_type()?;
+
+                for _ in 0..self.leb(Leb::U32)? {
+                    match self.cursor.read_u8().ok()? {
+                        0x00 | 0x01 => {
+                            self.leb(Leb::U32)?;
+                        }
+                        0x02 | 0x03 => {}
+                        _ => return None,
+                    }
+                    self.leb(Leb::U32)?;
+                }
+            }
+            0x20..=0x22 => {
+                self.leb(Leb::Local)?;
+            }
+            0x23..=0x26 | 0x3F | 0x40 | 0xD2 | 0xD5 | 0xD6 => {
+                self.leb(Leb::U32)?;
+            }
+            0x28..=0x3E => self.memarg()?,
+            0x41 => {
+                self.leb(Leb::I32)?;
+            }
+            0x42 => {
+                self.leb(Leb::I64)?;
+            }
+            0x43 => self.skip(4)?,
+            0x44 => self.skip(8)?,
+            0x45..=0xC4 | 0xD1 | 0xD3 | 0xD4 => {}
+            0xD0 => {
+                self.leb(Leb::S33)?;
+            }
+            0xFC => match self.leb(Leb::U32)? {
+                0..=7 => {}
+                9 | 11 | 13 | 15..=17 => {
+                    self.leb(Leb::U32)?;
+                }
+                8 | 10 | 12 | 14 => {
+                    self.leb(Leb::U32)?;
+                    self.leb(Leb::U32)?;
+                }
+                _ => return None,
+            },
+            0xFD => match self.leb(Leb::U32)? {
+                0x00..=0x0B | 0x5C | 0x5D => self.memarg()?,
+                0x0C | 0x0D => self.skip(16)?,
+                0x15..=0x22 => self.skip(1)?,
+                0x54..=0x5B => {
+                    self.memarg()?;
+                    self.skip(1)?;
+                }
+                0x0E..=0x14 | 0x23..=0x53 | 0x5E..=0x113 => {}
+                _ => return None,
+            },
+            0xFE => match self.leb(Leb::U32)? {
+                0x00..=0x02 | 0x10..=0x4E => self.memarg()?,
+                0x03 => self.skip(1)?,
+                _ => return None,
+            },
+            _ => return None,
+        }
+
+        Some(())
+    }
+}
+
+// === Bodies === //
+
+/// A function body in its normalized form.
+#[derive(Debug, Clone)]
+pub struct NormalizedBody {
+    /// The normalized body, including the function's size.
+    pub data: Vec<u8>,
+
+    /// The edits turning `data` back into the original body, sorted by offset.
+    pub edits: Vec<BlobEdit>,
+
+    /// The offset in `data` of every relocation passed to [`normalize_func_body`], in the same
+    /// order.
+    pub reloc_offsets: Vec<u32>,
+}
+
+/// Normalizes a function body `entry`, which includes its size field. `params` is the number of
+/// parameters of the function's type, whose local indices are left alone, and `reloc_offsets` are
+/// the offsets in `entry` of the immediates which are relocated.
+///
+/// Returns `None` if the body is already normal or can't be normalized, which is the case if a
+/// relocation lies in its header or doesn't start at an immediate. Bodies whose immediates can't be
+/// determined only have their header normalized.
+pub fn normalize_func_body(
+    entry: &[u8],
+    params: u32,
+    reloc_offsets: &[u32],
+) -> Option<NormalizedBody> {
+    normalize_func_code(entry, params, reloc_offsets).or_else(|| {
+        // Fall back to normalizing just the header, which is the only part relocations may not
+        // refer to.
+        let normalized = normalize_func_header(entry)?;
+        let shift = |offset: &u32| {
+            (*offset as usize >= normalized.original_len).then(|| {
+                (*offset as usize - normalized.original_len + normalized.header.len()) as u32
+            })
+        };
+        let reloc_offsets = reloc_offsets
+            .iter()
+            .map(shift)
+            .collect::<Option<Vec<_>>>()?;
+
+        let mut data = normalized.header.clone();
+        data.extend_from_slice(&entry[normalized.original_len..]);
+
+        Some(NormalizedBody {
+            data,
+            edits: vec![BlobEdit {
+                offset: 0,
+                len: normalized.header.len(),
+                original: entry[..normalized.original_len].to_vec(),
+            }],
+            reloc_offsets,
+        })
+    })
+}
+
+fn normalize_func_code(entry: &[u8], params: u32, reloc_offsets: &[u32]) -> Option<NormalizedBody> {
+    let mut cursor = ByteCursor(entry);
+    let size = cursor.read_var_u32().ok()?;
+    if cursor.0.len() != size as usize {
+        return None;
+    }
+
+    // Parse the local declarations, noting the type of each declared local.
+    let mut locals = Vec::<u8>::new();
+
+    for _ in 0..cursor.read_var_u32().ok()? {
+        let count = cursor.read_var_u32().ok()? as usize;
+        let ty = cursor.read_u8().ok()?;
+
+        if !SIMPLE_VAL_TYPES.contains(&ty) || locals.len() + count > MAX_LOCALS {
+            return None;
+        }
+
+        locals.resize(locals.len() + count, ty);
+    }
+
+    let header_len = entry.len() - cursor.0.len();
+    let code = cursor.0;
+    let immediates = ImmediateScanner::scan(code)?;
+
+    // Every relocation must target an immediate, which is then copied verbatim.
+    let mut relocated = Vec::with_capacity(reloc_offsets.len());
+    for &offset in reloc_offsets {
+        let offset = (offset as usize).checked_sub(header_len)?;
+        relocated.push(
+            immediates
+                .binary_search_by_key(&offset, |(_, range)| range.start)
+                .ok()?,
+        );
+    }
+
+    // Number the locals by type and then by first use. Unused locals of the same type are
+    // interchangeable so their relative order doesn't matter.
+    let local_index = |range: &Range<usize>| {
+        let index = ByteCursor(&code[range.clone()]).read_var_u32().ok()?;
+        Some(index.checked_sub(params).map(|index| index as usize))
+    };
+
+    let mut first_use = vec![usize::MAX; locals.len()];
+    for (i, (kind, range)) in immediates.iter().enumerate() {
+        if *kind != Leb::Local || relocated.contains(&i) {
+            continue;
+        }
+
+        if let Some(index) = local_index(range)? {
+            let first = first_use.get_mut(index)?;
+            *first = (*first).min(range.start);
+        }
+    }
+
+    let type_rank = |ty: u8| SIMPLE_VAL_TYPES.iter().position(|&other| other == ty);
+    let mut order = (0..locals.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&local| (type_rank(locals[local]), first_use[local]));
+
+    let mut renumbered = vec![0; locals.len()];
+    for (new_index, &local) in order.iter().enumerate() {
+        renumbered[local] = params.checked_add(new_index as u32)?;
+    }
+
+    // Encode the normalized code, recording an edit for every immediate that changed.
+    let mut out = Vec::with_capacity(code.len());
+    let mut edits = Vec::new();
+    let mut new_offsets = vec![0; immediates.len()];
+    let mut copied = 0;
+
+    for (i, (kind, range)) in immediates.iter().enumerate() {
+        out.extend_from_slice(&code[copied..range.start]);
+        copied = range.end;
+
+        let start = out.len();
+        new_offsets[i] = start;
+
+        let original = &code[range.clone()];
+        if relocated.contains(&i) {
+            out.extend_from_slice(original);
+            continue;
+        }
+
+        let mut imm = ByteCursor(original);
+        match kind {
+            Leb::U32 => out.write_var_u32(imm.read_var_u32().ok()?),
+            Leb::U64 => out.write_var_u64(imm.read_var_u64().ok()?),
+            Leb::I32 => out.write_var_i32(imm.read_var_i32().ok()?),
+            Leb::I64 => out.write_var_i64(imm.read_var_i64().ok()?),
+            Leb::S33 => out.write_var_s33(imm.read_var_s33().ok()?),
+            Leb::Local => match local_index(range)? {
+                Some(index) => out.write_var_u32(renumbered[index]),
+                None => out.write_var_u32(imm.read_var_u32().ok()?),
+            },
+        }
+
+        if out[start..] != *original {
+            edits.push(BlobEdit {
+                offset: start,
+                len: out.len() - start,
+                original: original.to_vec(),
+            });
+        }
+    }
+    out.extend_from_slice(&code[copied..]);
+
+    // Encode the normalized header, with one group per type.
+    let mut groups = Vec::<(u32, u8)>::new();
+    for &local in &order {
+        match groups.last_mut() {
+            Some((count, ty)) if *ty == locals[local] => *count += 1,
+            _ => groups.push((1, locals[local])),
+        }
+    }
+
+    let locals = encode_locals(&groups);
+    let mut data = Vec::with_capacity(out.len() + locals.len() + 5);
+    data.write_var_u32(u32::try_from(locals.len() + out.len()).ok()?);
+    data.extend_from_slice(&locals);
+    let new_header_len = data.len();
+    data.extend_from_slice(&out);
+
+    // Shift the code's edits past the header and merge adjacent ones.
+    let mut merged = Vec::<BlobEdit>::with_capacity(edits.len() + 1);
+    if data[..new_header_len] != entry[..header_len] {
+        merged.push(BlobEdit {
+            offset: 0,
+            len: new_header_len,
+            original: entry[..header_len].to_vec(),
+        });
+    }
+
+    for mut edit in edits {
+        edit.offset += new_header_len;
+
+        match merged.last_mut() {
+            Some(last) if last.offset + last.len == edit.offset => {
+                last.len += edit.len;
+                last.original.extend_from_slice(&edit.original);
+            }
+            _ => merged.push(edit),
+        }
+    }
+
+    if merged.is_empty() {
+        return None;
+    }
+
+    Some(NormalizedBody {
+        data,
+        edits: merged,
+        reloc_offsets: relocated
+            .into_iter()
+            .map(|i| (new_header_len + new_offsets[i]) as u32)
+            .collect(),
+    })
+}
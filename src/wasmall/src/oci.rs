@@ -0,0 +1,629 @@
+//! Packaging of indices and packs as OCI artifacts so they can be distributed through container
+//! registries.
+//!
+//! An artifact's manifest uses the empty config and has one layer for the index followed by a data
+//! layer and an index layer for every pack. Layers are named by their
+//! `org.opencontainers.image.title` annotation and the manifest records the module's blake3 hash
+//! in its [`MODULE_DIGEST_ANNOTATION`].
+
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use blake3::Hash;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    coder::WasmallMod,
+    pack::{pack_index_path, MappedPackStore},
+    util::{ByteCursor, ByteParse},
+};
+
+// === Format === //
+
+pub const ARTIFACT_TYPE: &str = "application/vnd.wasmall.module.v1";
+
+pub const INDEX_MEDIA_TYPE: &str = "application/vnd.wasmall.index.v1";
+
+pub const PACK_MEDIA_TYPE: &str = "application/vnd.wasmall.pack.v1";
+
+pub const PACK_INDEX_MEDIA_TYPE: &str = "application/vnd.wasmall.pack.index.v1";
+
+/// The manifest annotation holding the `blake3:`-prefixed hash of the assembled module.
+pub const MODULE_DIGEST_ANNOTATION: &str = "dev.wasmall.module.digest";
+
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+
+const EMPTY_MEDIA_TYPE: &str = "application/vnd.oci.empty.v1+json";
+
+const EMPTY_CONFIG: &[u8] = b"{}";
+
+/// The largest manifest or blob the puller is willing to download.
+const MAX_DOWNLOAD_LEN: u64 = 1 << 32;
+
+pub fn sha256_digest(data: &[u8]) -> String {
+    let mut digest = String::from("sha256:");
+    for byte in Sha256::digest(data) {
+        digest.push_str(&format!("{byte:02x}"));
+    }
+    digest
+}
+
+fn check_title(title: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !title.is_empty() && title != "." && title != ".." && !title.contains(['/', '\\', '\0']),
+        "layer title {title:?} is not a plain file name"
+    );
+    Ok(())
+}
+
+// === OciArtifact === //
+
+#[derive(Debug, Clone)]
+pub struct OciBlob {
+    pub media_type: String,
+    pub digest: String,
+    pub title: Option<String>,
+    pub data: Vec<u8>,
+}
+
+impl OciBlob {
+    pub fn new(media_type: &str, title: Option<String>, data: Vec<u8>) -> Self {
+        Self {
+            media_type: media_type.to_string(),
+            digest: sha256_digest(&data),
+            title,
+            data,
+        }
+    }
+
+    fn descriptor(&self) -> Value {
+        let mut descriptor = json!({
+            "mediaType": self.media_type,
+            "digest": self.digest,
+            "size": self.data.len(),
+        });
+
+        if let Some(title) = &self.title {
+            descriptor["annotations"] = json!({ TITLE_ANNOTATION: title });
+        }
+
+        descriptor
+    }
+}
+
+/// A fully materialized artifact ready to be pushed or written out.
+#[derive(Debug, Clone)]
+pub struct OciArtifact {
+    pub manifest: Vec<u8>,
+    pub config: OciBlob,
+    pub layers: Vec<OciBlob>,
+}
+
+impl OciArtifact {
+    /// Packages an index along with the packs at the specified paths. Each pack's index file is
+    /// expected to sit next to it.
+    pub fn package(index: &[u8], pack_paths: &[PathBuf]) -> anyhow::Result<Self> {
+        let module = WasmallMod::parse(&mut ByteCursor(index))?;
+
+        let mut layers = vec![OciBlob::new(
+            INDEX_MEDIA_TYPE,
+            Some("module.wsml".to_string()),
+            index.to_vec(),
+        )];
+
+        for pack_path in pack_paths {
+            for (media_type, path) in [
+                (PACK_MEDIA_TYPE, pack_path.clone()),
+                (PACK_INDEX_MEDIA_TYPE, pack_index_path(pack_path)),
+            ] {
+                let title = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .with_context(|| format!("{path:?} does not have a valid file name"))?
+                    .to_string();
+
+                let data = fs::read(&path).with_context(|| format!("failed to read {path:?}"))?;
+                layers.push(OciBlob::new(media_type, Some(title), data));
+            }
+        }
+
+        let config = OciBlob::new(EMPTY_MEDIA_TYPE, None, EMPTY_CONFIG.to_vec());
+
+        let manifest = json!({
+            "schemaVersion": 2,
+            "mediaType": MANIFEST_MEDIA_TYPE,
+            "artifactType": ARTIFACT_TYPE,
+            "config": config.descriptor(),
+            "layers": layers.iter().map(OciBlob::descriptor).collect::<Vec<_>>(),
+            "annotations": {
+                MODULE_DIGEST_ANNOTATION: format!("blake3:{}", module.module_hash()),
+            },
+        });
+
+        Ok(Self {
+            manifest: serde_json::to_vec(&manifest)?,
+            config,
+            layers,
+        })
+    }
+
+    pub fn manifest_digest(&self) -> String {
+        sha256_digest(&self.manifest)
+    }
+
+    /// Writes the artifact out as an OCI image layout directory, tagging it with `tag`.
+    pub fn write_layout(&self, dir: &Path, tag: &str) -> anyhow::Result<()> {
+        let blob_dir = dir.join("blobs").join("sha256");
+        fs::create_dir_all(&blob_dir)
+            .with_context(|| format!("failed to create blob directory {blob_dir:?}"))?;
+
+        let manifest_digest = self.manifest_digest();
+
+        for (digest, data) in [
+            (&manifest_digest, &self.manifest),
+            (&self.config.digest, &self.config.data),
+        ]
+        .into_iter()
+        .chain(self.layers.iter().map(|layer| (&layer.digest, &layer.data)))
+        {
+            let path = blob_dir.join(digest.trim_start_matches("sha256:"));
+            fs::write(&path, data).with_context(|| format!("failed to write {path:?}"))?;
+        }
+
+        let index = json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.index.v1+json",
+            "manifests": [{
+                "mediaType": MANIFEST_MEDIA_TYPE,
+                "artifactType": ARTIFACT_TYPE,
+                "digest": manifest_digest,
+                "size": self.manifest.len(),
+                "annotations": { "org.opencontainers.image.ref.name": tag },
+            }],
+        });
+
+        fs::write(dir.join("index.json"), serde_json::to_vec(&index)?)?;
+        fs::write(dir.join("oci-layout"), br#"{"imageLayoutVersion":"1.0.0"}"#)?;
+
+        Ok(())
+    }
+}
+
+// === OciReference === //
+
+/// A reference to an artifact in a registry of the form `registry/repository[:tag|@digest]`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OciReference {
+    pub registry: String,
+    pub repository: String,
+    pub reference: String,
+}
+
+impl FromStr for OciReference {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (registry, rest) = s
+            .split_once('/')
+            .with_context(|| format!("reference {s:?} does not specify a registry"))?;
+
+        let (repository, reference) = if let Some((repository, digest)) = rest.split_once('@') {
+            (repository, digest)
+        } else {
+            match rest.rsplit_once(':') {
+                Some((repository, tag)) if !tag.contains('/') => (repository, tag),
+                _ => (rest, "latest"),
+            }
+        };
+
+        anyhow::ensure!(
+            !registry.is_empty() && !repository.is_empty() && !reference.is_empty(),
+            "malformed reference {s:?}"
+        );
+
+        Ok(Self {
+            registry: registry.to_string(),
+            repository: repository.to_string(),
+            reference: reference.to_string(),
+        })
+    }
+}
+
+// === OciClient === //
+
+/// The result of pulling an artifact into a local directory.
+#[derive(Debug)]
+pub struct PulledArtifact {
+    pub manifest_digest: String,
+    pub module_hash: Hash,
+    pub index: Vec<u8>,
+    pub store: MappedPackStore,
+}
+
+/// A minimal client for the OCI distribution API supporting anonymous, basic, and bearer token
+/// authentication.
+#[derive(Debug)]
+pub struct OciClient {
+    agent: ureq::Agent,
+    plain_http: bool,
+    credentials: Option<(String, String)>,
+    token: Mutex<Option<String>>,
+}
+
+impl Default for OciClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OciClient {
+    pub fn new() -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new().build(),
+            plain_http: false,
+            credentials: None,
+            token: Mutex::new(None),
+        }
+    }
+
+    pub fn with_credentials(
+        mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Talks to the registry over plain HTTP. This is only meant for local test registries.
+    pub fn with_plain_http(mut self, plain_http: bool) -> Self {
+        self.plain_http = plain_http;
+        self
+    }
+
+    fn base_url(&self, reference: &OciReference) -> String {
+        let scheme = if self.plain_http { "http" } else { "https" };
+        format!(
+            "{scheme}://{}/v2/{}",
+            reference.registry, reference.repository
+        )
+    }
+
+    fn send(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+    ) -> anyhow::Result<ureq::Response> {
+        // `ureq::Error` is big but it only lives long enough to be inspected.
+        #[allow(clippy::result_large_err)]
+        let attempt = |auth: Option<String>| {
+            let mut request = self.agent.request(method, url);
+            for &(name, value) in headers {
+                request = request.set(name, value);
+            }
+            if let Some(auth) = auth {
+                request = request.set("Authorization", &auth);
+            }
+
+            match body {
+                Some(body) => request.send_bytes(body),
+                None => request.call(),
+            }
+        };
+
+        let auth = self
+            .token
+            .lock()
+            .unwrap()
+            .clone()
+            .map(|token| format!("Bearer {token}"));
+
+        match attempt(auth) {
+            Ok(response) => Ok(response),
+            Err(ureq::Error::Status(401, response)) => {
+                let challenge = response
+                    .header("WWW-Authenticate")
+                    .context("registry rejected the request without an authentication challenge")?
+                    .to_string();
+
+                let auth = self.authenticate(&challenge)?;
+                attempt(Some(auth)).with_context(|| format!("{method} {url} failed"))
+            }
+            Err(err) => Err(err).with_context(|| format!("{method} {url} failed")),
+        }
+    }
+
+    fn authenticate(&self, challenge: &str) -> anyhow::Result<String> {
+        let basic = self.credentials.as_ref().map(|(username, password)| {
+            format!(
+                "Basic {}",
+                base64(format!("{username}:{password}").as_bytes())
+            )
+        });
+
+        let Some(params) = challenge.strip_prefix("Bearer ") else {
+            return basic.context("registry requires credentials");
+        };
+
+        let params = parse_challenge_params(params);
+        let realm = params
+            .iter()
+            .find(|(key, _)| key == "realm")
+            .map(|(_, value)| value.as_str())
+            .context("bearer challenge does not specify a realm")?;
+
+        let mut url = realm.to_string();
+        let mut sep = if url.contains('?') { '&' } else { '?' };
+        for (key, value) in &params {
+            if key == "service" || key == "scope" {
+                url.push(sep);
+                url.push_str(&format!("{key}={}", percent_encode(value)));
+                sep = '&';
+            }
+        }
+
+        let mut request = self.agent.get(&url);
+        if let Some(basic) = &basic {
+            request = request.set("Authorization", basic);
+        }
+
+        let response: Value = serde_json::from_reader(
+            request
+                .call()
+                .context("failed to fetch registry token")?
+                .into_reader(),
+        )
+        .context("failed to parse registry token response")?;
+
+        let token = response["token"]
+            .as_str()
+            .or_else(|| response["access_token"].as_str())
+            .context("registry token response did not contain a token")?
+            .to_string();
+
+        *self.token.lock().unwrap() = Some(token.clone());
+        Ok(format!("Bearer {token}"))
+    }
+
+    fn read_body(response: ureq::Response) -> anyhow::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        response
+            .into_reader()
+            .take(MAX_DOWNLOAD_LEN + 1)
+            .read_to_end(&mut data)?;
+
+        anyhow::ensure!(
+            (data.len() as u64) <= MAX_DOWNLOAD_LEN,
+            "registry response is too big"
+        );
+        Ok(data)
+    }
+
+    fn push_blob(&self, reference: &OciReference, blob: &OciBlob) -> anyhow::Result<()> {
+        let base = self.base_url(reference);
+
+        let exists = match self.send("HEAD", &format!("{base}/blobs/{}", blob.digest), &[], None) {
+            Ok(_) => true,
+            Err(err)
+                if err
+                    .downcast_ref::<ureq::Error>()
+                    .is_some_and(|err| matches!(err, ureq::Error::Status(404, _))) =>
+            {
+                false
+            }
+            Err(err) => return Err(err),
+        };
+
+        if exists {
+            return Ok(());
+        }
+
+        let response = self.send("POST", &format!("{base}/blobs/uploads/"), &[], Some(&[]))?;
+        let location = response
+            .header("Location")
+            .context("registry did not return an upload location")?;
+
+        let mut upload_url = if location.starts_with('/') {
+            let scheme = if self.plain_http { "http" } else { "https" };
+            format!("{scheme}://{}{location}", reference.registry)
+        } else {
+            location.to_string()
+        };
+
+        upload_url.push(if upload_url.contains('?') { '&' } else { '?' });
+        upload_url.push_str(&format!("digest={}", percent_encode(&blob.digest)));
+
+        self.send(
+            "PUT",
+            &upload_url,
+            &[("Content-Type", "application/octet-stream")],
+            Some(&blob.data),
+        )?;
+
+        Ok(())
+    }
+
+    /// Pushes the artifact to the registry, returning the digest of its manifest.
+    pub fn push(&self, reference: &OciReference, artifact: &OciArtifact) -> anyhow::Result<String> {
+        self.push_blob(reference, &artifact.config)?;
+        for layer in &artifact.layers {
+            self.push_blob(reference, layer)?;
+        }
+
+        self.send(
+            "PUT",
+            &format!(
+                "{}/manifests/{}",
+                self.base_url(reference),
+                reference.reference
+            ),
+            &[("Content-Type", MANIFEST_MEDIA_TYPE)],
+            Some(&artifact.manifest),
+        )?;
+
+        Ok(artifact.manifest_digest())
+    }
+
+    /// Pulls the artifact into `dest_dir` and opens the packs it contains as a blob store. Every
+    /// download is checked against its digest and the index is checked against the module digest
+    /// recorded in the manifest.
+    pub fn pull(
+        &self,
+        reference: &OciReference,
+        dest_dir: &Path,
+    ) -> anyhow::Result<PulledArtifact> {
+        let base = self.base_url(reference);
+
+        let manifest = Self::read_body(self.send(
+            "GET",
+            &format!("{base}/manifests/{}", reference.reference),
+            &[("Accept", MANIFEST_MEDIA_TYPE)],
+            None,
+        )?)?;
+
+        let manifest_digest = sha256_digest(&manifest);
+        if reference.reference.starts_with("sha256:") {
+            anyhow::ensure!(
+                manifest_digest == reference.reference,
+                "manifest digest mismatch; expected {}, got {manifest_digest}",
+                reference.reference,
+            );
+        }
+
+        let manifest: Value =
+            serde_json::from_slice(&manifest).context("failed to parse manifest")?;
+        anyhow::ensure!(
+            manifest["artifactType"] == ARTIFACT_TYPE,
+            "{reference:?} is not a wasmall artifact"
+        );
+
+        let module_digest = manifest["annotations"][MODULE_DIGEST_ANNOTATION]
+            .as_str()
+            .and_then(|digest| digest.strip_prefix("blake3:"))
+            .context("manifest does not record a module digest")?;
+
+        let module_hash = Hash::from_hex(module_digest).context("malformed module digest")?;
+
+        fs::create_dir_all(dest_dir)
+            .with_context(|| format!("failed to create destination directory {dest_dir:?}"))?;
+
+        let mut index = None;
+
+        for layer in manifest["layers"]
+            .as_array()
+            .context("manifest has no layers")?
+        {
+            let media_type = layer["mediaType"]
+                .as_str()
+                .context("layer has no media type")?;
+            let digest = layer["digest"].as_str().context("layer has no digest")?;
+
+            if ![INDEX_MEDIA_TYPE, PACK_MEDIA_TYPE, PACK_INDEX_MEDIA_TYPE].contains(&media_type) {
+                continue;
+            }
+
+            let data =
+                Self::read_body(self.send("GET", &format!("{base}/blobs/{digest}"), &[], None)?)?;
+            let actual_digest = sha256_digest(&data);
+            anyhow::ensure!(
+                actual_digest == digest,
+                "layer digest mismatch; expected {digest}, got {actual_digest}"
+            );
+
+            if media_type == INDEX_MEDIA_TYPE {
+                index = Some(data);
+                continue;
+            }
+
+            let title = layer["annotations"][TITLE_ANNOTATION]
+                .as_str()
+                .context("pack layer has no title")?;
+            check_title(title)?;
+
+            let path = dest_dir.join(title);
+            fs::write(&path, &data).with_context(|| format!("failed to write {path:?}"))?;
+        }
+
+        let index = index.context("artifact does not contain an index")?;
+        let module = WasmallMod::parse(&mut ByteCursor(&index))?;
+        anyhow::ensure!(
+            module.module_hash() == module_hash,
+            "index describes module {} but the manifest claims {module_hash}",
+            module.module_hash(),
+        );
+
+        Ok(PulledArtifact {
+            manifest_digest,
+            module_hash,
+            store: MappedPackStore::open(dest_dir)?,
+            index,
+        })
+    }
+}
+
+// === Encoding helpers === //
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, &byte)| acc | (byte as u32) << (16 - 8 * i));
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[((n >> (18 - 6 * i)) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn percent_encode(value: &str) -> String {
+    let mut out = String::new();
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    out
+}
+
+fn parse_challenge_params(params: &str) -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    let mut rest = params.trim();
+
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_string();
+
+        let (value, after) = if let Some(quoted) = after.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            (&quoted[..end], quoted.get(end + 1..).unwrap_or(""))
+        } else {
+            after.split_once(',').unwrap_or((after, ""))
+        };
+
+        out.push((key, value.to_string()));
+        rest = after.trim_start_matches(',').trim();
+    }
+
+    out
+}
@@ -0,0 +1,336 @@
+//! A packfile format which stores many blobs in a single memory-mapped data file.
+//!
+//! A pack is made up of two files:
+//!
+//! - The data file (`<name>.pack`), consisting of the [`PACK_MAGIC`] followed by the concatenated
+//!   blob payloads.
+//! - The index file (`<name>.idx`), consisting of the [`IDX_MAGIC`], a little-endian `u32` entry
+//!   count, and that many fixed-size entries sorted by hash. Each entry is a 32 byte hash followed
+//!   by the little-endian `u64` offset and length of the blob within the data file.
+
+use std::{
+    borrow::Cow,
+    fs,
+    io::{BufWriter, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU8, Ordering::Relaxed},
+};
+
+use anyhow::Context;
+use blake3::Hash;
+use memmap2::Mmap;
+use rustc_hash::FxHashSet;
+
+use crate::{
+    filter::BlobFilter,
+    store::BlobSource,
+    util::{unique_temp_path, ByteCursor, SliceExt},
+};
+
+// === Format === //
+
+pub const PACK_MAGIC: [u8; 8] = *b"WSMLPACK";
+
+pub const IDX_MAGIC: [u8; 8] = *b"WSMLPIDX";
+
+const IDX_HEADER_LEN: usize = IDX_MAGIC.len() + 4;
+
+const IDX_ENTRY_LEN: usize = blake3::OUT_LEN + 8 + 8;
+
+pub fn pack_index_path(pack_path: &Path) -> PathBuf {
+    pack_path.with_extension("idx")
+}
+
+// === Writer === //
+
+/// Writes a new pack to `pack_path` (and its index next to it) containing the specified blobs.
+/// Duplicate blobs are only written once.
+///
+/// Both files are written under temporary names and renamed into place once complete so concurrent
+/// readers never map a partially-written pack.
+pub fn write_pack<'a>(
+    pack_path: &Path,
+    blobs: impl IntoIterator<Item = (Hash, &'a [u8])>,
+) -> anyhow::Result<()> {
+    let mut entries = Vec::<(Hash, u64, u64)>::new();
+    let mut seen = FxHashSet::default();
+
+    // Write the data file
+    let temp_pack_path = unique_temp_path(pack_path);
+    {
+        let mut data_file = BufWriter::new(
+            fs::File::create(&temp_pack_path)
+                .with_context(|| format!("failed to create pack file {temp_pack_path:?}"))?,
+        );
+
+        data_file.write_all(&PACK_MAGIC)?;
+        let mut offset = PACK_MAGIC.len() as u64;
+
+        for (hash, data) in blobs {
+            if !seen.insert(hash) {
+                continue;
+            }
+
+            entries.push((hash, offset, data.len() as u64));
+            data_file.write_all(data)?;
+            offset += data.len() as u64;
+        }
+
+        data_file.flush()?;
+    }
+
+    // Write the index file
+    let idx_path = pack_index_path(pack_path);
+    let temp_idx_path = unique_temp_path(&idx_path);
+    {
+        entries.sort_by(|(a, ..), (b, ..)| a.as_bytes().cmp(b.as_bytes()));
+
+        let mut idx_file = BufWriter::new(
+            fs::File::create(&temp_idx_path)
+                .with_context(|| format!("failed to create pack index {temp_idx_path:?}"))?,
+        );
+
+        idx_file.write_all(&IDX_MAGIC)?;
+        idx_file.write_all(
+            &u32::try_from(entries.len())
+                .context("too many blobs in pack")?
+                .to_le_bytes(),
+        )?;
+
+        for (hash, offset, len) in entries {
+            idx_file.write_all(hash.as_bytes())?;
+            idx_file.write_all(&offset.to_le_bytes())?;
+            idx_file.write_all(&len.to_le_bytes())?;
+        }
+
+        idx_file.flush()?;
+    }
+
+    // Move the index into place first since readers discover packs by their data file.
+    fs::rename(&temp_idx_path, &idx_path)
+        .with_context(|| format!("failed to move pack index into place at {idx_path:?}"))?;
+
+    fs::rename(&temp_pack_path, pack_path)
+        .with_context(|| format!("failed to move pack into place at {pack_path:?}"))?;
+
+    Ok(())
+}
+
+// === MappedPack === //
+
+const VERIFY_PENDING: u8 = 0;
+const VERIFY_OK: u8 = 1;
+const VERIFY_CORRUPT: u8 = 2;
+
+/// A single memory-mapped pack. Blobs are verified against their hash the first time they are
+/// accessed rather than when the pack is opened.
+#[derive(Debug)]
+pub struct MappedPack {
+    path: PathBuf,
+    data: Mmap,
+    index: Mmap,
+    verified: Box<[AtomicU8]>,
+}
+
+impl MappedPack {
+    pub fn open(pack_path: &Path) -> anyhow::Result<Self> {
+        let map = |path: &Path| -> anyhow::Result<Mmap> {
+            let file = fs::File::open(path).with_context(|| format!("failed to open {path:?}"))?;
+
+            // Safety: packs are never modified in place once written. Writers always produce new
+            // files and rename them into place.
+            unsafe { Mmap::map(&file) }.with_context(|| format!("failed to map {path:?}"))
+        };
+
+        let data = map(pack_path)?;
+        let index = map(&pack_index_path(pack_path))?;
+
+        // Validate the headers
+        anyhow::ensure!(
+            data.limit_len(PACK_MAGIC.len()) == PACK_MAGIC,
+            "{pack_path:?} is not a pack file"
+        );
+
+        let mut cursor = ByteCursor(&index);
+        anyhow::ensure!(
+            cursor.consume(IDX_MAGIC.len()).ok() == Some(&IDX_MAGIC[..]),
+            "{pack_path:?} has an invalid pack index"
+        );
+
+        let count = cursor
+            .read_u32()
+            .context("failed to read pack entry count")? as usize;
+        anyhow::ensure!(
+            cursor.0.len() == count * IDX_ENTRY_LEN,
+            "pack index for {pack_path:?} has length {} but claims to have {count} entries",
+            index.len(),
+        );
+
+        let me = Self {
+            path: pack_path.to_path_buf(),
+            data,
+            index,
+            verified: (0..count).map(|_| AtomicU8::new(VERIFY_PENDING)).collect(),
+        };
+
+        // Validate the entries so lookups never have to.
+        let mut prev_hash = None::<Hash>;
+        for i in 0..count {
+            let (hash, range) = me.entry_raw(i)?;
+            anyhow::ensure!(
+                prev_hash.is_none_or(|prev| prev.as_bytes() < hash.as_bytes()),
+                "pack index for {pack_path:?} is not sorted at entry {i}"
+            );
+            anyhow::ensure!(
+                range.start >= PACK_MAGIC.len() && range.end <= me.data.len(),
+                "pack entry {i} of {pack_path:?} is out of bounds"
+            );
+            prev_hash = Some(hash);
+        }
+
+        Ok(me)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.verified.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.verified.is_empty()
+    }
+
+    fn entry_raw(&self, i: usize) -> anyhow::Result<(Hash, Range<usize>)> {
+        let mut cursor = ByteCursor(&self.index[IDX_HEADER_LEN + i * IDX_ENTRY_LEN..]);
+        let hash = Hash::from_bytes(cursor.consume_arr()?);
+        let offset = usize::try_from(cursor.read_u64()?)?;
+        let len = usize::try_from(cursor.read_u64()?)?;
+        let end = offset.checked_add(len).context("pack entry is too big")?;
+
+        Ok((hash, offset..end))
+    }
+
+    fn entry(&self, i: usize) -> (Hash, Range<usize>) {
+        // Entries are validated when the pack is opened.
+        self.entry_raw(i).unwrap()
+    }
+
+    pub fn hashes(&self) -> impl Iterator<Item = Hash> + '_ {
+        (0..self.len()).map(|i| self.entry(i).0)
+    }
+
+    pub fn find(&self, hash: Hash) -> Option<usize> {
+        let mut lo = 0;
+        let mut hi = self.len();
+
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.entry(mid).0.as_bytes().cmp(hash.as_bytes()) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(mid),
+            }
+        }
+
+        None
+    }
+
+    pub fn contains(&self, hash: Hash) -> bool {
+        self.find(hash).is_some()
+    }
+
+    pub fn get(&self, hash: Hash) -> anyhow::Result<Option<&[u8]>> {
+        let Some(i) = self.find(hash) else {
+            return Ok(None);
+        };
+
+        let data = &self.data[self.entry(i).1];
+
+        match self.verified[i].load(Relaxed) {
+            VERIFY_OK => {}
+            VERIFY_CORRUPT => anyhow::bail!("blob {hash} in {:?} is corrupted", self.path),
+            _ => {
+                // Racing verifiers will just end up doing redundant work.
+                let actual_hash = blake3::hash(data);
+                if actual_hash != hash {
+                    self.verified[i].store(VERIFY_CORRUPT, Relaxed);
+                    anyhow::bail!(
+                        "blob {hash} in {:?} is corrupted; got hash {actual_hash}",
+                        self.path
+                    );
+                }
+                self.verified[i].store(VERIFY_OK, Relaxed);
+            }
+        }
+
+        Ok(Some(data))
+    }
+}
+
+// === MappedPackStore === //
+
+/// A read-only [`BlobSource`] serving blobs directly out of every pack in a directory.
+#[derive(Debug, Default)]
+pub struct MappedPackStore {
+    packs: Vec<MappedPack>,
+}
+
+impl MappedPackStore {
+    pub fn open(dir: &Path) -> anyhow::Result<Self> {
+        let mut packs = Vec::new();
+
+        for entry in
+            fs::read_dir(dir).with_context(|| format!("failed to read pack directory {dir:?}"))?
+        {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "pack") {
+                packs.push(MappedPack::open(&path)?);
+            }
+        }
+
+        // Make lookup order deterministic.
+        packs.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(Self { packs })
+    }
+
+    pub fn packs(&self) -> &[MappedPack] {
+        &self.packs
+    }
+
+    /// Builds a [`BlobFilter`] over every blob in the store.
+    pub fn build_filter(&self, false_positive_rate: f64) -> BlobFilter {
+        let mut filter = BlobFilter::with_capacity(
+            self.packs.iter().map(MappedPack::len).sum(),
+            false_positive_rate,
+        );
+
+        for pack in &self.packs {
+            for hash in pack.hashes() {
+                filter.insert(hash);
+            }
+        }
+
+        filter
+    }
+
+    pub fn get(&self, hash: Hash) -> anyhow::Result<Option<&[u8]>> {
+        for pack in &self.packs {
+            if let Some(data) = pack.get(hash)? {
+                return Ok(Some(data));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl BlobSource for MappedPackStore {
+    fn get_blob(&self, hash: Hash) -> anyhow::Result<Option<Cow<'_, [u8]>>> {
+        self.get(hash).map(|data| data.map(Cow::Borrowed))
+    }
+}
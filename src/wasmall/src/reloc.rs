@@ -1,8 +1,17 @@
 //! Utilities for parsing, writing, interpreting, and applying relocations.
 
+use std::ops::Range;
+
 use anyhow::Context;
+use wasmparser::{
+    Comdat, ComdatSymbolKind, DefinedDataSymbol, InitFunc, Linking, LinkingSectionReader, Parser,
+    Payload, Segment, SymbolFlags, SymbolInfo,
+};
 
-use crate::util::{BufWriter, ByteCursor, ByteParse, ByteParseList, ByteSliceExt, Leb128WriteExt};
+use crate::{
+    builder::SectionBuilder,
+    util::{BufWriter, ByteCursor, ByteParse, ByteParseList, ByteSliceExt, Leb128WriteExt},
+};
 
 // === Parsing === //
 
@@ -39,8 +48,36 @@ impl<'a> ByteParse<'a> for RelocSection<'a> {
 }
 
 impl<'a> RelocSection<'a> {
-    pub fn entries(&self) -> impl Iterator<Item = anyhow::Result<RelocEntry>> + 'a {
-        ByteParseList::<RelocEntry>::new(ByteCursor(self.entries)).take(self.entry_count as usize)
+    pub fn entries(&self) -> ByteParseList<'a, RelocEntry> {
+        ByteParseList::with_count(ByteCursor(self.entries), self.entry_count as usize)
+    }
+
+    /// Yields the entries whose offsets lie within `range`, re-based to be relative to its start.
+    /// Entries are yielded in the order they appear in the section, which needn't be sorted.
+    pub fn entries_in_range(
+        &self,
+        range: Range<u32>,
+    ) -> impl Iterator<Item = anyhow::Result<RelocEntry>> + 'a {
+        self.entries().filter_map(move |entry| match entry {
+            Ok(entry) => range.contains(&entry.offset).then(|| {
+                Ok(RelocEntry {
+                    offset: entry.offset - range.start,
+                    ..entry
+                })
+            }),
+            Err(err) => Some(Err(err)),
+        })
+    }
+
+    /// Builds a relocation section named `name`, conventionally `reloc.` followed by the name of
+    /// the target section, applying `entries` to the section at index `target_section`.
+    pub fn build(name: &str, target_section: u32, entries: &[RelocEntry]) -> SectionBuilder {
+        let mut writer = RelocSectionWriter::new(target_section);
+        for entry in entries {
+            writer.push(entry);
+        }
+
+        writer.finish(name)
     }
 }
 
@@ -49,7 +86,9 @@ pub struct RelocEntry {
     pub ty: RelocEntryType,
     pub offset: u32,
     pub index: u32,
-    pub addend: Option<i32>,
+    /// The addend of relocations of a type which [has one](RelocEntryType::has_addend). It is
+    /// only 64 bits wide for [64-bit](RelocEntryType::is_64_bit) relocations.
+    pub addend: Option<i64>,
 }
 
 impl ByteParse<'_> for RelocEntry {
@@ -63,10 +102,10 @@ impl ByteParse<'_> for RelocEntry {
         let offset = buf.read_var_u32().context("failed to read offset")?;
         let index = buf.read_var_u32().context("failed to read index")?;
 
-        let addend = if ty.has_addend() {
-            Some(buf.read_var_i32()?)
-        } else {
-            None
+        let addend = match (ty.has_addend(), ty.is_64_bit()) {
+            (true, true) => Some(buf.read_var_i64()?),
+            (true, false) => Some(buf.read_var_i32()?.into()),
+            (false, _) => None,
         };
 
         Ok(Self {
@@ -76,8 +115,51 @@ impl ByteParse<'_> for RelocEntry {
             addend,
         })
     }
+
+    /// Entries of a known type can be skipped even if their fields are out of range since the type
+    /// determines which fields follow.
+    fn skip_malformed(buf: &mut ByteCursor<'_>) -> bool {
+        buf.attempt(|c| {
+            let ty = RelocEntryType::parse(c.read_u8()?)?;
+
+            let fields = if ty.has_addend() { 3 } else { 2 };
+            for _ in 0..fields {
+                c.skip_var()?;
+            }
+
+            Ok(())
+        })
+        .is_some()
+    }
+}
+
+impl RelocEntry {
+    /// Encodes the entry as it appears in relocation sections and blobs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the addend of a 32-bit relocation doesn't fit in 32 bits.
+    pub fn write(&self, out: &mut (impl ?Sized + BufWriter)) {
+        out.push(self.ty as u8);
+        out.write_var_u32(self.offset);
+        out.write_var_u32(self.index);
+
+        match self.addend {
+            Some(addend) if self.ty.is_64_bit() => out.write_var_i64(addend),
+            Some(addend) => out.write_var_i32(
+                i32::try_from(addend).expect("addend of a 32-bit relocation is out of range"),
+            ),
+            None => {}
+        }
+    }
 }
 
+/// The kinds of relocations found in LLVM objects.
+///
+/// Indices store the value of every relocation as a 32-bit integer, including those of the 64-bit
+/// kinds, which are only emitted for `memory64` objects. Splitting fails for 64-bit relocations
+/// whose value doesn't fit in 32 bits, so such objects may only refer to the first 4 GiB of their
+/// memories and tables through relocations.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum RelocEntryType {
     FunctionIndexLeb = 0,
@@ -90,8 +172,30 @@ pub enum RelocEntryType {
     GlobalIndexLeb = 7,
     FunctionOffsetI32 = 8,
     SectionOffsetI32 = 9,
-    EventIndexLeb = 10,
+    /// Formerly `R_WASM_EVENT_INDEX_LEB`. Emitted for the tag operands of `throw` and `catch`.
+    TagIndexLeb = 10,
+    /// An address relative to `__memory_base`, emitted by position-independent code.
+    MemoryAddrRelSleb = 11,
+    /// A table index relative to `__table_base`, emitted by position-independent code.
+    TableIndexRelSleb = 12,
     GlobalIndexI32 = 13,
+    MemoryAddrLeb64 = 14,
+    MemoryAddrSleb64 = 15,
+    MemoryAddrI64 = 16,
+    MemoryAddrRelSleb64 = 17,
+    TableIndexSleb64 = 18,
+    TableIndexI64 = 19,
+    /// Emitted for the table operands of `call_indirect` and the table instructions now that
+    /// modules may have several tables.
+    TableNumberLeb = 20,
+    /// An address relative to `__tls_base`.
+    MemoryAddrTlsSleb = 21,
+    FunctionOffsetI64 = 22,
+    /// An address relative to the location being relocated.
+    MemoryAddrLocrelI32 = 23,
+    TableIndexRelSleb64 = 24,
+    MemoryAddrTlsSleb64 = 25,
+    FunctionIndexI32 = 26,
 }
 
 impl RelocEntryType {
@@ -109,8 +213,23 @@ impl RelocEntryType {
             7 => GlobalIndexLeb,
             8 => FunctionOffsetI32,
             9 => SectionOffsetI32,
-            10 => EventIndexLeb,
+            10 => TagIndexLeb,
+            11 => MemoryAddrRelSleb,
+            12 => TableIndexRelSleb,
             13 => GlobalIndexI32,
+            14 => MemoryAddrLeb64,
+            15 => MemoryAddrSleb64,
+            16 => MemoryAddrI64,
+            17 => MemoryAddrRelSleb64,
+            18 => TableIndexSleb64,
+            19 => TableIndexI64,
+            20 => TableNumberLeb,
+            21 => MemoryAddrTlsSleb,
+            22 => FunctionOffsetI64,
+            23 => MemoryAddrLocrelI32,
+            24 => TableIndexRelSleb64,
+            25 => MemoryAddrTlsSleb64,
+            26 => FunctionIndexI32,
             _ => anyhow::bail!("unknown relocation type {v}"),
         })
     }
@@ -120,10 +239,28 @@ impl RelocEntryType {
 
         matches!(
             self,
-            MemoryAddrLeb | MemoryAddrSleb | MemoryAddrI32 | FunctionOffsetI32 | SectionOffsetI32
+            MemoryAddrLeb
+                | MemoryAddrSleb
+                | MemoryAddrI32
+                | MemoryAddrRelSleb
+                | MemoryAddrLeb64
+                | MemoryAddrSleb64
+                | MemoryAddrI64
+                | MemoryAddrRelSleb64
+                | MemoryAddrTlsSleb
+                | MemoryAddrLocrelI32
+                | MemoryAddrTlsSleb64
+                | FunctionOffsetI32
+                | FunctionOffsetI64
+                | SectionOffsetI32
         )
     }
 
+    /// Whether the relocated value, and therefore the addend, is 64 bits wide.
+    pub fn is_64_bit(self) -> bool {
+        matches!(self.rewrite_kind().width(), 8 | 10)
+    }
+
     pub fn rewrite_kind(self) -> ScalarRewriteKind {
         use {RelocEntryType::*, ScalarRewriteKind::*};
 
@@ -138,14 +275,663 @@ impl RelocEntryType {
             GlobalIndexLeb => VarU32,
             FunctionOffsetI32 => U32,
             SectionOffsetI32 => U32,
-            EventIndexLeb => VarU32,
+            TagIndexLeb => VarU32,
+            MemoryAddrRelSleb => VarI32,
+            TableIndexRelSleb => VarI32,
             GlobalIndexI32 => U32,
+            MemoryAddrLeb64 => VarU64,
+            MemoryAddrSleb64 => VarI64,
+            MemoryAddrI64 => U64,
+            MemoryAddrRelSleb64 => VarI64,
+            TableIndexSleb64 => VarI64,
+            TableIndexI64 => U64,
+            TableNumberLeb => VarU32,
+            MemoryAddrTlsSleb => VarI32,
+            FunctionOffsetI64 => U64,
+            MemoryAddrLocrelI32 => I32,
+            TableIndexRelSleb64 => VarI64,
+            MemoryAddrTlsSleb64 => VarI64,
+            FunctionIndexI32 => U32,
+        }
+    }
+}
+
+/// The subsections of a `linking` section describing an object's symbols and data segments.
+/// COMDATs and unknown subsections are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct LinkingSection<'a> {
+    pub symbols: Vec<SymbolInfo<'a>>,
+    pub segments: Vec<Segment<'a>>,
+    pub init_funcs: Vec<InitFunc>,
+}
+
+impl<'a> LinkingSection<'a> {
+    /// Parses the contents of a `linking` section, which begin at `offset` in the module.
+    pub fn parse(data: &'a [u8], offset: usize) -> anyhow::Result<Self> {
+        let reader = LinkingSectionReader::new(data, offset)?;
+        let mut section = Self::default();
+
+        for subsection in reader {
+            match subsection.context("failed to parse linking subsection")? {
+                Linking::SymbolTable(symbols) => {
+                    for symbol in symbols {
+                        section
+                            .symbols
+                            .push(symbol.context("failed to parse symbol")?);
+                    }
+                }
+                Linking::SegmentInfo(segments) => {
+                    for segment in segments {
+                        section
+                            .segments
+                            .push(segment.context("failed to parse segment info")?);
+                    }
+                }
+                Linking::InitFuncs(funcs) => {
+                    for func in funcs {
+                        section
+                            .init_funcs
+                            .push(func.context("failed to parse init function")?);
+                    }
+                }
+                _ => {}
+            }
         }
+
+        Ok(section)
+    }
+
+    /// Yields the index and name of every function the object defines a symbol for, in symbol
+    /// table order. Functions with several symbols are yielded once for each of them.
+    pub fn defined_funcs(&self) -> impl Iterator<Item = (u32, &'a str)> + '_ {
+        self.symbols.iter().filter_map(|symbol| match *symbol {
+            SymbolInfo::Func {
+                flags,
+                index,
+                name: Some(name),
+            } if !flags.contains(SymbolFlags::UNDEFINED) => Some((index, name)),
+            _ => None,
+        })
+    }
+
+    /// Yields the location and name of every data symbol the object defines, in symbol table
+    /// order.
+    pub fn defined_data(&self) -> impl Iterator<Item = (DefinedDataSymbol, &'a str)> + '_ {
+        self.symbols.iter().filter_map(|symbol| match *symbol {
+            SymbolInfo::Data {
+                symbol: Some(symbol),
+                name,
+                ..
+            } => Some((symbol, name)),
+            _ => None,
+        })
     }
 }
 
+// === Writing === //
+
+/// Builds the contents of a relocation section one entry at a time.
+#[derive(Debug, Clone)]
+pub struct RelocSectionWriter {
+    target_section: u32,
+    entry_count: u32,
+    entries: Vec<u8>,
+}
+
+impl RelocSectionWriter {
+    pub fn new(target_section: u32) -> Self {
+        Self {
+            target_section,
+            entry_count: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Re-encodes every entry of a parsed section.
+    pub fn from_section(section: &RelocSection<'_>) -> anyhow::Result<Self> {
+        let mut writer = Self::new(section.target_section);
+        for (i, entry) in section.entries().enumerate() {
+            writer.push(&entry.with_context(|| format!("failed to parse relocation entry {i}"))?);
+        }
+
+        Ok(writer)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the section has more than `u32::MAX` entries.
+    pub fn push(&mut self, entry: &RelocEntry) {
+        self.entry_count = self
+            .entry_count
+            .checked_add(1)
+            .expect("too many relocations");
+
+        entry.write(&mut self.entries);
+    }
+
+    pub fn entry_count(&self) -> u32 {
+        self.entry_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entry_count == 0
+    }
+
+    /// Views the entries written so far as a parsed section.
+    pub fn as_section(&self) -> RelocSection<'_> {
+        RelocSection {
+            target_section: self.target_section,
+            entry_count: self.entry_count,
+            entries: &self.entries,
+        }
+    }
+
+    /// Builds a relocation section named `name`, conventionally `reloc.` followed by the name of
+    /// the target section.
+    pub fn finish(self, name: &str) -> SectionBuilder {
+        let mut section = SectionBuilder::custom(name);
+        let data = section.data();
+        data.write_var_u32(self.target_section);
+        data.write_var_u32(self.entry_count);
+        data.extend_from_slice(&self.entries);
+        section
+    }
+}
+
+/// The version of the linking metadata format emitted by [`LinkingSectionWriter`], which is the
+/// only version [`LinkingSectionReader`] accepts.
+pub const LINKING_VERSION: u32 = 2;
+
+const WASM_SEGMENT_INFO: u8 = 5;
+const WASM_INIT_FUNCS: u8 = 6;
+const WASM_COMDAT_INFO: u8 = 7;
+const WASM_SYMBOL_TABLE: u8 = 8;
+
+/// Builds the contents of a `linking` section one subsection at a time. Subsections are written in
+/// the order they are added. Their sizes are encoded minimally whereas LLVM pads them to five
+/// bytes, so re-encoding LLVM's output is stable but not byte-for-byte identical.
+#[derive(Debug, Clone, Default)]
+pub struct LinkingSectionWriter {
+    subsections: Vec<u8>,
+}
+
+impl LinkingSectionWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Re-encodes every subsection of a parsed section, in order. Unknown subsections are copied
+    /// verbatim.
+    pub fn from_reader(reader: LinkingSectionReader<'_>) -> anyhow::Result<Self> {
+        let mut writer = Self::new();
+
+        for subsection in reader {
+            match subsection.context("failed to parse linking subsection")? {
+                Linking::SegmentInfo(segments) => {
+                    writer.segment_info(segments.into_iter().collect::<Result<Vec<_>, _>>()?);
+                }
+                Linking::InitFuncs(funcs) => {
+                    writer.init_funcs(funcs.into_iter().collect::<Result<Vec<_>, _>>()?);
+                }
+                Linking::ComdatInfo(comdats) => {
+                    writer.comdat_info(comdats.into_iter().collect::<Result<Vec<_>, _>>()?)?;
+                }
+                Linking::SymbolTable(symbols) => {
+                    writer.symbol_table(symbols.into_iter().collect::<Result<Vec<_>, _>>()?)?;
+                }
+                Linking::Unknown { ty, data, .. } => writer.raw_subsection(ty, data),
+            }
+        }
+
+        Ok(writer)
+    }
+
+    pub fn segment_info<'a>(&mut self, segments: impl IntoIterator<Item = Segment<'a>>) {
+        self.list_subsection(WASM_SEGMENT_INFO, segments, |out, segment| {
+            write_name(out, segment.name);
+            out.write_var_u32(segment.alignment);
+            out.write_var_u32(segment.flags.bits());
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    pub fn init_funcs(&mut self, funcs: impl IntoIterator<Item = InitFunc>) {
+        self.list_subsection(WASM_INIT_FUNCS, funcs, |out, func| {
+            out.write_var_u32(func.priority);
+            out.write_var_u32(func.symbol_index);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    pub fn comdat_info<'a>(
+        &mut self,
+        comdats: impl IntoIterator<Item = Comdat<'a>>,
+    ) -> anyhow::Result<()> {
+        self.list_subsection(WASM_COMDAT_INFO, comdats, |out, comdat| {
+            let symbols = comdat
+                .symbols
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| {
+                    format!("failed to parse the symbols of COMDAT `{}`", comdat.name)
+                })?;
+
+            write_name(out, comdat.name);
+            out.write_var_u32(comdat.flags);
+            write_count(out, symbols.len());
+
+            for symbol in symbols {
+                out.push(match symbol.kind {
+                    ComdatSymbolKind::Data => 0,
+                    ComdatSymbolKind::Func => 1,
+                    ComdatSymbolKind::Global => 2,
+                    ComdatSymbolKind::Event => 3,
+                    ComdatSymbolKind::Table => 4,
+                    ComdatSymbolKind::Section => 5,
+                });
+                out.write_var_u32(symbol.index);
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Writes a symbol table. Symbols must carry exactly the optional fields their flags call
+    /// for, as they do when parsed.
+    pub fn symbol_table<'a>(
+        &mut self,
+        symbols: impl IntoIterator<Item = SymbolInfo<'a>>,
+    ) -> anyhow::Result<()> {
+        let mut index = 0;
+
+        self.list_subsection(WASM_SYMBOL_TABLE, symbols, |out, symbol| {
+            write_symbol(out, &symbol)
+                .with_context(|| format!("failed to write symbol {index}"))?;
+            index += 1;
+            Ok(())
+        })
+    }
+
+    pub fn raw_subsection(&mut self, id: u8, data: &[u8]) {
+        self.subsections.push(id);
+        write_count(&mut self.subsections, data.len());
+        self.subsections.extend_from_slice(data);
+    }
+
+    fn list_subsection<T>(
+        &mut self,
+        id: u8,
+        items: impl IntoIterator<Item = T>,
+        mut write: impl FnMut(&mut Vec<u8>, T) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut count = 0;
+        let mut data = Vec::new();
+
+        for item in items {
+            write(&mut data, item)?;
+            count += 1;
+        }
+
+        let mut payload = Vec::with_capacity(data.len() + 5);
+        write_count(&mut payload, count);
+        payload.extend_from_slice(&data);
+
+        self.raw_subsection(id, &payload);
+        Ok(())
+    }
+
+    pub fn finish(self) -> SectionBuilder {
+        let mut section = SectionBuilder::custom("linking");
+        let data = section.data();
+        data.write_var_u32(LINKING_VERSION);
+        data.extend_from_slice(&self.subsections);
+        section
+    }
+}
+
+fn write_symbol(out: &mut Vec<u8>, symbol: &SymbolInfo<'_>) -> anyhow::Result<()> {
+    let (kind, flags) = match *symbol {
+        SymbolInfo::Func { flags, .. } => (0, flags),
+        SymbolInfo::Data { flags, .. } => (1, flags),
+        SymbolInfo::Global { flags, .. } => (2, flags),
+        SymbolInfo::Section { flags, .. } => (3, flags),
+        SymbolInfo::Event { flags, .. } => (4, flags),
+        SymbolInfo::Table { flags, .. } => (5, flags),
+    };
+
+    let defined = !flags.contains(SymbolFlags::UNDEFINED);
+
+    out.push(kind);
+    out.write_var_u32(flags.bits());
+
+    match *symbol {
+        SymbolInfo::Func { index, name, .. }
+        | SymbolInfo::Global { index, name, .. }
+        | SymbolInfo::Event { index, name, .. }
+        | SymbolInfo::Table { index, name, .. } => {
+            out.write_var_u32(index);
+
+            match (defined || flags.contains(SymbolFlags::EXPLICIT_NAME), name) {
+                (true, Some(name)) => write_name(out, name),
+                (false, None) => {}
+                (true, None) => anyhow::bail!("symbol for index {index} is missing its name"),
+                (false, Some(name)) => {
+                    anyhow::bail!(
+                        "symbol `{name}` is named but is neither defined nor explicitly named"
+                    )
+                }
+            }
+        }
+        SymbolInfo::Data { name, symbol, .. } => {
+            write_name(out, name);
+
+            match (defined, symbol) {
+                (true, Some(symbol)) => {
+                    out.write_var_u32(symbol.index);
+                    out.write_var_u32(symbol.offset);
+                    out.write_var_u32(symbol.size);
+                }
+                (false, None) => {}
+                (true, None) => anyhow::bail!("defined data symbol `{name}` has no definition"),
+                (false, Some(_)) => {
+                    anyhow::bail!("undefined data symbol `{name}` has a definition")
+                }
+            }
+        }
+        SymbolInfo::Section { section, .. } => out.write_var_u32(section),
+    }
+
+    Ok(())
+}
+
+fn write_count(out: &mut Vec<u8>, count: usize) {
+    out.write_var_u32(u32::try_from(count).expect("linking subsection is too big"));
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_count(out, name.len());
+    out.extend_from_slice(name.as_bytes());
+}
+
+/// Checks that the `linking` and `reloc.*` sections of a module re-encode stably, meaning that
+/// writing out what was parsed and parsing that back yields the same bytes when written out again.
+/// Returns the number of sections checked, which is zero for modules which aren't relocatable.
+pub fn check_metadata_round_trip(module: &[u8]) -> anyhow::Result<usize> {
+    fn encode(name: &str, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut section = if name == "linking" {
+            LinkingSectionWriter::from_reader(LinkingSectionReader::new(data, 0)?)?.finish()
+        } else {
+            let section = RelocSection::parse(&mut ByteCursor(data))?;
+            RelocSectionWriter::from_section(&section)?.finish(name)
+        };
+
+        Ok(std::mem::take(section.data()))
+    }
+
+    let mut checked = 0;
+
+    for payload in Parser::new(0).parse_all(module) {
+        let Payload::CustomSection(reader) = payload? else {
+            continue;
+        };
+
+        let name = reader.name();
+        if name != "linking" && !name.starts_with("reloc.") {
+            continue;
+        }
+
+        let first = encode(name, reader.data())
+            .with_context(|| format!("failed to re-encode the {name:?} section"))?;
+
+        let second = encode(name, &first)
+            .with_context(|| format!("failed to parse the re-encoded {name:?} section"))?;
+
+        anyhow::ensure!(
+            first == second,
+            "the {name:?} section changed when re-encoded a second time"
+        );
+
+        checked += 1;
+    }
+
+    Ok(checked)
+}
+
+// === Indexing === //
+
+/// The relocations targeting a single section, sorted by offset so that the relocations affecting
+/// any given range of the section can be found with a binary search.
+#[derive(Debug, Clone, Default)]
+pub struct RelocIndex {
+    entries: Vec<RelocEntry>,
+}
+
+impl RelocIndex {
+    pub fn new(mut entries: Vec<RelocEntry>) -> Self {
+        entries.sort_by_key(|reloc| reloc.offset);
+        Self { entries }
+    }
+
+    pub fn entries(&self) -> &[RelocEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the relocations whose offsets lie within `range`, in order.
+    pub fn range(&self, range: Range<u32>) -> &[RelocEntry] {
+        let start = self
+            .entries
+            .partition_point(|reloc| reloc.offset < range.start);
+
+        let end = start + self.entries[start..].partition_point(|reloc| reloc.offset < range.end);
+
+        &self.entries[start..end]
+    }
+
+    /// Returns the relocations whose offsets lie within `range`, in order, re-based to be relative
+    /// to its start.
+    pub fn entries_in_range(
+        &self,
+        range: Range<u32>,
+    ) -> impl ExactSizeIterator<Item = RelocEntry> + Clone + '_ {
+        self.range(range.clone())
+            .iter()
+            .map(move |entry| RelocEntry {
+                offset: entry.offset - range.start,
+                ..*entry
+            })
+    }
+}
+
+impl FromIterator<RelocEntry> for RelocIndex {
+    fn from_iter<T: IntoIterator<Item = RelocEntry>>(iter: T) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+/// An owned list of relocations sorted by offset, usually scoped to a single function body or
+/// blob by [`RelocIndex::entries_in_range`].
+#[derive(Debug, Clone, Default)]
+pub struct RelocSet {
+    entries: Vec<RelocEntry>,
+}
+
+impl RelocSet {
+    pub fn new(mut entries: Vec<RelocEntry>) -> Self {
+        entries.sort_by_key(|reloc| reloc.offset);
+        Self { entries }
+    }
+
+    pub fn entries(&self) -> &[RelocEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The offset and rewrite kind of each relocation, as taken by [`validate_relocations`] and
+    /// [`estimate_rewritten_len`].
+    pub fn rewrite_kinds(
+        &self,
+    ) -> impl ExactSizeIterator<Item = (usize, ScalarRewriteKind)> + Clone + '_ {
+        self.entries
+            .iter()
+            .map(|reloc| (reloc.offset as usize, reloc.ty.rewrite_kind()))
+    }
+}
+
+impl FromIterator<RelocEntry> for RelocSet {
+    fn from_iter<T: IntoIterator<Item = RelocEntry>>(iter: T) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+// === Validation === //
+
+/// A problem with a list of relocation entries which would otherwise corrupt the rewritten output.
+/// Entries are identified by their position in the list and offsets are relative to the start of
+/// the buffer being rewritten.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum RewriteError {
+    /// The entry starts before the entry preceding it.
+    Unsorted {
+        entry: usize,
+        offset: usize,
+        prev_offset: usize,
+    },
+
+    /// The entry starts before the entry preceding it ends.
+    Overlapping {
+        entry: usize,
+        offset: usize,
+        prev_end: usize,
+    },
+
+    /// The entry extends past the end of the buffer.
+    OutOfBounds {
+        entry: usize,
+        offset: usize,
+        end: usize,
+        buf_len: usize,
+    },
+
+    /// The entry refers to a relocation value which doesn't exist.
+    BadIndex {
+        entry: usize,
+        index: u32,
+        value_count: usize,
+    },
+}
+
+impl std::fmt::Display for RewriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unsorted {
+                entry,
+                offset,
+                prev_offset,
+            } => write!(
+                f,
+                "relocation entry {entry} at offset {offset:#x} comes after an entry at offset \
+                 {prev_offset:#x}"
+            ),
+            Self::Overlapping {
+                entry,
+                offset,
+                prev_end,
+            } => write!(
+                f,
+                "relocation entry {entry} at offset {offset:#x} overlaps the previous entry, which \
+                 ends at offset {prev_end:#x}"
+            ),
+            Self::OutOfBounds {
+                entry,
+                offset,
+                end,
+                buf_len,
+            } => write!(
+                f,
+                "relocation entry {entry} spanning offsets {offset:#x} to {end:#x} extends past \
+                 the end of its {buf_len:#x} byte buffer"
+            ),
+            Self::BadIndex {
+                entry,
+                index,
+                value_count,
+            } => write!(
+                f,
+                "relocation entry {entry} refers to value {index} but only {value_count} values \
+                 were provided"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RewriteError {}
+
+/// Checks that a list of relocations is sorted, non-overlapping, and within the bounds of a
+/// `buf_len` byte buffer so that it can be passed to [`rewrite_relocated`].
+pub fn validate_relocations(
+    relocations: impl IntoIterator<Item = (usize, ScalarRewriteKind)>,
+    buf_len: usize,
+) -> Result<(), RewriteError> {
+    let mut prev = None;
+
+    for (entry, (offset, kind)) in relocations.into_iter().enumerate() {
+        let end = offset.saturating_add(kind.width());
+
+        if let Some((prev_offset, prev_end)) = prev {
+            if offset < prev_offset {
+                return Err(RewriteError::Unsorted {
+                    entry,
+                    offset,
+                    prev_offset,
+                });
+            }
+
+            if offset < prev_end {
+                return Err(RewriteError::Overlapping {
+                    entry,
+                    offset,
+                    prev_end,
+                });
+            }
+        }
+
+        if end > buf_len {
+            return Err(RewriteError::OutOfBounds {
+                entry,
+                offset,
+                end,
+                buf_len,
+            });
+        }
+
+        prev = Some((offset, end));
+    }
+
+    Ok(())
+}
+
 // === Rewriting === //
 
+/// Copies `buf` into `writer`, passing the bytes at each replacement's offset through its rewriter.
+/// Replacements must be sorted and must not overlap. Violations are reported as [`RewriteError`]s
+/// but callers should prefer catching them up front with [`validate_relocations`].
 pub fn rewrite_relocated<W: BufWriter, C>(
     buf: &[u8],
     writer: &mut W,
@@ -155,12 +941,24 @@ pub fn rewrite_relocated<W: BufWriter, C>(
     // Invariant: `buf_cursor` is always less than or equal to the `buf` length.
     let mut buf_cursor = 0;
 
-    for (reloc_start, rewriter) in replacements {
-        // While there are still relocations affecting bytes in our at the end of our buffer...
-        debug_assert!(reloc_start >= buf_cursor);
+    for (entry, (reloc_start, rewriter)) in replacements.into_iter().enumerate() {
+        if reloc_start < buf_cursor {
+            return Err(RewriteError::Overlapping {
+                entry,
+                offset: reloc_start,
+                prev_end: buf_cursor,
+            }
+            .into());
+        }
 
         if reloc_start > buf.len() {
-            break;
+            return Err(RewriteError::OutOfBounds {
+                entry,
+                offset: reloc_start,
+                end: reloc_start,
+                buf_len: buf.len(),
+            }
+            .into());
         }
 
         // Push the bytes up until the start of the relocation.
@@ -168,7 +966,11 @@ pub fn rewrite_relocated<W: BufWriter, C>(
 
         // Push the new relocation bytes.
         let reloc_end = reloc_start
-            + buf[reloc_start..].try_count_bytes_read(|c| rewriter.rewrite(c, writer, cx))?;
+            + buf[reloc_start..]
+                .try_count_bytes_read(|c| rewriter.rewrite(c, writer, cx))
+                .with_context(|| {
+                    format!("failed to rewrite relocation entry {entry} at offset {reloc_start:#x}")
+                })?;
 
         // Bump the `buf_cursor`
         buf_cursor = reloc_end;
@@ -180,6 +982,38 @@ pub fn rewrite_relocated<W: BufWriter, C>(
     Ok(())
 }
 
+/// Estimates the number of bytes [`rewrite_relocated`] will write when rewriting `buf` with
+/// [`ScalarRewrite`]s of the given kinds so that writers can be preallocated. LEB-encoded values
+/// which aren't padded to their full width in `buf` grow to it when rewritten.
+pub fn estimate_rewritten_len(
+    buf: &[u8],
+    relocations: impl IntoIterator<Item = (usize, ScalarRewriteKind)>,
+) -> usize {
+    use ScalarRewriteKind::*;
+
+    let mut len = buf.len();
+
+    for (offset, kind) in relocations {
+        let width = kind.width();
+        let encoded = match kind {
+            VarU32 | VarI32 | VarU64 | VarI64 => {
+                let bytes = buf.get(offset..).unwrap_or_default();
+                let bytes = &bytes[..bytes.len().min(width)];
+
+                bytes
+                    .iter()
+                    .position(|&byte| byte & 0x80 == 0)
+                    .map_or(bytes.len(), |last| last + 1)
+            }
+            U32 | I32 | U64 | I64 => width,
+        };
+
+        len += width.saturating_sub(encoded);
+    }
+
+    len
+}
+
 pub trait Rewriter<W, C> {
     fn rewrite(self, buf: &mut ByteCursor, writer: &mut W, cx: &mut C) -> anyhow::Result<()>;
 }
@@ -199,6 +1033,10 @@ pub enum ScalarRewriteKind {
     VarI32,
     U32,
     I32,
+    VarU64,
+    VarI64,
+    U64,
+    I64,
 }
 
 impl ScalarRewriteKind {
@@ -208,21 +1046,46 @@ impl ScalarRewriteKind {
             Self::VarI32 => buf.read_var_i32().map(ScalarRewrite::VarI32),
             Self::U32 => buf.read_u32().map(ScalarRewrite::U32),
             Self::I32 => buf.read_i32().map(ScalarRewrite::I32),
+            Self::VarU64 => buf.read_var_u64().map(ScalarRewrite::VarU64),
+            Self::VarI64 => buf.read_var_i64().map(ScalarRewrite::VarI64),
+            Self::U64 => buf.read_u64().map(ScalarRewrite::U64),
+            Self::I64 => buf.read_i64().map(ScalarRewrite::I64),
         }
     }
 
+    /// Produces a rewrite to `value`. 64-bit kinds zero-extend it.
     pub fn with_value(self, value: u32) -> ScalarRewrite {
+        self.with_value_u64(u64::from(value))
+    }
+
+    /// Produces a rewrite to `value`. 32-bit kinds truncate it.
+    pub fn with_value_u64(self, value: u64) -> ScalarRewrite {
         match self {
-            ScalarRewriteKind::VarU32 => ScalarRewrite::VarU32(value),
-            ScalarRewriteKind::VarI32 => ScalarRewrite::VarI32(value as i32),
-            ScalarRewriteKind::U32 => ScalarRewrite::U32(value),
-            ScalarRewriteKind::I32 => ScalarRewrite::I32(value as i32),
+            ScalarRewriteKind::VarU32 => ScalarRewrite::VarU32(value as u32),
+            ScalarRewriteKind::VarI32 => ScalarRewrite::VarI32(value as u32 as i32),
+            ScalarRewriteKind::U32 => ScalarRewrite::U32(value as u32),
+            ScalarRewriteKind::I32 => ScalarRewrite::I32(value as u32 as i32),
+            ScalarRewriteKind::VarU64 => ScalarRewrite::VarU64(value),
+            ScalarRewriteKind::VarI64 => ScalarRewrite::VarI64(value as i64),
+            ScalarRewriteKind::U64 => ScalarRewrite::U64(value),
+            ScalarRewriteKind::I64 => ScalarRewrite::I64(value as i64),
         }
     }
 
     pub fn with_zeroed(self) -> ScalarRewrite {
         self.with_value(0)
     }
+
+    /// The number of bytes the relocated value occupies. LEB-encoded values are always padded to
+    /// their full width.
+    pub fn width(self) -> usize {
+        match self {
+            Self::VarU32 | Self::VarI32 => 5,
+            Self::U32 | Self::I32 => 4,
+            Self::VarU64 | Self::VarI64 => 10,
+            Self::U64 | Self::I64 => 8,
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -231,22 +1094,41 @@ pub enum ScalarRewrite {
     VarI32(i32),
     U32(u32),
     I32(i32),
+    VarU64(u64),
+    VarI64(i64),
+    U64(u64),
+    I64(i64),
 }
 
 impl ScalarRewrite {
+    /// The value being written, truncated if it is 64 bits wide.
     pub fn as_u32(self) -> u32 {
+        self.as_u64() as u32
+    }
+
+    /// The value being written with `addend` undone, truncated if it is 64 bits wide.
+    pub fn as_u32_neg_offset(self, addend: i64) -> u32 {
+        self.as_u64_neg_offset(addend) as u32
+    }
+
+    /// The value being written, zero-extended if it is 32 bits wide.
+    pub fn as_u64(self) -> u64 {
         use ScalarRewrite::*;
 
         match self {
-            VarU32(v) => v,
-            VarI32(v) => v as u32,
-            U32(v) => v,
-            I32(v) => v as u32,
+            VarU32(v) => v.into(),
+            VarI32(v) => (v as u32).into(),
+            U32(v) => v.into(),
+            I32(v) => (v as u32).into(),
+            VarU64(v) => v,
+            VarI64(v) => v as u64,
+            U64(v) => v,
+            I64(v) => v as u64,
         }
     }
 
-    pub fn as_u32_neg_offset(self, addend: i32) -> u32 {
-        self.as_u32().wrapping_add_signed(addend.wrapping_neg())
+    pub fn as_u64_neg_offset(self, addend: i64) -> u64 {
+        self.as_u64().wrapping_add_signed(addend.wrapping_neg())
     }
 
     pub fn kind(self) -> ScalarRewriteKind {
@@ -255,6 +1137,10 @@ impl ScalarRewrite {
             ScalarRewrite::VarI32(_) => ScalarRewriteKind::VarI32,
             ScalarRewrite::U32(_) => ScalarRewriteKind::U32,
             ScalarRewrite::I32(_) => ScalarRewriteKind::I32,
+            ScalarRewrite::VarU64(_) => ScalarRewriteKind::VarU64,
+            ScalarRewrite::VarI64(_) => ScalarRewriteKind::VarI64,
+            ScalarRewrite::U64(_) => ScalarRewriteKind::U64,
+            ScalarRewrite::I64(_) => ScalarRewriteKind::I64,
         }
     }
 
@@ -299,6 +1185,48 @@ impl ScalarRewrite {
         writer.write_i32(val);
         Ok(())
     }
+
+    pub fn rewrite_var_u64(
+        buf: &mut ByteCursor,
+        writer: &mut impl BufWriter,
+        val: u64,
+    ) -> anyhow::Result<()> {
+        // N.B. relocation values are always full width in the LLVM spec.
+        buf.read_var_u64_full()?;
+        writer.write_var_u64_full(val);
+        Ok(())
+    }
+
+    pub fn rewrite_var_i64(
+        buf: &mut ByteCursor,
+        writer: &mut impl BufWriter,
+        val: i64,
+    ) -> anyhow::Result<()> {
+        // N.B. relocation values are always full width in the LLVM spec.
+        buf.read_var_i64_full()?;
+        writer.write_var_i64_full(val);
+        Ok(())
+    }
+
+    pub fn rewrite_u64(
+        buf: &mut ByteCursor,
+        writer: &mut impl BufWriter,
+        val: u64,
+    ) -> anyhow::Result<()> {
+        buf.read_u64()?;
+        writer.write_u64(val);
+        Ok(())
+    }
+
+    pub fn rewrite_i64(
+        buf: &mut ByteCursor,
+        writer: &mut impl BufWriter,
+        val: i64,
+    ) -> anyhow::Result<()> {
+        buf.read_i64()?;
+        writer.write_i64(val);
+        Ok(())
+    }
 }
 
 impl<W: BufWriter, C> Rewriter<W, C> for ScalarRewrite {
@@ -310,6 +1238,166 @@ impl<W: BufWriter, C> Rewriter<W, C> for ScalarRewrite {
             VarI32(val) => Self::rewrite_var_i32(buf, writer, val),
             U32(val) => Self::rewrite_u32(buf, writer, val),
             I32(val) => Self::rewrite_i32(buf, writer, val),
+            VarU64(val) => Self::rewrite_var_u64(buf, writer, val),
+            VarI64(val) => Self::rewrite_var_i64(buf, writer, val),
+            U64(val) => Self::rewrite_u64(buf, writer, val),
+            I64(val) => Self::rewrite_i64(buf, writer, val),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ScalarRewriteKind::{VarU32, U32};
+
+    #[test]
+    fn validate_accepts_adjacent_relocations() {
+        assert_eq!(validate_relocations([(0, VarU32), (5, U32)], 9), Ok(()));
+    }
+
+    #[test]
+    fn validate_rejects_unsorted_relocations() {
+        assert_eq!(
+            validate_relocations([(5, U32), (0, VarU32)], 9),
+            Err(RewriteError::Unsorted {
+                entry: 1,
+                offset: 0,
+                prev_offset: 5,
+            }),
+        );
+    }
+
+    #[test]
+    fn validate_rejects_overlapping_relocations() {
+        assert_eq!(
+            validate_relocations([(0, VarU32), (4, U32)], 9),
+            Err(RewriteError::Overlapping {
+                entry: 1,
+                offset: 4,
+                prev_end: 5,
+            }),
+        );
+    }
+
+    #[test]
+    fn validate_rejects_out_of_bounds_relocations() {
+        assert_eq!(
+            validate_relocations([(0, VarU32), (6, U32)], 9),
+            Err(RewriteError::OutOfBounds {
+                entry: 1,
+                offset: 6,
+                end: 10,
+                buf_len: 9,
+            }),
+        );
+
+        // Offsets near the end of the address space mustn't wrap around.
+        assert_eq!(
+            validate_relocations([(usize::MAX, U32)], 9),
+            Err(RewriteError::OutOfBounds {
+                entry: 0,
+                offset: usize::MAX,
+                end: usize::MAX,
+                buf_len: 9,
+            }),
+        );
+    }
+
+    #[test]
+    fn rewrite_replaces_values() {
+        let buf = [0xAA, 0x80, 0x80, 0x80, 0x80, 0x00, 0xBB, 0, 0, 0, 0];
+        let mut out = Vec::new();
+
+        rewrite_relocated(
+            &buf,
+            &mut out,
+            &mut (),
+            [(1, VarU32.with_value(1)), (7, U32.with_value(2))],
+        )
+        .unwrap();
+
+        assert_eq!(out, [0xAA, 0x81, 0x80, 0x80, 0x80, 0x00, 0xBB, 2, 0, 0, 0]);
+    }
+
+    #[test]
+    fn rewrite_rejects_invalid_relocations() {
+        let buf = [0; 8];
+
+        let err = rewrite_relocated(
+            &buf,
+            &mut Vec::new(),
+            &mut (),
+            [(0, U32.with_zeroed()), (2, U32.with_zeroed())],
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&RewriteError::Overlapping {
+                entry: 1,
+                offset: 2,
+                prev_end: 4,
+            }),
+        );
+
+        let err = rewrite_relocated(&buf, &mut Vec::new(), &mut (), [(9, U32.with_zeroed())])
+            .unwrap_err();
+
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&RewriteError::OutOfBounds {
+                entry: 0,
+                offset: 9,
+                end: 9,
+                buf_len: 8,
+            }),
+        );
+
+        // Relocations running off the end of the buffer fail to read their value.
+        assert!(
+            rewrite_relocated(&buf, &mut Vec::new(), &mut (), [(6, U32.with_zeroed())]).is_err()
+        );
+    }
+
+    #[test]
+    fn reloc_entries_recover_from_out_of_range_fields() {
+        let entry = |ty: RelocEntryType, offset: u32, index: u32| RelocEntry {
+            ty,
+            offset,
+            index,
+            addend: ty.has_addend().then_some(-4),
+        };
+
+        let mut buf = Vec::new();
+        entry(RelocEntryType::FunctionIndexLeb, 1, 2).write(&mut buf);
+
+        // An offset which overflows a `u32`.
+        buf.push(RelocEntryType::MemoryAddrLeb as u8);
+        buf.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0x7F]);
+        buf.write_var_u32(0);
+        buf.write_var_i32(0);
+
+        entry(RelocEntryType::MemoryAddrSleb, 3, 4).write(&mut buf);
+
+        // An unknown relocation type stops parsing since its fields are unknown.
+        buf.extend_from_slice(&[0xFF, 0, 0]);
+        entry(RelocEntryType::FunctionIndexLeb, 5, 6).write(&mut buf);
+
+        let (entries, errors) =
+            ByteParseList::<RelocEntry>::new(ByteCursor(&buf)).parse_recovering();
+
+        assert_eq!(
+            entries
+                .iter()
+                .map(|e| (e.offset, e.index))
+                .collect::<Vec<_>>(),
+            [(1, 2), (3, 4)],
+        );
+        assert_eq!(
+            errors.iter().map(|err| err.index).collect::<Vec<_>>(),
+            [1, 3],
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! Checkpointed assembly into a file, allowing interrupted assemblies to pick up where they left off.
+
+use std::{
+    fs,
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use blake3::{Hash, Hasher};
+
+use crate::{
+    coder::WasmallMod,
+    store::BlobSource,
+    util::{write_atomic, ByteCursor},
+};
+
+// === Checkpoint === //
+
+pub const CHECKPOINT_MAGIC: [u8; 8] = *b"WSMLCKPT";
+
+/// A record of how far a previous assembly got.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Checkpoint {
+    /// The hash of the module being assembled. Checkpoints for other modules are ignored.
+    pub module_hash: Hash,
+
+    /// The number of segments which have been fully fetched, verified, and written.
+    pub segments_done: u64,
+
+    /// The number of bytes of output those segments produced.
+    pub bytes_written: u64,
+
+    /// The hash of those bytes, used to detect output files modified since the checkpoint.
+    pub prefix_hash: Hash,
+}
+
+impl Checkpoint {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&CHECKPOINT_MAGIC);
+        buf.extend_from_slice(self.module_hash.as_bytes());
+        buf.extend_from_slice(&self.segments_done.to_le_bytes());
+        buf.extend_from_slice(&self.bytes_written.to_le_bytes());
+        buf.extend_from_slice(self.prefix_hash.as_bytes());
+        buf
+    }
+
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        let mut cursor = ByteCursor(data);
+        anyhow::ensure!(
+            cursor.consume(CHECKPOINT_MAGIC.len()).ok() == Some(&CHECKPOINT_MAGIC[..]),
+            "not an assembly checkpoint"
+        );
+
+        let me = Self {
+            module_hash: Hash::from_bytes(cursor.consume_arr()?),
+            segments_done: cursor.read_u64()?,
+            bytes_written: cursor.read_u64()?,
+            prefix_hash: Hash::from_bytes(cursor.consume_arr()?),
+        };
+        anyhow::ensure!(cursor.at_eof(), "trailing bytes after checkpoint");
+
+        Ok(me)
+    }
+
+    /// Loads the checkpoint at `path`, returning `None` if there isn't one.
+    pub fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+        match fs::read(path) {
+            Ok(data) => Self::decode(&data)
+                .with_context(|| format!("failed to decode checkpoint {path:?}"))
+                .map(Some),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).with_context(|| format!("failed to read checkpoint {path:?}")),
+        }
+    }
+
+    /// Atomically replaces the checkpoint at `path`.
+    pub fn store(&self, path: &Path) -> anyhow::Result<()> {
+        write_atomic(path, &self.encode(), "checkpoint", true)
+    }
+}
+
+// === Resumable Assembly === //
+
+#[derive(Debug, Clone)]
+pub struct ResumeOptions {
+    /// Where the checkpoint is kept. It is deleted once assembly completes.
+    pub checkpoint_path: PathBuf,
+
+    /// The minimum number of bytes to write between checkpoints. Each checkpoint flushes the
+    /// output to disk so this trades resume granularity for throughput.
+    pub checkpoint_interval: u64,
+}
+
+impl ResumeOptions {
+    pub fn new(checkpoint_path: impl Into<PathBuf>) -> Self {
+        Self {
+            checkpoint_path: checkpoint_path.into(),
+            checkpoint_interval: 1 << 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ResumeReport {
+    /// The number of segments skipped thanks to a previous checkpoint.
+    pub segments_skipped: u64,
+
+    /// The number of output bytes reused from a previous checkpoint.
+    pub bytes_reused: u64,
+
+    /// The number of checkpoints recorded during this run.
+    pub checkpoints_written: usize,
+}
+
+/// Assembles `module` into the file at `out_path`, resuming from the checkpoint described by
+/// `options` if a compatible one exists. The full output is checked against the module hash before
+/// the checkpoint is removed.
+pub fn assemble_resumable(
+    module: &WasmallMod<'_>,
+    source: &(impl ?Sized + BlobSource),
+    out_path: &Path,
+    options: &ResumeOptions,
+) -> anyhow::Result<ResumeReport> {
+    let mut report = ResumeReport::default();
+
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(out_path)
+        .with_context(|| format!("failed to open output file {out_path:?}"))?;
+
+    // Determine where to resume from.
+    let mut hasher = Hasher::new();
+    let checkpoint = Checkpoint::load(&options.checkpoint_path)?
+        .filter(|checkpoint| checkpoint.module_hash == module.module_hash());
+
+    if let Some(checkpoint) = checkpoint {
+        // Hash the prefix straight from the file since it may be too large to hold in memory.
+        let mut prefix_hasher = Hasher::new();
+        prefix_hasher
+            .update_reader((&mut file).take(checkpoint.bytes_written))
+            .with_context(|| format!("failed to read partial output {out_path:?}"))?;
+
+        if prefix_hasher.count() == checkpoint.bytes_written
+            && prefix_hasher.finalize() == checkpoint.prefix_hash
+        {
+            hasher = prefix_hasher;
+            report.segments_skipped = checkpoint.segments_done;
+            report.bytes_reused = checkpoint.bytes_written;
+        }
+    }
+
+    // Discard anything written after the checkpoint.
+    file.set_len(report.bytes_reused)?;
+    file.seek(SeekFrom::Start(report.bytes_reused))?;
+
+    // Assemble the remaining segments.
+    let mut segments_done = 0u64;
+    let mut bytes_written = report.bytes_reused;
+    let mut last_checkpoint = bytes_written;
+    let mut seg_buf = Vec::new();
+
+    for segment in module.segments() {
+        let segment = segment?;
+        segments_done += 1;
+
+        if segments_done <= report.segments_skipped {
+            continue;
+        }
+
+        seg_buf.clear();
+        module.assemble_segment(&segment, source, &mut seg_buf)?;
+        file.write_all(&seg_buf)
+            .with_context(|| format!("failed to write to output file {out_path:?}"))?;
+        hasher.update(&seg_buf);
+        bytes_written += seg_buf.len() as u64;
+
+        if bytes_written - last_checkpoint >= options.checkpoint_interval {
+            // The output must hit the disk before the checkpoint claims it has.
+            file.sync_data()?;
+            Checkpoint {
+                module_hash: module.module_hash(),
+                segments_done,
+                bytes_written,
+                prefix_hash: hasher.finalize(),
+            }
+            .store(&options.checkpoint_path)?;
+
+            last_checkpoint = bytes_written;
+            report.checkpoints_written += 1;
+        }
+    }
+
+    anyhow::ensure!(
+        segments_done >= report.segments_skipped,
+        "checkpoint claims more segments than the module has"
+    );
+
+    file.sync_data()?;
+
+    let actual_hash = hasher.finalize();
+    if actual_hash != module.module_hash() {
+        // The checkpoint obviously can't be trusted anymore.
+        let _ = fs::remove_file(&options.checkpoint_path);
+        anyhow::bail!(
+            "assembled module hash mismatch; expected {}, got {actual_hash}",
+            module.module_hash(),
+        );
+    }
+
+    match fs::remove_file(&options.checkpoint_path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => {
+            return Err(err).with_context(|| {
+                format!("failed to remove checkpoint {:?}", options.checkpoint_path)
+            })
+        }
+    }
+
+    Ok(report)
+}
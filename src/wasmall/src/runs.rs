@@ -0,0 +1,190 @@
+//! Deduplication of byte runs shared between otherwise different blobs.
+//!
+//! Blobs are only deduplicated when they are byte-identical but a bundle's blobs often share long
+//! runs of bytes regardless: string tables repeated across sections, lookup tables compiled into
+//! several modules, and so on. A [`RunStore`] cuts every blob it is given into content-defined runs,
+//! meaning that identical runs are cut identically wherever they appear, and stores each distinct
+//! run once. Blobs are reconstituted from their runs when they are fetched.
+
+use std::{borrow::Cow, collections::hash_map, ops::Range};
+
+use blake3::Hash;
+use rustc_hash::FxHashMap;
+
+use crate::{bundle::WasmallBundle, store::BlobSource};
+
+// === Chunking === //
+
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// Runs are never cut shorter than this, except at the end of a blob.
+    pub min_run_len: usize,
+
+    /// The expected length of a run past its minimum length. Must be a power of two.
+    pub avg_run_len: usize,
+
+    /// Runs are always cut once they reach this length.
+    pub max_run_len: usize,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            min_run_len: 64,
+            avg_run_len: 256,
+            max_run_len: 4096,
+        }
+    }
+}
+
+/// Random values for the gear hash, generated with `splitmix64`.
+const GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut state = 0x9E37_79B9_7F4A_7C15_u64;
+    let mut i = 0;
+
+    while i < table.len() {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+
+    table
+};
+
+impl RunOptions {
+    /// Splits `data` into consecutive runs. Cut points only depend on the bytes preceding them so
+    /// runs shared between blobs tend to be cut at the same places.
+    pub fn cut<'a>(&self, mut data: &'a [u8]) -> impl Iterator<Item = &'a [u8]> + 'a {
+        assert!(
+            self.avg_run_len.is_power_of_two(),
+            "average run length must be a power of two"
+        );
+
+        let mask = self.avg_run_len as u64 - 1;
+        let min = self.min_run_len;
+        let max = self.max_run_len.max(min).max(1);
+
+        std::iter::from_fn(move || {
+            if data.is_empty() {
+                return None;
+            }
+
+            let mut gear = 0u64;
+            let mut len = data.len().min(max);
+
+            for (i, &byte) in data[..len].iter().enumerate().skip(min) {
+                gear = (gear << 1).wrapping_add(GEAR[byte as usize]);
+
+                if gear & mask == 0 {
+                    len = i + 1;
+                    break;
+                }
+            }
+
+            let (run, rest) = data.split_at(len);
+            data = rest;
+            Some(run)
+        })
+    }
+}
+
+// === RunStore === //
+
+/// A [`BlobSource`] which stores the runs of its blobs rather than the blobs themselves.
+#[derive(Debug, Default)]
+pub struct RunStore {
+    pub options: RunOptions,
+    pub run_buf: Vec<u8>,
+
+    /// The location of every distinct run in the `run_buf`.
+    pub runs: FxHashMap<Hash, Range<usize>>,
+
+    /// The ranges of the `run_buf` making up each blob, in order. Runs stored back to back are
+    /// merged into a single range so blobs sharing nothing are stored as one range.
+    pub blobs: FxHashMap<Hash, Vec<Range<usize>>>,
+}
+
+impl RunStore {
+    pub fn new(options: RunOptions) -> Self {
+        Self {
+            options,
+            ..Self::default()
+        }
+    }
+
+    /// Builds a store out of every blob of the bundle.
+    pub fn from_bundle(bundle: &WasmallBundle, options: RunOptions) -> Self {
+        let mut store = Self::new(options);
+
+        for (&hash, range) in &bundle.hashes {
+            store.push(hash, &bundle.blob_buf[range.clone()]);
+        }
+
+        store
+    }
+
+    /// Adds a blob to the store, storing only the runs the store doesn't have yet. Returns the
+    /// number of bytes which were already present.
+    pub fn push(&mut self, hash: Hash, data: &[u8]) -> usize {
+        if self.blobs.contains_key(&hash) {
+            return data.len();
+        }
+
+        let mut bytes_shared = 0;
+        let mut ranges = Vec::<Range<usize>>::new();
+
+        for run in self.options.cut(data) {
+            let range = match self.runs.entry(blake3::hash(run)) {
+                hash_map::Entry::Occupied(entry) => {
+                    bytes_shared += run.len();
+                    entry.get().clone()
+                }
+                hash_map::Entry::Vacant(entry) => {
+                    let start = self.run_buf.len();
+                    self.run_buf.extend_from_slice(run);
+                    entry.insert(start..self.run_buf.len()).clone()
+                }
+            };
+
+            match ranges.last_mut() {
+                Some(last) if last.end == range.start => last.end = range.end,
+                _ => ranges.push(range),
+            }
+        }
+
+        self.blobs.insert(hash, ranges);
+        bytes_shared
+    }
+
+    pub fn len(&self) -> usize {
+        self.blobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blobs.is_empty()
+    }
+}
+
+impl BlobSource for RunStore {
+    fn get_blob(&self, hash: Hash) -> anyhow::Result<Option<Cow<'_, [u8]>>> {
+        let Some(ranges) = self.blobs.get(&hash) else {
+            return Ok(None);
+        };
+
+        Ok(Some(match &ranges[..] {
+            [] => Cow::Borrowed(&[][..]),
+            [range] => Cow::Borrowed(&self.run_buf[range.clone()]),
+            ranges => Cow::Owned(
+                ranges
+                    .iter()
+                    .flat_map(|range| &self.run_buf[range.clone()])
+                    .copied()
+                    .collect(),
+            ),
+        }))
+    }
+}
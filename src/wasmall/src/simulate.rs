@@ -0,0 +1,274 @@
+//! A [`BlobSource`] wrapper simulating unreliable networks for tuning and testing fetch logic.
+//!
+//! Every effect is driven by a seeded generator so a given configuration and sequence of requests
+//! always produces the same latencies, failures, and delivery orders. Delays are accumulated on a
+//! virtual clock and are only actually slept through if [`NetworkConditions::sleep`] is set, which
+//! keeps tests fast while still letting them assert on the time a fetch would have taken.
+
+use std::{
+    borrow::Cow,
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use blake3::Hash;
+
+use crate::store::BlobSource;
+
+// === NetworkConditions === //
+
+#[derive(Debug, Clone)]
+pub struct NetworkConditions {
+    /// The seed of the generator driving every random effect.
+    pub seed: u64,
+
+    /// The latency added to every request, regardless of how many blobs it fetches.
+    pub latency: Duration,
+
+    /// The maximum amount of random latency added on top of [`latency`](Self::latency).
+    pub jitter: Duration,
+
+    /// The bandwidth cap in bytes per second, if any.
+    pub bandwidth: Option<u64>,
+
+    /// The probability that fetching any individual blob fails.
+    pub failure_rate: f64,
+
+    /// The probability that any two adjacent blobs in a batch are delivered out of order.
+    pub reorder_rate: f64,
+
+    /// Whether to actually sleep through the simulated delays.
+    pub sleep: bool,
+}
+
+impl Default for NetworkConditions {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            bandwidth: None,
+            failure_rate: 0.,
+            reorder_rate: 0.,
+            sleep: false,
+        }
+    }
+}
+
+// === SimulatedBlobSource === //
+
+/// The outcome of fetching a single blob.
+pub type BlobFetch<'a> = anyhow::Result<Option<Cow<'a, [u8]>>>;
+
+/// Counters describing the traffic seen by a [`SimulatedBlobSource`].
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct NetworkStats {
+    /// The number of round-trips made, where a batch counts as a single round-trip.
+    pub requests: u64,
+
+    /// The number of blobs requested, including failed ones.
+    pub blobs_requested: u64,
+
+    /// The number of blobs which were found and delivered.
+    pub blobs_served: u64,
+
+    /// The number of blobs the inner source didn't have.
+    pub blobs_missing: u64,
+
+    /// The number of injected failures.
+    pub failures: u64,
+
+    /// The number of bytes delivered.
+    pub bytes_served: u64,
+
+    /// The total simulated delay.
+    pub simulated_delay: Duration,
+}
+
+#[derive(Debug, Default)]
+struct AtomicStats {
+    requests: AtomicU64,
+    blobs_requested: AtomicU64,
+    blobs_served: AtomicU64,
+    blobs_missing: AtomicU64,
+    failures: AtomicU64,
+    bytes_served: AtomicU64,
+    simulated_delay_nanos: AtomicU64,
+}
+
+/// A SplitMix64 generator, which is plenty for simulating network noise.
+#[derive(Debug)]
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a uniformly distributed value in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0. && self.next_f64() < probability
+    }
+}
+
+/// Wraps a [`BlobSource`], subjecting every fetch to the configured [`NetworkConditions`].
+#[derive(Debug)]
+pub struct SimulatedBlobSource<S> {
+    inner: S,
+    conditions: NetworkConditions,
+    rng: Mutex<SplitMix64>,
+    stats: AtomicStats,
+}
+
+impl<S: BlobSource> SimulatedBlobSource<S> {
+    pub fn new(inner: S, conditions: NetworkConditions) -> Self {
+        Self {
+            inner,
+            rng: Mutex::new(SplitMix64(conditions.seed)),
+            conditions,
+            stats: AtomicStats::default(),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn conditions(&self) -> &NetworkConditions {
+        &self.conditions
+    }
+
+    pub fn stats(&self) -> NetworkStats {
+        let stats = &self.stats;
+
+        NetworkStats {
+            requests: stats.requests.load(Relaxed),
+            blobs_requested: stats.blobs_requested.load(Relaxed),
+            blobs_served: stats.blobs_served.load(Relaxed),
+            blobs_missing: stats.blobs_missing.load(Relaxed),
+            failures: stats.failures.load(Relaxed),
+            bytes_served: stats.bytes_served.load(Relaxed),
+            simulated_delay: Duration::from_nanos(stats.simulated_delay_nanos.load(Relaxed)),
+        }
+    }
+
+    pub fn reset_stats(&self) {
+        let stats = &self.stats;
+
+        for counter in [
+            &stats.requests,
+            &stats.blobs_requested,
+            &stats.blobs_served,
+            &stats.blobs_missing,
+            &stats.failures,
+            &stats.bytes_served,
+            &stats.simulated_delay_nanos,
+        ] {
+            counter.store(0, Relaxed);
+        }
+    }
+
+    fn delay(&self, delay: Duration) {
+        self.stats
+            .simulated_delay_nanos
+            .fetch_add(delay.as_nanos().try_into().unwrap_or(u64::MAX), Relaxed);
+
+        if self.conditions.sleep && !delay.is_zero() {
+            std::thread::sleep(delay);
+        }
+    }
+
+    fn request_latency(&self, rng: &mut SplitMix64) -> Duration {
+        let jitter = self.conditions.jitter.mul_f64(rng.next_f64());
+        self.conditions.latency + jitter
+    }
+
+    fn transfer_time(&self, bytes: usize) -> Duration {
+        match self.conditions.bandwidth {
+            Some(bandwidth) => Duration::from_secs_f64(bytes as f64 / bandwidth.max(1) as f64),
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Fetches a single blob without accounting for the request's latency.
+    fn fetch(&self, rng: &mut SplitMix64, hash: Hash) -> BlobFetch<'_> {
+        self.stats.blobs_requested.fetch_add(1, Relaxed);
+
+        if rng.chance(self.conditions.failure_rate) {
+            self.stats.failures.fetch_add(1, Relaxed);
+            anyhow::bail!("simulated network failure fetching blob {hash}");
+        }
+
+        let Some(data) = self.inner.get_blob(hash)? else {
+            self.stats.blobs_missing.fetch_add(1, Relaxed);
+            return Ok(None);
+        };
+
+        self.stats.blobs_served.fetch_add(1, Relaxed);
+        self.stats
+            .bytes_served
+            .fetch_add(data.len() as u64, Relaxed);
+
+        Ok(Some(data))
+    }
+
+    /// Fetches several blobs in a single simulated round-trip. Results are delivered in a possibly
+    /// shuffled order, each alongside the hash it was requested by.
+    pub fn get_blobs(&self, hashes: &[Hash]) -> Vec<(Hash, BlobFetch<'_>)> {
+        let mut rng = self.rng.lock().unwrap();
+        self.stats.requests.fetch_add(1, Relaxed);
+
+        let mut delay = self.request_latency(&mut rng);
+        let mut results = Vec::with_capacity(hashes.len());
+
+        for &hash in hashes {
+            let result = self.fetch(&mut rng, hash);
+            if let Ok(Some(data)) = &result {
+                delay = delay.saturating_add(self.transfer_time(data.len()));
+            }
+            results.push((hash, result));
+        }
+
+        for i in 1..results.len() {
+            if rng.chance(self.conditions.reorder_rate) {
+                results.swap(i - 1, i);
+            }
+        }
+
+        drop(rng);
+        self.delay(delay);
+        results
+    }
+}
+
+impl<S: BlobSource> BlobSource for SimulatedBlobSource<S> {
+    fn get_blob(&self, hash: Hash) -> anyhow::Result<Option<Cow<'_, [u8]>>> {
+        let mut rng = self.rng.lock().unwrap();
+        self.stats.requests.fetch_add(1, Relaxed);
+
+        let mut delay = self.request_latency(&mut rng);
+        let result = self.fetch(&mut rng, hash);
+        if let Ok(Some(data)) = &result {
+            delay = delay.saturating_add(self.transfer_time(data.len()));
+        }
+
+        drop(rng);
+        self.delay(delay);
+        result
+    }
+
+    fn verifies_blobs(&self) -> bool {
+        self.inner.verifies_blobs()
+    }
+}
@@ -0,0 +1,413 @@
+//! Property testing of the parsers, the splitter, and assembly against generated inputs.
+//!
+//! Each [`Target`] turns a buffer of random bytes into a test case: modules are generated with
+//! [`wasm_smith`], relocatable objects with [`arbitrary_object`], and the parsers are fed the bytes
+//! directly. Modules are checked with the [corpus](crate::corpus) harness and must round-trip
+//! exactly; parsers may reject their input but must never panic.
+//!
+//! Failing inputs are minimized and persisted as fixtures. Generated modules are stored as `.wasm`
+//! files so the corpus harness picks them up as regression tests, and parser inputs as `.bin` files
+//! which [`replay`] feeds back through the parsers. The `smith` binary persists them to
+//! `tests/fixtures/smith` by default, which the `smith` integration test replays.
+
+use std::{
+    cell::Cell,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::Once,
+};
+
+use anyhow::Context;
+use arbitrary::Unstructured;
+
+use crate::{
+    builder::{ModuleBuilder, SectionBuilder},
+    corpus::{check_module, find_modules, CorpusConfig},
+    fuzz::fuzz_parsers,
+    reloc::{RelocEntry, RelocEntryType, RelocSection},
+    util::PatchableWriter,
+};
+
+/// The length of the random inputs a [`run`] generates test cases from.
+const INPUT_LEN: usize = 4096;
+
+/// The maximum number of candidate inputs [`minimize`] tries before giving up.
+const MAX_SHRINK_ATTEMPTS: usize = 4096;
+
+// === Generators === //
+
+fn smith_config() -> wasm_smith::Config {
+    wasm_smith::Config {
+        bulk_memory_enabled: true,
+        reference_types_enabled: true,
+        simd_enabled: true,
+        generate_custom_sections: true,
+        ..wasm_smith::Config::default()
+    }
+}
+
+/// Generates a valid module using only the proposals enabled by default.
+pub fn arbitrary_module(u: &mut Unstructured<'_>) -> arbitrary::Result<Vec<u8>> {
+    Ok(wasm_smith::Module::new(smith_config(), u)?.to_bytes())
+}
+
+/// Encodes `value` as an LEB of exactly `width` bytes. Signed values are sign-extended.
+fn write_padded_leb(out: &mut Vec<u8>, value: i64, width: usize) {
+    for i in 0..width {
+        let byte = ((value >> (7 * i)) & 0x7F) as u8;
+        out.push(if i + 1 < width { byte | 0x80 } else { byte });
+    }
+}
+
+/// The minimum width of an unsigned LEB encoding `value`.
+fn leb_width(value: u32) -> usize {
+    (32 - value.leading_zeros() as usize).div_ceil(7).max(1)
+}
+
+/// Generates a valid relocatable object with a `reloc.CODE` section covering the immediates of its
+/// `call` and `i32.const` instructions. The count and size fields of the code section are padded to
+/// arbitrary widths like those emitted by linkers.
+pub fn arbitrary_object(u: &mut Unstructured<'_>) -> arbitrary::Result<Vec<u8>> {
+    let func_count = u.int_in_range(1..=8u32)?;
+
+    // Generate the code section along with its relocations.
+    let mut code = Vec::new();
+    let mut relocs = Vec::new();
+    write_padded_leb(
+        &mut code,
+        func_count.into(),
+        u.int_in_range(leb_width(func_count)..=5)?,
+    );
+
+    for _ in 0..func_count {
+        // Reserve a possibly padded size field. Bodies never exceed 127 bytes so any width fits.
+        let size = code.reserve_patch(u.int_in_range(1..=5)?);
+
+        // Generate the body, recording the offsets of its relocations.
+        code.push(0x00);
+
+        for _ in 0..u.int_in_range(0..=16)? {
+            let symbol = u.int_in_range(0..=3u32)?;
+
+            match u.int_in_range(0..=3)? {
+                0 => code.push(0x01),
+                1 => {
+                    code.push(0x10);
+                    relocs.push(RelocEntry {
+                        ty: RelocEntryType::FunctionIndexLeb,
+                        offset: code.len() as u32,
+                        index: symbol,
+                        addend: None,
+                    });
+                    write_padded_leb(&mut code, u.int_in_range(0..=func_count - 1)?.into(), 5);
+                }
+                kind => {
+                    let (ty, addend) = if kind == 2 {
+                        (
+                            RelocEntryType::MemoryAddrSleb,
+                            Some(u.int_in_range(-64..=64i64)?),
+                        )
+                    } else {
+                        (RelocEntryType::TableIndexSleb, None)
+                    };
+
+                    code.push(0x41);
+                    relocs.push(RelocEntry {
+                        ty,
+                        offset: code.len() as u32,
+                        index: symbol,
+                        addend,
+                    });
+                    write_padded_leb(&mut code, u.arbitrary::<i32>()?.into(), 5);
+                    code.push(0x1A);
+                }
+            }
+        }
+
+        code.push(0x0B);
+        size.fill_len(&mut code);
+    }
+
+    // Assemble the object. The relocation section targets the code section at index 2.
+    let mut funcs = SectionBuilder::vec(3);
+    for _ in 0..func_count {
+        funcs.item().push(0);
+    }
+
+    let mut out = ModuleBuilder::new(Vec::new());
+    out.section(1, &[0x01, 0x60, 0x00, 0x00])
+        .and_then(|out| out.build_section(funcs))
+        .and_then(|out| out.section(10, &code))
+        .and_then(|out| out.build_section(RelocSection::build("reloc.CODE", 2, &relocs)))
+        .expect("generated object is too big");
+
+    Ok(out.finish())
+}
+
+// === Targets === //
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Target {
+    /// Feeds the input directly to the index, blob, and relocation parsers.
+    Parsers,
+
+    /// Round-trips modules generated by [`arbitrary_module`].
+    Modules,
+
+    /// Round-trips objects generated by [`arbitrary_object`].
+    Objects,
+}
+
+impl Target {
+    pub const ALL: [Self; 3] = [Self::Parsers, Self::Modules, Self::Objects];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Parsers => "parsers",
+            Self::Modules => "modules",
+            Self::Objects => "objects",
+        }
+    }
+
+    pub fn from_name(name: &str) -> anyhow::Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|target| target.name() == name)
+            .with_context(|| format!("unknown target {name:?}"))
+    }
+
+    /// Turns random bytes into the test case for this target.
+    pub fn generate(self, input: &[u8]) -> arbitrary::Result<Vec<u8>> {
+        let mut u = Unstructured::new(input);
+
+        match self {
+            Self::Parsers => Ok(input.to_vec()),
+            Self::Modules => arbitrary_module(&mut u),
+            Self::Objects => arbitrary_object(&mut u),
+        }
+    }
+
+    /// Checks a test case, returning a description of the failure if it fails.
+    pub fn check_case(self, case: &[u8]) -> Option<String> {
+        catch_panic(|| match self {
+            Self::Parsers => {
+                fuzz_parsers(case);
+                None
+            }
+            Self::Modules | Self::Objects => CorpusConfig::standard().iter().find_map(|config| {
+                check_module(case, config).map(|kind| format!("[{}]: {kind}", config.name))
+            }),
+        })
+        .unwrap_or_else(|panic| Some(format!("panicked: {panic}")))
+    }
+
+    /// Generates and checks the test case for the specified input. Inputs from which no test case
+    /// can be generated pass trivially.
+    pub fn check(self, input: &[u8]) -> Option<String> {
+        self.generate(input)
+            .ok()
+            .and_then(|case| self.check_case(&case))
+    }
+}
+
+thread_local! {
+    static SILENCED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Silences panics on the current thread while it is alive. Expected panics would otherwise flood
+/// the output during minimization.
+///
+/// The process-wide panic hook is wrapped once rather than swapped out for the duration of a
+/// [`run`], so panics on other threads are still reported and concurrent runs can't leave an empty
+/// hook installed.
+struct SilencePanics;
+
+impl SilencePanics {
+    fn new() -> Self {
+        static INSTALL: Once = Once::new();
+
+        INSTALL.call_once(|| {
+            let hook = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                if SILENCED.get() == 0 {
+                    hook(info);
+                }
+            }));
+        });
+
+        SILENCED.set(SILENCED.get() + 1);
+        Self
+    }
+}
+
+impl Drop for SilencePanics {
+    fn drop(&mut self) {
+        SILENCED.set(SILENCED.get() - 1);
+    }
+}
+
+fn catch_panic<R>(f: impl FnOnce() -> R) -> Result<R, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        payload
+            .downcast_ref::<&str>()
+            .map(|msg| msg.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "<non-string panic payload>".to_string())
+    })
+}
+
+// === Minimization === //
+
+/// Shrinks a failing input by repeatedly removing chunks of it while it keeps failing.
+pub fn minimize(target: Target, mut input: Vec<u8>) -> Vec<u8> {
+    let mut attempts = 0;
+    let mut chunk = input.len().div_ceil(2);
+
+    while chunk > 0 && attempts < MAX_SHRINK_ATTEMPTS {
+        let mut start = 0;
+
+        while start < input.len() && attempts < MAX_SHRINK_ATTEMPTS {
+            let mut candidate = input.clone();
+            candidate.drain(start..(start + chunk).min(input.len()));
+            attempts += 1;
+
+            if target.check(&candidate).is_some() {
+                input = candidate;
+            } else {
+                start += chunk;
+            }
+        }
+
+        chunk /= 2;
+    }
+
+    input
+}
+
+// === Runner === //
+
+#[derive(Debug)]
+pub struct PropertyFailure {
+    pub target: Target,
+
+    /// The minimized input.
+    pub input: Vec<u8>,
+
+    pub message: String,
+
+    /// Where the failing test case was persisted.
+    pub fixture: Option<PathBuf>,
+}
+
+#[derive(Debug, Default)]
+pub struct PropertyReport {
+    pub cases: usize,
+    pub failures: Vec<PropertyFailure>,
+}
+
+impl PropertyReport {
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Writes a failing test case to `dir`, named after its hash so that repeated failures don't
+/// accumulate duplicate fixtures.
+pub fn persist(target: Target, case: &[u8], dir: &Path) -> anyhow::Result<PathBuf> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create fixture directory {dir:?}"))?;
+
+    let hash = blake3::hash(case).to_hex();
+    let ext = if target == Target::Parsers {
+        "bin"
+    } else {
+        "wasm"
+    };
+    let path = dir.join(format!("{}-{}.{ext}", target.name(), &hash[..16]));
+
+    std::fs::write(&path, case).with_context(|| format!("failed to write fixture {path:?}"))?;
+    Ok(path)
+}
+
+/// Derives the random input for the specified case from `seed`.
+fn case_input(seed: u64, case: u64) -> Vec<u8> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&seed.to_le_bytes());
+    hasher.update(&case.to_le_bytes());
+
+    let mut input = vec![0; INPUT_LEN];
+    hasher.finalize_xof().fill(&mut input);
+    input
+}
+
+/// Checks `cases` generated test cases against `target`, minimizing each failure and persisting it
+/// to `fixtures` if specified.
+pub fn run(
+    target: Target,
+    seed: u64,
+    cases: u64,
+    fixtures: Option<&Path>,
+) -> anyhow::Result<PropertyReport> {
+    let mut report = PropertyReport::default();
+    let _silence = SilencePanics::new();
+
+    for case in 0..cases {
+        report.cases += 1;
+
+        let input = case_input(seed, case);
+        if target.check(&input).is_none() {
+            continue;
+        }
+
+        let input = minimize(target, input);
+        if report.failures.iter().any(|failure| failure.input == input) {
+            continue;
+        }
+
+        let message = target.check(&input).unwrap_or_default();
+        let fixture = match (fixtures, target.generate(&input)) {
+            (Some(dir), Ok(case)) => Some(persist(target, &case, dir)?),
+            _ => None,
+        };
+
+        report.failures.push(PropertyFailure {
+            target,
+            input,
+            message,
+            fixture,
+        });
+    }
+
+    Ok(report)
+}
+
+/// Re-checks every fixture persisted to `dir`, returning the failing ones along with their
+/// failures.
+pub fn replay(dir: &Path) -> anyhow::Result<Vec<(PathBuf, String)>> {
+    let mut failures = Vec::new();
+
+    let mut parser_inputs = Vec::new();
+    for dirent in std::fs::read_dir(dir).with_context(|| format!("failed to list {dir:?}"))? {
+        let path = dirent?.path();
+        if path.extension().is_some_and(|ext| ext == "bin") {
+            parser_inputs.push(path);
+        }
+    }
+
+    parser_inputs.sort();
+
+    for path in parser_inputs.into_iter().chain(find_modules(dir)?) {
+        let case = std::fs::read(&path).with_context(|| format!("failed to read {path:?}"))?;
+        let target = if path.extension().is_some_and(|ext| ext == "bin") {
+            Target::Parsers
+        } else {
+            Target::Modules
+        };
+
+        if let Some(message) = target.check_case(&case) {
+            failures.push((path, message));
+        }
+    }
+
+    Ok(failures)
+}
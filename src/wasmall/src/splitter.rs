@@ -2,12 +2,17 @@ use std::{collections::hash_map, num::Wrapping, ops::Range};
 
 use anyhow::Context;
 use rustc_hash::FxHashMap;
-use wasmparser::{DefinedDataSymbol, Linking, LinkingSectionReader, Parser, Payload, SymbolInfo};
+use wasmparser::{CompositeType, Parser, Payload, TypeRef};
 
 use crate::{
-    coder::{WasmallArchive, WasmallWriter},
-    reloc::{RelocEntry, RelocSection},
-    util::{len_of, ByteCursor, ByteParse, Leb128WriteExt, OffsetTracker, VecExt},
+    builder::{SectionWriteExt, CORE_MODULE_HEADER},
+    callgraph::startup_order,
+    chunker::ChunkingOptions,
+    coder::{SymbolKind, WasmallArchive, WasmallWriter, WriterOptions},
+    features::{check_features, validate, WasmFeatures},
+    normalize::normalize_func_body,
+    reloc::{validate_relocations, LinkingSection, RelocEntry, RelocIndex, RelocSection, RelocSet},
+    util::{ByteCursor, ByteParse, LenCounter, OffsetTracker, SectionTracker, VecExt},
 };
 
 #[derive(Debug)]
@@ -16,9 +21,265 @@ pub struct SplitModuleResult {
     pub bytes_truncated: usize,
 }
 
+#[derive(Debug, Clone)]
+pub struct SplitOptions {
+    pub writer: WriterOptions,
+
+    /// The proposals the module may use. Modules are validated against these before being split,
+    /// or merely checked for the proposals they use if validation is skipped.
+    pub features: WasmFeatures,
+
+    /// Whether to validate modules at all. Validation must be skipped for modules using the legacy
+    /// exception handling opcodes (`try`, `catch`, and friends) since [`wasmparser`] can't validate
+    /// them. Such modules must still enable `exceptions` in [`features`](Self::features).
+    pub validate: bool,
+
+    /// Whether to normalize function bodies before hashing so equivalent functions encoded
+    /// differently by different toolchains share blobs. See [`normalize`](crate::normalize).
+    pub normalize: bool,
+
+    /// Whether to record the order in which clients should fetch function bodies, as determined by
+    /// the module's [call graph](crate::callgraph), so that the code needed to start the module
+    /// can be fetched first.
+    pub prioritize: bool,
+
+    /// Whether to store the type, import, and global sections as blobs rather than verbatim so that
+    /// modules declaring identical sections can share their storage. See [`bundle`](crate::bundle).
+    pub share_sections: bool,
+
+    /// How to chunk the code and data sections of modules without relocation sections, which are
+    /// otherwise split into one blob per function body whose embedded indices rarely match across
+    /// builds. See [`chunker`](crate::chunker). Chunked modules can't be diffed by
+    /// [`incremental`](crate::incremental) or have their startup code prioritized.
+    pub chunking: Option<ChunkingOptions>,
+
+    /// How to chunk data segments too large to fit in a single chunk, which are otherwise stored
+    /// verbatim so that changing one byte of them changes the whole index. Chunk boundaries are
+    /// moved off of any relocations they would split, so the chunks of segments holding addresses
+    /// can be shared across builds like function bodies can. Smaller segments are stored verbatim.
+    pub data_chunking: Option<ChunkingOptions>,
+
+    /// Whether to record the names the `linking` section gives to the symbols held by each
+    /// function body and data chunk in the index, so that tools can report changes by name.
+    /// Mangled names are long so this can grow the index considerably.
+    pub symbol_names: bool,
+}
+
+impl Default for SplitOptions {
+    fn default() -> Self {
+        Self {
+            writer: WriterOptions::default(),
+            features: WasmFeatures::default(),
+            validate: true,
+            normalize: false,
+            prioritize: true,
+            share_sections: false,
+            chunking: None,
+            data_chunking: Some(ChunkingOptions::default()),
+            symbol_names: false,
+        }
+    }
+}
+
 pub fn split_module(src: &[u8]) -> anyhow::Result<SplitModuleResult> {
+    split_module_with(src, &SplitOptions::default())
+}
+
+/// Splits a core module or a component. Components are split by running every core module they
+/// embed, including those of nested components, through the regular pipeline while the component
+/// layer itself is stored verbatim.
+pub fn split_module_with(src: &[u8], options: &SplitOptions) -> anyhow::Result<SplitModuleResult> {
     let _guard = OffsetTracker::new(src);
 
+    if options.validate {
+        validate(src, options.features)?;
+    } else {
+        check_features(src, options.features)?;
+    }
+
+    if let Some(chunking) = &options.chunking {
+        chunking.validate()?;
+    }
+
+    if let Some(data_chunking) = &options.data_chunking {
+        data_chunking.validate()?;
+    }
+
+    let mut writer = WasmallWriter::new(options.writer.clone());
+    let bytes_truncated = if is_component(src) {
+        split_component_into(&mut writer, src, options)?
+    } else {
+        split_core_module_into(&mut writer, src, options)?
+    };
+
+    Ok(SplitModuleResult {
+        archive: writer.finish()?,
+        bytes_truncated,
+    })
+}
+
+// === Components === //
+
+const CORE_MODULE_SECTION_ID: u8 = 1;
+const COMPONENT_SECTION_ID: u8 = 4;
+
+/// The names of core module sections by ID, used in diagnostics.
+pub const CORE_SECTION_NAMES: [&str; 14] = [
+    "custom",
+    "type",
+    "import",
+    "function",
+    "table",
+    "memory",
+    "global",
+    "export",
+    "start",
+    "element",
+    "code",
+    "data",
+    "data count",
+    "tag",
+];
+
+/// The names of component sections by ID, used in diagnostics.
+pub const COMPONENT_SECTION_NAMES: [&str; 12] = [
+    "custom",
+    "core module",
+    "core instance",
+    "core type",
+    "component",
+    "instance",
+    "alias",
+    "type",
+    "canonical function",
+    "start",
+    "import",
+    "export",
+];
+
+/// Names a section for the duration of its processing so that diagnostics report offsets relative
+/// to it.
+fn track_section<'a>(names: &[&str], id: u8, data: &'a [u8]) -> SectionTracker<'a> {
+    match names.get(id as usize) {
+        Some(name) => SectionTracker::new(format_args!("the {name} section"), data),
+        None => SectionTracker::new(format_args!("section {id}"), data),
+    }
+}
+
+/// Like [`track_section`] but for the payloads of a core module, naming custom sections as well.
+fn track_payload<'a>(payload: &Payload<'_>, src: &'a [u8]) -> Option<SectionTracker<'a>> {
+    let (id, range) = payload.as_section()?;
+
+    Some(match payload {
+        Payload::CustomSection(reader) => SectionTracker::new(
+            format_args!("the custom section {:?}", reader.name()),
+            &src[range],
+        ),
+        _ => track_section(&CORE_SECTION_NAMES, id, &src[range]),
+    })
+}
+
+/// Whether the binary is a component rather than a core module, as indicated by the layer field
+/// of its header.
+pub fn is_component(src: &[u8]) -> bool {
+    src.len() >= 8 && src[0..4] == *b"\0asm" && src[6..8] == [0x01, 0x00]
+}
+
+/// Iterates over the sections of a module or component, yielding their IDs and contents.
+fn sections(src: &[u8]) -> impl Iterator<Item = anyhow::Result<(u8, &[u8])>> {
+    let mut cursor = ByteCursor(src.get(8..).unwrap_or_default());
+
+    std::iter::from_fn(move || {
+        (!cursor.at_eof()).then(|| {
+            let id = cursor.read_u8()?;
+            let len = cursor.read_var_u32()?;
+            let data = cursor
+                .consume(len as usize)
+                .context("section extends past the end of the binary")?;
+
+            Ok((id, data))
+        })
+    })
+}
+
+/// Determines the length of a module or component once it has been split and reassembled, which
+/// is shorter than the original if custom sections were dropped or sizes were padded.
+fn assembled_len(src: &[u8]) -> anyhow::Result<usize> {
+    let component = is_component(src);
+    let mut len = 8;
+
+    for section in sections(src) {
+        let (id, data) = section?;
+
+        let data_len = match id {
+            0 if !component => continue,
+            CORE_MODULE_SECTION_ID | COMPONENT_SECTION_ID if component => assembled_len(data)?,
+            _ => data.len(),
+        };
+
+        let mut header = LenCounter::default();
+        header.write_section_header(id, data_len)?;
+        len += header.0 + data_len;
+    }
+
+    Ok(len)
+}
+
+fn split_component_into(
+    writer: &mut WasmallWriter,
+    src: &[u8],
+    options: &SplitOptions,
+) -> anyhow::Result<usize> {
+    // Write the component's header verbatim. Unlike core modules, its version field is meaningful.
+    // Nested components are only validated along with their parent so their header may be missing.
+    let header = src.get(..8).context("component is missing its header")?;
+    writer.push_verbatim(|sink| sink.extend_from_slice(header));
+
+    let mut bytes_truncated = 0;
+
+    for (section_idx, section) in sections(src).enumerate() {
+        let (id, data) = section?;
+        let _section = track_section(&COMPONENT_SECTION_NAMES, id, data);
+
+        let nested = id == CORE_MODULE_SECTION_ID || id == COMPONENT_SECTION_ID;
+        let data_len = if nested {
+            assembled_len(data)?
+        } else {
+            data.len()
+        };
+
+        writer.push_verbatim(|sink| {
+            if nested {
+                sink.write_section_header(id, data_len)
+            } else {
+                sink.write_section(id, data)
+            }
+        })?;
+
+        // The component has already been validated as a whole.
+        let inner_options = SplitOptions {
+            validate: false,
+            ..options.clone()
+        };
+
+        bytes_truncated += match id {
+            CORE_MODULE_SECTION_ID => split_core_module_into(writer, data, &inner_options),
+            COMPONENT_SECTION_ID => split_component_into(writer, data, &inner_options),
+            _ => Ok(0),
+        }
+        .with_context(|| format!("failed to split the component's section {section_idx}"))?;
+    }
+
+    Ok(bytes_truncated)
+}
+
+// === Core Modules === //
+
+fn split_core_module_into(
+    writer: &mut WasmallWriter,
+    src: &[u8],
+    options: &SplitOptions,
+) -> anyhow::Result<usize> {
     // Collect all payloads ahead of time so we don't have to deal with the somewhat arcane parser API.
     let payloads = {
         let mut payloads = Vec::new();
@@ -34,8 +295,18 @@ pub fn split_module(src: &[u8]) -> anyhow::Result<SplitModuleResult> {
     // Maps sections to a list of their relocations.
     let mut orig_reloc_map = <Vec<Vec<RelocEntry>>>::new();
 
-    // Maps data segments to a list of ranges associated with symbols.
-    let mut data_seg_map = FxHashMap::<u32, Vec<(usize, Range<u32>)>>::default();
+    // Maps functions to the names of their symbols.
+    let mut func_names = FxHashMap::<u32, Vec<&str>>::default();
+
+    // Maps data segments to the ranges and names of their symbols.
+    let mut data_seg_map = FxHashMap::<u32, Vec<(Range<u32>, &str)>>::default();
+
+    let mut func_imports = 0;
+
+    // The number of parameters of every type and defined function, which normalization leaves in
+    // place when renumbering locals.
+    let mut type_params = Vec::<u32>::new();
+    let mut func_params = Vec::<u32>::new();
 
     {
         let mut section_index = Wrapping(usize::MAX);
@@ -45,119 +316,171 @@ pub fn split_module(src: &[u8]) -> anyhow::Result<SplitModuleResult> {
                 section_index += 1;
             }
 
+            let _section = track_payload(payload, src);
+
             match payload {
+                Payload::TypeSection(reader) => {
+                    for group in reader.clone() {
+                        for ty in group?.into_types() {
+                            type_params.push(match &ty.composite_type {
+                                CompositeType::Func(func) => func.params().len() as u32,
+                                _ => 0,
+                            });
+                        }
+                    }
+                }
+                Payload::ImportSection(reader) => {
+                    for import in reader.clone() {
+                        if matches!(import?.ty, TypeRef::Func(_)) {
+                            func_imports += 1;
+                        }
+                    }
+                }
+                Payload::FunctionSection(reader) => {
+                    for ty in reader.clone() {
+                        func_params.push(type_params.get(ty? as usize).copied().unwrap_or(0));
+                    }
+                }
                 Payload::CustomSection(payload) if payload.name() == "linking" => {
-                    let reader = LinkingSectionReader::new(payload.data(), payload.data_offset())?;
+                    let linking = LinkingSection::parse(payload.data(), payload.data_offset())?;
 
-                    for subsection in reader.subsections() {
-                        let subsection = subsection?;
+                    for (index, name) in linking.defined_funcs() {
+                        func_names.entry(index).or_default().push(name);
+                    }
 
-                        let Linking::SymbolTable(stab) = subsection else {
-                            continue;
-                        };
-
-                        for (sym_idx, info) in stab.into_iter().enumerate() {
-                            let info = info?;
-
-                            if let SymbolInfo::Data {
-                                symbol:
-                                    Some(DefinedDataSymbol {
-                                        offset,
-                                        size,
-                                        index,
-                                    }),
-                                ..
-                            } = info
-                            {
-                                data_seg_map
-                                    .entry(index)
-                                    .or_default()
-                                    .push((sym_idx, offset..(offset + size)));
-                            }
-                        }
+                    for (symbol, name) in linking.defined_data() {
+                        let range = symbol.offset..symbol.offset.saturating_add(symbol.size);
+                        data_seg_map
+                            .entry(symbol.index)
+                            .or_default()
+                            .push((range, name));
                     }
                 }
                 Payload::CustomSection(payload) if payload.name().starts_with("reloc.") => {
                     let relocs = RelocSection::parse(&mut ByteCursor(payload.data()))?;
-                    let out_vec = orig_reloc_map.ensure_index(relocs.target_section as usize);
 
-                    for reloc in relocs.entries() {
-                        out_vec.push(reloc?);
+                    // Report every damaged entry at once rather than just the first.
+                    let (entries, errors) = relocs.entries().parse_recovering();
+                    if !errors.is_empty() {
+                        let listed = errors
+                            .iter()
+                            .map(|err| format!("\n- {err}: {:#}", err.error))
+                            .collect::<String>();
+
+                        anyhow::bail!(
+                            "found {} malformed entr{} in {:?}:{listed}",
+                            errors.len(),
+                            if errors.len() == 1 { "y" } else { "ies" },
+                            payload.name(),
+                        );
                     }
+
+                    orig_reloc_map
+                        .ensure_index(relocs.target_section as usize)
+                        .extend(entries);
                 }
                 _ => {}
             }
         }
 
-        for vec in &mut orig_reloc_map {
-            vec.sort_unstable_by(|a, b| a.offset.cmp(&b.offset));
-        }
-
         for ranges in data_seg_map.values_mut() {
-            ranges.sort_unstable_by(|(_, a), (_, b)| a.start.cmp(&b.start))
+            ranges.sort_by_key(|(range, _)| range.start)
         }
     }
 
+    // Index each section's relocations by offset.
+    let orig_reloc_map = orig_reloc_map
+        .into_iter()
+        .map(RelocIndex::new)
+        .collect::<Vec<_>>();
+
+    // Modules without any relocations fall back to content-defined chunking if enabled.
+    let chunking = options.chunking.as_ref().filter(|_| {
+        !payloads.iter().any(|payload| {
+            matches!(payload, Payload::CustomSection(payload) if payload.name().starts_with("reloc."))
+        })
+    });
+
+    // Determine which function bodies are needed first.
+    let startup_order = if options.prioritize && chunking.is_none() {
+        startup_order(src).context("failed to analyze call graph")?
+    } else {
+        Vec::new()
+    };
+
     // Run a second pass to create both the blobs and the split module.
-    let mut writer = WasmallWriter::default();
     let mut bytes_truncated = 0;
     {
         // Write the magic number
-        writer.push_verbatim(|sink| {
-            #[rustfmt::skip]
-            sink.extend_from_slice(&[
-                // Magic
-                0x00, 0x61, 0x73, 0x6D,
-				// Version
-                0x01, 0x00, 0x00, 0x00,
-            ]);
-        });
+        writer.push_verbatim(|sink| sink.extend_from_slice(&CORE_MODULE_HEADER));
 
         // Write the file's sections
         let mut parser = payloads.iter().peekable();
         let mut section_idx = 0;
 
         while let Some(payload) = parser.next() {
+            let _section = track_payload(payload, src);
+
             match payload {
+                Payload::CodeSectionStart { .. } | Payload::DataSection(_)
+                    if chunking.is_some() =>
+                {
+                    let (section_id, section_range) = payload.as_section().unwrap();
+                    let data = &src[section_range];
+
+                    writer
+                        .push_verbatim(|sink| sink.write_section_header(section_id, data.len()))?;
+
+                    // The chunks cover every function body so their entries are never visited.
+                    for chunk in chunking.unwrap().chunks(data) {
+                        writer.push_blob(&[], &[], &data[chunk]);
+                    }
+
+                    while let Some(Payload::CodeSectionEntry(_)) = parser.peek() {
+                        parser.next();
+                    }
+                }
                 Payload::CodeSectionStart { range, count, .. } => {
                     let section_start = range.start;
 
-                    assert_eq!(
-                        ByteCursor(&src[section_start..]).read_var_u32().unwrap(),
-                        *count
-                    );
+                    // Function bodies start right after the `count` field.
+                    let mut count_cursor = ByteCursor(&src[section_start..]);
+                    assert_eq!(count_cursor.read_var_u32().unwrap(), *count);
+                    let mut next_entry_start = src.len() - count_cursor.0.len();
 
                     // Write section header verbatim
                     writer.push_verbatim::<anyhow::Result<_>>(|sink| {
-                        // Write section ID
-                        sink.push(10);
-
-                        // Write section length. Note that the `range` already contains the `count`
-                        // field.
-                        sink.write_var_u32(
-                            u32::try_from(range.len()).context("code section is too big")?,
-                        );
+                        // Note that the `range` already contains the `count` field.
+                        sink.write_section_header(10, range.len())
+                            .context("code section is too big")?;
 
-                        // Write the count field
-                        sink.write_var_u32(*count);
+                        // Write the count field verbatim since it may be padded.
+                        sink.extend_from_slice(&src[section_start..next_entry_start]);
 
                         Ok(())
                     })?;
 
                     // Determine the set of relocations affecting this section
-                    let relocations = &orig_reloc_map[section_idx];
-                    let mut relocations_idx = 0;
+                    let empty_relocations = RelocIndex::default();
+                    let relocations = orig_reloc_map
+                        .get(section_idx)
+                        .unwrap_or(&empty_relocations);
+
+                    // Every function body becomes exactly one blob so the startup order maps
+                    // directly onto the blobs we're about to push.
+                    let first_blob = writer.blob_count();
+                    writer.prioritize(startup_order.iter().map(|&body| first_blob + body as usize));
+
+                    let mut func_index = func_imports;
 
                     // For each function...
                     while let Some(Payload::CodeSectionEntry(func)) = parser.peek() {
                         parser.next();
 
-                        // Extend the range byte view to include the size field in the function
-                        let func_range = func.range();
-                        let size_field_byte_count =
-                            len_of(|w| w.write_var_u32(func_range.len() as u32));
-
-                        let func_range = (func_range.start - size_field_byte_count)..func_range.end;
+                        // Extend the range byte view to include the size field in the function.
+                        // The size field may be padded so we can't infer its width from its value.
+                        let func_range = next_entry_start..func.range().end;
+                        next_entry_start = func_range.end;
 
                         // Determine the range of this code entry relative to the section start
                         let entry_start = (func_range.start - section_start) as u32;
@@ -165,103 +488,198 @@ pub fn split_module(src: &[u8]) -> anyhow::Result<SplitModuleResult> {
                         let entry_data = &src[func_range];
 
                         // Collect the set of relocations affecting this function
-                        let relocations = {
-                            while relocations
-                                .get(relocations_idx)
-                                .is_some_and(|reloc| reloc.offset < entry_start)
+                        let relocations = relocations
+                            .entries_in_range(entry_start..entry_end)
+                            .collect::<RelocSet>();
+
+                        validate_relocations(relocations.rewrite_kinds(), entry_data.len())
+                            .with_context(|| {
+                                format!(
+                                    "invalid relocations for the function body at offset {:#x}",
+                                    section_start + entry_start as usize,
+                                )
+                            })?;
+
+                        let (mut local_relocations, local_relocation_values) =
+                            localize_relocations(
+                                &relocations,
+                                entry_data,
+                                section_start + entry_start as usize,
+                            )?;
+
+                        // Normalize the function if requested. Functions small enough to be inlined
+                        // aren't deduplicated anyways.
+                        let params = func_params
+                            .get((func_index - func_imports) as usize)
+                            .copied()
+                            .unwrap_or(0);
+
+                        let normalized = (options.normalize
+                            && entry_data.len() >= options.writer.inline_threshold)
+                            .then(|| {
+                                let offsets = local_relocations
+                                    .iter()
+                                    .map(|reloc| reloc.offset)
+                                    .collect::<Vec<_>>();
+
+                                normalize_func_body(entry_data, params, &offsets)
+                            })
+                            .flatten();
+
+                        // Complete the blob
+                        if let Some(normalized) = normalized {
+                            for (reloc, offset) in
+                                local_relocations.iter_mut().zip(normalized.reloc_offsets)
                             {
-                                relocations_idx += 1;
+                                reloc.offset = offset;
                             }
 
-                            let start = relocations_idx;
+                            writer.push_blob_with_edits(
+                                &local_relocations,
+                                &local_relocation_values,
+                                &normalized.data,
+                                &normalized.edits,
+                            );
+                        } else {
+                            writer.push_blob(
+                                &local_relocations,
+                                &local_relocation_values,
+                                entry_data,
+                            );
+                        }
 
-                            while relocations
-                                .get(relocations_idx)
-                                .is_some_and(|reloc| reloc.offset <= entry_end)
-                            {
-                                relocations_idx += 1;
+                        if options.symbol_names {
+                            let blob = writer.blob_count() - 1;
+                            for name in func_names.get(&func_index).into_iter().flatten() {
+                                writer.name_blob(blob, SymbolKind::Function, name);
                             }
+                        }
+                        func_index += 1;
+                    }
+                }
+                Payload::DataSection(reader) if options.data_chunking.is_some() => {
+                    let data_chunking = options.data_chunking.as_ref().unwrap();
+                    let (section_id, section_range) = payload.as_section().unwrap();
+                    let section_start = section_range.start;
 
-                            &relocations[start..relocations_idx]
-                        };
+                    writer.push_verbatim(|sink| {
+                        sink.write_section_header(section_id, section_range.len())
+                    })?;
 
-                        // Transform the blob's globally-indexed relocations into locally-indexed
-                        // relocations for the `WasmallWriter`.
-                        let mut local_relocations = Vec::new();
-                        let mut local_relocation_values = Vec::new();
-                        {
-                            // Map global symbol indexes to their blob-local index. Note that a global
-                            // symbol may be assigned to multiple different blob-local indices over
-                            // the course of a blob because, sometimes, the relocation system lies.
-                            let mut global_to_local_sym_map = <FxHashMap<u32, usize>>::default();
-
-                            for reloc in relocations {
-                                // Determine the value this relocation takes on.
-                                let reloc_ty = reloc.ty;
-                                let reloc_value = reloc_ty
-                                    .rewrite_kind()
-                                    .read(&mut ByteCursor(
-                                        &entry_data[(reloc.offset - entry_start) as usize..],
-                                    ))?
-                                    // Undo the addend.
-                                    .as_u32_neg_offset(reloc.addend.unwrap_or(0));
-
-                                // Determine whether we can use the old local symbol, generating a
-                                // new local symbol if not.
-                                let local_sym = match global_to_local_sym_map.entry(reloc.index) {
-                                    hash_map::Entry::Occupied(entry) => {
-                                        let entry = entry.into_mut();
-                                        let entry_value = local_relocation_values[*entry];
-
-                                        if reloc_value != entry_value {
-                                            *entry = local_relocation_values.len();
-                                            local_relocation_values.push(reloc_value);
-                                        }
-
-                                        *entry
-                                    }
-                                    hash_map::Entry::Vacant(entry) => {
-                                        let entry_idx = local_relocation_values.len();
-                                        entry.insert(entry_idx);
-                                        local_relocation_values.push(reloc_value);
-                                        entry_idx
-                                    }
-                                };
-
-                                let local_sym = u32::try_from(local_sym).unwrap();
-
-                                local_relocations.push(RelocEntry {
-                                    offset: reloc.offset - entry_start,
-                                    index: local_sym,
-                                    ..*reloc
-                                });
+                    // Determine the set of relocations affecting this section
+                    let empty_relocations = RelocIndex::default();
+                    let relocations = orig_reloc_map
+                        .get(section_idx)
+                        .unwrap_or(&empty_relocations);
+
+                    // Segments which fit in a single chunk are copied verbatim along with the
+                    // headers of the segments we chunk.
+                    let mut verbatim_start = section_start;
+
+                    for (segment_index, segment) in reader.clone().into_iter().enumerate() {
+                        let segment = segment?;
+                        if segment.data.len() <= data_chunking.max_size {
+                            continue;
+                        }
+
+                        // A segment's contents are the last thing in its encoding.
+                        let data_start = segment.range.end - segment.data.len();
+                        let rel_start = (data_start - section_start) as u32;
+
+                        let segment_relocations = relocations
+                            .entries_in_range(rel_start..rel_start + segment.data.len() as u32)
+                            .collect::<RelocSet>();
+
+                        validate_relocations(
+                            segment_relocations.rewrite_kinds(),
+                            segment.data.len(),
+                        )
+                        .with_context(|| {
+                            format!("invalid relocations for the data segment at offset {data_start:#x}")
+                        })?;
+
+                        writer.push_verbatim(|sink| {
+                            sink.extend_from_slice(&src[verbatim_start..data_start])
+                        });
+                        verbatim_start = segment.range.end;
+
+                        // Chunks never split a relocation so each can be relocated on its own.
+                        let spans = segment_relocations
+                            .rewrite_kinds()
+                            .map(|(offset, kind)| offset..offset + kind.width());
+
+                        for chunk in data_chunking.chunks_avoiding(segment.data, spans) {
+                            let chunk_relocations = relocations
+                                .entries_in_range(
+                                    rel_start + chunk.start as u32..rel_start + chunk.end as u32,
+                                )
+                                .collect::<RelocSet>();
+
+                            let (local_relocations, local_relocation_values) =
+                                localize_relocations(
+                                    &chunk_relocations,
+                                    &segment.data[chunk.clone()],
+                                    data_start + chunk.start,
+                                )?;
+
+                            writer.push_blob(
+                                &local_relocations,
+                                &local_relocation_values,
+                                &segment.data[chunk.clone()],
+                            );
+
+                            // Symbols spanning several chunks name each of them, and empty ones
+                            // name the chunk they start in.
+                            if options.symbol_names {
+                                let blob = writer.blob_count() - 1;
+                                let symbols = data_seg_map
+                                    .get(&(segment_index as u32))
+                                    .into_iter()
+                                    .flatten()
+                                    .filter(|(range, _)| {
+                                        let end =
+                                            range.end.max(range.start.saturating_add(1)) as usize;
+                                        (range.start as usize) < chunk.end && chunk.start < end
+                                    });
+
+                                for (_, name) in symbols {
+                                    writer.name_blob(blob, SymbolKind::Data, name);
+                                }
                             }
                         }
+                    }
 
-                        // Complete the blob
-                        writer.push_blob(&local_relocations, &local_relocation_values, entry_data);
+                    if verbatim_start < section_range.end {
+                        writer.push_verbatim(|sink| {
+                            sink.extend_from_slice(&src[verbatim_start..section_range.end])
+                        });
                     }
                 }
-                // TODO: Handle data segments as well
+                // Everything else, including the exception handling proposal's tag section, is
+                // copied verbatim. Function bodies are never decoded so `try`, `catch`, and `throw`
+                // need no special treatment beyond their `TagIndexLeb` relocations.
                 payload => {
                     if let Some((section_id, section_range)) = payload.as_section() {
                         if matches!(payload, Payload::CustomSection(_)) {
                             bytes_truncated += section_range.len();
-                        } else {
-                            writer.push_verbatim::<anyhow::Result<_>>(|sink| {
-                                // Write section ID
-                                sink.push(section_id);
-
-                                // Write section length
-                                sink.write_var_u32(
-                                    u32::try_from(section_range.len())
-                                        .context("section is too big")?,
-                                );
-
-                                // Write section data
-                                sink.extend_from_slice(&src[section_range]);
+                        } else if options.share_sections
+                            && matches!(
+                                payload,
+                                Payload::TypeSection(_)
+                                    | Payload::ImportSection(_)
+                                    | Payload::GlobalSection(_)
+                            )
+                        {
+                            // These sections are never relocated so their contents can be stored
+                            // as-is.
+                            writer.push_verbatim(|sink| {
+                                sink.write_section_header(section_id, section_range.len())
+                            })?;
 
-                                Ok(())
+                            writer.push_blob(&[], &[], &src[section_range]);
+                        } else {
+                            writer.push_verbatim(|sink| {
+                                sink.write_section(section_id, &src[section_range])
                             })?;
                         }
                     }
@@ -274,8 +692,80 @@ pub fn split_module(src: &[u8]) -> anyhow::Result<SplitModuleResult> {
         }
     }
 
-    Ok(SplitModuleResult {
-        archive: writer.finish(),
-        bytes_truncated,
-    })
+    Ok(bytes_truncated)
+}
+
+/// Transforms the globally-indexed relocations affecting `data` into the locally-indexed
+/// relocations and values expected by the `WasmallWriter`. `data_offset` locates `data` in the
+/// module for diagnostics.
+fn localize_relocations(
+    relocations: &RelocSet,
+    data: &[u8],
+    data_offset: usize,
+) -> anyhow::Result<(Vec<RelocEntry>, Vec<u32>)> {
+    let mut local_relocations = Vec::new();
+    let mut local_relocation_values = Vec::new();
+
+    // Map global symbol indexes to their blob-local index. Note that a global symbol may be
+    // assigned to multiple different blob-local indices over the course of a blob because,
+    // sometimes, the relocation system lies.
+    let mut global_to_local_sym_map = <FxHashMap<u32, usize>>::default();
+
+    for reloc in relocations.entries() {
+        // Determine the value this relocation takes on.
+        let reloc_ty = reloc.ty;
+        let value = reloc_ty
+            .rewrite_kind()
+            .read(&mut ByteCursor(&data[reloc.offset as usize..]))?;
+
+        // Undo the addend. Indices store 32-bit values so the upper bits of 64-bit relocations
+        // can't be reconstructed. See `RelocEntryType`.
+        let addend = reloc.addend.unwrap_or(0);
+        let reloc_value = if reloc_ty.is_64_bit() {
+            let value = value.as_u64_neg_offset(addend);
+
+            u32::try_from(value).with_context(|| {
+                format!(
+                    "{reloc_ty:?} relocation at offset {:#x} has the value {value:#x}, which \
+                     doesn't fit in 32 bits; wasmall indices store relocated values as 32-bit \
+                     integers so relocations may only refer to the first 4 GiB of a 64-bit \
+                     memory or table",
+                    data_offset + reloc.offset as usize,
+                )
+            })?
+        } else {
+            value.as_u32_neg_offset(addend)
+        };
+
+        // Determine whether we can use the old local symbol, generating a new local symbol if
+        // not.
+        let local_sym = match global_to_local_sym_map.entry(reloc.index) {
+            hash_map::Entry::Occupied(entry) => {
+                let entry = entry.into_mut();
+                let entry_value = local_relocation_values[*entry];
+
+                if reloc_value != entry_value {
+                    *entry = local_relocation_values.len();
+                    local_relocation_values.push(reloc_value);
+                }
+
+                *entry
+            }
+            hash_map::Entry::Vacant(entry) => {
+                let entry_idx = local_relocation_values.len();
+                entry.insert(entry_idx);
+                local_relocation_values.push(reloc_value);
+                entry_idx
+            }
+        };
+
+        let local_sym = u32::try_from(local_sym).unwrap();
+
+        local_relocations.push(RelocEntry {
+            index: local_sym,
+            ..*reloc
+        });
+    }
+
+    Ok((local_relocations, local_relocation_values))
 }
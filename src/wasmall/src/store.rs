@@ -0,0 +1,519 @@
+//! Abstractions over the places blobs can be fetched from during assembly and stored in.
+//!
+//! Every store implements [`BlobSource`] so that modules can be assembled out of it. Stores which
+//! can also be written to implement [`BlobStore`], which lets [`store_archive`] skip blobs they
+//! already have, [`fetch_missing`] fill them in from a slower source ahead of assembly, and
+//! [`collect_garbage`] drop the blobs no module refers to anymore. [`verify_archive`] audits a store
+//! for the blobs of a module without assembling it.
+//!
+//! Blobs are addressed by the hash of their stored form, so assembly checks every blob it consumes
+//! against its hash before splicing it in. Sources which already check them can say so through
+//! [`BlobSource::verifies_blobs`].
+
+use std::{
+    borrow::Cow,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use blake3::Hash;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::{
+    coder::{BlobEncoding, WasmallArchive, WasmallMod, WasmallModSeg, WasmallModSegBlob},
+    util::{write_atomic, LenCounter},
+};
+
+// === BlobSource === //
+
+/// A source of blob data addressed by the hash of its contents.
+pub trait BlobSource {
+    /// Fetches the blob with the specified hash, returning `None` if the source doesn't have it.
+    fn get_blob(&self, hash: Hash) -> anyhow::Result<Option<Cow<'_, [u8]>>>;
+
+    /// Whether the source checks every blob it returns against its hash, which lets assembly skip
+    /// checking them again.
+    fn verifies_blobs(&self) -> bool {
+        false
+    }
+}
+
+impl<T: ?Sized + BlobSource> BlobSource for &'_ T {
+    fn get_blob(&self, hash: Hash) -> anyhow::Result<Option<Cow<'_, [u8]>>> {
+        (**self).get_blob(hash)
+    }
+
+    fn verifies_blobs(&self) -> bool {
+        (**self).verifies_blobs()
+    }
+}
+
+/// Checks that the stored form of a blob matches its hash.
+pub fn verify_blob(hash: Hash, stored: &[u8]) -> anyhow::Result<()> {
+    let actual_hash = blake3::hash(stored);
+    anyhow::ensure!(
+        actual_hash == hash,
+        "blob {hash} is corrupted; got hash {actual_hash}",
+    );
+    Ok(())
+}
+
+impl BlobSource for WasmallArchive {
+    fn get_blob(&self, hash: Hash) -> anyhow::Result<Option<Cow<'_, [u8]>>> {
+        Ok(self
+            .hashes
+            .get(&hash)
+            .map(|range| Cow::Borrowed(&self.blob_buf[range.clone()])))
+    }
+}
+
+// === BlobStore === //
+
+/// A [`BlobSource`] which blobs can be written to and enumerated.
+pub trait BlobStore: BlobSource {
+    fn has_blob(&self, hash: Hash) -> anyhow::Result<bool>;
+
+    /// Stores a blob under its hash. Storing a blob which is already present does nothing.
+    fn put_blob(&mut self, hash: Hash, data: &[u8]) -> anyhow::Result<()>;
+
+    /// Lists the hashes of every blob in the store, in no particular order.
+    fn list_blobs(&self) -> anyhow::Result<Vec<Hash>>;
+
+    /// Removes every blob for which `is_live` returns `false`, returning the number removed.
+    fn retain_blobs(&mut self, is_live: &mut dyn FnMut(Hash) -> bool) -> anyhow::Result<usize>;
+}
+
+impl<T: ?Sized + BlobStore> BlobStore for &'_ mut T {
+    fn has_blob(&self, hash: Hash) -> anyhow::Result<bool> {
+        (**self).has_blob(hash)
+    }
+
+    fn put_blob(&mut self, hash: Hash, data: &[u8]) -> anyhow::Result<()> {
+        (**self).put_blob(hash, data)
+    }
+
+    fn list_blobs(&self) -> anyhow::Result<Vec<Hash>> {
+        (**self).list_blobs()
+    }
+
+    fn retain_blobs(&mut self, is_live: &mut dyn FnMut(Hash) -> bool) -> anyhow::Result<usize> {
+        (**self).retain_blobs(is_live)
+    }
+}
+
+impl<T: ?Sized + BlobSource> BlobSource for &'_ mut T {
+    fn get_blob(&self, hash: Hash) -> anyhow::Result<Option<Cow<'_, [u8]>>> {
+        (**self).get_blob(hash)
+    }
+
+    fn verifies_blobs(&self) -> bool {
+        (**self).verifies_blobs()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StoreReport {
+    /// The number of blobs written to the store.
+    pub blobs_written: usize,
+
+    /// The number of blobs skipped because the store already had them.
+    pub blobs_skipped: usize,
+
+    /// The total size of the blobs written.
+    pub bytes_written: usize,
+}
+
+/// Writes the blobs of an archive which `store` doesn't have yet.
+pub fn store_archive(
+    store: &mut (impl ?Sized + BlobStore),
+    archive: &WasmallArchive,
+) -> anyhow::Result<StoreReport> {
+    let mut report = StoreReport::default();
+
+    for (&hash, range) in &archive.hashes {
+        if store.has_blob(hash)? {
+            report.blobs_skipped += 1;
+            continue;
+        }
+
+        store.put_blob(hash, &archive.blob_buf[range.clone()])?;
+        report.blobs_written += 1;
+        report.bytes_written += range.len();
+    }
+
+    Ok(report)
+}
+
+/// Copies the blobs `module` refers to which `store` doesn't have yet from `upstream` so that the
+/// module can be assembled from `store` alone. Returns the number of blobs fetched.
+pub fn fetch_missing(
+    module: &WasmallMod<'_>,
+    store: &mut (impl ?Sized + BlobStore),
+    upstream: &(impl ?Sized + BlobSource),
+) -> anyhow::Result<usize> {
+    let mut fetched = 0;
+
+    for hash in module.fetch_order()? {
+        if store.has_blob(hash)? {
+            continue;
+        }
+
+        let data = upstream
+            .get_blob(hash)?
+            .with_context(|| format!("missing blob {hash}"))?;
+
+        // A corrupted blob would otherwise be served from the cache indefinitely.
+        if !upstream.verifies_blobs() {
+            verify_blob(hash, &data)?;
+        }
+
+        store.put_blob(hash, &data)?;
+        fetched += 1;
+    }
+
+    Ok(fetched)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GcReport {
+    /// The number of distinct blobs referred to by the retained modules.
+    pub live_blobs: usize,
+
+    /// The number of blobs removed from the store.
+    pub blobs_removed: usize,
+}
+
+/// Removes every blob from `store` which none of the `modules` refer to.
+pub fn collect_garbage<'a>(
+    store: &mut (impl ?Sized + BlobStore),
+    modules: impl IntoIterator<Item = &'a WasmallMod<'a>>,
+) -> anyhow::Result<GcReport> {
+    let mut live = FxHashSet::default();
+
+    for (i, module) in modules.into_iter().enumerate() {
+        for hash in module.blob_hashes() {
+            live.insert(hash.with_context(|| format!("failed to list the blobs of module {i}"))?);
+        }
+    }
+
+    let blobs_removed = store.retain_blobs(&mut |hash| live.contains(&hash))?;
+
+    Ok(GcReport {
+        live_blobs: live.len(),
+        blobs_removed,
+    })
+}
+
+// === Auditing === //
+
+#[derive(Debug, Default)]
+pub struct AuditReport {
+    /// The number of distinct blobs checked, including the dictionary.
+    pub blobs_checked: usize,
+
+    /// The blobs the store doesn't have, in fetch order.
+    pub missing: Vec<Hash>,
+
+    /// The blobs the store has but which can't be used, along with what is wrong with them.
+    pub damaged: Vec<(Hash, anyhow::Error)>,
+}
+
+impl AuditReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.damaged.is_empty()
+    }
+
+    /// Turns the problems found into a single error listing all of them.
+    pub fn into_result(self) -> anyhow::Result<()> {
+        if self.is_ok() {
+            return Ok(());
+        }
+
+        let listed = self
+            .missing
+            .iter()
+            .map(|hash| format!("\n- blob {hash} is missing"))
+            .chain(
+                self.damaged
+                    .iter()
+                    .map(|(hash, err)| format!("\n- blob {hash} is damaged: {err:#}")),
+            )
+            .collect::<String>();
+
+        anyhow::bail!(
+            "found {} problem(s) with the archive's blobs:{listed}",
+            self.missing.len() + self.damaged.len(),
+        );
+    }
+}
+
+/// Checks that `store` has every blob `module` needs and that each of them matches its hash,
+/// decodes, and expands to what the index expects, all without assembling the module. The index's
+/// merkle root is checked too if it records one. Blobs of [locked](WasmallMod::is_locked) indices
+/// can only be checked against their hashes.
+pub fn verify_archive(
+    module: &WasmallMod<'_>,
+    store: &(impl ?Sized + BlobSource),
+) -> anyhow::Result<AuditReport> {
+    if module.merkle_root().is_some() {
+        module.verify_merkle_root()?;
+    }
+
+    // Each blob is only fetched and decoded once, however many segments use it.
+    let mut uses = FxHashMap::<Hash, Vec<WasmallModSegBlob<'_>>>::default();
+    for segment in module.segments() {
+        if let WasmallModSeg::Blob(segment) = segment? {
+            uses.entry(segment.hash()).or_default().push(segment);
+        }
+    }
+
+    let mut report = AuditReport::default();
+    let mut dictionary_ok = true;
+
+    for hash in module.fetch_order()? {
+        report.blobs_checked += 1;
+
+        let stored = match store.get_blob(hash) {
+            Ok(Some(stored)) => stored,
+            Ok(None) => {
+                dictionary_ok &= module.dictionary_hash() != Some(hash);
+                report.missing.push(hash);
+                continue;
+            }
+            Err(err) => {
+                dictionary_ok &= module.dictionary_hash() != Some(hash);
+                report.damaged.push((hash, err));
+                continue;
+            }
+        };
+
+        let check = || {
+            if !store.verifies_blobs() {
+                verify_blob(hash, &stored)?;
+            }
+
+            if module.is_locked() {
+                return Ok(());
+            }
+
+            for segment in uses.get(&hash).into_iter().flatten() {
+                // Blobs aren't to blame for a broken dictionary, which is reported on its own.
+                if segment.encoding() == BlobEncoding::ZstdDict && !dictionary_ok {
+                    continue;
+                }
+
+                module.with_stored_blob(segment, Cow::Borrowed(&stored), store, |blob| {
+                    segment.write(&blob, &mut LenCounter::default())
+                })?;
+            }
+
+            anyhow::Ok(())
+        };
+
+        if let Err(err) = check() {
+            dictionary_ok &= module.dictionary_hash() != Some(hash);
+            report.damaged.push((hash, err));
+        }
+    }
+
+    Ok(report)
+}
+
+// === MemoryBlobSource === //
+
+/// An in-memory set of blobs, typically fetched ahead of time from some slower source.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBlobSource<'a> {
+    blobs: FxHashMap<Hash, Cow<'a, [u8]>>,
+}
+
+impl<'a> MemoryBlobSource<'a> {
+    pub fn insert(&mut self, hash: Hash, data: Cow<'a, [u8]>) {
+        self.blobs.insert(hash, data);
+    }
+
+    pub fn len(&self) -> usize {
+        self.blobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blobs.is_empty()
+    }
+}
+
+impl BlobSource for MemoryBlobSource<'_> {
+    fn get_blob(&self, hash: Hash) -> anyhow::Result<Option<Cow<'_, [u8]>>> {
+        Ok(self.blobs.get(&hash).map(|data| Cow::Borrowed(&data[..])))
+    }
+}
+
+impl BlobStore for MemoryBlobSource<'_> {
+    fn has_blob(&self, hash: Hash) -> anyhow::Result<bool> {
+        Ok(self.blobs.contains_key(&hash))
+    }
+
+    fn put_blob(&mut self, hash: Hash, data: &[u8]) -> anyhow::Result<()> {
+        self.blobs
+            .entry(hash)
+            .or_insert_with(|| Cow::Owned(data.to_vec()));
+
+        Ok(())
+    }
+
+    fn list_blobs(&self) -> anyhow::Result<Vec<Hash>> {
+        Ok(self.blobs.keys().copied().collect())
+    }
+
+    fn retain_blobs(&mut self, is_live: &mut dyn FnMut(Hash) -> bool) -> anyhow::Result<usize> {
+        let len = self.blobs.len();
+        self.blobs.retain(|&hash, _| is_live(hash));
+        Ok(len - self.blobs.len())
+    }
+}
+
+// === DirBlobStore === //
+
+/// A blob store which keeps every blob in its own file, sharded into subdirectories by the first
+/// byte of its hash.
+#[derive(Debug, Clone)]
+pub struct DirBlobStore {
+    root: PathBuf,
+}
+
+impl DirBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn blob_path(&self, hash: Hash) -> PathBuf {
+        let hash = hash.to_hex();
+        let mut blob_path = self.root.clone();
+        blob_path.push(&hash[0..2]);
+        blob_path.push(&hash[2..]);
+        blob_path
+    }
+
+    pub fn put_blob(&self, hash: Hash, data: &[u8]) -> anyhow::Result<()> {
+        let blob_path = self.blob_path(hash);
+        let parent = blob_path.parent().unwrap();
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create blob directory {parent:?}"))?;
+
+        write_atomic(&blob_path, data, "blob", false)
+    }
+
+    pub fn remove_blob(&self, hash: Hash) -> anyhow::Result<()> {
+        let blob_path = self.blob_path(hash);
+
+        match fs::remove_file(&blob_path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err).with_context(|| format!("failed to remove blob {blob_path:?}")),
+        }
+    }
+
+    /// Lists the hashes of every blob in the store. Files which don't look like blobs are ignored.
+    pub fn hashes(&self) -> anyhow::Result<Vec<Hash>> {
+        let mut hashes = Vec::new();
+
+        let shards = match fs::read_dir(&self.root) {
+            Ok(shards) => shards,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(hashes),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("failed to read blob directory {:?}", self.root))
+            }
+        };
+
+        for shard in shards {
+            let shard = shard?;
+            let Some(prefix) = shard.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+
+            if prefix.len() != 2 || !shard.file_type()?.is_dir() {
+                continue;
+            }
+
+            for blob in fs::read_dir(shard.path())? {
+                let blob = blob?;
+                let Some(rest) = blob.file_name().to_str().map(str::to_owned) else {
+                    continue;
+                };
+
+                if let Ok(hash) = Hash::from_hex(format!("{prefix}{rest}")) {
+                    hashes.push(hash);
+                }
+            }
+        }
+
+        Ok(hashes)
+    }
+}
+
+impl BlobSource for DirBlobStore {
+    fn get_blob(&self, hash: Hash) -> anyhow::Result<Option<Cow<'_, [u8]>>> {
+        let blob_path = self.blob_path(hash);
+
+        let data = match fs::read(&blob_path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to read blob at {blob_path:?}"))
+            }
+        };
+
+        // We have to read the entire file anyways so we might as well verify it eagerly.
+        let actual_hash = blake3::hash(&data);
+        anyhow::ensure!(
+            actual_hash == hash,
+            "blob at {blob_path:?} is corrupted; expected hash {hash}, got {actual_hash}",
+        );
+
+        Ok(Some(Cow::Owned(data)))
+    }
+
+    fn verifies_blobs(&self) -> bool {
+        true
+    }
+}
+
+impl BlobStore for DirBlobStore {
+    fn has_blob(&self, hash: Hash) -> anyhow::Result<bool> {
+        let blob_path = self.blob_path(hash);
+        blob_path
+            .try_exists()
+            .with_context(|| format!("failed to check for blob at {blob_path:?}"))
+    }
+
+    fn put_blob(&mut self, hash: Hash, data: &[u8]) -> anyhow::Result<()> {
+        if self.has_blob(hash)? {
+            return Ok(());
+        }
+
+        DirBlobStore::put_blob(self, hash, data)
+    }
+
+    fn list_blobs(&self) -> anyhow::Result<Vec<Hash>> {
+        self.hashes()
+    }
+
+    fn retain_blobs(&mut self, is_live: &mut dyn FnMut(Hash) -> bool) -> anyhow::Result<usize> {
+        let mut removed = 0;
+
+        for hash in self.hashes()? {
+            if !is_live(hash) {
+                self.remove_blob(hash)?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
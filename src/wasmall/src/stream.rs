@@ -0,0 +1,331 @@
+//! Incremental assembly of modules whose blobs arrive over time.
+//!
+//! A [`StreamingDecoder`] is created from an index and fed blobs in whatever order they arrive. It
+//! expands segments as soon as every blob before them is available, so the assembled prefix of the
+//! module grows while the rest is still downloading and can be handed to a streaming compiler. As
+//! the prefix grows, the decoder reports the sections and function bodies which have been completed.
+
+use std::{borrow::Cow, collections::VecDeque, ops::Range};
+
+use anyhow::Context;
+use blake3::Hash;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::{
+    coder::{BlobEncoding, WasmallMod, WasmallModSeg},
+    splitter::is_component,
+    store::{verify_blob, BlobSource},
+    util::ByteCursor,
+};
+
+// === StreamEvent === //
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum StreamEvent {
+    /// A section has been fully assembled. The range covers the section's ID and size too.
+    Section { id: u8, range: Range<usize> },
+
+    /// A function body has been fully assembled, which happens before its code section completes.
+    /// `index` counts from the code section's first body and so excludes imported functions. The
+    /// range excludes the body's size.
+    FunctionBody { index: u32, range: Range<usize> },
+}
+
+/// Finds the sections and function bodies completed by a growing prefix of a module.
+#[derive(Debug, Clone, Default)]
+struct PrefixScanner {
+    pos: usize,
+    state: ScanState,
+}
+
+#[derive(Debug, Clone, Default)]
+enum ScanState {
+    #[default]
+    Header,
+    Sections {
+        component: bool,
+    },
+    Code {
+        section: Range<usize>,
+        index: u32,
+        remaining: u32,
+    },
+}
+
+impl PrefixScanner {
+    fn scan(&mut self, prefix: &[u8], events: &mut VecDeque<StreamEvent>) {
+        // Reads which run off the end of the prefix just mean we have to wait for more of it.
+        while let Some(()) = self.step(prefix, events) {}
+    }
+
+    fn step(&mut self, prefix: &[u8], events: &mut VecDeque<StreamEvent>) -> Option<()> {
+        let mut cursor = ByteCursor(prefix.get(self.pos..)?);
+
+        match &mut self.state {
+            ScanState::Header => {
+                cursor.consume(8).ok()?;
+                self.pos = 8;
+                self.state = ScanState::Sections {
+                    component: is_component(prefix),
+                };
+            }
+            ScanState::Sections { component } => {
+                let start = self.pos;
+                let id = cursor.read_u8().ok()?;
+                let len = cursor.read_var_u32().ok()? as usize;
+                let body_start = prefix.len() - cursor.0.len();
+                let end = body_start + len;
+
+                // Core modules have their code sections broken down into individual functions.
+                if id == 10 && !*component {
+                    let remaining = cursor.read_var_u32().ok()?;
+                    self.pos = prefix.len() - cursor.0.len();
+                    self.state = ScanState::Code {
+                        section: start..end,
+                        index: 0,
+                        remaining,
+                    };
+                    return Some(());
+                }
+
+                if prefix.len() < end {
+                    return None;
+                }
+
+                events.push_back(StreamEvent::Section {
+                    id,
+                    range: start..end,
+                });
+                self.pos = end;
+            }
+            ScanState::Code {
+                section,
+                index,
+                remaining,
+            } => {
+                if *remaining == 0 {
+                    if prefix.len() < section.end {
+                        return None;
+                    }
+
+                    events.push_back(StreamEvent::Section {
+                        id: 10,
+                        range: section.clone(),
+                    });
+                    self.pos = section.end;
+                    self.state = ScanState::Sections { component: false };
+                    return Some(());
+                }
+
+                let len = cursor.read_var_u32().ok()? as usize;
+                let start = prefix.len() - cursor.0.len();
+                let end = start + len;
+                if prefix.len() < end {
+                    return None;
+                }
+
+                events.push_back(StreamEvent::FunctionBody {
+                    index: *index,
+                    range: start..end,
+                });
+                self.pos = end;
+                *index += 1;
+                *remaining -= 1;
+            }
+        }
+
+        Some(())
+    }
+}
+
+// === StreamingDecoder === //
+
+/// Blobs which have been fed to the decoder but are still needed by unexpanded segments, along
+/// with how many of those segments need them.
+#[derive(Debug, Default)]
+struct PendingBlobs(FxHashMap<Hash, (Vec<u8>, usize)>);
+
+impl BlobSource for PendingBlobs {
+    fn get_blob(&self, hash: Hash) -> anyhow::Result<Option<Cow<'_, [u8]>>> {
+        Ok(self.0.get(&hash).map(|(data, _)| Cow::Borrowed(&data[..])))
+    }
+
+    // Blobs are checked as they are fed.
+    fn verifies_blobs(&self) -> bool {
+        true
+    }
+}
+
+/// Assembles a module as its blobs arrive. See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct StreamingDecoder<'a> {
+    module: WasmallMod<'a>,
+    segments: Vec<WasmallModSeg<'a>>,
+    required: Vec<Hash>,
+    missing: FxHashSet<Hash>,
+    uses: FxHashMap<Hash, usize>,
+    pending: PendingBlobs,
+    next_segment: usize,
+    out: Vec<u8>,
+    scanner: PrefixScanner,
+    events: VecDeque<StreamEvent>,
+}
+
+impl<'a> StreamingDecoder<'a> {
+    /// Starts assembling `module`, which must already be unlocked if its blobs are encrypted.
+    /// Segments which don't need any blobs are expanded immediately.
+    pub fn new(module: WasmallMod<'a>) -> anyhow::Result<Self> {
+        let segments = module.segments().collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut required = Vec::new();
+        let mut uses = FxHashMap::<Hash, usize>::default();
+
+        // Any number of blobs may need the dictionary so it's kept until the decoder is dropped.
+        if let Some(hash) = module.dictionary_hash() {
+            required.push(hash);
+            uses.insert(hash, 1);
+        }
+
+        for segment in &segments {
+            if let WasmallModSeg::Blob(segment) = segment {
+                let count = uses.entry(segment.hash()).or_default();
+                if *count == 0 {
+                    required.push(segment.hash());
+                }
+                *count += 1;
+            }
+        }
+
+        let mut me = Self {
+            module,
+            segments,
+            missing: required.iter().copied().collect(),
+            required,
+            uses,
+            pending: PendingBlobs::default(),
+            next_segment: 0,
+            // The lengths the index declares aren't checked until their blobs arrive so the output
+            // grows as segments are expanded instead of being reserved up front.
+            out: Vec::new(),
+            scanner: PrefixScanner::default(),
+            events: VecDeque::new(),
+        };
+        me.advance()?;
+
+        Ok(me)
+    }
+
+    pub fn module(&self) -> &WasmallMod<'a> {
+        &self.module
+    }
+
+    /// Every blob the module needs, deduplicated and in the order assembly consumes them, starting
+    /// with the dictionary if there is one. Fetching them in this order lets the assembled prefix
+    /// grow as quickly as possible.
+    pub fn required_blobs(&self) -> &[Hash] {
+        &self.required
+    }
+
+    /// The required blobs which haven't been fed yet, in the order assembly consumes them.
+    pub fn missing_blobs(&self) -> impl Iterator<Item = Hash> + '_ {
+        self.required
+            .iter()
+            .copied()
+            .filter(|hash| self.missing.contains(hash))
+    }
+
+    /// Whether every segment has been expanded.
+    pub fn is_complete(&self) -> bool {
+        self.next_segment == self.segments.len()
+    }
+
+    /// The assembled prefix of the module.
+    pub fn assembled(&self) -> &[u8] {
+        &self.out
+    }
+
+    /// Accepts the stored form of a blob, expanding every segment it unblocks. Returns `false`
+    /// without doing anything if the module doesn't need the blob or it was already fed, so blobs
+    /// can be forwarded straight from a transport which may duplicate them.
+    pub fn feed_blob(&mut self, hash: Hash, data: Vec<u8>) -> anyhow::Result<bool> {
+        if !self.missing.contains(&hash) {
+            return Ok(false);
+        }
+
+        verify_blob(hash, &data)?;
+
+        self.missing.remove(&hash);
+        self.pending.0.insert(hash, (data, self.uses[&hash]));
+        self.advance()?;
+
+        Ok(true)
+    }
+
+    /// Takes the oldest section or function body completed since the last call.
+    pub fn next_event(&mut self) -> Option<StreamEvent> {
+        self.events.pop_front()
+    }
+
+    /// Takes every section and function body completed since the last call.
+    pub fn drain_events(&mut self) -> impl Iterator<Item = StreamEvent> + '_ {
+        self.events.drain(..)
+    }
+
+    /// Returns the reassembled module once every blob has been fed, checking it against the module
+    /// hash recorded in the index.
+    pub fn finish(self) -> anyhow::Result<Vec<u8>> {
+        anyhow::ensure!(
+            self.is_complete(),
+            "cannot finish assembly with {} blob(s) still missing",
+            self.missing.len(),
+        );
+
+        self.module.verify_module(&self.out)?;
+        Ok(self.out)
+    }
+
+    /// Expands segments until reaching one whose blob hasn't arrived yet.
+    fn advance(&mut self) -> anyhow::Result<()> {
+        let start = self.next_segment;
+
+        while let Some(segment) = self.segments.get(self.next_segment) {
+            let (blob, dictionary) = match segment {
+                WasmallModSeg::Blob(segment) => (
+                    Some(segment.hash()),
+                    (segment.encoding() == BlobEncoding::ZstdDict)
+                        .then(|| self.module.dictionary_hash())
+                        .flatten(),
+                ),
+                _ => (None, None),
+            };
+
+            if blob
+                .into_iter()
+                .chain(dictionary)
+                .any(|hash| !self.pending.0.contains_key(&hash))
+            {
+                break;
+            }
+
+            self.module
+                .assemble_segment(segment, &self.pending, &mut self.out)
+                .with_context(|| format!("failed to assemble segment {}", self.next_segment))?;
+            self.next_segment += 1;
+
+            // Drop blobs as soon as nothing else needs them.
+            if let Some(hash) = blob {
+                let (_, uses) = self.pending.0.get_mut(&hash).unwrap();
+                *uses -= 1;
+                if *uses == 0 {
+                    self.pending.0.remove(&hash);
+                }
+            }
+        }
+
+        if self.next_segment != start {
+            self.scanner.scan(&self.out, &mut self.events);
+        }
+
+        Ok(())
+    }
+}
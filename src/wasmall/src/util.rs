@@ -1,12 +1,19 @@
 use core::fmt;
 use std::{
     any::type_name,
+    cell::RefCell,
     collections::BTreeMap,
-    io::ErrorKind,
+    fs,
+    io::{ErrorKind, Write},
     marker::PhantomData,
     mem,
     ops::Range,
-    sync::{Mutex, MutexGuard},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
 };
 
 use anyhow::Context;
@@ -73,12 +80,74 @@ pub trait SliceExt<T>: ExtensionFor<[T]> {
 
 impl<T> SliceExt<T> for [T] {}
 
+// === Parallelism === //
+
+/// Maps every item of `items` in order, on the rayon thread pool if `parallel` is set and the
+/// `parallel` feature is enabled.
+pub fn map_ordered<T, R>(parallel: bool, items: &[T], f: impl Fn(&T) -> R + Send + Sync) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+{
+    #[cfg(feature = "parallel")]
+    if parallel {
+        use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+        return items.par_iter().map(f).collect();
+    }
+
+    let _ = parallel;
+    items.iter().map(f).collect()
+}
+
+// === Files === //
+
+/// Derives a temporary path next to `path` which no other process or thread will use.
+pub fn unique_temp_path(path: &Path) -> PathBuf {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    path.with_extension(format!("{}.{id}.tmp", process::id()))
+}
+
+/// Replaces the file at `path` with `data` such that readers never observe a partial write. The
+/// data is written to a [`unique_temp_path`] first, so concurrent writers of the same file don't
+/// clobber each other, and moved into place once complete. `what` names the file in errors.
+///
+/// With `sync`, the data is flushed to disk before it is moved into place.
+pub fn write_atomic(path: &Path, data: &[u8], what: &str, sync: bool) -> anyhow::Result<()> {
+    let temp_path = unique_temp_path(path);
+    let res = fs::File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            if sync {
+                file.sync_data()?;
+            }
+            Ok(())
+        })
+        .with_context(|| format!("failed to write {what} to {temp_path:?}"))
+        .and_then(|()| {
+            fs::rename(&temp_path, path)
+                .with_context(|| format!("failed to move {what} into place at {path:?}"))
+        });
+
+    if res.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    res
+}
+
 // === OffsetTracker === //
 
+/// Registers a slice so that pointers into it can be reported as offsets from its start.
+///
+/// Slices overlapping an already tracked slice, such as those tracked concurrently by another
+/// thread, are left untracked rather than rejected.
 #[derive(Debug)]
 pub struct OffsetTracker<'a> {
     _ty: PhantomData<&'a [()]>,
     range: Range<usize>,
+    tracked: bool,
 }
 
 impl OffsetTracker<'_> {
@@ -91,56 +160,73 @@ impl OffsetTracker<'_> {
         }
     }
 
-    pub fn new_raw(range: Range<usize>) -> Self {
-        let mut slices = Self::get_slices();
-
-        // Ensure that the range is properly formed.
-        assert!(range.start <= range.end);
-
-        // See if there are any ranges that start before our end.
-        if let Some((_, &closest_end)) = slices.range(..range.end).next() {
-            // If there is, ensure that it ends before we begin. This is sufficient to ensure that
-            // all ranges starting before us don't overlap with us because all subsequent range ends
-            // are less than or equal to this range's end by the no-overlap invariant.
-            assert!(closest_end <= range.start);
+    // Inserts `range` unless it overlaps a tracked range, returning whether it was inserted.
+    fn try_insert(slices: &mut BTreeMap<usize, usize>, range: &Range<usize>) -> bool {
+        // See if there are any ranges that start before our end. If there is, ensure that it ends
+        // before we begin. This is sufficient to ensure that all ranges starting before us don't
+        // overlap with us because all subsequent range ends are less than or equal to this range's
+        // end by the no-overlap invariant.
+        if let Some((_, &closest_end)) = slices.range(..range.end).next_back() {
+            if closest_end > range.start {
+                return false;
+            }
         }
 
         // See if we're about to collide into the start of the next range.
         if let Some((&closest_start, _)) = slices.range(range.start..).next() {
             // ibid
-            assert!(range.end <= closest_start);
+            if range.end > closest_start {
+                return false;
+            }
         }
 
         // Our range does not overlap so let's insert it.
         slices.insert(range.start, range.end);
+        true
+    }
+
+    pub fn new_raw(range: Range<usize>) -> Self {
+        // Ensure that the range is properly formed.
+        assert!(range.start <= range.end);
+
+        let tracked = Self::try_insert(&mut Self::get_slices(), &range);
 
         Self {
             _ty: PhantomData,
             range,
+            tracked,
         }
     }
 
     pub fn lookup_parent_raw(addr: usize) -> Option<Range<usize>> {
-        Self::get_slices()
-            .range(..addr)
-            .next()
+        Self::lookup_parent_in(&Self::get_slices(), addr)
+    }
+
+    fn lookup_parent_in(slices: &BTreeMap<usize, usize>, addr: usize) -> Option<Range<usize>> {
+        slices
+            .range(..=addr)
+            .next_back()
             .map(|(&start, &end)| start..end)
             .filter(|range| range.contains(&addr))
     }
 
     pub fn cast_lifetime<'b>(self) -> OffsetTracker<'b> {
         let range = self.range.clone();
+        let tracked = self.tracked;
         std::mem::forget(self);
         OffsetTracker {
             _ty: PhantomData,
             range,
+            tracked,
         }
     }
 }
 
 impl Drop for OffsetTracker<'_> {
     fn drop(&mut self) {
-        Self::get_slices().remove(&self.range.start);
+        if self.tracked {
+            Self::get_slices().remove(&self.range.start);
+        }
     }
 }
 
@@ -152,12 +238,101 @@ impl OffsetTracker<'_> {
         Self::new_raw(range)
     }
 
+    /// Tracks `slice` unless it already lies within a tracked slice, in which case offsets are
+    /// reported relative to that slice instead.
+    pub fn new_if_untracked<T>(slice: &[T]) -> Option<Self> {
+        let range = slice.as_ptr_range();
+        let range = (range.start as usize)..(range.end as usize);
+
+        // Look up and insert under one lock so that threads tracking the same slice don't race.
+        let mut slices = Self::get_slices();
+        if range.is_empty()
+            || Self::lookup_parent_in(&slices, range.start).is_some()
+            || !Self::try_insert(&mut slices, &range)
+        {
+            return None;
+        }
+
+        Some(Self {
+            _ty: PhantomData,
+            range,
+            tracked: true,
+        })
+    }
+
     pub fn index_in_parent<T>(value: *const T) -> Option<usize> {
         let value = value as usize;
         Self::lookup_parent_raw(value).map(|range| (value - range.start) / mem::size_of::<T>())
     }
 }
 
+// === SectionTracker === //
+
+/// Names a region of a tracked slice so that offsets within it are additionally reported relative
+/// to the start of the region. Regions may nest, in which case the innermost one is reported.
+///
+/// Regions are only named on the thread which tracked them so that concurrent parses don't contend
+/// for them or name each other's regions.
+#[derive(Debug)]
+pub struct SectionTracker<'a> {
+    // Trackers must be dropped on the thread which created them.
+    _ty: PhantomData<(&'a [()], *const ())>,
+    id: u64,
+}
+
+struct TrackedSection {
+    id: u64,
+    range: Range<usize>,
+    name: String,
+}
+
+thread_local! {
+    static SECTIONS: RefCell<(u64, Vec<TrackedSection>)> = const { RefCell::new((0, Vec::new())) };
+}
+
+impl SectionTracker<'_> {
+    pub fn new<T>(name: impl fmt::Display, slice: &[T]) -> Self {
+        let range = slice.as_ptr_range();
+        let range = (range.start as usize)..(range.end as usize);
+        let name = name.to_string();
+
+        let id = SECTIONS.with_borrow_mut(|(next_id, sections)| {
+            let id = *next_id;
+            *next_id += 1;
+            sections.push(TrackedSection { id, range, name });
+            id
+        });
+
+        Self {
+            _ty: PhantomData,
+            id,
+        }
+    }
+
+    /// Finds the innermost section containing `addr`, returning its name and start address.
+    pub fn lookup_raw(addr: usize) -> Option<(String, usize)> {
+        SECTIONS.with_borrow(|(_, sections)| {
+            sections
+                .iter()
+                .filter(|section| section.range.contains(&addr))
+                .min_by_key(|section| section.range.len())
+                .map(|section| (section.name.clone(), section.range.start))
+        })
+    }
+}
+
+impl Drop for SectionTracker<'_> {
+    fn drop(&mut self) {
+        // The thread-local may already be gone if the tracker lives in another thread-local.
+        let _ = SECTIONS.try_with(|sections| {
+            sections
+                .borrow_mut()
+                .1
+                .retain(|section| section.id != self.id)
+        });
+    }
+}
+
 pub struct FmtOffset<T>(pub *const T);
 
 impl<T> Copy for FmtOffset<T> {}
@@ -170,11 +345,18 @@ impl<T> Clone for FmtOffset<T> {
 
 impl<T> fmt::Display for FmtOffset<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if let Some(offset) = OffsetTracker::index_in_parent(self.0) {
-            offset.fmt(f)
-        } else {
-            f.write_str("offset unavailable")
+        let Some(offset) = OffsetTracker::index_in_parent(self.0) else {
+            return f.write_str("an unknown offset");
+        };
+
+        write!(f, "offset {offset:#x}")?;
+
+        if let Some((name, start)) = SectionTracker::lookup_raw(self.0 as usize) {
+            let relative = (self.0 as usize - start) / mem::size_of::<T>();
+            write!(f, ", within {name} at +{relative:#x}")?;
         }
+
+        Ok(())
     }
 }
 
@@ -184,12 +366,23 @@ impl<T> fmt::Display for FmtOffset<T> {
 #[derive(Debug, Clone)]
 pub struct ByteCursor<'a>(pub &'a [u8]);
 
+/// A position in a [`ByteCursor`] which the cursor can later be rolled back to.
+#[derive(Debug, Copy, Clone)]
+#[must_use]
+pub struct Savepoint<'a>(&'a [u8]);
+
 impl<'a> ByteCursor<'a> {
     // Debug
     pub fn global_offset(&self) -> FmtOffset<u8> {
         FmtOffset(self.0.as_ptr())
     }
 
+    /// The absolute offset of the cursor within its tracked slice, if it is part of one. See
+    /// [`OffsetTracker`].
+    pub fn offset(&self) -> Option<usize> {
+        OffsetTracker::index_in_parent(self.0.as_ptr())
+    }
+
     // Primitives
     pub fn at_eof(&self) -> bool {
         self.0.is_empty()
@@ -198,7 +391,7 @@ impl<'a> ByteCursor<'a> {
     pub fn peek(&self, count: usize) -> anyhow::Result<&'a [u8]> {
         anyhow::ensure!(
             self.0.len() >= count,
-            "failed to read {count} byte{} at position {}",
+            "failed to read {count} byte{} at {}",
             if count == 1 { "" } else { "s" },
             self.global_offset(),
         );
@@ -208,7 +401,7 @@ impl<'a> ByteCursor<'a> {
     pub fn consume(&mut self, count: usize) -> anyhow::Result<&'a [u8]> {
         anyhow::ensure!(
             self.0.len() >= count,
-            "failed to read {count} byte{} at position {}",
+            "failed to read {count} byte{} at {}",
             if count == 1 { "" } else { "s" },
             self.global_offset(),
         );
@@ -226,18 +419,50 @@ impl<'a> ByteCursor<'a> {
         self.consume(count).unwrap();
     }
 
+    // Savepoints
+    fn debug_check_savepoint(&self, savepoint: Savepoint<'a>) {
+        debug_assert!(
+            savepoint.0.len() >= self.0.len()
+                && savepoint.0.as_ptr_range().end == self.0.as_ptr_range().end,
+            "savepoint belongs to a different cursor or lies ahead of it"
+        );
+    }
+
+    pub fn savepoint(&self) -> Savepoint<'a> {
+        Savepoint(self.0)
+    }
+
+    /// Moves the cursor back to `savepoint`, un-reading everything read since.
+    pub fn rollback(&mut self, savepoint: Savepoint<'a>) {
+        self.debug_check_savepoint(savepoint);
+        self.0 = savepoint.0;
+    }
+
+    /// Keeps everything read since `savepoint`, returning the bytes which were read.
+    pub fn commit(&self, savepoint: Savepoint<'a>) -> &'a [u8] {
+        self.debug_check_savepoint(savepoint);
+        &savepoint.0[..savepoint.0.len() - self.0.len()]
+    }
+
+    /// Runs `f` as a transaction, rolling the cursor back if it fails.
     pub fn lookahead<R>(
         &mut self,
         f: impl FnOnce(&mut Self) -> anyhow::Result<R>,
     ) -> anyhow::Result<R> {
-        let mut fork = self.clone();
-        let res = f(&mut fork);
-        if res.is_ok() {
-            *self = fork;
+        let savepoint = self.savepoint();
+        let res = f(self);
+        if res.is_err() {
+            self.rollback(savepoint);
         }
         res
     }
 
+    /// Speculatively runs `f`, rolling the cursor back and returning `None` if it fails so that
+    /// another parse can be attempted from the same position.
+    pub fn attempt<R>(&mut self, f: impl FnOnce(&mut Self) -> anyhow::Result<R>) -> Option<R> {
+        self.lookahead(f).ok()
+    }
+
     pub fn lookahead_annotated<R>(
         &mut self,
         what: impl fmt::Display,
@@ -245,18 +470,16 @@ impl<'a> ByteCursor<'a> {
     ) -> anyhow::Result<R> {
         let start = self.global_offset();
         self.lookahead(f)
-            .map_err(|err| err.context(format!("failed to parse {what} starting at {start}")))
+            .map_err(|err| err.context(format!("failed to parse {what} at {start}")))
     }
 
     pub fn get_slice_read<R>(
         &mut self,
         f: impl FnOnce(&mut Self) -> anyhow::Result<R>,
     ) -> anyhow::Result<(R, &'a [u8])> {
-        let orig_remainder = self.0;
+        let savepoint = self.savepoint();
         let res = f(self)?;
-        let new_remainder = self.0;
-        let read = &orig_remainder[0..(orig_remainder.len() - new_remainder.len())];
-        Ok((res, read))
+        Ok((res, self.commit(savepoint)))
     }
 
     // Specified readers
@@ -280,45 +503,77 @@ impl<'a> ByteCursor<'a> {
         self.consume_arr().map(i64::from_le_bytes)
     }
 
-    pub fn read_var_u32(&mut self) -> anyhow::Result<u32> {
-        let mut reader = self.0.limit_len(5);
-        let start_len = reader.len();
+    pub fn read_f32(&mut self) -> anyhow::Result<f32> {
+        self.consume_arr().map(f32::from_le_bytes)
+    }
 
-        match leb128::read::unsigned(&mut reader) {
-            Ok(v) => {
-                self.advance(start_len - reader.len());
-                Ok(v as u32)
+    pub fn read_f64(&mut self) -> anyhow::Result<f64> {
+        self.consume_arr().map(f64::from_le_bytes)
+    }
+
+    /// Decodes the LEB128 at the start of the buffer without consuming it, returning its 7-bit
+    /// groups and its length in bytes. Only succeeds for single-byte encodings and for encodings of
+    /// at most `max_len` bytes which can be decoded from a single word load. Everything else,
+    /// including every malformed encoding, is left to the byte-by-byte decoder so that errors are
+    /// reported the same way regardless of which path was taken.
+    #[inline(always)]
+    fn peek_var_fast(&self, max_len: usize) -> Option<(u64, usize)> {
+        let &first = self.0.first()?;
+        if first & 0x80 == 0 {
+            return Some((u64::from(first), 1));
+        }
+
+        if max_len > 8 {
+            return None;
+        }
+
+        // Branching on each byte rather than finding the terminator with a bit scan lets the CPU
+        // speculate past runs of equally long encodings, such as padded relocation targets.
+        let word = u64::from_le_bytes(*self.0.first_chunk::<8>()?);
+        let mut value = 0;
+        for i in 0..max_len {
+            let byte = word >> (8 * i);
+            value |= (byte & 0x7F) << (7 * i);
+
+            if byte & 0x80 == 0 {
+                return Some((value, i + 1));
             }
-            Err(leb128::read::Error::Overflow) => Err(anyhow::anyhow!(
-                "LEB128-encoded `u32` starting at {} would overflow",
-                self.global_offset()
-            )),
-            Err(leb128::read::Error::IoError(err)) if err.kind() == ErrorKind::UnexpectedEof => {
-                Err(anyhow::anyhow!(
-                    "not enough bytes to read LEB128-encoded `u32` starting at {}",
-                    self.global_offset()
-                ))
+        }
+
+        None
+    }
+
+    /// Reads an unsigned LEB128 with at most `bits` significant bits, rejecting encodings which are
+    /// longer than necessary to hold that many bits or whose value doesn't fit.
+    #[inline(always)]
+    fn read_var_unsigned(&mut self, ty: &str, bits: u32) -> anyhow::Result<u64> {
+        if let Some((v, len)) = self.peek_var_fast(bits.div_ceil(7) as usize) {
+            if bits == 64 || v >> bits == 0 {
+                self.0 = &self.0[len..];
+                return Ok(v);
             }
-            _ => unreachable!(),
         }
+
+        self.read_var_unsigned_slow(ty, bits)
     }
 
-    pub fn read_var_i32(&mut self) -> anyhow::Result<i32> {
-        let mut reader = self.0.limit_len(5);
+    #[cold]
+    fn read_var_unsigned_slow(&mut self, ty: &str, bits: u32) -> anyhow::Result<u64> {
+        let mut reader = self.0.limit_len(bits.div_ceil(7) as usize);
         let start_len = reader.len();
 
-        match leb128::read::signed(&mut reader) {
-            Ok(v) => {
+        match leb128::read::unsigned(&mut reader) {
+            Ok(v) if bits == 64 || v >> bits == 0 => {
                 self.advance(start_len - reader.len());
-                Ok(v as i32)
+                Ok(v)
             }
-            Err(leb128::read::Error::Overflow) => Err(anyhow::anyhow!(
-                "LEB128-encoded `i32` starting at {} would overflow",
+            Ok(_) | Err(leb128::read::Error::Overflow) => Err(anyhow::anyhow!(
+                "LEB128-encoded `{ty}` starting at {} would overflow",
                 self.global_offset()
             )),
             Err(leb128::read::Error::IoError(err)) if err.kind() == ErrorKind::UnexpectedEof => {
                 Err(anyhow::anyhow!(
-                    "not enough bytes to read LEB128-encoded `i32` starting at {}",
+                    "not enough bytes to read LEB128-encoded `{ty}` at {}",
                     self.global_offset()
                 ))
             }
@@ -326,45 +581,41 @@ impl<'a> ByteCursor<'a> {
         }
     }
 
-    pub fn read_var_u64(&mut self) -> anyhow::Result<u64> {
-        let mut reader = self.0.limit_len(10);
-        let start_len = self.0.len();
+    /// Reads a signed LEB128 with at most `bits` significant bits, like
+    /// [`read_var_unsigned`](Self::read_var_unsigned).
+    #[inline(always)]
+    fn read_var_signed(&mut self, ty: &str, bits: u32) -> anyhow::Result<i64> {
+        if let Some((v, len)) = self.peek_var_fast(bits.div_ceil(7) as usize) {
+            // Sign-extend from the last group read.
+            let unused = 64 - 7 * len as u32;
+            let v = ((v << unused) as i64) >> unused;
 
-        match leb128::read::unsigned(&mut reader) {
-            Ok(v) => {
-                self.advance(start_len - reader.len());
-                Ok(v)
+            if bits == 64 || (v >> (bits - 1) == 0 || v >> (bits - 1) == -1) {
+                self.0 = &self.0[len..];
+                return Ok(v);
             }
-            Err(leb128::read::Error::Overflow) => Err(anyhow::anyhow!(
-                "LEB128-encoded `u64` starting at {} would overflow",
-                self.global_offset()
-            )),
-            Err(leb128::read::Error::IoError(err)) if err.kind() == ErrorKind::UnexpectedEof => {
-                Err(anyhow::anyhow!(
-                    "not enough bytes to read LEB128-encoded `u64` starting at {}",
-                    self.global_offset()
-                ))
-            }
-            _ => unreachable!(),
         }
+
+        self.read_var_signed_slow(ty, bits)
     }
 
-    pub fn read_var_i64(&mut self) -> anyhow::Result<i64> {
-        let mut reader = self.0.limit_len(10);
-        let start_len = self.0.len();
+    #[cold]
+    fn read_var_signed_slow(&mut self, ty: &str, bits: u32) -> anyhow::Result<i64> {
+        let mut reader = self.0.limit_len(bits.div_ceil(7) as usize);
+        let start_len = reader.len();
 
         match leb128::read::signed(&mut reader) {
-            Ok(v) => {
+            Ok(v) if bits == 64 || (v >> (bits - 1) == 0 || v >> (bits - 1) == -1) => {
                 self.advance(start_len - reader.len());
                 Ok(v)
             }
-            Err(leb128::read::Error::Overflow) => Err(anyhow::anyhow!(
-                "LEB128-encoded `i64` starting at {} would overflow",
+            Ok(_) | Err(leb128::read::Error::Overflow) => Err(anyhow::anyhow!(
+                "LEB128-encoded `{ty}` starting at {} would overflow",
                 self.global_offset()
             )),
             Err(leb128::read::Error::IoError(err)) if err.kind() == ErrorKind::UnexpectedEof => {
                 Err(anyhow::anyhow!(
-                    "not enough bytes to read LEB128-encoded `i64` starting at {}",
+                    "not enough bytes to read LEB128-encoded `{ty}` at {}",
                     self.global_offset()
                 ))
             }
@@ -372,6 +623,45 @@ impl<'a> ByteCursor<'a> {
         }
     }
 
+    #[inline]
+    pub fn read_var_u32(&mut self) -> anyhow::Result<u32> {
+        self.read_var_unsigned("u32", 32).map(|v| v as u32)
+    }
+
+    #[inline]
+    pub fn read_var_i32(&mut self) -> anyhow::Result<i32> {
+        self.read_var_signed("i32", 32).map(|v| v as i32)
+    }
+
+    #[inline]
+    pub fn read_var_u64(&mut self) -> anyhow::Result<u64> {
+        self.read_var_unsigned("u64", 64)
+    }
+
+    #[inline]
+    pub fn read_var_i64(&mut self) -> anyhow::Result<i64> {
+        self.read_var_signed("i64", 64)
+    }
+
+    /// Reads a signed 33-bit LEB128, as used by block types to encode type indices.
+    pub fn read_var_s33(&mut self) -> anyhow::Result<i64> {
+        self.read_var_signed("s33", 33)
+    }
+
+    /// Skips over a LEB128 of at most 10 bytes without checking whether its value fits any
+    /// particular type, as when recovering from a malformed one.
+    pub fn skip_var(&mut self) -> anyhow::Result<()> {
+        let len = self
+            .0
+            .iter()
+            .take(10)
+            .position(|byte| byte & 0x80 == 0)
+            .with_context(|| format!("no terminated LEB128 at {}", self.global_offset()))?;
+
+        self.advance(len + 1);
+        Ok(())
+    }
+
     //
 
     pub fn read_expecting_width<R>(
@@ -402,6 +692,10 @@ impl<'a> ByteCursor<'a> {
     pub fn read_var_i64_full(&mut self) -> anyhow::Result<i64> {
         self.read_expecting_width(10, Self::read_var_i64)
     }
+
+    pub fn read_var_s33_full(&mut self) -> anyhow::Result<i64> {
+        self.read_expecting_width(5, Self::read_var_s33)
+    }
 }
 
 // ByteSliceExt
@@ -425,6 +719,15 @@ pub trait ByteSliceExt: ExtensionFor<[u8]> {
 impl ByteSliceExt for [u8] {}
 
 // ByteParse
+/// Derives [`ByteParse`] for structs whose fields are read one after the other. See
+/// [`wasmall_derive::ByteParse`] for the supported field attributes.
+pub use wasmall_derive::ByteParse;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use anyhow;
+}
+
 pub trait ByteParse<'a>: Sized {
     type Out;
 
@@ -433,31 +736,143 @@ pub trait ByteParse<'a>: Sized {
     }
 
     fn parse_naked(buf: &mut ByteCursor<'a>) -> anyhow::Result<Self::Out>;
+
+    /// Skips past an element which failed to parse, returning whether its extent could be
+    /// determined regardless. Lists of elements which can be skipped can keep parsing past them with
+    /// [`ByteParseList::parse_recovering`]. By default, no element can be skipped.
+    fn skip_malformed(buf: &mut ByteCursor<'a>) -> bool {
+        let _ = buf;
+        false
+    }
 }
 
+/// Parses elements one after the other until the end of the buffer or until a limit on their count
+/// is reached. Iteration stops after the first error, which is annotated with the index and offset
+/// of the offending element.
 pub struct ByteParseList<'a, P> {
     _ty: PhantomData<fn() -> P>,
     cursor: ByteCursor<'a>,
+    start_len: usize,
+    index: usize,
+    limit: Option<usize>,
+    failed: bool,
 }
 
 impl<'a, P> ByteParseList<'a, P> {
     pub fn new(cursor: ByteCursor<'a>) -> Self {
         Self {
             _ty: PhantomData,
+            start_len: cursor.0.len(),
             cursor,
+            index: 0,
+            limit: None,
+            failed: false,
+        }
+    }
+
+    /// Parses at most `count` elements, as is the case for lists prefixed by their length.
+    pub fn with_count(cursor: ByteCursor<'a>, count: usize) -> Self {
+        Self {
+            limit: Some(count),
+            ..Self::new(cursor)
         }
     }
 
     pub fn cursor(&self) -> ByteCursor<'a> {
         self.cursor.clone()
     }
+
+    /// The index of the next element.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The offset of the next element relative to the start of the list.
+    pub fn offset(&self) -> usize {
+        self.start_len - self.cursor.0.len()
+    }
+
+    fn is_done(&self) -> bool {
+        self.failed || self.cursor.at_eof() || self.limit.is_some_and(|limit| self.index >= limit)
+    }
+}
+
+impl<'a, P: ByteParse<'a>> ByteParseList<'a, P> {
+    fn next_element(&mut self) -> Option<Result<P::Out, ListError>> {
+        if self.is_done() {
+            return None;
+        }
+
+        let index = self.index;
+        let offset = self.offset();
+        self.index += 1;
+
+        Some(P::parse(&mut self.cursor).map_err(|error| ListError {
+            index,
+            offset,
+            error,
+        }))
+    }
+
+    /// Parses every element, skipping past malformed elements whose extent can be determined
+    /// regardless. Returns the elements which parsed along with the errors of those which didn't.
+    /// Parsing stops at the first element which can't be skipped, whose error is reported last.
+    pub fn parse_recovering(mut self) -> (Vec<P::Out>, Vec<ListError>) {
+        let mut elements = Vec::new();
+        let mut errors = Vec::new();
+
+        while let Some(element) = self.next_element() {
+            match element {
+                Ok(element) => elements.push(element),
+                Err(err) => {
+                    errors.push(err);
+
+                    if !P::skip_malformed(&mut self.cursor) {
+                        self.failed = true;
+                    }
+                }
+            }
+        }
+
+        (elements, errors)
+    }
 }
 
 impl<'a, P: ByteParse<'a>> Iterator for ByteParseList<'a, P> {
     type Item = anyhow::Result<P::Out>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        (!self.cursor.at_eof()).then(|| P::parse(&mut self.cursor))
+        let element = self.next_element()?;
+        self.failed |= element.is_err();
+        Some(element.map_err(anyhow::Error::from))
+    }
+}
+
+/// An element of a [`ByteParseList`] which failed to parse.
+#[derive(Debug)]
+pub struct ListError {
+    /// The index of the element in its list.
+    pub index: usize,
+
+    /// The offset of the element relative to the start of its list.
+    pub offset: usize,
+
+    pub error: anyhow::Error,
+}
+
+impl fmt::Display for ListError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to parse element {} at +{:#x} into the list",
+            self.index, self.offset
+        )
+    }
+}
+
+impl std::error::Error for ListError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.error.as_ref())
     }
 }
 
@@ -490,6 +905,10 @@ impl<P> Clone for ByteParseList<'_, P> {
         Self {
             _ty: PhantomData,
             cursor: self.cursor.clone(),
+            start_len: self.start_len,
+            index: self.index,
+            limit: self.limit,
+            failed: self.failed,
         }
     }
 }
@@ -516,6 +935,39 @@ impl ByteParse<'_> for VarI32 {
     }
 }
 
+#[non_exhaustive]
+pub struct VarU64;
+
+impl ByteParse<'_> for VarU64 {
+    type Out = u64;
+
+    fn parse_naked(buf: &mut ByteCursor<'_>) -> anyhow::Result<Self::Out> {
+        buf.read_var_u64()
+    }
+}
+
+#[non_exhaustive]
+pub struct VarI64;
+
+impl ByteParse<'_> for VarI64 {
+    type Out = i64;
+
+    fn parse_naked(buf: &mut ByteCursor<'_>) -> anyhow::Result<Self::Out> {
+        buf.read_var_i64()
+    }
+}
+
+#[non_exhaustive]
+pub struct VarS33;
+
+impl ByteParse<'_> for VarS33 {
+    type Out = i64;
+
+    fn parse_naked(buf: &mut ByteCursor<'_>) -> anyhow::Result<Self::Out> {
+        buf.read_var_s33()
+    }
+}
+
 #[non_exhaustive]
 pub struct VarByteVec;
 
@@ -528,17 +980,105 @@ impl<'a> ByteParse<'a> for VarByteVec {
     }
 }
 
+// === SectionWalker === //
+
+/// Follows the sections of an assembled module or component while its segments are walked in
+/// order, without assembling it.
+#[derive(Debug, Clone)]
+pub struct SectionWalker {
+    header_left: usize,
+    section: Option<u8>,
+    remaining: usize,
+}
+
+impl Default for SectionWalker {
+    fn default() -> Self {
+        Self {
+            header_left: 8,
+            section: None,
+            remaining: 0,
+        }
+    }
+}
+
+impl SectionWalker {
+    /// The ID of the section the walk is in, or `None` while in the header.
+    pub fn section(&self) -> Option<u8> {
+        self.section.filter(|_| self.header_left == 0)
+    }
+
+    /// Takes the leading bytes of verbatim `data` which belong to a single section, along with
+    /// that section's ID or `None` for the header. Section headers belong to the section they
+    /// introduce.
+    pub fn take<'a>(&mut self, data: &mut &'a [u8]) -> anyhow::Result<(Option<u8>, &'a [u8])> {
+        if self.header_left > 0 {
+            let (header, rest) = data.split_at(self.header_left.min(data.len()));
+            self.header_left -= header.len();
+            *data = rest;
+            return Ok((None, header));
+        }
+
+        let mut cursor = ByteCursor(data);
+        if self.remaining == 0 {
+            let id = cursor.read_u8()?;
+            self.remaining = cursor.read_var_u32()? as usize;
+            self.section = Some(id);
+        }
+
+        let header_len = data.len() - cursor.0.len();
+        let content_len = self.remaining.min(cursor.0.len());
+        self.remaining -= content_len;
+
+        let (taken, rest) = data.split_at(header_len + content_len);
+        *data = rest;
+        Ok((self.section, taken))
+    }
+
+    /// Accounts for a blob expanding to `len` bytes, returning the ID of the section it lies in.
+    pub fn take_blob(&mut self, len: usize) -> anyhow::Result<Option<u8>> {
+        anyhow::ensure!(
+            self.header_left == 0 && len <= self.remaining,
+            "blob extends past the end of its section"
+        );
+
+        self.remaining -= len;
+        Ok(self.section)
+    }
+}
+
 // === Writing === //
 
 pub trait BufWriter {
+    /// The number of bytes written so far.
+    fn position(&self) -> usize;
+
     fn push(&mut self, v: u8) {
         self.extend(&[v]);
     }
 
     fn extend(&mut self, v: &[u8]);
+
+    /// Hints that at least `additional` more bytes are about to be written.
+    fn reserve(&mut self, additional: usize) {
+        let _ = additional;
+    }
+
+    /// Runs `f` on the writer, returning the range of positions it wrote to.
+    fn with_span(&mut self, f: impl FnOnce(&mut Self)) -> Range<usize>
+    where
+        Self: Sized,
+    {
+        let start = self.position();
+        f(self);
+        start..self.position()
+    }
 }
 
 impl BufWriter for Vec<u8> {
+    fn position(&self) -> usize {
+        self.len()
+    }
+
     fn push(&mut self, v: u8) {
         self.push(v)
     }
@@ -546,6 +1086,10 @@ impl BufWriter for Vec<u8> {
     fn extend(&mut self, v: &[u8]) {
         self.extend_from_slice(v)
     }
+
+    fn reserve(&mut self, additional: usize) {
+        self.reserve(additional)
+    }
 }
 
 pub trait Leb128WriteExt: BufWriter {
@@ -569,15 +1113,30 @@ pub trait Leb128WriteExt: BufWriter {
         self.extend(&v.to_le_bytes());
     }
 
+    fn write_f32(&mut self, v: f32) {
+        self.extend(&v.to_le_bytes());
+    }
+
+    fn write_f64(&mut self, v: f64) {
+        self.extend(&v.to_le_bytes());
+    }
+
     fn write_leb_zero_extended(&mut self, data: &mut [u8], width: Option<usize>) {
+        self.write_leb_extended(data, width, false);
+    }
+
+    /// Pads an encoded LEB to `width` bytes. Negative signed values must be padded with ones rather
+    /// than zeroes to preserve their sign.
+    fn write_leb_extended(&mut self, data: &mut [u8], width: Option<usize>, negative: bool) {
         if width.is_some_and(|width| data.len() < width) {
             *data.last_mut().unwrap() |= 0x80;
             self.extend(data);
 
             let extra = width.unwrap() - data.len();
+            let fill = if negative { 0x7F } else { 0 };
 
             for i in 1..=extra {
-                self.push(if i == extra { 0 } else { 0x80 });
+                self.push(if i == extra { fill } else { fill | 0x80 });
             }
         } else {
             self.extend(data);
@@ -593,7 +1152,7 @@ pub trait Leb128WriteExt: BufWriter {
     fn write_var_i32_with_width(&mut self, v: i32, min_width: Option<usize>) {
         let mut buf = [0u8; 5];
         let written = leb128::write::signed(&mut &mut buf[..], v.into()).unwrap();
-        self.write_leb_zero_extended(&mut buf[0..written], min_width);
+        self.write_leb_extended(&mut buf[0..written], min_width, v < 0);
     }
 
     fn write_var_u64_with_width(&mut self, v: u64, min_width: Option<usize>) {
@@ -605,7 +1164,20 @@ pub trait Leb128WriteExt: BufWriter {
     fn write_var_i64_with_width(&mut self, v: i64, min_width: Option<usize>) {
         let mut buf = [0u8; 10];
         let written = leb128::write::signed(&mut &mut buf[..], v).unwrap();
-        self.write_leb_zero_extended(&mut buf[0..written], min_width);
+        self.write_leb_extended(&mut buf[0..written], min_width, v < 0);
+    }
+
+    /// Writes a signed 33-bit LEB128, as used by block types to encode type indices.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `v` doesn't fit in 33 bits.
+    fn write_var_s33_with_width(&mut self, v: i64, min_width: Option<usize>) {
+        assert!(
+            (-(1 << 32)..(1 << 32)).contains(&v),
+            "{v} does not fit in an `s33`"
+        );
+        self.write_var_i64_with_width(v, min_width);
     }
 
     fn write_var_u32(&mut self, v: u32) {
@@ -624,6 +1196,10 @@ pub trait Leb128WriteExt: BufWriter {
         self.write_var_i64_with_width(v, None);
     }
 
+    fn write_var_s33(&mut self, v: i64) {
+        self.write_var_s33_with_width(v, None);
+    }
+
     fn write_var_u32_full(&mut self, v: u32) {
         self.write_var_u32_with_width(v, Some(5));
     }
@@ -639,21 +1215,575 @@ pub trait Leb128WriteExt: BufWriter {
     fn write_var_i64_full(&mut self, v: i64) {
         self.write_var_i64_with_width(v, Some(10));
     }
+
+    fn write_var_s33_full(&mut self, v: i64) {
+        self.write_var_s33_with_width(v, Some(5));
+    }
 }
 
 impl<E: ?Sized + BufWriter> Leb128WriteExt for E {}
 
+impl<W: ?Sized + BufWriter> BufWriter for &mut W {
+    fn position(&self) -> usize {
+        (**self).position()
+    }
+
+    fn push(&mut self, v: u8) {
+        (**self).push(v);
+    }
+
+    fn extend(&mut self, v: &[u8]) {
+        (**self).extend(v);
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        (**self).reserve(additional);
+    }
+}
+
+impl BufWriter for blake3::Hasher {
+    fn position(&self) -> usize {
+        self.count() as usize
+    }
+
+    fn extend(&mut self, v: &[u8]) {
+        self.update(v);
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct LenCounter(pub usize);
 
 impl BufWriter for LenCounter {
+    fn position(&self) -> usize {
+        self.0
+    }
+
     fn extend(&mut self, v: &[u8]) {
         self.0 += v.len();
     }
 }
 
+/// Wraps a writer to count the bytes written through it. Positions are relative to where the
+/// inner writer was when it was wrapped.
+#[derive(Debug)]
+pub struct CountingWriter<W> {
+    inner: W,
+    start: usize,
+}
+
+impl<W: BufWriter> CountingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            start: inner.position(),
+            inner,
+        }
+    }
+
+    /// The number of bytes written through the adapter.
+    pub fn written(&self) -> usize {
+        self.inner.position() - self.start
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: BufWriter> BufWriter for CountingWriter<W> {
+    fn position(&self) -> usize {
+        self.written()
+    }
+
+    fn push(&mut self, v: u8) {
+        self.inner.push(v);
+    }
+
+    fn extend(&mut self, v: &[u8]) {
+        self.inner.extend(v);
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.inner.reserve(additional);
+    }
+}
+
+impl<W: PatchableWriter> PatchableWriter for CountingWriter<W> {
+    fn patch(&mut self, offset: usize, data: &[u8]) {
+        self.inner.patch(self.start + offset, data);
+    }
+}
+
+#[derive(Debug)]
+pub struct SliceWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> SliceWriter<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.len
+    }
+
+    pub fn written(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl BufWriter for SliceWriter<'_> {
+    fn position(&self) -> usize {
+        self.len
+    }
+
+    fn extend(&mut self, v: &[u8]) {
+        assert!(
+            v.len() <= self.remaining(),
+            "attempted to write {} byte(s) into a `SliceWriter` with only {} byte(s) remaining",
+            v.len(),
+            self.remaining(),
+        );
+
+        self.buf[self.len..][..v.len()].copy_from_slice(v);
+        self.len += v.len();
+    }
+}
+
+// Backpatching
+/// A [`BufWriter`] whose already-written bytes can be overwritten, allowing placeholders like
+/// section sizes to be filled in once their value is known.
+pub trait PatchableWriter: BufWriter {
+    /// Overwrites already-written bytes starting at `offset`.
+    fn patch(&mut self, offset: usize, data: &[u8]);
+
+    /// Writes a `width` byte placeholder to be filled in later through the returned [`Patch`].
+    fn reserve_patch(&mut self, width: usize) -> Patch {
+        let offset = self.position();
+        for _ in 0..width {
+            self.push(0);
+        }
+
+        Patch { offset, width }
+    }
+
+    /// Reserves room for a full-width LEB128-encoded `u32`, such as a section size.
+    fn reserve_var_u32(&mut self) -> Patch {
+        self.reserve_patch(5)
+    }
+}
+
+impl<W: ?Sized + PatchableWriter> PatchableWriter for &mut W {
+    fn patch(&mut self, offset: usize, data: &[u8]) {
+        (**self).patch(offset, data);
+    }
+}
+
+impl PatchableWriter for Vec<u8> {
+    fn patch(&mut self, offset: usize, data: &[u8]) {
+        self[offset..][..data.len()].copy_from_slice(data);
+    }
+}
+
+impl PatchableWriter for SliceWriter<'_> {
+    fn patch(&mut self, offset: usize, data: &[u8]) {
+        assert!(
+            offset + data.len() <= self.len,
+            "attempted to patch bytes which haven't been written yet"
+        );
+        self.buf[offset..][..data.len()].copy_from_slice(data);
+    }
+}
+
+impl PatchableWriter for LenCounter {
+    fn patch(&mut self, offset: usize, data: &[u8]) {
+        assert!(
+            offset + data.len() <= self.0,
+            "attempted to patch bytes which haven't been written yet"
+        );
+    }
+}
+
+/// A placeholder written by [`PatchableWriter::reserve_patch`].
+#[derive(Debug)]
+#[must_use = "placeholders must be filled in"]
+pub struct Patch {
+    offset: usize,
+    width: usize,
+}
+
+impl Patch {
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// The offset of the first byte written after the placeholder.
+    pub fn end(&self) -> usize {
+        self.offset + self.width
+    }
+
+    /// Fills the placeholder with `data`, which must be exactly as wide as the placeholder.
+    pub fn fill(self, writer: &mut (impl ?Sized + PatchableWriter), data: &[u8]) {
+        assert_eq!(
+            data.len(),
+            self.width,
+            "patch data does not match the width of its placeholder"
+        );
+        writer.patch(self.offset, data);
+    }
+
+    pub fn fill_u32(self, writer: &mut (impl ?Sized + PatchableWriter), v: u32) {
+        self.fill(writer, &v.to_le_bytes());
+    }
+
+    pub fn fill_u64(self, writer: &mut (impl ?Sized + PatchableWriter), v: u64) {
+        self.fill(writer, &v.to_le_bytes());
+    }
+
+    fn fill_leb(self, writer: &mut (impl ?Sized + PatchableWriter), f: impl FnOnce(&mut Vec<u8>)) {
+        let mut data = Vec::with_capacity(self.width);
+        f(&mut data);
+        assert!(
+            data.len() == self.width,
+            "value does not fit in a {}-byte LEB128",
+            self.width
+        );
+        self.fill(writer, &data);
+    }
+
+    /// Fills the placeholder with `v` as an LEB128 padded to the width of the placeholder.
+    pub fn fill_var_u32(self, writer: &mut (impl ?Sized + PatchableWriter), v: u32) {
+        let width = self.width;
+        self.fill_leb(writer, |data| data.write_var_u32_with_width(v, Some(width)));
+    }
+
+    pub fn fill_var_i32(self, writer: &mut (impl ?Sized + PatchableWriter), v: i32) {
+        let width = self.width;
+        self.fill_leb(writer, |data| data.write_var_i32_with_width(v, Some(width)));
+    }
+
+    pub fn fill_var_u64(self, writer: &mut (impl ?Sized + PatchableWriter), v: u64) {
+        let width = self.width;
+        self.fill_leb(writer, |data| data.write_var_u64_with_width(v, Some(width)));
+    }
+
+    pub fn fill_var_i64(self, writer: &mut (impl ?Sized + PatchableWriter), v: i64) {
+        let width = self.width;
+        self.fill_leb(writer, |data| data.write_var_i64_with_width(v, Some(width)));
+    }
+
+    /// Fills the placeholder with the number of bytes written since it as a padded LEB128, as is
+    /// needed for size-prefixed sections and function bodies.
+    pub fn fill_len(self, writer: &mut (impl ?Sized + PatchableWriter)) {
+        let len = writer.position() - self.end();
+        self.fill_var_u32(
+            writer,
+            u32::try_from(len).expect("length does not fit in a `u32`"),
+        );
+    }
+}
+
 pub fn len_of(f: impl FnOnce(&mut LenCounter)) -> usize {
     let mut lc = LenCounter::default();
     f(&mut lc);
     lc.0
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn section_tracker_names_innermost_section() {
+        let data = [0u8; 16];
+        let _guard = OffsetTracker::new(&data);
+        let _outer = SectionTracker::new("the outer section", &data[4..]);
+
+        {
+            let _inner = SectionTracker::new("the inner section", &data[8..12]);
+            assert_eq!(
+                FmtOffset(&data[9]).to_string(),
+                "offset 0x9, within the inner section at +0x1"
+            );
+        }
+
+        assert_eq!(
+            FmtOffset(&data[9]).to_string(),
+            "offset 0x9, within the outer section at +0x5"
+        );
+        assert_eq!(FmtOffset(&data[2]).to_string(), "offset 0x2");
+    }
+
+    #[test]
+    fn section_tracker_is_thread_local() {
+        let data = [0u8; 16];
+        let addr = data.as_ptr() as usize;
+        let _section = SectionTracker::new("the section", &data);
+
+        assert!(SectionTracker::lookup_raw(addr).is_some());
+        assert!(std::thread::spawn(move || SectionTracker::lookup_raw(addr))
+            .join()
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn offset_tracker_tolerates_concurrent_tracking() {
+        let data = [0u8; 16];
+
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..256 {
+                        let _guard = OffsetTracker::new_if_untracked(&data);
+                        let _overlapping = OffsetTracker::new(&data[4..]);
+                        if let Some(index) = OffsetTracker::index_in_parent(&data[9]) {
+                            assert!(index < 16);
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(OffsetTracker::index_in_parent(&data[9]), None);
+    }
+
+    #[test]
+    fn read_var_u32_accepts_max() {
+        let mut cursor = ByteCursor(&[0xFF, 0xFF, 0xFF, 0xFF, 0x0F, 0xAA]);
+        assert_eq!(cursor.read_var_u32().unwrap(), u32::MAX);
+        assert_eq!(cursor.0, [0xAA]);
+    }
+
+    #[test]
+    fn read_var_u32_rejects_overflowing_fifth_byte() {
+        // The fifth byte may only hold the top four bits.
+        let bytes = [0xFF, 0xFF, 0xFF, 0xFF, 0x1F];
+        let mut cursor = ByteCursor(&bytes);
+        assert!(cursor.read_var_u32().is_err());
+        assert_eq!(cursor.0.len(), bytes.len());
+
+        // A sixth byte is never allowed either.
+        assert!(ByteCursor(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x00])
+            .read_var_u32()
+            .is_err());
+
+        assert!(ByteCursor(&[0xFF, 0xFF, 0xFF, 0xFF, 0x08])
+            .read_var_i32()
+            .is_err());
+    }
+
+    #[test]
+    fn read_var_i64_accepts_sign_padding() {
+        let mut padded_minus_two = [0xFF; 10];
+        padded_minus_two[0] = 0xFE;
+        padded_minus_two[9] = 0x7F;
+
+        let mut padded_min = [0x80; 10];
+        padded_min[9] = 0x7F;
+
+        for (bytes, expected) in [(padded_minus_two, -2), (padded_min, i64::MIN)] {
+            let mut cursor = ByteCursor(&bytes);
+            assert_eq!(cursor.read_var_i64_full().unwrap(), expected);
+            assert!(cursor.at_eof());
+        }
+    }
+
+    #[test]
+    fn read_var_unsigned_advances_past_value_only() {
+        let mut cursor = ByteCursor(&[0x80, 0x01, 0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF, 0, 0, 0, 0]);
+        assert_eq!(cursor.read_var_u64().unwrap(), 0x80);
+        assert_eq!(cursor.0.len(), 10);
+    }
+
+    #[test]
+    fn read_var_rejects_truncated_input() {
+        let bytes = [0x80, 0x80];
+
+        let mut cursor = ByteCursor(&bytes);
+        assert!(cursor.read_var_u32().is_err());
+        assert!(cursor.read_var_i32().is_err());
+        assert!(cursor.read_var_u64().is_err());
+        assert!(cursor.read_var_i64().is_err());
+        assert!(cursor.read_var_s33().is_err());
+        assert_eq!(cursor.0.len(), bytes.len());
+
+        assert!(ByteCursor(&[]).read_var_u32().is_err());
+    }
+
+    #[test]
+    fn read_var_s33_checks_range() {
+        // The largest and smallest `s33`s.
+        assert_eq!(
+            ByteCursor(&[0xFF, 0xFF, 0xFF, 0xFF, 0x0F])
+                .read_var_s33()
+                .unwrap(),
+            u32::MAX as i64
+        );
+        assert_eq!(
+            ByteCursor(&[0x80, 0x80, 0x80, 0x80, 0x70])
+                .read_var_s33()
+                .unwrap(),
+            -(1 << 32)
+        );
+
+        assert!(ByteCursor(&[0x80, 0x80, 0x80, 0x80, 0x10])
+            .read_var_s33()
+            .is_err());
+    }
+
+    #[test]
+    fn padded_leb_preserves_sign() {
+        let mut buf = Vec::new();
+        buf.write_var_u32_full(1);
+        buf.write_var_i32_full(-1);
+        buf.write_var_i32_full(1);
+        assert_eq!(
+            buf,
+            [
+                0x81, 0x80, 0x80, 0x80, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 0x81, 0x80, 0x80, 0x80,
+                0x00
+            ],
+        );
+
+        for v in [0, 1, -1, 63, 64, -64, -65, i32::MAX, i32::MIN] {
+            let mut buf = Vec::new();
+            buf.write_var_i32_full(v);
+            assert_eq!(ByteCursor(&buf).read_var_i32_full().unwrap(), v);
+        }
+
+        for v in [0, -1, -(1 << 40), i64::MAX, i64::MIN] {
+            let mut buf = Vec::new();
+            buf.write_var_i64_full(v);
+            assert_eq!(ByteCursor(&buf).read_var_i64_full().unwrap(), v);
+        }
+
+        for v in [-1, -(1 << 32), (1 << 32) - 1] {
+            let mut buf = Vec::new();
+            buf.write_var_s33_full(v);
+            assert_eq!(ByteCursor(&buf).read_var_s33_full().unwrap(), v);
+        }
+    }
+
+    #[test]
+    fn patch_fills_placeholders() {
+        let mut buf = vec![0xAA];
+        let size = buf.reserve_var_u32();
+        let value = buf.reserve_patch(5);
+        buf.extend_from_slice(&[1, 2, 3]);
+        value.fill_var_i32(&mut buf, -1);
+        size.fill_len(&mut buf);
+
+        assert_eq!(
+            buf,
+            [0xAA, 0x88, 0x80, 0x80, 0x80, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 1, 2, 3],
+        );
+    }
+
+    #[test]
+    fn patch_is_relative_to_counting_writer() {
+        let mut buf = vec![0xAA, 0xBB];
+        let mut writer = CountingWriter::new(&mut buf);
+        let size = writer.reserve_var_u32();
+        writer.push(1);
+        size.fill_len(&mut writer);
+
+        assert_eq!(buf, [0xAA, 0xBB, 0x81, 0x80, 0x80, 0x80, 0x00, 1]);
+    }
+
+    #[test]
+    fn patch_slice_writer() {
+        let mut buf = [0; 6];
+        let mut writer = SliceWriter::new(&mut buf);
+        let size = writer.reserve_var_u32();
+        writer.push(1);
+        size.fill_len(&mut writer);
+
+        assert_eq!(buf, [0x81, 0x80, 0x80, 0x80, 0x00, 1]);
+    }
+
+    #[test]
+    #[should_panic = "value does not fit in a 2-byte LEB128"]
+    fn patch_rejects_overlong_values() {
+        let mut buf = Vec::new();
+        let patch = buf.reserve_patch(2);
+        patch.fill_var_u32(&mut buf, 1 << 14);
+    }
+
+    #[test]
+    #[should_panic = "attempted to patch bytes which haven't been written yet"]
+    fn patch_rejects_unwritten_bytes() {
+        let mut buf = [0; 8];
+        SliceWriter::new(&mut buf).patch(2, &[1]);
+    }
+
+    /// A byte which must be even. Odd bytes can be skipped.
+    struct EvenByte;
+
+    impl ByteParse<'_> for EvenByte {
+        type Out = u8;
+
+        fn parse_naked(buf: &mut ByteCursor<'_>) -> anyhow::Result<Self::Out> {
+            let v = buf.read_u8()?;
+            anyhow::ensure!(v % 2 == 0, "{v} is odd");
+            Ok(v)
+        }
+
+        fn skip_malformed(buf: &mut ByteCursor<'_>) -> bool {
+            buf.read_u8().is_ok()
+        }
+    }
+
+    #[test]
+    fn parse_recovering_skips_malformed_elements() {
+        let (elements, errors) =
+            ByteParseList::<EvenByte>::new(ByteCursor(&[2, 3, 4, 5])).parse_recovering();
+
+        assert_eq!(elements, [2, 4]);
+        assert_eq!(
+            errors
+                .iter()
+                .map(|err| (err.index, err.offset))
+                .collect::<Vec<_>>(),
+            [(1, 1), (3, 3)],
+        );
+    }
+
+    #[test]
+    fn parse_recovering_respects_count() {
+        let (elements, errors) =
+            ByteParseList::<EvenByte>::with_count(ByteCursor(&[3, 2, 5]), 2).parse_recovering();
+
+        assert_eq!(elements, [2]);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn parse_recovering_stops_at_unskippable_elements() {
+        let (elements, errors) =
+            ByteParseList::<VarU32>::new(ByteCursor(&[1, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 2]))
+                .parse_recovering();
+
+        assert_eq!(elements, [1]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].index, errors[0].offset), (1, 1));
+    }
+}
@@ -0,0 +1,101 @@
+//! Repacking of an append-only pack store alongside a loose blob store.
+
+mod common;
+
+use common::TempDir;
+use wasmall::{append::AppendPackStore, store::DirBlobStore};
+
+fn blob(name: &str) -> (blake3::Hash, Vec<u8>) {
+    let data = format!("the blob named {name}").repeat(8).into_bytes();
+    (blake3::hash(&data), data)
+}
+
+#[test]
+fn repack_folds_loose_and_drops_dead() {
+    let dir = TempDir::new("repack");
+    let loose = DirBlobStore::new(dir.path().join("loose"));
+    let mut store = AppendPackStore::open(dir.path().join("pack")).unwrap();
+
+    // Force several data files so that every one of them has to be rewritten.
+    store.set_max_data_len(256);
+
+    let (packed_live, packed_dead) = (blob("packed live"), blob("packed dead"));
+    let (loose_live, loose_dead) = (blob("loose live"), blob("loose dead"));
+    let (both_live, both_dead) = (blob("both live"), blob("both dead"));
+
+    for (hash, data) in [&packed_live, &packed_dead, &both_live, &both_dead] {
+        store.put_blob(*hash, data).unwrap();
+    }
+    for (hash, data) in [&loose_live, &loose_dead, &both_live, &both_dead] {
+        loose.put_blob(*hash, data).unwrap();
+    }
+
+    let dead = [packed_dead.0, loose_dead.0, both_dead.0];
+    let report = store
+        .repack(Some(&loose), |hash| !dead.contains(&hash))
+        .unwrap();
+
+    assert_eq!(report.blobs_kept, 3);
+    assert_eq!(report.blobs_dropped, 3);
+    assert_eq!(report.loose_folded, 1);
+    assert!(report.bytes_after < report.bytes_before);
+
+    // The loose blobs are gone whether they were folded in or dropped.
+    assert!(loose.hashes().unwrap().is_empty());
+
+    // The live blobs read back, including after reopening the store.
+    let check = |store: &AppendPackStore| {
+        assert_eq!(store.len(), 3);
+
+        for (hash, data) in [&packed_live, &loose_live, &both_live] {
+            assert_eq!(store.get(*hash).unwrap().as_ref(), Some(data));
+        }
+        for hash in dead {
+            assert!(!store.contains(hash));
+            assert!(store.get(hash).unwrap().is_none());
+        }
+    };
+
+    check(&store);
+    drop(store);
+    check(&AppendPackStore::open(dir.path().join("pack")).unwrap());
+}
+
+#[test]
+fn failed_repack_keeps_entries() {
+    let dir = TempDir::new("repack-failure");
+    let mut store = AppendPackStore::open(dir.path().join("pack")).unwrap();
+    store.set_max_data_len(256);
+
+    let blobs = ["a", "b", "c", "d"].map(blob);
+    for (hash, data) in &blobs {
+        store.put_blob(*hash, data).unwrap();
+    }
+
+    // Corrupt the last blob written so the repack fails partway through copying the blobs.
+    let mut data_files = std::fs::read_dir(store.dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "data"))
+        .collect::<Vec<_>>();
+    data_files.sort();
+
+    let last = data_files.last().unwrap();
+    let mut data = std::fs::read(last).unwrap();
+    *data.last_mut().unwrap() ^= 1;
+    std::fs::write(last, data).unwrap();
+
+    assert!(store.repack(None, |_| true).is_err());
+
+    // Both the store and the index on disk still have every blob.
+    let check = |store: &AppendPackStore| {
+        assert_eq!(store.len(), blobs.len());
+        for (hash, data) in &blobs[..3] {
+            assert_eq!(store.get(*hash).unwrap().as_ref(), Some(data));
+        }
+    };
+
+    check(&store);
+    drop(store);
+    check(&AppendPackStore::open(dir.path().join("pack")).unwrap());
+}
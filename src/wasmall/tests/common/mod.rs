@@ -0,0 +1,70 @@
+//! Helpers shared by the integration tests.
+
+#![allow(dead_code)]
+
+use wasmall::{
+    builder::{SectionWriteExt, CORE_MODULE_HEADER},
+    coder::WasmallMod,
+    splitter::{split_module_with, SplitOptions},
+    util::{ByteCursor, ByteParse},
+};
+use wasmparser::{Parser, Payload};
+
+/// Reads a file from `tests/fixtures`.
+pub fn fixture(name: &str) -> Vec<u8> {
+    let path = format!("{}/tests/fixtures/{name}", env!("CARGO_MANIFEST_DIR"));
+    std::fs::read(&path).unwrap_or_else(|err| panic!("failed to read {path}: {err}"))
+}
+
+/// Splits a module and assembles it back together.
+pub fn round_trip(src: &[u8], options: &SplitOptions) -> Vec<u8> {
+    let archive = split_module_with(src, options).unwrap().archive;
+    let module = WasmallMod::parse(&mut ByteCursor(&archive.out_buf)).unwrap();
+    module.assemble(&archive).unwrap()
+}
+
+/// Drops a core module's custom sections and re-encodes its section sizes minimally, which is
+/// what a round-trip through the splitter is expected to produce.
+pub fn strip_custom_sections(src: &[u8]) -> Vec<u8> {
+    let mut out = CORE_MODULE_HEADER.to_vec();
+
+    for payload in Parser::new(0).parse_all(src) {
+        let payload = payload.unwrap();
+        if matches!(payload, Payload::CustomSection(_)) {
+            continue;
+        }
+
+        if let Some((id, range)) = payload.as_section() {
+            out.write_section(id, &src[range]).unwrap();
+        }
+    }
+
+    out
+}
+
+/// A directory under the system's temporary directory which is removed once dropped.
+pub struct TempDir(std::path::PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        static NEXT_ID: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+        let id = NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let path =
+            std::env::temp_dir().join(format!("wasmall-test-{name}-{}-{id}", std::process::id()));
+
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
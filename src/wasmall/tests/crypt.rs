@@ -0,0 +1,123 @@
+//! Sealing and opening of blobs, and the splitting and assembly of encrypted indices.
+
+#![cfg(feature = "encryption")]
+
+mod common;
+
+use common::{fixture, strip_custom_sections};
+use wasmall::{
+    coder::{WasmallMod, WriterOptions},
+    crypt::{BlobCipher, BlobKey, SingleKey},
+    splitter::{split_module_with, SplitOptions},
+    store::verify_archive,
+    util::{ByteCursor, ByteParse},
+};
+
+fn cipher(key_id: &str, key: u8) -> BlobCipher {
+    BlobCipher::new(key_id, BlobKey::from_bytes([key; 32]))
+}
+
+#[test]
+fn seal_round_trips() {
+    let cipher = cipher("key", 1);
+
+    for plaintext in [&b""[..], b"a", &[7; 4096]] {
+        // The nonce, the ciphertext, and the tag.
+        let sealed = cipher.seal(plaintext);
+        assert_eq!(sealed.len(), 24 + plaintext.len() + 16);
+        if !plaintext.is_empty() {
+            assert_ne!(&sealed[24..24 + plaintext.len()], plaintext);
+        }
+        assert_eq!(cipher.open(&sealed).unwrap(), plaintext);
+    }
+}
+
+#[test]
+fn seal_is_deterministic() {
+    let cipher = cipher("key", 1);
+    assert_eq!(cipher.seal(b"the blob"), cipher.seal(b"the blob"));
+    assert_ne!(cipher.seal(b"the blob"), cipher.seal(b"the blub"));
+
+    // Nonces depend on the key so equal blobs can't be linked across keys.
+    assert_ne!(
+        cipher.seal(b"the blob")[..24],
+        self::cipher("key", 2).seal(b"the blob")[..24]
+    );
+}
+
+#[test]
+fn open_rejects_wrong_keys() {
+    let sealed = cipher("key", 1).seal(b"the blob");
+    assert!(cipher("key", 2).open(&sealed).is_err());
+
+    // The key ID is authenticated too.
+    assert!(cipher("other key", 1).open(&sealed).is_err());
+}
+
+#[test]
+fn open_rejects_tampering() {
+    let cipher = cipher("key", 1);
+    let sealed = cipher.seal(b"the blob");
+
+    for i in 0..sealed.len() {
+        let mut tampered = sealed.clone();
+        tampered[i] ^= 1;
+        assert!(cipher.open(&tampered).is_err(), "flipping byte {i}");
+    }
+
+    assert!(cipher.open(&sealed[..sealed.len() - 1]).is_err());
+    assert!(cipher.open(&sealed[..10]).is_err());
+}
+
+#[test]
+fn locked_index_splits_and_assembles() {
+    let src = fixture("link_main.o");
+    let options = SplitOptions {
+        writer: WriterOptions {
+            inline_threshold: 0,
+            encryption: Some(cipher("key", 1)),
+            ..WriterOptions::default()
+        },
+        ..SplitOptions::default()
+    };
+
+    let archive = split_module_with(&src, &options).unwrap().archive;
+    assert!(!archive.hashes.is_empty());
+
+    let mut module = WasmallMod::parse(&mut ByteCursor(&archive.out_buf)).unwrap();
+    assert_eq!(module.key_id(), Some(&b"key"[..]));
+    assert!(module.is_locked());
+
+    // Locked indices can't be assembled but their blobs can still be audited by hash.
+    assert!(module.assemble(&archive).is_err());
+    assert!(verify_archive(&module, &archive).unwrap().is_ok());
+
+    let wrong_id = SingleKey {
+        key_id: b"other key".to_vec(),
+        key: BlobKey::from_bytes([1; 32]),
+    };
+    assert!(module.unlock(&wrong_id).is_err());
+    assert!(module.is_locked());
+
+    module
+        .unlock(&SingleKey {
+            key_id: b"key".to_vec(),
+            key: BlobKey::from_bytes([1; 32]),
+        })
+        .unwrap();
+    assert!(!module.is_locked());
+    assert_eq!(
+        module.assemble_verified(&archive).unwrap(),
+        strip_custom_sections(&src)
+    );
+
+    // Unlocking with the wrong key only fails once the blobs are opened.
+    let mut module = WasmallMod::parse(&mut ByteCursor(&archive.out_buf)).unwrap();
+    module
+        .unlock(&SingleKey {
+            key_id: b"key".to_vec(),
+            key: BlobKey::from_bytes([2; 32]),
+        })
+        .unwrap();
+    assert!(module.assemble(&archive).is_err());
+}
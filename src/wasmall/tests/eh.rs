@@ -0,0 +1,68 @@
+//! Round-trips of objects using the legacy exception handling proposal, whose `throw` and `catch`
+//! instructions refer to tags through `TAG_INDEX_LEB` relocations.
+
+mod common;
+
+use common::{fixture, round_trip, strip_custom_sections};
+use wasmall::{
+    reloc::{RelocEntryType, RelocSection},
+    splitter::{split_module_with, SplitOptions},
+    util::{ByteCursor, ByteParse},
+};
+use wasmparser::{Parser, Payload};
+
+/// [`wasmparser`] can't validate the legacy exception handling opcodes.
+fn options() -> SplitOptions {
+    let mut options = SplitOptions {
+        validate: false,
+        ..SplitOptions::default()
+    };
+    options.features.exceptions = true;
+    options
+}
+
+fn assert_round_trips(name: &str) {
+    let src = fixture(name);
+
+    // Make sure the fixture exercises what it is meant to.
+    let mut tag_relocs = 0;
+    for payload in Parser::new(0).parse_all(&src) {
+        if let Payload::CustomSection(reader) = payload.unwrap() {
+            if reader.name().starts_with("reloc.") {
+                let relocs = RelocSection::parse(&mut ByteCursor(reader.data())).unwrap();
+                tag_relocs += relocs
+                    .entries()
+                    .map(Result::unwrap)
+                    .filter(|reloc| reloc.ty == RelocEntryType::TagIndexLeb)
+                    .count();
+            }
+        }
+    }
+    assert_eq!(tag_relocs, 2, "{name} should throw and catch a tag");
+
+    assert_eq!(round_trip(&src, &options()), strip_custom_sections(&src));
+}
+
+#[test]
+fn imported_tag() {
+    assert_round_trips("eh_imported_tag.o");
+}
+
+#[test]
+fn defined_tag() {
+    assert_round_trips("eh_defined_tag.o");
+}
+
+#[test]
+fn rejects_disabled_exceptions() {
+    let mut options = options();
+    options.features.exceptions = false;
+
+    for name in ["eh_imported_tag.o", "eh_defined_tag.o"] {
+        let err = split_module_with(&fixture(name), &options).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("exceptions support is not enabled"),
+            "unexpected error for {name}: {err:#}"
+        );
+    }
+}
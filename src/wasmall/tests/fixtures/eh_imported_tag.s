@@ -0,0 +1,35 @@
+# llvm-mc -triple=wasm32-unknown-unknown -mattr=+exception-handling -filetype=obj --no-type-check eh_imported_tag.s -o eh_imported_tag.o
+	.tagtype	__cpp_exception i32
+	.functype	ext (i32) -> ()
+
+	.section	.text.thrower,"",@
+	.globl	thrower
+	.type	thrower,@function
+thrower:
+	.functype	thrower (i32) -> ()
+	local.get	0
+	throw	__cpp_exception
+	end_function
+
+	.section	.text.catcher,"",@
+	.globl	catcher
+	.type	catcher,@function
+catcher:
+	.functype	catcher (i32) -> ()
+	try
+	local.get	0
+	call	thrower
+	catch	__cpp_exception
+	call	ext
+	end_try
+	i32.const	counter
+	i32.load	0
+	call	ext
+	end_function
+
+	.section	.data.counter,"",@
+	.globl	counter
+	.p2align	2
+counter:
+	.int32	5
+	.size	counter, 4
@@ -0,0 +1,11 @@
+# llvm-mc -triple=wasm32-unknown-unknown -filetype=obj link_ext_i64.s -o link_ext_i64.o
+	.functype	ext (i64) -> ()
+
+	.section	.text.call_ext,"",@
+	.globl	call_ext
+	.type	call_ext,@function
+call_ext:
+	.functype	call_ext () -> ()
+	i64.const	1
+	call	ext
+	end_function
@@ -0,0 +1,28 @@
+# llvm-mc -triple=wasm32-unknown-unknown -filetype=obj link_lib.s -o link_lib.o
+	.section	.text.add,"",@
+	.globl	add
+	.type	add,@function
+add:
+	.functype	add (i32, i32) -> (i32)
+	local.get	0
+	local.get	1
+	i32.add
+	end_function
+
+	.section	.text.double,"",@
+	.globl	double
+	.type	double,@function
+double:
+	.functype	double (i32) -> (i32)
+	local.get	0
+	local.get	0
+	call	add
+	end_function
+
+	.section	.data.lib_value,"",@
+	.globl	lib_value
+	.p2align	3
+lib_value:
+	.int32	40
+	.int32	2
+	.size	lib_value, 8
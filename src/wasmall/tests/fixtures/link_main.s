@@ -0,0 +1,33 @@
+# llvm-mc -triple=wasm32-unknown-unknown -filetype=obj link_main.s -o link_main.o
+	.functype	ext (i32) -> ()
+	.functype	add (i32, i32) -> (i32)
+	.functype	double (i32) -> (i32)
+
+	.section	.text.run,"",@
+	.globl	run
+	.type	run,@function
+run:
+	.functype	run () -> (i32)
+	i32.const	value_ptr
+	i32.load	0
+	call	ext
+	i32.const	lib_value
+	i32.load	0
+	i32.const	2
+	call	add
+	end_function
+
+	.section	.text.get_double,"",@
+	.globl	get_double
+	.type	get_double,@function
+get_double:
+	.functype	get_double () -> (i32)
+	i32.const	double
+	end_function
+
+	.section	.data.value_ptr,"",@
+	.globl	value_ptr
+	.p2align	2
+value_ptr:
+	.int32	lib_value+4
+	.size	value_ptr, 4
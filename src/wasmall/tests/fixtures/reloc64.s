@@ -0,0 +1,18 @@
+# llvm-mc -triple=wasm64-unknown-unknown -filetype=obj reloc64.s -o reloc64.o
+	.text
+	.functype	get () -> (i64)
+	.section	.text.get,"",@
+	.globl	get
+	.type	get,@function
+get:
+	.functype	get () -> (i64)
+	i64.const	value+8
+	end_function
+
+	.section	.data.value,"",@
+	.globl	value
+	.p2align	3, 0x0
+value:
+	.int64	1
+	.int64	2
+	.size	value, 16
@@ -0,0 +1,296 @@
+//! Links of LLVM objects where one object calls functions and reads data defined by another.
+
+mod common;
+
+use common::fixture;
+use wasmall::{
+    builder::ModuleBuilder,
+    coder::WasmallMod,
+    link::{link_objects, LinkOptions, LinkResult},
+    reloc::LinkingSectionWriter,
+    util::{ByteCursor, ByteParse},
+};
+use wasmparser::{
+    DataKind, ElementItems, ElementKind, ExternalKind, Linking, LinkingSectionReader, Operator,
+    Parser, Payload, SymbolFlags, SymbolInfo, TypeRef,
+};
+
+fn link(names: &[&str]) -> anyhow::Result<LinkResult> {
+    let objects = names.iter().map(|name| fixture(name)).collect::<Vec<_>>();
+    let objects = objects.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+    link_objects(
+        &objects,
+        &LinkOptions {
+            exports: vec!["run".to_string(), "get_double".to_string()],
+            ..LinkOptions::default()
+        },
+    )
+}
+
+fn const_offset(expr: wasmparser::ConstExpr<'_>) -> i32 {
+    match expr.get_operators_reader().read().unwrap() {
+        Operator::I32Const { value } => value,
+        op => panic!("unexpected offset expression {op:?}"),
+    }
+}
+
+/// The pieces of the linked module the relocations should have resolved.
+#[derive(Debug, Default)]
+struct Linked {
+    func_imports: Vec<String>,
+    exports: Vec<(String, u32)>,
+    table: Vec<(i32, Vec<u32>)>,
+    data: Vec<(i32, Vec<u8>)>,
+    calls: Vec<Vec<u32>>,
+    consts: Vec<Vec<i32>>,
+}
+
+fn inspect(module: &[u8]) -> Linked {
+    let mut linked = Linked::default();
+
+    for payload in Parser::new(0).parse_all(module) {
+        match payload.unwrap() {
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    let import = import.unwrap();
+                    if let TypeRef::Func(_) = import.ty {
+                        linked.func_imports.push(import.name.to_string());
+                    }
+                }
+            }
+            Payload::ExportSection(reader) => {
+                for export in reader {
+                    let export = export.unwrap();
+                    if export.kind == ExternalKind::Func {
+                        linked.exports.push((export.name.to_string(), export.index));
+                    }
+                }
+            }
+            Payload::ElementSection(reader) => {
+                for element in reader {
+                    let element = element.unwrap();
+                    let ElementKind::Active { offset_expr, .. } = element.kind else {
+                        panic!("linker emitted a non-active element segment");
+                    };
+                    let ElementItems::Functions(funcs) = element.items else {
+                        panic!("linker emitted an expression element segment");
+                    };
+
+                    linked.table.push((
+                        const_offset(offset_expr),
+                        funcs.into_iter().map(Result::unwrap).collect(),
+                    ));
+                }
+            }
+            Payload::DataSection(reader) => {
+                for data in reader {
+                    let data = data.unwrap();
+                    let DataKind::Active { offset_expr, .. } = data.kind else {
+                        panic!("linker emitted a passive data segment");
+                    };
+
+                    linked
+                        .data
+                        .push((const_offset(offset_expr), data.data.to_vec()));
+                }
+            }
+            Payload::CodeSectionEntry(body) => {
+                let (mut calls, mut consts) = (Vec::new(), Vec::new());
+
+                for op in body.get_operators_reader().unwrap() {
+                    match op.unwrap() {
+                        Operator::Call { function_index } => calls.push(function_index),
+                        Operator::I32Const { value } => consts.push(value),
+                        _ => {}
+                    }
+                }
+
+                linked.calls.push(calls);
+                linked.consts.push(consts);
+            }
+            _ => {}
+        }
+    }
+
+    linked
+}
+
+#[test]
+fn resolves_across_objects() {
+    let result = link(&["link_main.o", "link_lib.o"]).unwrap();
+    wasmparser::validate(&result.module).unwrap();
+
+    let linked = inspect(&result.module);
+
+    // `ext` stays an import, `run` and `get_double` follow it, then `add` and `double`.
+    assert_eq!(linked.func_imports, ["ext"]);
+    assert_eq!(
+        linked.exports,
+        [("run".to_string(), 1), ("get_double".to_string(), 2)]
+    );
+
+    // `run` calls the imported `ext` and `add`, and `double` calls `add` within its own object.
+    assert_eq!(linked.calls, [vec![0, 3], vec![], vec![], vec![3]]);
+
+    // `double` is the only address-taken function and is placed in the first table slot.
+    assert_eq!(linked.table, [(1, vec![4])]);
+
+    // `value_ptr` is placed at the global base and `lib_value` at the next multiple of 8. The
+    // former points into the middle of the latter.
+    let (value_ptr, lib_value) = (1024i32, 1032i32);
+    assert_eq!(
+        linked.data,
+        [
+            (value_ptr, (lib_value + 4).to_le_bytes().to_vec()),
+            (
+                lib_value,
+                [40i32.to_le_bytes(), 2i32.to_le_bytes()].concat()
+            ),
+        ]
+    );
+
+    assert_eq!(linked.consts[0], [value_ptr, lib_value, 2]);
+    assert_eq!(linked.consts[1], [1]);
+}
+
+#[test]
+fn archive_assembles_to_module() {
+    for names in [["link_main.o", "link_lib.o"], ["link_lib.o", "link_main.o"]] {
+        let result = link(&names).unwrap();
+
+        let archive = &result.archive;
+        let module = WasmallMod::parse(&mut ByteCursor(&archive.out_buf)).unwrap();
+        assert_eq!(module.assemble(archive).unwrap(), result.module);
+    }
+}
+
+#[test]
+fn rejects_conflicting_import_types() {
+    let err = link(&["link_main.o", "link_ext_i64.o"]).unwrap_err();
+    assert!(
+        err.to_string().contains("with different types"),
+        "unexpected error: {err:#}"
+    );
+}
+
+#[test]
+fn rejects_tags() {
+    // Both objects throw and catch a tag, which they refer to through `TAG_INDEX_LEB` relocations.
+    for name in ["eh_imported_tag.o", "eh_defined_tag.o"] {
+        let err = link(&[name]).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("tags are not supported"),
+            "unexpected error for {name}: {err:#}"
+        );
+    }
+}
+
+/// Re-encodes the object `name` with `edit` applied to each of its symbols.
+fn with_symbols(name: &str, mut edit: impl FnMut(&mut SymbolInfo<'_>)) -> Vec<u8> {
+    let src = fixture(name);
+    let mut module = ModuleBuilder::new(Vec::new());
+
+    for payload in Parser::new(0).parse_all(&src) {
+        let payload = payload.unwrap();
+        let Some((id, range)) = payload.as_section() else {
+            continue;
+        };
+
+        let Payload::CustomSection(reader) = payload else {
+            module.section(id, &src[range]).unwrap();
+            continue;
+        };
+
+        if reader.name() != "linking" {
+            module.section(id, &src[range]).unwrap();
+            continue;
+        }
+
+        let mut writer = LinkingSectionWriter::new();
+        for subsection in LinkingSectionReader::new(reader.data(), reader.data_offset()).unwrap() {
+            match subsection.unwrap() {
+                Linking::SegmentInfo(segments) => {
+                    writer.segment_info(segments.into_iter().map(Result::unwrap))
+                }
+                Linking::SymbolTable(symbols) => {
+                    let symbols = symbols.into_iter().map(|symbol| {
+                        let mut symbol = symbol.unwrap();
+                        edit(&mut symbol);
+                        symbol
+                    });
+                    writer.symbol_table(symbols).unwrap();
+                }
+                subsection => panic!("unexpected linking subsection {subsection:?}"),
+            }
+        }
+        module.build_section(writer.finish()).unwrap();
+    }
+
+    module.finish()
+}
+
+#[test]
+fn rejects_missing_symbol_indices() {
+    let is_defined = |flags: SymbolFlags| !flags.contains(SymbolFlags::UNDEFINED);
+
+    let cases = [
+        // `run` is defined but claims to be the imported `ext`.
+        (
+            with_symbols("link_main.o", |symbol| match symbol {
+                SymbolInfo::Func { flags, index, .. } if is_defined(*flags) => *index = 0,
+                _ => {}
+            }),
+            "function 0, which the object doesn't define",
+        ),
+        (
+            with_symbols("link_main.o", |symbol| match symbol {
+                SymbolInfo::Func { flags, index, .. } if is_defined(*flags) => *index = 100,
+                _ => {}
+            }),
+            "function 100, which the object doesn't define",
+        ),
+        (
+            with_symbols("link_main.o", |symbol| match symbol {
+                SymbolInfo::Func { flags, index, .. } if !is_defined(*flags) => *index = 100,
+                _ => {}
+            }),
+            "function 100, which the object doesn't import",
+        ),
+        (
+            with_symbols("link_main.o", |symbol| {
+                if let SymbolInfo::Data {
+                    symbol: Some(data), ..
+                } = symbol
+                {
+                    data.index = 100;
+                }
+            }),
+            "refers to the missing segment 100",
+        ),
+        (
+            with_symbols("link_main.o", |symbol| {
+                if let SymbolInfo::Data {
+                    symbol: Some(data), ..
+                } = symbol
+                {
+                    data.offset = u32::MAX;
+                }
+            }),
+            "lies outside of segment 0",
+        ),
+    ];
+
+    for (object, expected) in &cases {
+        let err =
+            link_objects(&[object, &fixture("link_lib.o")], &LinkOptions::default()).unwrap_err();
+        assert!(
+            format!("{err:#}").contains(expected),
+            "expected {expected:?}, got: {err:#}"
+        );
+    }
+
+    // The re-encoding itself is faithful.
+    let object = with_symbols("link_main.o", |_| {});
+    link_objects(&[&object, &fixture("link_lib.o")], &LinkOptions::default()).unwrap();
+}
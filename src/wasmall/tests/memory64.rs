@@ -0,0 +1,45 @@
+//! Splitting of `memory64` objects, whose relocations are 64 bits wide.
+
+mod common;
+
+use common::{fixture, round_trip, strip_custom_sections};
+use wasmall::splitter::{split_module_with, SplitOptions};
+
+fn options() -> SplitOptions {
+    let mut options = SplitOptions::default();
+    options.features.memory64 = true;
+    options
+}
+
+/// The padded `MEMORY_ADDR_SLEB64` operand of `get`'s `i64.const value+8`.
+const VALUE_OPERAND: [u8; 10] = [0x88, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00];
+
+#[test]
+fn relocations_below_4_gib_round_trip() {
+    let src = fixture("reloc64.o");
+    assert_eq!(round_trip(&src, &options()), strip_custom_sections(&src));
+}
+
+#[test]
+fn relocations_above_4_gib_are_rejected() {
+    let mut src = fixture("reloc64.o");
+    let start = src
+        .windows(VALUE_OPERAND.len())
+        .position(|window| window == VALUE_OPERAND)
+        .unwrap();
+
+    // Move `value` to 4 GiB, keeping the operand padded.
+    let mut operand = Vec::new();
+    let value = (1i64 << 32) + 8;
+    for i in 0..10 {
+        let group = (value >> (7 * i)) as u8 & 0x7F;
+        operand.push(if i < 9 { group | 0x80 } else { group });
+    }
+    src[start..start + 10].copy_from_slice(&operand);
+
+    let err = split_module_with(&src, &options()).unwrap_err();
+    assert!(
+        format!("{err:#}").contains("doesn't fit in 32 bits"),
+        "unexpected error: {err:#}"
+    );
+}
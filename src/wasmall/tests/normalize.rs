@@ -0,0 +1,121 @@
+//! Normalization of function bodies which differ only in how their immediates and locals are
+//! encoded.
+
+mod common;
+
+use common::{fixture, round_trip, strip_custom_sections};
+use wasmall::{
+    builder::{SectionWriteExt, CORE_MODULE_HEADER},
+    coder::WriterOptions,
+    splitter::{split_module_with, SplitOptions},
+};
+
+fn options() -> SplitOptions {
+    SplitOptions {
+        normalize: true,
+        ..SplitOptions::default()
+    }
+}
+
+/// Builds a module exporting a single `(func (param i32) (result i32))` with the given body, which
+/// excludes its size.
+fn module(body: &[u8]) -> Vec<u8> {
+    let mut out = CORE_MODULE_HEADER.to_vec();
+    out.write_section(1, &[1, 0x60, 1, 0x7F, 1, 0x7F]).unwrap();
+    out.write_section(3, &[1, 0]).unwrap();
+    out.write_section(7, &[1, 1, b'f', 0, 0]).unwrap();
+
+    // Pad the body's size to five bytes.
+    let mut code = vec![1];
+    let len = body.len() as u32;
+    code.extend((0..5).map(|i| (len >> (7 * i)) as u8 & 0x7F | if i < 4 { 0x80 } else { 0 }));
+    code.extend_from_slice(body);
+    out.write_section(10, &code).unwrap();
+
+    out
+}
+
+/// Encodes `v` as a padded five-byte LEB.
+fn padded(v: u8) -> [u8; 5] {
+    [v | 0x80, 0x80, 0x80, 0x80, 0x00]
+}
+
+/// Declares its locals as `i64 i32 i32`, across three groups, and pads its immediates.
+fn loose_body() -> Vec<u8> {
+    let mut body = vec![3, 1, 0x7E, 1, 0x7F, 1, 0x7F];
+    for _ in 0..8 {
+        body.push(0x20);
+        body.extend(padded(0));
+        body.push(0x21);
+        body.extend(padded(2));
+        body.extend([0x20, 2, 0x21, 3]);
+        body.push(0x42);
+        body.extend(padded(5));
+        body.extend([0x21, 1]);
+    }
+    body.extend([0x20, 3, 0x0B]);
+    body
+}
+
+/// The same function as [`loose_body`] with its locals declared as `i32 i32 i64` and minimal
+/// immediates.
+fn tight_body() -> Vec<u8> {
+    let mut body = vec![2, 2, 0x7F, 1, 0x7E];
+    for _ in 0..8 {
+        body.extend([0x20, 0, 0x21, 1, 0x20, 1, 0x21, 2, 0x42, 5, 0x21, 3]);
+    }
+    body.extend([0x20, 2, 0x0B]);
+    body
+}
+
+#[test]
+fn equivalent_bodies_share_blobs() {
+    let (loose, tight) = (module(&loose_body()), module(&tight_body()));
+    wasmparser::validate(&loose).unwrap();
+    wasmparser::validate(&tight).unwrap();
+
+    let blobs = |src: &[u8], options: &SplitOptions| {
+        let archive = split_module_with(src, options).unwrap().archive;
+        let mut hashes = archive.hashes.keys().copied().collect::<Vec<_>>();
+        hashes.sort_by_key(|hash| *hash.as_bytes());
+        hashes
+    };
+
+    assert_eq!(blobs(&loose, &options()), blobs(&tight, &options()));
+    assert_ne!(
+        blobs(&loose, &SplitOptions::default()),
+        blobs(&tight, &SplitOptions::default())
+    );
+
+    // Both modules still reassemble bit-exactly.
+    assert_eq!(round_trip(&loose, &options()), loose);
+    assert_eq!(round_trip(&tight, &options()), tight);
+}
+
+#[test]
+fn relocated_objects_round_trip() {
+    let mut options = SplitOptions {
+        // `wasmparser` can't validate the legacy exception handling opcodes.
+        validate: false,
+        writer: WriterOptions {
+            inline_threshold: 0,
+            ..WriterOptions::default()
+        },
+        ..options()
+    };
+    options.features.exceptions = true;
+
+    for name in [
+        "link_main.o",
+        "link_lib.o",
+        "eh_imported_tag.o",
+        "eh_defined_tag.o",
+    ] {
+        let src = fixture(name);
+        assert_eq!(
+            round_trip(&src, &options),
+            strip_custom_sections(&src),
+            "{name} didn't round trip"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! Resuming an interrupted assembly from its checkpoint.
+
+mod common;
+
+use std::{borrow::Cow, cell::Cell};
+
+use blake3::Hash;
+use common::TempDir;
+use wasmall::{
+    coder::{WasmallArchive, WasmallMod, WasmallWriter, WriterOptions},
+    resume::{assemble_resumable, ResumeOptions},
+    store::BlobSource,
+    util::{ByteCursor, ByteParse},
+};
+
+/// Builds an archive of several blobs, each too large to be inlined.
+fn archive() -> WasmallArchive {
+    let mut writer = WasmallWriter::new(WriterOptions::default());
+    for i in 0..8 {
+        writer.push_verbatim(|sink| sink.extend_from_slice(b"--"));
+        writer.push_blob(
+            &[],
+            &[],
+            format!("the blob numbered {i}").repeat(64).as_bytes(),
+        );
+    }
+    writer.finish().unwrap()
+}
+
+/// Serves blobs from an archive until it has served `limit` of them.
+struct FlakySource<'a> {
+    archive: &'a WasmallArchive,
+    limit: Cell<usize>,
+}
+
+impl BlobSource for FlakySource<'_> {
+    fn get_blob(&self, hash: Hash) -> anyhow::Result<Option<Cow<'_, [u8]>>> {
+        let limit = self.limit.get();
+        anyhow::ensure!(limit > 0, "the connection dropped");
+        self.limit.set(limit - 1);
+        self.archive.get_blob(hash)
+    }
+}
+
+#[test]
+fn resumes_from_checkpoint() {
+    let dir = TempDir::new("resume");
+    let archive = archive();
+    let module = WasmallMod::parse(&mut ByteCursor(&archive.out_buf)).unwrap();
+
+    let out_path = dir.path().join("module.wasm");
+    let options = ResumeOptions {
+        checkpoint_path: dir.path().join("module.ckpt"),
+        checkpoint_interval: 1,
+    };
+
+    let flaky = FlakySource {
+        archive: &archive,
+        limit: Cell::new(5),
+    };
+    assert!(assemble_resumable(&module, &flaky, &out_path, &options).is_err());
+    assert!(options.checkpoint_path.exists());
+
+    let report = assemble_resumable(&module, &archive, &out_path, &options).unwrap();
+    assert!(report.segments_skipped > 0);
+    assert!(report.bytes_reused > 0);
+    assert!(!options.checkpoint_path.exists());
+
+    assert_eq!(
+        std::fs::read(&out_path).unwrap(),
+        module.assemble(&archive).unwrap()
+    );
+}
+
+#[test]
+fn ignores_damaged_prefix() {
+    let dir = TempDir::new("resume-damaged");
+    let archive = archive();
+    let module = WasmallMod::parse(&mut ByteCursor(&archive.out_buf)).unwrap();
+
+    let out_path = dir.path().join("module.wasm");
+    let options = ResumeOptions {
+        checkpoint_path: dir.path().join("module.ckpt"),
+        checkpoint_interval: 1,
+    };
+
+    let flaky = FlakySource {
+        archive: &archive,
+        limit: Cell::new(5),
+    };
+    assert!(assemble_resumable(&module, &flaky, &out_path, &options).is_err());
+
+    let mut partial = std::fs::read(&out_path).unwrap();
+    partial[0] ^= 1;
+    std::fs::write(&out_path, partial).unwrap();
+
+    let report = assemble_resumable(&module, &archive, &out_path, &options).unwrap();
+    assert_eq!(report.segments_skipped, 0);
+    assert_eq!(report.bytes_reused, 0);
+
+    assert_eq!(
+        std::fs::read(&out_path).unwrap(),
+        module.assemble(&archive).unwrap()
+    );
+}
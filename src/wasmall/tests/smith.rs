@@ -0,0 +1,31 @@
+//! A few generated cases of every property test target, along with the persisted failures.
+
+#![cfg(feature = "smith")]
+
+use wasmall::smith::{replay, run, Target};
+
+#[test]
+fn small_seed() {
+    for target in Target::ALL {
+        let report = run(target, 0, 64, None).unwrap();
+        assert_eq!(report.cases, 64);
+
+        for failure in &report.failures {
+            eprintln!(
+                "{} ({:?}): {}",
+                target.name(),
+                failure.input,
+                failure.message
+            );
+        }
+        assert!(report.is_ok(), "{} failed", target.name());
+    }
+}
+
+#[test]
+fn regression_fixtures() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/smith");
+
+    let failures = replay(dir.as_ref()).unwrap();
+    assert!(failures.is_empty(), "{failures:#?}");
+}
@@ -0,0 +1,164 @@
+//! Storing, fetching, collecting, and auditing the blobs of archives in a directory store.
+
+mod common;
+
+use std::borrow::Cow;
+
+use common::TempDir;
+use wasmall::{
+    coder::{WasmallArchive, WasmallMod, WasmallWriter, WriterOptions},
+    store::{
+        collect_garbage, fetch_missing, store_archive, verify_archive, BlobStore, DirBlobStore,
+        MemoryBlobSource,
+    },
+    util::{ByteCursor, ByteParse},
+};
+
+/// Builds an archive with one blob per entry of `blobs`, each too large to be inlined.
+fn archive(blobs: &[&str]) -> WasmallArchive {
+    let mut writer = WasmallWriter::new(WriterOptions::default());
+    for blob in blobs {
+        writer.push_verbatim(|sink| sink.extend_from_slice(b"--"));
+        writer.push_blob(
+            &[],
+            &[],
+            format!("the blob named {blob}").repeat(16).as_bytes(),
+        );
+    }
+    writer.finish().unwrap()
+}
+
+fn parse(archive: &WasmallArchive) -> WasmallMod<'_> {
+    WasmallMod::parse(&mut ByteCursor(&archive.out_buf)).unwrap()
+}
+
+#[test]
+fn store_archive_skips_present_blobs() {
+    let dir = TempDir::new("store-archive");
+    let mut store = DirBlobStore::new(dir.path());
+    let archive = archive(&["a", "b", "a"]);
+
+    let report = store_archive(&mut store, &archive).unwrap();
+    assert_eq!(report.blobs_written, 2);
+    assert_eq!(report.blobs_skipped, 0);
+    assert_eq!(report.bytes_written, archive.blob_buf.len());
+
+    let report = store_archive(&mut store, &archive).unwrap();
+    assert_eq!(report.blobs_written, 0);
+    assert_eq!(report.blobs_skipped, 2);
+
+    let module = parse(&archive);
+    assert_eq!(
+        module.assemble_verified(&store).unwrap(),
+        module.assemble(&archive).unwrap()
+    );
+}
+
+#[test]
+fn fetch_missing_fills_in_from_upstream() {
+    let dir = TempDir::new("fetch-missing");
+    let mut store = DirBlobStore::new(dir.path());
+
+    store_archive(&mut store, &archive(&["a"])).unwrap();
+
+    let archive = archive(&["a", "b", "c"]);
+    let module = parse(&archive);
+    assert_eq!(fetch_missing(&module, &mut store, &archive).unwrap(), 2);
+    assert_eq!(fetch_missing(&module, &mut store, &archive).unwrap(), 0);
+    module.assemble_verified(&store).unwrap();
+}
+
+#[test]
+fn fetch_missing_rejects_corrupted_blobs() {
+    let dir = TempDir::new("fetch-corrupted");
+    let mut store = DirBlobStore::new(dir.path());
+
+    let archive = archive(&["a"]);
+    let module = parse(&archive);
+    let hash = *archive.hashes.keys().next().unwrap();
+
+    let mut upstream = MemoryBlobSource::default();
+    upstream.insert(hash, Cow::Borrowed(b"not the blob"));
+
+    let err = fetch_missing(&module, &mut store, &upstream).unwrap_err();
+    assert!(err.to_string().contains("is corrupted"), "{err:#}");
+    assert!(!store.has_blob(hash).unwrap());
+}
+
+#[test]
+fn collect_garbage_keeps_live_blobs() {
+    let dir = TempDir::new("collect-garbage");
+    let mut store = DirBlobStore::new(dir.path());
+
+    let (kept, dropped) = (archive(&["a", "b"]), archive(&["b", "c", "d"]));
+    store_archive(&mut store, &kept).unwrap();
+    store_archive(&mut store, &dropped).unwrap();
+
+    let module = parse(&kept);
+    let report = collect_garbage(&mut store, [&module]).unwrap();
+    assert_eq!(report.live_blobs, 2);
+    assert_eq!(report.blobs_removed, 2);
+
+    let mut hashes = store.hashes().unwrap();
+    let mut expected = kept.hashes.keys().copied().collect::<Vec<_>>();
+    hashes.sort_by_key(|hash| *hash.as_bytes());
+    expected.sort_by_key(|hash| *hash.as_bytes());
+    assert_eq!(hashes, expected);
+
+    module.assemble_verified(&store).unwrap();
+}
+
+#[test]
+fn verify_archive_reports_missing_and_damaged_blobs() {
+    let dir = TempDir::new("verify-archive");
+    let mut store = DirBlobStore::new(dir.path());
+
+    let archive = archive(&["a", "b", "c"]);
+    let module = parse(&archive);
+    store_archive(&mut store, &archive).unwrap();
+
+    let report = verify_archive(&module, &store).unwrap();
+    assert_eq!(report.blobs_checked, 3);
+    assert!(report.is_ok());
+
+    let order = module.fetch_order().unwrap();
+    store.remove_blob(order[0]).unwrap();
+    std::fs::write(store.blob_path(order[1]), b"not the blob").unwrap();
+
+    let report = verify_archive(&module, &store).unwrap();
+    assert_eq!(report.blobs_checked, 3);
+    assert_eq!(report.missing, [order[0]]);
+    assert_eq!(report.damaged.len(), 1);
+    assert_eq!(report.damaged[0].0, order[1]);
+
+    let err = report.into_result().unwrap_err().to_string();
+    assert!(err.contains("found 2 problem(s)"), "{err}");
+}
+
+#[test]
+fn concurrent_puts_of_the_same_blob() {
+    let dir = TempDir::new("concurrent-puts");
+    let store = DirBlobStore::new(dir.path());
+
+    let data = b"a blob written by many threads at once".repeat(1024);
+    let hash = blake3::hash(&data);
+
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| {
+                for _ in 0..16 {
+                    store.put_blob(hash, &data).unwrap();
+                }
+            });
+        }
+    });
+
+    assert_eq!(store.hashes().unwrap(), [hash]);
+    assert_eq!(
+        std::fs::read_dir(store.blob_path(hash).parent().unwrap())
+            .unwrap()
+            .count(),
+        1,
+        "temporary files were left behind"
+    );
+}